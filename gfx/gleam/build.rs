@@ -13,18 +13,30 @@ fn main() {
     let mut file_gles = File::create(&Path::new(&dest).join("gles_bindings.rs")).unwrap();
 
     // OpenGL 3.3 bindings
-    let gl_extensions = ["GL_ARB_texture_rectangle", "GL_EXT_debug_marker"];
+    let gl_extensions = [
+        "GL_ARB_texture_rectangle",
+        "GL_EXT_debug_marker",
+        "GL_KHR_debug",
+        "GL_ARB_compute_shader",
+        "GL_ARB_shader_image_load_store",
+        "GL_ARB_draw_indirect",
+        "GL_ARB_multi_draw_indirect",
+        "GL_ARB_invalidate_subdata",
+    ];
     let gl_reg = Registry::new(Api::Gl, (3, 3), Profile::Core, Fallbacks::All, gl_extensions);
     gl_reg.write_bindings(gl_generator::StructGenerator, &mut file_gl)
           .unwrap();
 
-    // GLES 2.0 bindings
+    // GLES bindings. Generated at 3.1 so that the compute shader entry
+    // points exist; they are only called on devices that report 3.1+.
     let gles_extensions = [
         "GL_EXT_texture_format_BGRA8888",
         "GL_OES_EGL_image",
         "GL_OES_EGL_image_external",
+        "GL_KHR_debug",
+        "GL_EXT_discard_framebuffer",
     ];
-    let gles_reg = Registry::new(Api::Gles2, (3, 0), Profile::Core, Fallbacks::All, gles_extensions);
+    let gles_reg = Registry::new(Api::Gles2, (3, 1), Profile::Core, Fallbacks::All, gles_extensions);
     gles_reg.write_bindings(gl_generator::StructGenerator, &mut file_gles)
             .unwrap();
 
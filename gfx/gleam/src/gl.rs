@@ -398,6 +398,18 @@ pub trait Gl {
     fn insert_event_marker_ext(&self, message: &str);
     fn push_group_marker_ext(&self, message: &str);
     fn pop_group_marker_ext(&self);
+    fn debug_message_insert_khr(&self, message: &str);
+    fn push_debug_group_khr(&self, message: &str);
+    fn pop_debug_group_khr(&self);
+    fn object_label_khr(&self, identifier: GLenum, name: GLuint, label: &str);
+    fn dispatch_compute(&self, num_groups_x: GLuint, num_groups_y: GLuint, num_groups_z: GLuint);
+    fn memory_barrier(&self, barriers: GLbitfield);
+    fn bind_image_texture(&self, unit: GLuint, texture: GLuint, level: GLint, layered: bool,
+                          layer: GLint, access: GLenum, format: GLenum);
+    fn multi_draw_elements_indirect(&self, mode: GLenum, element_type: GLenum,
+                                    indirect_offset: GLuint, draw_count: GLsizei,
+                                    stride: GLsizei);
+    fn invalidate_framebuffer(&self, target: GLenum, attachments: &[GLenum]);
     fn fence_sync(&self, condition: GLenum, flags: GLbitfield) -> GLsync;
     fn client_wait_sync(&self, sync: GLsync, flags: GLbitfield, timeout: GLuint64);
     fn wait_sync(&self, sync: GLsync, flags: GLbitfield, timeout: GLuint64);
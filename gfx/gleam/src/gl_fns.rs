@@ -1377,6 +1377,99 @@ impl Gl for GlFns {
         }
     }
 
+    fn debug_message_insert_khr(&self, message: &str) {
+        if self.ffi_gl_.DebugMessageInsert.is_loaded() {
+            unsafe {
+                self.ffi_gl_.DebugMessageInsert(DEBUG_SOURCE_APPLICATION,
+                                                DEBUG_TYPE_MARKER,
+                                                0,
+                                                DEBUG_SEVERITY_NOTIFICATION,
+                                                message.len() as GLsizei,
+                                                message.as_ptr() as *const _);
+            }
+        }
+    }
+
+    fn push_debug_group_khr(&self, message: &str) {
+        if self.ffi_gl_.PushDebugGroup.is_loaded() {
+            unsafe {
+                self.ffi_gl_.PushDebugGroup(DEBUG_SOURCE_APPLICATION,
+                                            0,
+                                            message.len() as GLsizei,
+                                            message.as_ptr() as *const _);
+            }
+        }
+    }
+
+    fn pop_debug_group_khr(&self) {
+        if self.ffi_gl_.PopDebugGroup.is_loaded() {
+            unsafe {
+                self.ffi_gl_.PopDebugGroup();
+            }
+        }
+    }
+
+    fn object_label_khr(&self, identifier: GLenum, name: GLuint, label: &str) {
+        if self.ffi_gl_.ObjectLabel.is_loaded() {
+            unsafe {
+                self.ffi_gl_.ObjectLabel(identifier,
+                                         name,
+                                         label.len() as GLsizei,
+                                         label.as_ptr() as *const _);
+            }
+        }
+    }
+
+    fn dispatch_compute(&self, num_groups_x: GLuint, num_groups_y: GLuint, num_groups_z: GLuint) {
+        if self.ffi_gl_.DispatchCompute.is_loaded() {
+            unsafe {
+                self.ffi_gl_.DispatchCompute(num_groups_x, num_groups_y, num_groups_z);
+            }
+        }
+    }
+
+    fn memory_barrier(&self, barriers: GLbitfield) {
+        if self.ffi_gl_.MemoryBarrier.is_loaded() {
+            unsafe {
+                self.ffi_gl_.MemoryBarrier(barriers);
+            }
+        }
+    }
+
+    fn bind_image_texture(&self, unit: GLuint, texture: GLuint, level: GLint, layered: bool,
+                          layer: GLint, access: GLenum, format: GLenum) {
+        if self.ffi_gl_.BindImageTexture.is_loaded() {
+            unsafe {
+                self.ffi_gl_.BindImageTexture(unit, texture, level, layered as GLboolean,
+                                              layer, access, format);
+            }
+        }
+    }
+
+    fn multi_draw_elements_indirect(&self, mode: GLenum, element_type: GLenum,
+                                    indirect_offset: GLuint, draw_count: GLsizei,
+                                    stride: GLsizei) {
+        if self.ffi_gl_.MultiDrawElementsIndirect.is_loaded() {
+            unsafe {
+                self.ffi_gl_.MultiDrawElementsIndirect(mode,
+                                                       element_type,
+                                                       indirect_offset as *const c_void,
+                                                       draw_count,
+                                                       stride);
+            }
+        }
+    }
+
+    fn invalidate_framebuffer(&self, target: GLenum, attachments: &[GLenum]) {
+        if self.ffi_gl_.InvalidateFramebuffer.is_loaded() {
+            unsafe {
+                self.ffi_gl_.InvalidateFramebuffer(target,
+                                                   attachments.len() as GLsizei,
+                                                   attachments.as_ptr());
+            }
+        }
+    }
+
     fn fence_sync(&self, condition: GLenum, flags: GLbitfield) -> GLsync {
         unsafe {
            self.ffi_gl_.FenceSync(condition, flags) as *const _
@@ -1331,6 +1331,97 @@ impl Gl for GlesFns {
     fn pop_group_marker_ext(&self) {
     }
 
+    fn debug_message_insert_khr(&self, message: &str) {
+        if self.ffi_gl_.DebugMessageInsertKHR.is_loaded() {
+            unsafe {
+                self.ffi_gl_.DebugMessageInsertKHR(DEBUG_SOURCE_APPLICATION_KHR,
+                                                   DEBUG_TYPE_MARKER_KHR,
+                                                   0,
+                                                   DEBUG_SEVERITY_NOTIFICATION_KHR,
+                                                   message.len() as GLsizei,
+                                                   message.as_ptr() as *const _);
+            }
+        }
+    }
+
+    fn push_debug_group_khr(&self, message: &str) {
+        if self.ffi_gl_.PushDebugGroupKHR.is_loaded() {
+            unsafe {
+                self.ffi_gl_.PushDebugGroupKHR(DEBUG_SOURCE_APPLICATION_KHR,
+                                               0,
+                                               message.len() as GLsizei,
+                                               message.as_ptr() as *const _);
+            }
+        }
+    }
+
+    fn pop_debug_group_khr(&self) {
+        if self.ffi_gl_.PopDebugGroupKHR.is_loaded() {
+            unsafe {
+                self.ffi_gl_.PopDebugGroupKHR();
+            }
+        }
+    }
+
+    fn object_label_khr(&self, identifier: GLenum, name: GLuint, label: &str) {
+        if self.ffi_gl_.ObjectLabelKHR.is_loaded() {
+            unsafe {
+                self.ffi_gl_.ObjectLabelKHR(identifier,
+                                            name,
+                                            label.len() as GLsizei,
+                                            label.as_ptr() as *const _);
+            }
+        }
+    }
+
+    fn dispatch_compute(&self, num_groups_x: GLuint, num_groups_y: GLuint, num_groups_z: GLuint) {
+        if self.ffi_gl_.DispatchCompute.is_loaded() {
+            unsafe {
+                self.ffi_gl_.DispatchCompute(num_groups_x, num_groups_y, num_groups_z);
+            }
+        }
+    }
+
+    fn memory_barrier(&self, barriers: GLbitfield) {
+        if self.ffi_gl_.MemoryBarrier.is_loaded() {
+            unsafe {
+                self.ffi_gl_.MemoryBarrier(barriers);
+            }
+        }
+    }
+
+    fn bind_image_texture(&self, unit: GLuint, texture: GLuint, level: GLint, layered: bool,
+                          layer: GLint, access: GLenum, format: GLenum) {
+        if self.ffi_gl_.BindImageTexture.is_loaded() {
+            unsafe {
+                self.ffi_gl_.BindImageTexture(unit, texture, level, layered as GLboolean,
+                                              layer, access, format);
+            }
+        }
+    }
+
+    fn multi_draw_elements_indirect(&self, _mode: GLenum, _element_type: GLenum,
+                                    _indirect_offset: GLuint, _draw_count: GLsizei,
+                                    _stride: GLsizei) {
+        panic!("not supported")
+    }
+
+    fn invalidate_framebuffer(&self, target: GLenum, attachments: &[GLenum]) {
+        if self.ffi_gl_.InvalidateFramebuffer.is_loaded() {
+            unsafe {
+                self.ffi_gl_.InvalidateFramebuffer(target,
+                                                   attachments.len() as GLsizei,
+                                                   attachments.as_ptr());
+            }
+        } else if self.ffi_gl_.DiscardFramebufferEXT.is_loaded() {
+            unsafe {
+                self.ffi_gl_.DiscardFramebufferEXT(target,
+                                                   attachments.len() as GLsizei,
+                                                   attachments.as_ptr());
+            }
+        }
+    }
+
     fn fence_sync(&self, condition: GLenum, flags: GLbitfield) -> GLsync {
         unsafe {
            self.ffi_gl_.FenceSync(condition, flags) as *const _
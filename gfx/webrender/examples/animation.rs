@@ -37,6 +37,8 @@ impl Example for App {
                                       TransformStyle::Flat,
                                       None,
                                       MixBlendMode::Normal,
+                                      PixelSnapping::Auto,
+                                      false,
                                       Vec::new());
 
         // Fill it with a white rect
@@ -191,6 +191,8 @@ impl Example for App {
                                       TransformStyle::Flat,
                                       None,
                                       MixBlendMode::Normal,
+                                      PixelSnapping::Auto,
+                                      false,
                                       Vec::new());
 
         let image_mask_key = api.generate_image_key();
@@ -205,7 +207,9 @@ impl Example for App {
             rect: (75, 75).by(100, 100),
             repeat: false,
         };
-        let complex = ComplexClipRegion::new((50, 50).to(150, 150), BorderRadius::uniform(20.0));
+        let complex = ComplexClipRegion::new((50, 50).to(150, 150),
+                                             BorderRadius::uniform(20.0),
+                                             ClipMode::Clip);
         let id = builder.define_clip(None, bounds, vec![complex], Some(mask));
         builder.push_clip_id(id);
 
@@ -247,6 +247,8 @@ impl Example for App {
                                       api::TransformStyle::Flat,
                                       None,
                                       api::MixBlendMode::Normal,
+                                      api::PixelSnapping::Auto,
+                                      false,
                                       Vec::new());
 
         builder.push_image(
@@ -98,7 +98,7 @@ pub fn main_wrapper(example: &mut Example,
     println!("OpenGL version {}", gl.get_string(gl::VERSION));
     println!("Shader resource path: {:?}", res_path);
 
-    let (width, height) = window.get_inner_size_pixels().unwrap();
+    let (mut width, mut height) = window.get_inner_size_pixels().unwrap();
 
     let opts = webrender::RendererOptions {
         resource_override_path: res_path,
@@ -120,7 +120,7 @@ pub fn main_wrapper(example: &mut Example,
     let root_background_color = ColorF::new(0.3, 0.0, 0.0, 1.0);
 
     let pipeline_id = PipelineId(0, 0);
-    let layout_size = LayoutSize::new(width as f32, height as f32);
+    let mut layout_size = LayoutSize::new(width as f32, height as f32);
     let mut builder = DisplayListBuilder::new(pipeline_id, layout_size);
     let mut resources = ResourceUpdates::new();
 
@@ -171,7 +171,37 @@ pub fn main_wrapper(example: &mut Example,
                 }
                 glutin::Event::KeyboardInput(glutin::ElementState::Pressed,
                                              _, Some(glutin::VirtualKeyCode::M)) => {
-                    api.notify_memory_pressure();
+                    api.notify_memory_pressure(MemoryPressureLevel::Critical);
+                }
+                glutin::Event::Resized(..) => {
+                    // The window (and with it the swapchain backing the
+                    // default framebuffer) changed size. Tell the backend
+                    // about the new window parameters and rebuild the display
+                    // list at the new layout size.
+                    let (new_width, new_height) = window.get_inner_size_pixels().unwrap();
+                    width = new_width;
+                    height = new_height;
+                    layout_size = LayoutSize::new(width as f32, height as f32);
+
+                    let size = DeviceUintSize::new(width, height);
+                    api.set_window_parameters(document_id,
+                                              size,
+                                              DeviceUintRect::new(DeviceUintPoint::zero(), size));
+
+                    let mut builder = DisplayListBuilder::new(pipeline_id, layout_size);
+                    let mut resources = ResourceUpdates::new();
+
+                    example.render(&api, &mut builder, &mut resources, layout_size, pipeline_id, document_id);
+                    api.set_display_list(
+                        document_id,
+                        epoch,
+                        Some(root_background_color),
+                        layout_size,
+                        builder.finalize(),
+                        true,
+                        resources
+                    );
+                    api.generate_frame(document_id, None);
                 }
                 _ => {
                     if example.on_event(event, &api, document_id) {
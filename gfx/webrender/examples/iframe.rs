@@ -41,6 +41,8 @@ impl Example for App {
                                           TransformStyle::Flat,
                                           None,
                                           MixBlendMode::Normal,
+                                          PixelSnapping::Auto,
+                                          false,
                                           Vec::new());
         // green rect visible == success
         sub_builder.push_rect(sub_bounds, None, ColorF::new(0.0, 1.0, 0.0, 1.0));
@@ -64,6 +66,8 @@ impl Example for App {
                                       TransformStyle::Flat,
                                       None,
                                       MixBlendMode::Normal,
+                                      PixelSnapping::Auto,
+                                      false,
                                       Vec::new());
         // red rect under the iframe: if this is visible, things have gone wrong
         builder.push_rect(bounds, None, ColorF::new(1.0, 0.0, 0.0, 1.0));
@@ -46,6 +46,8 @@ impl Example for App {
                                       TransformStyle::Flat,
                                       None,
                                       MixBlendMode::Normal,
+                                      PixelSnapping::Auto,
+                                      false,
                                       Vec::new());
 
         let image_size = LayoutSize::new(100.0, 100.0);
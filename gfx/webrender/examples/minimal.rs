@@ -0,0 +1,59 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The smallest complete embedding of the renderer: the boilerplate harness
+//! owns the window, GL context, swapchain resizing, notifier and main loop,
+//! and this example only supplies a display list. Use it as the starting
+//! point for minimal reproductions of rendering bugs.
+
+extern crate gleam;
+extern crate glutin;
+extern crate webrender;
+
+#[path="common/boilerplate.rs"]
+mod boilerplate;
+
+use boilerplate::{Example, HandyDandyRectBuilder};
+use webrender::api::*;
+
+struct Minimal;
+
+impl Example for Minimal {
+    fn render(&mut self,
+              _api: &RenderApi,
+              builder: &mut DisplayListBuilder,
+              _resources: &mut ResourceUpdates,
+              layout_size: LayoutSize,
+              pipeline_id: PipelineId,
+              _document_id: DocumentId) {
+        let bounds = LayoutRect::new(LayoutPoint::zero(), layout_size);
+        builder.push_stacking_context(ScrollPolicy::Scrollable,
+                                      bounds,
+                                      None,
+                                      TransformStyle::Flat,
+                                      None,
+                                      MixBlendMode::Normal,
+                                      PixelSnapping::Auto,
+                                      false,
+                                      Vec::new());
+
+        builder.push_rect((100, 100).by(100, 100),
+                          None,
+                          ColorF::new(0.0, 1.0, 0.0, 1.0));
+
+        builder.pop_stacking_context();
+    }
+
+    fn on_event(&mut self,
+                _event: glutin::Event,
+                _api: &RenderApi,
+                _document_id: DocumentId) -> bool {
+        false
+    }
+}
+
+fn main() {
+    let mut minimal = Minimal;
+    boilerplate::main_wrapper(&mut minimal, None);
+}
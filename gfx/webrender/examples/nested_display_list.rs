@@ -31,6 +31,8 @@ impl Example for App {
                                       TransformStyle::Flat,
                                       None,
                                       MixBlendMode::Normal,
+                                      PixelSnapping::Auto,
+                                      false,
                                       Vec::new());
 
         let outer_scroll_frame_rect = (100, 100).to(600, 400);
@@ -58,6 +60,8 @@ impl Example for App {
                                       TransformStyle::Flat,
                                       None,
                                       MixBlendMode::Normal,
+                                      PixelSnapping::Auto,
+                                      false,
                                       Vec::new());
         let rect = (0, 0).to(100, 100);
         builder3.push_rect(rect, None, ColorF::new(0.0, 1.0, 0.0, 1.0));
@@ -31,6 +31,8 @@ impl Example for App {
                                       TransformStyle::Flat,
                                       None,
                                       MixBlendMode::Normal,
+                                      PixelSnapping::Auto,
+                                      false,
                                       Vec::new());
 
         if true {   // scrolling and clips stuff
@@ -43,6 +45,8 @@ impl Example for App {
                                           TransformStyle::Flat,
                                           None,
                                           MixBlendMode::Normal,
+                                          PixelSnapping::Auto,
+                                          false,
                                           Vec::new());
             // set the scrolling clip
             let clip_id = builder.define_scroll_frame(None,
@@ -0,0 +1,71 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Compiles every shader permutation that `Renderer::new` can build
+//! against the ANGLE translator, without needing a GPU or a window, and
+//! reports the failures. The same check runs as a cargo test in
+//! `tests/angle_shader_validation.rs`; this binary exists so that shader
+//! changes can be checked quickly from the command line:
+//!
+//! ```sh
+//! cargo run --example validate_shaders
+//! ```
+
+extern crate angle;
+extern crate webrender;
+
+use angle::hl::{BuiltInResources, Output, ShaderSpec, ShaderValidator};
+use std::process;
+use webrender::renderer::{self, ShaderStage};
+
+// from glslang
+const FRAGMENT_SHADER: u32 = 0x8B30;
+const VERTEX_SHADER: u32 = 0x8B31;
+
+fn main() {
+    angle::hl::initialize().unwrap();
+
+    // ANGLE's ESSL3 translator has no rectangle or external texture
+    // support, so those image permutations can't be validated here.
+    let permutations: Vec<_> = renderer::shader_permutations()
+        .into_iter()
+        .filter(|permutation| {
+            permutation.features.iter().all(|feature| {
+                *feature != "TEXTURE_RECT" && *feature != "TEXTURE_EXTERNAL"
+            })
+        })
+        .collect();
+
+    println!("Validating {} shader permutations...", permutations.len());
+
+    let errors = renderer::validate_shaders(&permutations, |stage, source| {
+        let gl_type = match stage {
+            ShaderStage::Vertex => VERTEX_SHADER,
+            ShaderStage::Fragment => FRAGMENT_SHADER,
+        };
+        let resources = BuiltInResources::default();
+        let validator = ShaderValidator::new(gl_type,
+                                             ShaderSpec::Gles3,
+                                             Output::Essl,
+                                             &resources).unwrap();
+
+        let full_source = format!("#version 300 es\n{}", source);
+        match validator.compile_and_translate(&[&full_source]) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(validator.info_log()),
+        }
+    });
+
+    for error in &errors {
+        println!("Shader validation failed: {} {:?} ({:?} stage)\n{}",
+                 error.name, error.features, error.stage, error.log);
+    }
+
+    if errors.is_empty() {
+        println!("All shaders validated successfully.");
+    } else {
+        println!("{} shader stage(s) failed validation.", errors.len());
+        process::exit(1);
+    }
+}
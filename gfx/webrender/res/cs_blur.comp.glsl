@@ -0,0 +1,91 @@
+#line 1
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+// Compute variant of cs_blur. Each invocation computes one pixel of the
+// blur task rect and writes it with image store, so no render target
+// needs to be bound while the cache texture is filled in. The math must
+// stay in sync with cs_blur.vs/.fs.
+
+#define DIR_HORIZONTAL  0
+#define DIR_VERTICAL    1
+
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+// One layer of the color cache texture being rendered to.
+layout(binding = 0) writeonly uniform highp image2D uDestImage;
+
+// uData.x = blur render task index
+// uData.y = source render task index
+// uData.z = blur direction
+
+float gauss(float x, float sigma) {
+    return (1.0 / sqrt(6.283185307179586 * sigma * sigma)) * exp(-(x * x) / (2.0 * sigma * sigma));
+}
+
+void main(void) {
+    RenderTaskData task = fetch_render_task(uData.x);
+    RenderTaskData src_task = fetch_render_task(uData.y);
+
+    ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+    if (pixel.x >= int(task.data0.z) || pixel.y >= int(task.data0.w)) {
+        return;
+    }
+    ivec2 dest_pos = ivec2(task.data0.xy) + pixel;
+
+    vec2 texture_size = vec2(textureSize(sCacheRGBA8, 0).xy);
+    float src_layer = src_task.data1.x;
+    int blur_radius = int(task.data1.y);
+    float sigma = task.data1.y * 0.5;
+
+    vec2 offset_scale;
+    switch (uData.z) {
+        case DIR_HORIZONTAL:
+            offset_scale = vec2(1.0 / texture_size.x, 0.0);
+            break;
+        case DIR_VERTICAL:
+            offset_scale = vec2(0.0, 1.0 / texture_size.y);
+            break;
+    }
+
+    vec4 uv_rect = vec4(src_task.data0.xy + vec2(0.5),
+                        src_task.data0.xy + src_task.data0.zw - vec2(0.5));
+    uv_rect /= texture_size.xyxy;
+
+    // Sample at the center of the texel that maps to this task pixel,
+    // where the fragment variant samples at the fragment center.
+    vec2 uv = (src_task.data0.xy + vec2(pixel) + vec2(0.5)) / texture_size;
+
+    vec4 cache_sample = textureLod(sCacheRGBA8, vec3(uv, src_layer), 0.0);
+
+    // TODO(gw): The gauss function gets NaNs when blur radius
+    //           is zero. In the future, detect this earlier
+    //           and skip the blur passes completely.
+    if (blur_radius == 0) {
+        imageStore(uDestImage, dest_pos, cache_sample);
+        return;
+    }
+
+    vec4 color = vec4(cache_sample.rgb, 1.0) * (cache_sample.a * gauss(0.0, sigma));
+
+    for (int i=1 ; i < blur_radius ; ++i) {
+        vec2 offset = vec2(float(i)) * offset_scale;
+
+        vec2 st0 = clamp(uv + offset, uv_rect.xy, uv_rect.zw);
+        vec4 color0 = textureLod(sCacheRGBA8, vec3(st0, src_layer), 0.0);
+
+        vec2 st1 = clamp(uv - offset, uv_rect.xy, uv_rect.zw);
+        vec4 color1 = textureLod(sCacheRGBA8, vec3(st1, src_layer), 0.0);
+
+        // Alpha must be premultiplied in order to properly blur the alpha channel.
+        float weight = gauss(float(i), sigma);
+        color += vec4(color0.rgb * color0.a, color0.a) * weight;
+        color += vec4(color1.rgb * color1.a, color1.a) * weight;
+    }
+
+    // Unpremultiply the alpha.
+    color.rgb /= color.a;
+
+    imageStore(uDestImage, dest_pos, color);
+}
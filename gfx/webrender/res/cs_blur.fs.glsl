@@ -15,7 +15,7 @@ float gauss(float x, float sigma) {
 }
 
 void main(void) {
-    vec4 cache_sample = texture(sCacheRGBA8, vUv);
+    vec4 cache_sample = texture(sCacheRGBA8, cache_uv(vUv));
 
     // TODO(gw): The gauss function gets NaNs when blur radius
     //           is zero. In the future, detect this earlier
@@ -31,10 +31,10 @@ void main(void) {
         vec2 offset = vec2(float(i)) * vOffsetScale;
 
         vec2 st0 = clamp(vUv.xy + offset, vUvRect.xy, vUvRect.zw);
-        vec4 color0 = texture(sCacheRGBA8, vec3(st0, vUv.z));
+        vec4 color0 = texture(sCacheRGBA8, cache_uv(vec3(st0, vUv.z)));
 
         vec2 st1 = clamp(vUv.xy - offset, vUvRect.xy, vUvRect.zw);
-        vec4 color1 = texture(sCacheRGBA8, vec3(st1, vUv.z));
+        vec4 color1 = texture(sCacheRGBA8, cache_uv(vec3(st1, vUv.z)));
 
         // Alpha must be premultiplied in order to properly blur the alpha channel.
         float weight = gauss(float(i), vSigma);
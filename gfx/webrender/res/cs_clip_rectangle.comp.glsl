@@ -0,0 +1,220 @@
+#line 1
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+// Compute variant of cs_clip_rectangle. One dispatch covers a whole clip
+// task: each invocation evaluates every rectangle instance of the task
+// for its pixel and multiplies the results in registers, replacing the
+// multiplicative blend the raster path relies on. The math must stay in
+// sync with cs_clip_rectangle.vs/.fs and clip_shared.glsl.
+
+#define SEGMENT_ALL         0
+#define SEGMENT_CORNER_TL   1
+#define SEGMENT_CORNER_TR   2
+#define SEGMENT_CORNER_BL   3
+#define SEGMENT_CORNER_BR   4
+
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+// One layer of the clip mask cache texture being rendered to.
+layout(binding = 0) writeonly uniform highp image2D uDestImage;
+
+uniform float uDevicePixelRatio;
+
+// uData.x = clip render task index
+// uData.w = number of entries in uInstances
+
+// xyz = (layer index, clip data address, segment index) per instance.
+uniform ivec4 uInstances[WR_MAX_COMPUTE_CLIP_INSTANCES];
+
+struct ClipRect {
+    RectWithSize rect;
+    vec4 mode;
+};
+
+ClipRect fetch_clip_rect(int index) {
+    vec4 data[2] = fetch_from_resource_cache_2(index);
+    return ClipRect(RectWithSize(data[0].xy, data[0].zw), data[1]);
+}
+
+struct ClipCorner {
+    RectWithSize rect;
+    vec4 outer_inner_radius;
+};
+
+ClipCorner fetch_clip_corner(int index) {
+    vec4 data[2] = fetch_from_resource_cache_2(index);
+    return ClipCorner(RectWithSize(data[0].xy, data[0].zw), data[1]);
+}
+
+struct ClipData {
+    ClipRect rect;
+    ClipCorner top_left;
+    ClipCorner top_right;
+    ClipCorner bottom_left;
+    ClipCorner bottom_right;
+};
+
+ClipData fetch_clip(int index) {
+    ClipData clip;
+
+    clip.rect = fetch_clip_rect(index + 0);
+    clip.top_left = fetch_clip_corner(index + 2);
+    clip.top_right = fetch_clip_corner(index + 4);
+    clip.bottom_left = fetch_clip_corner(index + 6);
+    clip.bottom_right = fetch_clip_corner(index + 8);
+
+    return clip;
+}
+
+vec2 compute_local_pos(vec2 screen_pos, Layer layer) {
+    vec4 layer_pos = get_layer_pos(screen_pos / uDevicePixelRatio, layer);
+    return layer_pos.xy / layer_pos.w;
+}
+
+float signed_distance_rect(vec2 pos, vec2 p0, vec2 p1) {
+    vec2 d = max(p0 - pos, pos - p1);
+    return length(max(vec2(0.0), d)) + min(0.0, max(d.x, d.y));
+}
+
+float clip_against_ellipse_if_needed(vec2 pos,
+                                     float current_distance,
+                                     vec4 ellipse_center_radius,
+                                     vec2 sign_modifier,
+                                     float afwidth) {
+    float ellipse_distance = distance_to_ellipse(pos - ellipse_center_radius.xy,
+                                                 ellipse_center_radius.zw);
+
+    return mix(current_distance,
+               ellipse_distance + afwidth,
+               all(lessThan(sign_modifier * pos, sign_modifier * ellipse_center_radius.xy)));
+}
+
+float rounded_rect(vec2 pos, ClipData clip, float afwidth) {
+    RectWithEndpoint clip_rect = to_rect_with_endpoint(clip.rect.rect);
+    float current_distance = 0.0;
+
+    // Clip against each ellipse.
+    current_distance = clip_against_ellipse_if_needed(pos,
+                                                      current_distance,
+                                                      vec4(clip_rect.p0 + clip.top_left.outer_inner_radius.xy,
+                                                           clip.top_left.outer_inner_radius.xy),
+                                                      vec2(1.0),
+                                                      afwidth);
+
+    current_distance = clip_against_ellipse_if_needed(pos,
+                                                      current_distance,
+                                                      vec4(clip_rect.p1.x - clip.top_right.outer_inner_radius.x,
+                                                           clip_rect.p0.y + clip.top_right.outer_inner_radius.y,
+                                                           clip.top_right.outer_inner_radius.xy),
+                                                      vec2(-1.0, 1.0),
+                                                      afwidth);
+
+    current_distance = clip_against_ellipse_if_needed(pos,
+                                                      current_distance,
+                                                      vec4(clip_rect.p1 - clip.bottom_right.outer_inner_radius.xy,
+                                                           clip.bottom_right.outer_inner_radius.xy),
+                                                      vec2(-1.0),
+                                                      afwidth);
+
+    current_distance = clip_against_ellipse_if_needed(pos,
+                                                      current_distance,
+                                                      vec4(clip_rect.p0.x + clip.bottom_left.outer_inner_radius.x,
+                                                           clip_rect.p1.y - clip.bottom_left.outer_inner_radius.y,
+                                                           clip.bottom_left.outer_inner_radius.xy),
+                                                      vec2(1.0, -1.0),
+                                                      afwidth);
+
+    return smoothstep(0.0, afwidth, 1.0 - current_distance);
+}
+
+// Evaluates one clip rectangle instance at the given screen space
+// position, returning the alpha it would have written via the
+// multiplicative blend on the raster path.
+float evaluate_instance(vec2 screen_pos, ClipArea area, ivec4 instance) {
+    Layer layer = fetch_layer(instance.x);
+    ClipData clip = fetch_clip(instance.y);
+
+    // The raster path only touches the pixels of the segment quad; any
+    // pixel outside it must be left untouched, i.e. multiplied by one.
+    vec2 outer_p0 = area.screen_origin_target_index.xy;
+    vec2 outer_p1 = outer_p0 + area.task_bounds.zw - area.task_bounds.xy;
+    vec2 inner_p0 = area.inner_rect.xy;
+    vec2 inner_p1 = area.inner_rect.zw;
+
+    vec2 p0, p1;
+    switch (instance.z) {
+        case SEGMENT_ALL:
+            p0 = outer_p0;
+            p1 = outer_p1;
+            break;
+        case SEGMENT_CORNER_TL:
+            p0 = outer_p0;
+            p1 = inner_p0;
+            break;
+        case SEGMENT_CORNER_BL:
+            p0 = vec2(outer_p0.x, outer_p1.y);
+            p1 = vec2(inner_p0.x, inner_p1.y);
+            break;
+        case SEGMENT_CORNER_TR:
+            p0 = vec2(outer_p1.x, outer_p1.y);
+            p1 = vec2(inner_p1.x, inner_p1.y);
+            break;
+        case SEGMENT_CORNER_BR:
+            p0 = vec2(outer_p1.x, outer_p0.y);
+            p1 = vec2(inner_p1.x, inner_p0.y);
+            break;
+    }
+
+    vec4 segment_rect = vec4(min(p0, p1), max(p0, p1));
+    if (any(lessThan(screen_pos, segment_rect.xy)) ||
+        any(greaterThanEqual(screen_pos, segment_rect.zw))) {
+        return 1.0;
+    }
+
+    vec2 local_pos = compute_local_pos(screen_pos, layer);
+
+    // The fragment variant gets this from fwidth(); take the same
+    // derivative manually by untransforming the neighbouring pixels.
+    vec2 local_pos_dx = compute_local_pos(screen_pos + vec2(1.0, 0.0), layer);
+    vec2 local_pos_dy = compute_local_pos(screen_pos + vec2(0.0, 1.0), layer);
+    vec2 fw = abs(local_pos_dx - local_pos) + abs(local_pos_dy - local_pos);
+    float afwidth = 0.5 * length(fw);
+
+    // Apply AA against the clipped local rect, as init_transform_fs does.
+    RectWithSize clipped_local_rect = intersect_rect(clip.rect.rect,
+                                                     layer.local_clip_rect);
+    float d = signed_distance_rect(local_pos,
+                                   clipped_local_rect.p0,
+                                   clipped_local_rect.p0 + clipped_local_rect.size);
+    float alpha = 1.0 - smoothstep(0.0, afwidth, d);
+
+    float clip_alpha = rounded_rect(local_pos, clip, afwidth);
+
+    float combined_alpha = min(alpha, clip_alpha);
+
+    // Select alpha or inverse alpha depending on clip in/out.
+    return mix(combined_alpha, 1.0 - combined_alpha, clip.rect.mode.x);
+}
+
+void main(void) {
+    ClipArea area = fetch_clip_area(uData.x);
+
+    ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+    vec2 task_size = area.task_bounds.zw - area.task_bounds.xy;
+    if (pixel.x >= int(task_size.x) || pixel.y >= int(task_size.y)) {
+        return;
+    }
+    ivec2 dest_pos = ivec2(area.task_bounds.xy) + pixel;
+
+    vec2 screen_pos = area.screen_origin_target_index.xy + vec2(pixel) + vec2(0.5);
+
+    // The target is cleared to 1.0, which every instance multiplies into.
+    float mask = 1.0;
+    for (int i = 0 ; i < uData.w ; ++i) {
+        mask *= evaluate_instance(screen_pos, area, uInstances[i]);
+    }
+
+    imageStore(uDestImage, dest_pos, vec4(mask, 0.0, 0.0, 1.0));
+}
@@ -0,0 +1,33 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+// Alpha render targets are texture arrays, so the shared sColor0
+// sampler can't be used here. sCacheA8 is bound to the same texture
+// unit the main shaders use for the alpha cache. With the 2D target
+// fallback the targets are plain textures and the layer coordinate
+// is ignored.
+#ifdef WR_FEATURE_TEXTURE_2D_TARGETS
+uniform sampler2D sCacheA8;
+#else
+uniform sampler2DArray sCacheA8;
+#endif
+
+varying vec3 vColorTexCoord;
+
+void main(void)
+{
+#ifdef WR_FEATURE_TEXTURE_2D_TARGETS
+    float value = texture(sCacheA8, vColorTexCoord.xy).r;
+#else
+    float value = texture(sCacheA8, vColorTexCoord).r;
+#endif
+
+    // A blue -> green -> red false color ramp, so that coverage
+    // gradients in the R8 target are visible against the black
+    // cleared background.
+    vec3 color = vec3(clamp(2.0 * value - 1.0, 0.0, 1.0),
+                      1.0 - 2.0 * abs(value - 0.5),
+                      clamp(1.0 - 2.0 * value, 0.0, 1.0));
+    oFragColor = vec4(color, 1.0);
+}
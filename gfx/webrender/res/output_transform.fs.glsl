@@ -0,0 +1,26 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+uniform sampler2D sColor0;
+
+// The row-major output color matrix is uploaded without transposition,
+// so its rows are the columns of this mat3; v * m below applies the
+// rows as intended. GLES2-era drivers reject transposed uploads, which
+// is why the transposition happens here instead.
+uniform mat3 uColorMatrix;
+// x = gamma that decodes rendered values to linear light,
+// y = gamma of the output display.
+uniform vec2 uGamma;
+
+varying vec2 vColorTexCoord;
+
+void main(void)
+{
+    vec4 texel = texture(sColor0, vColorTexCoord);
+    vec3 rgb = pow(max(texel.rgb, vec3(0.0)), vec3(uGamma.x));
+    // Gamut mapping can produce out of range values; clamp them before
+    // re-encoding so pow doesn't see a negative base.
+    rgb = clamp(rgb * uColorMatrix, vec3(0.0), vec3(1.0));
+    oFragColor = vec4(pow(rgb, vec3(1.0 / uGamma.y)), texel.a);
+}
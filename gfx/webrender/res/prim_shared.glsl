@@ -51,20 +51,40 @@
 #define UV_NORMALIZED    uint(0)
 #define UV_PIXEL         uint(1)
 
-#define EXTEND_MODE_CLAMP  0
-#define EXTEND_MODE_REPEAT 1
+#define EXTEND_MODE_CLAMP   0
+#define EXTEND_MODE_REPEAT  1
+#define EXTEND_MODE_REFLECT 2
 
 #define LINE_STYLE_SOLID        0
 #define LINE_STYLE_DOTTED       1
 #define LINE_STYLE_DASHED       2
 #define LINE_STYLE_WAVY         3
+#define LINE_STYLE_DOUBLE       4
 
 #define SUBPX_DIR_NONE        0
 #define SUBPX_DIR_HORIZONTAL  1
 #define SUBPX_DIR_VERTICAL    2
 
+#ifdef WR_FEATURE_TEXTURE_2D_TARGETS
+uniform sampler2D sCacheA8;
+uniform sampler2D sCacheRGBA8;
+#else
 uniform sampler2DArray sCacheA8;
 uniform sampler2DArray sCacheRGBA8;
+#endif
+
+// The z coordinate selects the pass target that a task rendered to. On
+// devices that can't render to texture array layers each target is its
+// own 2D texture, so the coordinate collapses to plain UVs.
+#ifdef WR_FEATURE_TEXTURE_2D_TARGETS
+vec2 cache_uv(vec3 uv_layer) {
+    return uv_layer.xy;
+}
+#else
+vec3 cache_uv(vec3 uv_layer) {
+    return uv_layer;
+}
+#endif
 
 uniform sampler2D sGradients;
 
@@ -254,6 +274,8 @@ struct AlphaBatchTask {
     vec2 render_target_origin;
     vec2 size;
     float render_target_layer_index;
+    float rasterization_scale;        // < 1.0 when the task was too large for
+                                      //     the target and rendered scaled down.
 };
 
 AlphaBatchTask fetch_alpha_batch_task(int index) {
@@ -264,6 +286,7 @@ AlphaBatchTask fetch_alpha_batch_task(int index) {
     task.size = data.data0.zw;
     task.screen_space_origin = data.data1.xy;
     task.render_target_layer_index = data.data1.z;
+    task.rasterization_scale = data.data1.w;
 
     return task;
 }
@@ -624,6 +647,25 @@ vec2 compute_snap_offset(vec2 local_pos,
     return mix(snap_offsets.xy, snap_offsets.zw, normalized_snap_pos);
 }
 
+// Snap a border or line width to a whole number of device pixels, with
+// a minimum of one. At fractional device pixel ratios an unsnapped
+// hairline covers a fractional span of pixels and either disappears or
+// doubles in width depending on where it lands on the pixel grid.
+// Zero stays zero so that absent border edges aren't given a width.
+float snap_width(float width) {
+    if (width <= 0.0) {
+        return 0.0;
+    }
+    return max(floor(width * uDevicePixelRatio + 0.5), 1.0) / uDevicePixelRatio;
+}
+
+// The fraction of a snapped width that the original width covers, used
+// to scale a color's alpha so widths below one device pixel keep their
+// apparent weight.
+float snap_width_coverage(float width, float snapped_width) {
+    return snapped_width > 0.0 ? min(width / snapped_width, 1.0) : 1.0;
+}
+
 struct VertexInfo {
     vec2 local_pos;
     vec2 screen_pos;
@@ -653,8 +695,8 @@ VertexInfo write_vertex(RectWithSize instance_rect,
     vec2 device_pos = world_pos.xy / world_pos.w * uDevicePixelRatio;
 
     // Apply offsets for the render task to get correct screen location.
-    vec2 final_pos = device_pos + snap_offset -
-                     task.screen_space_origin +
+    vec2 final_pos = (device_pos + snap_offset - task.screen_space_origin) *
+                     task.rasterization_scale +
                      task.render_target_origin;
 
     gl_Position = uTransform * vec4(final_pos, z, 1.0);
@@ -756,8 +798,8 @@ TransformVertexInfo write_transform_vertex(RectWithSize instance_rect,
     vec4 layer_pos = get_layer_pos(device_pos / uDevicePixelRatio, layer);
 
     // Apply offsets for the render task to get correct screen location.
-    vec2 final_pos = device_pos - //Note: `snap_rect` is not used
-                     task.screen_space_origin +
+    vec2 final_pos = (device_pos - task.screen_space_origin) * //Note: `snap_rect` is not used
+                     task.rasterization_scale +
                      task.render_target_origin;
 
     gl_Position = uTransform * vec4(final_pos, z, 1.0);
@@ -834,11 +876,13 @@ struct Image {
     vec4 stretch_size_and_tile_spacing;  // Size of the actual image and amount of space between
                                          //     tiled instances of this image.
     vec4 sub_rect;                          // If negative, ignored.
+    vec4 color;                          // Modulation color; only the alpha
+                                         //     channel is currently used.
 };
 
 Image fetch_image(int address) {
-    vec4 data[2] = fetch_from_resource_cache_2(address);
-    return Image(data[0], data[1]);
+    vec4 data[3] = fetch_from_resource_cache_3(address);
+    return Image(data[0], data[1], data[2]);
 }
 
 struct YuvImage {
@@ -902,15 +946,23 @@ float do_clip() {
         vec4(vClipMaskUv.xy, vClipMaskUvBounds.zw));
     // check for the dummy bounds, which are given to the opaque objects
     return vClipMaskUvBounds.xy == vClipMaskUvBounds.zw ? 1.0:
-        all(inside) ? textureLod(sCacheA8, vClipMaskUv, 0.0).r : 0.0;
+        all(inside) ? textureLod(sCacheA8, cache_uv(vClipMaskUv), 0.0).r : 0.0;
 }
 
 #ifdef WR_FEATURE_DITHERING
 vec4 dither(vec4 color) {
+#ifdef WR_FEATURE_BLUE_NOISE_DITHERING
+    // The blue noise matrix is 64x64, using the full 8 bit range.
+    const int matrix_mask = 63;
+    const float matrix_levels = 256.0;
+#else
+    // The Bayer matrix is 8x8, with 64 levels.
     const int matrix_mask = 7;
+    const float matrix_levels = 64.0;
+#endif
 
     ivec2 pos = ivec2(gl_FragCoord.xy) & ivec2(matrix_mask);
-    float noise_normalized = (texelFetch(sDither, pos, 0).r * 255.0 + 0.5) / 64.0;
+    float noise_normalized = (texelFetch(sDither, pos, 0).r * 255.0 + 0.5) / matrix_levels;
     float noise = (noise_normalized - 0.5) / 256.0; // scale down to the unit length
 
     return color + vec4(noise, noise, noise, 0);
@@ -921,9 +973,20 @@ vec4 dither(vec4 color) {
 }
 #endif //WR_FEATURE_DITHERING
 
-vec4 sample_gradient(int address, float offset, float gradient_repeat) {
-    // Modulo the offset if the gradient repeats.
-    float x = mix(offset, fract(offset), gradient_repeat);
+vec4 sample_gradient(int address, float offset, int extend_mode) {
+    // Apply the spread method. Repeat tiles the [0, 1) stop range, while
+    // reflect mirrors every other repetition.
+    float x = offset;
+    switch (extend_mode) {
+        case EXTEND_MODE_REPEAT:
+            x = fract(x);
+            break;
+        case EXTEND_MODE_REFLECT:
+            x = 1.0 - abs(fract(x * 0.5) * 2.0 - 1.0);
+            break;
+        default:
+            break;
+    }
 
     // Calculate the color entry index to use for this offset:
     //     offsets < 0 use the first color entry, 0
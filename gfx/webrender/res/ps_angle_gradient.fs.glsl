@@ -16,5 +16,5 @@ void main(void) {
 
     oFragColor = sample_gradient(vGradientAddress,
                                  offset,
-                                 vGradientRepeat);
+                                 vGradientExtendMode);
 }
@@ -3,7 +3,7 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 flat varying int vGradientAddress;
-flat varying float vGradientRepeat;
+flat varying int vGradientExtendMode;
 
 flat varying vec2 vScaledDir;
 flat varying vec2 vStartPoint;
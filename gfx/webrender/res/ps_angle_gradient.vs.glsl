@@ -26,8 +26,10 @@ void main(void) {
     vTileSize = gradient.tile_size_repeat.xy;
     vTileRepeat = gradient.tile_size_repeat.zw;
 
-    vGradientAddress = prim.specific_prim_address + VECS_PER_GRADIENT;
+    // The gradient data table lives in a shared block, so gradients with
+    // identical stops can sample one table. See prepare_prim_for_render.
+    vGradientAddress = prim.user_data0;
 
-    // Whether to repeat the gradient instead of clamping.
-    vGradientRepeat = float(int(gradient.extend_mode.x) == EXTEND_MODE_REPEAT);
+    // How the gradient is extended outside the [0, 1] stop range.
+    vGradientExtendMode = int(gradient.extend_mode.x);
 }
@@ -100,7 +100,7 @@ vec4 Opacity(vec4 Cs, float amount) {
 
 void main(void) {
     vec2 uv = clamp(vUv.xy, vUvBounds.xy, vUvBounds.zw);
-    vec4 Cs = textureLod(sCacheRGBA8, vec3(uv, vUv.z), 0.0);
+    vec4 Cs = textureLod(sCacheRGBA8, cache_uv(vec3(uv, vUv.z)), 0.0);
 
     if (Cs.a == 0.0) {
         discard;
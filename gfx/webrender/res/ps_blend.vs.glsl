@@ -8,12 +8,16 @@ void main(void) {
     AlphaBatchTask dest_task = fetch_alpha_batch_task(ci.render_task_index);
     AlphaBatchTask src_task = fetch_alpha_batch_task(ci.src_task_index);
 
-    vec2 dest_origin = dest_task.render_target_origin -
-                       dest_task.screen_space_origin +
-                       src_task.screen_space_origin;
+    // The source may have been rendered at a reduced scale; its screen
+    // footprint is its size divided by that scale, and positions in the
+    // destination task are scaled by the destination's own factor.
+    vec2 src_size = src_task.size / src_task.rasterization_scale;
+    vec2 dest_origin = dest_task.render_target_origin +
+                       (src_task.screen_space_origin - dest_task.screen_space_origin) *
+                       dest_task.rasterization_scale;
 
     vec2 local_pos = mix(dest_origin,
-                         dest_origin + src_task.size,
+                         dest_origin + src_size * dest_task.rasterization_scale,
                          aPosition.xy);
 
     vec2 texture_size = vec2(textureSize(sCacheRGBA8, 0));
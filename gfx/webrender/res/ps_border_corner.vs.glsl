@@ -119,6 +119,19 @@ void main(void) {
     Primitive prim = load_primitive();
     Border border = fetch_border(prim.specific_prim_address);
     int sub_part = prim.user_data0;
+
+    // Match the width snapping done by ps_border_edge, so the corner
+    // geometry lines up with the snapped edges and the adjacent edge
+    // colors carry the same sub-pixel coverage in their alpha.
+    vec4 original_widths = border.widths;
+    border.widths = vec4(snap_width(original_widths.x),
+                         snap_width(original_widths.y),
+                         snap_width(original_widths.z),
+                         snap_width(original_widths.w));
+    for (int i = 0; i < 4; i++) {
+        border.colors[i].a *= snap_width_coverage(original_widths[i], border.widths[i]);
+    }
+
     BorderCorners corners = get_border_corners(border, prim.local_rect);
 
     vec2 p0, p1;
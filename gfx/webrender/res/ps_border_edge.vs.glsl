@@ -130,6 +130,20 @@ void main(void) {
     Primitive prim = load_primitive();
     Border border = fetch_border(prim.specific_prim_address);
     int sub_part = prim.user_data0;
+
+    // Snap the border widths to whole device pixels before any geometry
+    // is derived from them, so hairline edges neither disappear nor
+    // double in width at fractional device pixel ratios. Widths below
+    // one device pixel keep their coverage through the color's alpha.
+    vec4 original_widths = border.widths;
+    border.widths = vec4(snap_width(original_widths.x),
+                         snap_width(original_widths.y),
+                         snap_width(original_widths.z),
+                         snap_width(original_widths.w));
+    for (int i = 0; i < 4; i++) {
+        border.colors[i].a *= snap_width_coverage(original_widths[i], border.widths[i]);
+    }
+
     BorderCorners corners = get_border_corners(border, prim.local_rect);
     vec4 color = border.colors[sub_part];
 
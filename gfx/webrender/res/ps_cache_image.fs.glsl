@@ -5,5 +5,5 @@
 
 void main(void) {
     vec2 uv = clamp(vUv.xy, vUvBounds.xy, vUvBounds.zw);
-    oFragColor = texture(sCacheRGBA8, vec3(uv, vUv.z));
+    oFragColor = texture(sCacheRGBA8, cache_uv(vec3(uv, vUv.z)));
 }
@@ -168,8 +168,8 @@ const int MixBlendMode_Color       = 14;
 const int MixBlendMode_Luminosity  = 15;
 
 void main(void) {
-    vec4 Cb = texture(sCacheRGBA8, vUv0);
-    vec4 Cs = texture(sCacheRGBA8, vUv1);
+    vec4 Cb = texture(sCacheRGBA8, cache_uv(vUv0));
+    vec4 Cs = texture(sCacheRGBA8, cache_uv(vUv1));
 
     // The mix-blend-mode functions assume no premultiplied alpha
     Cb.rgb /= Cb.a;
@@ -3,5 +3,7 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 void main(void) {
-    oFragColor = texture(sCacheRGBA8, vUv);
+    // The cache contents are premultiplied, so the opacity scales every
+    // channel uniformly.
+    oFragColor = vOpacity * texture(sCacheRGBA8, cache_uv(vUv));
 }
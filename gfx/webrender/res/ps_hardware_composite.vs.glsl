@@ -8,18 +8,23 @@ void main(void) {
     AlphaBatchTask dest_task = fetch_alpha_batch_task(ci.render_task_index);
     AlphaBatchTask src_task = fetch_alpha_batch_task(ci.src_task_index);
 
-    vec2 dest_origin = dest_task.render_target_origin -
-                       dest_task.screen_space_origin +
-                       src_task.screen_space_origin;
+    // The source may have been rendered at a reduced scale; its screen
+    // footprint is its size divided by that scale, and positions in the
+    // destination task are scaled by the destination's own factor.
+    vec2 src_size = src_task.size / src_task.rasterization_scale;
+    vec2 dest_origin = dest_task.render_target_origin +
+                       (src_task.screen_space_origin - dest_task.screen_space_origin) *
+                       dest_task.rasterization_scale;
 
     vec2 local_pos = mix(dest_origin,
-                         dest_origin + src_task.size,
+                         dest_origin + src_size * dest_task.rasterization_scale,
                          aPosition.xy);
 
     vec2 texture_size = vec2(textureSize(sCacheRGBA8, 0));
     vec2 st0 = src_task.render_target_origin / texture_size;
     vec2 st1 = (src_task.render_target_origin + src_task.size) / texture_size;
     vUv = vec3(mix(st0, st1, aPosition.xy), src_task.render_target_layer_index);
+    vOpacity = float(ci.user_data0) / 65535.0;
 
     gl_Position = uTransform * vec4(local_pos, ci.z, 1.0);
 }
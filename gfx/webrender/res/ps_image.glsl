@@ -9,6 +9,7 @@ flat varying vec2 vTextureOffset; // Offset of this image into the texture atlas
 flat varying vec2 vTextureSize;   // Size of the image in the texture atlas.
 flat varying vec2 vTileSpacing;   // Amount of space between tiled instances of this image.
 flat varying vec4 vStRect;        // Rectangle of valid texture rect.
+flat varying float vOpacity;      // Opacity folded in from a collapsed stacking context.
 
 #ifdef WR_FEATURE_TRANSFORM
 varying vec3 vLocalPos;
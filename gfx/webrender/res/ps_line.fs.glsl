@@ -117,6 +117,21 @@ void main(void) {
                                      d);
             break;
         }
+        case LINE_STYLE_DOUBLE: {
+            // Cross-axis offset within the line rect.
+            float y = pos.y - vLocalOrigin.y;
+
+            // Covered by the first line, above the gap...
+            float first = 1.0 - smoothstep(vParams.x - 0.5 * afwidth,
+                                           vParams.x + 0.5 * afwidth,
+                                           y);
+            // ...or by the second line, below it.
+            float second = smoothstep(vParams.y - 0.5 * afwidth,
+                                      vParams.y + 0.5 * afwidth,
+                                      y);
+            alpha = min(alpha, max(first, second));
+            break;
+        }
     }
 
     oFragColor = vColor * vec4(1.0, 1.0, 1.0, alpha);
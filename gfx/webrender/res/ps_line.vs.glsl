@@ -10,6 +10,20 @@ void main(void) {
     Primitive prim = load_primitive();
     Line line = fetch_line(prim.specific_prim_address);
 
+    // Snap the line thickness to a whole number of device pixels, one
+    // at minimum, so hairlines stay visible and uniform at fractional
+    // device pixel ratios. Thicknesses below one device pixel keep
+    // their apparent weight by scaling the color's alpha instead.
+    if (int(line.orientation) == LINE_ORIENTATION_VERTICAL) {
+        float thickness = prim.local_rect.size.x;
+        prim.local_rect.size.x = snap_width(thickness);
+        line.color.a *= snap_width_coverage(thickness, prim.local_rect.size.x);
+    } else {
+        float thickness = prim.local_rect.size.y;
+        prim.local_rect.size.y = snap_width(thickness);
+        line.color.a *= snap_width_coverage(thickness, prim.local_rect.size.y);
+    }
+
     vec2 pos, size;
 
     switch (int(line.orientation)) {
@@ -63,11 +77,27 @@ void main(void) {
             // Choose some arbitrary values to scale thickness,
             // wave period etc.
             // TODO(gw): Tune these to get closer to what Gecko uses.
-            float thickness = 0.15 * size.y;
+            // The stroke thickness and the half-period are snapped to
+            // whole device pixels, so every wave covers the same pixel
+            // span and renders uniformly at fractional device pixel
+            // ratios.
+            float thickness = snap_width(0.15 * size.y);
             vParams = vec4(thickness,
                            size.y * 0.5,
                            size.y * 0.75,
-                           size.y * 0.5);
+                           snap_width(size.y * 0.5));
+            break;
+        }
+        case LINE_STYLE_DOUBLE: {
+            // Two lines of a third of the total thickness each, like
+            // Gecko's double text decorations, snapped to whole device
+            // pixels so both lines and the gap between them survive
+            // fractional device pixel ratios.
+            float line_thickness = snap_width(size.y / 3.0);
+            vParams = vec4(line_thickness,
+                           max(size.y - line_thickness, line_thickness),
+                           0.0,
+                           0.0);
             break;
         }
     }
@@ -51,5 +51,5 @@ void main(void) {
 
     oFragColor = sample_gradient(vGradientAddress,
                                  offset,
-                                 vGradientRepeat);
+                                 vGradientExtendMode);
 }
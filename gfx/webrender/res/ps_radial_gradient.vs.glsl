@@ -34,8 +34,10 @@ void main(void) {
     vTileSize.y *= ratio_xy;
     vTileRepeat.y *= ratio_xy;
 
-    vGradientAddress = prim.specific_prim_address + VECS_PER_GRADIENT;
+    // The gradient data table lives in a shared block, so gradients with
+    // identical stops can sample one table. See prepare_prim_for_render.
+    vGradientAddress = prim.user_data0;
 
-    // Whether to repeat the gradient instead of clamping.
-    vGradientRepeat = float(int(gradient.start_end_radius_ratio_xy_extend_mode.w) == EXTEND_MODE_REPEAT);
+    // How the gradient is extended outside the [0, 1] stop range.
+    vGradientExtendMode = int(gradient.start_end_radius_ratio_xy_extend_mode.w);
 }
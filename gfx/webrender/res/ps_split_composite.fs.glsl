@@ -8,7 +8,7 @@ void main(void) {
                                  vec4(vUv.xy, vUvTaskBounds.zw));
     if (all(inside)) {
         vec2 uv = clamp(vUv.xy, vUvSampleBounds.xy, vUvSampleBounds.zw);
-        oFragColor = textureLod(sCacheRGBA8, vec3(uv, vUv.z), 0.0);
+        oFragColor = textureLod(sCacheRGBA8, cache_uv(vec3(uv, vUv.z)), 0.0);
     } else {
         oFragColor = vec4(0.0);
     }
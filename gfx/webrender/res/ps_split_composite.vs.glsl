@@ -41,7 +41,8 @@ void main(void) {
     gl_Position = uTransform * final_pos;
 
     vec2 uv_origin = src_task.render_target_origin;
-    vec2 uv_pos = uv_origin + world_pos.xy - src_task.screen_space_origin;
+    vec2 uv_pos = uv_origin + (world_pos.xy - src_task.screen_space_origin) *
+                  src_task.rasterization_scale;
     vec2 texture_size = vec2(textureSize(sCacheRGBA8, 0));
     vUv = vec3(uv_pos / texture_size, src_task.render_target_layer_index);
     vUvTaskBounds = vec4(uv_origin, uv_origin + src_task.size) / texture_size.xyxy;
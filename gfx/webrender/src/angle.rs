@@ -0,0 +1,127 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Integration points for running on top of ANGLE on Windows.
+//!
+//! WebRender itself doesn't link against EGL; the embedder resolves the
+//! handful of entry points below with eglGetProcAddress and hands them
+//! over. With those in place this module can
+//!
+//!  * query the ID3D11Device ANGLE renders with (EGL_EXT_device_query),
+//!    so video and WebGL textures can be created on the same device, and
+//!  * wrap a D3D11 shared handle in an EGLImage, which the device then
+//!    binds to an external texture without copying the pixels.
+
+use std::os::raw::c_void;
+use std::ptr;
+
+pub type EGLDisplay = *const c_void;
+pub type EGLDeviceEXT = *const c_void;
+pub type EGLImage = *const c_void;
+pub type EGLClientBuffer = *const c_void;
+pub type EGLAttrib = isize;
+pub type EGLint = i32;
+pub type EGLBoolean = u32;
+
+pub const EGL_TRUE: EGLBoolean = 1;
+pub const EGL_NO_CONTEXT: *const c_void = 0 as *const c_void;
+pub const EGL_NONE: EGLint = 0x3038;
+
+// EGL_EXT_device_query
+pub const EGL_DEVICE_EXT: EGLint = 0x322C;
+// EGL_ANGLE_device_d3d
+pub const EGL_D3D11_DEVICE_ANGLE: EGLint = 0x33A1;
+// EGL_ANGLE_d3d_share_handle_client_buffer
+pub const EGL_D3D_TEXTURE_2D_SHARE_HANDLE_ANGLE: u32 = 0x3200;
+
+pub type EglQueryDisplayAttribEXTFn =
+    unsafe extern "C" fn(dpy: EGLDisplay, attribute: EGLint, value: *mut EGLAttrib) -> EGLBoolean;
+pub type EglQueryDeviceAttribEXTFn =
+    unsafe extern "C" fn(device: EGLDeviceEXT, attribute: EGLint, value: *mut EGLAttrib) -> EGLBoolean;
+pub type EglCreateImageKHRFn =
+    unsafe extern "C" fn(dpy: EGLDisplay,
+                         ctx: *const c_void,
+                         target: u32,
+                         buffer: EGLClientBuffer,
+                         attrib_list: *const EGLint) -> EGLImage;
+pub type EglDestroyImageKHRFn =
+    unsafe extern "C" fn(dpy: EGLDisplay, image: EGLImage) -> EGLBoolean;
+
+/// The EGL entry points the integration needs, resolved by the embedder
+/// with eglGetProcAddress.
+pub struct AngleFunctions {
+    pub query_display_attrib: EglQueryDisplayAttribEXTFn,
+    pub query_device_attrib: EglQueryDeviceAttribEXTFn,
+    pub create_image: EglCreateImageKHRFn,
+    pub destroy_image: EglDestroyImageKHRFn,
+}
+
+/// A handle to the ANGLE display the GL context lives on.
+pub struct AngleDevice {
+    display: EGLDisplay,
+    functions: AngleFunctions,
+}
+
+impl AngleDevice {
+    pub fn new(display: EGLDisplay, functions: AngleFunctions) -> AngleDevice {
+        AngleDevice {
+            display,
+            functions,
+        }
+    }
+
+    /// Returns the ID3D11Device ANGLE renders with, via the
+    /// EGL_EXT_device_query path. The embedder opens shared handles on
+    /// this device so the textures are visible to the compositor context.
+    /// Returns None when the display isn't backed by D3D11.
+    pub fn query_d3d11_device(&self) -> Option<*const c_void> {
+        unsafe {
+            let mut egl_device: EGLAttrib = 0;
+            if (self.functions.query_display_attrib)(self.display,
+                                                     EGL_DEVICE_EXT,
+                                                     &mut egl_device) != EGL_TRUE {
+                return None;
+            }
+
+            let mut d3d_device: EGLAttrib = 0;
+            if (self.functions.query_device_attrib)(egl_device as EGLDeviceEXT,
+                                                    EGL_D3D11_DEVICE_ANGLE,
+                                                    &mut d3d_device) != EGL_TRUE {
+                return None;
+            }
+
+            if d3d_device == 0 {
+                None
+            } else {
+                Some(d3d_device as *const c_void)
+            }
+        }
+    }
+
+    /// Wraps a D3D11 shared handle in an EGLImage. The image can be bound
+    /// to an external texture with `Device::init_texture_from_egl_image`,
+    /// giving the shaders zero-copy access to the D3D texture.
+    pub fn create_image_from_share_handle(&self,
+                                          share_handle: *const c_void) -> Option<EGLImage> {
+        unsafe {
+            let attribs = [EGL_NONE];
+            let image = (self.functions.create_image)(self.display,
+                                                      EGL_NO_CONTEXT,
+                                                      EGL_D3D_TEXTURE_2D_SHARE_HANDLE_ANGLE,
+                                                      share_handle,
+                                                      attribs.as_ptr());
+            if image == ptr::null() {
+                None
+            } else {
+                Some(image)
+            }
+        }
+    }
+
+    pub fn destroy_image(&self, image: EGLImage) {
+        unsafe {
+            (self.functions.destroy_image)(self.display, image);
+        }
+    }
+}
@@ -219,15 +219,23 @@ impl ClipScrollTree {
         self.nodes.get_mut(&clip_id).unwrap().scroll(scroll_location, phase)
     }
 
-    pub fn update_all_node_transforms(&mut self, pan: LayerPoint) {
+    pub fn update_all_node_transforms(&mut self, pan: LayerPoint, async_zoom: f32) {
         if self.nodes.is_empty() {
             return;
         }
 
+        // The async zoom is applied as a pre-transform on the root
+        // reference frame, so all content - fixed-position included - is
+        // scaled around the viewport origin without touching the scene,
+        // and the pan then moves the zoomed content. This is what lets
+        // pinch zoom run entirely on the compositor.
+        let root_transform = LayerToWorldTransform::create_translation(pan.x, pan.y, 0.0)
+            .pre_scale(async_zoom, async_zoom, 1.0);
+
         let root_reference_frame_id = self.root_reference_frame_id();
         let root_viewport = self.nodes[&root_reference_frame_id].local_clip_rect;
         self.update_node_transform(root_reference_frame_id,
-                                   &LayerToWorldTransform::create_translation(pan.x, pan.y, 0.0),
+                                   &root_transform,
                                    &root_viewport,
                                    LayerVector2D::zero(),
                                    LayerVector2D::zero());
@@ -291,6 +299,22 @@ impl ClipScrollTree {
         }
     }
 
+    /// Replace the local transform of a reference frame node with a newly
+    /// resolved value. Used when an animated property binding changes without
+    /// the scene being rebuilt.
+    pub fn update_reference_frame_transform(&mut self,
+                                            id: ClipId,
+                                            transform: LayerToScrollTransform) {
+        if let Some(node) = self.nodes.get_mut(&id) {
+            match node.node_type {
+                NodeType::ReferenceFrame(ref mut local_transform) => {
+                    *local_transform = transform;
+                }
+                _ => warn!("Tried to animate the transform of a non-reference frame node."),
+            }
+        }
+    }
+
     pub fn tick_scrolling_bounce_animations(&mut self) {
         for (_, node) in &mut self.nodes {
             node.tick_scrolling_bounce_animation()
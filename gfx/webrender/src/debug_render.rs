@@ -28,6 +28,15 @@ const DESC_COLOR: VertexDescriptor = VertexDescriptor {
     instance_attributes: &[]
 };
 
+const DESC_TARGET: VertexDescriptor = VertexDescriptor {
+    vertex_attributes: &[
+        VertexAttribute { name: "aPosition", count: 2, kind: VertexAttributeKind::F32 },
+        // The z component selects the texture array layer.
+        VertexAttribute { name: "aColorTexCoord", count: 3, kind: VertexAttributeKind::F32 },
+    ],
+    instance_attributes: &[]
+};
+
 #[repr(C)]
 pub struct DebugFontVertex {
     pub x: f32,
@@ -49,6 +58,27 @@ impl DebugFontVertex {
     }
 }
 
+#[repr(C)]
+pub struct DebugTargetVertex {
+    pub x: f32,
+    pub y: f32,
+    pub u: f32,
+    pub v: f32,
+    pub layer: f32,
+}
+
+impl DebugTargetVertex {
+    pub fn new(x: f32, y: f32, u: f32, v: f32, layer: f32) -> DebugTargetVertex {
+        DebugTargetVertex {
+            x,
+            y,
+            u,
+            v,
+            layer,
+        }
+    }
+}
+
 #[repr(C)]
 pub struct DebugColorVertex {
     pub x: f32,
@@ -79,16 +109,29 @@ pub struct DebugRenderer {
     line_vertices: Vec<DebugColorVertex>,
     line_vao: VAOId,
     color_program: Program,
+
+    target_program: Program,
+    target_vao: VAOId,
 }
 
 impl DebugRenderer {
     pub fn new(device: &mut Device) -> DebugRenderer {
         let font_program = device.create_program("debug_font", "shared_other", &DESC_FONT).unwrap();
         let color_program = device.create_program("debug_color", "shared_other", &DESC_COLOR).unwrap();
+        let target_prefix = if device.get_capabilities().avoid_texture_arrays {
+            Some(String::from("#define WR_FEATURE_TEXTURE_2D_TARGETS\n"))
+        } else {
+            None
+        };
+        let target_program = device.create_program_with_prefix("debug_target",
+                                                               &["shared_other"],
+                                                               target_prefix,
+                                                               &DESC_TARGET).unwrap();
 
         let font_vao = device.create_vao(&DESC_FONT, 32);
         let line_vao = device.create_vao(&DESC_COLOR, 32);
         let tri_vao = device.create_vao(&DESC_COLOR, 32);
+        let target_vao = device.create_vao(&DESC_TARGET, 32);
 
         let font_texture_id = device.create_texture_ids(1, TextureTarget::Default)[0];
         device.init_texture(font_texture_id,
@@ -111,12 +154,15 @@ impl DebugRenderer {
             font_vao,
             line_vao,
             font_texture_id,
+            target_program,
+            target_vao,
         }
     }
 
     pub fn deinit(&mut self, device: &mut Device) {
         device.delete_program(&mut self.font_program);
         device.delete_program(&mut self.color_program);
+        device.delete_program(&mut self.target_program);
     }
 
     pub fn line_height(&self) -> f32 {
@@ -213,6 +259,56 @@ impl DebugRenderer {
         self.line_vertices.push(DebugColorVertex::new(x1 as f32, y1 as f32, color1));
     }
 
+    /// Draws one layer of an A8 render target into the given screen rect,
+    /// mapped through a false color ramp. Unlike the other debug
+    /// primitives this draws immediately, since the render target
+    /// textures are recycled before `render` runs.
+    pub fn draw_alpha_target_slice(&mut self,
+                                   device: &mut Device,
+                                   texture_id: TextureId,
+                                   layer_index: i32,
+                                   rect: &Rect<f32>,
+                                   viewport_size: &DeviceUintSize) {
+        let _gm = GpuMarker::new(device.rc_gl(), "debug target");
+        device.disable_depth();
+        device.set_blend(false);
+
+        let projection = Transform3D::ortho(0.0,
+                                            viewport_size.width as f32,
+                                            viewport_size.height as f32,
+                                            0.0,
+                                            ORTHO_NEAR_PLANE,
+                                            ORTHO_FAR_PLANE);
+
+        let x0 = rect.origin.x;
+        let y0 = rect.origin.y;
+        let x1 = x0 + rect.size.width;
+        let y1 = y0 + rect.size.height;
+        let layer = layer_index as f32;
+
+        // The target was rendered with a y-up projection, so the top of
+        // the on-screen rect samples the top of the texture.
+        let vertices = [
+            DebugTargetVertex::new(x0, y0, 0.0, 1.0, layer),
+            DebugTargetVertex::new(x1, y0, 1.0, 1.0, layer),
+            DebugTargetVertex::new(x0, y1, 0.0, 0.0, layer),
+            DebugTargetVertex::new(x1, y1, 1.0, 0.0, layer),
+        ];
+        let indices: [u32; 6] = [0, 1, 2, 2, 1, 3];
+
+        device.bind_program(&self.target_program);
+        device.set_uniforms(&self.target_program, &projection);
+        device.bind_texture(TextureSampler::CacheA8, texture_id);
+        device.bind_vao(self.target_vao);
+        device.update_vao_indices(self.target_vao,
+                                  &indices,
+                                  VertexUsageHint::Dynamic);
+        device.update_vao_main_vertices(self.target_vao,
+                                        &vertices,
+                                        VertexUsageHint::Dynamic);
+        device.draw_triangles_u32(0, indices.len() as i32);
+    }
+
     pub fn render(&mut self,
                   device: &mut Device,
                   viewport_size: &DeviceUintSize) {
@@ -7,6 +7,8 @@ use gleam::gl;
 use internal_types::{RenderTargetMode, TextureSampler, DEFAULT_TEXTURE, FastHashMap};
 //use notify::{self, Watcher};
 use super::shader_source;
+use std::cmp;
+use std::fmt;
 use std::fs::File;
 use std::io::Read;
 use std::iter::repeat;
@@ -18,7 +20,8 @@ use std::rc::Rc;
 //use std::sync::mpsc::{channel, Sender};
 use std::thread;
 use api::{ColorF, ImageFormat};
-use api::{DeviceIntPoint, DeviceIntRect, DeviceIntSize, DeviceUintSize};
+use api::{DeviceIntPoint, DeviceIntRect, DeviceIntSize, DeviceUintPoint, DeviceUintRect, DeviceUintSize};
+use workarounds::GpuInfo;
 
 #[derive(Debug, Copy, Clone, PartialEq, Ord, Eq, PartialOrd)]
 pub struct FrameId(usize);
@@ -51,6 +54,17 @@ const SHADER_VERSION_GL: &str = "#version 150\n";
 
 const SHADER_VERSION_GLES: &str = "#version 300 es\n";
 
+// Compute shaders need a later GLSL version than the rest of the
+// pipeline: image load/store arrived in GL 4.3 / GLES 3.1.
+const SHADER_VERSION_COMPUTE_GL: &str = "#version 430\n";
+
+const SHADER_VERSION_COMPUTE_GLES: &str = "#version 310 es\n";
+
+// The number of staging PBOs in the texture upload ring. Deep enough
+// that by the time a buffer comes around again the driver is normally
+// done reading it, so orphaning it is free.
+const TEXTURE_UPLOAD_PBO_COUNT: usize = 4;
+
 static SHADER_PREAMBLE: &str = "shared";
 
 #[repr(u32)]
@@ -98,7 +112,7 @@ pub struct VertexAttribute {
     pub kind: VertexAttributeKind,
 }
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug)]
 pub struct VertexDescriptor {
     pub vertex_attributes: &'static [VertexAttribute],
     pub instance_attributes: &'static [VertexAttribute],
@@ -131,6 +145,17 @@ fn get_shader_version(gl: &gl::Gl) -> &'static str {
     }
 }
 
+fn get_compute_shader_version(gl: &gl::Gl) -> &'static str {
+    match gl.get_type() {
+        gl::GlType::Gl => {
+            SHADER_VERSION_COMPUTE_GL
+        }
+        gl::GlType::Gles => {
+            SHADER_VERSION_COMPUTE_GLES
+        }
+    }
+}
+
 fn get_optional_shader_source(shader_name: &str, base_path: &Option<PathBuf>) -> Option<String> {
     if let Some(ref base) = *base_path {
         let shader_path = base.join(&format!("{}.glsl", shader_name));
@@ -149,6 +174,99 @@ fn get_shader_source(shader_name: &str, base_path: &Option<PathBuf>) -> String {
         .expect(&format!("Couldn't get required shader: {}", shader_name))
 }
 
+/// Assembles complete shader stage sources for the driver.
+///
+/// Each source file is read at most once, and the flattened result of
+/// resolving a stage's include chain is cached keyed by (stage name,
+/// feature defines), so the many feature variants of each shader reuse
+/// the assembly work. A `#line` directive at every file boundary gives
+/// each constituent file its own source string number, and a comment
+/// table at the top of the flattened source maps those numbers back to
+/// file names, so driver error logs can be traced to a line in the
+/// original `res/*.glsl` files rather than into the concatenation.
+pub struct ShaderPreprocessor {
+    base_path: Option<PathBuf>,
+    files: FastHashMap<String, Option<Rc<String>>>,
+    flattened: FastHashMap<(String, String), Rc<String>>,
+}
+
+impl ShaderPreprocessor {
+    pub fn new(base_path: Option<PathBuf>) -> ShaderPreprocessor {
+        ShaderPreprocessor {
+            base_path,
+            files: FastHashMap::default(),
+            flattened: FastHashMap::default(),
+        }
+    }
+
+    /// Returns the source of a single file, reading it at most once.
+    /// Files from the resource override path are cached too, so edits
+    /// made there while the device is alive aren't picked up.
+    fn file_source(&mut self, filename: &str) -> Option<Rc<String>> {
+        if let Some(source) = self.files.get(filename) {
+            return source.clone();
+        }
+        let source = get_optional_shader_source(filename, &self.base_path).map(Rc::new);
+        self.files.insert(filename.to_owned(), source.clone());
+        source
+    }
+
+    /// Returns the flattened source for one stage of `base_filename`:
+    /// the stage and feature defines, then the shared preamble, the
+    /// include files, the shader's optional shared source, and finally
+    /// the per-stage source, with `#line` markers at every boundary.
+    pub fn process(&mut self,
+                   base_filename: &str,
+                   stage_suffix: &str,
+                   stage_define: &str,
+                   features: &Option<String>,
+                   include_filenames: &[&str]) -> Rc<String> {
+        let stage_name = format!("{}{}", base_filename, stage_suffix);
+        let features_key = match *features {
+            Some(ref features) => features.clone(),
+            None => String::new(),
+        };
+        let key = (stage_name.clone(), features_key);
+        if let Some(source) = self.flattened.get(&key) {
+            return source.clone();
+        }
+
+        let mut pieces: Vec<(&str, Rc<String>)> = Vec::new();
+        pieces.push((SHADER_PREAMBLE,
+                     self.file_source(SHADER_PREAMBLE)
+                         .expect(&format!("Couldn't get required shader: {}", SHADER_PREAMBLE))));
+        for &inc_filename in include_filenames {
+            pieces.push((inc_filename,
+                         self.file_source(inc_filename)
+                             .expect(&format!("Couldn't get required shader: {}", inc_filename))));
+        }
+        if let Some(shared_source) = self.file_source(base_filename) {
+            pieces.push((base_filename, shared_source));
+        }
+        let stage_source = self.file_source(&stage_name)
+            .expect(&format!("Couldn't get required shader: {}", stage_name));
+        pieces.push((&stage_name, stage_source));
+
+        let mut source = String::new();
+        source.push_str(stage_define);
+        if let Some(ref features) = *features {
+            source.push_str(features);
+        }
+        source.push_str(&format!("// Base shader: {}\n", base_filename));
+        for (number, &(filename, _)) in pieces.iter().enumerate() {
+            source.push_str(&format!("// {}: {}.glsl\n", number + 1, filename));
+        }
+        for (number, &(_, ref file_source)) in pieces.iter().enumerate() {
+            source.push_str(&format!("#line 1 {}\n", number + 1));
+            source.push_str(file_source);
+        }
+
+        let source = Rc::new(source);
+        self.flattened.insert(key, source.clone());
+        source
+    }
+}
+
 pub trait FileWatcherHandler : Send {
     fn file_changed(&self, path: PathBuf);
 }
@@ -228,23 +346,34 @@ impl VertexDescriptor {
         }
 
         if !self.instance_attributes.is_empty() {
-            instance.bind(gl);
-            let instance_stride: u32 = self.instance_attributes
-                                           .iter()
-                                           .map(|attr| attr.size_in_bytes()).sum();
-            let mut instance_offset = 0;
-
-            let base_attr = self.vertex_attributes.len() as u32;
-
-            for (i, attr) in self.instance_attributes.iter().enumerate() {
-                let attr_index = base_attr + i as u32;
-                attr.bind_to_vao(attr_index,
-                                 1,
-                                 instance_stride as gl::GLint,
-                                 instance_offset,
-                                 gl);
-                instance_offset += attr.size_in_bytes();
-            }
+            self.bind_instances(gl, instance, 0);
+        }
+    }
+
+    // Points the instance attributes at the instance VBO, starting at the
+    // given byte offset. Re-binding with a non-zero offset is how instanced
+    // draws are sliced out of a shared instance buffer on GL versions
+    // without glDrawElementsInstancedBaseInstance.
+    fn bind_instances(&self,
+                      gl: &gl::Gl,
+                      instance: VBOId,
+                      base_offset: u32) {
+        instance.bind(gl);
+        let instance_stride: u32 = self.instance_attributes
+                                       .iter()
+                                       .map(|attr| attr.size_in_bytes()).sum();
+        let mut instance_offset = base_offset;
+
+        let base_attr = self.vertex_attributes.len() as u32;
+
+        for (i, attr) in self.instance_attributes.iter().enumerate() {
+            let attr_index = base_attr + i as u32;
+            attr.bind_to_vao(attr_index,
+                             1,
+                             instance_stride as gl::GLint,
+                             instance_offset,
+                             gl);
+            instance_offset += attr.size_in_bytes();
         }
     }
 }
@@ -254,6 +383,12 @@ impl TextureId {
         gl.bind_texture(self.target, self.name);
     }
 
+    /// The GL name of the texture, for handing to code that talks to GL
+    /// directly, e.g. an external compositor.
+    pub fn name(&self) -> gl::GLuint {
+        self.name
+    }
+
     pub fn new(name: gl::GLuint, texture_target: TextureTarget) -> TextureId {
         TextureId {
             name,
@@ -302,16 +437,15 @@ struct Texture {
 
     filter: TextureFilter,
     mode: RenderTargetMode,
-    fbo_ids: Vec<FBOId>,
+    /// The number of layers that can be rendered to, zero when the
+    /// texture is not a render target. The framebuffers wrapping the
+    /// layers live in the device's FBO cache; see `Device::get_fbo`.
+    layer_count: i32,
     depth_rb: Option<RBOId>,
 }
 
 impl Drop for Texture {
     fn drop(&mut self) {
-        if !self.fbo_ids.is_empty() {
-            let fbo_ids: Vec<_> = self.fbo_ids.iter().map(|&FBOId(fbo_id)| fbo_id).collect();
-            self.gl.delete_framebuffers(&fbo_ids[..]);
-        }
         self.gl.delete_textures(&[self.id]);
     }
 }
@@ -321,9 +455,8 @@ pub struct Program {
     u_transform: gl::GLint,
     u_device_pixel_ratio: gl::GLint,
     name: String,
-    vs_source: String,
-    fs_source: String,
-    prefix: Option<String>,
+    vs_source: Rc<String>,
+    fs_source: Rc<String>,
     vs_id: Option<gl::GLuint>,
     fs_id: Option<gl::GLuint>,
 }
@@ -333,7 +466,8 @@ impl Program {
                                vs_id: gl::GLuint,
                                fs_id: gl::GLuint,
                                descriptor: &VertexDescriptor,
-                               gl: &gl::Gl) -> Result<(), ShaderError> {
+                               gl: &gl::Gl,
+                               sink: &mut Option<Box<DeviceEventSink>>) -> Result<(), ShaderError> {
         gl.attach_shader(self.id, vs_id);
         gl.attach_shader(self.id, fs_id);
 
@@ -349,7 +483,7 @@ impl Program {
         gl.link_program(self.id);
         if gl.get_program_iv(self.id, gl::LINK_STATUS) == (0 as gl::GLint) {
             let error_log = gl.get_program_info_log(self.id);
-            println!("Failed to link shader program: {:?}\n{}", self.name, error_log);
+            emit_device_event(sink, DeviceEvent::ShaderLinkFailed(self.name.clone(), error_log.clone()));
             gl.detach_shader(self.id, vs_id);
             gl.detach_shader(self.id, fs_id);
             return Err(ShaderError::Link(self.name.clone(), error_log));
@@ -365,13 +499,33 @@ impl Drop for Program {
     }
 }
 
+/// A linked compute shader, used by the compute variants of the cache
+/// shaders. Unlike `Program` there is no vertex input to describe; the
+/// only per-dispatch input is the `uData` uniform.
+pub struct ComputeProgram {
+    id: gl::GLuint,
+    u_data: gl::GLint,
+    u_instances: gl::GLint,
+    u_device_pixel_ratio: gl::GLint,
+}
+
+impl Drop for ComputeProgram {
+    fn drop(&mut self) {
+        debug_assert!(thread::panicking() || self.id == 0);
+    }
+}
+
 struct VAO {
     gl: Rc<gl::Gl>,
     id: gl::GLuint,
+    descriptor: VertexDescriptor,
     ibo_id: IBOId,
     main_vbo_id: VBOId,
     instance_vbo_id: VBOId,
     instance_stride: gl::GLint,
+    // The instance the attribute pointers currently start at, when draws
+    // are being sliced out of a shared instance buffer.
+    instance_offset: usize,
     owns_indices: bool,
     owns_vertices: bool,
     owns_instances: bool,
@@ -400,6 +554,17 @@ pub struct TextureId {
     target: gl::GLuint,
 }
 
+// The draw command layout glMultiDrawElementsIndirect reads from the
+// indirect buffer, per GL 4.3.
+#[repr(C)]
+struct DrawElementsIndirectCommand {
+    count: gl::GLuint,
+    instance_count: gl::GLuint,
+    first_index: gl::GLuint,
+    base_vertex: gl::GLuint,
+    base_instance: gl::GLuint,
+}
+
 #[derive(PartialEq, Eq, Hash, Debug, Copy, Clone)]
 pub struct VAOId(gl::GLuint);
 
@@ -421,10 +586,41 @@ pub struct PBOId(gl::GLuint);
 const MAX_EVENTS_PER_FRAME: usize = 256;
 const MAX_PROFILE_FRAMES: usize = 4;
 
+// From EXT_disjoint_timer_query. Reading the flag also resets it.
+const GPU_DISJOINT_EXT: gl::GLenum = 0x8FBB;
+
 pub trait NamedTag {
     fn get_label(&self) -> &str;
 }
 
+/// How GPU timing can be measured on the current context.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TimerQuerySupport {
+    /// Desktop GL TIME_ELAPSED queries.
+    TimeElapsed,
+    /// GLES with EXT_disjoint_timer_query: the same queries, except that the
+    /// results must be thrown away if the GPU clock was disjoint (after a
+    /// frequency or power state change, say) while they were collected.
+    DisjointTimeElapsed,
+    /// No timer queries at all; samples report zero time.
+    None,
+}
+
+fn detect_timer_query_support(gl: &gl::Gl) -> TimerQuerySupport {
+    match gl.get_type() {
+        gl::GlType::Gl => TimerQuerySupport::TimeElapsed,
+        gl::GlType::Gles => {
+            let extensions = gl.get_string(gl::EXTENSIONS);
+            if extensions.split_whitespace()
+                         .any(|extension| extension == "GL_EXT_disjoint_timer_query") {
+                TimerQuerySupport::DisjointTimeElapsed
+            } else {
+                TimerQuerySupport::None
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct GpuSample<T> {
     pub tag: T,
@@ -439,71 +635,69 @@ pub struct GpuFrameProfile<T> {
     pending_query: gl::GLuint,
     frame_id: FrameId,
     inside_frame: bool,
+    enabled: bool,
+    timer_support: TimerQuerySupport,
 }
 
 impl<T> GpuFrameProfile<T> {
-    fn new(gl: Rc<gl::Gl>) -> Self {
-        match gl.get_type() {
-            gl::GlType::Gl => {
-                let queries = gl.gen_queries(MAX_EVENTS_PER_FRAME as gl::GLint);
-                GpuFrameProfile {
-                    gl,
-                    queries,
-                    samples: Vec::new(),
-                    next_query: 0,
-                    pending_query: 0,
-                    frame_id: FrameId(0),
-                    inside_frame: false,
-                }
-            }
-            gl::GlType::Gles => {
-                GpuFrameProfile {
-                    gl,
-                    queries: Vec::new(),
-                    samples: Vec::new(),
-                    next_query: 0,
-                    pending_query: 0,
-                    frame_id: FrameId(0),
-                    inside_frame: false,
-                }
-            }
+    fn new(gl: Rc<gl::Gl>, timer_support: TimerQuerySupport) -> Self {
+        GpuFrameProfile {
+            gl,
+            queries: Vec::new(),
+            samples: Vec::new(),
+            next_query: 0,
+            pending_query: 0,
+            frame_id: FrameId(0),
+            inside_frame: false,
+            enabled: false,
+            timer_support,
         }
     }
 
-    fn begin_frame(&mut self, frame_id: FrameId) {
+    fn begin_frame(&mut self, frame_id: FrameId, enabled: bool) {
         self.frame_id = frame_id;
         self.next_query = 0;
         self.pending_query = 0;
         self.samples.clear();
         self.inside_frame = true;
+        self.enabled = enabled;
+
+        // The timer queries are only allocated once profiling is actually
+        // turned on, so that the (surprisingly expensive on some drivers)
+        // query machinery costs nothing while the profiler HUD is off.
+        if self.enabled && self.queries.is_empty() &&
+           self.timer_support != TimerQuerySupport::None {
+            self.queries = self.gl.gen_queries(MAX_EVENTS_PER_FRAME as gl::GLint);
+        }
     }
 
     fn end_frame(&mut self) {
         self.inside_frame = false;
-        match self.gl.get_type() {
-            gl::GlType::Gl => {
-                if self.pending_query != 0 {
-                    self.gl.end_query(gl::TIME_ELAPSED);
-                }
-            }
-            gl::GlType::Gles => {},
+        if self.pending_query != 0 {
+            self.gl.end_query(gl::TIME_ELAPSED);
         }
     }
 
     fn add_marker(&mut self, tag: T) -> GpuMarker
     where T: NamedTag {
         debug_assert!(self.inside_frame);
-        match self.gl.get_type() {
-            gl::GlType::Gl => {
-                self.add_marker_gl(tag)
+        if !self.enabled {
+            // Still emit the group marker for external GPU debuggers, but
+            // don't issue any timer queries or record samples.
+            return GpuMarker::new(&self.gl, tag.get_label());
+        }
+        match self.timer_support {
+            TimerQuerySupport::TimeElapsed |
+            TimerQuerySupport::DisjointTimeElapsed => {
+                self.add_marker_timed(tag)
             }
-            gl::GlType::Gles => {
-                self.add_marker_gles(tag)
+            TimerQuerySupport::None => {
+                self.add_marker_untimed(tag)
             }
         }
     }
 
-    fn add_marker_gl(&mut self, tag: T) -> GpuMarker
+    fn add_marker_timed(&mut self, tag: T) -> GpuMarker
     where T: NamedTag {
         if self.pending_query != 0 {
             self.gl.end_query(gl::TIME_ELAPSED);
@@ -526,7 +720,7 @@ impl<T> GpuFrameProfile<T> {
         marker
     }
 
-    fn add_marker_gles(&mut self, tag: T) -> GpuMarker
+    fn add_marker_untimed(&mut self, tag: T) -> GpuMarker
     where T: NamedTag {
         let marker = GpuMarker::new(&self.gl, tag.get_label());
         self.samples.push(GpuSample {
@@ -542,36 +736,35 @@ impl<T> GpuFrameProfile<T> {
 
     fn build_samples(&mut self) -> Vec<GpuSample<T>> {
         debug_assert!(!self.inside_frame);
-        match self.gl.get_type() {
-            gl::GlType::Gl => {
-                self.build_samples_gl()
-            }
-            gl::GlType::Gles => {
-                self.build_samples_gles()
-            }
-        }
-    }
 
-    fn build_samples_gl(&mut self) -> Vec<GpuSample<T>> {
-        for (index, sample) in self.samples.iter_mut().enumerate() {
-            sample.time_ns = self.gl.get_query_object_ui64v(self.queries[index], gl::QUERY_RESULT)
-        }
+        match self.timer_support {
+            TimerQuerySupport::TimeElapsed |
+            TimerQuerySupport::DisjointTimeElapsed => {
+                for (index, sample) in self.samples.iter_mut().enumerate() {
+                    sample.time_ns = self.gl.get_query_object_ui64v(self.queries[index], gl::QUERY_RESULT)
+                }
 
-        mem::replace(&mut self.samples, Vec::new())
-    }
+                // If the GPU clock was disjoint at any point while the
+                // queries were pending, the timings are garbage and are
+                // reported as zero instead. Reading the flag resets it.
+                if self.timer_support == TimerQuerySupport::DisjointTimeElapsed &&
+                   self.gl.get_integer_v(GPU_DISJOINT_EXT) != 0 {
+                    for sample in &mut self.samples {
+                        sample.time_ns = 0;
+                    }
+                }
+            }
+            TimerQuerySupport::None => {}
+        }
 
-    fn build_samples_gles(&mut self) -> Vec<GpuSample<T>> {
         mem::replace(&mut self.samples, Vec::new())
     }
 }
 
 impl<T> Drop for GpuFrameProfile<T> {
     fn drop(&mut self) {
-        match self.gl.get_type() {
-            gl::GlType::Gl =>  {
-                self.gl.delete_queries(&self.queries);
-            }
-            gl::GlType::Gles => {},
+        if !self.queries.is_empty() {
+            self.gl.delete_queries(&self.queries);
         }
     }
 }
@@ -579,21 +772,31 @@ impl<T> Drop for GpuFrameProfile<T> {
 pub struct GpuProfiler<T> {
     frames: [GpuFrameProfile<T>; MAX_PROFILE_FRAMES],
     next_frame: usize,
+    enabled: bool,
 }
 
 impl<T> GpuProfiler<T> {
     pub fn new(gl: &Rc<gl::Gl>) -> GpuProfiler<T> {
+        let timer_support = detect_timer_query_support(&**gl);
         GpuProfiler {
             next_frame: 0,
             frames: [
-                      GpuFrameProfile::new(Rc::clone(gl)),
-                      GpuFrameProfile::new(Rc::clone(gl)),
-                      GpuFrameProfile::new(Rc::clone(gl)),
-                      GpuFrameProfile::new(Rc::clone(gl)),
+                      GpuFrameProfile::new(Rc::clone(gl), timer_support),
+                      GpuFrameProfile::new(Rc::clone(gl), timer_support),
+                      GpuFrameProfile::new(Rc::clone(gl), timer_support),
+                      GpuFrameProfile::new(Rc::clone(gl), timer_support),
                     ],
+            enabled: false,
         }
     }
 
+    /// Turn timer query profiling on or off. Takes effect at the next
+    /// `begin_frame`; frames already in flight keep the setting they were
+    /// started with.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
     pub fn build_samples(&mut self) -> Option<(FrameId, Vec<GpuSample<T>>)> {
         let frame = &mut self.frames[self.next_frame];
         if frame.is_valid() {
@@ -604,8 +807,9 @@ impl<T> GpuProfiler<T> {
     }
 
     pub fn begin_frame(&mut self, frame_id: FrameId) {
+        let enabled = self.enabled;
         let frame = &mut self.frames[self.next_frame];
-        frame.begin_frame(frame_id);
+        frame.begin_frame(frame_id, enabled);
     }
 
     pub fn end_frame(&mut self) {
@@ -627,19 +831,20 @@ pub struct GpuMarker{
 
 impl GpuMarker {
     pub fn new(gl: &Rc<gl::Gl>, message: &str) -> GpuMarker {
+        // Desktop drivers commonly expose the old EXT marker extension,
+        // while mobile GLES drivers expose KHR_debug instead. Both calls
+        // are no-ops when the relevant extension isn't present.
         match gl.get_type() {
             gl::GlType::Gl =>  {
                 gl.push_group_marker_ext(message);
-                GpuMarker{
-                    gl: Rc::clone(gl),
-                }
             }
             gl::GlType::Gles => {
-                GpuMarker{
-                    gl: Rc::clone(gl),
-                }
+                gl.push_debug_group_khr(message);
             }
         }
+        GpuMarker{
+            gl: Rc::clone(gl),
+        }
     }
 
     pub fn fire(gl: &gl::Gl, message: &str) {
@@ -647,19 +852,22 @@ impl GpuMarker {
             gl::GlType::Gl =>  {
                 gl.insert_event_marker_ext(message);
             }
-            gl::GlType::Gles => {},
+            gl::GlType::Gles => {
+                gl.debug_message_insert_khr(message);
+            }
         }
     }
 }
 
-#[cfg(not(any(target_arch="arm", target_arch="aarch64")))]
 impl Drop for GpuMarker {
     fn drop(&mut self) {
         match self.gl.get_type() {
             gl::GlType::Gl =>  {
                 self.gl.pop_group_marker_ext();
             }
-            gl::GlType::Gles => {},
+            gl::GlType::Gles => {
+                self.gl.pop_debug_group_khr();
+            }
         }
     }
 }
@@ -761,6 +969,40 @@ impl FileWatcherThread {
 
 pub struct Capabilities {
     pub supports_multisampling: bool,
+    /// True on GL 4.3 / GLES 3.1 contexts, where cache shaders can run
+    /// as compute and write their results with image load/store.
+    pub supports_compute_shaders: bool,
+    /// True on GL 4.3 contexts, where runs of batches that share their
+    /// GL state can be submitted with one glMultiDrawElementsIndirect.
+    pub supports_multi_draw_indirect: bool,
+    /// PBO texture uploads stall or corrupt texels on some Adreno
+    /// drivers; upload from client memory instead. See `workarounds`.
+    pub avoid_pbo_uploads: bool,
+    /// Client-memory glTexSubImage2D copies synchronously on some tiled
+    /// GPUs; stage texture cache uploads through the PBO ring instead.
+    pub prefer_pbo_texture_uploads: bool,
+    /// Rendering to texture array layers is broken on older Mali
+    /// drivers. The renderer falls back to one 2D texture per pass
+    /// target, with the shaders compiled to drop the layer coordinate;
+    /// see `WR_FEATURE_TEXTURE_2D_TARGETS`.
+    pub avoid_texture_arrays: bool,
+    /// Scissored clears miss the fast clear path on some tiled GPUs.
+    pub avoid_scissored_clears: bool,
+    /// True when glInvalidateFramebuffer or the discard extension is
+    /// available, so intermediate targets can drop attachment contents
+    /// instead of clearing them. See `Device::invalidate_target`.
+    pub supports_framebuffer_invalidation: bool,
+    /// True when OVR_multiview2 is available, so both eye views of a
+    /// stereo frame could in principle come from a single draw. The
+    /// shaders are not multiview-aware yet, so stereo presentation
+    /// currently draws the two eye viewports back to back; see
+    /// `Renderer::set_stereo_params`.
+    pub supports_multiview: bool,
+    /// True when the driver accepts BGRA texels: always on desktop GL,
+    /// and on GLES contexts with EXT_texture_format_BGRA8888. Without
+    /// it, BGRA8 uploads are swizzled to RGBA on the CPU so content
+    /// doesn't render with red and blue swapped.
+    pub supports_bgra_textures: bool,
 }
 
 #[derive(Clone, Debug)]
@@ -769,6 +1011,70 @@ pub enum ShaderError {
     Link(String, String), // name, error message
 }
 
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DeviceEventSeverity {
+    /// The device continued, possibly with reduced quality.
+    Warning,
+    /// The operation failed and rendering is likely broken.
+    Error,
+}
+
+/// A structured device-level event. See `DeviceEventSink`.
+#[derive(Clone, Debug)]
+pub enum DeviceEvent {
+    /// A shader stage failed to compile: shader name and driver log.
+    ShaderCompileFailed(String, String),
+    /// The driver reported warnings for a shader that still compiled:
+    /// shader name and driver log.
+    ShaderCompileWarnings(String, String),
+    /// A shader program failed to link: program name and driver log.
+    ShaderLinkFailed(String, String),
+    /// A texture was requested above the device limit: requested and
+    /// allowed size in pixels.
+    MaxTextureSizeExceeded(u32, u32),
+}
+
+impl DeviceEvent {
+    pub fn severity(&self) -> DeviceEventSeverity {
+        match *self {
+            DeviceEvent::ShaderCompileFailed(..) |
+            DeviceEvent::ShaderLinkFailed(..) => DeviceEventSeverity::Error,
+            DeviceEvent::ShaderCompileWarnings(..) |
+            DeviceEvent::MaxTextureSizeExceeded(..) => DeviceEventSeverity::Warning,
+        }
+    }
+}
+
+impl fmt::Display for DeviceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DeviceEvent::ShaderCompileFailed(ref name, ref log) =>
+                write!(f, "Failed to compile shader: {:?}\n{}", name, log),
+            DeviceEvent::ShaderCompileWarnings(ref name, ref log) =>
+                write!(f, "Warnings detected on shader: {:?}\n{}", name, log),
+            DeviceEvent::ShaderLinkFailed(ref name, ref log) =>
+                write!(f, "Failed to link shader program: {:?}\n{}", name, log),
+            DeviceEvent::MaxTextureSizeExceeded(requested, allowed) =>
+                write!(f, "Texture size {} exceeds the device limit of {}", requested, allowed),
+        }
+    }
+}
+
+/// Receives structured device events, so embedders can route shader
+/// logs and resource problems to crash annotations or a console. Set
+/// via `Renderer::set_device_event_sink`; events are delivered on the
+/// render thread. Without a sink, events go to stdout.
+pub trait DeviceEventSink {
+    fn device_event(&mut self, event: DeviceEvent);
+}
+
+fn emit_device_event(sink: &mut Option<Box<DeviceEventSink>>, event: DeviceEvent) {
+    match *sink {
+        Some(ref mut sink) => sink.device_event(event),
+        None => println!("{}", event),
+    }
+}
+
 pub struct Device {
     gl: Rc<gl::Gl>,
     // device state
@@ -780,10 +1086,15 @@ pub struct Device {
     bound_draw_fbo: FBOId,
     default_read_fbo: gl::GLuint,
     default_draw_fbo: gl::GLuint,
+    // When set, target binds that would go to the default framebuffer
+    // are redirected into this externally owned FBO, e.g. one wrapping
+    // a native compositor surface.
+    external_fbo: Option<gl::GLuint>,
     device_pixel_ratio: f32,
 
     // HW or API capabilties
     capabilities: Capabilities,
+    gpu_info: GpuInfo,
 
     // debug
     inside_frame: bool,
@@ -791,10 +1102,27 @@ pub struct Device {
     // resources
     resource_override_path: Option<PathBuf>,
     textures: FastHashMap<TextureId, Texture>,
+    // Framebuffers wrapping one layer of a render target texture,
+    // created lazily on first bind and kept until the texture's storage
+    // changes. See `get_fbo`.
+    fbo_cache: FastHashMap<(TextureId, i32), FBOId>,
+    // Number of GL framebuffer binds issued this frame.
+    fbo_bind_count: usize,
+    // Receives shader logs and other structured events; see
+    // `DeviceEventSink`.
+    event_sink: Option<Box<DeviceEventSink>>,
     vaos: FastHashMap<VAOId, VAO>,
+    // Lazily created buffer holding the draw commands for multi-draw
+    // indirect submissions.
+    indirect_buffer_id: Option<VBOId>,
+    // Lazily created ring of staging PBOs for texture cache uploads,
+    // used when the driver prefers staged uploads. See
+    // `Capabilities::prefer_pbo_texture_uploads`.
+    upload_pbos: Vec<PBOId>,
+    upload_pbo_index: usize,
 
     // misc.
-    shader_preamble: String,
+    preprocessor: ShaderPreprocessor,
     //file_watcher: FileWatcherThread,
 
     // Used on android only
@@ -814,11 +1142,30 @@ impl Device {
                _file_changed_handler: Box<FileWatcherHandler>) -> Device {
         //let file_watcher = FileWatcherThread::new(file_changed_handler);
 
-        let shader_preamble = get_shader_source(SHADER_PREAMBLE, &resource_override_path);
+        let preprocessor = ShaderPreprocessor::new(resource_override_path.clone());
         //file_watcher.add_watch(resource_path);
 
         let max_texture_size = gl.get_integer_v(gl::MAX_TEXTURE_SIZE) as u32;
 
+        let gpu_info = GpuInfo::new(&gl.get_string(gl::RENDERER),
+                                    &gl.get_string(gl::VERSION));
+
+        let extensions = gl.get_string(gl::EXTENSIONS);
+        let supports_multiview = extensions.split_whitespace()
+                                           .any(|extension| extension == "GL_OVR_multiview2");
+        let supports_framebuffer_invalidation =
+            gpu_info.supports_framebuffer_invalidation() ||
+            extensions.split_whitespace()
+                      .any(|extension| extension == "GL_ARB_invalidate_subdata" ||
+                                       extension == "GL_EXT_discard_framebuffer");
+        let supports_bgra_textures = match gl.get_type() {
+            gl::GlType::Gl => true,
+            gl::GlType::Gles => {
+                extensions.split_whitespace()
+                          .any(|extension| extension == "GL_EXT_texture_format_BGRA8888")
+            }
+        };
+
         Device {
             gl,
             resource_override_path,
@@ -829,7 +1176,17 @@ impl Device {
 
             capabilities: Capabilities {
                 supports_multisampling: false, //TODO
+                supports_compute_shaders: gpu_info.supports_compute_shaders(),
+                supports_multi_draw_indirect: gpu_info.supports_multi_draw_indirect(),
+                avoid_pbo_uploads: gpu_info.avoid_pbo_uploads(),
+                prefer_pbo_texture_uploads: gpu_info.prefer_pbo_texture_uploads(),
+                avoid_texture_arrays: gpu_info.avoid_texture_arrays(),
+                avoid_scissored_clears: gpu_info.avoid_scissored_clears(),
+                supports_framebuffer_invalidation,
+                supports_multiview,
+                supports_bgra_textures,
             },
+            gpu_info,
 
             bound_textures: [ TextureId::invalid(); 16 ],
             bound_program: 0,
@@ -839,11 +1196,18 @@ impl Device {
             bound_draw_fbo: FBOId(0),
             default_read_fbo: 0,
             default_draw_fbo: 0,
+            external_fbo: None,
 
             textures: FastHashMap::default(),
+            fbo_cache: FastHashMap::default(),
+            fbo_bind_count: 0,
+            event_sink: None,
             vaos: FastHashMap::default(),
+            indirect_buffer_id: None,
+            upload_pbos: Vec::new(),
+            upload_pbo_index: 0,
 
-            shader_preamble,
+            preprocessor,
 
             next_vao_id: 1,
             //file_watcher: file_watcher,
@@ -869,16 +1233,28 @@ impl Device {
         &self.capabilities
     }
 
+    pub fn gpu_info(&self) -> &GpuInfo {
+        &self.gpu_info
+    }
+
+    /// Installs the sink that receives structured device events. See
+    /// `DeviceEventSink`.
+    pub fn set_event_sink(&mut self, sink: Box<DeviceEventSink>) {
+        self.event_sink = Some(sink);
+    }
+
     pub fn compile_shader(gl: &gl::Gl,
                           name: &str,
                           source_str: &str,
                           shader_type: gl::GLenum,
-                          shader_preamble: &[String])
+                          version: &str,
+                          shader_preamble: &[String],
+                          sink: &mut Option<Box<DeviceEventSink>>)
                           -> Result<gl::GLuint, ShaderError> {
         debug!("compile {:?}", name);
 
         let mut s = String::new();
-        s.push_str(get_shader_version(gl));
+        s.push_str(version);
         for prefix in shader_preamble {
             s.push_str(prefix);
         }
@@ -891,11 +1267,11 @@ impl Device {
         gl.compile_shader(id);
         let log = gl.get_shader_info_log(id);
         if gl.get_shader_iv(id, gl::COMPILE_STATUS) == (0 as gl::GLint) {
-            println!("Failed to compile shader: {:?}\n{}", name, log);
+            emit_device_event(sink, DeviceEvent::ShaderCompileFailed(name.to_string(), log.clone()));
             Err(ShaderError::Compilation(name.to_string(), log))
         } else {
             if !log.is_empty() {
-                println!("Warnings detected on shader: {:?}\n{}", name, log);
+                emit_device_event(sink, DeviceEvent::ShaderCompileWarnings(name.to_string(), log));
             }
             Ok(id)
         }
@@ -930,6 +1306,7 @@ impl Device {
         // FBO state
         self.bound_read_fbo = FBOId(self.default_read_fbo);
         self.bound_draw_fbo = FBOId(self.default_draw_fbo);
+        self.fbo_bind_count = 0;
 
         // Pixel op state
         self.gl.pixel_store_i(gl::UNPACK_ALIGNMENT, 1);
@@ -959,12 +1336,14 @@ impl Device {
     pub fn bind_read_target(&mut self, texture_id: Option<(TextureId, i32)>) {
         debug_assert!(self.inside_frame);
 
-        let fbo_id = texture_id.map_or(FBOId(self.default_read_fbo), |texture_id| {
-            self.textures.get(&texture_id.0).unwrap().fbo_ids[texture_id.1 as usize]
-        });
+        let fbo_id = match texture_id {
+            Some((texture_id, layer)) => self.get_fbo(texture_id, layer),
+            None => FBOId(self.external_fbo.unwrap_or(self.default_read_fbo)),
+        };
 
         if self.bound_read_fbo != fbo_id {
             self.bound_read_fbo = fbo_id;
+            self.fbo_bind_count += 1;
             fbo_id.bind(self.gl(), FBOTarget::Read);
         }
     }
@@ -974,12 +1353,14 @@ impl Device {
                             dimensions: Option<DeviceUintSize>) {
         debug_assert!(self.inside_frame);
 
-        let fbo_id = texture_id.map_or(FBOId(self.default_draw_fbo), |texture_id| {
-            self.textures.get(&texture_id.0).unwrap().fbo_ids[texture_id.1 as usize]
-        });
+        let fbo_id = match texture_id {
+            Some((texture_id, layer)) => self.get_fbo(texture_id, layer),
+            None => FBOId(self.external_fbo.unwrap_or(self.default_draw_fbo)),
+        };
 
         if self.bound_draw_fbo != fbo_id {
             self.bound_draw_fbo = fbo_id;
+            self.fbo_bind_count += 1;
             fbo_id.bind(self.gl(), FBOTarget::Draw);
         }
 
@@ -988,6 +1369,109 @@ impl Device {
         }
     }
 
+    /// Returns the framebuffer wrapping one layer of a render target
+    /// texture, creating and caching it on first use. Cached entries stay
+    /// valid until the texture's storage changes, which deletes them via
+    /// `invalidate_fbos`.
+    fn get_fbo(&mut self, texture_id: TextureId, layer: i32) -> FBOId {
+        if let Some(&fbo_id) = self.fbo_cache.get(&(texture_id, layer)) {
+            return fbo_id;
+        }
+
+        let fbo = self.gl.gen_framebuffers(1)[0];
+        self.gl.bind_framebuffer(gl::FRAMEBUFFER, fbo);
+
+        {
+            let texture = &self.textures[&texture_id];
+            debug_assert!(layer < texture.layer_count);
+
+            if texture_id.target == gl::TEXTURE_2D_ARRAY {
+                self.gl.framebuffer_texture_layer(gl::FRAMEBUFFER,
+                                                  gl::COLOR_ATTACHMENT0,
+                                                  texture_id.name,
+                                                  0,
+                                                  layer);
+                let depth_rb = texture.depth_rb.expect("BUG: array render target has no depth");
+                self.gl.framebuffer_renderbuffer(gl::FRAMEBUFFER,
+                                                 gl::DEPTH_ATTACHMENT,
+                                                 gl::RENDERBUFFER,
+                                                 depth_rb.0);
+            } else {
+                debug_assert_eq!(layer, 0);
+                self.gl.framebuffer_texture_2d(gl::FRAMEBUFFER,
+                                               gl::COLOR_ATTACHMENT0,
+                                               texture_id.target,
+                                               texture_id.name,
+                                               0);
+                // 2D targets only carry a depth buffer when they stand in
+                // for an array layer on devices with the texture array
+                // fallback active.
+                if let Some(depth_rb) = texture.depth_rb {
+                    self.gl.framebuffer_renderbuffer(gl::FRAMEBUFFER,
+                                                     gl::DEPTH_ATTACHMENT,
+                                                     gl::RENDERBUFFER,
+                                                     depth_rb.0);
+                }
+            }
+        }
+
+        // Restore the bindings the device state tracking expects.
+        self.gl.bind_framebuffer(gl::READ_FRAMEBUFFER, self.bound_read_fbo.0);
+        self.gl.bind_framebuffer(gl::DRAW_FRAMEBUFFER, self.bound_draw_fbo.0);
+
+        let fbo_id = FBOId(fbo);
+        self.fbo_cache.insert((texture_id, layer), fbo_id);
+        fbo_id
+    }
+
+    /// Deletes any cached framebuffers for the texture. Called when the
+    /// texture's storage changes or is freed, so that stale attachments
+    /// are never rebound; `get_fbo` recreates entries on demand.
+    fn invalidate_fbos(&mut self, texture_id: TextureId) {
+        let mut fbo_ids = Vec::new();
+        self.fbo_cache.retain(|key, fbo_id| {
+            if key.0 == texture_id {
+                fbo_ids.push(fbo_id.0);
+                false
+            } else {
+                true
+            }
+        });
+        if !fbo_ids.is_empty() {
+            self.gl.delete_framebuffers(&fbo_ids[..]);
+        }
+    }
+
+    /// Number of GL framebuffer binds issued so far this frame.
+    pub fn fbo_bind_count(&self) -> usize {
+        self.fbo_bind_count
+    }
+
+    /// Returns the GL name of the framebuffer wrapping one layer of a
+    /// render target texture, creating it if necessary. The name stays
+    /// valid until the texture's storage changes.
+    pub fn texture_fbo_name(&mut self, texture_id: TextureId, layer: i32) -> gl::GLuint {
+        self.get_fbo(texture_id, layer).0
+    }
+
+    /// Redirects draw and read target binds that would go to the default
+    /// framebuffer into an externally owned FBO, e.g. one wrapping a
+    /// native compositor surface. Pass `None` to restore the default
+    /// framebuffer. The FBO must stay valid while the override is set.
+    pub fn set_external_framebuffer(&mut self, fbo_id: Option<gl::GLuint>) {
+        self.external_fbo = fbo_id;
+    }
+
+    /// Overrides the full-target viewport set by `bind_draw_target`, for
+    /// drawing into a sub-rect of the bound target.
+    pub fn set_viewport_rect(&mut self, rect: DeviceUintRect) {
+        debug_assert!(self.inside_frame);
+        self.gl.viewport(rect.origin.x as gl::GLint,
+                         rect.origin.y as gl::GLint,
+                         rect.size.width as gl::GLint,
+                         rect.size.height as gl::GLint);
+    }
+
     pub fn bind_program(&mut self, program: &Program) {
         debug_assert!(self.inside_frame);
 
@@ -1017,7 +1501,7 @@ impl Device {
                 format: ImageFormat::Invalid,
                 filter: TextureFilter::Nearest,
                 mode: RenderTargetMode::None,
-                fbo_ids: vec![],
+                layer_count: 0,
                 depth_rb: None,
             };
 
@@ -1080,6 +1564,12 @@ impl Device {
                         pixels: Option<&[u8]>) {
         debug_assert!(self.inside_frame);
 
+        if width > self.max_texture_size || height > self.max_texture_size {
+            emit_device_event(&mut self.event_sink,
+                              DeviceEvent::MaxTextureSizeExceeded(cmp::max(width, height),
+                                                                  self.max_texture_size));
+        }
+
         let resized;
         {
             let texture = self.textures.get_mut(&texture_id).expect("Didn't find texture!");
@@ -1091,7 +1581,10 @@ impl Device {
             texture.mode = mode;
         }
 
-        let (internal_format, gl_format) = gl_texture_formats_for_image_format(self.gl(), format);
+        let (internal_format, gl_format) =
+            gl_texture_formats_for_image_format(self.gl(),
+                                                format,
+                                                self.capabilities.supports_bgra_textures);
         let type_ = gl_type_for_texture_format(format);
 
         match mode {
@@ -1135,105 +1628,120 @@ impl Device {
         }
     }
 
+    /// Binds an EGLImage as the backing store of `texture_id`, without
+    /// copying the pixels. The image typically wraps a D3D11 shared
+    /// handle created through the `angle` module; the texture must have
+    /// been created with the external target the EGLImage requires.
+    pub fn init_texture_from_egl_image(&mut self,
+                                       texture_id: TextureId,
+                                       image: gl::GLeglImageOES,
+                                       width: u32,
+                                       height: u32) {
+        debug_assert!(self.inside_frame);
+
+        {
+            let texture = self.textures.get_mut(&texture_id).expect("Didn't find texture!");
+            texture.format = ImageFormat::BGRA8;
+            texture.width = width;
+            texture.height = height;
+            texture.filter = TextureFilter::Linear;
+            texture.mode = RenderTargetMode::None;
+        }
+
+        self.bind_texture(DEFAULT_TEXTURE, texture_id);
+        self.set_texture_parameters(texture_id.target, TextureFilter::Linear);
+        self.gl.egl_image_target_texture2d_oes(texture_id.target, image);
+    }
+
     pub fn get_render_target_layer_count(&self, texture_id: TextureId) -> usize {
-        self.textures[&texture_id].fbo_ids.len()
+        self.textures[&texture_id].layer_count as usize
     }
 
-    /// Updates the texture storage for the texture, creating
-    /// FBOs as required.
+    /// Updates the texture storage for the texture, invalidating any
+    /// cached FBOs as required.
     pub fn update_texture_storage(&mut self,
                                   texture_id: TextureId,
                                   layer_count: Option<i32>,
                                   resized: bool) {
-        let texture = self.textures.get_mut(&texture_id).unwrap();
-
         match layer_count {
             Some(layer_count) => {
+                // A 2D texture can stand in for a single array layer on
+                // devices that can't render to array layers; see
+                // `Capabilities::avoid_texture_arrays`.
                 assert!(layer_count > 0);
-                assert_eq!(texture_id.target, gl::TEXTURE_2D_ARRAY);
+                assert!(texture_id.target == gl::TEXTURE_2D_ARRAY || layer_count == 1);
 
-                let current_layer_count = texture.fbo_ids.len() as i32;
-                // If the texture is already the required size skip.
-                if current_layer_count == layer_count && !resized {
-                    return;
-                }
+                {
+                    let texture = self.textures.get_mut(&texture_id).unwrap();
 
-                let (internal_format, gl_format) = gl_texture_formats_for_image_format(&*self.gl, texture.format);
-                let type_ = gl_type_for_texture_format(texture.format);
+                    // If the texture is already the required size skip.
+                    if texture.layer_count == layer_count && !resized {
+                        return;
+                    }
 
-                self.gl.tex_image_3d(texture_id.target,
-                                     0,
-                                     internal_format as gl::GLint,
-                                     texture.width as gl::GLint,
-                                     texture.height as gl::GLint,
-                                     layer_count,
-                                     0,
-                                     gl_format,
-                                     type_,
-                                     None);
-
-                let needed_layer_count = layer_count - current_layer_count;
-                if needed_layer_count > 0 {
-                    // Create more framebuffers to fill the gap
-                    let new_fbos = self.gl.gen_framebuffers(needed_layer_count);
-                    texture.fbo_ids.extend(new_fbos.into_iter().map(|id| FBOId(id)));
-                } else if needed_layer_count < 0 {
-                    // Remove extra framebuffers
-                    for old in texture.fbo_ids.drain(layer_count as usize ..) {
-                        self.gl.delete_framebuffers(&[old.0]);
+                    let (internal_format, gl_format) =
+                        gl_texture_formats_for_image_format(&*self.gl,
+                                                            texture.format,
+                                                            self.capabilities.supports_bgra_textures);
+                    let type_ = gl_type_for_texture_format(texture.format);
+
+                    if texture_id.target == gl::TEXTURE_2D_ARRAY {
+                        self.gl.tex_image_3d(texture_id.target,
+                                             0,
+                                             internal_format as gl::GLint,
+                                             texture.width as gl::GLint,
+                                             texture.height as gl::GLint,
+                                             layer_count,
+                                             0,
+                                             gl_format,
+                                             type_,
+                                             None);
+                    } else {
+                        self.gl.tex_image_2d(texture_id.target,
+                                             0,
+                                             internal_format as gl::GLint,
+                                             texture.width as gl::GLint,
+                                             texture.height as gl::GLint,
+                                             0,
+                                             gl_format,
+                                             type_,
+                                             None);
                     }
-                }
 
-                let depth_rb = if let Some(rbo) = texture.depth_rb {
-                    rbo.0
-                } else {
-                    let renderbuffer_ids = self.gl.gen_renderbuffers(1);
-                    let depth_rb = renderbuffer_ids[0];
-                    texture.depth_rb = Some(RBOId(depth_rb));
-                    depth_rb
-                };
-                self.gl.bind_renderbuffer(gl::RENDERBUFFER, depth_rb);
-                self.gl.renderbuffer_storage(gl::RENDERBUFFER,
-                                             gl::DEPTH_COMPONENT24,
-                                             texture.width as gl::GLsizei,
-                                             texture.height as gl::GLsizei);
-
-                for (fbo_index, fbo_id) in texture.fbo_ids.iter().enumerate() {
-                    self.gl.bind_framebuffer(gl::FRAMEBUFFER, fbo_id.0);
-                    self.gl.framebuffer_texture_layer(gl::FRAMEBUFFER,
-                                                      gl::COLOR_ATTACHMENT0,
-                                                      texture_id.name,
-                                                      0,
-                                                      fbo_index as gl::GLint);
-                    self.gl.framebuffer_renderbuffer(gl::FRAMEBUFFER,
-                                                     gl::DEPTH_ATTACHMENT,
-                                                     gl::RENDERBUFFER,
-                                                     depth_rb);
+                    let depth_rb = if let Some(rbo) = texture.depth_rb {
+                        rbo.0
+                    } else {
+                        let renderbuffer_ids = self.gl.gen_renderbuffers(1);
+                        let depth_rb = renderbuffer_ids[0];
+                        texture.depth_rb = Some(RBOId(depth_rb));
+                        depth_rb
+                    };
+                    self.gl.bind_renderbuffer(gl::RENDERBUFFER, depth_rb);
+                    self.gl.renderbuffer_storage(gl::RENDERBUFFER,
+                                                 gl::DEPTH_COMPONENT24,
+                                                 texture.width as gl::GLsizei,
+                                                 texture.height as gl::GLsizei);
+
+                    texture.layer_count = layer_count;
                 }
+
+                // Any cached framebuffers point at layers of the old
+                // storage, so they must be recreated on their next bind.
+                self.invalidate_fbos(texture_id);
             }
             None => {
-                if texture.fbo_ids.is_empty() {
-                    assert!(texture_id.target != gl::TEXTURE_2D_ARRAY);
-
-                    let new_fbo = self.gl.gen_framebuffers(1)[0];
-                    self.gl.bind_framebuffer(gl::FRAMEBUFFER, new_fbo);
+                let texture = self.textures.get_mut(&texture_id).unwrap();
 
-                    self.gl.framebuffer_texture_2d(gl::FRAMEBUFFER,
-                                                   gl::COLOR_ATTACHMENT0,
-                                                   texture_id.target,
-                                                   texture_id.name,
-                                                   0);
-
-                    texture.fbo_ids.push(FBOId(new_fbo));
+                if texture.layer_count == 0 {
+                    assert!(texture_id.target != gl::TEXTURE_2D_ARRAY);
+                    texture.layer_count = 1;
                 } else {
-                    assert_eq!(texture.fbo_ids.len(), 1);
+                    // A 2D target keeps its cached framebuffer; the
+                    // attachment tracks the texture storage.
+                    assert_eq!(texture.layer_count, 1);
                 }
             }
         }
-
-        // TODO(gw): Hack! Modify the code above to use the normal binding interfaces the device exposes.
-        self.gl.bind_framebuffer(gl::READ_FRAMEBUFFER, self.bound_read_fbo.0);
-        self.gl.bind_framebuffer(gl::DRAW_FRAMEBUFFER, self.bound_draw_fbo.0);
     }
 
     pub fn blit_render_target(&mut self,
@@ -1263,6 +1771,31 @@ impl Device {
                                   gl::LINEAR);
     }
 
+    /// Copies a rect of one texture into another on the GPU, without a
+    /// round trip through the CPU. The source texture must be a render
+    /// target so that it can be bound as a read framebuffer.
+    pub fn copy_texture_rect(&mut self,
+                             src_texture_id: TextureId,
+                             src_rect: DeviceUintRect,
+                             dest_texture_id: TextureId,
+                             dest_origin: DeviceUintPoint) {
+        debug_assert!(self.inside_frame);
+
+        self.bind_read_target(Some((src_texture_id, 0)));
+        self.bind_texture(DEFAULT_TEXTURE, dest_texture_id);
+
+        self.gl.copy_tex_sub_image_2d(dest_texture_id.target,
+                                       0,
+                                       dest_origin.x as i32,
+                                       dest_origin.y as i32,
+                                       src_rect.origin.x as i32,
+                                       src_rect.origin.y as i32,
+                                       src_rect.size.width as i32,
+                                       src_rect.size.height as i32);
+
+        self.bind_read_target(None);
+    }
+
     pub fn resize_texture(&mut self,
                           texture_id: TextureId,
                           new_width: u32,
@@ -1314,32 +1847,48 @@ impl Device {
 
         self.bind_texture(DEFAULT_TEXTURE, texture_id);
 
-        let texture = self.textures.get_mut(&texture_id).unwrap();
-        let (internal_format, gl_format) = gl_texture_formats_for_image_format(&*self.gl, texture.format);
-        let type_ = gl_type_for_texture_format(texture.format);
-
-        self.gl.tex_image_2d(texture_id.target,
-                              0,
-                              internal_format,
-                              0,
-                              0,
-                              0,
-                              gl_format,
-                              type_,
-                              None);
+        {
+            let texture = self.textures.get_mut(&texture_id).unwrap();
+            let (internal_format, gl_format) =
+                gl_texture_formats_for_image_format(&*self.gl,
+                                                    texture.format,
+                                                    self.capabilities.supports_bgra_textures);
+            let type_ = gl_type_for_texture_format(texture.format);
+
+            self.gl.tex_image_2d(texture_id.target,
+                                  0,
+                                  internal_format,
+                                  0,
+                                  0,
+                                  0,
+                                  gl_format,
+                                  type_,
+                                  None);
+
+            if let Some(RBOId(depth_rb)) = texture.depth_rb.take() {
+                self.gl.delete_renderbuffers(&[depth_rb]);
+            }
 
-        if let Some(RBOId(depth_rb)) = texture.depth_rb.take() {
-            self.gl.delete_renderbuffers(&[depth_rb]);
+            texture.format = ImageFormat::Invalid;
+            texture.width = 0;
+            texture.height = 0;
+            texture.layer_count = 0;
         }
 
-        if !texture.fbo_ids.is_empty() {
-            let fbo_ids: Vec<_> = texture.fbo_ids.drain(..).map(|FBOId(fbo_id)| fbo_id).collect();
-            self.gl.delete_framebuffers(&fbo_ids[..]);
-        }
+        self.invalidate_fbos(texture_id);
+    }
 
-        texture.format = ImageFormat::Invalid;
-        texture.width = 0;
-        texture.height = 0;
+    /// Attach a KHR_debug label to a texture, describing what it is used
+    /// for, so that GPU debuggers show something more useful than a bare
+    /// GL name. A no-op when the driver doesn't expose KHR_debug.
+    pub fn set_texture_label(&mut self, texture_id: TextureId, label: &str) {
+        self.gl.object_label_khr(gl::TEXTURE, texture_id.name, label);
+    }
+
+    /// As `set_texture_label`, for vertex array objects.
+    pub fn set_vao_label(&mut self, vao_id: VAOId, label: &str) {
+        let VAOId(id) = vao_id;
+        self.gl.object_label_khr(gl::VERTEX_ARRAY, id, label);
     }
 
     pub fn create_program(&mut self,
@@ -1366,72 +1915,53 @@ impl Device {
 
         let pid = self.gl.create_program();
 
-        let mut vs_name = String::from(base_filename);
-        vs_name.push_str(".vs");
-        let mut fs_name = String::from(base_filename);
-        fs_name.push_str(".fs");
-
-        let mut include = format!("// Base shader: {}\n", base_filename);
-        for inc_filename in include_filenames {
-            let src = get_shader_source(inc_filename, &self.resource_override_path);
-            include.push_str(&src);
-        }
-
-        if let Some(shared_src) = get_optional_shader_source(base_filename, &self.resource_override_path) {
-            include.push_str(&shared_src);
-        }
+        let vs_source = self.preprocessor.process(base_filename,
+                                                  ".vs",
+                                                  "#define WR_VERTEX_SHADER\n",
+                                                  &prefix,
+                                                  include_filenames);
+        let fs_source = self.preprocessor.process(base_filename,
+                                                  ".fs",
+                                                  "#define WR_FRAGMENT_SHADER\n",
+                                                  &prefix,
+                                                  include_filenames);
 
         let mut program = Program {
             name: base_filename.to_owned(),
             id: pid,
             u_transform: -1,
             u_device_pixel_ratio: -1,
-            vs_source: get_shader_source(&vs_name, &self.resource_override_path),
-            fs_source: get_shader_source(&fs_name, &self.resource_override_path),
-            prefix,
+            vs_source,
+            fs_source,
             vs_id: None,
             fs_id: None,
         };
 
-        try!{ self.load_program(&mut program, include, descriptor) };
+        try!{ self.load_program(&mut program, descriptor) };
 
         Ok(program)
     }
 
     fn load_program(&mut self,
                     program: &mut Program,
-                    include: String,
                     descriptor: &VertexDescriptor) -> Result<(), ShaderError> {
         debug_assert!(self.inside_frame);
 
-        let mut vs_preamble = Vec::new();
-        let mut fs_preamble = Vec::new();
-
-        vs_preamble.push("#define WR_VERTEX_SHADER\n".to_owned());
-        fs_preamble.push("#define WR_FRAGMENT_SHADER\n".to_owned());
-
-        if let Some(ref prefix) = program.prefix {
-            vs_preamble.push(prefix.clone());
-            fs_preamble.push(prefix.clone());
-        }
-
-        vs_preamble.push(self.shader_preamble.to_owned());
-        fs_preamble.push(self.shader_preamble.to_owned());
-
-        vs_preamble.push(include.clone());
-        fs_preamble.push(include);
-
         // todo(gw): store shader ids so they can be freed!
         let vs_id = try!{ Device::compile_shader(&*self.gl,
                                                  &program.name,
                                                  &program.vs_source,
                                                  gl::VERTEX_SHADER,
-                                                 &vs_preamble) };
+                                                 get_shader_version(&*self.gl),
+                                                 &[],
+                                                 &mut self.event_sink) };
         let fs_id = try!{ Device::compile_shader(&*self.gl,
                                                  &program.name,
                                                  &program.fs_source,
                                                  gl::FRAGMENT_SHADER,
-                                                 &fs_preamble) };
+                                                 get_shader_version(&*self.gl),
+                                                 &[],
+                                                 &mut self.event_sink) };
 
         if let Some(vs_id) = program.vs_id {
             self.gl.detach_shader(program.id, vs_id);
@@ -1441,9 +1971,11 @@ impl Device {
             self.gl.detach_shader(program.id, fs_id);
         }
 
-        if let Err(bind_error) = program.attach_and_bind_shaders(vs_id, fs_id, descriptor, &*self.gl) {
+        if let Err(bind_error) = program.attach_and_bind_shaders(vs_id, fs_id, descriptor, &*self.gl,
+                                                                &mut self.event_sink) {
             if let (Some(vs_id), Some(fs_id)) = (program.vs_id, program.fs_id) {
-                try! { program.attach_and_bind_shaders(vs_id, fs_id, descriptor, &*self.gl) };
+                try! { program.attach_and_bind_shaders(vs_id, fs_id, descriptor, &*self.gl,
+                                                       &mut self.event_sink) };
             } else {
                return Err(bind_error);
             }
@@ -1460,51 +1992,193 @@ impl Device {
             program.fs_id = Some(fs_id);
         }
 
+        // Label the program with its shader name, so that it shows up by
+        // name in RenderDoc / Android GPU Inspector captures. This is a
+        // no-op when the driver doesn't expose KHR_debug.
+        self.gl.object_label_khr(gl::PROGRAM, program.id, &program.name);
+
         program.u_transform = self.gl.get_uniform_location(program.id, "uTransform");
         program.u_device_pixel_ratio = self.gl.get_uniform_location(program.id, "uDevicePixelRatio");
 
         self.bind_program(program);
-        let u_color_0 = self.gl.get_uniform_location(program.id, "sColor0");
+        self.init_sampler_uniforms(program.id);
+
+        Ok(())
+    }
+
+    /// Points each of the well-known sampler uniforms that a program
+    /// declares at its fixed texture unit. The program must be bound.
+    fn init_sampler_uniforms(&self, program_id: gl::GLuint) {
+        let u_color_0 = self.gl.get_uniform_location(program_id, "sColor0");
         if u_color_0 != -1 {
             self.gl.uniform_1i(u_color_0, TextureSampler::Color0 as i32);
         }
-        let u_color1 = self.gl.get_uniform_location(program.id, "sColor1");
+        let u_color1 = self.gl.get_uniform_location(program_id, "sColor1");
         if u_color1 != -1 {
             self.gl.uniform_1i(u_color1, TextureSampler::Color1 as i32);
         }
-        let u_color_2 = self.gl.get_uniform_location(program.id, "sColor2");
+        let u_color_2 = self.gl.get_uniform_location(program_id, "sColor2");
         if u_color_2 != -1 {
             self.gl.uniform_1i(u_color_2, TextureSampler::Color2 as i32);
         }
-        let u_noise = self.gl.get_uniform_location(program.id, "sDither");
+        let u_noise = self.gl.get_uniform_location(program_id, "sDither");
         if u_noise != -1 {
             self.gl.uniform_1i(u_noise, TextureSampler::Dither as i32);
         }
-        let u_cache_a8 = self.gl.get_uniform_location(program.id, "sCacheA8");
+        let u_cache_a8 = self.gl.get_uniform_location(program_id, "sCacheA8");
         if u_cache_a8 != -1 {
             self.gl.uniform_1i(u_cache_a8, TextureSampler::CacheA8 as i32);
         }
-        let u_cache_rgba8 = self.gl.get_uniform_location(program.id, "sCacheRGBA8");
+        let u_cache_rgba8 = self.gl.get_uniform_location(program_id, "sCacheRGBA8");
         if u_cache_rgba8 != -1 {
             self.gl.uniform_1i(u_cache_rgba8, TextureSampler::CacheRGBA8 as i32);
         }
 
-        let u_layers = self.gl.get_uniform_location(program.id, "sLayers");
+        let u_layers = self.gl.get_uniform_location(program_id, "sLayers");
         if u_layers != -1 {
             self.gl.uniform_1i(u_layers, TextureSampler::Layers as i32);
         }
 
-        let u_tasks = self.gl.get_uniform_location(program.id, "sRenderTasks");
+        let u_tasks = self.gl.get_uniform_location(program_id, "sRenderTasks");
         if u_tasks != -1 {
             self.gl.uniform_1i(u_tasks, TextureSampler::RenderTasks as i32);
         }
 
-        let u_resource_cache = self.gl.get_uniform_location(program.id, "sResourceCache");
+        let u_resource_cache = self.gl.get_uniform_location(program_id, "sResourceCache");
         if u_resource_cache != -1 {
             self.gl.uniform_1i(u_resource_cache, TextureSampler::ResourceCache as i32);
         }
+    }
 
-        Ok(())
+    /// Compiles and links a compute shader. `base_filename` names the
+    /// shader source directly (e.g. "cs_blur.comp" for cs_blur.comp.glsl);
+    /// there is no .vs/.fs pair and no shared preamble, since shared.glsl
+    /// assumes a vertex or fragment stage.
+    pub fn create_compute_program(&mut self,
+                                  base_filename: &str,
+                                  include_filenames: &[&str],
+                                  prefix: Option<String>) -> Result<ComputeProgram, ShaderError> {
+        debug_assert!(self.inside_frame);
+
+        let mut preamble = Vec::new();
+        preamble.push("#define WR_COMPUTE_SHADER\n".to_owned());
+        if let Some(prefix) = prefix {
+            preamble.push(prefix);
+        }
+        for inc_filename in include_filenames {
+            preamble.push(get_shader_source(inc_filename, &self.resource_override_path));
+        }
+
+        let source = get_shader_source(base_filename, &self.resource_override_path);
+        let cs_id = try!{ Device::compile_shader(&*self.gl,
+                                                 base_filename,
+                                                 &source,
+                                                 gl::COMPUTE_SHADER,
+                                                 get_compute_shader_version(&*self.gl),
+                                                 &preamble,
+                                                 &mut self.event_sink) };
+
+        let pid = self.gl.create_program();
+        self.gl.attach_shader(pid, cs_id);
+        self.gl.link_program(pid);
+        if self.gl.get_program_iv(pid, gl::LINK_STATUS) == (0 as gl::GLint) {
+            let error_log = self.gl.get_program_info_log(pid);
+            emit_device_event(&mut self.event_sink,
+                              DeviceEvent::ShaderLinkFailed(base_filename.to_string(), error_log.clone()));
+            self.gl.detach_shader(pid, cs_id);
+            self.gl.delete_shader(cs_id);
+            self.gl.delete_program(pid);
+            return Err(ShaderError::Link(base_filename.to_string(), error_log));
+        }
+        self.gl.delete_shader(cs_id);
+
+        self.gl.object_label_khr(gl::PROGRAM, pid, base_filename);
+
+        let program = ComputeProgram {
+            id: pid,
+            u_data: self.gl.get_uniform_location(pid, "uData"),
+            u_instances: self.gl.get_uniform_location(pid, "uInstances"),
+            u_device_pixel_ratio: self.gl.get_uniform_location(pid, "uDevicePixelRatio"),
+        };
+
+        self.bind_compute_program(&program);
+        self.init_sampler_uniforms(program.id);
+
+        Ok(program)
+    }
+
+    pub fn delete_compute_program(&mut self, program: &mut ComputeProgram) {
+        self.gl.delete_program(program.id);
+        program.id = 0;
+    }
+
+    pub fn bind_compute_program(&mut self, program: &ComputeProgram) {
+        debug_assert!(self.inside_frame);
+
+        if self.bound_program != program.id {
+            self.gl.use_program(program.id);
+            self.bound_program = program.id;
+        }
+
+        if program.u_device_pixel_ratio != -1 {
+            self.gl.uniform_1f(program.u_device_pixel_ratio, self.device_pixel_ratio);
+        }
+    }
+
+    /// Sets the `uData` uniform of a compute program. The interpretation
+    /// of the four integers is up to the shader. The program must be bound.
+    pub fn set_compute_data(&mut self, program: &ComputeProgram, data: [i32; 4]) {
+        debug_assert!(self.inside_frame);
+        debug_assert_eq!(self.bound_program, program.id);
+        if program.u_data != -1 {
+            self.gl.uniform_4i(program.u_data, data[0], data[1], data[2], data[3]);
+        }
+    }
+
+    /// Sets the `uInstances` uniform array of a compute program, four
+    /// integers per instance. The program must be bound.
+    pub fn set_compute_instances(&mut self, program: &ComputeProgram, data: &[i32]) {
+        debug_assert!(self.inside_frame);
+        debug_assert_eq!(self.bound_program, program.id);
+        debug_assert_eq!(data.len() % 4, 0);
+        if program.u_instances != -1 {
+            self.gl.uniform_4iv(program.u_instances, data);
+        }
+    }
+
+    /// Binds one layer of a render target texture to an image unit, so
+    /// that a compute shader can write to it with image store.
+    pub fn bind_image_texture(&mut self,
+                              unit: u32,
+                              texture_id: TextureId,
+                              layer_index: i32,
+                              access: gl::GLenum,
+                              format: gl::GLenum) {
+        debug_assert!(self.inside_frame);
+        self.gl.bind_image_texture(unit,
+                                   texture_id.name,
+                                   0,
+                                   false,
+                                   layer_index,
+                                   access,
+                                   format);
+    }
+
+    pub fn dispatch_compute(&mut self,
+                            num_groups_x: u32,
+                            num_groups_y: u32,
+                            num_groups_z: u32) {
+        debug_assert!(self.inside_frame);
+        self.gl.dispatch_compute(num_groups_x, num_groups_y, num_groups_z);
+    }
+
+    /// Makes image writes from previous compute dispatches visible to
+    /// subsequent image loads, texture fetches and framebuffer draws.
+    pub fn compute_image_barrier(&mut self) {
+        debug_assert!(self.inside_frame);
+        self.gl.memory_barrier(gl::SHADER_IMAGE_ACCESS_BARRIER_BIT |
+                               gl::TEXTURE_FETCH_BARRIER_BIT |
+                               gl::FRAMEBUFFER_BARRIER_BIT);
     }
 
 /*
@@ -1554,6 +2228,12 @@ impl Device {
         self.gl.uniform_2f(location, x, y);
     }
 
+    pub fn set_uniform_mat3(&self, uniform: UniformLocation, values: &[f32; 9]) {
+        debug_assert!(self.inside_frame);
+        let UniformLocation(location) = uniform;
+        self.gl.uniform_matrix_3fv(location, false, values);
+    }
+
     pub fn set_uniforms(&self,
                         program: &Program,
                         transform: &Transform3D<f32>) {
@@ -1639,19 +2319,45 @@ impl Device {
 
         let mut expanded_data = Vec::new();
 
+        let supports_bgra = self.capabilities.supports_bgra_textures;
         let (gl_format, bpp, data, data_type) = match self.textures.get(&texture_id).unwrap().format {
             ImageFormat::A8 => {
                 if cfg!(any(target_arch="arm", target_arch="aarch64")) {
+                    // The expanded texels are gray, so the channel order
+                    // doesn't matter when BGRA isn't available.
+                    let gl_format = if supports_bgra {
+                        get_gl_format_bgra(self.gl())
+                    } else {
+                        gl::RGBA
+                    };
                     expanded_data.extend(data.iter().flat_map(|byte| repeat(*byte).take(4)));
-                    (get_gl_format_bgra(self.gl()), 4, expanded_data.as_slice(), gl::UNSIGNED_BYTE)
+                    (gl_format, 4, expanded_data.as_slice(), gl::UNSIGNED_BYTE)
                 } else {
                     (GL_FORMAT_A, 1, data, gl::UNSIGNED_BYTE)
                 }
             }
             ImageFormat::RGB8 => (gl::RGB, 3, data, gl::UNSIGNED_BYTE),
-            ImageFormat::BGRA8 => (get_gl_format_bgra(self.gl()), 4, data, gl::UNSIGNED_BYTE),
+            ImageFormat::BGRA8 => {
+                if supports_bgra {
+                    (get_gl_format_bgra(self.gl()), 4, data, gl::UNSIGNED_BYTE)
+                } else {
+                    // The driver can't take BGRA texels; swap red and
+                    // blue on the CPU as part of the upload. See
+                    // `Capabilities::supports_bgra_textures`.
+                    expanded_data.reserve(data.len());
+                    for texel in data.chunks(4) {
+                        expanded_data.push(texel[2]);
+                        expanded_data.push(texel[1]);
+                        expanded_data.push(texel[0]);
+                        expanded_data.push(texel[3]);
+                    }
+                    (gl::RGBA, 4, expanded_data.as_slice(), gl::UNSIGNED_BYTE)
+                }
+            }
             ImageFormat::RG8 => (gl::RG, 2, data, gl::UNSIGNED_BYTE),
             ImageFormat::RGBAF32 => (gl::RGBA, 16, data, gl::FLOAT),
+            ImageFormat::RGB10A2 => (gl::RGBA, 4, data, gl::UNSIGNED_INT_2_10_10_10_REV),
+            ImageFormat::RGBA16F => (gl::RGBA, 8, data, gl::HALF_FLOAT),
             ImageFormat::Invalid => unreachable!(),
         };
 
@@ -1671,15 +2377,40 @@ impl Device {
 
         self.bind_texture(DEFAULT_TEXTURE, texture_id);
 
-        self.gl.tex_sub_image_2d(texture_id.target,
-                                 0,
-                                 x0 as gl::GLint,
-                                 y0 as gl::GLint,
-                                 width as gl::GLint,
-                                 height as gl::GLint,
-                                 gl_format,
-                                 data_type,
-                                 data);
+        if self.capabilities.prefer_pbo_texture_uploads {
+            // Stage the texels through a pooled PBO so that the driver
+            // can copy them asynchronously, instead of blocking in
+            // glTexSubImage2D. Orphaning the buffer before filling it
+            // keeps the driver from syncing on an earlier upload that
+            // is still in flight.
+            let pbo_id = self.next_upload_pbo();
+            self.bind_pbo(Some(pbo_id));
+            self.orphan_pbo(data.len());
+            self.gl.buffer_sub_data_untyped(gl::PIXEL_UNPACK_BUFFER,
+                                            0,
+                                            data.len() as isize,
+                                            data.as_ptr() as *const _);
+            self.gl.tex_sub_image_2d_pbo(texture_id.target,
+                                         0,
+                                         x0 as gl::GLint,
+                                         y0 as gl::GLint,
+                                         width as gl::GLint,
+                                         height as gl::GLint,
+                                         gl_format,
+                                         data_type,
+                                         0);
+            self.bind_pbo(None);
+        } else {
+            self.gl.tex_sub_image_2d(texture_id.target,
+                                     0,
+                                     x0 as gl::GLint,
+                                     y0 as gl::GLint,
+                                     width as gl::GLint,
+                                     height as gl::GLint,
+                                     gl_format,
+                                     data_type,
+                                     data);
+        }
 
         // Reset row length to 0, otherwise the stride would apply to all texture uploads.
         if let Some(..) = stride {
@@ -1687,6 +2418,20 @@ impl Device {
         }
     }
 
+    /// Returns the next staging PBO in the upload ring, creating the
+    /// pool on first use.
+    fn next_upload_pbo(&mut self) -> PBOId {
+        if self.upload_pbos.is_empty() {
+            for _ in 0..TEXTURE_UPLOAD_PBO_COUNT {
+                let id = self.gl.gen_buffers(1)[0];
+                self.upload_pbos.push(PBOId(id));
+            }
+        }
+        let pbo_id = self.upload_pbos[self.upload_pbo_index];
+        self.upload_pbo_index = (self.upload_pbo_index + 1) % self.upload_pbos.len();
+        pbo_id
+    }
+
     fn clear_vertex_array(&mut self) {
         debug_assert!(self.inside_frame);
         self.gl.bind_vertex_array(0);
@@ -1726,10 +2471,12 @@ impl Device {
         let vao = VAO {
             gl: Rc::clone(&self.gl),
             id: vao_id,
+            descriptor: *descriptor,
             ibo_id,
             main_vbo_id,
             instance_vbo_id,
             instance_stride,
+            instance_offset: 0,
             owns_indices,
             owns_vertices,
             owns_instances,
@@ -1807,12 +2554,37 @@ impl Device {
                                    usage_hint: VertexUsageHint) {
         debug_assert!(self.inside_frame);
 
-        let vao = self.vaos.get(&vao_id).unwrap();
+        let vao = self.vaos.get_mut(&vao_id).unwrap();
         debug_assert_eq!(self.bound_vao, vao_id);
         debug_assert_eq!(vao.instance_stride as usize, mem::size_of::<V>());
 
-        vao.instance_vbo_id.bind(self.gl());
-        gl::buffer_data(self.gl(), gl::ARRAY_BUFFER, instances, usage_hint.to_gl());
+        // A previous sliced draw may have left the attribute pointers
+        // part-way into the old buffer contents.
+        if vao.instance_offset != 0 {
+            vao.descriptor.bind_instances(&*vao.gl, vao.instance_vbo_id, 0);
+            vao.instance_offset = 0;
+        }
+
+        vao.instance_vbo_id.bind(&*vao.gl);
+        gl::buffer_data(&*vao.gl, gl::ARRAY_BUFFER, instances, usage_hint.to_gl());
+    }
+
+    // Re-points the instance attributes of a VAO at the given instance
+    // within its instance buffer. This allows many batches to share one
+    // buffer upload, with each draw sourcing a contiguous slice of it.
+    pub fn set_vao_instance_offset(&mut self,
+                                   vao_id: VAOId,
+                                   first_instance: usize) {
+        debug_assert!(self.inside_frame);
+
+        let vao = self.vaos.get_mut(&vao_id).unwrap();
+        debug_assert_eq!(self.bound_vao, vao_id);
+
+        if vao.instance_offset != first_instance {
+            let offset = (first_instance * vao.instance_stride as usize) as u32;
+            vao.descriptor.bind_instances(&*vao.gl, vao.instance_vbo_id, offset);
+            vao.instance_offset = first_instance;
+        }
     }
 
     pub fn update_vao_indices<I>(&mut self,
@@ -1858,6 +2630,46 @@ impl Device {
         self.gl.draw_elements_instanced(gl::TRIANGLES, index_count, gl::UNSIGNED_SHORT, 0, instance_count);
     }
 
+    // Submits one instanced draw per (first_instance, instance_count)
+    // entry with a single GL call. The instances are sliced out of the
+    // bound VAO's instance buffer via the base instance of each command,
+    // so the instance attribute pointers must be at offset zero.
+    pub fn draw_indexed_triangles_instanced_multi_u16(&mut self,
+                                                      index_count: i32,
+                                                      draws: &[(usize, usize)]) {
+        debug_assert!(self.inside_frame);
+        debug_assert!(self.capabilities.supports_multi_draw_indirect);
+
+        let commands: Vec<DrawElementsIndirectCommand> = draws.iter()
+            .map(|&(first_instance, instance_count)| {
+                DrawElementsIndirectCommand {
+                    count: index_count as gl::GLuint,
+                    instance_count: instance_count as gl::GLuint,
+                    first_index: 0,
+                    base_vertex: 0,
+                    base_instance: first_instance as gl::GLuint,
+                }
+            })
+            .collect();
+
+        let buffer_id = match self.indirect_buffer_id {
+            Some(buffer_id) => buffer_id,
+            None => {
+                let buffer_id = VBOId(self.gl.gen_buffers(1)[0]);
+                self.indirect_buffer_id = Some(buffer_id);
+                buffer_id
+            }
+        };
+
+        self.gl.bind_buffer(gl::DRAW_INDIRECT_BUFFER, buffer_id.0);
+        gl::buffer_data(self.gl(), gl::DRAW_INDIRECT_BUFFER, &commands, gl::STREAM_DRAW);
+        self.gl.multi_draw_elements_indirect(gl::TRIANGLES,
+                                             gl::UNSIGNED_SHORT,
+                                             0,
+                                             commands.len() as gl::GLsizei,
+                                             0);
+    }
+
     pub fn end_frame(&mut self) {
         self.bind_draw_target(None, None);
         self.bind_read_target(None);
@@ -1878,6 +2690,38 @@ impl Device {
         self.frame_id.0 += 1;
     }
 
+    /// Forget every GL resource this device knows about, without trying to
+    /// delete any of them. Called when the GL context was lost: the resource
+    /// names all belonged to the dead context, and the replacement context
+    /// starts out empty. The caller is responsible for recreating whatever it
+    /// still needs.
+    pub fn on_context_lost(&mut self) {
+        debug_assert!(!self.inside_frame);
+
+        // Dropping the textures and VAOs runs their destructors, which issue
+        // deletes for names the new context has never seen. That is safe: GL
+        // silently ignores deletes of unknown names.
+        self.textures.clear();
+        self.vaos.clear();
+        self.next_vao_id = 1;
+        self.indirect_buffer_id = None;
+        self.upload_pbos.clear();
+        self.upload_pbo_index = 0;
+
+        // None of the cached binding state applies to the new context.
+        // The cached framebuffer names died with the old context, so
+        // drop them without deleting.
+        self.fbo_cache.clear();
+        self.bound_textures = [ TextureId::invalid(); 16 ];
+        self.bound_program = 0;
+        self.bound_vao = VAOId(0);
+        self.bound_pbo = PBOId(0);
+        self.bound_read_fbo = FBOId(0);
+        self.bound_draw_fbo = FBOId(0);
+        self.default_read_fbo = 0;
+        self.default_draw_fbo = 0;
+    }
+
     pub fn clear_target(&self,
                         color: Option<[f32; 4]>,
                         depth: Option<f32>) {
@@ -1922,6 +2766,33 @@ impl Device {
         }
     }
 
+    /// Tells the driver that the given attachments of the current draw
+    /// target need not be preserved, without writing them. On tiler GPUs
+    /// this skips loading or storing the attachment, depending on whether
+    /// it is called at pass start or pass end. A no-op when the driver
+    /// has no invalidation support, so callers keep their regular clears
+    /// as the fallback. Only valid while a texture target is bound; the
+    /// default framebuffer uses different attachment enums.
+    pub fn invalidate_target(&self, color: bool, depth: bool) {
+        if !self.capabilities.supports_framebuffer_invalidation {
+            return;
+        }
+
+        let mut attachments = [0; 2];
+        let mut count = 0;
+        if color {
+            attachments[count] = gl::COLOR_ATTACHMENT0;
+            count += 1;
+        }
+        if depth {
+            attachments[count] = gl::DEPTH_ATTACHMENT;
+            count += 1;
+        }
+        if count != 0 {
+            self.gl.invalidate_framebuffer(gl::FRAMEBUFFER, &attachments[..count]);
+        }
+    }
+
     pub fn enable_depth(&self) {
         self.gl.enable(gl::DEPTH_TEST);
     }
@@ -1942,14 +2813,33 @@ impl Device {
         self.gl.depth_mask(false);
     }
 
+    pub fn enable_color_write(&self) {
+        self.gl.color_mask(true, true, true, true);
+    }
+
+    pub fn disable_color_write(&self) {
+        self.gl.color_mask(false, false, false, false);
+    }
+
     pub fn disable_stencil(&self) {
         self.gl.disable(gl::STENCIL_TEST);
     }
 
+    pub fn enable_scissor(&self) {
+        self.gl.enable(gl::SCISSOR_TEST);
+    }
+
     pub fn disable_scissor(&self) {
         self.gl.disable(gl::SCISSOR_TEST);
     }
 
+    pub fn set_scissor_rect(&self, rect: DeviceIntRect) {
+        self.gl.scissor(rect.origin.x,
+                        rect.origin.y,
+                        rect.size.width,
+                        rect.size.height);
+    }
+
     pub fn set_blend(&self, enable: bool) {
         if enable {
             self.gl.enable(gl::BLEND);
@@ -1979,6 +2869,11 @@ impl Device {
                                      gl::ZERO, gl::SRC_ALPHA);
         self.gl.blend_equation(gl::FUNC_ADD);
     }
+    pub fn set_blend_mode_screen(&self) {
+        // Screen with a premultiplied source: dest = src + dest * (1 - src).
+        self.gl.blend_func(gl::ONE, gl::ONE_MINUS_SRC_COLOR);
+        self.gl.blend_equation(gl::FUNC_ADD);
+    }
     pub fn set_blend_mode_max(&self) {
         self.gl.blend_func_separate(gl::ONE, gl::ONE,
                                      gl::ONE, gl::ONE);
@@ -1998,17 +2893,29 @@ impl Drop for Device {
 }
 
 /// return (gl_internal_format, gl_format)
-fn gl_texture_formats_for_image_format(gl: &gl::Gl, format: ImageFormat) -> (gl::GLint, gl::GLuint) {
+fn gl_texture_formats_for_image_format(gl: &gl::Gl,
+                                       format: ImageFormat,
+                                       supports_bgra: bool) -> (gl::GLint, gl::GLuint) {
     match format {
         ImageFormat::A8 => {
             if cfg!(any(target_arch="arm", target_arch="aarch64")) {
-                (get_gl_format_bgra(gl) as gl::GLint, get_gl_format_bgra(gl))
+                if supports_bgra {
+                    (get_gl_format_bgra(gl) as gl::GLint, get_gl_format_bgra(gl))
+                } else {
+                    (gl::RGBA as gl::GLint, gl::RGBA)
+                }
             } else {
                 (GL_FORMAT_A as gl::GLint, GL_FORMAT_A)
             }
         },
         ImageFormat::RGB8 => (gl::RGB as gl::GLint, gl::RGB),
         ImageFormat::BGRA8 => {
+            // Without EXT_texture_format_BGRA8888 a GLES context only
+            // takes RGBA; the texels are swizzled on the CPU during
+            // upload. See `Capabilities::supports_bgra_textures`.
+            if !supports_bgra {
+                return (gl::RGBA as gl::GLint, gl::RGBA);
+            }
             match gl.get_type() {
                 gl::GlType::Gl =>  {
                     (gl::RGBA as gl::GLint, get_gl_format_bgra(gl))
@@ -2020,6 +2927,8 @@ fn gl_texture_formats_for_image_format(gl: &gl::Gl, format: ImageFormat) -> (gl:
         }
         ImageFormat::RGBAF32 => (gl::RGBA32F as gl::GLint, gl::RGBA),
         ImageFormat::RG8 => (gl::RG8 as gl::GLint, gl::RG),
+        ImageFormat::RGB10A2 => (gl::RGB10_A2 as gl::GLint, gl::RGBA),
+        ImageFormat::RGBA16F => (gl::RGBA16F as gl::GLint, gl::RGBA),
         ImageFormat::Invalid => unreachable!(),
     }
 }
@@ -2027,6 +2936,8 @@ fn gl_texture_formats_for_image_format(gl: &gl::Gl, format: ImageFormat) -> (gl:
 fn gl_type_for_texture_format(format: ImageFormat) -> gl::GLuint {
     match format {
         ImageFormat::RGBAF32 => gl::FLOAT,
+        ImageFormat::RGB10A2 => gl::UNSIGNED_INT_2_10_10_10_REV,
+        ImageFormat::RGBA16F => gl::HALF_FLOAT,
         _ => gl::UNSIGNED_BYTE,
     }
 }
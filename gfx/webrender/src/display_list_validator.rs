@@ -0,0 +1,92 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Display list validation.
+//!
+//! Content can hand the backend an arbitrary display list, and a
+//! defective one (NaN rects, inverted clips, absurd blur radii) would
+//! otherwise produce undefined rendering or panics deep inside tiling.
+//! This pass walks a freshly received list before it enters the scene
+//! and collects structured warnings, which the backend forwards to the
+//! embedder via `RenderNotifier::display_list_warning`. Lists with a
+//! fatal warning are dropped and the previous scene for the pipeline is
+//! kept. The pass runs in debug builds by default; see
+//! `RendererOptions::validate_display_lists`.
+
+use api::{BuiltDisplayList, DisplayListWarning, FilterOp, LayoutRect, SpecificDisplayItem};
+
+// The largest blur radius the downscaling blur passes handle without
+// visible artifacts. Larger (finite) radii are only reported, since the
+// shadow still draws, just poorly.
+const MAX_BLUR_RADIUS: f32 = 300.0;
+
+/// Walks the display list and returns a warning for every defect found,
+/// in item order. An empty vector means the list is clean.
+pub fn validate_display_list(list: &BuiltDisplayList) -> Vec<DisplayListWarning> {
+    let mut warnings = Vec::new();
+    let mut sc_depth = 0;
+    let mut index = 0;
+    let mut traversal = list.iter();
+    while let Some(item) = traversal.next() {
+        check_rect(&item.rect(), index, &mut warnings);
+        check_rect(item.local_clip().clip_rect(), index, &mut warnings);
+
+        match *item.item() {
+            SpecificDisplayItem::PushStackingContext(..) => {
+                sc_depth += 1;
+                for filter in item.display_list().get(item.filters()) {
+                    if let FilterOp::Blur(radius) = filter {
+                        check_blur_radius(radius, index, &mut warnings);
+                    }
+                }
+            }
+            SpecificDisplayItem::PopStackingContext => {
+                if sc_depth == 0 {
+                    warnings.push(DisplayListWarning::UnbalancedStackingContexts(index));
+                } else {
+                    sc_depth -= 1;
+                }
+            }
+            SpecificDisplayItem::BoxShadow(ref info) => {
+                check_rect(&info.box_bounds, index, &mut warnings);
+                check_blur_radius(info.blur_radius, index, &mut warnings);
+            }
+            SpecificDisplayItem::PushTextShadow(ref shadow) => {
+                check_blur_radius(shadow.blur_radius, index, &mut warnings);
+            }
+            _ => {}
+        }
+        index += 1;
+    }
+    if sc_depth != 0 {
+        warnings.push(DisplayListWarning::UnbalancedStackingContexts(index));
+    }
+    warnings
+}
+
+/// True when the defect makes the whole list unusable, as opposed to one
+/// the frame builder can clamp its way around.
+pub fn is_fatal(warning: &DisplayListWarning) -> bool {
+    match *warning {
+        DisplayListWarning::NonFiniteRect(..) |
+        DisplayListWarning::UnbalancedStackingContexts(..) => true,
+        DisplayListWarning::NegativeRectSize(..) => false,
+        DisplayListWarning::ExcessiveBlurRadius(_, radius) => !radius.is_finite(),
+    }
+}
+
+fn check_rect(rect: &LayoutRect, index: usize, warnings: &mut Vec<DisplayListWarning>) {
+    if !rect.origin.x.is_finite() || !rect.origin.y.is_finite() ||
+       !rect.size.width.is_finite() || !rect.size.height.is_finite() {
+        warnings.push(DisplayListWarning::NonFiniteRect(index));
+    } else if rect.size.width < 0.0 || rect.size.height < 0.0 {
+        warnings.push(DisplayListWarning::NegativeRectSize(index));
+    }
+}
+
+fn check_blur_radius(radius: f32, index: usize, warnings: &mut Vec<DisplayListWarning>) {
+    if !radius.is_finite() || radius > MAX_BLUR_RADIUS {
+        warnings.push(DisplayListWarning::ExcessiveBlurRadius(index, radius));
+    }
+}
@@ -0,0 +1,238 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A correctness audit for fractional device pixel ratios.
+//!
+//! `Renderer::audit_device_pixel_ratio` draws a calibration pattern at a
+//! given ratio - one logical pixel bars swept across sub-pixel offsets,
+//! fixed width bars, and the same text twice a whole number of device
+//! pixels apart - reads it back, and checks the result against the
+//! snapping rules the rest of the pipeline is supposed to follow. This
+//! module holds the pattern layout and the readback analysis; the GL
+//! work lives in the renderer. Failing cases come back as structured
+//! results, so a harness can run the audit at ratios like 1.25, 1.5 and
+//! 1.75 and catalogue systemic rounding issues instead of eyeballing
+//! screenshots.
+
+use std::cmp;
+
+/// The device size of the calibration target, sized for ratios up to 2.
+pub const TARGET_WIDTH: u32 = 512;
+pub const TARGET_HEIGHT: u32 = 96;
+
+/// Logical stride between bars, and how many are drawn. Each bar adds an
+/// extra eighth of a logical pixel of offset, so the common fractional
+/// ratios see every sub-pixel phase.
+const BAR_STRIDE: u32 = 16;
+const BAR_COUNT: u32 = 12;
+
+/// Device pixel rows of the two bar bands, in the top-down coordinates
+/// the pattern is drawn in.
+pub const EDGE_BAND_TOP: f32 = 8.0;
+pub const EDGE_BAND_BOTTOM: f32 = 24.0;
+pub const WIDTH_BAND_TOP: f32 = 40.0;
+pub const WIDTH_BAND_BOTTOM: f32 = 56.0;
+
+/// The logical width of the width consistency bars.
+pub const WIDTH_BAR_LOGICAL: f32 = 2.0;
+
+/// The logical x position of the text sample, the exact device pixel
+/// offset its copy is drawn at, and the baseline row.
+pub const TEXT_LOGICAL_X: f32 = 3.3;
+pub const TEXT_COPY_OFFSET: f32 = 64.0;
+pub const TEXT_BASELINE: f32 = 80.0;
+pub const TEXT_SAMPLE: &'static str = "Ag15";
+
+/// The device pixel rows and columns compared for the text check; wide
+/// enough for the sample, narrow enough that the windows don't overlap.
+const TEXT_BAND_TOP: u32 = 60;
+const TEXT_BAND_BOTTOM: u32 = 92;
+const TEXT_WINDOW_WIDTH: u32 = 56;
+
+/// The aspect of fractional ratio handling a failing case covers.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DprAuditCase {
+    /// A snapped edge rasterized to a different device pixel than the
+    /// snapping math places it on.
+    EdgeSnapping,
+    /// A bar of fixed logical width rasterized to a device width that
+    /// depends on its sub-pixel offset.
+    WidthConsistency,
+    /// The same text drawn a whole number of device pixels apart
+    /// rasterized differently.
+    TextConsistency,
+}
+
+/// One failing case from the audit. `expected` and `actual` hold the
+/// edge position for `EdgeSnapping`, the width for `WidthConsistency`,
+/// and the differing pixel count (expected zero) for `TextConsistency`;
+/// an `actual` of -1 means the element didn't rasterize at all.
+#[derive(Clone, Debug)]
+pub struct DprAuditFailure {
+    pub case: DprAuditCase,
+    /// The ratio under audit.
+    pub device_pixel_ratio: f32,
+    /// The logical x coordinate of the element that misrendered.
+    pub logical: f32,
+    pub expected: i32,
+    pub actual: i32,
+}
+
+/// Snaps a device coordinate to the nearest device pixel, the same rule
+/// the primitive shaders apply to snapped edges.
+pub fn snap(device: f32) -> i32 {
+    device.round() as i32
+}
+
+/// The logical x positions the bars are drawn at.
+pub fn bar_positions() -> Vec<f32> {
+    (0 .. BAR_COUNT).map(|i| (i * BAR_STRIDE) as f32 + i as f32 * 0.125)
+                    .collect()
+}
+
+/// Checks the pattern readback (tightly packed RGBA8, bottom-up rows as
+/// `glReadPixels` produces) against the layout above, returning every
+/// failing case.
+pub fn analyze(pixels: &[u8], device_pixel_ratio: f32) -> Vec<DprAuditFailure> {
+    assert_eq!(pixels.len(), (TARGET_WIDTH * TARGET_HEIGHT * 4) as usize);
+    let dpr = device_pixel_ratio;
+    let positions = bar_positions();
+    let tolerance = ((BAR_STRIDE as f32 * dpr) as i32) / 2;
+    let mut failures = Vec::new();
+
+    // Edge snapping: each one logical pixel bar must fill exactly the
+    // device pixels the snapping rule predicts for its edges.
+    let edge_row = (EDGE_BAND_TOP + EDGE_BAND_BOTTOM) as u32 / 2;
+    let spans = filled_spans(row(pixels, edge_row));
+    for &x in &positions {
+        let expected_start = snap(x * dpr);
+        // A one logical pixel bar must stay visible even when both edges
+        // snap to the same device pixel.
+        let expected_end = cmp::max(expected_start + 1, snap((x + 1.0) * dpr));
+        match find_span(&spans, expected_start, tolerance) {
+            Some(span) => {
+                if span.0 != expected_start {
+                    failures.push(DprAuditFailure {
+                        case: DprAuditCase::EdgeSnapping,
+                        device_pixel_ratio: dpr,
+                        logical: x,
+                        expected: expected_start,
+                        actual: span.0,
+                    });
+                }
+                if span.1 != expected_end {
+                    failures.push(DprAuditFailure {
+                        case: DprAuditCase::EdgeSnapping,
+                        device_pixel_ratio: dpr,
+                        logical: x,
+                        expected: expected_end,
+                        actual: span.1,
+                    });
+                }
+            }
+            None => {
+                failures.push(DprAuditFailure {
+                    case: DprAuditCase::EdgeSnapping,
+                    device_pixel_ratio: dpr,
+                    logical: x,
+                    expected: expected_start,
+                    actual: -1,
+                });
+            }
+        }
+    }
+
+    // Width consistency: bars of the same logical width must come out
+    // the same device width wherever they sit.
+    let width_row = (WIDTH_BAND_TOP + WIDTH_BAND_BOTTOM) as u32 / 2;
+    let spans = filled_spans(row(pixels, width_row));
+    let expected_width = cmp::max(1, snap(WIDTH_BAR_LOGICAL * dpr));
+    for &x in &positions {
+        match find_span(&spans, snap(x * dpr), tolerance) {
+            Some(span) => {
+                let actual = span.1 - span.0;
+                if actual != expected_width {
+                    failures.push(DprAuditFailure {
+                        case: DprAuditCase::WidthConsistency,
+                        device_pixel_ratio: dpr,
+                        logical: x,
+                        expected: expected_width,
+                        actual,
+                    });
+                }
+            }
+            None => {
+                failures.push(DprAuditFailure {
+                    case: DprAuditCase::WidthConsistency,
+                    device_pixel_ratio: dpr,
+                    logical: x,
+                    expected: expected_width,
+                    actual: -1,
+                });
+            }
+        }
+    }
+
+    // Text consistency: the second copy sits a whole number of device
+    // pixels to the right of the first, so the two windows must match
+    // pixel for pixel.
+    let window_start = (TEXT_LOGICAL_X * dpr).floor() as usize;
+    let copy_offset = TEXT_COPY_OFFSET as usize;
+    let mut differing = 0;
+    for y in TEXT_BAND_TOP .. TEXT_BAND_BOTTOM {
+        let line = row(pixels, y);
+        for x in window_start .. window_start + TEXT_WINDOW_WIDTH as usize {
+            if line[x * 4 .. x * 4 + 4] != line[(x + copy_offset) * 4 .. (x + copy_offset) * 4 + 4] {
+                differing += 1;
+            }
+        }
+    }
+    if differing != 0 {
+        failures.push(DprAuditFailure {
+            case: DprAuditCase::TextConsistency,
+            device_pixel_ratio: dpr,
+            logical: TEXT_LOGICAL_X,
+            expected: 0,
+            actual: differing,
+        });
+    }
+
+    failures
+}
+
+/// The readback row holding the device pixel row `y_device`. The pattern
+/// is drawn with the top-down debug projection, while `glReadPixels`
+/// hands rows back bottom-up.
+fn row(pixels: &[u8], y_device: u32) -> &[u8] {
+    let y = (TARGET_HEIGHT - 1 - y_device) as usize;
+    let stride = (TARGET_WIDTH * 4) as usize;
+    &pixels[y * stride .. (y + 1) * stride]
+}
+
+/// Scans a readback row for runs of lit pixels, as half open spans.
+fn filled_spans(line: &[u8]) -> Vec<(i32, i32)> {
+    let mut spans = Vec::new();
+    let mut start = None;
+    for (x, texel) in line.chunks(4).enumerate() {
+        let lit = texel[0] > 127;
+        match (lit, start) {
+            (true, None) => start = Some(x as i32),
+            (false, Some(span_start)) => {
+                spans.push((span_start, x as i32));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(span_start) = start {
+        spans.push((span_start, (line.len() / 4) as i32));
+    }
+    spans
+}
+
+fn find_span(spans: &[(i32, i32)], near_start: i32, tolerance: i32) -> Option<(i32, i32)> {
+    spans.iter()
+         .cloned()
+         .find(|span| (span.0 - near_start).abs() <= tolerance)
+}
@@ -2,17 +2,19 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use api::{BuiltDisplayList, BuiltDisplayListIter, ClipAndScrollInfo, ClipId, ColorF};
+use api::{BuiltDisplayList, BuiltDisplayListIter, ClipAndScrollInfo, ClipId, ClipMode, ColorF};
 use api::{ComplexClipRegion, DeviceUintRect, DeviceUintSize, DisplayItemRef, Epoch, FilterOp};
+use api::HitTestResult;
 use api::{ImageDisplayItem, ItemRange, LayerPoint, LayerRect, LayerSize, LayerToScrollTransform};
-use api::{LayerVector2D, LayoutSize, LayoutTransform, LocalClip, MixBlendMode, PipelineId};
+use api::{LayerVector2D, LayoutSize, LayoutTransform, LocalClip, MixBlendMode, PipelineId, PixelSnapping};
 use api::{PropertyBinding, ScrollClamping, ScrollEventPhase, ScrollLayerState, ScrollLocation};
 use api::{ScrollPolicy, ScrollSensitivity, SpecificDisplayItem, StackingContext, TileOffset};
 use api::{TransformStyle, WorldPoint};
 use clip_scroll_tree::{ClipScrollTree, ScrollStates};
 use euclid::rect;
-use gpu_cache::GpuCache;
-use internal_types::{FastHashMap, RendererFrame};
+use gpu_cache::{GpuCache, GpuCacheHandle};
+use hit_test;
+use internal_types::{FastHashMap, QualitySettings, RendererFrame};
 use frame_builder::{FrameBuilder, FrameBuilderConfig};
 use mask_cache::ClipRegion;
 use profiler::{GpuCacheProfileCounters, TextureCacheProfileCounters};
@@ -96,12 +98,12 @@ struct FlattenContext<'a> {
 impl<'a> FlattenContext<'a> {
     fn new(scene: &'a Scene,
            builder: &'a mut FrameBuilder,
-           resource_cache: &ResourceCache)
+           tiled_image_map: TiledImageMap)
            -> FlattenContext<'a> {
         FlattenContext {
             scene,
             builder,
-            tiled_image_map: resource_cache.get_tiled_image_map(),
+            tiled_image_map,
             replacements: Vec::new(),
             nested_display_list_info: Vec::new(),
             current_nested_display_list_index: 0,
@@ -170,6 +172,18 @@ impl<'a> FlattenContext<'a> {
     }
 }
 
+/// A reference frame in the current clip-scroll tree whose transform is driven
+/// by an animated property binding. The static parts of the transform (the
+/// reference frame relative offset, bounds origin and perspective) are kept
+/// here so that a new binding value can be combined with them and patched into
+/// the tree without flattening the display list again.
+struct AnimatedReferenceFrame {
+    clip_id: ClipId,
+    transform: PropertyBinding<LayoutTransform>,
+    pre_transform: LayerToScrollTransform,
+    perspective: LayoutTransform,
+}
+
 // TODO: doc
 pub struct Frame {
     pub clip_scroll_tree: ClipScrollTree,
@@ -177,14 +191,19 @@ pub struct Frame {
     id: FrameId,
     frame_builder_config: FrameBuilderConfig,
     frame_builder: Option<FrameBuilder>,
+    animated_reference_frames: Vec<AnimatedReferenceFrame>,
+    // GPU cache locations harvested from the frame this one replaces, to be
+    // seeded into the frame builder on the next `create`. Only used when the
+    // frame is built away from the frame it replaces (on the scene builder
+    // thread); otherwise the frame builder recycles its own locations.
+    gpu_location_seed: Option<FastHashMap<u64, GpuCacheHandle>>,
 }
 
 trait StackingContextHelpers {
     fn mix_blend_mode_for_compositing(&self) -> Option<MixBlendMode>;
     fn filter_ops_for_compositing(&self,
                                   display_list: &BuiltDisplayList,
-                                  input_filters: ItemRange<FilterOp>,
-                                  properties: &SceneProperties) -> Vec<FilterOp>;
+                                  input_filters: ItemRange<FilterOp>) -> Vec<FilterOp>;
 }
 
 impl StackingContextHelpers for StackingContext {
@@ -197,19 +216,17 @@ impl StackingContextHelpers for StackingContext {
 
     fn filter_ops_for_compositing(&self,
                                   display_list: &BuiltDisplayList,
-                                  input_filters: ItemRange<FilterOp>,
-                                  properties: &SceneProperties) -> Vec<FilterOp> {
+                                  input_filters: ItemRange<FilterOp>) -> Vec<FilterOp> {
+        // Bound opacity values deliberately stay unresolved here: the frame
+        // builder resolves them against the current scene properties on
+        // every frame build, so animating them never requires a scene
+        // rebuild.
         let mut filters = vec![];
         for filter in display_list.get(input_filters) {
             if filter.is_noop() {
                 continue;
             }
-            if let FilterOp::Opacity(ref value) = filter {
-                let amount = properties.resolve_float(value, 1.0);
-                filters.push(FilterOp::Opacity(PropertyBinding::Value(amount)));
-            } else {
-                filters.push(filter);
-            }
+            filters.push(filter);
         }
         filters
     }
@@ -223,11 +240,39 @@ impl Frame {
             id: FrameId(0),
             frame_builder: None,
             frame_builder_config: config,
+            animated_reference_frames: Vec::new(),
+            gpu_location_seed: None,
+        }
+    }
+
+    /// Replaces the quality settings used when building the scene. Takes
+    /// effect the next time the scene is flattened.
+    pub fn set_quality(&mut self, quality: QualitySettings) {
+        self.frame_builder_config.quality = quality;
+    }
+
+    pub fn quality(&self) -> QualitySettings {
+        self.frame_builder_config.quality
+    }
+
+    /// The GPU cache locations of this frame's interned primitives. Pass the
+    /// result to `seed_gpu_locations` on a frame that is being built to
+    /// replace this one, so that unchanged primitives keep their GPU cache
+    /// blocks across the swap.
+    pub fn harvest_gpu_locations(&self) -> FastHashMap<u64, GpuCacheHandle> {
+        match self.frame_builder {
+            Some(ref frame_builder) => frame_builder.harvest_gpu_locations(),
+            None => FastHashMap::default(),
         }
     }
 
+    pub fn seed_gpu_locations(&mut self, locations: FastHashMap<u64, GpuCacheHandle>) {
+        self.gpu_location_seed = Some(locations);
+    }
+
     pub fn reset(&mut self) -> ScrollStates {
         self.pipeline_epoch_map.clear();
+        self.animated_reference_frames.clear();
 
         // Advance to the next frame.
         self.id.0 += 1;
@@ -239,6 +284,18 @@ impl Frame {
         self.clip_scroll_tree.get_scroll_node_state()
     }
 
+    /// Hit tests the tagged items of the last built scene against the
+    /// clip-scroll tree's current scroll offsets and transforms.
+    pub fn hit_test(&self,
+                    pipeline_id: Option<PipelineId>,
+                    point: WorldPoint) -> HitTestResult {
+        let items = match self.frame_builder {
+            Some(ref builder) => builder.hit_testing_items(),
+            None => return HitTestResult::default(),
+        };
+        hit_test::hit_test(&self.clip_scroll_tree, items, pipeline_id, point)
+    }
+
     /// Returns true if the node actually changed position or false otherwise.
     pub fn scroll_node(&mut self, origin: LayerPoint, id: ClipId, clamp: ScrollClamping) -> bool {
         self.clip_scroll_tree.scroll_node(origin, id, clamp)
@@ -261,9 +318,32 @@ impl Frame {
         self.clip_scroll_tree.discard_frame_state_for_pipeline(pipeline_id);
     }
 
+    /// Re-resolve any animated transform bindings against the given property
+    /// set and patch the resolved values into the clip-scroll tree. This is
+    /// cheap relative to rebuilding the scene and is all that is required to
+    /// animate a bound transform.
+    pub fn update_animated_properties(&mut self, properties: &SceneProperties) {
+        for animated in &self.animated_reference_frames {
+            let resolved = properties.resolve_layout_transform(Some(&animated.transform));
+            let transform = animated.pre_transform
+                                    .pre_mul(&resolved)
+                                    .pre_mul(&animated.perspective);
+            self.clip_scroll_tree.update_reference_frame_transform(animated.clip_id, transform);
+        }
+    }
+
+    /// Adopt the parts of the frame state that have to survive a scene swap:
+    /// scroll offsets, and the frame id that the resource cache uses to track
+    /// entry expiry. `old_frame` is the frame that this one is replacing.
+    pub fn adopt_frame_state(&mut self, old_frame: &mut Frame) {
+        self.id = old_frame.id;
+        let old_scroll_states = old_frame.clip_scroll_tree.drain();
+        self.clip_scroll_tree.finalize_and_apply_pending_scroll_offsets(old_scroll_states);
+    }
+
     pub fn create(&mut self,
                   scene: &Scene,
-                  resource_cache: &mut ResourceCache,
+                  tiled_image_map: TiledImageMap,
                   window_size: DeviceUintSize,
                   inner_rect: DeviceUintRect,
                   device_pixel_ratio: f32) {
@@ -303,8 +383,12 @@ impl Frame {
                                                   background_color,
                                                   self.frame_builder_config);
 
+        if let Some(locations) = self.gpu_location_seed.take() {
+            frame_builder.seed_gpu_locations(locations);
+        }
+
         {
-            let mut context = FlattenContext::new(scene, &mut frame_builder, resource_cache);
+            let mut context = FlattenContext::new(scene, &mut frame_builder, tiled_image_map);
 
             context.builder.push_root(root_pipeline_id,
                                       &root_pipeline.viewport_size,
@@ -387,7 +471,7 @@ impl Frame {
                                       .get(&pipeline_id)
                                       .expect("No display list?!");
             CompositeOps::new(
-                stacking_context.filter_ops_for_compositing(display_list, filters, &context.scene.properties),
+                stacking_context.filter_ops_for_compositing(display_list, filters),
                 stacking_context.mix_blend_mode_for_compositing())
         };
 
@@ -406,17 +490,18 @@ impl Frame {
         let is_reference_frame = stacking_context.transform.is_some() ||
                                  stacking_context.perspective.is_some();
         if is_reference_frame {
-            let transform = stacking_context.transform.as_ref();
-            let transform = context.scene.properties.resolve_layout_transform(transform);
+            let resolved_transform = context.scene
+                                            .properties
+                                            .resolve_layout_transform(stacking_context.transform.as_ref());
             let perspective =
                 stacking_context.perspective.unwrap_or_else(LayoutTransform::identity);
-            let transform =
+            let pre_transform =
                 LayerToScrollTransform::create_translation(reference_frame_relative_offset.x,
                                                            reference_frame_relative_offset.y,
                                                            0.0)
-                                        .pre_translate(bounds.origin.to_vector().to_3d())
-                                        .pre_mul(&transform)
-                                        .pre_mul(&perspective);
+                                        .pre_translate(bounds.origin.to_vector().to_3d());
+            let transform = pre_transform.pre_mul(&resolved_transform)
+                                         .pre_mul(&perspective);
 
             let reference_frame_bounds = LayerRect::new(LayerPoint::zero(), bounds.size);
             let mut clip_id = context.apply_scroll_frame_id_replacement(context_scroll_node_id);
@@ -425,6 +510,19 @@ impl Frame {
                                                            &reference_frame_bounds,
                                                            &transform,
                                                            &mut self.clip_scroll_tree);
+
+            // If the transform is an animated binding, remember enough about this
+            // reference frame that new binding values can be applied to the tree
+            // without having to flatten the display list again.
+            if let Some(transform_binding @ PropertyBinding::Binding(..)) = stacking_context.transform {
+                self.animated_reference_frames.push(AnimatedReferenceFrame {
+                    clip_id,
+                    transform: transform_binding,
+                    pre_transform,
+                    perspective,
+                });
+            }
+
             context.replacements.push((context_scroll_node_id, clip_id));
             reference_frame_relative_offset = LayerVector2D::zero();
         } else {
@@ -433,10 +531,17 @@ impl Frame {
                 reference_frame_relative_offset.y + bounds.origin.y);
         }
 
+        let has_animated_transform = match stacking_context.transform {
+            Some(PropertyBinding::Binding(..)) => true,
+            _ => false,
+        };
         context.builder.push_stacking_context(&reference_frame_relative_offset,
                                               pipeline_id,
                                               composition_operations,
-                                              stacking_context.transform_style);
+                                              stacking_context.transform_style,
+                                              stacking_context.pixel_snapping,
+                                              has_animated_transform,
+                                              stacking_context.will_change);
 
         self.flatten_items(traversal,
                            pipeline_id,
@@ -527,6 +632,19 @@ impl Frame {
 
         let item_rect_with_offset = item.rect().translate(&reference_frame_relative_offset);
         let clip_with_offset = item.local_clip_with_offset(&reference_frame_relative_offset);
+
+        // Record tagged items against the resolved clip and scroll info, so
+        // hit tests look nodes up by the same ids the primitives use. The
+        // tag is also stamped onto the primitives the item creates below,
+        // for the renderer's debug views.
+        context.builder.set_current_item_tag(item.tag());
+        if let Some(tag) = item.tag() {
+            context.builder.add_hit_test_item(clip_and_scroll,
+                                              item_rect_with_offset,
+                                              &clip_with_offset,
+                                              tag);
+        }
+
         match *item.item() {
             SpecificDisplayItem::WebGL(ref info) => {
                 context.builder.add_webgl_rectangle(clip_and_scroll,
@@ -577,6 +695,16 @@ impl Frame {
                                          item.display_list().get(item.glyphs()).count(),
                                          text_info.glyph_options);
             }
+            SpecificDisplayItem::FilledPath(ref info) => {
+                context.builder.add_filled_path(clip_and_scroll,
+                                                item_rect_with_offset,
+                                                &clip_with_offset,
+                                                &info.color,
+                                                info.fill_rule,
+                                                item.display_list()
+                                                    .get(item.path_commands())
+                                                    .collect());
+            }
             SpecificDisplayItem::Rectangle(ref info) => {
                 if !self.try_to_add_rectangle_splitting_on_clip(context,
                                                                 &item_rect_with_offset,
@@ -612,6 +740,7 @@ impl Frame {
                                              item.display_list()
                                                  .get(item.gradient_stops()).count(),
                                              info.gradient.extend_mode,
+                                             info.gradient.interpolation,
                                              info.tile_size,
                                              info.tile_spacing);
             }
@@ -626,6 +755,7 @@ impl Frame {
                                                     info.gradient.ratio_xy,
                                                     item.gradient_stops(),
                                                     info.gradient.extend_mode,
+                                                    info.gradient.interpolation,
                                                     info.tile_size,
                                                     info.tile_spacing);
             }
@@ -754,7 +884,14 @@ impl Frame {
 
         let inner_unclipped_rect = match local_clip {
             &LocalClip::Rect(_) => return false,
-            &LocalClip::RoundedRect(_, ref region) => region.get_inner_rect_full(),
+            &LocalClip::RoundedRect(_, ref region) => {
+                // For a clip-out region, the inner rect is the part that is
+                // clipped away, so there's nothing to split out.
+                if region.mode != ClipMode::Clip {
+                    return false;
+                }
+                region.get_inner_rect_full()
+            }
         };
         let inner_unclipped_rect = match inner_unclipped_rect {
             Some(rect) => rect,
@@ -791,7 +928,10 @@ impl Frame {
         context.builder.push_stacking_context(&LayerVector2D::zero(),
                                               pipeline_id,
                                               CompositeOps::default(),
-                                              TransformStyle::Flat);
+                                              TransformStyle::Flat,
+                                              PixelSnapping::Auto,
+                                              false,
+                                              false);
 
         // We do this here, rather than above because we want any of the top-level
         // stacking contexts in the display list to be treated like root stacking contexts.
@@ -1153,15 +1293,18 @@ impl Frame {
                  resource_cache: &mut ResourceCache,
                  gpu_cache: &mut GpuCache,
                  display_lists: &DisplayListMap,
+                 properties: &SceneProperties,
                  device_pixel_ratio: f32,
                  pan: LayerPoint,
+                 async_zoom: f32,
                  texture_cache_profile: &mut TextureCacheProfileCounters,
                  gpu_cache_profile: &mut GpuCacheProfileCounters)
                  -> RendererFrame {
-        self.clip_scroll_tree.update_all_node_transforms(pan);
+        self.clip_scroll_tree.update_all_node_transforms(pan, async_zoom);
         let frame = self.build_frame(resource_cache,
                                      gpu_cache,
                                      display_lists,
+                                     properties,
                                      device_pixel_ratio,
                                      texture_cache_profile,
                                      gpu_cache_profile);
@@ -1172,6 +1315,7 @@ impl Frame {
                    resource_cache: &mut ResourceCache,
                    gpu_cache: &mut GpuCache,
                    display_lists: &DisplayListMap,
+                   properties: &SceneProperties,
                    device_pixel_ratio: f32,
                    texture_cache_profile: &mut TextureCacheProfileCounters,
                    gpu_cache_profile: &mut GpuCacheProfileCounters)
@@ -1183,6 +1327,7 @@ impl Frame {
                           self.id,
                           &mut self.clip_scroll_tree,
                           display_lists,
+                          properties,
                           device_pixel_ratio,
                           texture_cache_profile,
                           gpu_cache_profile)
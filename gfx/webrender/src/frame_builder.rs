@@ -2,38 +2,49 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use api::{BorderDetails, BorderDisplayItem, BoxShadowClipMode, ClipAndScrollInfo, ClipId, ColorF};
-use api::{DeviceIntPoint, DeviceIntRect, DeviceIntSize, DeviceUintRect, DeviceUintSize};
-use api::{ExtendMode, FontKey, FontRenderMode, GlyphInstance, GlyphOptions, GradientStop};
-use api::{ImageKey, ImageRendering, ItemRange, LayerPoint, LayerRect, LayerSize, SubpixelDirection};
+use api::{BorderDetails, BorderDisplayItem, BoxShadowClipMode, ClipAndScrollInfo, ClipId, ClipMode};
+use api::{ColorF, ColorInterpolation, ComplexClipRegion, DeviceIntPoint, DeviceIntRect, DeviceIntSize};
+use api::{DeviceUintRect, DeviceUintSize};
+use api::{ExtendMode, FillRule, FilterOp, FontKey, FontRenderMode, GlyphInstance, GlyphOptions};
+use api::{GradientStop, PathCommand};
+use api::{ImageKey, ImageRendering, ItemRange, ItemTag, LayerPoint, LayerRect, LayerSize};
 use api::{LayerToScrollTransform, LayerVector2D, LayoutVector2D, LineOrientation, LineStyle};
-use api::{LocalClip, PipelineId, RepeatMode, ScrollSensitivity, TextShadow, TileOffset};
-use api::{TransformStyle, WebGLContextId, WorldPixel, YuvColorSpace, YuvData};
+use api::{LocalClip, PipelineId, PixelSnapping, PropertyBinding, RepeatMode, ScrollSensitivity};
+use api::{SubpixelDirection, TextShadow, TileOffset, TileSize, TransformStyle, WebGLContextId};
+use api::{WorldPixel, YuvColorSpace, YuvData};
+use api::snap_to_device_pixel;
 use app_units::Au;
 use frame::FrameId;
-use gpu_cache::GpuCache;
-use internal_types::{FastHashMap, HardwareCompositeOp};
-use mask_cache::{ClipMode, ClipRegion, ClipSource, MaskCacheInfo};
+use fxhash::FxHasher;
+use gpu_cache::{GpuCache, GpuCacheHandle};
+use hit_test::HitTestingItem;
+use internal_types::{FastHashMap, HardwareCompositeOp, QualitySettings};
+use mask_cache::{ClipRegion, ClipSource, MaskCacheInfo, clip_sources_content_hash};
+use path_rasterizer::PathShape;
 use plane_split::{BspSplitter, Polygon, Splitter};
 use prim_store::{GradientPrimitiveCpu, ImagePrimitiveCpu, LinePrimitive, PrimitiveKind};
 use prim_store::{ImagePrimitiveKind, PrimitiveContainer, PrimitiveIndex};
 use prim_store::{PrimitiveStore, RadialGradientPrimitiveCpu, TextRunMode};
 use prim_store::{RectanglePrimitive, TextRunPrimitiveCpu, TextShadowPrimitiveCpu};
 use prim_store::{BoxShadowPrimitiveCpu, TexelRect, YuvImagePrimitiveCpu};
-use profiler::{FrameProfileCounters, GpuCacheProfileCounters, TextureCacheProfileCounters};
+use profiler::{FrameProfileCounters, GpuCacheProfileCounters, PipelineProfile};
+use profiler::TextureCacheProfileCounters;
 use render_task::{AlphaRenderItem, ClipWorkItem, MaskCacheKey, RenderTask, RenderTaskIndex};
 use render_task::{RenderTaskId, RenderTaskLocation};
 use resource_cache::ResourceCache;
+use scene::SceneProperties;
 use clip_scroll_node::{ClipInfo, ClipScrollNode, NodeType};
 use clip_scroll_tree::ClipScrollTree;
 use std::{cmp, f32, i32, mem, usize};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
 use euclid::{SideOffsets2D, vec2, vec3};
 use tiling::{ContextIsolation, StackingContextIndex};
 use tiling::{ClipScrollGroup, ClipScrollGroupIndex, CompositeOps, DisplayListMap, Frame};
 use tiling::{PackedLayer, PackedLayerIndex, PrimitiveFlags, PrimitiveRunCmd, RenderPass};
 use tiling::{RenderTargetContext, RenderTaskCollection, ScrollbarPrimitive, StackingContext};
 use util::{self, pack_as_float, subtract_rect, recycle_vec};
-use util::{MatrixHelpers, RectHelpers};
+use util::{ComplexClipRegionHelpers, MatrixHelpers, RectHelpers};
 
 #[derive(Debug, Clone)]
 struct ImageBorderSegment {
@@ -98,12 +109,81 @@ fn make_polygon(stacking_context: &StackingContext, node: &ClipScrollNode,
     Polygon::from_transformed_rect(bounds, node.world_content_transform, anchor)
 }
 
+/// Compute a key identifying a set of polygons handed to the plane splitter,
+/// so that the split results can be reused while the polygons don't change.
+fn hash_polygons(polygons: &[Polygon<f32, WorldPixel>]) -> u64 {
+    let mut hasher = FxHasher::default();
+    for polygon in polygons {
+        hasher.write_usize(polygon.anchor);
+        for point in &polygon.points {
+            hasher.write_u32(point.x.to_bits());
+            hasher.write_u32(point.y.to_bits());
+            hasher.write_u32(point.z.to_bits());
+        }
+    }
+    hasher.finish()
+}
+
+/// Minimum size of the mask-free interior of a rounded rect clip for
+/// segmentation to be worthwhile - below this the extra primitives cost
+/// more than the saved mask area.
+const MIN_SEGMENT_INTERIOR_SIZE: f32 = 16.0;
+
+/// When a rectangular primitive carries a rounded rect local clip, most
+/// of its area usually lies between the corners and doesn't need the
+/// clip mask at all. This computes that interior - the clip rect inset
+/// by the corner radii, intersected with the primitive rect - together
+/// with the edge rects that do still need the mask. Returns `None` when
+/// there is no interior worth splitting out.
+fn segment_rounded_rect_clip(rect: &LayerRect,
+                             clip_rect: &LayerRect,
+                             complex: &ComplexClipRegion)
+                             -> Option<(LayerRect, Vec<LayerRect>)> {
+    // A clip-out region has no unmasked interior - the area inside the
+    // rounded rect is exactly what gets clipped away.
+    if complex.mode != ClipMode::Clip {
+        return None;
+    }
+
+    let bounds = match rect.intersection(clip_rect) {
+        Some(bounds) => bounds,
+        None => return None,
+    };
+
+    let inner = match complex.get_inner_rect_safe() {
+        Some(inner) => inner,
+        None => return None,
+    };
+
+    let interior = match bounds.intersection(&inner) {
+        Some(interior) => interior,
+        None => return None,
+    };
+
+    if interior.size.width < MIN_SEGMENT_INTERIOR_SIZE ||
+       interior.size.height < MIN_SEGMENT_INTERIOR_SIZE {
+        return None;
+    }
+
+    let mut edges = Vec::new();
+    subtract_rect(&bounds, &interior, &mut edges);
+
+    Some((interior, edges))
+}
+
 #[derive(Clone, Copy)]
 pub struct FrameBuilderConfig {
     pub enable_scrollbars: bool,
     pub default_font_render_mode: FontRenderMode,
     pub debug: bool,
     pub cache_expiry_frames: u32,
+    pub enable_depth_prepass: bool,
+    /// Tile size for blob image rasterization. See
+    /// `RendererOptions::blob_tile_size`.
+    pub blob_tile_size: Option<TileSize>,
+    /// Current quality settings. Downgraded at runtime in response to
+    /// embedder pressure signals; see `QualitySettings`.
+    pub quality: QualitySettings,
 }
 
 pub struct FrameBuilder {
@@ -135,6 +215,22 @@ pub struct FrameBuilder {
     /// Whether or not we've pushed a root stacking context for the current pipeline.
     has_root_stacking_context: bool,
 
+    /// The tagged display items recorded during flattening, in paint order,
+    /// kept for answering hit test queries against this scene.
+    hit_testing_items: Vec<HitTestingItem>,
+
+    /// The tag of the display item currently being flattened, stamped onto
+    /// the primitives it creates.
+    current_item_tag: Option<ItemTag>,
+
+    /// A stack of resolved pixel snapping hints, matching the stacking
+    /// context stack, applied to the primitives added below each context.
+    pixel_snapping_stack: Vec<PixelSnapping>,
+
+    /// Sorted plane splitting results for preserve-3d contexts, keyed by a
+    /// hash of the input polygons and kept across frames. Entries that a
+    /// frame does not use are dropped when it finishes building.
+    split_result_cache: FastHashMap<u64, Vec<Polygon<f32, WorldPixel>>>,
 }
 
 impl FrameBuilder {
@@ -159,6 +255,10 @@ impl FrameBuilder {
                     background_color,
                     config,
                     has_root_stacking_context: false,
+                    hit_testing_items: recycle_vec(prev.hit_testing_items),
+                    current_item_tag: None,
+                    pixel_snapping_stack: recycle_vec(prev.pixel_snapping_stack),
+                    split_result_cache: FastHashMap::default(),
                 }
             }
             None => {
@@ -177,11 +277,48 @@ impl FrameBuilder {
                     background_color,
                     config,
                     has_root_stacking_context: false,
+                    hit_testing_items: Vec::new(),
+                    current_item_tag: None,
+                    pixel_snapping_stack: Vec::new(),
+                    split_result_cache: FastHashMap::default(),
                 }
             }
         }
     }
 
+    /// Records a tagged display item for hit testing. Called during
+    /// flattening, so items arrive in paint order.
+    pub fn add_hit_test_item(&mut self,
+                             clip_and_scroll: ClipAndScrollInfo,
+                             rect: LayerRect,
+                             local_clip: &LocalClip,
+                             tag: ItemTag) {
+        self.hit_testing_items.push(HitTestingItem {
+            clip_and_scroll,
+            rect,
+            local_clip: *local_clip,
+            tag,
+        });
+    }
+
+    pub fn hit_testing_items(&self) -> &[HitTestingItem] {
+        &self.hit_testing_items
+    }
+
+    pub fn set_current_item_tag(&mut self, tag: Option<ItemTag>) {
+        self.current_item_tag = tag;
+    }
+
+    /// See `PrimitiveStore::harvest_gpu_locations`.
+    pub fn harvest_gpu_locations(&self) -> FastHashMap<u64, GpuCacheHandle> {
+        self.prim_store.harvest_gpu_locations()
+    }
+
+    /// See `PrimitiveStore::seed_gpu_locations`.
+    pub fn seed_gpu_locations(&mut self, locations: FastHashMap<u64, GpuCacheHandle>) {
+        self.prim_store.seed_gpu_locations(locations);
+    }
+
     pub fn create_clip_scroll_group_if_necessary(&mut self, info: ClipAndScrollInfo) {
         if self.clip_scroll_group_indices.contains_key(&info) {
             return;
@@ -213,10 +350,13 @@ impl FrameBuilder {
             None
         };
 
+        let pixel_snapping = *self.pixel_snapping_stack.last().unwrap_or(&PixelSnapping::Auto);
         let prim_index = self.prim_store.add_primitive(rect,
                                                        &local_clip.clip_rect(),
                                                        clip_sources,
                                                        clip_info,
+                                                       self.current_item_tag,
+                                                       pixel_snapping,
                                                        container);
 
         prim_index
@@ -282,7 +422,10 @@ impl FrameBuilder {
                                  reference_frame_offset: &LayerVector2D,
                                  pipeline_id: PipelineId,
                                  composite_ops: CompositeOps,
-                                 transform_style: TransformStyle) {
+                                 transform_style: TransformStyle,
+                                 pixel_snapping: PixelSnapping,
+                                 has_animated_transform: bool,
+                                 will_change: bool) {
         if let Some(parent_index) = self.stacking_context_stack.last() {
             let parent_is_root = self.stacking_context_store[parent_index.0].is_page_root;
 
@@ -305,19 +448,79 @@ impl FrameBuilder {
                                                               !self.has_root_stacking_context,
                                                               reference_frame_id,
                                                               transform_style,
-                                                              composite_ops));
+                                                              composite_ops,
+                                                              will_change));
         self.has_root_stacking_context = true;
         self.cmds.push(PrimitiveRunCmd::PushStackingContext(stacking_context_index));
         self.stacking_context_stack.push(stacking_context_index);
+
+        // Resolve the snapping hint for this subtree. Explicit hints win and
+        // are inherited by descendants; an Auto context follows its parent,
+        // except that snapping is suppressed below an animated transform to
+        // avoid shimmering as the transform interpolates across pixels.
+        let parent_snapping = *self.pixel_snapping_stack.last().unwrap_or(&PixelSnapping::Auto);
+        let resolved_snapping = match pixel_snapping {
+            PixelSnapping::Auto if parent_snapping == PixelSnapping::Auto && has_animated_transform =>
+                PixelSnapping::Never,
+            PixelSnapping::Auto => parent_snapping,
+            explicit => explicit,
+        };
+        self.pixel_snapping_stack.push(resolved_snapping);
     }
 
     pub fn pop_stacking_context(&mut self) {
+        // If the context wraps nothing but a single primitive, try folding
+        // its opacity filter into the primitive instead of compositing it
+        // through an intermediate render target.
+        self.collapse_single_primitive_opacity();
         self.cmds.push(PrimitiveRunCmd::PopStackingContext);
         self.stacking_context_stack.pop();
+        self.pixel_snapping_stack.pop();
         assert!(self.shadow_prim_stack.is_empty(),
             "Found unpopped text shadows when popping stacking context!");
     }
 
+    /// Detect a stacking context that contains exactly one primitive and
+    /// whose only composite operation is an opacity filter, and fold the
+    /// opacity into the primitive. With a single primitive there is nothing
+    /// to blend together first, so the result is identical and the
+    /// intermediate render target and composite pass can be skipped.
+    fn collapse_single_primitive_opacity(&mut self) {
+        let stacking_context_index = match self.stacking_context_stack.last() {
+            Some(index) => *index,
+            None => return,
+        };
+
+        let opacity = {
+            let composite_ops = &self.stacking_context_store[stacking_context_index.0].composite_ops;
+            if composite_ops.mix_blend_mode.is_some() || composite_ops.filters.len() != 1 {
+                return;
+            }
+            match composite_ops.filters[0] {
+                FilterOp::Opacity(PropertyBinding::Value(opacity)) => opacity,
+                _ => return,
+            }
+        };
+
+        let prim_index = {
+            let len = self.cmds.len();
+            if len < 2 {
+                return;
+            }
+            match (&self.cmds[len - 2], &self.cmds[len - 1]) {
+                (&PrimitiveRunCmd::PushStackingContext(index),
+                 &PrimitiveRunCmd::PrimitiveRun(prim_index, 1, _))
+                    if index == stacking_context_index => prim_index,
+                _ => return,
+            }
+        };
+
+        if !self.prim_store.collapse_opacity(prim_index, opacity) {
+            return;
+        }
+        self.stacking_context_store[stacking_context_index.0].composite_ops.filters.clear();
+    }
+
     pub fn push_reference_frame(&mut self,
                                 parent_id: Option<ClipId>,
                                 pipeline_id: PipelineId,
@@ -339,12 +542,23 @@ impl FrameBuilder {
                                  inner_rect: DeviceUintRect,
                                  device_pixel_ratio: f32,
                                  clip_scroll_tree: &mut ClipScrollTree) {
+        // Snap the viewport offset and size to device pixel boundaries rather
+        // than rounding the layout-space values. At fractional device pixel
+        // ratios, rounding in layout space puts the viewport edge up to half a
+        // layout pixel away from a device pixel boundary, which shifts every
+        // snapped hairline drawn inside it.
         let inner_origin = inner_rect.origin.to_f32();
-        let viewport_offset = LayerPoint::new((inner_origin.x / device_pixel_ratio).round(),
-                                              (inner_origin.y / device_pixel_ratio).round());
+        let viewport_offset =
+            LayerPoint::new(snap_to_device_pixel(inner_origin.x / device_pixel_ratio,
+                                                 device_pixel_ratio),
+                            snap_to_device_pixel(inner_origin.y / device_pixel_ratio,
+                                                 device_pixel_ratio));
         let outer_size = window_size.to_f32();
-        let outer_size = LayerSize::new((outer_size.width / device_pixel_ratio).round(),
-                                        (outer_size.height / device_pixel_ratio).round());
+        let outer_size =
+            LayerSize::new(snap_to_device_pixel(outer_size.width / device_pixel_ratio,
+                                                device_pixel_ratio),
+                           snap_to_device_pixel(outer_size.height / device_pixel_ratio,
+                                                device_pixel_ratio));
         let clip_size = LayerSize::new(outer_size.width + 2.0 * viewport_offset.x,
                                        outer_size.height + 2.0 * viewport_offset.y);
 
@@ -471,6 +685,30 @@ impl FrameBuilder {
             color: *color,
         };
 
+        // Rects with a rounded rect clip are split into an interior drawn
+        // without any clip mask - so an opaque color can stay in the
+        // opaque pass - plus edge segments that keep the mask. Scrollbar
+        // rects are exempt since they need a single primitive index.
+        if let PrimitiveFlags::None = flags {
+            if let &LocalClip::RoundedRect(clip_rect, ref complex) = local_clip {
+                if let Some((interior, edges)) = segment_rounded_rect_clip(rect, &clip_rect, complex) {
+                    self.add_primitive(clip_and_scroll,
+                                       rect,
+                                       &LocalClip::Rect(interior),
+                                       &[],
+                                       PrimitiveContainer::Rectangle(prim.clone()));
+                    for edge in edges {
+                        self.add_primitive(clip_and_scroll,
+                                           rect,
+                                           &LocalClip::RoundedRect(edge, *complex),
+                                           &[],
+                                           PrimitiveContainer::Rectangle(prim.clone()));
+                    }
+                    return;
+                }
+            }
+        }
+
         let prim_index = self.add_primitive(clip_and_scroll,
                                             rect,
                                             local_clip,
@@ -737,6 +975,7 @@ impl FrameBuilder {
                                       gradient_stops,
                                       gradient_stops_count,
                                       border.gradient.extend_mode,
+                                      border.gradient.interpolation,
                                       segment.size,
                                       LayerSize::zero());
                 }
@@ -755,6 +994,7 @@ impl FrameBuilder {
                                              border.gradient.ratio_xy,
                                              gradient_stops,
                                              border.gradient.extend_mode,
+                                             border.gradient.interpolation,
                                              segment.size,
                                              LayerSize::zero());
                 }
@@ -771,6 +1011,7 @@ impl FrameBuilder {
                         stops: ItemRange<GradientStop>,
                         stops_count: usize,
                         extend_mode: ExtendMode,
+                        interpolation: ColorInterpolation,
                         tile_size: LayerSize,
                         tile_spacing: LayerSize) {
         let tile_repeat = tile_size + tile_spacing;
@@ -784,8 +1025,12 @@ impl FrameBuilder {
                                       start_point.x.min(end_point.x) <= 0.0 &&
                                       start_point.x.max(end_point.x) >= rect.size.width);
 
-        // Fast path for clamped, axis-aligned gradients, with gradient lines intersecting all of rect:
-        let aligned = extend_mode == ExtendMode::Clamp && is_not_tiled && aligned_and_fills_rect;
+        // Fast path for clamped, axis-aligned gradients, with gradient lines intersecting all of rect.
+        // The aligned shader interpolates the raw stop colors, so only sRGB interpolation can use it;
+        // linear RGB gradients go through the angle path, whose lookup table is built on the CPU in
+        // the requested color space.
+        let aligned = extend_mode == ExtendMode::Clamp && is_not_tiled && aligned_and_fills_rect &&
+                      interpolation == ColorInterpolation::Srgb;
 
         // Try to ensure that if the gradient is specified in reverse, then so long as the stops
         // are also supplied in reverse that the rendered result will be equivalent. To do this,
@@ -811,7 +1056,9 @@ impl FrameBuilder {
             stops_range: stops,
             stops_count,
             extend_mode,
+            interpolation,
             reverse_stops,
+            gradient_data_key: 0,
             gpu_blocks: [
                 [sp.x, sp.y, ep.x, ep.y].into(),
                 [tile_size.width, tile_size.height, tile_repeat.width, tile_repeat.height].into(),
@@ -839,6 +1086,7 @@ impl FrameBuilder {
                                ratio_xy: f32,
                                stops: ItemRange<GradientStop>,
                                extend_mode: ExtendMode,
+                               interpolation: ColorInterpolation,
                                tile_size: LayerSize,
                                tile_spacing: LayerSize) {
         let tile_repeat = tile_size + tile_spacing;
@@ -846,7 +1094,9 @@ impl FrameBuilder {
         let radial_gradient_cpu = RadialGradientPrimitiveCpu {
             stops_range: stops,
             extend_mode,
+            interpolation,
             gpu_data_count: 0,
+            gradient_data_key: 0,
             gpu_blocks: [
                 [start_center.x, start_center.y, end_center.x, end_center.y].into(),
                 [start_radius, end_radius, ratio_xy, pack_as_float(extend_mode as u32)].into(),
@@ -889,6 +1139,12 @@ impl FrameBuilder {
                 normal_render_mode = FontRenderMode::Alpha;
             }
 
+            // Under pressure, the quality settings downgrade subpixel AA
+            // to the cheaper grayscale alpha.
+            if !self.config.quality.allow_subpixel_aa {
+                normal_render_mode = FontRenderMode::Alpha;
+            }
+
             // text on a stacking context that has filters
             // (e.g. opacity) can't use sub-pixel.
             // TODO(gw): It's possible we can relax this in
@@ -1204,7 +1460,42 @@ impl FrameBuilder {
         let prim_cpu = ImagePrimitiveCpu {
             kind: ImagePrimitiveKind::WebGL(context_id),
             gpu_blocks: [ [rect.size.width, rect.size.height, 0.0, 0.0].into(),
-                          TexelRect::invalid().into() ],
+                          TexelRect::invalid().into(),
+                          [1.0, 1.0, 1.0, 1.0].into() ],
+        };
+
+        self.add_primitive(clip_and_scroll,
+                           &rect,
+                           local_clip,
+                           &[],
+                           PrimitiveContainer::Image(prim_cpu));
+    }
+
+    pub fn add_filled_path(&mut self,
+                           clip_and_scroll: ClipAndScrollInfo,
+                           rect: LayerRect,
+                           local_clip: &LocalClip,
+                           color: &ColorF,
+                           fill_rule: FillRule,
+                           commands: Vec<PathCommand>) {
+        // Trivial early out checks
+        if color.a == 0.0 || commands.is_empty() ||
+           rect.size.width <= 0.0 || rect.size.height <= 0.0 {
+            return;
+        }
+
+        // The path renders through the regular image path: the mask is
+        // rasterized on the worker threads into the texture cache, and
+        // the primitive samples it stretched over the item rect. The
+        // cache size is filled in when the primitive is prepared, once
+        // the device pixel ratio is known.
+        let shape = Arc::new(PathShape::new(commands, fill_rule, *color));
+
+        let prim_cpu = ImagePrimitiveCpu {
+            kind: ImagePrimitiveKind::Path(shape, DeviceUintSize::zero()),
+            gpu_blocks: [ [rect.size.width, rect.size.height, 0.0, 0.0].into(),
+                          TexelRect::invalid().into(),
+                          [1.0, 1.0, 1.0, 1.0].into() ],
         };
 
         self.add_primitive(clip_and_scroll,
@@ -1226,24 +1517,50 @@ impl FrameBuilder {
                      tile: Option<TileOffset>) {
         let sub_rect_block = sub_rect.unwrap_or(TexelRect::invalid()).into();
 
-        let prim_cpu = ImagePrimitiveCpu {
-            kind: ImagePrimitiveKind::Image(image_key,
-                                            image_rendering,
-                                            tile,
-                                            *tile_spacing),
-            gpu_blocks: [ [ stretch_size.width,
-                            stretch_size.height,
-                            tile_spacing.width,
-                            tile_spacing.height ].into(),
-                            sub_rect_block,
-                        ],
+        let make_prim = || {
+            ImagePrimitiveCpu {
+                kind: ImagePrimitiveKind::Image(image_key,
+                                                image_rendering,
+                                                tile,
+                                                *tile_spacing),
+                gpu_blocks: [ [ stretch_size.width,
+                                stretch_size.height,
+                                tile_spacing.width,
+                                tile_spacing.height ].into(),
+                                sub_rect_block,
+                                [1.0, 1.0, 1.0, 1.0].into(),
+                            ],
+            }
         };
 
+        // Images with a rounded rect clip are split into an interior that
+        // doesn't need the clip mask and edge segments that keep it. Each
+        // segment spans the full image rect and is narrowed only through
+        // its local clip rect, so texture coordinates are unaffected by
+        // the split.
+        if let &LocalClip::RoundedRect(clip_rect, ref complex) = local_clip {
+            if let Some((interior, edges)) = segment_rounded_rect_clip(&rect, &clip_rect, complex) {
+                self.add_primitive(clip_and_scroll,
+                                   &rect,
+                                   &LocalClip::Rect(interior),
+                                   &[],
+                                   PrimitiveContainer::Image(make_prim()));
+                for edge in edges {
+                    self.add_primitive(clip_and_scroll,
+                                       &rect,
+                                       &LocalClip::RoundedRect(edge, *complex),
+                                       &[],
+                                       PrimitiveContainer::Image(make_prim()));
+                }
+                return;
+            }
+        }
+
         self.add_primitive(clip_and_scroll,
                            &rect,
                            local_clip,
                            &[],
-                           PrimitiveContainer::Image(prim_cpu));
+                           PrimitiveContainer::Image(make_prim()));
     }
 
     pub fn add_yuv_image(&mut self,
@@ -1286,6 +1603,7 @@ impl FrameBuilder {
                                                 resource_cache: &mut ResourceCache,
                                                 gpu_cache: &mut GpuCache,
                                                 profile_counters: &mut FrameProfileCounters,
+                                                pipeline_profiles: &mut FastHashMap<PipelineId, PipelineProfile>,
                                                 device_pixel_ratio: f32) {
         profile_scope!("cull");
         LayerRectCalculationAndCullingPass::create_and_run(self,
@@ -1295,6 +1613,7 @@ impl FrameBuilder {
                                                            resource_cache,
                                                            gpu_cache,
                                                            profile_counters,
+                                                           pipeline_profiles,
                                                            device_pixel_ratio);
     }
 
@@ -1347,7 +1666,9 @@ impl FrameBuilder {
 
     fn build_render_task(&mut self,
                          clip_scroll_tree: &ClipScrollTree,
-                         gpu_cache: &mut GpuCache)
+                         gpu_cache: &mut GpuCache,
+                         properties: &SceneProperties,
+                         max_target_size: i32)
                          -> (RenderTask, usize) {
         profile_scope!("build_render_task");
 
@@ -1357,7 +1678,8 @@ impl FrameBuilder {
         let mut sc_stack: Vec<StackingContextIndex> = Vec::new();
         let mut current_task = RenderTask::new_alpha_batch(next_task_index,
                                                            DeviceIntPoint::zero(),
-                                                           RenderTaskLocation::Fixed);
+                                                           RenderTaskLocation::Fixed,
+                                                           1.0);
         next_task_index.0 += 1;
         // A stack of the alpha batcher tasks. We create them on the way down,
         // and then actually populate with items and dependencies on the way up.
@@ -1368,8 +1690,12 @@ impl FrameBuilder {
         //  - ones with `ContextIsolation::Items`, for their actual items to be backed
         //  - immediate children of `ContextIsolation::Items`
         let mut preserve_3d_map: FastHashMap<StackingContextIndex, RenderTask> = FastHashMap::default();
-        // The plane splitter stack, using a simple BSP tree.
-        let mut splitter_stack = Vec::new();
+        // The plane splitter stack. Polygons accumulate here and are solved
+        // with a BSP tree when the enclosing preserve-3d context is popped.
+        let mut polygon_stack: Vec<Vec<Polygon<f32, WorldPixel>>> = Vec::new();
+        // The split results this frame has used. They replace the cache once
+        // the frame is built, so that stale entries don't accumulate.
+        let mut used_split_results = FastHashMap::default();
 
         debug!("build_render_task()");
 
@@ -1392,17 +1718,23 @@ impl FrameBuilder {
 
                     if stacking_context.isolation == ContextIsolation::Full && composite_count == 0 {
                         alpha_task_stack.push(current_task);
-                        current_task = RenderTask::new_dynamic_alpha_batch(next_task_index, stacking_context_rect);
+                        current_task = RenderTask::new_dynamic_alpha_batch(next_task_index,
+                                                                           stacking_context_rect,
+                                                                           max_target_size,
+                                                                           self.config.quality.target_scale);
                         next_task_index.0 += 1;
                     }
 
                     if parent_isolation == Some(ContextIsolation::Items) ||
                        stacking_context.isolation == ContextIsolation::Items {
                         if parent_isolation != Some(ContextIsolation::Items) {
-                            splitter_stack.push(BspSplitter::new());
+                            polygon_stack.push(Vec::new());
                         }
                         alpha_task_stack.push(current_task);
-                        current_task = RenderTask::new_dynamic_alpha_batch(next_task_index, stacking_context_rect);
+                        current_task = RenderTask::new_dynamic_alpha_batch(next_task_index,
+                                                                           stacking_context_rect,
+                                                                           max_target_size,
+                                                                           self.config.quality.target_scale);
                         next_task_index.0 += 1;
                         //Note: technically, we shouldn't make a new alpha task for "preserve-3d" contexts
                         // that have no child items (only other stacking contexts). However, we don't know if
@@ -1411,14 +1743,17 @@ impl FrameBuilder {
                         // is because we need to preserve the order of drawing for planes that match together.
                         let frame_node = clip_scroll_tree.nodes.get(&stacking_context.reference_frame_id).unwrap();
                         let sc_polygon = make_polygon(stacking_context, frame_node, stacking_context_index.0);
-                        debug!("\tsplitter[{}]: add {:?} -> {:?} with bounds {:?}", splitter_stack.len(),
+                        debug!("\tsplitter[{}]: add {:?} -> {:?} with bounds {:?}", polygon_stack.len(),
                             stacking_context_index, sc_polygon, stacking_context.isolated_items_bounds);
-                        splitter_stack.last_mut().unwrap().add(sc_polygon);
+                        polygon_stack.last_mut().unwrap().push(sc_polygon);
                     }
 
                     for _ in 0..composite_count {
                         alpha_task_stack.push(current_task);
-                        current_task = RenderTask::new_dynamic_alpha_batch(next_task_index, stacking_context_rect);
+                        current_task = RenderTask::new_dynamic_alpha_batch(next_task_index,
+                                                                           stacking_context_rect,
+                                                                           max_target_size,
+                                                                           self.config.quality.target_scale);
                         next_task_index.0 += 1;
                     }
                 }
@@ -1448,10 +1783,20 @@ impl FrameBuilder {
                     }
 
                     for filter in &stacking_context.composite_ops.filters {
+                        // Bound opacity values are resolved here, on every
+                        // frame build, so an updated property value only
+                        // needs a new frame to take effect.
+                        let filter = match *filter {
+                            FilterOp::Opacity(ref value) => {
+                                let amount = properties.resolve_float(value, 1.0);
+                                FilterOp::Opacity(PropertyBinding::Value(amount))
+                            }
+                            filter => filter,
+                        };
                         let mut prev_task = alpha_task_stack.pop().unwrap();
                         let item = AlphaRenderItem::Blend(stacking_context_index,
                                                           current_task.id,
-                                                          *filter,
+                                                          filter,
                                                           next_z);
                         next_z += 1;
                         prev_task.as_alpha_batch().items.push(item);
@@ -1488,13 +1833,23 @@ impl FrameBuilder {
 
                     if parent_isolation != Some(ContextIsolation::Items) &&
                        stacking_context.isolation == ContextIsolation::Items {
-                        debug!("\tsplitter[{}]: flush {:?}", splitter_stack.len(), current_task.id);
-                        let mut splitter = splitter_stack.pop().unwrap();
+                        debug!("\tsplitter[{}]: flush {:?}", polygon_stack.len(), current_task.id);
+                        let polygons = polygon_stack.pop().unwrap();
                         // Flush the accumulated plane splits onto the task tree.
                         // Notice how this is done before splitting in order to avoid duplicate tasks.
                         current_task.children.extend(preserve_3d_map.values().cloned());
-                        // Z axis is directed at the screen, `sort` is ascending, and we need back-to-front order.
-                        for poly in splitter.sort(vec3(0.0, 0.0, 1.0)) {
+                        // Splitting is relatively expensive, so reuse the result
+                        // from the previous frame when the polygons (and thus the
+                        // transforms they were built from) have not changed.
+                        let split_key = hash_polygons(&polygons);
+                        let sorted_polygons = self.split_result_cache
+                                                  .remove(&split_key)
+                                                  .unwrap_or_else(|| {
+                            let mut splitter = BspSplitter::new();
+                            // Z axis is directed at the screen, `sort` is ascending, and we need back-to-front order.
+                            splitter.solve(&polygons, vec3(0.0, 0.0, 1.0)).to_vec()
+                        });
+                        for poly in &sorted_polygons {
                             let sc_index = StackingContextIndex(poly.anchor);
                             let task_id = preserve_3d_map[&sc_index].id;
                             debug!("\t\tproduce {:?} -> {:?} for {:?}", sc_index, poly, task_id);
@@ -1508,6 +1863,7 @@ impl FrameBuilder {
                             let item = AlphaRenderItem::SplitComposite(sc_index, task_id, handle, next_z);
                             current_task.as_alpha_batch().items.push(item);
                         }
+                        used_split_results.insert(split_key, sorted_polygons);
                         preserve_3d_map.clear();
                         next_z += 1;
                     }
@@ -1552,6 +1908,9 @@ impl FrameBuilder {
         debug_assert!(alpha_task_stack.is_empty());
         debug_assert!(preserve_3d_map.is_empty());
         debug_assert_eq!(current_task.id, RenderTaskId::Static(RenderTaskIndex(0)));
+        // Only retain the split results this frame actually used, so the
+        // cache does not grow without bound while transforms animate.
+        self.split_result_cache = used_split_results;
         (current_task, next_task_index.0)
     }
 
@@ -1561,6 +1920,7 @@ impl FrameBuilder {
                  frame_id: FrameId,
                  clip_scroll_tree: &mut ClipScrollTree,
                  display_lists: &DisplayListMap,
+                 properties: &SceneProperties,
                  device_pixel_ratio: f32,
                  texture_cache_profile: &mut TextureCacheProfileCounters,
                  gpu_cache_profile: &mut GpuCacheProfileCounters)
@@ -1569,6 +1929,7 @@ impl FrameBuilder {
 
         let mut profile_counters = FrameProfileCounters::new();
         profile_counters.total_primitives.set(self.prim_store.prim_count());
+        let mut pipeline_profiles = FastHashMap::default();
 
         resource_cache.begin_frame(frame_id);
         gpu_cache.begin_frame();
@@ -1593,14 +1954,30 @@ impl FrameBuilder {
                                                       resource_cache,
                                                       gpu_cache,
                                                       &mut profile_counters,
+                                                      &mut pipeline_profiles,
                                                       device_pixel_ratio);
 
-        let (main_render_task, static_render_task_count) = self.build_render_task(clip_scroll_tree, gpu_cache);
+        let (main_render_task, static_render_task_count) =
+            self.build_render_task(clip_scroll_tree,
+                                   gpu_cache,
+                                   properties,
+                                   resource_cache.max_texture_size() as i32);
         let mut render_tasks = RenderTaskCollection::new(static_render_task_count);
 
         let mut required_pass_count = 0;
         main_render_task.max_depth(0, &mut required_pass_count);
 
+        // Record the task dependency graph now if requested - assigning the
+        // tasks to passes below consumes the dependency links.
+        let render_task_graph_dump = if self.config.debug {
+            let mut output = String::from("digraph render_tasks {\n    node [shape=box];\n");
+            main_render_task.dump_as_dot(0, &mut output);
+            output.push_str("}\n");
+            Some(output)
+        } else {
+            None
+        };
+
         resource_cache.block_until_all_resources_added(gpu_cache, texture_cache_profile);
 
         let mut deferred_resolves = vec![];
@@ -1624,6 +2001,7 @@ impl FrameBuilder {
                 clip_scroll_group_store: &self.clip_scroll_group_store,
                 prim_store: &self.prim_store,
                 resource_cache,
+                enable_depth_prepass: self.config.enable_depth_prepass,
             };
 
             pass.build(&ctx, gpu_cache, &mut render_tasks, &mut deferred_resolves);
@@ -1633,6 +2011,16 @@ impl FrameBuilder {
             profile_counters.alpha_targets.add(pass.alpha_targets.target_count());
         }
 
+        // Batches are assembled per target, so the per-pipeline instance
+        // counts can only be tallied once every pass has been built.
+        for pass in &passes {
+            for target in &pass.color_targets.targets {
+                target.alpha_batcher
+                      .accumulate_pipeline_profiles(&self.clip_scroll_group_store,
+                                                    &mut pipeline_profiles);
+            }
+        }
+
         let gpu_cache_updates = gpu_cache.end_frame(gpu_cache_profile);
 
         resource_cache.end_frame();
@@ -1647,7 +2035,15 @@ impl FrameBuilder {
             layer_texture_data: self.packed_layers.clone(),
             render_task_data: render_tasks.render_task_data,
             deferred_resolves,
+            webgl_acquires: resource_cache.take_webgl_acquires(),
+            painted_tags: self.prim_store.collect_painted_tags(),
+            pipeline_profiles: pipeline_profiles.into_iter()
+                                                .map(|(_, profile)| profile)
+                                                .collect(),
             gpu_cache_updates: Some(gpu_cache_updates),
+            render_task_graph_dump,
+            // Document state the render backend patches in after the build.
+            output_color_transform: None,
         }
     }
 
@@ -1667,6 +2063,7 @@ struct LayerRectCalculationAndCullingPass<'a> {
     resource_cache: &'a mut ResourceCache,
     gpu_cache: &'a mut GpuCache,
     profile_counters: &'a mut FrameProfileCounters,
+    pipeline_profiles: &'a mut FastHashMap<PipelineId, PipelineProfile>,
     device_pixel_ratio: f32,
     stacking_context_stack: Vec<StackingContextIndex>,
 
@@ -1688,6 +2085,7 @@ impl<'a> LayerRectCalculationAndCullingPass<'a> {
                       resource_cache: &'a mut ResourceCache,
                       gpu_cache: &'a mut GpuCache,
                       profile_counters: &'a mut FrameProfileCounters,
+                      pipeline_profiles: &'a mut FastHashMap<PipelineId, PipelineProfile>,
                       device_pixel_ratio: f32) {
 
         let mut pass = LayerRectCalculationAndCullingPass {
@@ -1698,6 +2096,7 @@ impl<'a> LayerRectCalculationAndCullingPass<'a> {
             resource_cache,
             gpu_cache,
             profile_counters,
+            pipeline_profiles,
             device_pixel_ratio,
             stacking_context_stack: Vec::new(),
             current_clip_stack: Vec::new(),
@@ -1773,7 +2172,7 @@ impl<'a> LayerRectCalculationAndCullingPass<'a> {
                 if let Some(mask) = clip_source.image_mask() {
                     // We don't add the image mask for resolution, because
                     // layer masks are resolved later.
-                    self.resource_cache.request_image(mask.image, ImageRendering::Auto, None);
+                    self.resource_cache.request_image(mask.image, ImageRendering::Auto, None, None);
                 }
             }
         }
@@ -1962,6 +2361,8 @@ impl<'a> LayerRectCalculationAndCullingPass<'a> {
                                              .expect("No display list?");
         debug!("\tclip_bounds {:?}, layer_local_clip {:?}", clip_bounds, packed_layer.local_clip_rect);
 
+        let quality = self.frame_builder.config.quality;
+
         for i in 0..prim_count {
             let prim_index = PrimitiveIndex(base_prim_index.0 + i);
             let prim_store = &mut self.frame_builder.prim_store;
@@ -1983,7 +2384,8 @@ impl<'a> LayerRectCalculationAndCullingPass<'a> {
                                                                    &packed_layer.transform,
                                                                    self.device_pixel_ratio,
                                                                    display_list,
-                                                                   TextRunMode::Normal);
+                                                                   TextRunMode::Normal,
+                                                                   &quality);
 
             stacking_context.screen_bounds = stacking_context.screen_bounds.union(&prim_screen_rect);
             stacking_context.isolated_items_bounds = stacking_context.isolated_items_bounds.union(&prim_local_rect);
@@ -2007,7 +2409,24 @@ impl<'a> LayerRectCalculationAndCullingPass<'a> {
                             }
                             _ => prim_screen_rect,
                         };
-                        (MaskCacheKey::Primitive(prim_index),
+                        // Key the task off the chain contents where possible, so
+                        // that primitives with identical clip chains share one
+                        // mask task instead of rasterizing one each.
+                        let mask_key = match clip_sources_content_hash(&prim_metadata.clips) {
+                            Some(content_hash) => {
+                                let mut hasher = FxHasher::default();
+                                hasher.write_u64(content_hash);
+                                clip_and_scroll.clip_node_id().hash(&mut hasher);
+                                hasher.write_usize(packed_layer_index.0);
+                                hasher.write_i32(mask_rect.origin.x);
+                                hasher.write_i32(mask_rect.origin.y);
+                                hasher.write_i32(mask_rect.size.width);
+                                hasher.write_i32(mask_rect.size.height);
+                                MaskCacheKey::ContentHash(hasher.finish())
+                            }
+                            None => MaskCacheKey::Primitive(prim_index),
+                        };
+                        (mask_key,
                          mask_rect,
                          Some((packed_layer_index, info.strip_aligned())))
                     }
@@ -2026,6 +2445,19 @@ impl<'a> LayerRectCalculationAndCullingPass<'a> {
             }
 
             self.profile_counters.visible_primitives.inc();
+
+            let pipeline_profile = self.pipeline_profiles
+                                       .entry(pipeline_id)
+                                       .or_insert_with(|| PipelineProfile::new(pipeline_id));
+            pipeline_profile.primitives += 1;
+            if prim_metadata.render_task.is_some() {
+                pipeline_profile.render_tasks += 1;
+            }
+            if prim_metadata.clip_task.is_some() {
+                pipeline_profile.render_tasks += 1;
+            }
+            pipeline_profile.gpu_cache_blocks +=
+                self.gpu_cache.get_block_count(&prim_metadata.gpu_location);
         }
     }
 }
@@ -0,0 +1,193 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Frame sequence capture for debugging.
+//!
+//! When enabled via `Renderer::start_frame_sequence_capture`, every
+//! presented frame is blitted into a capture target on the GPU and read
+//! back a frame later, so the copy never stalls the render pipeline. The
+//! pixels are handed to the encoder thread here, which converts them to
+//! 4:2:0 YUV and appends them to a YUV4MPEG2 (y4m) stream that video
+//! tools such as ffmpeg and mpv open directly. This exists to capture
+//! animation glitches that are hard to reproduce under a debugger.
+
+use api::DeviceUintSize;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+
+/// One captured frame handed to the encoder thread: tightly packed RGBA8
+/// rows in the bottom-up order `glReadPixels` produces.
+pub struct CapturedFrame {
+    pub size: DeviceUintSize,
+    pub data: Vec<u8>,
+}
+
+enum EncoderMsg {
+    Frame(CapturedFrame),
+    Stop,
+}
+
+/// Encodes captured frames to a y4m stream on a background thread, so
+/// the color conversion and file writes stay off the render thread.
+pub struct FrameSequenceEncoder {
+    tx: Sender<EncoderMsg>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl FrameSequenceEncoder {
+    /// Creates the output file and starts the encoder thread. The frame
+    /// rate only sets the nominal pacing in the stream header; frames
+    /// are written in the order they are captured.
+    pub fn new(path: PathBuf, frame_rate: u32) -> io::Result<FrameSequenceEncoder> {
+        let file = try!(File::create(path));
+        let (tx, rx) = channel();
+        let thread = try!(thread::Builder::new()
+            .name("FrameCapture".to_string())
+            .spawn(move || {
+                let mut writer = Y4mWriter::new(BufWriter::new(file), frame_rate);
+                while let Ok(msg) = rx.recv() {
+                    match msg {
+                        EncoderMsg::Frame(frame) => {
+                            if let Err(e) = writer.write_frame(&frame) {
+                                error!("frame capture: write failed: {}", e);
+                                return;
+                            }
+                        }
+                        EncoderMsg::Stop => return,
+                    }
+                }
+            }));
+        Ok(FrameSequenceEncoder {
+            tx,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn encode_frame(&self, frame: CapturedFrame) {
+        self.tx.send(EncoderMsg::Frame(frame)).ok();
+    }
+}
+
+impl Drop for FrameSequenceEncoder {
+    fn drop(&mut self) {
+        self.tx.send(EncoderMsg::Stop).ok();
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+struct Y4mWriter {
+    writer: BufWriter<File>,
+    frame_rate: u32,
+    /// Stream dimensions, fixed by the first frame. 4:2:0 chroma needs
+    /// even dimensions, so odd framebuffer sizes are rounded down.
+    size: Option<DeviceUintSize>,
+    y_plane: Vec<u8>,
+    u_plane: Vec<u8>,
+    v_plane: Vec<u8>,
+}
+
+impl Y4mWriter {
+    fn new(writer: BufWriter<File>, frame_rate: u32) -> Y4mWriter {
+        Y4mWriter {
+            writer,
+            frame_rate,
+            size: None,
+            y_plane: Vec::new(),
+            u_plane: Vec::new(),
+            v_plane: Vec::new(),
+        }
+    }
+
+    fn write_frame(&mut self, frame: &CapturedFrame) -> io::Result<()> {
+        let size = DeviceUintSize::new(frame.size.width & !1,
+                                       frame.size.height & !1);
+        if size.width == 0 || size.height == 0 {
+            return Ok(());
+        }
+        match self.size {
+            None => {
+                self.size = Some(size);
+                try!(write!(self.writer,
+                            "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C420jpeg\n",
+                            size.width,
+                            size.height,
+                            self.frame_rate));
+            }
+            Some(stream_size) => {
+                if stream_size != size {
+                    // A y4m stream has fixed dimensions; the window was
+                    // resized mid-capture, so this frame can't be stored.
+                    warn!("frame capture: dropping {}x{} frame from a {}x{} stream",
+                          size.width, size.height,
+                          stream_size.width, stream_size.height);
+                    return Ok(());
+                }
+            }
+        }
+
+        self.convert(frame, &size);
+        try!(self.writer.write_all(b"FRAME\n"));
+        try!(self.writer.write_all(&self.y_plane));
+        try!(self.writer.write_all(&self.u_plane));
+        try!(self.writer.write_all(&self.v_plane));
+        Ok(())
+    }
+
+    /// Converts the bottom-up RGBA8 pixels to top-down planar 4:2:0 YUV
+    /// (BT.601 limited range, integer arithmetic). Chroma is averaged
+    /// over each 2x2 block.
+    fn convert(&mut self, frame: &CapturedFrame, size: &DeviceUintSize) {
+        let width = size.width as usize;
+        let height = size.height as usize;
+        let src_height = frame.size.height as usize;
+        let stride = frame.size.width as usize * 4;
+        let data = &frame.data;
+
+        self.y_plane.resize(width * height, 0);
+        self.u_plane.resize(width * height / 4, 0);
+        self.v_plane.resize(width * height / 4, 0);
+
+        for y in 0..height {
+            let src_row = (src_height - 1 - y) * stride;
+            for x in 0..width {
+                let p = src_row + x * 4;
+                let r = data[p] as i32;
+                let g = data[p + 1] as i32;
+                let b = data[p + 2] as i32;
+                self.y_plane[y * width + x] =
+                    (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+            }
+        }
+
+        for cy in 0..height / 2 {
+            let top_row = (src_height - 1 - cy * 2) * stride;
+            let bottom_row = (src_height - 2 - cy * 2) * stride;
+            for cx in 0..width / 2 {
+                let mut r = 0;
+                let mut g = 0;
+                let mut b = 0;
+                for &row in &[top_row, bottom_row] {
+                    for dx in 0..2 {
+                        let p = row + (cx * 2 + dx) * 4;
+                        r += data[p] as i32;
+                        g += data[p + 1] as i32;
+                        b += data[p + 2] as i32;
+                    }
+                }
+                r = r / 4;
+                g = g / 4;
+                b = b / 4;
+                self.u_plane[cy * (width / 2) + cx] =
+                    (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+                self.v_plane[cy * (width / 2) + cx] =
+                    (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+            }
+        }
+    }
+}
@@ -0,0 +1,292 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A backend trait between the renderer and the graphics API.
+//!
+//! `Device` is hard-wired to gleam::gl today. This trait names the
+//! operations the renderer actually depends on — texture init and
+//! updates, program creation, vertex arrays and instanced draws, blits
+//! and blend state — so a Vulkan or Metal backend can be brought up
+//! against it one operation at a time without forking renderer.rs. The
+//! GL implementation just forwards to the existing `Device` methods;
+//! renderer call sites migrate to the trait incrementally.
+//!
+//! The trait has generic methods, so backends are selected statically
+//! rather than through trait objects.
+
+use device::{Device, FrameId, Program, ShaderError, TextureFilter, TextureId};
+use device::{TextureTarget, VAOId, VertexDescriptor, VertexUsageHint};
+use euclid::Transform3D;
+use gleam::gl;
+use internal_types::{RenderTargetMode, TextureSampler};
+use api::{ColorF, DeviceIntRect, DeviceUintSize, ImageFormat};
+
+pub trait GpuBackend {
+    // Frames
+    fn begin_frame(&mut self, device_pixel_ratio: f32) -> FrameId;
+    fn end_frame(&mut self);
+
+    // Textures
+    fn create_texture_ids(&mut self, count: i32, target: TextureTarget) -> Vec<TextureId>;
+    fn init_texture(&mut self,
+                    texture_id: TextureId,
+                    width: u32,
+                    height: u32,
+                    format: ImageFormat,
+                    filter: TextureFilter,
+                    mode: RenderTargetMode,
+                    pixels: Option<&[u8]>);
+    fn update_texture(&mut self,
+                      texture_id: TextureId,
+                      x0: u32,
+                      y0: u32,
+                      width: u32,
+                      height: u32,
+                      stride: Option<u32>,
+                      data: &[u8]);
+    fn deinit_texture(&mut self, texture_id: TextureId);
+    fn bind_texture(&mut self, sampler: TextureSampler, texture_id: TextureId);
+
+    // Programs
+    fn create_program(&mut self,
+                      base_filename: &str,
+                      include_filename: &str,
+                      descriptor: &VertexDescriptor) -> Result<Program, ShaderError>;
+    fn bind_program(&mut self, program: &Program);
+    fn set_uniforms(&mut self, program: &Program, transform: &Transform3D<f32>);
+    fn delete_program(&mut self, program: &mut Program);
+
+    // Vertex arrays and draws
+    fn create_vao(&mut self,
+                  descriptor: &VertexDescriptor,
+                  inst_stride: gl::GLint) -> VAOId;
+    fn bind_vao(&mut self, vao_id: VAOId);
+    fn update_vao_main_vertices<V>(&mut self,
+                                   vao_id: VAOId,
+                                   vertices: &[V],
+                                   usage_hint: VertexUsageHint);
+    fn update_vao_instances<V>(&mut self,
+                               vao_id: VAOId,
+                               instances: &[V],
+                               usage_hint: VertexUsageHint);
+    fn update_vao_indices<I>(&mut self,
+                             vao_id: VAOId,
+                             indices: &[I],
+                             usage_hint: VertexUsageHint);
+    fn set_vao_instance_offset(&mut self,
+                               vao_id: VAOId,
+                               first_instance: usize);
+    fn draw_triangles_u16(&mut self, first_vertex: i32, index_count: i32);
+    fn draw_triangles_u32(&mut self, first_vertex: i32, index_count: i32);
+    fn draw_nonindexed_lines(&mut self, first_vertex: i32, vertex_count: i32);
+    fn draw_indexed_triangles_instanced_u16(&mut self,
+                                            index_count: i32,
+                                            instance_count: i32);
+    fn draw_indexed_triangles_instanced_multi_u16(&mut self,
+                                                  index_count: i32,
+                                                  draws: &[(usize, usize)]);
+
+    // Render targets
+    fn bind_draw_target(&mut self,
+                        texture_id: Option<(TextureId, i32)>,
+                        dimensions: Option<DeviceUintSize>);
+    fn bind_read_target(&mut self, texture_id: Option<(TextureId, i32)>);
+    fn blit_render_target(&mut self,
+                          src_texture: Option<(TextureId, i32)>,
+                          src_rect: Option<DeviceIntRect>,
+                          dest_rect: DeviceIntRect);
+    fn clear_target(&mut self, color: Option<[f32; 4]>, depth: Option<f32>);
+
+    // Pipeline state
+    fn set_blend(&mut self, enable: bool);
+    fn set_blend_mode_alpha(&mut self);
+    fn set_blend_mode_premultiplied_alpha(&mut self);
+    fn set_blend_mode_subpixel(&mut self, color: ColorF);
+    fn enable_depth(&mut self);
+    fn disable_depth(&mut self);
+    fn enable_depth_write(&mut self);
+    fn disable_depth_write(&mut self);
+    fn disable_stencil(&mut self);
+}
+
+impl GpuBackend for Device {
+    fn begin_frame(&mut self, device_pixel_ratio: f32) -> FrameId {
+        Device::begin_frame(self, device_pixel_ratio)
+    }
+
+    fn end_frame(&mut self) {
+        Device::end_frame(self)
+    }
+
+    fn create_texture_ids(&mut self, count: i32, target: TextureTarget) -> Vec<TextureId> {
+        Device::create_texture_ids(self, count, target)
+    }
+
+    fn init_texture(&mut self,
+                    texture_id: TextureId,
+                    width: u32,
+                    height: u32,
+                    format: ImageFormat,
+                    filter: TextureFilter,
+                    mode: RenderTargetMode,
+                    pixels: Option<&[u8]>) {
+        Device::init_texture(self, texture_id, width, height, format, filter, mode, pixels)
+    }
+
+    fn update_texture(&mut self,
+                      texture_id: TextureId,
+                      x0: u32,
+                      y0: u32,
+                      width: u32,
+                      height: u32,
+                      stride: Option<u32>,
+                      data: &[u8]) {
+        Device::update_texture(self, texture_id, x0, y0, width, height, stride, data)
+    }
+
+    fn deinit_texture(&mut self, texture_id: TextureId) {
+        Device::deinit_texture(self, texture_id)
+    }
+
+    fn bind_texture(&mut self, sampler: TextureSampler, texture_id: TextureId) {
+        Device::bind_texture(self, sampler, texture_id)
+    }
+
+    fn create_program(&mut self,
+                      base_filename: &str,
+                      include_filename: &str,
+                      descriptor: &VertexDescriptor) -> Result<Program, ShaderError> {
+        Device::create_program(self, base_filename, include_filename, descriptor)
+    }
+
+    fn bind_program(&mut self, program: &Program) {
+        Device::bind_program(self, program)
+    }
+
+    fn set_uniforms(&mut self, program: &Program, transform: &Transform3D<f32>) {
+        Device::set_uniforms(self, program, transform)
+    }
+
+    fn delete_program(&mut self, program: &mut Program) {
+        Device::delete_program(self, program)
+    }
+
+    fn create_vao(&mut self,
+                  descriptor: &VertexDescriptor,
+                  inst_stride: gl::GLint) -> VAOId {
+        Device::create_vao(self, descriptor, inst_stride)
+    }
+
+    fn bind_vao(&mut self, vao_id: VAOId) {
+        Device::bind_vao(self, vao_id)
+    }
+
+    fn update_vao_main_vertices<V>(&mut self,
+                                   vao_id: VAOId,
+                                   vertices: &[V],
+                                   usage_hint: VertexUsageHint) {
+        Device::update_vao_main_vertices(self, vao_id, vertices, usage_hint)
+    }
+
+    fn update_vao_instances<V>(&mut self,
+                               vao_id: VAOId,
+                               instances: &[V],
+                               usage_hint: VertexUsageHint) {
+        Device::update_vao_instances(self, vao_id, instances, usage_hint)
+    }
+
+    fn update_vao_indices<I>(&mut self,
+                             vao_id: VAOId,
+                             indices: &[I],
+                             usage_hint: VertexUsageHint) {
+        Device::update_vao_indices(self, vao_id, indices, usage_hint)
+    }
+
+    fn set_vao_instance_offset(&mut self,
+                               vao_id: VAOId,
+                               first_instance: usize) {
+        Device::set_vao_instance_offset(self, vao_id, first_instance)
+    }
+
+    fn draw_triangles_u16(&mut self, first_vertex: i32, index_count: i32) {
+        Device::draw_triangles_u16(self, first_vertex, index_count)
+    }
+
+    fn draw_triangles_u32(&mut self, first_vertex: i32, index_count: i32) {
+        Device::draw_triangles_u32(self, first_vertex, index_count)
+    }
+
+    fn draw_nonindexed_lines(&mut self, first_vertex: i32, vertex_count: i32) {
+        Device::draw_nonindexed_lines(self, first_vertex, vertex_count)
+    }
+
+    fn draw_indexed_triangles_instanced_u16(&mut self,
+                                            index_count: i32,
+                                            instance_count: i32) {
+        Device::draw_indexed_triangles_instanced_u16(self, index_count, instance_count)
+    }
+
+    fn draw_indexed_triangles_instanced_multi_u16(&mut self,
+                                                  index_count: i32,
+                                                  draws: &[(usize, usize)]) {
+        Device::draw_indexed_triangles_instanced_multi_u16(self, index_count, draws)
+    }
+
+    fn bind_draw_target(&mut self,
+                        texture_id: Option<(TextureId, i32)>,
+                        dimensions: Option<DeviceUintSize>) {
+        Device::bind_draw_target(self, texture_id, dimensions)
+    }
+
+    fn bind_read_target(&mut self, texture_id: Option<(TextureId, i32)>) {
+        Device::bind_read_target(self, texture_id)
+    }
+
+    fn blit_render_target(&mut self,
+                          src_texture: Option<(TextureId, i32)>,
+                          src_rect: Option<DeviceIntRect>,
+                          dest_rect: DeviceIntRect) {
+        Device::blit_render_target(self, src_texture, src_rect, dest_rect)
+    }
+
+    fn clear_target(&mut self, color: Option<[f32; 4]>, depth: Option<f32>) {
+        Device::clear_target(self, color, depth)
+    }
+
+    fn set_blend(&mut self, enable: bool) {
+        Device::set_blend(self, enable)
+    }
+
+    fn set_blend_mode_alpha(&mut self) {
+        Device::set_blend_mode_alpha(self)
+    }
+
+    fn set_blend_mode_premultiplied_alpha(&mut self) {
+        Device::set_blend_mode_premultiplied_alpha(self)
+    }
+
+    fn set_blend_mode_subpixel(&mut self, color: ColorF) {
+        Device::set_blend_mode_subpixel(self, color)
+    }
+
+    fn enable_depth(&mut self) {
+        Device::enable_depth(self)
+    }
+
+    fn disable_depth(&mut self) {
+        Device::disable_depth(self)
+    }
+
+    fn enable_depth_write(&mut self) {
+        Device::enable_depth_write(self)
+    }
+
+    fn disable_depth_write(&mut self) {
+        Device::disable_depth_write(self)
+    }
+
+    fn disable_stencil(&mut self) {
+        Device::disable_stencil(self)
+    }
+}
@@ -179,13 +179,19 @@ struct Row {
     // Each row becomes a slab allocator for a fixed block size.
     // This means no dealing with fragmentation within a cache
     // row as items are allocated and freed.
+    // Zero for a row that has been reclaimed by the GC and is
+    // waiting to be reused by a new slab.
     block_count_per_item: usize,
+    // Number of currently allocated items in this row. When it
+    // drops to zero the row becomes a candidate for reclamation.
+    occupied_count: usize,
 }
 
 impl Row {
     fn new(block_count_per_item: usize) -> Row {
         Row {
             block_count_per_item,
+            occupied_count: 0,
         }
     }
 }
@@ -278,6 +284,12 @@ struct Texture {
     // makes it faster to iterate blocks looking for
     // candidates to be evicted from the cache.
     occupied_list_head: Option<BlockIndex>,
+    // Rows that the GC has reclaimed. They are reused (with a new
+    // block size, if needed) before the texture is grown.
+    free_rows: Vec<usize>,
+    // Block pool entries whose row was reclaimed. Reused when a new
+    // slab is created, so the pool doesn't grow over long sessions.
+    free_block_indices: Vec<BlockIndex>,
     // Pending blocks that have been written this frame
     // and will need to be sent to the GPU.
     pending_blocks: Vec<GpuBlockData>,
@@ -297,6 +309,8 @@ impl Texture {
             pending_blocks: Vec::new(),
             updates: Vec::new(),
             occupied_list_head: None,
+            free_rows: Vec::new(),
+            free_block_indices: Vec::new(),
             allocated_block_count: 0,
         }
     }
@@ -314,24 +328,45 @@ impl Texture {
 
         // See if we need a new row (if free-list has nothing available)
         if free_list.is_none() {
-            if self.rows.len() as u32 == self.height {
-                self.height += NEW_ROWS_PER_RESIZE;
-            }
-
-            // Create a new row.
-            let items_per_row = MAX_VERTEX_TEXTURE_WIDTH / alloc_size;
-            let row_index = self.rows.len();
-            self.rows.push(Row::new(alloc_size));
+            // Reuse a reclaimed row if the GC has freed one; only grow
+            // the texture when there are none.
+            let row_index = match self.free_rows.pop() {
+                Some(row_index) => {
+                    self.rows[row_index] = Row::new(alloc_size);
+                    row_index
+                }
+                None => {
+                    if self.rows.len() as u32 == self.height {
+                        self.height += NEW_ROWS_PER_RESIZE;
+                    }
+                    self.rows.push(Row::new(alloc_size));
+                    self.rows.len() - 1
+                }
+            };
 
             // Create a ```Block``` for each possible allocation address
             // in this row, and link it in to the free-list for this
-            // block size.
+            // block size. Pool entries from reclaimed rows are reused;
+            // they keep their bumped epoch so that a stale handle can
+            // never match a reused block.
+            let items_per_row = MAX_VERTEX_TEXTURE_WIDTH / alloc_size;
             let mut prev_block_index = None;
             for i in 0..items_per_row {
                 let address = GpuCacheAddress::new(i * alloc_size, row_index);
-                let block_index = BlockIndex(self.blocks.len());
-                let block = Block::new(address, prev_block_index, frame_id);
-                self.blocks.push(block);
+                let block_index = match self.free_block_indices.pop() {
+                    Some(block_index) => {
+                        let block = &mut self.blocks[block_index.0];
+                        block.address = address;
+                        block.next = prev_block_index;
+                        block.last_access_time = frame_id;
+                        block_index
+                    }
+                    None => {
+                        let block_index = BlockIndex(self.blocks.len());
+                        self.blocks.push(Block::new(address, prev_block_index, frame_id));
+                        block_index
+                    }
+                };
                 prev_block_index = Some(block_index);
             }
 
@@ -349,6 +384,7 @@ impl Texture {
         block.next = self.occupied_list_head;
         block.last_access_time = frame_id;
         self.occupied_list_head = Some(free_block_index);
+        self.rows[block.address.v as usize].occupied_count += 1;
         self.allocated_block_count += alloc_size;
 
         if let Some(pending_block_index) = pending_block_index {
@@ -401,6 +437,7 @@ impl Texture {
                     block.next = *free_list;
                     *free_list = Some(index);
 
+                    row.occupied_count -= 1;
                     self.allocated_block_count -= row.block_count_per_item;
                 };
 
@@ -425,6 +462,62 @@ impl Texture {
             current_block = next_block;
         }
     }
+
+    // Reclaim rows whose blocks have all been evicted, so that a row
+    // can be reused by a slab of a different block size instead of
+    // growing the texture. Handles pointing into a reclaimed row are
+    // remapped lazily: their epoch no longer matches, so the next
+    // request - at the latest when the scene is rebuilt - reallocates
+    // the data at its new address. Returns the number of texture bytes
+    // freed, for the profiler.
+    fn reclaim_empty_rows(&mut self) -> usize {
+        let mut empty_rows = Vec::new();
+        for (row_index, row) in self.rows.iter().enumerate() {
+            if row.block_count_per_item != 0 && row.occupied_count == 0 {
+                empty_rows.push(row_index);
+            }
+        }
+
+        for &row_index in &empty_rows {
+            let block_count_per_item = self.rows[row_index].block_count_per_item;
+            self.rows[row_index].block_count_per_item = 0;
+
+            // Unlink every block of this row from its size class
+            // free-list, and return the pool entries for reuse.
+            let (_, free_list) = self.free_lists
+                                     .get_actual_block_count_and_free_list(block_count_per_item);
+            let mut current_block = *free_list;
+            let mut prev_block: Option<BlockIndex> = None;
+
+            while let Some(index) = current_block {
+                let next_block = self.blocks[index.0].next;
+
+                if self.blocks[index.0].address.v as usize == row_index {
+                    // Make sure a handle that saw this block's current
+                    // epoch can't match it again once it is reused.
+                    self.blocks[index.0].epoch.next();
+                    self.free_block_indices.push(index);
+
+                    match prev_block {
+                        Some(prev_block) => {
+                            self.blocks[prev_block.0].next = next_block;
+                        }
+                        None => {
+                            *free_list = next_block;
+                        }
+                    }
+                } else {
+                    prev_block = current_block;
+                }
+
+                current_block = next_block;
+            }
+
+            self.free_rows.push(row_index);
+        }
+
+        empty_rows.len() * MAX_VERTEX_TEXTURE_WIDTH * mem::size_of::<GpuBlockData>()
+    }
 }
 
 
@@ -473,6 +566,9 @@ pub struct GpuCache {
     frame_id: FrameId,
     /// CPU-side texture allocator.
     texture: Texture,
+    /// Bytes of texture space reclaimed by the row GC this frame,
+    /// for the profiler.
+    freed_bytes: usize,
 }
 
 impl GpuCache {
@@ -480,6 +576,7 @@ impl GpuCache {
         GpuCache {
             frame_id: FrameId::new(0),
             texture: Texture::new(),
+            freed_bytes: 0,
         }
     }
 
@@ -488,6 +585,7 @@ impl GpuCache {
         debug_assert!(self.texture.pending_blocks.is_empty());
         self.frame_id = self.frame_id + 1;
         self.texture.evict_old_blocks(self.frame_id);
+        self.freed_bytes = self.texture.reclaim_empty_rows();
     }
 
     // Invalidate a (possibly) existing block in the cache.
@@ -503,13 +601,17 @@ impl GpuCache {
     // Request a resource be added to the cache. If the resource
     /// is already in the cache, `None` will be returned.
     pub fn request<'a>(&'a mut self, handle: &'a mut GpuCacheHandle) -> Option<GpuDataRequest<'a>> {
-        // Check if the allocation for this handle is still valid.
+        // Check if the allocation for this handle is still valid. Look the
+        // block up with `get_mut` rather than indexing: a retained handle can
+        // outlive a cache reset (for example after a GL context loss), which
+        // leaves it pointing past the end of the rebuilt block array.
         if let Some(ref location) = handle.location {
-            let block = &mut self.texture.blocks[location.block_index.0];
-            if block.epoch == location.epoch {
-                // Mark last access time to avoid evicting this block.
-                block.last_access_time = self.frame_id;
-                return None
+            if let Some(block) = self.texture.blocks.get_mut(location.block_index.0) {
+                if block.epoch == location.epoch {
+                    // Mark last access time to avoid evicting this block.
+                    block.last_access_time = self.frame_id;
+                    return None
+                }
             }
         }
 
@@ -556,6 +658,7 @@ impl GpuCache {
                      profile_counters: &mut GpuCacheProfileCounters) -> GpuCacheUpdateList {
         profile_counters.allocated_rows.set(self.texture.rows.len());
         profile_counters.allocated_blocks.set(self.texture.allocated_block_count);
+        profile_counters.freed_bytes.set(self.freed_bytes);
 
         GpuCacheUpdateList {
             height: self.texture.height,
@@ -564,6 +667,29 @@ impl GpuCache {
         }
     }
 
+    /// An estimate of the heap memory held by the CPU-side allocator
+    /// state, reported when memory pressure replaces the cache with a
+    /// fresh one.
+    pub fn cpu_memory_in_use(&self) -> usize {
+        self.texture.blocks.len() * mem::size_of::<Block>() +
+            self.texture.rows.len() * mem::size_of::<Row>()
+    }
+
+    /// Returns the number of blocks allocated to a slot, including the
+    /// rounding up to its row's bucket size, or zero for a handle that
+    /// has never been requested or whose slot has been freed.
+    pub fn get_block_count(&self, id: &GpuCacheHandle) -> usize {
+        let location = match id.location {
+            Some(ref location) => location,
+            None => return 0,
+        };
+        let block = &self.texture.blocks[location.block_index.0];
+        if block.epoch != location.epoch {
+            return 0;
+        }
+        self.texture.rows[block.address.v as usize].block_count_per_item
+    }
+
     /// Get the actual GPU address in the texture for a given slot ID.
     /// It's assumed at this point that the given slot has been requested
     /// and built for this frame. Attempting to get the address for a
@@ -0,0 +1,156 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Hit testing against the compositor's view of the scene.
+//!
+//! Layout knows where display items were when it built the display list,
+//! but only the compositor knows where they are now that async scrolling
+//! and zooming have moved them. The frame builder records every item that
+//! carries an `ItemTag` while flattening the display list, and queries
+//! walk that record against the clip-scroll tree's current transforms, so
+//! the backend can answer point queries without a round trip to layout.
+
+use api::{ClipAndScrollInfo, ClipMode, ComplexClipRegion, HitTestItem, HitTestResult, ItemTag};
+use api::{LayerPoint, LayerRect, LayerVector2D, LayoutPoint, LayoutVector2D, LocalClip};
+use api::{PipelineId, WorldPoint};
+use clip_scroll_node::NodeType;
+use clip_scroll_tree::ClipScrollTree;
+
+/// A tagged display item, recorded in paint order while the display list
+/// was flattened. Coordinates are in the local space of the item's
+/// clip-scroll node, with reference frame offsets already applied.
+pub struct HitTestingItem {
+    pub clip_and_scroll: ClipAndScrollInfo,
+    pub rect: LayerRect,
+    pub local_clip: LocalClip,
+    pub tag: ItemTag,
+}
+
+pub fn hit_test(clip_scroll_tree: &ClipScrollTree,
+                items: &[HitTestingItem],
+                pipeline_id: Option<PipelineId>,
+                point: WorldPoint)
+                -> HitTestResult {
+    let mut result = HitTestResult::default();
+
+    // Items were recorded in paint order, so walking them backwards
+    // reports the topmost hit first.
+    for item in items.iter().rev() {
+        let scroll_node_id = item.clip_and_scroll.scroll_node_id;
+        if let Some(pipeline_id) = pipeline_id {
+            if scroll_node_id.pipeline_id() != pipeline_id {
+                continue;
+            }
+        }
+
+        let node = match clip_scroll_tree.nodes.get(&scroll_node_id) {
+            Some(node) => node,
+            None => continue,
+        };
+
+        // Bring the point into the item's local space. A node whose
+        // transform has no inverse is collapsed to nothing on screen, so
+        // nothing inside it can be hit.
+        let local_point = match node.world_content_transform.inverse() {
+            Some(inverse) => inverse.transform_point2d(&point),
+            None => continue,
+        };
+
+        if !item.rect.contains(&local_point) || !local_clip_contains(&item.local_clip, &local_point) {
+            continue;
+        }
+
+        // Points that the item's clip node has scrolled or clipped out
+        // of view don't hit either.
+        if let Some(clip_node) = clip_scroll_tree.nodes.get(&item.clip_and_scroll.clip_node_id()) {
+            if !clip_node.combined_local_viewport_rect.contains(&local_point) {
+                continue;
+            }
+        }
+
+        let scroll_offset = match node.node_type {
+            NodeType::ScrollFrame(ref scrolling) => scrolling.offset,
+            _ => LayerVector2D::zero(),
+        };
+
+        result.items.push(HitTestItem {
+            pipeline: scroll_node_id.pipeline_id(),
+            clip_id: scroll_node_id,
+            scroll_offset: LayoutVector2D::new(scroll_offset.x, scroll_offset.y),
+            tag: item.tag,
+            point_in_item: LayoutPoint::new(local_point.x - item.rect.origin.x,
+                                            local_point.y - item.rect.origin.y),
+        });
+    }
+
+    result
+}
+
+/// Whether `point` survives the item's local clip, taking rounded corners
+/// and the clip mode into account.
+fn local_clip_contains(local_clip: &LocalClip, point: &LayerPoint) -> bool {
+    match *local_clip {
+        LocalClip::Rect(rect) => rect.contains(point),
+        LocalClip::RoundedRect(rect, ref region) => {
+            if !rect.contains(point) {
+                return false;
+            }
+            match region.mode {
+                ClipMode::Clip => rounded_rect_contains(region, point),
+                ClipMode::ClipOut => !rounded_rect_contains(region, point),
+            }
+        }
+    }
+}
+
+/// Whether `point` is inside the rounded rect described by `region`,
+/// ignoring the clip mode.
+fn rounded_rect_contains(region: &ComplexClipRegion, point: &LayerPoint) -> bool {
+    let rect = &region.rect;
+    if !rect.contains(point) {
+        return false;
+    }
+
+    // If the point lies in the bounding box of a corner, it only hits if
+    // it is inside that corner's ellipse.
+    let corners = [
+        (LayerPoint::new(rect.origin.x + region.radii.top_left.width,
+                         rect.origin.y + region.radii.top_left.height),
+         region.radii.top_left,
+         -1.0,
+         -1.0),
+        (LayerPoint::new(rect.max_x() - region.radii.top_right.width,
+                         rect.origin.y + region.radii.top_right.height),
+         region.radii.top_right,
+         1.0,
+         -1.0),
+        (LayerPoint::new(rect.origin.x + region.radii.bottom_left.width,
+                         rect.max_y() - region.radii.bottom_left.height),
+         region.radii.bottom_left,
+         -1.0,
+         1.0),
+        (LayerPoint::new(rect.max_x() - region.radii.bottom_right.width,
+                         rect.max_y() - region.radii.bottom_right.height),
+         region.radii.bottom_right,
+         1.0,
+         1.0),
+    ];
+
+    for &(center, radius, sign_x, sign_y) in &corners {
+        if radius.width <= 0.0 || radius.height <= 0.0 {
+            continue;
+        }
+        let dx = point.x - center.x;
+        let dy = point.y - center.y;
+        if dx * sign_x > 0.0 && dy * sign_y > 0.0 {
+            let nx = dx / radius.width;
+            let ny = dy / radius.height;
+            if nx * nx + ny * ny > 1.0 {
+                return false;
+            }
+        }
+    }
+
+    true
+}
@@ -3,23 +3,92 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use device::TextureFilter;
+use frame::FrameId;
 use fxhash::FxHasher;
 use profiler::BackendProfileCounters;
+use std::cmp;
 use std::collections::{HashMap, HashSet};
 use std::f32;
 use std::hash::BuildHasherDefault;
 use std::{i32, usize};
+use std::mem;
 use std::path::PathBuf;
 use std::sync::Arc;
 use tiling;
 use renderer::BlendMode;
-use api::{ClipId, DevicePoint, DeviceUintRect, DocumentId, Epoch};
-use api::{ExternalImageData, ExternalImageId};
-use api::{ImageData, ImageFormat, PipelineId};
+use api::{ClipId, DevicePoint, DeviceUintPoint, DeviceUintRect, DeviceUintSize, DocumentId, Epoch};
+use api::{ExternalImageData, ExternalImageId, FontKey};
+use api::{ImageData, ImageFormat, ImageKey, MemoryPressureLevel, PipelineId};
+use api::QualitySignals;
 
 pub type FastHashMap<K, V> = HashMap<K, V, BuildHasherDefault<FxHasher>>;
 pub type FastHashSet<K> = HashSet<K, BuildHasherDefault<FxHasher>>;
 
+/// Device-space cap applied to blur radii while the device is thermally
+/// throttled. Blur cost scales with the radius, and very large blurs are
+/// the ones worth degrading first.
+const MAX_BLUR_RADIUS_THROTTLED: i32 = 16;
+
+/// Scale applied to intermediate render targets while the system is low
+/// on memory.
+const LOW_MEMORY_TARGET_SCALE: f32 = 0.5;
+
+/// Runtime-adjustable rendering quality. The settings are lowered in
+/// response to embedder pressure signals and restored when the signals
+/// clear; see `QualitySettings::for_signals`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QualitySettings {
+    /// When false, subpixel text AA is downgraded to grayscale alpha.
+    pub allow_subpixel_aa: bool,
+    /// When set, device-space blur radii are clamped to this many pixels.
+    /// Large blurs lose accuracy, but their cost scales with the radius.
+    pub max_blur_radius: Option<i32>,
+    /// Scale applied to intermediate render targets. Below 1.0 they are
+    /// rendered at reduced resolution and upsampled when composited,
+    /// reusing the scaling path for targets that exceed the maximum
+    /// target size.
+    pub target_scale: f32,
+}
+
+impl QualitySettings {
+    pub fn full() -> QualitySettings {
+        QualitySettings {
+            allow_subpixel_aa: true,
+            max_blur_radius: None,
+            target_scale: 1.0,
+        }
+    }
+
+    /// Map embedder pressure signals to quality settings. Thermal
+    /// throttling trades visual quality for GPU work; low memory shrinks
+    /// the intermediate surfaces.
+    pub fn for_signals(signals: &QualitySignals) -> QualitySettings {
+        let mut settings = QualitySettings::full();
+        if signals.thermal_throttle {
+            settings.allow_subpixel_aa = false;
+            settings.max_blur_radius = Some(MAX_BLUR_RADIUS_THROTTLED);
+        }
+        if signals.low_memory {
+            settings.target_scale = LOW_MEMORY_TARGET_SCALE;
+        }
+        settings
+    }
+
+    /// Combine two settings, keeping the lower quality of each knob. Used
+    /// so that runtime downgrades never raise quality above the baseline
+    /// the embedder configured in `RendererOptions`.
+    pub fn min(&self, other: &QualitySettings) -> QualitySettings {
+        QualitySettings {
+            allow_subpixel_aa: self.allow_subpixel_aa && other.allow_subpixel_aa,
+            max_blur_radius: match (self.max_blur_radius, other.max_blur_radius) {
+                (Some(a), Some(b)) => Some(cmp::min(a, b)),
+                (radius, None) | (None, radius) => radius,
+            },
+            target_scale: self.target_scale.min(other.target_scale),
+        }
+    }
+}
+
 // An ID for a texture that is owned by the
 // texture cache module. This can include atlases
 // or standalone textures allocated via the
@@ -49,6 +118,15 @@ pub enum SourceTexture {
     WebGL(u32),
 }
 
+/// A server-side wait the renderer must issue before the frame samples
+/// a WebGL canvas texture. The sync object is the raw GL fence handle
+/// the producing context signalled after its last draw into the
+/// texture; the wait consumes (deletes) it.
+pub struct WebGLAcquire {
+    pub texture_id: u32,
+    pub sync_object: usize,
+}
+
 pub const ORTHO_NEAR_PLANE: f32 = -1000000.0;
 pub const ORTHO_FAR_PLANE: f32 = 1000000.0;
 
@@ -80,7 +158,7 @@ impl TextureSampler {
 
 /// Optional textures that can be used as a source in the shaders.
 /// Textures that are not used by the batch are equal to TextureId::invalid().
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct BatchTextures {
     pub colors: [SourceTexture; 3],
 }
@@ -136,6 +214,14 @@ pub enum TextureUpdateOp {
         filter: TextureFilter,
         mode: RenderTargetMode,
     },
+    // Copies a rect from another cache texture into this one, on the
+    // GPU. Used when the texture cache relocates an item during page
+    // compaction.
+    CopyRect {
+        src_id: CacheTextureId,
+        src_rect: DeviceUintRect,
+        dest_rect: DeviceUintRect,
+    },
     Free,
 }
 
@@ -145,6 +231,22 @@ pub struct TextureUpdate {
     pub op: TextureUpdateOp,
 }
 
+impl TextureUpdate {
+    /// The destination rectangle of a rect upload op, if this is one.
+    /// Structural ops (create, grow, free) and GPU-side copies return
+    /// None.
+    fn upload_rect(&self) -> Option<DeviceUintRect> {
+        match self.op {
+            TextureUpdateOp::Update { page_pos_x, page_pos_y, width, height, .. } => {
+                Some(DeviceUintRect::new(DeviceUintPoint::new(page_pos_x, page_pos_y),
+                                         DeviceUintSize::new(width, height)))
+            }
+            TextureUpdateOp::UpdateForExternalBuffer { rect, .. } => Some(rect),
+            _ => None,
+        }
+    }
+}
+
 pub struct TextureUpdateList {
     pub updates: Vec<TextureUpdate>,
 }
@@ -160,6 +262,81 @@ impl TextureUpdateList {
     pub fn push(&mut self, update: TextureUpdate) {
         self.updates.push(update);
     }
+
+    /// Batches the rect uploads accumulated over a frame so the renderer
+    /// touches each texture page as few times as possible. Uploads whose
+    /// rectangle is fully overwritten by a later upload to the same page
+    /// are dropped, and the survivors are sorted by page and position so
+    /// consecutive updates hit the same texture in row order. Structural
+    /// ops (create, grow, free, copy) change the page layout, so they act
+    /// as barriers that uploads are never reordered across.
+    pub fn sort_and_merge(&mut self) {
+        let updates = mem::replace(&mut self.updates, Vec::new());
+        let mut run: Vec<TextureUpdate> = Vec::new();
+
+        for update in updates {
+            match update.upload_rect() {
+                Some(rect) => {
+                    run.retain(|prev| {
+                        match prev.upload_rect() {
+                            Some(prev_rect) => {
+                                prev.id != update.id || !rect.contains_rect(&prev_rect)
+                            }
+                            None => true,
+                        }
+                    });
+                    run.push(update);
+                }
+                None => {
+                    flush_upload_run(&mut run, &mut self.updates);
+                    self.updates.push(update);
+                }
+            }
+        }
+
+        flush_upload_run(&mut run, &mut self.updates);
+    }
+}
+
+/// Sorts one barrier-free run of rect uploads and appends it to the
+/// final update list. See `TextureUpdateList::sort_and_merge`.
+fn flush_upload_run(run: &mut Vec<TextureUpdate>, updates: &mut Vec<TextureUpdate>) {
+    run.sort_by_key(|update| {
+        let rect = update.upload_rect().unwrap();
+        (update.id.0, rect.origin.y, rect.origin.x)
+    });
+    updates.extend(run.drain(..));
+}
+
+/// What a texture cache allocation holds, for the texture cache debug
+/// overlay.
+#[derive(Clone, Debug)]
+pub enum TextureCacheOwner {
+    Image(ImageKey),
+    /// A rasterized glyph; the u32 is the glyph index within the font.
+    Glyph(FontKey, u32),
+    /// A rasterized filled-path mask; the u64 is the path content hash.
+    Path(u64),
+}
+
+#[derive(Clone, Debug)]
+pub struct TextureCacheAllocInfo {
+    pub texture_id: CacheTextureId,
+    pub rect: DeviceUintRect,
+    pub format: ImageFormat,
+    /// The frame this allocation was last requested on, so the overlay can
+    /// dim stale entries.
+    pub last_access: FrameId,
+    pub owner: TextureCacheOwner,
+}
+
+/// A snapshot of every live texture cache allocation, published by the
+/// backend with each frame while the texture cache debug overlay is
+/// enabled.
+#[derive(Clone, Debug)]
+pub struct TextureCacheDebugInfo {
+    pub current_frame_id: FrameId,
+    pub allocations: Vec<TextureCacheAllocInfo>,
 }
 
 /// Mostly wraps a tiling::Frame, adding a bit of extra information.
@@ -189,8 +366,16 @@ impl RendererFrame {
 
 pub enum ResultMsg {
     RefreshShader(PathBuf),
-    NewFrame(DocumentId, RendererFrame, TextureUpdateList, BackendProfileCounters),
+    NewFrame(DocumentId, RendererFrame, TextureUpdateList, Option<TextureCacheDebugInfo>, BackendProfileCounters),
     UpdateResources { updates: TextureUpdateList, cancel_rendering: bool },
+    /// A memory pressure notification; the renderer frees its share of
+    /// the reclaimable memory (render target pool, GPU cache shadow
+    /// copy) according to the level.
+    MemoryPressure(MemoryPressureLevel),
+    /// Marks the point in the result queue at which the backend has processed
+    /// a context loss: everything before this message was built for the dead
+    /// context, everything after it for the new one.
+    ContextRestored,
 }
 
 #[derive(Debug, Clone, Copy, Eq, Hash, PartialEq)]
@@ -205,12 +390,26 @@ pub struct UvRect {
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum HardwareCompositeOp {
     PremultipliedAlpha,
+    /// Source-over with a uniform opacity applied to the source. The
+    /// opacity is quantized to 16 bits (like filter amounts in batch
+    /// instances) so that the op stays hashable.
+    PremultipliedAlphaWithOpacity(u16),
+    Multiply,
+    Screen,
 }
 
 impl HardwareCompositeOp {
+    /// Source-over with the given opacity in [0, 1].
+    pub fn with_opacity(opacity: f32) -> HardwareCompositeOp {
+        HardwareCompositeOp::PremultipliedAlphaWithOpacity((opacity * 65535.0).round() as u16)
+    }
+
     pub fn to_blend_mode(&self) -> BlendMode {
         match *self {
-            HardwareCompositeOp::PremultipliedAlpha => BlendMode::PremultipliedAlpha,
+            HardwareCompositeOp::PremultipliedAlpha |
+            HardwareCompositeOp::PremultipliedAlphaWithOpacity(..) => BlendMode::PremultipliedAlpha,
+            HardwareCompositeOp::Multiply => BlendMode::Multiply,
+            HardwareCompositeOp::Screen => BlendMode::Screen,
         }
     }
 }
@@ -49,6 +49,7 @@ extern crate bitflags;
 #[macro_use]
 extern crate thread_profiler;
 
+pub mod angle;
 mod border;
 mod clip_scroll_node;
 mod clip_scroll_tree;
@@ -56,29 +57,40 @@ mod debug_colors;
 mod debug_font_data;
 mod debug_render;
 mod device;
+mod display_list_validator;
+mod dpr_audit;
 mod ellipse;
 mod frame;
 mod frame_builder;
+mod frame_capture;
 mod freelist;
 mod geometry;
 mod glyph_cache;
 mod glyph_rasterizer;
+mod gpu_backend;
 mod gpu_cache;
+mod hit_test;
 mod internal_types;
 mod mask_cache;
+mod path_rasterizer;
 mod prim_store;
 mod print_tree;
 mod profiler;
 mod record;
 mod render_backend;
 mod render_task;
+#[cfg(feature = "renderdoc")]
+mod renderdoc;
+mod replay;
 mod resource_cache;
 mod scene;
+mod scene_builder;
 mod spring;
 mod texture_allocator;
 mod texture_cache;
 mod tiling;
 mod util;
+mod workarounds;
 
 #[doc(hidden)] // for benchmarks
 pub use texture_cache::TexturePage;
@@ -95,6 +107,7 @@ mod shader_source {
 }
 
 pub use record::{ApiRecordingReceiver, BinaryRecorder, WEBRENDER_RECORDING_HEADER};
+pub use replay::{BenchmarkRunner, BinaryPlayer, FrameTimeDistribution, ReplayTiming};
 
 mod platform {
     #[cfg(target_os="macos")]
@@ -131,6 +144,9 @@ extern crate freetype;
 #[cfg(target_os = "windows")]
 extern crate dwrote;
 
+#[cfg(unix)]
+extern crate libc;
+
 extern crate app_units;
 extern crate bincode;
 extern crate euclid;
@@ -149,7 +165,19 @@ extern crate plane_split;
 #[cfg(any(target_os="macos", target_os="windows"))]
 extern crate gamma_lut;
 
+pub use device::FrameId;
+pub use dpr_audit::{DprAuditCase, DprAuditFailure};
+pub use device::{DeviceEvent, DeviceEventSeverity, DeviceEventSink};
+pub use gpu_backend::GpuBackend;
+pub use profiler::PipelineProfile;
 pub use renderer::{ExternalImage, ExternalImageSource, ExternalImageHandler};
+pub use renderer::PresentationFeedbackHandler;
+pub use renderer::{EyeParams, StereoParams};
+pub use renderer::FrameOutput;
+pub use renderer::SurfaceOrientation;
 pub use renderer::{GraphicsApi, GraphicsApiInfo, ReadPixelsFormat, Renderer, RendererOptions};
+pub use renderer::{RendererOptionsBuilder, RendererOptionsError};
+pub use internal_types::QualitySettings;
+pub use workarounds::{GpuInfo, GpuVendor};
 
 pub use webrender_api as api;
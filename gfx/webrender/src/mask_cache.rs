@@ -2,13 +2,14 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use api::{BorderRadius, ComplexClipRegion, DeviceIntRect, ImageMask, LayerPoint, LayerRect};
-use api::{LayerSize, LayerToWorldTransform, LocalClip};
+use api::{BorderRadius, ClipMode, ComplexClipRegion, DeviceIntRect, ImageMask, LayerPoint};
+use api::{LayerRect, LayerSize, LayerToWorldTransform, LocalClip};
 use border::BorderCornerClipSource;
+use fxhash::FxHasher;
 use gpu_cache::{GpuCache, GpuCacheHandle, ToGpuBlocks};
 use prim_store::{CLIP_DATA_GPU_BLOCKS, ClipData, ImageMaskData};
 use util::{ComplexClipRegionHelpers, TransformedRect};
-use std::ops::Not;
+use std::hash::{Hash, Hasher};
 
 const MAX_CLIP: f32 = 1000000.0;
 
@@ -67,24 +68,6 @@ impl ClipRegion {
     }
 }
 
-#[repr(C)]
-#[derive(Copy, Clone, Debug, PartialEq)]
-pub enum ClipMode {
-    Clip,           // Pixels inside the region are visible.
-    ClipOut,        // Pixels outside the region are visible.
-}
-
-impl Not for ClipMode {
-    type Output = ClipMode;
-
-    fn not(self) -> ClipMode {
-        match self {
-            ClipMode::Clip => ClipMode::ClipOut,
-            ClipMode::ClipOut => ClipMode::Clip
-        }
-    }
-}
-
 #[derive(Clone, Debug)]
 pub enum ClipSource {
     Complex(LayerRect, f32, ClipMode),
@@ -259,7 +242,9 @@ impl MaskCacheInfo {
                             break;
                         }
                         local_rect = local_rect.and_then(|r| r.intersection(&rect));
-                        local_inner = ComplexClipRegion::new(rect, BorderRadius::uniform(radius))
+                        local_inner = ComplexClipRegion::new(rect,
+                                                             BorderRadius::uniform(radius),
+                                                             ClipMode::Clip)
                                                         .get_inner_rect_safe();
                     }
                     ClipSource::Region(ref region) => {
@@ -275,6 +260,13 @@ impl MaskCacheInfo {
                         };
 
                         for clip in &region.complex_clips {
+                            // A clip-out region doesn't restrict the visible
+                            // area, so it mustn't shrink the mask rect. Fall
+                            // back to unknown bounds, as for ClipSource::Complex.
+                            if clip.mode == ClipMode::ClipOut {
+                                has_clip_out = true;
+                                continue;
+                            }
                             local_rect = local_rect.and_then(|r| r.intersection(&clip.rect));
                             local_inner = local_inner.and_then(|r| clip.get_inner_rect_safe()
                                                                        .and_then(|ref inner| r.intersection(inner)));
@@ -378,3 +370,61 @@ impl MaskCacheInfo {
         }
     }
 }
+
+/// Hash the contents of a list of clip sources, so that primitives whose
+/// clip chains are identical can share a single clip mask render task.
+/// Returns `None` when the sources aren't expected to repeat between
+/// primitives (border corners carry per-border geometry), in which case
+/// the caller falls back to a per-primitive mask.
+pub fn clip_sources_content_hash(clips: &[ClipSource]) -> Option<u64> {
+    let mut hasher = FxHasher::default();
+
+    for clip in clips {
+        match *clip {
+            ClipSource::Complex(rect, radius, mode) => {
+                hasher.write_u8(0);
+                hash_rect(&mut hasher, &rect);
+                hash_f32(&mut hasher, radius);
+                hasher.write_u32(mode as u32);
+            }
+            ClipSource::Region(ref region) => {
+                hasher.write_u8(1);
+                hash_f32(&mut hasher, region.origin.x);
+                hash_f32(&mut hasher, region.origin.y);
+                hash_rect(&mut hasher, &region.main);
+                if let Some(ref mask) = region.image_mask {
+                    mask.image.hash(&mut hasher);
+                    hash_rect(&mut hasher, &mask.rect);
+                    hasher.write_u8(mask.repeat as u8);
+                }
+                for complex in &region.complex_clips {
+                    hash_rect(&mut hasher, &complex.rect);
+                    for size in &[complex.radii.top_left,
+                                  complex.radii.top_right,
+                                  complex.radii.bottom_left,
+                                  complex.radii.bottom_right] {
+                        hash_f32(&mut hasher, size.width);
+                        hash_f32(&mut hasher, size.height);
+                    }
+                    hasher.write_u32(complex.mode as u32);
+                }
+            }
+            ClipSource::BorderCorner(..) => {
+                return None;
+            }
+        }
+    }
+
+    Some(hasher.finish())
+}
+
+fn hash_f32(hasher: &mut FxHasher, value: f32) {
+    hasher.write_u32(value.to_bits());
+}
+
+fn hash_rect(hasher: &mut FxHasher, rect: &LayerRect) {
+    hash_f32(hasher, rect.origin.x);
+    hash_f32(hasher, rect.origin.y);
+    hash_f32(hasher, rect.size.width);
+    hash_f32(hasher, rect.size.height);
+}
@@ -0,0 +1,403 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use device::TextureFilter;
+use frame::FrameId;
+use fxhash::FxHasher;
+use gpu_cache::GpuCache;
+use internal_types::FastHashSet;
+use profiler::TextureCacheProfileCounters;
+use rayon::ThreadPool;
+use resource_cache::{Resource, ResourceClassCache};
+use std::collections::hash_map::Entry;
+use std::hash::Hasher;
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use texture_cache::{TextureCache, TextureCacheItemId};
+use api::{ColorF, DeviceUintSize, FillRule, PathCommand};
+use api::{ImageData, ImageDescriptor, ImageFormat};
+
+// Flattened curve segments deviate from the true curve by at most this
+// many device pixels.
+const FLATTEN_TOLERANCE: f32 = 0.25;
+
+// Each pixel row is sampled at this many vertical offsets; horizontal
+// coverage within a sample row is exact.
+const SAMPLES_PER_ROW: u32 = 4;
+
+/// A filled path as carried by an image primitive: the commands straight
+/// out of the display item, plus everything that determines the
+/// rasterized pixels, folded into a content hash that serves as the
+/// cache identity of the shape.
+#[derive(Debug)]
+pub struct PathShape {
+    pub commands: Vec<PathCommand>,
+    pub fill_rule: FillRule,
+    pub color: ColorF,
+    pub content_hash: u64,
+}
+
+impl PathShape {
+    pub fn new(commands: Vec<PathCommand>,
+               fill_rule: FillRule,
+               color: ColorF) -> PathShape {
+        let mut hasher = FxHasher::default();
+        hasher.write_u8(fill_rule as u8);
+        hash_f32(&mut hasher, color.r);
+        hash_f32(&mut hasher, color.g);
+        hash_f32(&mut hasher, color.b);
+        hash_f32(&mut hasher, color.a);
+        for command in &commands {
+            match *command {
+                PathCommand::MoveTo(point) => {
+                    hasher.write_u8(0);
+                    hash_f32(&mut hasher, point.x);
+                    hash_f32(&mut hasher, point.y);
+                }
+                PathCommand::LineTo(point) => {
+                    hasher.write_u8(1);
+                    hash_f32(&mut hasher, point.x);
+                    hash_f32(&mut hasher, point.y);
+                }
+                PathCommand::QuadraticTo(control, point) => {
+                    hasher.write_u8(2);
+                    hash_f32(&mut hasher, control.x);
+                    hash_f32(&mut hasher, control.y);
+                    hash_f32(&mut hasher, point.x);
+                    hash_f32(&mut hasher, point.y);
+                }
+                PathCommand::Close => {
+                    hasher.write_u8(3);
+                }
+            }
+        }
+
+        PathShape {
+            commands,
+            fill_rule,
+            color,
+            content_hash: hasher.finish(),
+        }
+    }
+}
+
+fn hash_f32(hasher: &mut FxHasher, value: f32) {
+    hasher.write_u32(value.to_bits());
+}
+
+/// The key a rasterized path mask is cached under. The same shape
+/// displayed at a different scale rasterizes to a different size and
+/// gets its own entry.
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct PathKey {
+    pub content_hash: u64,
+    pub size: DeviceUintSize,
+}
+
+pub struct CachedPathInfo {
+    pub texture_cache_id: TextureCacheItemId,
+    pub last_access: FrameId,
+}
+
+impl Resource for CachedPathInfo {
+    fn free(self, texture_cache: &mut TextureCache) {
+        texture_cache.free(self.texture_cache_id);
+    }
+    fn get_last_access_time(&self) -> FrameId {
+        self.last_access
+    }
+    fn set_last_access_time(&mut self, frame_id: FrameId) {
+        self.last_access = frame_id;
+    }
+    fn add_to_gpu_cache(&self,
+                        texture_cache: &mut TextureCache,
+                        gpu_cache: &mut GpuCache) {
+        let item = texture_cache.get_mut(&self.texture_cache_id);
+        if let Some(mut request) = gpu_cache.request(&mut item.uv_rect_handle) {
+            request.push(item.uv_rect);
+        }
+    }
+}
+
+pub type PathCache = ResourceClassCache<PathKey, CachedPathInfo>;
+
+pub struct PathRasterizer {
+    workers: Arc<ThreadPool>,
+
+    // Paths requested this frame whose rasterization hasn't been picked
+    // up yet, so a shape used by several primitives is only rasterized
+    // once per frame.
+    pending_paths: FastHashSet<PathKey>,
+
+    // Receives the rasterized masks.
+    path_rx: Receiver<PathRasterJob>,
+    path_tx: Sender<PathRasterJob>,
+}
+
+impl PathRasterizer {
+    pub fn new(workers: Arc<ThreadPool>) -> Self {
+        let (path_tx, path_rx) = channel();
+
+        PathRasterizer {
+            workers,
+            pending_paths: FastHashSet::default(),
+            path_rx,
+            path_tx,
+        }
+    }
+
+    pub fn request_path(&mut self,
+                        path_cache: &mut PathCache,
+                        current_frame_id: FrameId,
+                        shape: Arc<PathShape>,
+                        key: PathKey,
+                        scale: f32) {
+        match path_cache.entry(key.clone(), current_frame_id) {
+            Entry::Occupied(..) => return,
+            Entry::Vacant(..) => {}
+        }
+
+        if !self.pending_paths.insert(key.clone()) {
+            return;
+        }
+
+        let path_tx = self.path_tx.clone();
+        self.workers.spawn(move || {
+            profile_scope!("path-raster");
+            let bytes = rasterize_path(&shape, &key.size, scale);
+            path_tx.send(PathRasterJob {
+                key,
+                bytes,
+            }).unwrap();
+        });
+    }
+
+    pub fn resolve_paths(&mut self,
+                         current_frame_id: FrameId,
+                         path_cache: &mut PathCache,
+                         texture_cache: &mut TextureCache,
+                         texture_cache_profile: &mut TextureCacheProfileCounters) {
+        let mut rasterized_paths = Vec::with_capacity(self.pending_paths.len());
+
+        while !self.pending_paths.is_empty() {
+            let job = self.path_rx.recv().expect("BUG: Should be paths pending!");
+            debug_assert!(self.pending_paths.contains(&job.key));
+            self.pending_paths.remove(&job.key);
+
+            rasterized_paths.push(job);
+        }
+
+        // Process the masks in a stable order, so that texture cache
+        // allocation isn't affected by hash set iteration order.
+        rasterized_paths.sort_by_key(|job| {
+            (job.key.content_hash, job.key.size.width, job.key.size.height)
+        });
+
+        for job in rasterized_paths {
+            let texture_cache_id = texture_cache.insert(
+                ImageDescriptor {
+                    width: job.key.size.width,
+                    height: job.key.size.height,
+                    stride: None,
+                    format: ImageFormat::BGRA8,
+                    is_opaque: false,
+                    offset: 0,
+                },
+                TextureFilter::Linear,
+                ImageData::new(job.bytes),
+                [0.0, 0.0],
+                texture_cache_profile,
+            );
+
+            path_cache.insert(job.key, CachedPathInfo {
+                texture_cache_id,
+                last_access: current_frame_id,
+            });
+        }
+    }
+}
+
+struct PathRasterJob {
+    key: PathKey,
+    bytes: Vec<u8>,
+}
+
+// An edge of the flattened path, in device pixels. Horizontal edges are
+// never stored; they can't cross a scanline.
+struct Edge {
+    x0: f32,
+    y0: f32,
+    x1: f32,
+    y1: f32,
+}
+
+// Flattens the path into an edge list, scaling layout coordinates to
+// device pixels. Every subpath is closed, whether or not the commands
+// close it explicitly.
+fn flatten_path(commands: &[PathCommand], scale: f32) -> Vec<Edge> {
+    let mut edges = Vec::new();
+    let mut start = (0.0, 0.0);
+    let mut current = (0.0, 0.0);
+
+    {
+        let mut push_edge = |from: (f32, f32), to: (f32, f32)| {
+            if from.1 != to.1 {
+                edges.push(Edge {
+                    x0: from.0,
+                    y0: from.1,
+                    x1: to.0,
+                    y1: to.1,
+                });
+            }
+        };
+
+        for command in commands {
+            match *command {
+                PathCommand::MoveTo(point) => {
+                    push_edge(current, start);
+                    start = (point.x * scale, point.y * scale);
+                    current = start;
+                }
+                PathCommand::LineTo(point) => {
+                    let next = (point.x * scale, point.y * scale);
+                    push_edge(current, next);
+                    current = next;
+                }
+                PathCommand::QuadraticTo(control, point) => {
+                    let control = (control.x * scale, control.y * scale);
+                    let next = (point.x * scale, point.y * scale);
+
+                    // The distance from the control point to the chord
+                    // midpoint bounds how far the curve strays from the
+                    // chord, which gives the flattening step count for
+                    // the requested tolerance.
+                    let mid = ((current.0 + next.0) * 0.5,
+                               (current.1 + next.1) * 0.5);
+                    let deviation = ((control.0 - mid.0).powi(2) +
+                                     (control.1 - mid.1).powi(2)).sqrt();
+                    let steps = (deviation / FLATTEN_TOLERANCE).sqrt().ceil().max(1.0) as u32;
+
+                    let mut last = current;
+                    for step in 1..steps + 1 {
+                        let t = step as f32 / steps as f32;
+                        let omt = 1.0 - t;
+                        let x = omt * omt * current.0 +
+                                2.0 * omt * t * control.0 +
+                                t * t * next.0;
+                        let y = omt * omt * current.1 +
+                                2.0 * omt * t * control.1 +
+                                t * t * next.1;
+                        push_edge(last, (x, y));
+                        last = (x, y);
+                    }
+                    current = next;
+                }
+                PathCommand::Close => {
+                    push_edge(current, start);
+                    current = start;
+                }
+            }
+        }
+
+        push_edge(current, start);
+    }
+
+    edges
+}
+
+// Adds `amount` of coverage to the pixels spanned by [x0, x1], with the
+// partially covered pixels at either end weighted by how much of them
+// the span covers.
+fn accumulate_span(row: &mut [f32], x0: f32, x1: f32, amount: f32) {
+    let x0 = x0.max(0.0).min(row.len() as f32);
+    let x1 = x1.max(0.0).min(row.len() as f32);
+    if x0 >= x1 {
+        return;
+    }
+
+    let first = x0.floor() as usize;
+    let last = (x1.ceil() as usize - 1).min(row.len() - 1);
+
+    if first == last {
+        row[first] += amount * (x1 - x0);
+        return;
+    }
+
+    row[first] += amount * ((first + 1) as f32 - x0);
+    for x in first + 1..last {
+        row[x] += amount;
+    }
+    row[last] += amount * (x1 - last as f32);
+}
+
+/// Rasterizes the path into a tightly packed premultiplied BGRA8 mask of
+/// the given size: the fill color times the coverage of each pixel. Runs
+/// on the worker threads.
+fn rasterize_path(shape: &PathShape, size: &DeviceUintSize, scale: f32) -> Vec<u8> {
+    let width = size.width as usize;
+    let height = size.height as usize;
+
+    let edges = flatten_path(&shape.commands, scale);
+
+    let mut bytes = Vec::with_capacity(width * height * 4);
+    let mut coverage = vec![0.0; width];
+    let mut crossings: Vec<(f32, i32)> = Vec::new();
+    let sample_weight = 1.0 / SAMPLES_PER_ROW as f32;
+
+    for y in 0..height {
+        for pixel in &mut coverage {
+            *pixel = 0.0;
+        }
+
+        for sample in 0..SAMPLES_PER_ROW {
+            let yc = y as f32 + (sample as f32 + 0.5) * sample_weight;
+
+            crossings.clear();
+            for edge in &edges {
+                let (y_min, y_max, winding) = if edge.y1 > edge.y0 {
+                    (edge.y0, edge.y1, 1)
+                } else {
+                    (edge.y1, edge.y0, -1)
+                };
+                if yc < y_min || yc >= y_max {
+                    continue;
+                }
+                let x = edge.x0 + (yc - edge.y0) * (edge.x1 - edge.x0) /
+                                  (edge.y1 - edge.y0);
+                crossings.push((x, winding));
+            }
+            crossings.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+            let mut winding = 0;
+            let mut span_start = 0.0;
+            for &(x, direction) in &crossings {
+                let was_inside = match shape.fill_rule {
+                    FillRule::Nonzero => winding != 0,
+                    FillRule::EvenOdd => winding % 2 != 0,
+                };
+                winding += direction;
+                let is_inside = match shape.fill_rule {
+                    FillRule::Nonzero => winding != 0,
+                    FillRule::EvenOdd => winding % 2 != 0,
+                };
+
+                if !was_inside && is_inside {
+                    span_start = x;
+                } else if was_inside && !is_inside {
+                    accumulate_span(&mut coverage, span_start, x, sample_weight);
+                }
+            }
+        }
+
+        for &pixel in &coverage {
+            let alpha = shape.color.a * pixel.min(1.0);
+            bytes.push((shape.color.b * alpha * 255.0).round() as u8);
+            bytes.push((shape.color.g * alpha * 255.0).round() as u8);
+            bytes.push((shape.color.r * alpha * 255.0).round() as u8);
+            bytes.push((alpha * 255.0).round() as u8);
+        }
+    }
+
+    bytes
+}
@@ -2,20 +2,26 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use api::{BuiltDisplayList, ColorF, ComplexClipRegion, DeviceIntRect, DeviceIntSize, DevicePoint};
-use api::{ExtendMode, FontKey, FontRenderMode, GlyphInstance, GlyphOptions, GradientStop};
-use api::{ImageKey, ImageRendering, ItemRange, LayerPoint, LayerRect, LayerSize, TextShadow};
-use api::{GlyphKey, LayerToWorldTransform, TileOffset, WebGLContextId, YuvColorSpace, YuvFormat};
+use api::{BuiltDisplayList, ClipMode, ColorF, ColorInterpolation, ComplexClipRegion, DeviceIntRect, DeviceIntSize};
+use api::{DevicePoint, DeviceUintSize, ExtendMode, FontKey, FontRenderMode, GlyphInstance, GlyphOptions};
+use api::GradientStop;
+use api::{ImageKey, ImageRendering, ItemRange, ItemTag, LayerPoint, LayerRect, LayerSize, TextShadow};
+use api::{GlyphKey, LayerToWorldTransform, PixelSnapping, TileOffset, WebGLContextId, YuvColorSpace, YuvFormat};
 use api::{device_length, FontInstanceKey, LayerVector2D, LineOrientation, LineStyle, SubpixelDirection};
 use app_units::Au;
 use border::BorderCornerInstance;
 use euclid::{Size2D};
+use fxhash::FxHasher;
 use gpu_cache::{GpuCacheAddress, GpuBlockData, GpuCache, GpuCacheHandle, GpuDataRequest, ToGpuBlocks};
-use mask_cache::{ClipMode, ClipRegion, ClipSource, MaskCacheInfo};
+use internal_types::{FastHashMap, QualitySettings};
+use mask_cache::{ClipRegion, ClipSource, MaskCacheInfo};
+use path_rasterizer::PathShape;
 use renderer::MAX_VERTEX_TEXTURE_WIDTH;
 use render_task::{RenderTask, RenderTaskLocation};
 use resource_cache::{ImageProperties, ResourceCache};
-use std::{mem, usize};
+use std::{cmp, mem, usize};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
 use util::{pack_as_float, TransformedRect, recycle_vec};
 
 
@@ -155,6 +161,17 @@ pub struct PrimitiveMetadata {
     // text run.
     pub render_task: Option<RenderTask>,
     pub clip_task: Option<RenderTask>,
+    // A hash of the primitive's content, if its kind supports interning.
+    // Used to match the primitive with an identical one from the previous
+    // scene so that its GPU cache location can be retained across rebuilds.
+    pub intern_key: Option<u64>,
+    // The hit-testing tag of the display item this primitive came from,
+    // carried through so debug views can relate primitives back to the
+    // embedder's items.
+    pub tag: Option<ItemTag>,
+    // The snapping hint resolved from the stacking context the primitive
+    // belongs to, consulted when choosing the batch transform kind.
+    pub pixel_snapping: PixelSnapping,
 
     // TODO(gw): In the future, we should just pull these
     //           directly from the DL item, instead of
@@ -203,13 +220,17 @@ impl ToGpuBlocks for LinePrimitive {
 pub enum ImagePrimitiveKind {
     Image(ImageKey, ImageRendering, Option<TileOffset>, LayerSize),
     WebGL(WebGLContextId),
+    // A filled path, rasterized into a cached mask at the size the
+    // primitive is displayed at. The size is filled in each time the
+    // primitive is prepared, since it depends on the device pixel ratio.
+    Path(Arc<PathShape>, DeviceUintSize),
 }
 
 #[derive(Debug)]
 pub struct ImagePrimitiveCpu {
     pub kind: ImagePrimitiveKind,
     // TODO(gw): Build on demand
-    pub gpu_blocks: [GpuBlockData; 2],
+    pub gpu_blocks: [GpuBlockData; 3],
 }
 
 impl ToGpuBlocks for ImagePrimitiveCpu {
@@ -290,7 +311,11 @@ pub struct GradientPrimitiveCpu {
     pub stops_range: ItemRange<GradientStop>,
     pub stops_count: usize,
     pub extend_mode: ExtendMode,
+    pub interpolation: ColorInterpolation,
     pub reverse_stops: bool,
+    /// Hash of the stop list, identifying the shared gradient data table
+    /// this primitive samples. Filled in during prepare_prim_for_render.
+    pub gradient_data_key: u64,
     pub gpu_blocks: [GpuBlockData; 3],
 }
 
@@ -310,16 +335,6 @@ impl GradientPrimitiveCpu {
 
         opacity
     }
-
-    fn build_gpu_blocks_for_angle_radial(&self,
-                                         display_list: &BuiltDisplayList,
-                                         mut request: GpuDataRequest) {
-        request.extend_from_slice(&self.gpu_blocks);
-
-        let gradient_builder = GradientGpuBlockBuilder::new(self.stops_range,
-                                                            display_list);
-        gradient_builder.build(self.reverse_stops, &mut request);
-    }
 }
 
 // The gradient entry index for the first color stop
@@ -345,17 +360,90 @@ pub struct GradientDataEntry {
     pub end_color: ColorF,
 }
 
+// The exact sRGB transfer function. The gradient tables are small
+// enough that using the full formula instead of the 2.2 gamma
+// approximation doesn't cost anything measurable.
+fn srgb_channel_to_linear(s: f32) -> f32 {
+    if s <= 0.04045 {
+        s / 12.92
+    } else {
+        ((s + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_channel_to_srgb(l: f32) -> f32 {
+    if l <= 0.0031308 {
+        l * 12.92
+    } else {
+        1.055 * l.powf(1.0 / 2.4) - 0.055
+    }
+}
+
 struct GradientGpuBlockBuilder<'a> {
     stops_range: ItemRange<GradientStop>,
     display_list: &'a BuiltDisplayList,
+    interpolation: ColorInterpolation,
 }
 
 impl<'a> GradientGpuBlockBuilder<'a> {
     fn new(stops_range: ItemRange<GradientStop>,
-           display_list: &'a BuiltDisplayList) -> GradientGpuBlockBuilder<'a> {
+           display_list: &'a BuiltDisplayList,
+           interpolation: ColorInterpolation) -> GradientGpuBlockBuilder<'a> {
         GradientGpuBlockBuilder {
             stops_range,
             display_list,
+            interpolation,
+        }
+    }
+
+    /// Hash of the table this builder produces. Gradients with identical
+    /// stop lists hash to the same key and can share one table in the GPU
+    /// cache instead of each uploading their own copy.
+    fn data_key(&self, reverse_stops: bool) -> u64 {
+        let mut hasher = FxHasher::default();
+        for stop in self.display_list.get(self.stops_range) {
+            hash_f32(&mut hasher, stop.offset);
+            hash_color(&mut hasher, &stop.color);
+        }
+        hasher.write_u8(reverse_stops as u8);
+        hasher.write_u8(self.interpolation as u8);
+        hasher.finish()
+    }
+
+    /// Convert a stop color into the space the ramp is interpolated in.
+    /// The color is premultiplied in either space, so a transparent stop
+    /// pulls neighboring colors towards zero coverage rather than
+    /// towards transparent black.
+    fn stop_color(&self, color: &ColorF) -> ColorF {
+        let color = match self.interpolation {
+            ColorInterpolation::Srgb => *color,
+            ColorInterpolation::LinearRgb => ColorF {
+                r: srgb_channel_to_linear(color.r),
+                g: srgb_channel_to_linear(color.g),
+                b: srgb_channel_to_linear(color.b),
+                a: color.a,
+            },
+        };
+        color.premultiplied()
+    }
+
+    /// Convert an interpolated ramp color back to the premultiplied sRGB
+    /// encoding the shaders sample.
+    fn output_color(&self, color: ColorF) -> ColorF {
+        match self.interpolation {
+            ColorInterpolation::Srgb => color,
+            ColorInterpolation::LinearRgb => {
+                if color.a <= 0.0 {
+                    return color;
+                }
+                let inv_a = 1.0 / color.a;
+                ColorF {
+                    r: linear_channel_to_srgb(color.r * inv_a) * color.a,
+                    g: linear_channel_to_srgb(color.g * inv_a) * color.a,
+                    b: linear_channel_to_srgb(color.b * inv_a) * color.a,
+                    a: color.a,
+                }
+            }
         }
     }
 
@@ -408,7 +496,7 @@ impl<'a> GradientGpuBlockBuilder<'a> {
 
         let mut src_stops = src_stops.into_iter();
         let first = src_stops.next().unwrap();
-        let mut cur_color = first.color.premultiplied();
+        let mut cur_color = self.stop_color(&first.color);
         debug_assert_eq!(first.offset, 0.0);
 
         // A table of gradient entries, with two colors per entry, that specify the start and end color
@@ -431,7 +519,7 @@ impl<'a> GradientGpuBlockBuilder<'a> {
             // loop will then fill indices in [GRADIENT_DATA_TABLE_BEGIN, GRADIENT_DATA_TABLE_END).
             let mut cur_idx = GRADIENT_DATA_TABLE_END;
             for next in src_stops {
-                let next_color = next.color.premultiplied();
+                let next_color = self.stop_color(&next.color);
                 let next_idx = Self::get_index(1.0 - next.offset);
 
                 if next_idx < cur_idx {
@@ -455,7 +543,7 @@ impl<'a> GradientGpuBlockBuilder<'a> {
             // loop will then fill indices in [GRADIENT_DATA_TABLE_BEGIN, GRADIENT_DATA_TABLE_END).
             let mut cur_idx = GRADIENT_DATA_TABLE_BEGIN;
             for next in src_stops {
-                let next_color = next.color.premultiplied();
+                let next_color = self.stop_color(&next.color);
                 let next_idx = Self::get_index(next.offset);
 
                 if next_idx > cur_idx {
@@ -473,8 +561,8 @@ impl<'a> GradientGpuBlockBuilder<'a> {
         }
 
         for entry in entries.iter() {
-            request.push(entry.start_color);
-            request.push(entry.end_color);
+            request.push(self.output_color(entry.start_color));
+            request.push(self.output_color(entry.end_color));
         }
     }
 }
@@ -483,22 +571,14 @@ impl<'a> GradientGpuBlockBuilder<'a> {
 pub struct RadialGradientPrimitiveCpu {
     pub stops_range: ItemRange<GradientStop>,
     pub extend_mode: ExtendMode,
+    pub interpolation: ColorInterpolation,
     pub gpu_data_count: i32,
+    /// Hash of the stop list, identifying the shared gradient data table
+    /// this primitive samples. Filled in during prepare_prim_for_render.
+    pub gradient_data_key: u64,
     pub gpu_blocks: [GpuBlockData; 3],
 }
 
-impl RadialGradientPrimitiveCpu {
-    fn build_gpu_blocks_for_angle_radial(&self,
-                                         display_list: &BuiltDisplayList,
-                                         mut request: GpuDataRequest) {
-        request.extend_from_slice(&self.gpu_blocks);
-
-        let gradient_builder = GradientGpuBlockBuilder::new(self.stops_range,
-                                                            display_list);
-        gradient_builder.build(false, &mut request);
-    }
-}
-
 #[derive(Debug, Clone)]
 pub struct TextDecoration {
     pub local_rect: LayerRect,
@@ -565,8 +645,13 @@ impl TextRunPrimitiveCpu {
             let mut gpu_block = GpuBlockData::empty();
 
             for (i, src) in src_glyphs.enumerate() {
+                // Quantize the subpixel offset from the device-space glyph
+                // position. Doing it in layout space gives a different (and
+                // wrong) quantization bucket whenever the device pixel ratio
+                // is fractional.
+                let device_point = src.point * device_pixel_ratio;
                 let key = GlyphKey::new(src.index,
-                                        src.point,
+                                        device_point,
                                         font.render_mode,
                                         font.subpx_dir);
                 self.glyph_keys.push(key);
@@ -679,8 +764,7 @@ impl ClipData {
         ClipData {
             rect: ClipRect {
                 rect: clip.rect,
-                // TODO(gw): Support other clip modes for regions?
-                mode: ClipMode::Clip as u32 as f32,
+                mode: clip.mode as u32 as f32,
             },
             top_left: ClipCorner {
                 rect: LayerRect::new(
@@ -775,6 +859,118 @@ pub enum PrimitiveContainer {
     Line(LinePrimitive),
 }
 
+fn hash_f32(hasher: &mut FxHasher, value: f32) {
+    hasher.write_u32(value.to_bits());
+}
+
+fn hash_rect(hasher: &mut FxHasher, rect: &LayerRect) {
+    hash_f32(hasher, rect.origin.x);
+    hash_f32(hasher, rect.origin.y);
+    hash_f32(hasher, rect.size.width);
+    hash_f32(hasher, rect.size.height);
+}
+
+fn hash_color(hasher: &mut FxHasher, color: &ColorF) {
+    hash_f32(hasher, color.r);
+    hash_f32(hasher, color.g);
+    hash_f32(hasher, color.b);
+    hash_f32(hasher, color.a);
+}
+
+fn hash_gpu_blocks(hasher: &mut FxHasher, blocks: &[GpuBlockData]) {
+    for block in blocks {
+        for channel in &block.data {
+            hash_f32(hasher, *channel);
+        }
+    }
+}
+
+impl PrimitiveContainer {
+    /// Computes a hash of the primitive's content, used to match it against
+    /// an identical primitive from the scene being replaced so that retained
+    /// state (currently the GPU cache location) carries over. Returns `None`
+    /// for primitive kinds whose content references item ranges in the
+    /// display list, since those are not stable across display lists.
+    fn intern_key(&self, local_rect: &LayerRect, local_clip_rect: &LayerRect) -> Option<u64> {
+        let mut hasher = FxHasher::default();
+        hash_rect(&mut hasher, local_rect);
+        hash_rect(&mut hasher, local_clip_rect);
+
+        match *self {
+            PrimitiveContainer::Rectangle(ref rect) => {
+                hasher.write_u8(0);
+                hash_color(&mut hasher, &rect.color);
+            }
+            PrimitiveContainer::Line(ref line) => {
+                hasher.write_u8(1);
+                hash_color(&mut hasher, &line.color);
+                hasher.write_u32(line.style as u32);
+                hasher.write_u32(line.orientation as u32);
+            }
+            PrimitiveContainer::Image(ref image) => {
+                hasher.write_u8(2);
+                match image.kind {
+                    ImagePrimitiveKind::Image(image_key, image_rendering, tile_offset, tile_spacing) => {
+                        image_key.hash(&mut hasher);
+                        image_rendering.hash(&mut hasher);
+                        tile_offset.hash(&mut hasher);
+                        hash_f32(&mut hasher, tile_spacing.width);
+                        hash_f32(&mut hasher, tile_spacing.height);
+                    }
+                    ImagePrimitiveKind::WebGL(context_id) => {
+                        hasher.write_usize(context_id.0);
+                    }
+                    ImagePrimitiveKind::Path(ref shape, _) => {
+                        // The content hash covers the commands, fill rule
+                        // and color; the rasterized size is derived from
+                        // the local rect hashed above.
+                        hasher.write_u64(shape.content_hash);
+                    }
+                }
+                hash_gpu_blocks(&mut hasher, &image.gpu_blocks);
+            }
+            PrimitiveContainer::YuvImage(ref yuv) => {
+                hasher.write_u8(3);
+                for image_key in &yuv.yuv_key {
+                    image_key.hash(&mut hasher);
+                }
+                yuv.format.hash(&mut hasher);
+                yuv.color_space.hash(&mut hasher);
+                yuv.image_rendering.hash(&mut hasher);
+                hash_gpu_blocks(&mut hasher, &[yuv.gpu_block]);
+            }
+            PrimitiveContainer::Border(ref border) => {
+                hasher.write_u8(4);
+                for instance in &border.corner_instances {
+                    hasher.write_u8(*instance as u8);
+                }
+                hash_gpu_blocks(&mut hasher, &border.gpu_blocks);
+            }
+            PrimitiveContainer::TextRun(..) |
+            PrimitiveContainer::TextShadow(..) |
+            PrimitiveContainer::AlignedGradient(..) |
+            PrimitiveContainer::AngleGradient(..) |
+            PrimitiveContainer::RadialGradient(..) |
+            PrimitiveContainer::BoxShadow(..) => {
+                return None;
+            }
+        }
+
+        Some(hasher.finish())
+    }
+}
+
+/// The screen footprint of one tagged primitive in a built frame,
+/// consumed by the renderer's paint flashing debug view.
+#[derive(Clone, Debug)]
+pub struct PaintedTag {
+    pub tag: ItemTag,
+    /// A hash of the primitive's content, so a consumer can tell a
+    /// repainted primitive from one that merely moved on screen.
+    pub content_hash: u64,
+    pub screen_rect: DeviceIntRect,
+}
+
 pub struct PrimitiveStore {
     /// CPU side information only.
     pub cpu_bounding_rects: Vec<Option<DeviceIntRect>>,
@@ -789,6 +985,17 @@ pub struct PrimitiveStore {
     pub cpu_borders: Vec<BorderPrimitiveCpu>,
     pub cpu_box_shadows: Vec<BoxShadowPrimitiveCpu>,
     pub cpu_lines: Vec<LinePrimitive>,
+
+    /// The GPU cache locations that interned primitives of the previous scene
+    /// occupied, keyed on their content hash. Consulted when primitives are
+    /// added, so that unchanged primitives keep their GPU cache blocks alive
+    /// instead of re-uploading them after every scene rebuild.
+    retained_gpu_locations: FastHashMap<u64, GpuCacheHandle>,
+
+    /// Shared gradient data tables in the GPU cache, keyed on a hash of
+    /// the stop list. Gradients with identical stops sample one table
+    /// instead of each uploading their own copy.
+    cached_gradient_tables: FastHashMap<u64, GpuCacheHandle>,
 }
 
 impl PrimitiveStore {
@@ -806,10 +1013,68 @@ impl PrimitiveStore {
             cpu_borders: Vec::new(),
             cpu_box_shadows: Vec::new(),
             cpu_lines: Vec::new(),
+            retained_gpu_locations: FastHashMap::default(),
+            cached_gradient_tables: FastHashMap::default(),
+        }
+    }
+
+    /// Collect the GPU cache locations of all interned primitives, keyed on
+    /// their content hash, so that an identical primitive in a replacement
+    /// store can take them over. See `seed_gpu_locations`.
+    pub fn harvest_gpu_locations(&self) -> FastHashMap<u64, GpuCacheHandle> {
+        let mut locations = FastHashMap::default();
+        for metadata in &self.cpu_metadata {
+            if let Some(key) = metadata.intern_key {
+                locations.insert(key, metadata.gpu_location);
+            }
+        }
+        locations
+    }
+
+    /// Install the locations harvested from the store that this one replaces.
+    /// Must be called before any primitives are added.
+    pub fn seed_gpu_locations(&mut self, locations: FastHashMap<u64, GpuCacheHandle>) {
+        debug_assert!(self.cpu_metadata.is_empty());
+        self.retained_gpu_locations = locations;
+    }
+
+    /// Collects the screen rect and content hash of every visible tagged
+    /// primitive, for the paint flashing debug view. Only valid once the
+    /// screen bounding rects have been computed for this frame.
+    pub fn collect_painted_tags(&self) -> Vec<PaintedTag> {
+        let mut painted_tags = Vec::new();
+        for (metadata, screen_rect) in self.cpu_metadata.iter()
+                                           .zip(self.cpu_bounding_rects.iter()) {
+            let (tag, screen_rect) = match (metadata.tag, *screen_rect) {
+                (Some(tag), Some(screen_rect)) => (tag, screen_rect),
+                _ => continue,
+            };
+
+            // Interned kinds already carry a content hash. The rest make do
+            // with their kind and local rect, which still catches the common
+            // case of an item changing size or position.
+            let content_hash = metadata.intern_key.unwrap_or_else(|| {
+                let mut hasher = FxHasher::default();
+                hasher.write_u32(metadata.prim_kind as u32);
+                hash_rect(&mut hasher, &metadata.local_rect);
+                hasher.finish()
+            });
+
+            painted_tags.push(PaintedTag {
+                tag,
+                content_hash,
+                screen_rect,
+            });
         }
+
+        painted_tags
     }
 
     pub fn recycle(self) -> Self {
+        // Harvest the GPU cache locations of the scene being replaced, so
+        // that unchanged primitives in the new scene can take them over.
+        let retained_gpu_locations = self.harvest_gpu_locations();
+
         PrimitiveStore {
             cpu_metadata: recycle_vec(self.cpu_metadata),
             cpu_rectangles: recycle_vec(self.cpu_rectangles),
@@ -823,18 +1088,37 @@ impl PrimitiveStore {
             cpu_borders: recycle_vec(self.cpu_borders),
             cpu_box_shadows: recycle_vec(self.cpu_box_shadows),
             cpu_lines: recycle_vec(self.cpu_lines),
+            retained_gpu_locations,
+            cached_gradient_tables: self.cached_gradient_tables,
         }
     }
 
+    /// Resolves the GPU cache address of the shared gradient data table
+    /// written for this key during prepare_prim_for_render.
+    pub fn gradient_data_address(&self, key: u64, gpu_cache: &GpuCache) -> i32 {
+        self.cached_gradient_tables[&key].as_int(gpu_cache)
+    }
+
     pub fn add_primitive(&mut self,
                          local_rect: &LayerRect,
                          local_clip_rect: &LayerRect,
                          clips: Vec<ClipSource>,
                          clip_info: Option<MaskCacheInfo>,
+                         tag: Option<ItemTag>,
+                         pixel_snapping: PixelSnapping,
                          container: PrimitiveContainer) -> PrimitiveIndex {
         let prim_index = self.cpu_metadata.len();
         self.cpu_bounding_rects.push(None);
 
+        // If an identical primitive existed in the previous scene, adopt its
+        // GPU cache location. The blocks it refers to are revalidated by the
+        // GPU cache when they are requested, so a stale location only costs
+        // us a fresh upload.
+        let intern_key = container.intern_key(local_rect, local_clip_rect);
+        let gpu_location = intern_key.and_then(|key| {
+            self.retained_gpu_locations.get(&key).cloned()
+        }).unwrap_or_else(GpuCacheHandle::new);
+
         let metadata = match container {
             PrimitiveContainer::Rectangle(rect) => {
                 let metadata = PrimitiveMetadata {
@@ -843,7 +1127,10 @@ impl PrimitiveStore {
                     clip_cache_info: clip_info,
                     prim_kind: PrimitiveKind::Rectangle,
                     cpu_prim_index: SpecificPrimitiveIndex(self.cpu_rectangles.len()),
-                    gpu_location: GpuCacheHandle::new(),
+                    gpu_location,
+                    intern_key,
+                    tag,
+                    pixel_snapping,
                     render_task: None,
                     clip_task: None,
                     local_rect: *local_rect,
@@ -861,7 +1148,10 @@ impl PrimitiveStore {
                     clip_cache_info: clip_info,
                     prim_kind: PrimitiveKind::Line,
                     cpu_prim_index: SpecificPrimitiveIndex(self.cpu_lines.len()),
-                    gpu_location: GpuCacheHandle::new(),
+                    gpu_location,
+                    intern_key,
+                    tag,
+                    pixel_snapping,
                     render_task: None,
                     clip_task: None,
                     local_rect: *local_rect,
@@ -878,7 +1168,10 @@ impl PrimitiveStore {
                     clip_cache_info: clip_info,
                     prim_kind: PrimitiveKind::TextRun,
                     cpu_prim_index: SpecificPrimitiveIndex(self.cpu_text_runs.len()),
-                    gpu_location: GpuCacheHandle::new(),
+                    gpu_location,
+                    intern_key,
+                    tag,
+                    pixel_snapping,
                     render_task: None,
                     clip_task: None,
                     local_rect: *local_rect,
@@ -895,7 +1188,10 @@ impl PrimitiveStore {
                     clip_cache_info: clip_info,
                     prim_kind: PrimitiveKind::TextShadow,
                     cpu_prim_index: SpecificPrimitiveIndex(self.cpu_text_shadows.len()),
-                    gpu_location: GpuCacheHandle::new(),
+                    gpu_location,
+                    intern_key,
+                    tag,
+                    pixel_snapping,
                     render_task: None,
                     clip_task: None,
                     local_rect: *local_rect,
@@ -912,7 +1208,10 @@ impl PrimitiveStore {
                     clip_cache_info: clip_info,
                     prim_kind: PrimitiveKind::Image,
                     cpu_prim_index: SpecificPrimitiveIndex(self.cpu_images.len()),
-                    gpu_location: GpuCacheHandle::new(),
+                    gpu_location,
+                    intern_key,
+                    tag,
+                    pixel_snapping,
                     render_task: None,
                     clip_task: None,
                     local_rect: *local_rect,
@@ -929,7 +1228,10 @@ impl PrimitiveStore {
                     clip_cache_info: clip_info,
                     prim_kind: PrimitiveKind::YuvImage,
                     cpu_prim_index: SpecificPrimitiveIndex(self.cpu_yuv_images.len()),
-                    gpu_location: GpuCacheHandle::new(),
+                    gpu_location,
+                    intern_key,
+                    tag,
+                    pixel_snapping,
                     render_task: None,
                     clip_task: None,
                     local_rect: *local_rect,
@@ -946,7 +1248,10 @@ impl PrimitiveStore {
                     clip_cache_info: clip_info,
                     prim_kind: PrimitiveKind::Border,
                     cpu_prim_index: SpecificPrimitiveIndex(self.cpu_borders.len()),
-                    gpu_location: GpuCacheHandle::new(),
+                    gpu_location,
+                    intern_key,
+                    tag,
+                    pixel_snapping,
                     render_task: None,
                     clip_task: None,
                     local_rect: *local_rect,
@@ -963,7 +1268,10 @@ impl PrimitiveStore {
                     clip_cache_info: clip_info,
                     prim_kind: PrimitiveKind::AlignedGradient,
                     cpu_prim_index: SpecificPrimitiveIndex(self.cpu_gradients.len()),
-                    gpu_location: GpuCacheHandle::new(),
+                    gpu_location,
+                    intern_key,
+                    tag,
+                    pixel_snapping,
                     render_task: None,
                     clip_task: None,
                     local_rect: *local_rect,
@@ -981,7 +1289,10 @@ impl PrimitiveStore {
                     clip_cache_info: clip_info,
                     prim_kind: PrimitiveKind::AngleGradient,
                     cpu_prim_index: SpecificPrimitiveIndex(self.cpu_gradients.len()),
-                    gpu_location: GpuCacheHandle::new(),
+                    gpu_location,
+                    intern_key,
+                    tag,
+                    pixel_snapping,
                     render_task: None,
                     clip_task: None,
                     local_rect: *local_rect,
@@ -999,7 +1310,10 @@ impl PrimitiveStore {
                     clip_cache_info: clip_info,
                     prim_kind: PrimitiveKind::RadialGradient,
                     cpu_prim_index: SpecificPrimitiveIndex(self.cpu_radial_gradients.len()),
-                    gpu_location: GpuCacheHandle::new(),
+                    gpu_location,
+                    intern_key,
+                    tag,
+                    pixel_snapping,
                     render_task: None,
                     clip_task: None,
                     local_rect: *local_rect,
@@ -1041,7 +1355,10 @@ impl PrimitiveStore {
                     clip_cache_info: clip_info,
                     prim_kind: PrimitiveKind::BoxShadow,
                     cpu_prim_index: SpecificPrimitiveIndex(self.cpu_box_shadows.len()),
-                    gpu_location: GpuCacheHandle::new(),
+                    gpu_location,
+                    intern_key,
+                    tag,
+                    pixel_snapping,
                     render_task: Some(render_task),
                     clip_task: None,
                     local_rect: *local_rect,
@@ -1062,6 +1379,37 @@ impl PrimitiveStore {
         &self.cpu_metadata[index.0]
     }
 
+    /// Fold the opacity of a collapsed stacking context into the primitive
+    /// itself. Returns false if the primitive kind has nowhere to store the
+    /// opacity, in which case the caller must keep the composite operation.
+    pub fn collapse_opacity(&mut self, prim_index: PrimitiveIndex, opacity: f32) -> bool {
+        {
+            let metadata = &self.cpu_metadata[prim_index.0];
+            match metadata.prim_kind {
+                PrimitiveKind::Rectangle => {
+                    self.cpu_rectangles[metadata.cpu_prim_index.0].color.a *= opacity;
+                }
+                PrimitiveKind::Image => {
+                    // The last image block holds the modulation color.
+                    self.cpu_images[metadata.cpu_prim_index.0].gpu_blocks[2].data[3] *= opacity;
+                }
+                _ => return false,
+            }
+        }
+
+        let metadata = &mut self.cpu_metadata[prim_index.0];
+        metadata.opacity.accumulate(opacity);
+        // Mix the folded opacity into the intern key, so that the primitive
+        // only matches entries that were collapsed the same way.
+        metadata.intern_key = metadata.intern_key.map(|key| {
+            let mut hasher = FxHasher::default();
+            hasher.write_u64(key);
+            hash_f32(&mut hasher, opacity);
+            hasher.finish()
+        });
+        true
+    }
+
     pub fn prim_count(&self) -> usize {
         self.cpu_metadata.len()
     }
@@ -1096,7 +1444,8 @@ impl PrimitiveStore {
                                    layer_transform: &LayerToWorldTransform,
                                    device_pixel_ratio: f32,
                                    display_list: &BuiltDisplayList,
-                                   text_run_mode: TextRunMode)
+                                   text_run_mode: TextRunMode,
+                                   quality: &QualitySettings)
                                    -> &mut PrimitiveMetadata {
         let (prim_kind, cpu_prim_index) = {
             let metadata = &self.cpu_metadata[prim_index.0];
@@ -1116,7 +1465,8 @@ impl PrimitiveStore {
                                              layer_transform,
                                              device_pixel_ratio,
                                              display_list,
-                                             TextRunMode::Shadow);
+                                             TextRunMode::Shadow,
+                                             quality);
             }
         }
 
@@ -1130,7 +1480,7 @@ impl PrimitiveStore {
 
             for clip in &metadata.clips {
                 if let ClipSource::Region(ClipRegion{ image_mask: Some(ref mask), .. }, ..) = *clip {
-                    resource_cache.request_image(mask.image, ImageRendering::Auto, None);
+                    resource_cache.request_image(mask.image, ImageRendering::Auto, None, None);
                 }
             }
         }
@@ -1165,8 +1515,13 @@ impl PrimitiveStore {
                 let cache_height = (metadata.local_rect.size.height * device_pixel_ratio).ceil() as i32;
                 let cache_size = DeviceIntSize::new(cache_width, cache_height);
                 let cache_key = PrimitiveCacheKey::TextShadow(prim_index);
-                let blur_radius = device_length(shadow.shadow.blur_radius,
-                                                device_pixel_ratio);
+                let mut blur_radius = device_length(shadow.shadow.blur_radius,
+                                                    device_pixel_ratio);
+                if let Some(max_blur_radius) = quality.max_blur_radius {
+                    // Large blurs are expensive - when the quality settings
+                    // cap the radius, clamp it rather than skip the blur.
+                    blur_radius.0 = cmp::min(blur_radius.0, max_blur_radius);
+                }
                 metadata.render_task = Some(RenderTask::new_blur(cache_key,
                                                                  cache_size,
                                                                  blur_radius,
@@ -1184,7 +1539,18 @@ impl PrimitiveStore {
 
                 match image_cpu.kind {
                     ImagePrimitiveKind::Image(image_key, image_rendering, tile_offset, tile_spacing) => {
-                        resource_cache.request_image(image_key, image_rendering, tile_offset);
+                        // Hint the resource cache with the size the image is
+                        // displayed at, so that huge images can be uploaded
+                        // at a reduced size. This doesn't account for any
+                        // scale in the transform, but neither does the
+                        // device pixel ratio.
+                        let display_size = DeviceUintSize::new(
+                            (metadata.local_rect.size.width * device_pixel_ratio).ceil() as u32,
+                            (metadata.local_rect.size.height * device_pixel_ratio).ceil() as u32);
+                        resource_cache.request_image(image_key,
+                                                     image_rendering,
+                                                     tile_offset,
+                                                     Some(display_size));
 
                         // TODO(gw): This doesn't actually need to be calculated each frame.
                         // It's cheap enough that it's not worth introducing a cache for images
@@ -1196,6 +1562,16 @@ impl PrimitiveStore {
                                                      tile_spacing.height == 0.0;
                     }
                     ImagePrimitiveKind::WebGL(..) => {}
+                    ImagePrimitiveKind::Path(ref shape, ref mut cache_size) => {
+                        // The mask is rasterized at the size the path is
+                        // displayed at. Like the text shadow cache rects
+                        // above, this tracks the device pixel ratio but
+                        // not any scale in the transform.
+                        *cache_size = DeviceUintSize::new(
+                            (metadata.local_rect.size.width * device_pixel_ratio).ceil() as u32,
+                            (metadata.local_rect.size.height * device_pixel_ratio).ceil() as u32);
+                        resource_cache.request_path(shape, *cache_size, device_pixel_ratio);
+                    }
                 }
             }
             PrimitiveKind::YuvImage => {
@@ -1204,12 +1580,41 @@ impl PrimitiveStore {
                 let channel_num = image_cpu.format.get_plane_num();
                 debug_assert!(channel_num <= 3);
                 for channel in 0..channel_num {
-                    resource_cache.request_image(image_cpu.yuv_key[channel], image_cpu.image_rendering, None);
+                    resource_cache.request_image(image_cpu.yuv_key[channel], image_cpu.image_rendering, None, None);
+                }
+            }
+            PrimitiveKind::AlignedGradient => {}
+            PrimitiveKind::AngleGradient => {
+                let gradient = &mut self.cpu_gradients[cpu_prim_index.0];
+                let builder = GradientGpuBlockBuilder::new(gradient.stops_range,
+                                                           display_list,
+                                                           gradient.interpolation);
+                gradient.gradient_data_key = builder.data_key(gradient.reverse_stops);
+
+                // Request the shared gradient data table this primitive
+                // samples, building it if no gradient with the same stops
+                // has uploaded it already.
+                let handle = self.cached_gradient_tables
+                                 .entry(gradient.gradient_data_key)
+                                 .or_insert_with(GpuCacheHandle::new);
+                if let Some(mut request) = gpu_cache.request(handle) {
+                    builder.build(gradient.reverse_stops, &mut request);
+                }
+            }
+            PrimitiveKind::RadialGradient => {
+                let gradient = &mut self.cpu_radial_gradients[cpu_prim_index.0];
+                let builder = GradientGpuBlockBuilder::new(gradient.stops_range,
+                                                           display_list,
+                                                           gradient.interpolation);
+                gradient.gradient_data_key = builder.data_key(false);
+
+                let handle = self.cached_gradient_tables
+                                 .entry(gradient.gradient_data_key)
+                                 .or_insert_with(GpuCacheHandle::new);
+                if let Some(mut request) = gpu_cache.request(handle) {
+                    builder.build(false, &mut request);
                 }
             }
-            PrimitiveKind::AlignedGradient |
-            PrimitiveKind::AngleGradient |
-            PrimitiveKind::RadialGradient => {}
         }
 
         // Mark this GPU resource as required for this frame.
@@ -1249,13 +1654,11 @@ impl PrimitiveStore {
                 }
                 PrimitiveKind::AngleGradient => {
                     let gradient = &self.cpu_gradients[cpu_prim_index.0];
-                    gradient.build_gpu_blocks_for_angle_radial(display_list,
-                                                               request);
+                    request.extend_from_slice(&gradient.gpu_blocks);
                 }
                 PrimitiveKind::RadialGradient => {
                     let gradient = &self.cpu_radial_gradients[cpu_prim_index.0];
-                    gradient.build_gpu_blocks_for_angle_radial(display_list,
-                                                               request);
+                    request.extend_from_slice(&gradient.gpu_blocks);
                 }
                 PrimitiveKind::TextRun => {
                     let text = &self.cpu_text_runs[cpu_prim_index.0];
@@ -1285,6 +1688,12 @@ trait InsideTest<T> {
 impl InsideTest<ComplexClipRegion> for ComplexClipRegion {
     // Returns true if clip is inside self, can return false negative
     fn might_contain(&self, clip: &ComplexClipRegion) -> bool {
+        // Containment only makes sense between two regular clips; a
+        // clip-out region keeps the outside of its rect instead.
+        if self.mode != ClipMode::Clip || clip.mode != ClipMode::Clip {
+            return false;
+        }
+
         let delta_left = clip.rect.origin.x - self.rect.origin.x;
         let delta_top = clip.rect.origin.y - self.rect.origin.y;
         let delta_right = self.rect.max_x() - clip.rect.max_x();
@@ -8,7 +8,7 @@ use euclid::{Point2D, Size2D, Rect, vec2};
 use std::collections::vec_deque::VecDeque;
 use std::f32;
 use std::mem;
-use api::{ColorF, ColorU};
+use api::{ColorF, ColorU, PipelineId};
 use time::precise_time_ns;
 
 const GRAPH_WIDTH: f32 = 1024.0;
@@ -289,6 +289,36 @@ impl FrameProfileCounters {
     }
 }
 
+/// Frame building statistics attributed to a single pipeline, so
+/// compositor cost can be traced back to individual iframes or pieces
+/// of browser UI.
+#[derive(Clone, Debug)]
+pub struct PipelineProfile {
+    pub pipeline: PipelineId,
+    /// Primitives of this pipeline that survived culling.
+    pub primitives: usize,
+    /// Instances the pipeline's primitives contributed to alpha batches,
+    /// across all render targets.
+    pub batch_instances: usize,
+    /// Render tasks (clip masks, blurs, shadows) owned by the pipeline's
+    /// primitives.
+    pub render_tasks: usize,
+    /// GPU cache blocks currently occupied by the pipeline's primitives.
+    pub gpu_cache_blocks: usize,
+}
+
+impl PipelineProfile {
+    pub fn new(pipeline: PipelineId) -> PipelineProfile {
+        PipelineProfile {
+            pipeline,
+            primitives: 0,
+            batch_instances: 0,
+            render_tasks: 0,
+            gpu_cache_blocks: 0,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct TextureCacheProfileCounters {
     pub pages_a8: ResourceProfileCounter,
@@ -312,6 +342,7 @@ impl TextureCacheProfileCounters {
 pub struct GpuCacheProfileCounters {
     pub allocated_rows: IntProfileCounter,
     pub allocated_blocks: IntProfileCounter,
+    pub freed_bytes: IntProfileCounter,
 }
 
 impl GpuCacheProfileCounters {
@@ -319,6 +350,7 @@ impl GpuCacheProfileCounters {
         GpuCacheProfileCounters {
             allocated_rows: IntProfileCounter::new("GPU cache rows"),
             allocated_blocks: IntProfileCounter::new("GPU cache blocks"),
+            freed_bytes: IntProfileCounter::new("GPU cache freed bytes"),
         }
     }
 }
@@ -400,8 +432,14 @@ pub struct RendererProfileCounters {
     pub frame_counter: IntProfileCounter,
     pub frame_time: AverageTimeProfileCounter,
     pub draw_calls: IntProfileCounter,
+    pub fbo_binds: IntProfileCounter,
     pub vertices: IntProfileCounter,
     pub vao_count_and_size: ResourceProfileCounter,
+    pub texture_upload_kb: IntProfileCounter,
+    pub deferred_uploads: IntProfileCounter,
+    pub gpu_cache_dirty_rows: IntProfileCounter,
+    pub gpu_cache_block_updates: IntProfileCounter,
+    pub gpu_cache_upload_kb: IntProfileCounter,
 }
 
 pub struct RendererProfileTimers {
@@ -416,14 +454,26 @@ impl RendererProfileCounters {
             frame_counter: IntProfileCounter::new("Frame"),
             frame_time: AverageTimeProfileCounter::new("FPS", true, ONE_SECOND_NS / 2),
             draw_calls: IntProfileCounter::new("Draw Calls"),
+            fbo_binds: IntProfileCounter::new("FBO Binds"),
             vertices: IntProfileCounter::new("Vertices"),
             vao_count_and_size: ResourceProfileCounter::new("VAO"),
+            texture_upload_kb: IntProfileCounter::new("Texture uploads (kb)"),
+            deferred_uploads: IntProfileCounter::new("Deferred uploads"),
+            gpu_cache_dirty_rows: IntProfileCounter::new("GPU cache dirty rows"),
+            gpu_cache_block_updates: IntProfileCounter::new("GPU cache block updates"),
+            gpu_cache_upload_kb: IntProfileCounter::new("GPU cache uploads (kb)"),
         }
     }
 
     pub fn reset(&mut self) {
         self.draw_calls.reset();
+        self.fbo_binds.reset();
         self.vertices.reset();
+        self.texture_upload_kb.reset();
+        self.deferred_uploads.reset();
+        self.gpu_cache_dirty_rows.reset();
+        self.gpu_cache_block_updates.reset();
+        self.gpu_cache_upload_kb.reset();
     }
 }
 
@@ -559,6 +609,144 @@ impl ProfileGraph {
 
         rect
     }
+
+    /// Draws just the colored sample bars into the given box, without the
+    /// background and statistics of `draw_graph`. Used by the compact
+    /// profiler, where space is at a premium. Bars are colored by whether
+    /// the sample fits in a 60Hz or 30Hz frame.
+    fn draw_sparkline(&self,
+                      x: f32,
+                      y: f32,
+                      width: f32,
+                      height: f32,
+                      debug_renderer: &mut DebugRenderer) {
+        let stats = self.stats();
+        let w = width / self.max_samples as f32;
+
+        let color_t0 = ColorU::new(0, 255, 0, 255);
+        let color_b0 = ColorU::new(0, 180, 0, 255);
+
+        let color_t1 = ColorU::new(255, 255, 0, 255);
+        let color_b1 = ColorU::new(180, 180, 0, 255);
+
+        let color_t2 = ColorU::new(255, 0, 0, 255);
+        let color_b2 = ColorU::new(180, 0, 0, 255);
+
+        for (index, sample) in self.values.iter().enumerate() {
+            let sample = *sample;
+            let x1 = x + width - index as f32 * w;
+            let x0 = x1 - w;
+
+            let y0 = y + height - (sample / stats.max_value) * height;
+
+            let (color_top, color_bottom) = if sample < 1000.0 / 60.0 {
+                (color_t0, color_b0)
+            } else if sample < 1000.0 / 30.0 {
+                (color_t1, color_b1)
+            } else {
+                (color_t2, color_b2)
+            };
+
+            debug_renderer.add_quad(x0, y0, x1, y + height, color_top, color_bottom);
+        }
+    }
+}
+
+/// A scrolling bar graph of recent frame times, colored by whether each
+/// frame fit in the vsync budget: green within budget, yellow within two
+/// budgets, red beyond that. Makes dropped frames stand out during manual
+/// testing in a way the line graphs don't.
+struct FrameBarGraph {
+    budget_ns: u64,
+    max_samples: usize,
+    frames: VecDeque<u64>,
+}
+
+impl FrameBarGraph {
+    fn new(max_samples: usize, budget_ns: u64) -> FrameBarGraph {
+        FrameBarGraph {
+            budget_ns,
+            max_samples,
+            frames: VecDeque::new(),
+        }
+    }
+
+    fn set_budget(&mut self, budget_ns: u64) {
+        self.budget_ns = budget_ns;
+    }
+
+    fn push(&mut self, total_ns: u64) {
+        if self.frames.len() == self.max_samples {
+            self.frames.pop_back();
+        }
+        self.frames.push_front(total_ns);
+    }
+
+    fn draw(&self,
+            x: f32,
+            y: f32,
+            debug_renderer: &mut DebugRenderer) -> Rect<f32> {
+        let size = Size2D::new(600.0, 50.0);
+        let mut rect = Rect::new(Point2D::new(x, y), size);
+
+        let text_origin = rect.origin + vec2(rect.size.width, 20.0);
+        debug_renderer.add_text(text_origin.x,
+                                text_origin.y,
+                                "Frame budget",
+                                ColorU::new(0, 255, 0, 255));
+        debug_renderer.add_text(text_origin.x,
+                                text_origin.y + debug_renderer.line_height(),
+                                &format!("{:.1} ms", self.budget_ns as f32 / 1000000.0),
+                                ColorU::new(255, 255, 0, 255));
+
+        rect.size.width += 140.0;
+        debug_renderer.add_quad(rect.origin.x,
+                                rect.origin.y,
+                                rect.origin.x + rect.size.width + 10.0,
+                                rect.origin.y + rect.size.height,
+                                ColorF::new(0.1, 0.1, 0.1, 0.8).into(),
+                                ColorF::new(0.2, 0.2, 0.2, 0.8).into());
+
+        let bx0 = x + 10.0;
+        let by0 = y + 10.0;
+        let bx1 = bx0 + size.width - 20.0;
+        let by1 = by0 + size.height - 20.0;
+
+        let w = (bx1 - bx0) / self.max_samples as f32;
+        let h = by1 - by0;
+
+        let color_t0 = ColorU::new(0, 255, 0, 255);
+        let color_b0 = ColorU::new(0, 180, 0, 255);
+
+        let color_t1 = ColorU::new(255, 255, 0, 255);
+        let color_b1 = ColorU::new(180, 180, 0, 255);
+
+        let color_t2 = ColorU::new(255, 0, 0, 255);
+        let color_b2 = ColorU::new(180, 0, 0, 255);
+
+        for (index, total_ns) in self.frames.iter().enumerate() {
+            let total_ns = *total_ns;
+            let x1 = bx1 - index as f32 * w;
+            let x0 = x1 - w;
+
+            // The bar fills the graph at two budgets, by which point the
+            // frame has unambiguously been dropped.
+            let fraction = (total_ns as f32 / self.budget_ns as f32).min(2.0);
+            let y0 = by1 - 0.5 * fraction * h;
+
+            let (color_top, color_bottom) = if total_ns <= self.budget_ns {
+                (color_t0, color_b0)
+            } else if total_ns <= self.budget_ns * 2 {
+                (color_t1, color_b1)
+            } else {
+                (color_t2, color_b2)
+            };
+
+            debug_renderer.add_quad(x0, y0, x1, by1, color_top, color_bottom);
+        }
+
+        rect
+    }
 }
 
 struct GpuFrame {
@@ -651,10 +839,12 @@ pub struct Profiler {
     gpu_time: ProfileGraph,
     gpu_frames: GpuFrameCollection,
     ipc_time: ProfileGraph,
+    frame_bars: FrameBarGraph,
+    frame_time: ProfileGraph,
 }
 
 impl Profiler {
-    pub fn new() -> Profiler {
+    pub fn new(frame_budget_ns: u64) -> Profiler {
         Profiler {
             x_left: 0.0,
             y_left: 0.0,
@@ -665,9 +855,17 @@ impl Profiler {
             gpu_time: ProfileGraph::new(600),
             gpu_frames: GpuFrameCollection::new(),
             ipc_time: ProfileGraph::new(600),
+            frame_bars: FrameBarGraph::new(600, frame_budget_ns),
+            frame_time: ProfileGraph::new(120),
         }
     }
 
+    /// Changes the budget the frame bar graph colors against, e.g. to
+    /// 8.3ms when targeting a 120Hz display.
+    pub fn set_frame_budget(&mut self, budget_ns: u64) {
+        self.frame_bars.set_budget(budget_ns);
+    }
+
     fn draw_counters(&mut self,
                      counters: &[&ProfileCounter],
                      debug_renderer: &mut DebugRenderer,
@@ -766,6 +964,7 @@ impl Profiler {
             &frame_profile.alpha_targets,
             &backend_profile.resources.gpu_cache.allocated_rows,
             &backend_profile.resources.gpu_cache.allocated_blocks,
+            &backend_profile.resources.gpu_cache.freed_bytes,
         ], debug_renderer, true);
 
         self.draw_counters(&[
@@ -790,7 +989,13 @@ impl Profiler {
 
         self.draw_counters(&[
             &renderer_profile.draw_calls,
+            &renderer_profile.fbo_binds,
             &renderer_profile.vertices,
+            &renderer_profile.texture_upload_kb,
+            &renderer_profile.deferred_uploads,
+            &renderer_profile.gpu_cache_dirty_rows,
+            &renderer_profile.gpu_cache_block_updates,
+            &renderer_profile.gpu_cache_upload_kb,
         ], debug_renderer, true);
 
         self.draw_counters(&[
@@ -804,6 +1009,10 @@ impl Profiler {
         self.ipc_time.push(backend_profile.ipc.total_time.nanoseconds);
         self.gpu_time.push(gpu_time);
         self.gpu_frames.push(gpu_time, gpu_samples);
+        // The compositor CPU time and the GPU time are both on the critical
+        // path to the next vblank, so their sum is what has to fit in the
+        // budget.
+        self.frame_bars.push(renderer_timers.cpu_time.nanoseconds + gpu_time);
 
 
         let rect = self.backend_time.draw_graph(self.x_left, self.y_left, "CPU (backend)", debug_renderer);
@@ -818,5 +1027,62 @@ impl Profiler {
                                         self.y_left,
                                         debug_renderer);
         self.y_left += rect.size.height + PROFILE_PADDING;
+        let rect = self.frame_bars.draw(self.x_left,
+                                        self.y_left,
+                                        debug_renderer);
+        self.y_left += rect.size.height + PROFILE_PADDING;
+    }
+
+    /// Draws a minimal overlay - FPS, a frame time sparkline and the draw
+    /// call count - in the top left corner. Cheap and small enough to be
+    /// left enabled during development, unlike the full profiler.
+    pub fn draw_compact_profile(&mut self,
+                                device: &mut Device,
+                                frame_time_ns: u64,
+                                renderer_profile: &RendererProfileCounters,
+                                debug_renderer: &mut DebugRenderer) {
+        let _gm = GpuMarker::new(device.rc_gl(), "compact profile");
+
+        self.frame_time.push(frame_time_ns);
+
+        // Show the averaged frame time in the readout, so it is stable
+        // enough to read; the sparkline shows the per-frame variation.
+        let avg_ns = if renderer_profile.frame_time.nanoseconds > 0 {
+            renderer_profile.frame_time.nanoseconds
+        } else {
+            frame_time_ns
+        };
+        let fps = if avg_ns > 0 {
+            1000000000.0 / avg_ns as f64
+        } else {
+            0.0
+        };
+
+        let x0 = 20.0;
+        let y0 = 20.0;
+        let width = 220.0;
+        let line_height = debug_renderer.line_height();
+
+        debug_renderer.add_quad(x0,
+                                y0,
+                                x0 + width,
+                                y0 + line_height * 2.0 + 44.0,
+                                ColorF::new(0.1, 0.1, 0.1, 0.8).into(),
+                                ColorF::new(0.2, 0.2, 0.2, 0.8).into());
+        debug_renderer.add_text(x0 + 10.0,
+                                y0 + line_height,
+                                &format!("{:.1} fps ({:.2} ms)",
+                                         fps,
+                                         avg_ns as f64 / 1000000.0),
+                                ColorU::new(255, 255, 0, 255));
+        debug_renderer.add_text(x0 + 10.0,
+                                y0 + line_height * 2.0,
+                                &format!("{} draw calls", renderer_profile.draw_calls.get()),
+                                ColorU::new(255, 255, 255, 255));
+        self.frame_time.draw_sparkline(x0 + 10.0,
+                                       y0 + line_height * 2.0 + 10.0,
+                                       width - 20.0,
+                                       24.0,
+                                       debug_renderer);
     }
 }
@@ -4,15 +4,29 @@
 
 use bincode::{Infinite, serialize};
 use std::fmt::Debug;
-use std::mem;
-use std::any::TypeId;
 use std::fs::File;
 use std::io::Write;
 use std::path::PathBuf;
-use api::ApiMsg;
+use api::{ApiMsg, DocumentMsg};
 use byteorder::{LittleEndian, WriteBytesExt};
+use time::precise_time_ns;
 
-pub static WEBRENDER_RECORDING_HEADER: u64 = 0xbeefbeefbeefbe01u64;
+/// Recordings start with this header. The current (v2) format prefixes
+/// every record with a nanosecond timestamp relative to the start of the
+/// recording, so a recording can be replayed at its original pacing.
+pub static WEBRENDER_RECORDING_HEADER: u64 = 0xbeefbeefbeefbe02u64;
+
+/// The previous format, identical except that records carry no timestamps.
+/// The player still accepts it, but such recordings can only be replayed
+/// as fast as possible.
+pub static WEBRENDER_RECORDING_HEADER_V1: u64 = 0xbeefbeefbeefbe01u64;
+
+/// The header is followed by this message format discriminant. Recorded
+/// messages are bincode-serialized `ApiMsg` values with no versioning of
+/// their own, so bump this whenever `ApiMsg` or anything it contains
+/// changes serialized layout; the player refuses recordings whose
+/// discriminant doesn't match.
+pub static WEBRENDER_RECORDING_MSG_FORMAT: u64 = 1;
 
 pub trait ApiRecordingReceiver: Send + Debug {
     fn write_msg(&mut self, frame: u32, msg: &ApiMsg);
@@ -22,6 +36,7 @@ pub trait ApiRecordingReceiver: Send + Debug {
 #[derive(Debug)]
 pub struct BinaryRecorder {
     file: File,
+    start: u64,
 }
 
 impl BinaryRecorder {
@@ -29,18 +44,19 @@ impl BinaryRecorder {
         let mut file = File::create(dest).unwrap();
 
         // write the header
-        let apimsg_type_id = unsafe {
-            assert!(mem::size_of::<TypeId>() == mem::size_of::<u64>());
-            mem::transmute::<TypeId, u64>(TypeId::of::<ApiMsg>())
-        };
         file.write_u64::<LittleEndian>(WEBRENDER_RECORDING_HEADER).ok();
-        file.write_u64::<LittleEndian>(apimsg_type_id).ok();
+        file.write_u64::<LittleEndian>(WEBRENDER_RECORDING_MSG_FORMAT).ok();
 
         BinaryRecorder {
             file,
+            start: precise_time_ns(),
         }
     }
 
+    fn write_timestamp(&mut self) {
+        self.file.write_u64::<LittleEndian>(precise_time_ns() - self.start).ok();
+    }
+
     fn write_length_and_data(&mut self, data: &[u8]) {
         self.file.write_u32::<LittleEndian>(data.len() as u32).ok();
         self.file.write(data).ok();
@@ -51,12 +67,14 @@ impl ApiRecordingReceiver for BinaryRecorder {
     fn write_msg(&mut self, _: u32, msg: &ApiMsg) {
         if should_record_msg(msg) {
             let buf = serialize(msg, Infinite).unwrap();
+            self.write_timestamp();
             self.write_length_and_data(&buf);
         }
     }
 
     fn write_payload(&mut self, _: u32, data: &[u8]) {
         // signal payload with a 0 length
+        self.write_timestamp();
         self.file.write_u32::<LittleEndian>(0).ok();
         self.write_length_and_data(data);
     }
@@ -66,10 +84,23 @@ pub fn should_record_msg(msg: &ApiMsg) -> bool {
     match *msg {
         ApiMsg::UpdateResources(..) |
         ApiMsg::AddDocument{..} |
-        ApiMsg::UpdateDocument(..) |
         ApiMsg::DeleteDocument(..) |
         ApiMsg::WebGLCommand(..) =>
             true,
+        ApiMsg::UpdateDocument(_, ref msg, _) => should_record_document_msg(msg),
         _ => false
     }
 }
+
+/// Document messages are recorded when they change document state. That
+/// includes the scroll and animation property updates, so a captured user
+/// session replays with its original input and can serve as a benchmark.
+/// Queries carrying reply channels are skipped: they don't affect
+/// rendering and would go unanswered on replay.
+pub fn should_record_document_msg(msg: &DocumentMsg) -> bool {
+    match *msg {
+        DocumentMsg::GetScrollNodeState(..) |
+        DocumentMsg::HitTest(..) => false,
+        _ => true,
+    }
+}
@@ -2,27 +2,37 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use display_list_validator;
 use frame::Frame;
 use frame_builder::FrameBuilderConfig;
 use gpu_cache::GpuCache;
-use internal_types::{FastHashMap, SourceTexture, ResultMsg, RendererFrame};
+use internal_types::{FastHashMap, QualitySettings, SourceTexture, ResultMsg, RendererFrame};
 use profiler::{BackendProfileCounters, ResourceProfileCounters};
 use record::ApiRecordingReceiver;
 use resource_cache::ResourceCache;
 use scene::Scene;
+use scene_builder::{BuiltScene, SceneRequest};
+use std::collections::VecDeque;
+use std::mem;
 use std::sync::{Arc, Mutex};
-use std::sync::mpsc::Sender;
+use std::sync::mpsc::{Receiver, Sender};
 use std::u32;
 use texture_cache::TextureCache;
 use time::precise_time_ns;
 use thread_profiler::register_thread_with_profiler;
+#[cfg(unix)]
+use libc;
 use rayon::ThreadPool;
+use rayon::Configuration as ThreadPoolConfig;
 use webgl_types::{GLContextHandleWrapper, GLContextWrapper};
 use api::channel::{MsgReceiver, PayloadReceiver, PayloadReceiverHelperMethods};
 use api::channel::{PayloadSender, PayloadSenderHelperMethods};
 use api::{ApiMsg, BlobImageRenderer, BuiltDisplayList, DeviceIntPoint};
 use api::{DeviceUintPoint, DeviceUintRect, DeviceUintSize, DocumentId, DocumentMsg};
-use api::{IdNamespace, LayerPoint, RenderDispatcher, RenderNotifier};
+use api::DocumentPriority;
+use api::{IdNamespace, LayerPoint, MemoryPressureLevel, OutputColorTransform};
+use api::{RenderDispatcher, RenderNotifier, SchedulingPolicy};
+use api::TransactionId;
 use api::{VRCompositorCommand, VRCompositorHandler, WebGLCommand, WebGLContextId};
 
 #[cfg(feature = "webgl")]
@@ -31,6 +41,135 @@ use offscreen_gl_context::GLContextDispatcher;
 #[cfg(not(feature = "webgl"))]
 use webgl_types::GLContextDispatcher;
 
+/// How long the backend works on deferred low-priority messages before
+/// checking for newly arrived (high-priority) messages again.
+const LOW_PRIORITY_WORK_BUDGET_NS: u64 = 4 * 1000 * 1000;
+
+/// How the internally built worker thread pool is configured. Captured at
+/// startup so the pool can be rebuilt with the same settings when it is
+/// resized at runtime.
+#[derive(Clone, Copy, Debug)]
+pub struct WorkerPoolSettings {
+    /// Stack size of the worker threads, in bytes. `None` uses the
+    /// platform default.
+    pub stack_size: Option<usize>,
+    /// Whether the worker threads run at a lower scheduling priority, on
+    /// a best-effort, per-platform basis.
+    pub low_priority: bool,
+}
+
+/// Builds a worker thread pool with the given thread count (`None` uses
+/// one thread per logical CPU) and settings.
+pub fn new_worker_pool(num_threads: Option<usize>,
+                       settings: &WorkerPoolSettings) -> Arc<ThreadPool> {
+    let low_priority = settings.low_priority;
+    let mut config = ThreadPoolConfig::new()
+        .thread_name(|idx|{ format!("WebRender:Worker#{}", idx) })
+        .start_handler(move |idx| {
+            register_thread_with_profiler(format!("WebRender:Worker#{}", idx));
+            if low_priority {
+                lower_thread_priority();
+            }
+        });
+    if let Some(num_threads) = num_threads {
+        config = config.num_threads(num_threads);
+    }
+    if let Some(stack_size) = settings.stack_size {
+        config = config.stack_size(stack_size);
+    }
+    Arc::new(ThreadPool::new(config).unwrap())
+}
+
+/// Lower the calling thread's scheduling priority. Best effort: platforms
+/// without an implementation leave the priority unchanged.
+#[cfg(unix)]
+fn lower_thread_priority() {
+    // Positive nice values lower the priority. Failure (e.g. because the
+    // limit has been reached) leaves the thread at its current priority.
+    unsafe {
+        libc::nice(10);
+    }
+}
+
+#[cfg(not(unix))]
+fn lower_thread_priority() {
+}
+
+/// Logs one processed document message, so Gecko-side transactions can be
+/// correlated with the compositor work they caused. Compiled out unless
+/// the `message-tracing` feature is enabled.
+#[cfg(feature = "message-tracing")]
+fn trace_transaction(transaction_id: TransactionId,
+                     msg_name: &'static str,
+                     document_id: DocumentId,
+                     time_ns: u64,
+                     frame_counter: u32) {
+    info!(target: "wr-trace",
+          "transaction={} msg={} document={:?} time_ns={} frame={}",
+          transaction_id.0, msg_name, document_id, time_ns, frame_counter);
+}
+
+#[cfg(not(feature = "message-tracing"))]
+fn trace_transaction(_: TransactionId, _: &'static str, _: DocumentId, _: u64, _: u32) {
+}
+
+/// Per-document frame scheduling state. Applies the document's
+/// `SchedulingPolicy` to scroll and animation updates, and additionally
+/// suppresses scroll-driven rendering between `SetDisplayList` and the
+/// first `GenerateFrame` under any policy. If we allowed those, a reftest
+/// that scrolls a few layers before generating the first frame would
+/// produce inconsistent rendering results, because scroll events are not
+/// necessarily received in deterministic order.
+struct FrameScheduler {
+    policy: SchedulingPolicy,
+    waiting_for_first_frame: bool,
+    last_scroll_render_ns: u64,
+}
+
+impl FrameScheduler {
+    fn new(policy: SchedulingPolicy) -> FrameScheduler {
+        FrameScheduler {
+            policy,
+            waiting_for_first_frame: true,
+            last_scroll_render_ns: 0,
+        }
+    }
+
+    fn set_policy(&mut self, policy: SchedulingPolicy) {
+        self.policy = policy;
+    }
+
+    fn on_display_list(&mut self) {
+        self.waiting_for_first_frame = true;
+    }
+
+    fn on_generate_frame(&mut self) {
+        self.waiting_for_first_frame = false;
+    }
+
+    /// Whether a scroll or animation update that changed state should
+    /// render a new frame now, or let the last rendered frame be
+    /// re-presented instead.
+    fn should_render_scroll_update(&mut self) -> bool {
+        if self.waiting_for_first_frame {
+            return false;
+        }
+        match self.policy {
+            SchedulingPolicy::Synchronous => true,
+            SchedulingPolicy::OnDemand => false,
+            SchedulingPolicy::Throttled { min_frame_interval_ns } => {
+                let now = precise_time_ns();
+                if now - self.last_scroll_render_ns >= min_frame_interval_ns {
+                    self.last_scroll_render_ns = now;
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+}
+
 struct Document {
     scene: Scene,
     frame: Frame,
@@ -39,25 +178,35 @@ struct Document {
     pan: DeviceIntPoint,
     page_zoom_factor: f32,
     pinch_zoom_factor: f32,
-    // A helper switch to prevent any frames rendering triggered by scrolling
-    // messages between `SetDisplayList` and `GenerateFrame`.
-    // If we allow them, then a reftest that scrolls a few layers before generating
-    // the first frame would produce inconsistent rendering results, because
-    // scroll events are not necessarily received in deterministic order.
-    render_on_scroll: Option<bool>,
+    // Compositor-driven zoom, applied as a pre-transform on the root
+    // layer at frame build time. Unlike the pinch zoom factor it does
+    // not affect the scale content is rasterized at, so updating it
+    // never triggers a scene build.
+    async_zoom_factor: f32,
+    // Color transform applied when this document is composited to the
+    // framebuffer; see `RenderApi::set_output_color_transform`.
+    output_color_transform: Option<OutputColorTransform>,
+    // Decides whether scroll and animation updates render a new frame
+    // or re-present the last one; see `SchedulingPolicy`.
+    scheduler: FrameScheduler,
+    // How eagerly this document's messages are serviced relative to other
+    // documents; see `process_low_priority_queue`.
+    priority: DocumentPriority,
+    // The number of scenes for this document that are currently being built
+    // on the scene builder thread.
+    scene_builds_in_flight: u32,
+    // Messages that have to observe the new scene, received while a scene
+    // build was in flight. They are replayed in order once the built scene
+    // has been swapped in.
+    deferred_messages: Vec<(DocumentMsg, TransactionId)>,
 }
 
 impl Document {
     pub fn new(
         config: FrameBuilderConfig,
         initial_size: DeviceUintSize,
-        enable_render_on_scroll: bool,
+        scheduling_policy: SchedulingPolicy,
     ) -> Self {
-        let render_on_scroll = if enable_render_on_scroll {
-            Some(false)
-        } else {
-            None
-        };
         Document {
             scene: Scene::new(),
             frame: Frame::new(config),
@@ -66,7 +215,12 @@ impl Document {
             pan: DeviceIntPoint::zero(),
             page_zoom_factor: 1.0,
             pinch_zoom_factor: 1.0,
-            render_on_scroll,
+            async_zoom_factor: 1.0,
+            output_color_transform: None,
+            scheduler: FrameScheduler::new(scheduling_policy),
+            priority: DocumentPriority::High,
+            scene_builds_in_flight: 0,
+            deferred_messages: Vec::new(),
         }
     }
 
@@ -74,15 +228,36 @@ impl Document {
         hidpi_factor * self.page_zoom_factor * self.pinch_zoom_factor
     }
 
-    fn build_scene(&mut self, resource_cache: &mut ResourceCache, hidpi_factor: f32) {
+    fn build_scene(&mut self, resource_cache: &ResourceCache, hidpi_factor: f32) {
         let accumulated_scale_factor = self.accumulated_scale_factor(hidpi_factor);
         self.frame.create(&self.scene,
-                          resource_cache,
+                          resource_cache.get_tiled_image_map(),
                           self.window_size,
                           self.inner_rect,
                           accumulated_scale_factor);
     }
 
+    /// Snapshot the current scene and ask the scene builder thread to build
+    /// it. The old scene stays in place (and keeps producing scrolled frames)
+    /// until the built scene comes back and is swapped in.
+    fn request_scene_build(&mut self,
+                           document_id: DocumentId,
+                           resource_cache: &ResourceCache,
+                           hidpi_factor: f32,
+                           scene_tx: &Sender<SceneRequest>) {
+        self.scene_builds_in_flight += 1;
+        scene_tx.send(SceneRequest {
+            document_id,
+            scene: self.scene.clone(),
+            tiled_image_map: resource_cache.get_tiled_image_map(),
+            window_size: self.window_size,
+            inner_rect: self.inner_rect,
+            accumulated_scale_factor: self.accumulated_scale_factor(hidpi_factor),
+            quality: self.frame.quality(),
+            retained_gpu_locations: self.frame.harvest_gpu_locations(),
+        }).expect("The scene builder thread is gone?");
+    }
+
     fn render(&mut self,
         resource_cache: &mut ResourceCache,
         gpu_cache: &mut GpuCache,
@@ -92,13 +267,19 @@ impl Document {
         let accumulated_scale_factor = self.accumulated_scale_factor(hidpi_factor);
         let pan = LayerPoint::new(self.pan.x as f32 / accumulated_scale_factor,
                                   self.pan.y as f32 / accumulated_scale_factor);
-        self.frame.build(resource_cache,
-                         gpu_cache,
-                         &self.scene.display_lists,
-                         accumulated_scale_factor,
-                         pan,
-                         &mut resource_profile.texture_cache,
-                         &mut resource_profile.gpu_cache)
+        let mut renderer_frame = self.frame.build(resource_cache,
+                                                  gpu_cache,
+                                                  &self.scene.display_lists,
+                                                  &self.scene.properties,
+                                                  accumulated_scale_factor,
+                                                  pan,
+                                                  self.async_zoom_factor,
+                                                  &mut resource_profile.texture_cache,
+                                                  &mut resource_profile.gpu_cache);
+        if let Some(ref mut frame) = renderer_frame.frame {
+            frame.output_color_transform = self.output_color_transform;
+        }
+        renderer_frame
     }
 }
 
@@ -180,9 +361,25 @@ pub struct RenderBackend {
     gpu_cache: GpuCache,
     resource_cache: ResourceCache,
 
+    // The settings the worker pool was built with, so it can be rebuilt
+    // identically (apart from the thread count) when resized at runtime.
+    worker_settings: WorkerPoolSettings,
+
     frame_config: FrameBuilderConfig,
+    // The quality settings the renderer was configured with. Pressure
+    // signals downgrade `frame_config.quality` below this baseline, and
+    // clearing them restores it.
+    base_quality: QualitySettings,
     documents: FastHashMap<DocumentId, Document>,
 
+    scene_tx: Sender<SceneRequest>,
+    scene_rx: Receiver<BuiltScene>,
+
+    // Messages for low-priority documents, deferred so that high-priority
+    // documents are always serviced first. Processed in bounded chunks
+    // between incoming messages; see `process_low_priority_queue`.
+    low_priority_queue: VecDeque<(DocumentId, DocumentMsg, TransactionId)>,
+
     notifier: Arc<Mutex<Option<Box<RenderNotifier>>>>,
     webrender_context_handle: Option<GLContextHandleWrapper>,
     recorder: Option<Box<ApiRecordingReceiver>>,
@@ -191,7 +388,17 @@ pub struct RenderBackend {
     vr_compositor_handler: Arc<Mutex<Option<Box<VRCompositorHandler>>>>,
     webgl: WebGL,
 
-    enable_render_on_scroll: bool,
+    // Scheduling policy given to new documents; see `SchedulingPolicy`.
+    default_scheduling_policy: SchedulingPolicy,
+
+    /// Whether incoming display lists are run through the validation
+    /// pass before entering the scene. See the `display_list_validator`
+    /// module.
+    validate_display_lists: bool,
+
+    /// Whether to attach a snapshot of the texture cache contents to each
+    /// published frame, for the renderer's debug overlay.
+    texture_cache_debug_enabled: bool,
 }
 
 impl RenderBackend {
@@ -203,20 +410,25 @@ impl RenderBackend {
         hidpi_factor: f32,
         texture_cache: TextureCache,
         workers: Arc<ThreadPool>,
+        worker_settings: WorkerPoolSettings,
         notifier: Arc<Mutex<Option<Box<RenderNotifier>>>>,
         webrender_context_handle: Option<GLContextHandleWrapper>,
         frame_config: FrameBuilderConfig,
+        scene_tx: Sender<SceneRequest>,
+        scene_rx: Receiver<BuiltScene>,
         recorder: Option<Box<ApiRecordingReceiver>>,
         main_thread_dispatcher: Arc<Mutex<Option<Box<RenderDispatcher>>>>,
         blob_image_renderer: Option<Box<BlobImageRenderer>>,
         vr_compositor_handler: Arc<Mutex<Option<Box<VRCompositorHandler>>>>,
-        enable_render_on_scroll: bool,
+        default_scheduling_policy: SchedulingPolicy,
+        validate_display_lists: bool,
     ) -> RenderBackend {
 
         let resource_cache = ResourceCache::new(texture_cache,
                                                 workers,
                                                 blob_image_renderer,
-                                                frame_config.cache_expiry_frames);
+                                                frame_config.cache_expiry_frames,
+                                                frame_config.blob_tile_size);
 
         register_thread_with_profiler("Backend".to_string());
 
@@ -228,9 +440,14 @@ impl RenderBackend {
             hidpi_factor,
 
             resource_cache,
+            worker_settings,
             gpu_cache: GpuCache::new(),
+            base_quality: frame_config.quality,
             frame_config,
             documents: FastHashMap::default(),
+            scene_tx,
+            scene_rx,
+            low_priority_queue: VecDeque::new(),
             next_namespace_id: IdNamespace(1),
             notifier,
             webrender_context_handle,
@@ -240,16 +457,34 @@ impl RenderBackend {
             vr_compositor_handler,
             webgl: WebGL::new(),
 
-            enable_render_on_scroll,
+            default_scheduling_policy,
+            validate_display_lists,
+            texture_cache_debug_enabled: false,
         }
     }
 
     fn process_document(&mut self, document_id: DocumentId, message: DocumentMsg,
-                        frame_counter: u32, mut profile_counters: &mut BackendProfileCounters)
+                        transaction_id: TransactionId, frame_counter: u32,
+                        mut profile_counters: &mut BackendProfileCounters)
                         -> DocumentOp
     {
         let doc = self.documents.get_mut(&document_id).expect("No document?");
 
+        // While a scene build is in flight, defer any message that has to
+        // observe the new scene until it has been swapped in. Scrolling
+        // messages deliberately keep going against the old scene, so that
+        // async scrolling stays responsive during a large scene build.
+        if doc.scene_builds_in_flight > 0 {
+            match message {
+                DocumentMsg::GenerateFrame(..) |
+                DocumentMsg::UpdateDynamicProperties(..) => {
+                    doc.deferred_messages.push((message, transaction_id));
+                    return DocumentOp::Nop;
+                }
+                _ => {}
+            }
+        }
+
         match message {
             DocumentMsg::SetPageZoom(factor) => {
                 doc.page_zoom_factor = factor.get();
@@ -259,10 +494,18 @@ impl RenderBackend {
                 doc.pinch_zoom_factor = factor.get();
                 DocumentOp::Nop
             }
+            DocumentMsg::SetAsyncZoom(factor) => {
+                doc.async_zoom_factor = factor.get();
+                DocumentOp::Nop
+            }
             DocumentMsg::SetPan(pan) => {
                 doc.pan = pan;
                 DocumentOp::Nop
             }
+            DocumentMsg::SetOutputColorTransform(transform) => {
+                doc.output_color_transform = transform;
+                DocumentOp::Nop
+            }
             DocumentMsg::SetWindowParameters{ window_size, inner_rect } => {
                 doc.window_size = window_size;
                 doc.inner_rect = inner_rect;
@@ -299,6 +542,25 @@ impl RenderBackend {
                     list_descriptor
                 );
 
+                if self.validate_display_lists {
+                    let warnings = display_list_validator::validate_display_list(&built_display_list);
+                    if !warnings.is_empty() {
+                        let reject = warnings.iter().any(display_list_validator::is_fatal);
+                        let mut notifier = self.notifier.lock().unwrap();
+                        let notifier = notifier.as_mut().unwrap();
+                        for warning in warnings {
+                            notifier.display_list_warning(pipeline_id, warning);
+                        }
+                        if reject {
+                            // Keep showing the previous scene for this
+                            // pipeline rather than feed the defective
+                            // list to tiling.
+                            warn!("rejected defective display list for {:?}", pipeline_id);
+                            return DocumentOp::Nop;
+                        }
+                    }
+                }
+
                 if !preserve_frame_state {
                     doc.frame.discard_frame_state_for_pipeline(pipeline_id);
                 }
@@ -318,12 +580,13 @@ impl RenderBackend {
                         viewport_size,
                         content_size
                     );
-                    doc.build_scene(&mut self.resource_cache, self.hidpi_factor);
+                    doc.request_scene_build(document_id,
+                                            &self.resource_cache,
+                                            self.hidpi_factor,
+                                            &self.scene_tx);
                 }
 
-                if let Some(ref mut ros) = doc.render_on_scroll {
-                    *ros = false; //wait for `GenerateFrame`
-                }
+                doc.scheduler.on_display_list();
 
                 // Note: this isn't quite right as auxiliary values will be
                 // pulled out somewhere in the prim_store, but aux values are
@@ -346,17 +609,24 @@ impl RenderBackend {
                 if doc.scene.display_lists.get(&pipeline_id).is_some() {
                     self.webgl.flush();
                     let _timer = profile_counters.total_time.timer();
-                    doc.build_scene(&mut self.resource_cache, self.hidpi_factor);
+                    doc.request_scene_build(document_id,
+                                            &self.resource_cache,
+                                            self.hidpi_factor,
+                                            &self.scene_tx);
                     DocumentOp::Built
                 } else {
                     DocumentOp::Nop
                 }
             }
+            DocumentMsg::SetSchedulingPolicy(policy) => {
+                doc.scheduler.set_policy(policy);
+                DocumentOp::Nop
+            }
             DocumentMsg::Scroll(delta, cursor, move_phase) => {
                 profile_scope!("Scroll");
                 let _timer = profile_counters.total_time.timer();
 
-                if doc.frame.scroll(delta, cursor, move_phase) && doc.render_on_scroll == Some(true) {
+                if doc.frame.scroll(delta, cursor, move_phase) && doc.scheduler.should_render_scroll_update() {
                     let frame = doc.render(&mut self.resource_cache,
                                            &mut self.gpu_cache,
                                            &mut profile_counters.resources,
@@ -370,7 +640,7 @@ impl RenderBackend {
                 profile_scope!("ScrollNodeWithScrollId");
                 let _timer = profile_counters.total_time.timer();
 
-                if doc.frame.scroll_node(origin, id, clamp) && doc.render_on_scroll == Some(true) {
+                if doc.frame.scroll_node(origin, id, clamp) && doc.scheduler.should_render_scroll_update() {
                     let frame = doc.render(&mut self.resource_cache,
                                            &mut self.gpu_cache,
                                            &mut profile_counters.resources,
@@ -385,7 +655,7 @@ impl RenderBackend {
                 let _timer = profile_counters.total_time.timer();
 
                 doc.frame.tick_scrolling_bounce_animations();
-                if doc.render_on_scroll == Some(true) {
+                if doc.scheduler.should_render_scroll_update() {
                     let frame = doc.render(&mut self.resource_cache,
                                            &mut self.gpu_cache,
                                            &mut profile_counters.resources,
@@ -400,6 +670,11 @@ impl RenderBackend {
                 tx.send(doc.frame.get_scroll_node_state()).unwrap();
                 DocumentOp::Nop
             }
+            DocumentMsg::HitTest(pipeline_id, point, tx) => {
+                profile_scope!("HitTest");
+                tx.send(doc.frame.hit_test(pipeline_id, point)).unwrap();
+                DocumentOp::Nop
+            }
             DocumentMsg::GenerateFrame(property_bindings) => {
                 profile_scope!("GenerateFrame");
                 let _timer = profile_counters.total_time.timer();
@@ -417,12 +692,31 @@ impl RenderBackend {
                 if let Some(property_bindings) = property_bindings {
                     self.webgl.flush();
                     doc.scene.properties.set_properties(property_bindings);
-                    doc.build_scene(&mut self.resource_cache, self.hidpi_factor);
+                    doc.build_scene(&self.resource_cache, self.hidpi_factor);
                 }
 
-                if let Some(ref mut ros) = doc.render_on_scroll {
-                    *ros = true;
+                doc.scheduler.on_generate_frame();
+
+                if doc.scene.root_pipeline_id.is_some() {
+                    let frame = doc.render(&mut self.resource_cache,
+                                           &mut self.gpu_cache,
+                                           &mut profile_counters.resources,
+                                           self.hidpi_factor);
+                    DocumentOp::Rendered(frame)
+                } else {
+                    DocumentOp::ScrolledNop
                 }
+            }
+            DocumentMsg::UpdateDynamicProperties(properties) => {
+                profile_scope!("UpdateDynamicProperties");
+                let _timer = profile_counters.total_time.timer();
+
+                // Transform bindings are patched directly into the
+                // clip-scroll tree, and opacity bindings are resolved on
+                // every frame build, so a property update only needs a new
+                // frame - never a scene rebuild.
+                doc.scene.properties.set_properties(properties);
+                doc.frame.update_animated_properties(&doc.scene.properties);
 
                 if doc.scene.root_pipeline_id.is_some() {
                     let frame = doc.render(&mut self.resource_cache,
@@ -443,167 +737,440 @@ impl RenderBackend {
         loop {
             profile_scope!("handle_msg");
 
-            let msg = match self.api_rx.recv() {
-                Ok(msg) => {
-                    if let Some(ref mut r) = self.recorder {
-                        r.write_msg(frame_counter, &msg);
+            let msg = if self.low_priority_queue.is_empty() {
+                match self.api_rx.recv() {
+                    Ok(msg) => Some(msg),
+                    Err(..) => {
+                        let notifier = self.notifier.lock();
+                        notifier.unwrap()
+                                .as_mut()
+                                .unwrap()
+                                .shut_down();
+                        break;
                     }
-                    msg
-                }
-                Err(..) => {
-                    let notifier = self.notifier.lock();
-                    notifier.unwrap()
-                            .as_mut()
-                            .unwrap()
-                            .shut_down();
-                    break;
                 }
+            } else {
+                // There is deferred low-priority work to get back to, so
+                // don't block if nothing new has arrived.
+                self.api_rx.try_recv().ok()
             };
 
-            match msg {
-                ApiMsg::UpdateResources(updates) => {
-                    self.resource_cache.update_resources(updates, &mut profile_counters.resources);
-                }
-                ApiMsg::GetGlyphDimensions(font, glyph_keys, tx) => {
-                    let mut glyph_dimensions = Vec::with_capacity(glyph_keys.len());
-                    for glyph_key in &glyph_keys {
-                        let glyph_dim = self.resource_cache.get_glyph_dimensions(&font, glyph_key);
-                        glyph_dimensions.push(glyph_dim);
-                    };
-                    tx.send(glyph_dimensions).unwrap();
-                }
-                ApiMsg::GetGlyphIndices(font_key, text, tx) => {
-                    let mut glyph_indices = Vec::new();
-                    for ch in text.chars() {
-                        let index = self.resource_cache.get_glyph_index(font_key, ch);
-                        glyph_indices.push(index);
-                    };
-                    tx.send(glyph_indices).unwrap();
-                }
-                ApiMsg::CloneApi(sender) => {
-                    let namespace = self.next_namespace_id;
-                    self.next_namespace_id = IdNamespace(namespace.0 + 1);
-                    sender.send(namespace).unwrap();
-                }
-                ApiMsg::AddDocument(document_id, initial_size) => {
-                    let document = Document::new(self.frame_config.clone(),
-                                                 initial_size,
-                                                 self.enable_render_on_scroll);
-                    self.documents.insert(document_id, document);
+            if let Some(msg) = msg {
+                if let Some(ref mut r) = self.recorder {
+                    r.write_msg(frame_counter, &msg);
                 }
-                ApiMsg::UpdateDocument(document_id, doc_msg) => {
-                    match self.process_document(document_id, doc_msg, frame_counter, &mut profile_counters) {
-                        DocumentOp::Nop => {}
-                        DocumentOp::Built => {}
-                        DocumentOp::ScrolledNop => {
-                            self.notify_compositor_of_new_scroll_frame(false);
+
+                match msg {
+                    ApiMsg::UpdateResources(updates) => {
+                        self.resource_cache.update_resources(updates, &mut profile_counters.resources);
+                    }
+                    ApiMsg::GetGlyphDimensions(font, glyph_keys, tx) => {
+                        let mut glyph_dimensions = Vec::with_capacity(glyph_keys.len());
+                        for glyph_key in &glyph_keys {
+                            let glyph_dim = self.resource_cache.get_glyph_dimensions(&font, glyph_key);
+                            glyph_dimensions.push(glyph_dim);
+                        };
+                        tx.send(glyph_dimensions).unwrap();
+                    }
+                    ApiMsg::GetGlyphIndices(font_key, text, tx) => {
+                        let mut glyph_indices = Vec::new();
+                        for ch in text.chars() {
+                            let index = self.resource_cache.get_glyph_index(font_key, ch);
+                            glyph_indices.push(index);
+                        };
+                        tx.send(glyph_indices).unwrap();
+                    }
+                    ApiMsg::RequestGlyphs(font, glyph_indices) => {
+                        self.resource_cache.pre_rasterize_glyphs(
+                            font,
+                            &glyph_indices,
+                            &mut profile_counters.resources.texture_cache);
+                        // Push the uploads to the renderer right away;
+                        // the point of pre-warming is not to wait for
+                        // the next frame.
+                        let pending_update = self.resource_cache.pending_updates();
+                        let msg = ResultMsg::UpdateResources { updates: pending_update, cancel_rendering: false };
+                        self.result_tx.send(msg).unwrap();
+                        self.notifier.lock().unwrap().as_mut().unwrap().new_frame_ready();
+                    }
+                    ApiMsg::CloneApi(sender) |
+                    ApiMsg::GenerateIdNamespace(sender) => {
+                        let namespace = self.next_namespace_id;
+                        self.next_namespace_id = IdNamespace(namespace.0 + 1);
+                        sender.send(namespace).unwrap();
+                    }
+                    ApiMsg::AddDocument(document_id, initial_size) => {
+                        let document = Document::new(self.frame_config.clone(),
+                                                     initial_size,
+                                                     self.default_scheduling_policy);
+                        self.documents.insert(document_id, document);
+                    }
+                    ApiMsg::SetDocumentPriority(document_id, priority) => {
+                        if let Some(doc) = self.documents.get_mut(&document_id) {
+                            doc.priority = priority;
                         }
-                        DocumentOp::Scrolled(frame) => {
-                            self.publish_frame(document_id, frame, &mut profile_counters);
-                            self.notify_compositor_of_new_scroll_frame(true);
+                        if priority == DocumentPriority::High {
+                            // Any deferred messages of this document are
+                            // high-priority work now; process them in order.
+                            let mut deferred = Vec::new();
+                            let mut remaining = VecDeque::new();
+                            for (id, queued_msg, transaction_id) in self.low_priority_queue.drain(..) {
+                                if id == document_id {
+                                    deferred.push((queued_msg, transaction_id));
+                                } else {
+                                    remaining.push_back((id, queued_msg, transaction_id));
+                                }
+                            }
+                            self.low_priority_queue = remaining;
+
+                            for (queued_msg, transaction_id) in deferred {
+                                self.process_transaction(document_id,
+                                                         queued_msg,
+                                                         transaction_id,
+                                                         &mut frame_counter,
+                                                         &mut profile_counters);
+                            }
                         }
-                        DocumentOp::Rendered(frame) => {
-                            frame_counter += 1;
-                            self.publish_frame_and_notify_compositor(document_id, frame, &mut profile_counters);
+                    }
+                    ApiMsg::UpdateDocument(document_id, doc_msg, transaction_id) => {
+                        let low_priority = self.documents
+                                               .get(&document_id)
+                                               .map_or(false, |doc| {
+                                                   doc.priority == DocumentPriority::Low
+                                               });
+                        if low_priority {
+                            self.low_priority_queue.push_back((document_id, doc_msg, transaction_id));
+                        } else {
+                            self.process_transaction(document_id,
+                                                     doc_msg,
+                                                     transaction_id,
+                                                     &mut frame_counter,
+                                                     &mut profile_counters);
                         }
                     }
-                }
-                ApiMsg::DeleteDocument(document_id) => {
-                    self.documents.remove(&document_id);
-                }
-                ApiMsg::RequestWebGLContext(size, attributes, tx) => {
-                    if let Some(ref wrapper) = self.webrender_context_handle {
-                        let dispatcher: Option<Box<GLContextDispatcher>> = if cfg!(target_os = "windows") {
-                            Some(Box::new(WebRenderGLDispatcher {
-                                dispatcher: Arc::clone(&self.main_thread_dispatcher)
-                            }))
+                    ApiMsg::DeleteDocument(document_id) => {
+                        self.documents.remove(&document_id);
+                        self.low_priority_queue.retain(|&(id, _, _)| id != document_id);
+                    }
+                    ApiMsg::RequestWebGLContext(size, attributes, tx) => {
+                        if let Some(ref wrapper) = self.webrender_context_handle {
+                            let dispatcher: Option<Box<GLContextDispatcher>> = if cfg!(target_os = "windows") {
+                                Some(Box::new(WebRenderGLDispatcher {
+                                    dispatcher: Arc::clone(&self.main_thread_dispatcher)
+                                }))
+                            } else {
+                                None
+                            };
+
+                            let result = wrapper.new_context(size, attributes, dispatcher);
+
+                            match result {
+                                Ok(ctx) => {
+                                    let (real_size, texture_id, limits) = ctx.get_info();
+                                    let id = self.webgl.register(ctx);
+
+                                    self.resource_cache
+                                        .add_webgl_texture(id, SourceTexture::WebGL(texture_id),
+                                                           real_size);
+
+                                    tx.send(Ok((id, limits))).unwrap();
+                                },
+                                Err(msg) => {
+                                    tx.send(Err(msg.to_owned())).unwrap();
+                                }
+                            }
                         } else {
-                            None
-                        };
+                            tx.send(Err("Not implemented yet".to_owned())).unwrap();
+                        }
+                    }
+                    ApiMsg::ResizeWebGLContext(context_id, size) => {
+                        let ctx = self.webgl.activate(context_id);
+                        match ctx.resize(&size) {
+                            Ok(_) => {
+                                // Update webgl texture size. Texture id may change too.
+                                let (real_size, texture_id, _) = ctx.get_info();
+                                self.resource_cache
+                                    .update_webgl_texture(context_id, SourceTexture::WebGL(texture_id),
+                                                          real_size);
+                            },
+                            Err(msg) => {
+                                error!("Error resizing WebGLContext: {}", msg);
+                            }
+                        }
+                    }
+                    ApiMsg::UpdateWebGLSurface(context_id, descriptor) => {
+                        self.resource_cache.update_webgl_surface(context_id, &descriptor);
+                    }
+                    ApiMsg::WebGLCommand(context_id, command) => {
+                        // TODO: Buffer the commands and only apply them here if they need to
+                        // be synchronous.
+                        let ctx = self.webgl.activate(context_id);
+                        ctx.apply_command(command);
+                    },
+
+                    ApiMsg::VRCompositorCommand(context_id, command) => {
+                        self.webgl.activate(context_id);
+                        self.handle_vr_compositor_command(context_id, command);
+                    }
+                    ApiMsg::ExternalEvent(evt) => {
+                        let notifier = self.notifier.lock();
+                        notifier.unwrap()
+                                .as_mut()
+                                .unwrap()
+                                .external_event(evt);
+                    }
+                    ApiMsg::ClearNamespace(namespace_id) => {
+                        self.resource_cache.clear_namespace(namespace_id);
+                        let document_ids = self.documents.keys()
+                                                         .filter(|did| did.0 == namespace_id)
+                                                         .cloned()
+                                                         .collect::<Vec<_>>();
+                        for document in document_ids {
+                            self.documents.remove(&document);
+                        }
+                    }
+                    ApiMsg::MemoryPressure(level) => {
+                        // Each level also applies the cheaper measures
+                        // below it; the render-target pool trim for the
+                        // lowest level happens entirely on the render
+                        // thread.
+                        let mut reclaimed = 0;
+
+                        if level >= MemoryPressureLevel::Moderate {
+                            reclaimed += if level >= MemoryPressureLevel::Critical {
+                                self.resource_cache.on_memory_pressure()
+                            } else {
+                                self.resource_cache.evict_idle_resources()
+                            };
+                        }
 
-                        let result = wrapper.new_context(size, attributes, dispatcher);
+                        if level >= MemoryPressureLevel::Severe {
+                            // Retained gpu cache handles notice the reset
+                            // and re-request their blocks, the same way
+                            // handles survive a context loss, so the
+                            // rebuilt cache only holds what is in use.
+                            reclaimed += self.gpu_cache.cpu_memory_in_use();
+                            self.gpu_cache = GpuCache::new();
+                        }
 
-                        match result {
-                            Ok(ctx) => {
-                                let (real_size, texture_id, limits) = ctx.get_info();
-                                let id = self.webgl.register(ctx);
+                        // The renderer frees its own share (render target
+                        // pool, gpu cache shadow copy) when it sees this.
+                        self.result_tx.send(ResultMsg::MemoryPressure(level)).unwrap();
 
-                                self.resource_cache
-                                    .add_webgl_texture(id, SourceTexture::WebGL(texture_id),
-                                                       real_size);
+                        let pending_update = self.resource_cache.pending_updates();
+                        let msg = ResultMsg::UpdateResources { updates: pending_update, cancel_rendering: true };
+                        self.result_tx.send(msg).unwrap();
+                        // We use new_frame_ready to wake up the renderer and get the
+                        // resource updates processed, but the UpdateResources message
+                        // will cancel rendering the frame.
+                        self.notifier.lock().unwrap().as_mut().unwrap().new_frame_ready();
 
-                                tx.send(Ok((id, limits))).unwrap();
-                            },
-                            Err(msg) => {
-                                tx.send(Err(msg.to_owned())).unwrap();
+                        debug!("memory pressure ({:?}): backend reclaimed {} bytes",
+                               level, reclaimed);
+                    }
+                    ApiMsg::SetQualitySignals(signals) => {
+                        // Quality can only be downgraded below the baseline
+                        // the renderer was configured with; clearing the
+                        // signals restores it exactly.
+                        let quality = self.base_quality.min(&QualitySettings::for_signals(&signals));
+                        if quality != self.frame_config.quality {
+                            self.frame_config.quality = quality;
+                            // Subpixel AA is decided while flattening the
+                            // display list, so the scenes have to be rebuilt.
+                            // The new settings show up in the next generated
+                            // frame.
+                            self.webgl.flush();
+                            for doc in self.documents.values_mut() {
+                                doc.frame.set_quality(quality);
+                                doc.build_scene(&self.resource_cache, self.hidpi_factor);
                             }
                         }
-                    } else {
-                        tx.send(Err("Not implemented yet".to_owned())).unwrap();
                     }
-                }
-                ApiMsg::ResizeWebGLContext(context_id, size) => {
-                    let ctx = self.webgl.activate(context_id);
-                    match ctx.resize(&size) {
-                        Ok(_) => {
-                            // Update webgl texture size. Texture id may change too.
-                            let (real_size, texture_id, _) = ctx.get_info();
-                            self.resource_cache
-                                .update_webgl_texture(context_id, SourceTexture::WebGL(texture_id),
-                                                      real_size);
-                        },
-                        Err(msg) => {
-                            error!("Error resizing WebGLContext: {}", msg);
+                    ApiMsg::SetWorkerThreads(count) => {
+                        let workers = new_worker_pool(Some(count), &self.worker_settings);
+                        self.resource_cache.set_workers(workers);
+                    }
+                    ApiMsg::WakeUp => {}
+                    ApiMsg::SetCacheExpiryFrames(frames) => {
+                        self.resource_cache.set_cache_expiry_frames(frames);
+                    }
+                    ApiMsg::EnableTextureCacheDebug(enable) => {
+                        self.texture_cache_debug_enabled = enable;
+                    }
+                    ApiMsg::NotifyContextLost => {
+                        // Every texture we uploaded died with the old context,
+                        // and so did the GPU cache texture, which means the
+                        // cache locations stored in the current frames point
+                        // into a texture that no longer exists. Flush the
+                        // resource caches, restart the GPU cache, and rebuild
+                        // each document from scratch so that the frames we
+                        // publish only reference resources that get uploaded
+                        // to the new context.
+                        self.resource_cache.on_context_lost();
+                        self.gpu_cache = GpuCache::new();
+
+                        // Let the renderer know that everything it receives
+                        // from here on was built for the new context, so it
+                        // can stop discarding results.
+                        self.result_tx.send(ResultMsg::ContextRestored).unwrap();
+
+                        let document_ids: Vec<_> = self.documents.keys().cloned().collect();
+                        for document_id in document_ids {
+                            let rendered_frame = {
+                                let doc = self.documents.get_mut(&document_id).unwrap();
+                                let mut old_frame = mem::replace(&mut doc.frame,
+                                                                 Frame::new(self.frame_config.clone()));
+                                doc.build_scene(&self.resource_cache, self.hidpi_factor);
+                                doc.frame.adopt_frame_state(&mut old_frame);
+
+                                if doc.scene.root_pipeline_id.is_none() {
+                                    continue;
+                                }
+                                doc.render(&mut self.resource_cache,
+                                           &mut self.gpu_cache,
+                                           &mut profile_counters.resources,
+                                           self.hidpi_factor)
+                            };
+                            frame_counter += 1;
+                            self.publish_frame_and_notify_compositor(document_id,
+                                                                     rendered_frame,
+                                                                     &mut profile_counters);
                         }
                     }
-                }
-                ApiMsg::WebGLCommand(context_id, command) => {
-                    // TODO: Buffer the commands and only apply them here if they need to
-                    // be synchronous.
-                    let ctx = self.webgl.activate(context_id);
-                    ctx.apply_command(command);
-                },
-
-                ApiMsg::VRCompositorCommand(context_id, command) => {
-                    self.webgl.activate(context_id);
-                    self.handle_vr_compositor_command(context_id, command);
-                }
-                ApiMsg::ExternalEvent(evt) => {
-                    let notifier = self.notifier.lock();
-                    notifier.unwrap()
-                            .as_mut()
-                            .unwrap()
-                            .external_event(evt);
-                }
-                ApiMsg::ClearNamespace(namespace_id) => {
-                    self.resource_cache.clear_namespace(namespace_id);
-                    let document_ids = self.documents.keys()
-                                                     .filter(|did| did.0 == namespace_id)
-                                                     .cloned()
-                                                     .collect::<Vec<_>>();
-                    for document in document_ids {
-                        self.documents.remove(&document);
+                    ApiMsg::ShutDown => {
+                        let notifier = self.notifier.lock();
+                        notifier.unwrap()
+                                .as_mut()
+                                .unwrap()
+                                .shut_down();
+                        break;
                     }
                 }
-                ApiMsg::MemoryPressure => {
-                    self.resource_cache.on_memory_pressure();
-
-                    let pending_update = self.resource_cache.pending_updates();
-                    let msg = ResultMsg::UpdateResources { updates: pending_update, cancel_rendering: true };
-                    self.result_tx.send(msg).unwrap();
-                    // We use new_frame_ready to wake up the renderer and get the
-                    // resource updates processed, but the UpdateResources message
-                    // will cancel rendering the frame.
-                    self.notifier.lock().unwrap().as_mut().unwrap().new_frame_ready();
+            }
+
+            self.process_built_scenes(&mut frame_counter, &mut profile_counters);
+            self.process_low_priority_queue(&mut frame_counter, &mut profile_counters);
+        }
+    }
+
+    /// Processes one document message and its resulting operation, timing
+    /// the work and feeding the outcome to the tracing hook.
+    fn process_transaction(&mut self,
+                           document_id: DocumentId,
+                           msg: DocumentMsg,
+                           transaction_id: TransactionId,
+                           frame_counter: &mut u32,
+                           profile_counters: &mut BackendProfileCounters) {
+        let start_time = precise_time_ns();
+        let msg_name = msg.name();
+        let op = self.process_document(document_id,
+                                       msg,
+                                       transaction_id,
+                                       *frame_counter,
+                                       profile_counters);
+        self.handle_document_op(document_id, op, frame_counter, profile_counters);
+        trace_transaction(transaction_id,
+                          msg_name,
+                          document_id,
+                          precise_time_ns() - start_time,
+                          *frame_counter);
+    }
+
+    fn handle_document_op(&mut self,
+                          document_id: DocumentId,
+                          op: DocumentOp,
+                          frame_counter: &mut u32,
+                          profile_counters: &mut BackendProfileCounters) {
+        match op {
+            DocumentOp::Nop => {}
+            DocumentOp::Built => {}
+            DocumentOp::ScrolledNop => {
+                self.notify_compositor_of_new_scroll_frame(false);
+            }
+            DocumentOp::Scrolled(frame) => {
+                self.publish_frame(document_id, frame, profile_counters);
+                self.notify_compositor_of_new_scroll_frame(true);
+            }
+            DocumentOp::Rendered(frame) => {
+                *frame_counter += 1;
+                self.publish_frame_and_notify_compositor(document_id, frame, profile_counters);
+
+                // If the frame went out with placeholders for blob images
+                // that were still rasterizing, schedule a follow-up frame
+                // to patch the results in. It goes through the low-priority
+                // queue so that incoming messages are serviced first.
+                if self.resource_cache.has_missing_blob_images() {
+                    self.low_priority_queue.push_back((document_id,
+                                                       DocumentMsg::GenerateFrame(None),
+                                                       TransactionId(0)));
                 }
-                ApiMsg::ShutDown => {
-                    let notifier = self.notifier.lock();
-                    notifier.unwrap()
-                            .as_mut()
-                            .unwrap()
-                            .shut_down();
-                    break;
+            }
+        }
+    }
+
+    /// Swap in any scenes that have come back from the scene builder thread,
+    /// and replay the messages that were deferred while they were in flight.
+    fn process_built_scenes(&mut self,
+                            frame_counter: &mut u32,
+                            profile_counters: &mut BackendProfileCounters) {
+        while let Ok(mut built) = self.scene_rx.try_recv() {
+            let document_id = built.document_id;
+            let deferred = {
+                let doc = match self.documents.get_mut(&document_id) {
+                    Some(doc) => doc,
+                    // The document was deleted while the scene was building.
+                    None => continue,
+                };
+
+                // The document may have received newer dynamic property
+                // values while the scene was being built; keep those rather
+                // than the snapshot the build started from.
+                mem::swap(&mut doc.scene.properties, &mut built.scene.properties);
+                doc.scene = built.scene;
+
+                built.frame.adopt_frame_state(&mut doc.frame);
+                doc.frame = built.frame;
+
+                doc.scene_builds_in_flight -= 1;
+                if doc.scene_builds_in_flight > 0 {
+                    // A newer scene is still on its way. Hold the deferred
+                    // messages back until it has been swapped in as well.
+                    continue;
                 }
+
+                mem::replace(&mut doc.deferred_messages, Vec::new())
+            };
+
+            for (msg, transaction_id) in deferred {
+                self.process_transaction(document_id,
+                                         msg,
+                                         transaction_id,
+                                         frame_counter,
+                                         profile_counters);
+            }
+        }
+    }
+
+    /// Works on deferred messages of low-priority documents for at most a
+    /// fixed time budget, so that a busy offscreen document makes steady
+    /// progress without starving incoming high-priority messages.
+    fn process_low_priority_queue(&mut self,
+                                  frame_counter: &mut u32,
+                                  profile_counters: &mut BackendProfileCounters) {
+        if self.low_priority_queue.is_empty() {
+            return;
+        }
+
+        let deadline = precise_time_ns() + LOW_PRIORITY_WORK_BUDGET_NS;
+        while let Some((document_id, msg, transaction_id)) = self.low_priority_queue.pop_front() {
+            self.process_transaction(document_id,
+                                     msg,
+                                     transaction_id,
+                                     frame_counter,
+                                     profile_counters);
+
+            if precise_time_ns() >= deadline {
+                break;
             }
         }
     }
@@ -613,7 +1180,16 @@ impl RenderBackend {
                      frame: RendererFrame,
                      profile_counters: &mut BackendProfileCounters) {
         let pending_update = self.resource_cache.pending_updates();
-        let msg = ResultMsg::NewFrame(document_id, frame, pending_update, profile_counters.clone());
+        let texture_cache_debug = if self.texture_cache_debug_enabled {
+            Some(self.resource_cache.get_texture_cache_debug_info())
+        } else {
+            None
+        };
+        let msg = ResultMsg::NewFrame(document_id,
+                                      frame,
+                                      pending_update,
+                                      texture_cache_debug,
+                                      profile_counters.clone());
         self.result_tx.send(msg).unwrap();
         profile_counters.reset();
     }
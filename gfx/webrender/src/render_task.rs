@@ -35,6 +35,10 @@ pub enum RenderTaskKey {
 pub enum MaskCacheKey {
     Primitive(PrimitiveIndex),
     ClipNode(ClipId),
+    /// Hash of the clip chain contents and the device-space mask rect.
+    /// Primitives whose clip chains hash identically share a single
+    /// mask task instead of rasterizing one each.
+    ContentHash(u64),
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -63,6 +67,9 @@ pub enum AlphaRenderItem {
 pub struct AlphaRenderTask {
     screen_origin: DeviceIntPoint,
     pub items: Vec<AlphaRenderItem>,
+    // The scale content is rasterized at. Less than 1.0 when the task was
+    // too large for the maximum target size and had to be scaled down.
+    rasterization_scale: f32,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -139,7 +146,8 @@ pub struct RenderTask {
 impl RenderTask {
     pub fn new_alpha_batch(task_index: RenderTaskIndex,
                            screen_origin: DeviceIntPoint,
-                           location: RenderTaskLocation) -> RenderTask {
+                           location: RenderTaskLocation,
+                           rasterization_scale: f32) -> RenderTask {
         RenderTask {
             id: RenderTaskId::Static(task_index),
             children: Vec::new(),
@@ -147,14 +155,30 @@ impl RenderTask {
             kind: RenderTaskKind::Alpha(AlphaRenderTask {
                 screen_origin,
                 items: Vec::new(),
+                rasterization_scale,
             }),
         }
     }
 
     pub fn new_dynamic_alpha_batch(task_index: RenderTaskIndex,
-                                   rect: &DeviceIntRect) -> RenderTask {
-        let location = RenderTaskLocation::Dynamic(None, rect.size);
-        Self::new_alpha_batch(task_index, rect.origin, location)
+                                   rect: &DeviceIntRect,
+                                   max_target_size: i32,
+                                   quality_scale: f32) -> RenderTask {
+        // Tasks that wouldn't fit into a render target are rasterized at a
+        // reduced scale and upsampled when they are composited, instead of
+        // failing to allocate or being clipped. The quality settings apply
+        // an extra scale through the same path to shrink intermediate
+        // targets under memory pressure.
+        let max_dimension = cmp::max(rect.size.width, rect.size.height);
+        let rasterization_scale = quality_scale * if max_dimension > max_target_size {
+            max_target_size as f32 / max_dimension as f32
+        } else {
+            1.0
+        };
+        let size = DeviceIntSize::new((rect.size.width as f32 * rasterization_scale).ceil() as i32,
+                                      (rect.size.height as f32 * rasterization_scale).ceil() as i32);
+        let location = RenderTaskLocation::Dynamic(None, size);
+        Self::new_alpha_batch(task_index, rect.origin, location, rasterization_scale)
     }
 
     pub fn new_prim_cache(key: PrimitiveCacheKey,
@@ -288,6 +312,15 @@ impl RenderTask {
         }
     }
 
+    // For a clip mask task, the device rect the clips are known not to
+    // touch: every texel of the mask inside it is fully opaque.
+    pub fn inner_clip_rect(&self) -> Option<DeviceIntRect> {
+        match self.kind {
+            RenderTaskKind::CacheMask(ref task) => Some(task.inner_rect),
+            _ => None,
+        }
+    }
+
     // Write (up to) 8 floats of data specific to the type
     // of render task that is provided to the GPU shaders
     // via a vertex texture.
@@ -313,7 +346,7 @@ impl RenderTask {
                         task.screen_origin.x as f32,
                         task.screen_origin.y as f32,
                         target_index.0 as f32,
-                        0.0,
+                        task.rasterization_scale,
                         0.0,
                         0.0,
                         0.0,
@@ -437,6 +470,32 @@ impl RenderTask {
         }
     }
 
+    /// Append this task and its dependencies to a Graphviz DOT dump of the
+    /// render task graph. `depth` is the distance from the root task, which
+    /// matches the render pass the task ends up assigned to (passes run in
+    /// reverse depth order). This must be called before `assign_to_passes`,
+    /// which consumes the dependency links.
+    pub fn dump_as_dot(&self, depth: usize, output: &mut String) {
+        let kind = match self.kind {
+            RenderTaskKind::Alpha(..) => "Alpha",
+            RenderTaskKind::CachePrimitive(..) => "CachePrimitive",
+            RenderTaskKind::CacheMask(..) => "CacheMask",
+            RenderTaskKind::VerticalBlur(..) => "VerticalBlur",
+            RenderTaskKind::HorizontalBlur(..) => "HorizontalBlur",
+            RenderTaskKind::Readback(..) => "Readback",
+        };
+        let size = match self.location {
+            RenderTaskLocation::Fixed => "framebuffer".to_owned(),
+            RenderTaskLocation::Dynamic(_, size) => format!("{}x{}", size.width, size.height),
+        };
+        output.push_str(&format!("    \"{:?}\" [label=\"{}\\n{}\\ndepth {}\"];\n",
+                                 self.id, kind, size, depth));
+        for child in &self.children {
+            output.push_str(&format!("    \"{:?}\" -> \"{:?}\";\n", self.id, child.id));
+            child.dump_as_dot(depth + 1, output);
+        }
+    }
+
     pub fn target_kind(&self) -> RenderTargetKind {
         match self.kind {
             RenderTaskKind::Alpha(..) |
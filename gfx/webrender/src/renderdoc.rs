@@ -0,0 +1,136 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Integration with the RenderDoc in-application API.
+//!
+//! When the process is running under RenderDoc (or, on Android, has the
+//! RenderDoc capture layer loaded), this module looks up the in-application
+//! API so the renderer can trigger frame captures programmatically. When
+//! RenderDoc isn't present the lookup fails gracefully and capture requests
+//! become no-ops.
+
+use std::mem;
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr;
+
+/// eRENDERDOC_API_Version_1_1_2 from renderdoc_app.h.
+const RENDERDOC_API_VERSION_1_1_2: c_int = 10102;
+
+/// The prefix of RENDERDOC_API_1_1_2 from renderdoc_app.h. The real struct
+/// is a table of function pointers; entries we don't call are declared as
+/// untyped pointers to keep the offset of `trigger_capture` correct, and
+/// everything after it is omitted.
+#[repr(C)]
+#[allow(dead_code)]
+struct RenderDocApi {
+    get_api_version: extern "C" fn(major: *mut c_int, minor: *mut c_int, patch: *mut c_int),
+    set_capture_option_u32: *mut c_void,
+    set_capture_option_f32: *mut c_void,
+    get_capture_option_u32: *mut c_void,
+    get_capture_option_f32: *mut c_void,
+    set_focus_toggle_keys: *mut c_void,
+    set_capture_keys: *mut c_void,
+    get_overlay_bits: *mut c_void,
+    mask_overlay_bits: *mut c_void,
+    shutdown: *mut c_void,
+    unload_crash_handler: *mut c_void,
+    set_capture_file_path_template: *mut c_void,
+    get_capture_file_path_template: *mut c_void,
+    get_num_captures: *mut c_void,
+    get_capture: *mut c_void,
+    trigger_capture: extern "C" fn(),
+}
+
+type GetApiFn = extern "C" fn(version: c_int, out_pointers: *mut *mut c_void) -> c_int;
+
+/// A handle to the RenderDoc in-application API for this process.
+pub struct RenderDoc {
+    api: *const RenderDocApi,
+}
+
+impl RenderDoc {
+    /// Look up `RENDERDOC_GetAPI` in the already loaded RenderDoc library.
+    /// Returns `None` when the process isn't running under RenderDoc.
+    pub fn load() -> Option<RenderDoc> {
+        let get_api = match get_api_entry_point() {
+            Some(get_api) => get_api,
+            None => return None,
+        };
+
+        let mut api: *mut c_void = ptr::null_mut();
+        if get_api(RENDERDOC_API_VERSION_1_1_2, &mut api) != 1 || api.is_null() {
+            return None;
+        }
+
+        Some(RenderDoc {
+            api: api as *const RenderDocApi,
+        })
+    }
+
+    /// Ask RenderDoc to capture the next frame the application presents.
+    pub fn trigger_capture(&self) {
+        unsafe {
+            ((*self.api).trigger_capture)();
+        }
+    }
+}
+
+#[cfg(unix)]
+fn get_api_entry_point() -> Option<GetApiFn> {
+    extern "C" {
+        fn dlopen(filename: *const c_char, flag: c_int) -> *mut c_void;
+        fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    }
+
+    // RTLD_NOW | RTLD_NOLOAD: only succeed if RenderDoc has already been
+    // injected into the process; we never want to pull the library in
+    // ourselves. RTLD_NOW is 0 on 32-bit bionic, so leave it out there.
+    #[cfg(all(target_os = "android", target_pointer_width = "32"))]
+    const RTLD_FLAGS: c_int = 0x4;
+    #[cfg(not(all(target_os = "android", target_pointer_width = "32")))]
+    const RTLD_FLAGS: c_int = 0x2 | 0x4;
+
+    #[cfg(target_os = "android")]
+    const LIB_NAME: &'static [u8] = b"libVkLayer_GLES_RenderDoc.so\0";
+    #[cfg(not(target_os = "android"))]
+    const LIB_NAME: &'static [u8] = b"librenderdoc.so\0";
+
+    unsafe {
+        let handle = dlopen(LIB_NAME.as_ptr() as *const c_char, RTLD_FLAGS);
+        if handle.is_null() {
+            return None;
+        }
+        let sym = dlsym(handle, b"RENDERDOC_GetAPI\0".as_ptr() as *const c_char);
+        if sym.is_null() {
+            return None;
+        }
+        Some(mem::transmute(sym))
+    }
+}
+
+#[cfg(windows)]
+fn get_api_entry_point() -> Option<GetApiFn> {
+    extern "system" {
+        fn GetModuleHandleA(name: *const c_char) -> *mut c_void;
+        fn GetProcAddress(module: *mut c_void, name: *const c_char) -> *mut c_void;
+    }
+
+    unsafe {
+        // GetModuleHandle doesn't load the library, matching RTLD_NOLOAD.
+        let module = GetModuleHandleA(b"renderdoc.dll\0".as_ptr() as *const c_char);
+        if module.is_null() {
+            return None;
+        }
+        let sym = GetProcAddress(module, b"RENDERDOC_GetAPI\0".as_ptr() as *const c_char);
+        if sym.is_null() {
+            return None;
+        }
+        Some(mem::transmute(sym))
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn get_api_entry_point() -> Option<GetApiFn> {
+    None
+}
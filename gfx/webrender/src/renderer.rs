@@ -12,45 +12,57 @@
 use debug_colors;
 use debug_render::DebugRenderer;
 use device::{DepthFunction, Device, FrameId, Program, TextureId, VertexDescriptor, GpuMarker, GpuProfiler, PBOId};
-use device::{GpuSample, TextureFilter, VAOId, VertexUsageHint, FileWatcherHandler, TextureTarget, ShaderError};
-use device::{get_gl_format_bgra, VertexAttribute, VertexAttributeKind};
+use device::{ComputeProgram, GpuSample, TextureFilter, VAOId, VertexUsageHint, FileWatcherHandler, TextureTarget, ShaderError};
+use device::DeviceEventSink;
+use device::ShaderPreprocessor;
+use device::{get_gl_format_bgra, UniformLocation, VertexAttribute, VertexAttributeKind};
+use dpr_audit::{self, DprAuditFailure};
 use euclid::{Transform3D, rect};
 use frame_builder::FrameBuilderConfig;
+use frame_capture::{CapturedFrame, FrameSequenceEncoder};
 use gleam::gl;
 use gpu_cache::{GpuBlockData, GpuCacheUpdate, GpuCacheUpdateList};
-use internal_types::{FastHashMap, CacheTextureId, RendererFrame, ResultMsg, TextureUpdateOp};
+use internal_types::{FastHashMap, CacheTextureId, RendererFrame, ResultMsg, TextureUpdate, TextureUpdateOp};
 use internal_types::{TextureUpdateList, RenderTargetMode};
+use internal_types::{TextureCacheDebugInfo, TextureCacheOwner, QualitySettings};
 use internal_types::{ORTHO_NEAR_PLANE, ORTHO_FAR_PLANE, SourceTexture};
 use internal_types::{BatchTextures, TextureSampler};
 use profiler::{Profiler, BackendProfileCounters};
-use profiler::{GpuProfileTag, RendererProfileTimers, RendererProfileCounters};
+use profiler::{GpuProfileTag, PipelineProfile, RendererProfileTimers, RendererProfileCounters};
 use record::ApiRecordingReceiver;
-use render_backend::RenderBackend;
+use render_backend::{new_worker_pool, RenderBackend, WorkerPoolSettings};
+#[cfg(feature = "renderdoc")]
+use renderdoc::RenderDoc;
 use render_task::RenderTaskData;
+use scene_builder::SceneBuilder;
 use std;
 use std::cmp;
 use std::collections::VecDeque;
 use std::f32;
+use std::io;
 use std::marker::PhantomData;
 use std::mem;
 use std::path::PathBuf;
 use std::rc::Rc;
+use std::slice;
 use std::sync::{Arc, Mutex};
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::thread;
 use texture_cache::TextureCache;
 use rayon::ThreadPool;
-use rayon::Configuration as ThreadPoolConfig;
 use tiling::{AlphaBatchKind, BlurCommand, CompositePrimitiveInstance, Frame, PrimitiveBatch, RenderTarget};
 use tiling::{AlphaRenderTarget, CacheClipInstance, PrimitiveInstance, ColorRenderTarget, RenderTargetKind};
 use time::precise_time_ns;
 use thread_profiler::{register_thread_with_profiler, write_profile};
 use util::TransformedRectKind;
 use webgl_types::GLContextHandleWrapper;
-use api::{ColorF, Epoch, PipelineId, RenderApiSender, RenderNotifier, RenderDispatcher};
+use workarounds::GpuInfo;
+use api::{ColorF, ColorU, Epoch, ItemTag, PipelineId, RenderApiSender, RenderNotifier, RenderDispatcher};
+use api::RendererError;
+use api::OutputColorTransform;
 use api::{ExternalImageId, ExternalImageType, ImageData, ImageFormat};
 use api::{DeviceIntRect, DeviceUintRect, DeviceIntPoint, DeviceIntSize, DeviceUintSize};
-use api::{BlobImageRenderer, channel, FontRenderMode};
+use api::{ApiMsg, BlobImageRenderer, channel, FontRenderMode, MemoryPressureLevel, SchedulingPolicy, TileSize};
 use api::VRCompositorHandler;
 use api::{YuvColorSpace, YuvFormat};
 use api::{YUV_COLOR_SPACES, YUV_FORMATS};
@@ -58,6 +70,26 @@ use api::{YUV_COLOR_SPACES, YUV_FORMATS};
 pub const GPU_DATA_TEXTURE_POOL: usize = 5;
 pub const MAX_VERTEX_TEXTURE_WIDTH: usize = 1024;
 
+// The texture cache upload bytes processed per frame before the rest of
+// the pending updates spill over to the next frame. A tab switch can
+// enqueue hundreds of megabytes of uploads at once; feeding them to the
+// driver in one go stalls the frame for the whole transfer.
+const MAX_TEXTURE_UPLOAD_BYTES_PER_FRAME: usize = 64 * 1024 * 1024;
+
+/// Stub standing in for `renderdoc::RenderDoc` when the `renderdoc` feature
+/// is disabled, so the renderer doesn't need to gate every use site.
+#[cfg(not(feature = "renderdoc"))]
+struct RenderDoc;
+
+#[cfg(not(feature = "renderdoc"))]
+impl RenderDoc {
+    fn load() -> Option<RenderDoc> {
+        None
+    }
+
+    fn trigger_capture(&self) {}
+}
+
 const GPU_TAG_CACHE_BOX_SHADOW: GpuProfileTag = GpuProfileTag { label: "C_BoxShadow", color: debug_colors::BLACK };
 const GPU_TAG_CACHE_CLIP: GpuProfileTag = GpuProfileTag { label: "C_Clip", color: debug_colors::PURPLE };
 const GPU_TAG_CACHE_TEXT_RUN: GpuProfileTag = GpuProfileTag { label: "C_TextRun", color: debug_colors::MISTYROSE };
@@ -88,6 +120,25 @@ bitflags! {
         const PROFILER_DBG      = 1 << 0;
         const RENDER_TARGET_DBG = 1 << 1;
         const TEXTURE_CACHE_DBG = 1 << 2;
+        /// Label allocations in the texture cache debug view with the
+        /// image / glyph that owns them.
+        const TEXTURE_CACHE_INSPECT_DBG = 1 << 3;
+        /// Flash tagged primitives whose content changed since the
+        /// previous display list (paint flashing).
+        const PAINT_FLASHING_DBG = 1 << 4;
+        /// A minimal profiler overlay - FPS, a frame time sparkline and
+        /// the draw call count - suitable for leaving enabled during
+        /// development. Ignored while the full profiler is shown.
+        const COMPACT_PROFILER_DBG = 1 << 5;
+        /// Disable instanced batching: every primitive is submitted as
+        /// its own draw call. The runtime equivalent of creating the
+        /// renderer with `enable_batcher` off, for bisecting batching
+        /// bugs without restarting.
+        const DISABLE_BATCHING_DBG = 1 << 6;
+        /// Draw only the first N batches of each frame, where N is
+        /// stepped with `Renderer::step_debug_batch`, so a frame can be
+        /// watched building up batch by batch.
+        const BATCH_STEP_DBG = 1 << 7;
     }
 }
 
@@ -131,6 +182,22 @@ const DESC_CLIP: VertexDescriptor = VertexDescriptor {
     ]
 };
 
+const DESC_OUTPUT_TRANSFORM: VertexDescriptor = VertexDescriptor {
+    vertex_attributes: &[
+        VertexAttribute { name: "aPosition", count: 2, kind: VertexAttributeKind::F32 },
+        VertexAttribute { name: "aColorTexCoord", count: 2, kind: VertexAttributeKind::F32 },
+    ],
+    instance_attributes: &[]
+};
+
+#[repr(C)]
+struct OutputTransformVertex {
+    x: f32,
+    y: f32,
+    u: f32,
+    v: f32,
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum VertexFormat {
     PrimitiveInstances,
@@ -148,6 +215,9 @@ pub struct GraphicsApiInfo {
     pub kind: GraphicsApi,
     pub renderer: String,
     pub version: String,
+    /// The parsed GL strings plus the driver bug flags derived from
+    /// them, for blocklisting decisions in the embedder.
+    pub gpu: GpuInfo,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
@@ -198,6 +268,43 @@ pub enum RendererKind {
     OSMesa,
 }
 
+/// The classic 8x8 ordered Bayer matrix, with 64 levels.
+const BAYER_DITHER_MATRIX: [u8; 64] = [
+    00, 48, 12, 60, 03, 51, 15, 63,
+    32, 16, 44, 28, 35, 19, 47, 31,
+    08, 56, 04, 52, 11, 59, 07, 55,
+    40, 24, 36, 20, 43, 27, 39, 23,
+    02, 50, 14, 62, 01, 49, 13, 61,
+    34, 18, 46, 30, 33, 17, 45, 29,
+    10, 58, 06, 54, 09, 57, 05, 53,
+    42, 26, 38, 22, 41, 25, 37, 21
+];
+
+/// A 64x64 blue noise matrix with the full 8 bit range, generated with
+/// the void-and-cluster method.
+const BLUE_NOISE_DITHER_MATRIX: &'static [u8] = include_bytes!("../res/blue_noise_64x64.raw");
+
+/// The matrix used by the dithered gradient shaders.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DitherMatrixKind {
+    /// The ordered Bayer matrix. Cheap, but shows visible patterning on
+    /// large shallow gradients.
+    Bayer,
+    /// A blue noise matrix, which trades a larger texture for noise
+    /// without visible structure.
+    BlueNoise,
+}
+
+impl DitherMatrixKind {
+    /// Returns the side length and texel data of the matrix.
+    fn texture_data(&self) -> (u32, &'static [u8]) {
+        match *self {
+            DitherMatrixKind::Bayer => (8, &BAYER_DITHER_MATRIX),
+            DitherMatrixKind::BlueNoise => (64, BLUE_NOISE_DITHER_MATRIX),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct GpuProfile {
     pub frame_id: FrameId,
@@ -239,14 +346,238 @@ impl CpuProfile {
     }
 }
 
+/// Statistics about the render passes and intermediate targets of a frame,
+/// recorded per frame alongside `CpuProfile`. Lets embedders alert on
+/// pathological pages that build very deep render pass chains.
+#[derive(Debug)]
+pub struct FrameTargetProfile {
+    pub frame_id: FrameId,
+    /// Number of render passes, including the framebuffer pass.
+    pub pass_count: usize,
+    /// Total pixels allocated to intermediate color and alpha targets.
+    pub target_pixels: usize,
+    /// Total bytes allocated to intermediate targets.
+    pub target_bytes: usize,
+    /// The largest target working set any single pass touches - its own
+    /// targets plus the previous pass outputs it samples. With an
+    /// allocator that recycled targets between passes, this would be the
+    /// minimum target memory the frame needs.
+    pub peak_target_bytes: usize,
+}
+
+impl FrameTargetProfile {
+    fn new(frame_id: FrameId, frame: &Frame) -> FrameTargetProfile {
+        let mut target_pixels = 0;
+        let mut target_bytes = 0;
+        let mut peak_target_bytes = 0;
+        let mut prev_pass_bytes = 0;
+        for pass in &frame.passes {
+            let pass_bytes = pass.intermediate_target_bytes();
+            target_pixels += pass.intermediate_target_pixels();
+            target_bytes += pass_bytes;
+            peak_target_bytes = cmp::max(peak_target_bytes,
+                                         prev_pass_bytes + pass_bytes);
+            prev_pass_bytes = pass_bytes;
+        }
+        FrameTargetProfile {
+            frame_id,
+            pass_count: frame.passes.len(),
+            target_pixels,
+            target_bytes,
+            peak_target_bytes,
+        }
+    }
+}
+
+/// The texture the last frame was rendered into, handed to embedders
+/// that do their own final composition. See
+/// `RendererOptions::render_to_texture` and `Renderer::get_frame_output`.
+pub struct FrameOutput {
+    /// GL name of the texture. Owned by WebRender; sample it, don't
+    /// delete it. The contents have the same orientation as the window
+    /// framebuffer would.
+    pub texture_id: gl::GLuint,
+    /// Size of the texture in device pixels.
+    pub size: DeviceUintSize,
+    /// Fence inserted after the last draw into the texture. Wait on it
+    /// before sampling, then delete it; ownership passes to the caller.
+    pub sync: gl::GLsync,
+}
+
+/// State of an active frame sequence capture; see
+/// `Renderer::start_frame_sequence_capture`.
+struct FrameCapture {
+    encoder: FrameSequenceEncoder,
+    /// Rotating capture targets the presented frame is blitted into.
+    /// Reading a target back is deferred until the next frame starts, by
+    /// which point the GPU is done writing it, so two targets rotate to
+    /// keep the blit and the readback off the same texture.
+    targets: [TextureId; 2],
+    next_target: usize,
+    /// The target filled this frame, to read back when the next frame
+    /// starts.
+    pending: Option<(TextureId, DeviceUintSize)>,
+}
+
+/// Per-stage CPU time budgets, in nanoseconds. When set on
+/// `RendererOptions::cpu_stage_budgets`, every frame is checked against
+/// them and overruns are logged and recorded, so automated tests can fail
+/// on performance regressions. Stages without a budget are not checked.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CpuStageBudgets {
+    /// Budget for building the frame on the backend thread.
+    pub backend_ns: Option<u64>,
+    /// Budget for compositing the frame on the render thread.
+    pub composite_ns: Option<u64>,
+    /// Budget for uploading pending texture cache updates, which is part
+    /// of the composite stage but is sensitive to resource churn rather
+    /// than scene complexity.
+    pub texture_update_ns: Option<u64>,
+}
+
+/// A recorded CPU budget overrun. See `Renderer::take_cpu_budget_overruns`.
+#[derive(Clone, Copy, Debug)]
+pub struct CpuBudgetOverrun {
+    pub frame_id: FrameId,
+    pub stage: &'static str,
+    pub actual_ns: u64,
+    pub budget_ns: u64,
+}
+
+/// Aggregated distribution of one timing metric, in nanoseconds.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TimingStats {
+    pub mean_ns: u64,
+    pub median_ns: u64,
+    pub p95_ns: u64,
+    pub p99_ns: u64,
+}
+
+impl TimingStats {
+    fn compute(mut samples: Vec<u64>) -> TimingStats {
+        if samples.is_empty() {
+            return TimingStats::default();
+        }
+
+        samples.sort();
+        let sum: u64 = samples.iter().sum();
+        let mean_ns = sum / samples.len() as u64;
+
+        // Nearest-rank percentile: the smallest sample that at least p
+        // percent of the samples are less than or equal to.
+        let percentile = |p: usize| {
+            let rank = (p * samples.len() + 99) / 100;
+            samples[cmp::max(rank, 1) - 1]
+        };
+
+        TimingStats {
+            mean_ns,
+            median_ns: percentile(50),
+            p95_ns: percentile(95),
+            p99_ns: percentile(99),
+        }
+    }
+}
+
+/// Frame timing statistics aggregated over recently rendered frames, for
+/// embedder telemetry. See `Renderer::get_frame_stats`.
+#[derive(Clone, Debug, Default)]
+pub struct FrameStats {
+    /// The number of frames the CPU statistics were computed over.
+    pub cpu_frames: usize,
+    /// The number of frames the GPU statistics were computed over. GPU
+    /// timings are only collected while the GPU profiler is enabled, so
+    /// this can be zero even when CPU frames were recorded.
+    pub gpu_frames: usize,
+    pub backend_time: TimingStats,
+    pub composite_time: TimingStats,
+    pub gpu_time: TimingStats,
+}
+
+/// Decides whether it is worth starting to draw a frame right now, based on
+/// vsync timestamps supplied by the embedder and the measured cost of recent
+/// frames. If an average frame no longer fits in the time left before the
+/// next vblank, drawing is deferred until just after it, rather than starting
+/// a composite that is guaranteed to miss.
+struct FrameScheduler {
+    /// Timestamp of the most recent vblank, as reported by the embedder.
+    last_vsync_ns: u64,
+    /// Estimated time between vblanks. Zero until two vsync notifications
+    /// have been received, which disables scheduling entirely.
+    vsync_interval_ns: u64,
+    /// Exponential moving averages of recent frame costs.
+    avg_cpu_cost_ns: u64,
+    avg_gpu_cost_ns: u64,
+}
+
+impl FrameScheduler {
+    fn new() -> FrameScheduler {
+        FrameScheduler {
+            last_vsync_ns: 0,
+            vsync_interval_ns: 0,
+            avg_cpu_cost_ns: 0,
+            avg_gpu_cost_ns: 0,
+        }
+    }
+
+    fn notify_vsync(&mut self, timestamp_ns: u64) {
+        if self.last_vsync_ns > 0 && timestamp_ns > self.last_vsync_ns {
+            let interval = timestamp_ns - self.last_vsync_ns;
+            // Ignore implausible intervals (a stalled compositor, a dropped
+            // notification) so that one bad sample doesn't skew the estimate.
+            if interval > 1_000_000 && interval < 100_000_000 {
+                self.vsync_interval_ns = if self.vsync_interval_ns == 0 {
+                    interval
+                } else {
+                    (self.vsync_interval_ns * 3 + interval) / 4
+                };
+            }
+        }
+        self.last_vsync_ns = timestamp_ns;
+    }
+
+    fn record_frame_cost(&mut self, cpu_cost_ns: u64, gpu_cost_ns: u64) {
+        self.avg_cpu_cost_ns = (self.avg_cpu_cost_ns * 3 + cpu_cost_ns) / 4;
+        self.avg_gpu_cost_ns = (self.avg_gpu_cost_ns * 3 + gpu_cost_ns) / 4;
+    }
+
+    fn should_render(&self, now_ns: u64) -> bool {
+        // Without vsync information, or before any frame cost has been
+        // measured, draw unconditionally.
+        if self.vsync_interval_ns == 0 {
+            return true;
+        }
+
+        let estimated_cost = self.avg_cpu_cost_ns + self.avg_gpu_cost_ns;
+        if estimated_cost >= self.vsync_interval_ns {
+            // The frame doesn't fit in a whole interval; deferring it can
+            // only add latency.
+            return true;
+        }
+
+        let elapsed = now_ns.saturating_sub(self.last_vsync_ns);
+        let until_vblank = self.vsync_interval_ns - (elapsed % self.vsync_interval_ns);
+        estimated_cost <= until_vblank
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum BlendMode {
     None,
     Alpha,
     PremultipliedAlpha,
+    // Multiply / screen the (premultiplied) source with the backdrop,
+    // used by hardware composite ops.
+    Multiply,
+    Screen,
 
     // Use the color of the text itself as a constant color blend factor.
-    Subpixel(ColorF),
+    // The color is quantized to 8 bits per channel, so text runs whose
+    // colors only differ below display precision share a batch. This is
+    // the one remaining way a glyph color enters batch state: the alpha
+    // path reads its color per instance from the gpu cache, and a dual
+    // source blending path could do the same here.
+    Subpixel(ColorU),
 }
 
 // Tracks the state of each row in the GPU cache texture.
@@ -312,7 +643,22 @@ impl CacheTexture {
         }
     }
 
-    fn update(&mut self, device: &mut Device, updates: &GpuCacheUpdateList) {
+    /// Drops the CPU-side shadow copy of the cache data in response to
+    /// memory pressure, returning the bytes released. Only valid when
+    /// the backend restarts its side of the gpu cache at the same time,
+    /// so that everything still referenced is patched in again.
+    fn drop_shadow_copy(&mut self) -> usize {
+        let bytes = self.cpu_blocks.len() * mem::size_of::<GpuBlockData>() +
+                    self.rows.len() * mem::size_of::<CacheRow>();
+        self.rows = Vec::new();
+        self.cpu_blocks = Vec::new();
+        bytes
+    }
+
+    fn update(&mut self,
+              device: &mut Device,
+              updates: &GpuCacheUpdateList,
+              counters: &mut RendererProfileCounters) {
         // See if we need to create or resize the texture.
         let current_dimensions = device.get_texture_dimensions(self.texture_id);
         if updates.height > current_dimensions.height {
@@ -325,6 +671,7 @@ impl CacheTexture {
                                 TextureFilter::Nearest,
                                 RenderTargetMode::None,
                                 None);
+            device.set_texture_label(self.texture_id, "gpu cache");
 
             // Copy the current texture into the newly resized texture.
             if current_dimensions.height > 0 {
@@ -339,10 +686,48 @@ impl CacheTexture {
 
         for update in &updates.updates {
             self.apply_patch(update, &updates.blocks);
+            match *update {
+                GpuCacheUpdate::Copy { block_count, .. } => {
+                    counters.gpu_cache_block_updates.add(block_count);
+                }
+            }
         }
     }
 
-    fn flush(&mut self, device: &mut Device) {
+    fn flush(&mut self, device: &mut Device, counters: &mut RendererProfileCounters) {
+        // Record how much of the cache texture this frame touches, so
+        // that a regression that dirties the whole cache every frame
+        // shows up in the HUD.
+        let dirty_rows = self.rows.iter().filter(|row| row.is_dirty).count();
+        counters.gpu_cache_dirty_rows.add(dirty_rows);
+        counters.gpu_cache_upload_kb.add(
+            dirty_rows * MAX_VERTEX_TEXTURE_WIDTH * mem::size_of::<GpuBlockData>() / 1024);
+
+        // PBO uploads are themselves a driver bug on some GPUs; upload the
+        // dirty rows straight from client memory there instead. See
+        // `Capabilities::avoid_pbo_uploads`.
+        if device.get_capabilities().avoid_pbo_uploads {
+            for (row_index, row) in self.rows.iter_mut().enumerate() {
+                if row.is_dirty {
+                    let block_index = row_index * MAX_VERTEX_TEXTURE_WIDTH;
+                    let cpu_blocks = &self.cpu_blocks[block_index..(block_index + MAX_VERTEX_TEXTURE_WIDTH)];
+                    let data = unsafe {
+                        slice::from_raw_parts(cpu_blocks.as_ptr() as *const u8,
+                                              cpu_blocks.len() * mem::size_of::<GpuBlockData>())
+                    };
+                    device.update_texture(self.texture_id,
+                                          0,
+                                          row_index as u32,
+                                          MAX_VERTEX_TEXTURE_WIDTH as u32,
+                                          1,
+                                          None,
+                                          data);
+                    row.is_dirty = false;
+                }
+            }
+            return;
+        }
+
         // Bind a PBO to do the texture upload.
         // Updating the texture via PBO avoids CPU-side driver stalls.
         device.bind_pbo(Some(self.pbo_id));
@@ -554,6 +939,11 @@ impl LazilyCompiledShader {
             device.delete_program(program);
         }
     }
+
+    fn reset(&mut self, device: &mut Device) {
+        self.deinit(device);
+        self.program = None;
+    }
 }
 
 struct PrimitiveShader {
@@ -618,6 +1008,11 @@ impl PrimitiveShader {
         self.simple.deinit(device);
         self.transform.deinit(device);
     }
+
+    fn reset(&mut self, device: &mut Device) {
+        self.simple.reset(device);
+        self.transform.reset(device);
+    }
 }
 
 fn create_prim_shader(name: &'static str,
@@ -631,6 +1026,10 @@ fn create_prim_shader(name: &'static str,
         prefix.push_str(&format!("#define WR_FEATURE_{}\n", feature));
     }
 
+    if device.get_capabilities().avoid_texture_arrays {
+        prefix.push_str("#define WR_FEATURE_TEXTURE_2D_TARGETS\n");
+    }
+
     debug!("PrimShader {}", name);
 
     let includes = &["prim_shared"];
@@ -647,17 +1046,339 @@ fn create_prim_shader(name: &'static str,
                                       &vertex_descriptor)
 }
 
+/// Log a non-fatal renderer error and forward it to the embedder. A free
+/// function rather than a method so that it can be called while other parts
+/// of the renderer are mutably borrowed.
+// An estimate of the bytes a texture cache op uploads to the driver,
+// for the per-frame upload budget. Structural ops and GPU-side copies
+// are free.
+fn texture_upload_cost(op: &TextureUpdateOp) -> usize {
+    match *op {
+        TextureUpdateOp::Create { width, height, format, ref data, .. } => {
+            match *data {
+                Some(_) => (width * height * format.bytes_per_pixel().unwrap_or(0)) as usize,
+                None => 0,
+            }
+        }
+        TextureUpdateOp::Update { height, ref data, stride, offset, .. } => {
+            match stride {
+                Some(stride) => (height * stride) as usize,
+                None => data.len() - offset as usize,
+            }
+        }
+        TextureUpdateOp::UpdateForExternalBuffer { ref rect, stride, .. } => {
+            let row_bytes = stride.unwrap_or(rect.size.width * 4);
+            (rect.size.height * row_bytes) as usize
+        }
+        TextureUpdateOp::Grow { .. } |
+        TextureUpdateOp::CopyRect { .. } |
+        TextureUpdateOp::Free => 0,
+    }
+}
+
+fn report_renderer_error(notifier: &Mutex<Option<Box<RenderNotifier>>>,
+                         error: RendererError) {
+    error!("renderer error: {:?}", error);
+    let mut notifier = notifier.lock().unwrap();
+    if let Some(ref mut notifier) = *notifier {
+        notifier.renderer_error(error);
+    }
+}
+
+/// The clip rectangle compute shader takes its instances through a uniform
+/// array of this many entries. Targets with a clip task that needs more
+/// instances than this fall back to the raster path.
+const MAX_COMPUTE_CLIP_INSTANCES: usize = 8;
+
+/// Group size declared by the compute shaders, in pixels per side.
+const COMPUTE_GROUP_SIZE: u32 = 8;
+
+/// Converts batch screen bounds into a GL scissor rect for the given
+/// target. The main framebuffer is rendered with a flipped y axis, so
+/// rects headed there are flipped to match; the bounds are in content
+/// space, so any surface orientation is applied first.
+fn scissor_rect_for_target(bounds: &DeviceIntRect,
+                           render_target: Option<(TextureId, i32)>,
+                           target_size: DeviceUintSize,
+                           orientation: SurfaceOrientation) -> DeviceIntRect {
+    match render_target {
+        Some(..) => *bounds,
+        None => {
+            let bounds = orientation.surface_rect(bounds, &target_size);
+            let origin_y = target_size.height as i32 -
+                           bounds.origin.y - bounds.size.height;
+            DeviceIntRect::new(DeviceIntPoint::new(bounds.origin.x, origin_y),
+                               bounds.size)
+        }
+    }
+}
+
+/// Claims the next `count` instances of the current target's instance
+/// arena, returning the offset the draw should start at, or `None` when
+/// the arena path is not in use.
+fn claim_arena_slice(use_arena: bool,
+                     cursor: &mut usize,
+                     count: usize) -> Option<usize> {
+    if use_arena {
+        let first_instance = *cursor;
+        *cursor += count;
+        Some(first_instance)
+    } else {
+        None
+    }
+}
+
+fn compute_group_count(size: u32) -> u32 {
+    (size + COMPUTE_GROUP_SIZE - 1) / COMPUTE_GROUP_SIZE
+}
+
+fn create_compute_shader(name: &'static str, device: &mut Device) -> Option<ComputeProgram> {
+    let prefix = format!("#define WR_MAX_VERTEX_TEXTURE_WIDTH {}\n\
+                          #define WR_MAX_COMPUTE_CLIP_INSTANCES {}\n",
+                         MAX_VERTEX_TEXTURE_WIDTH,
+                         MAX_COMPUTE_CLIP_INSTANCES);
+
+    debug!("ComputeShader {}", name);
+
+    // The compute variants are an optimization: if one fails to compile,
+    // report it and stay on the raster path.
+    match device.create_compute_program(name, &["compute_shared"], Some(prefix)) {
+        Ok(program) => Some(program),
+        Err(err) => {
+            error!("Failed to create compute shader {}: {:?}", name, err);
+            None
+        }
+    }
+}
+
+/// Returns true if every run of clip rectangle instances that shares a
+/// task fits in the instance array of the compute shader. Instances are
+/// pushed task by task, so equal task ids are always adjacent.
+fn clip_task_runs_fit(rectangles: &[CacheClipInstance]) -> bool {
+    let mut run_start = 0;
+    for i in 0 .. rectangles.len() {
+        if rectangles[i].task_id != rectangles[run_start].task_id {
+            run_start = i;
+        }
+        if i - run_start + 1 > MAX_COMPUTE_CLIP_INSTANCES {
+            return false;
+        }
+    }
+    true
+}
+
 fn create_clip_shader(name: &'static str, device: &mut Device) -> Result<Program, ShaderError> {
-    let prefix = format!("#define WR_MAX_VERTEX_TEXTURE_WIDTH {}\n
+    let mut prefix = format!("#define WR_MAX_VERTEX_TEXTURE_WIDTH {}\n
                           #define WR_FEATURE_TRANSFORM",
                           MAX_VERTEX_TEXTURE_WIDTH);
 
+    if device.get_capabilities().avoid_texture_arrays {
+        prefix.push_str("\n#define WR_FEATURE_TEXTURE_2D_TARGETS");
+    }
+
     debug!("ClipShader {}", name);
 
     let includes = &["prim_shared", "clip_shared"];
     device.create_program_with_prefix(name, includes, Some(prefix), &DESC_CLIP)
 }
 
+/// One shader program that `Renderer::new` can build: the base shader
+/// name and the `WR_FEATURE_*` names it is compiled with.
+#[derive(Clone, Debug)]
+pub struct ShaderPermutation {
+    pub name: &'static str,
+    pub features: Vec<&'static str>,
+    includes: &'static [&'static str],
+}
+
+impl ShaderPermutation {
+    fn prim(name: &'static str, features: &[&'static str]) -> ShaderPermutation {
+        ShaderPermutation {
+            name,
+            features: features.to_vec(),
+            includes: &["prim_shared"],
+        }
+    }
+
+    fn clip(name: &'static str) -> ShaderPermutation {
+        ShaderPermutation {
+            name,
+            features: vec![TRANSFORM_FEATURE],
+            includes: &["prim_shared", "clip_shared"],
+        }
+    }
+
+    /// The same shader built with one more feature enabled.
+    fn with_feature(&self, feature: &'static str) -> ShaderPermutation {
+        let mut features = self.features.clone();
+        features.push(feature);
+        ShaderPermutation {
+            name: self.name,
+            features,
+            includes: self.includes,
+        }
+    }
+
+    /// Returns the flattened (vertex, fragment) sources for this
+    /// permutation, as they would be handed to the driver, minus the
+    /// leading `#version` line that the device adds at compile time.
+    pub fn build_sources(&self) -> (String, String) {
+        let mut prefix = format!("#define WR_MAX_VERTEX_TEXTURE_WIDTH {}\n",
+                                 MAX_VERTEX_TEXTURE_WIDTH);
+        for feature in &self.features {
+            prefix.push_str(&format!("#define WR_FEATURE_{}\n", feature));
+        }
+        let prefix = Some(prefix);
+
+        let mut preprocessor = ShaderPreprocessor::new(None);
+        let vs = preprocessor.process(self.name,
+                                      ".vs",
+                                      "#define WR_VERTEX_SHADER\n",
+                                      &prefix,
+                                      self.includes);
+        let fs = preprocessor.process(self.name,
+                                      ".fs",
+                                      "#define WR_FRAGMENT_SHADER\n",
+                                      &prefix,
+                                      self.includes);
+        ((*vs).clone(), (*fs).clone())
+    }
+}
+
+/// Returns every shader permutation that `Renderer::new` can build,
+/// across all renderer options, image buffer kinds and driver
+/// workarounds. Used for offline shader validation; see
+/// `validate_shaders`.
+pub fn shader_permutations() -> Vec<ShaderPermutation> {
+    let mut permutations = Vec::new();
+
+    // Cache shaders.
+    permutations.push(ShaderPermutation::prim("cs_box_shadow", &[]));
+    permutations.push(ShaderPermutation::prim("cs_text_run", &[]));
+    permutations.push(ShaderPermutation::prim("ps_line", &["CACHE"]));
+    permutations.push(ShaderPermutation::prim("cs_blur", &[]));
+
+    // Clip mask shaders. These are always built with the transform
+    // feature.
+    permutations.push(ShaderPermutation::clip("cs_clip_rectangle"));
+    permutations.push(ShaderPermutation::clip("cs_clip_image"));
+    permutations.push(ShaderPermutation::clip("cs_clip_border"));
+
+    // Primitive shaders. Each is built both with and without the
+    // transform feature.
+    let mut prims = Vec::new();
+    prims.push(ShaderPermutation::prim("ps_rectangle", &[]));
+    prims.push(ShaderPermutation::prim("ps_rectangle", &[CLIP_FEATURE]));
+    prims.push(ShaderPermutation::prim("ps_line", &[]));
+    prims.push(ShaderPermutation::prim("ps_text_run", &[]));
+    prims.push(ShaderPermutation::prim("ps_text_run", &[SUBPIXEL_AA_FEATURE]));
+
+    for buffer_kind in &IMAGE_BUFFER_KINDS {
+        let mut image_features = Vec::new();
+        let feature_string = buffer_kind.get_feature_string();
+        if feature_string != "" {
+            image_features.push(feature_string);
+        }
+        prims.push(ShaderPermutation::prim("ps_image", &image_features));
+
+        for format in &YUV_FORMATS {
+            for color_space in &YUV_COLOR_SPACES {
+                let mut yuv_features = image_features.clone();
+                let feature_string = format.get_feature_string();
+                if feature_string != "" {
+                    yuv_features.push(feature_string);
+                }
+                let feature_string = color_space.get_feature_string();
+                if feature_string != "" {
+                    yuv_features.push(feature_string);
+                }
+                prims.push(ShaderPermutation::prim("ps_yuv_image", &yuv_features));
+            }
+        }
+    }
+
+    prims.push(ShaderPermutation::prim("ps_border_corner", &[]));
+    prims.push(ShaderPermutation::prim("ps_border_edge", &[]));
+    prims.push(ShaderPermutation::prim("ps_box_shadow", &[]));
+
+    // Gradients, with every dither matrix the options can select.
+    for &name in &["ps_gradient", "ps_angle_gradient", "ps_radial_gradient"] {
+        prims.push(ShaderPermutation::prim(name, &[]));
+        prims.push(ShaderPermutation::prim(name, &["DITHERING"]));
+        prims.push(ShaderPermutation::prim(name, &["DITHERING", "BLUE_NOISE_DITHERING"]));
+    }
+
+    prims.push(ShaderPermutation::prim("ps_cache_image", &[]));
+
+    for prim in prims {
+        permutations.push(prim.with_feature(TRANSFORM_FEATURE));
+        permutations.push(prim);
+    }
+
+    // The composite shaders have no transform variant.
+    permutations.push(ShaderPermutation::prim("ps_blend", &[]));
+    permutations.push(ShaderPermutation::prim("ps_composite", &[]));
+    permutations.push(ShaderPermutation::prim("ps_hardware_composite", &[]));
+    permutations.push(ShaderPermutation::prim("ps_split_composite", &[]));
+
+    // Each of the above can additionally be built against the 2D render
+    // target fallback; see `WR_FEATURE_TEXTURE_2D_TARGETS`.
+    let fallback: Vec<ShaderPermutation> = permutations.iter()
+        .map(|permutation| permutation.with_feature("TEXTURE_2D_TARGETS"))
+        .collect();
+    permutations.extend(fallback);
+
+    permutations
+}
+
+/// Identifies the pipeline stage of a source handed to the shader
+/// validation callback.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ShaderStage {
+    Vertex,
+    Fragment,
+}
+
+/// A shader stage that failed offline validation, with the validator's
+/// log.
+#[derive(Debug)]
+pub struct ShaderValidationError {
+    pub name: &'static str,
+    pub features: Vec<&'static str>,
+    pub stage: ShaderStage,
+    pub log: String,
+}
+
+/// Builds the source of each given permutation and feeds every stage to
+/// `validate` — typically a headless GL context or an offline
+/// translator such as ANGLE or glslang. Returns one entry per failed
+/// stage; an empty vector means all shaders passed. Gated as a cargo
+/// test in `tests/angle_shader_validation.rs`.
+pub fn validate_shaders<F>(permutations: &[ShaderPermutation],
+                           mut validate: F) -> Vec<ShaderValidationError>
+    where F: FnMut(ShaderStage, &str) -> Result<(), String>
+{
+    let mut errors = Vec::new();
+
+    for permutation in permutations {
+        let (vs_source, fs_source) = permutation.build_sources();
+        let stages = [(ShaderStage::Vertex, vs_source),
+                      (ShaderStage::Fragment, fs_source)];
+        for &(stage, ref source) in &stages {
+            if let Err(log) = validate(stage, source) {
+                errors.push(ShaderValidationError {
+                    name: permutation.name,
+                    features: permutation.features.clone(),
+                    stage,
+                    log,
+                });
+            }
+        }
+    }
+
+    errors
+}
+
 struct GpuDataTextures {
     layer_texture: VertexDataTexture,
     render_task_texture: VertexDataTexture,
@@ -686,16 +1407,36 @@ pub enum ReadPixelsFormat {
     Bgra8,
 }
 
+/// One layer of a render target texture, recorded while drawing a frame so
+/// that the render target debug view can annotate it and show it after the
+/// texture has been returned to the pool.
+#[derive(Copy, Clone)]
+struct RenderTargetDebugItem {
+    texture_id: TextureId,
+    layer_index: i32,
+    kind: RenderTargetKind,
+    pass_index: usize,
+    task_count: usize,
+}
+
 /// The renderer is responsible for submitting to the GPU the work prepared by the
 /// RenderBackend.
 pub struct Renderer {
     result_rx: Receiver<ResultMsg>,
+    api_tx: channel::MsgSender<ApiMsg>,
     device: Device,
     pending_texture_updates: Vec<TextureUpdateList>,
+    /// Texture cache ops that didn't fit in the last frame's upload
+    /// budget, applied (in order) before any newly arrived updates.
+    deferred_texture_updates: Vec<TextureUpdate>,
     pending_gpu_cache_updates: Vec<GpuCacheUpdateList>,
     pending_shader_updates: Vec<PathBuf>,
     current_frame: Option<RendererFrame>,
 
+    // Set after a context loss, until the backend confirms that the results
+    // it sends were built for the new context. See `notify_context_lost`.
+    waiting_for_context_restore: bool,
+
     // These are "cache shaders". These shaders are used to
     // draw intermediate results to cache targets. The results
     // of these shaders are then used by the primitive shaders.
@@ -711,6 +1452,14 @@ pub struct Renderer {
     cs_clip_image: LazilyCompiledShader,
     cs_clip_border: LazilyCompiledShader,
 
+    /// Compute variants of the blur and clip rectangle cache shaders,
+    /// which write their results with image load/store instead of
+    /// rasterizing a quad per task into a bound framebuffer. `None`
+    /// when the context doesn't support compute shaders, or when the
+    /// variants failed to compile.
+    cs_blur_compute: Option<ComputeProgram>,
+    cs_clip_rectangle_compute: Option<ComputeProgram>,
+
     // The are "primitive shaders". These shaders draw and blend
     // final results on screen. They are aware of tile boundaries.
     // Most draw directly to the framebuffer, but some use inputs
@@ -746,18 +1495,50 @@ pub struct Renderer {
     clear_framebuffer: bool,
     clear_color: ColorF,
     enable_clear_scissor: bool,
+    /// The format of the intermediate color render targets, and the
+    /// format readbacks assume the framebuffer to have. See
+    /// `RendererOptions::render_target_format`.
+    color_target_format: ImageFormat,
     debug: DebugRenderer,
     debug_flags: DebugFlags,
     enable_batcher: bool,
+    /// How many batches of the frame are drawn while BATCH_STEP_DBG is
+    /// set; stepped with `step_debug_batch`.
+    debug_batch_limit: usize,
+    /// The number of batches encountered so far this frame, and the
+    /// total of the previous frame, while BATCH_STEP_DBG is set.
+    debug_batch_cursor: usize,
+    debug_batch_total: usize,
+    /// Scratch buffer that gathers the instances of every prim VAO draw on
+    /// a color target into one contiguous upload, so that each batch can be
+    /// drawn as a slice of it. Retains its allocation across frames.
+    prim_instance_arena: Vec<PrimitiveInstance>,
     backend_profile_counters: BackendProfileCounters,
     profile_counters: RendererProfileCounters,
     profiler: Profiler,
     last_time: u64,
 
+    frame_scheduler: FrameScheduler,
+
+    /// Frame times collected for a benchmark run, when one is active.
+    /// See `start_frame_time_recording`.
+    frame_time_samples: Option<Vec<u64>>,
+
     color_render_targets: Vec<TextureId>,
     alpha_render_targets: Vec<TextureId>,
 
+    /// The render target layers of the last drawn frame, in pass order, for
+    /// the render target debug view. Only collected while RENDER_TARGET_DBG
+    /// is set.
+    render_target_debug_info: Vec<RenderTargetDebugItem>,
+    /// When set, the render target debug view shows only this entry of
+    /// `render_target_debug_info`, scaled up to fill the framebuffer. See
+    /// `cycle_debug_render_target`.
+    render_target_debug_select: Option<usize>,
+
     gpu_profile: GpuProfiler<GpuProfileTag>,
+    gpu_capture_threshold_ns: Option<u64>,
+    renderdoc: Option<RenderDoc>,
     prim_vao_id: VAOId,
     blur_vao_id: VAOId,
     clip_vao_id: VAOId,
@@ -768,6 +1549,68 @@ pub struct Renderer {
     gpu_cache_texture: CacheTexture,
 
     pipeline_epoch_map: FastHashMap<PipelineId, Epoch>,
+
+    /// When set, the framebuffer pass is drawn once per eye viewport
+    /// with that eye's projection. See `StereoParams`.
+    stereo_params: Option<StereoParams>,
+    /// When set, post-multiplied onto the framebuffer pass projection.
+    /// See `set_external_projection`.
+    external_projection: Option<Transform3D<f32>>,
+    /// The rotation of the surface relative to the content. See
+    /// `SurfaceOrientation`.
+    surface_orientation: SurfaceOrientation,
+
+    /// Composites the intermediate output transform target to the
+    /// framebuffer when a frame carries an `OutputColorTransform`.
+    output_transform_program: Program,
+    output_transform_vao: VAOId,
+    u_color_matrix: UniformLocation,
+    u_gamma: UniformLocation,
+    /// The intermediate target the final pass is redirected to while an
+    /// output color transform is active. Allocated on first use and kept
+    /// across frames; released under memory pressure.
+    output_transform_texture_id: Option<TextureId>,
+
+    /// When set, frames are rendered into an FBO-backed texture owned by
+    /// the renderer instead of the default framebuffer; see
+    /// `RendererOptions::render_to_texture`.
+    render_to_texture: bool,
+    /// The texture holding the last rendered frame when
+    /// `render_to_texture` is set. Kept across frames so the embedder can
+    /// keep compositing it between renders.
+    frame_output_texture_id: Option<TextureId>,
+    /// Fence inserted after the last render into the frame output
+    /// texture, stored as a raw value; collected by `get_frame_output`.
+    frame_output_sync: Option<usize>,
+
+    /// State of the in-progress frame sequence capture, when one is
+    /// active. See `start_frame_sequence_capture`.
+    frame_capture: Option<FrameCapture>,
+
+    /// WebGL canvas textures acquired (waited on) for the frame being
+    /// drawn; a release fence is inserted for each after the draw.
+    acquired_webgl_textures: Vec<u32>,
+    /// The release fence inserted after the last composite that sampled
+    /// each WebGL canvas texture, until the producer collects it via
+    /// `take_webgl_release_sync`.
+    webgl_release_syncs: FastHashMap<u32, usize>,
+
+    presentation_feedback_handler: Option<Box<PresentationFeedbackHandler>>,
+    /// Epoch updates drawn since the last completed swap, waiting for
+    /// `notify_swap_complete` to report them to the handler.
+    pending_presentations: Vec<(PipelineId, Epoch, FrameId)>,
+    /// The epochs already queued for presentation feedback, so that each
+    /// (pipeline, epoch) pair is reported exactly once.
+    presented_epochs: FastHashMap<PipelineId, Epoch>,
+    /// The external images sampled by the most recent composite, held
+    /// until the embedder collects them via
+    /// `flush_sampled_external_images`.
+    last_sampled_external_images: Option<SampledExternalImages>,
+
+    /// The content hash last seen for each tagged primitive, used by the
+    /// paint flashing debug view to detect repaints.
+    painted_tag_hashes: FastHashMap<ItemTag, u64>,
+
     /// Used to dispatch functions to the main thread's event loop.
     /// Required to allow GLContext sharing in some implementations like WGL.
     main_thread_dispatcher: Arc<Mutex<Option<Box<RenderDispatcher>>>>,
@@ -782,12 +1625,23 @@ pub struct Renderer {
     /// use a hashmap, and allows a flat vector for performance.
     cache_texture_id_map: Vec<TextureId>,
 
+    /// The latest snapshot of the texture cache contents received from the
+    /// backend, for the texture cache debug overlay. Only present while
+    /// TEXTURE_CACHE_DBG is set.
+    texture_cache_debug: Option<TextureCacheDebugInfo>,
+
     /// A special 1x1 dummy cache texture used for shaders that expect to work
     /// with the cache but are actually running in the first pass
     /// when no target is yet provided as a cache texture input.
     dummy_cache_texture_id: TextureId,
 
+    /// A 1x1 debug-pink texture, bound in place of any texture that fails to
+    /// resolve so that the problem is visible on screen rather than fatal.
+    /// See `resolve_source_texture`.
+    fallback_texture_id: TextureId,
+
     dither_matrix_texture_id: Option<TextureId>,
+    dither_matrix_kind: DitherMatrixKind,
 
     /// Optional trait object that allows the client
     /// application to provide external buffers for image data.
@@ -796,6 +1650,10 @@ pub struct Renderer {
     /// Map of external image IDs to native textures.
     external_images: FastHashMap<(ExternalImageId, u8), TextureId>,
 
+    /// Optional trait object that provides OS compositor surfaces for
+    /// the top level render target in place of the window framebuffer.
+    native_compositor_handler: Option<Box<NativeCompositorHandler>>,
+
     // Optional trait object that handles WebVR commands.
     // Some WebVR commands such as SubmitFrame must be synced with the WebGL render thread.
     vr_compositor_handler: Arc<Mutex<Option<Box<VRCompositorHandler>>>>,
@@ -804,6 +1662,17 @@ pub struct Renderer {
     /// via get_frame_profiles().
     cpu_profiles: VecDeque<CpuProfile>,
     gpu_profiles: VecDeque<GpuProfile>,
+    target_profiles: VecDeque<FrameTargetProfile>,
+
+    /// Per-pipeline statistics of the most recently drawn frame. Can be
+    /// retrieved via get_pipeline_profiles().
+    last_pipeline_profiles: Vec<PipelineProfile>,
+
+    /// When set, per-stage CPU times are checked against these budgets
+    /// after every frame, and overruns are logged and recorded in
+    /// `cpu_budget_overruns`.
+    cpu_stage_budgets: Option<CpuStageBudgets>,
+    cpu_budget_overruns: Vec<CpuBudgetOverrun>,
 }
 
 #[derive(Debug)]
@@ -934,6 +1803,26 @@ impl Renderer {
                                       options.precache_shaders)
         };
 
+        // The compute paths sample the caches as texture arrays, so they
+        // are skipped when the 2D target fallback is active. Devices that
+        // need the fallback are too old for compute shaders anyway.
+        let (cs_blur_compute, cs_clip_rectangle_compute) =
+            if device.get_capabilities().supports_compute_shaders &&
+               !device.get_capabilities().avoid_texture_arrays {
+                // The blur variant declares its image layout as rgba8,
+                // which has to match the bound target, so it is skipped
+                // when the color targets use a wide format.
+                let cs_blur_compute = if options.render_target_format == ImageFormat::BGRA8 {
+                    create_compute_shader("cs_blur.comp", &mut device)
+                } else {
+                    None
+                };
+                (cs_blur_compute,
+                 create_compute_shader("cs_clip_rectangle.comp", &mut device))
+            } else {
+                (None, None)
+            };
+
         let ps_rectangle = try!{
             PrimitiveShader::new("ps_rectangle",
                                  &mut device,
@@ -1057,13 +1946,16 @@ impl Renderer {
                                  options.precache_shaders)
         };
 
-        let dithering_feature = ["DITHERING"];
+        let dithering_feature: &[&str] = match options.dither_matrix_kind {
+            DitherMatrixKind::Bayer => &["DITHERING"],
+            DitherMatrixKind::BlueNoise => &["DITHERING", "BLUE_NOISE_DITHERING"],
+        };
 
         let ps_gradient = try!{
             PrimitiveShader::new("ps_gradient",
                                  &mut device,
                                  if options.enable_dithering {
-                                    &dithering_feature
+                                    dithering_feature
                                  } else {
                                     &[]
                                  },
@@ -1074,7 +1966,7 @@ impl Renderer {
             PrimitiveShader::new("ps_angle_gradient",
                                  &mut device,
                                  if options.enable_dithering {
-                                    &dithering_feature
+                                    dithering_feature
                                  } else {
                                     &[]
                                  },
@@ -1085,7 +1977,7 @@ impl Renderer {
             PrimitiveShader::new("ps_radial_gradient",
                                  &mut device,
                                  if options.enable_dithering {
-                                    &dithering_feature
+                                    dithering_feature
                                  } else {
                                     &[]
                                  },
@@ -1136,7 +2028,13 @@ impl Renderer {
 
         let backend_profile_counters = BackendProfileCounters::new();
 
-        let dummy_cache_texture_id = device.create_texture_ids(1, TextureTarget::Array)[0];
+        // The dummy cache texture must match the sampler type the shaders
+        // were compiled with; see `WR_FEATURE_TEXTURE_2D_TARGETS`.
+        let dummy_cache_texture_id = if device.get_capabilities().avoid_texture_arrays {
+            device.create_texture_ids(1, TextureTarget::Default)[0]
+        } else {
+            device.create_texture_ids(1, TextureTarget::Array)[0]
+        };
         device.init_texture(dummy_cache_texture_id,
                             1,
                             1,
@@ -1144,27 +2042,30 @@ impl Renderer {
                             TextureFilter::Linear,
                             RenderTargetMode::LayerRenderTarget(1),
                             None);
+        device.set_texture_label(dummy_cache_texture_id, "dummy cache");
+
+        let fallback_texture_id = device.create_texture_ids(1, TextureTarget::Default)[0];
+        device.init_texture(fallback_texture_id,
+                            1,
+                            1,
+                            ImageFormat::BGRA8,
+                            TextureFilter::Nearest,
+                            RenderTargetMode::None,
+                            Some(&[ 255, 0, 255, 255 ]));
+        device.set_texture_label(fallback_texture_id, "fallback");
 
         let dither_matrix_texture_id = if options.enable_dithering {
-            let dither_matrix: [u8; 64] = [
-                00, 48, 12, 60, 03, 51, 15, 63,
-                32, 16, 44, 28, 35, 19, 47, 31,
-                08, 56, 04, 52, 11, 59, 07, 55,
-                40, 24, 36, 20, 43, 27, 39, 23,
-                02, 50, 14, 62, 01, 49, 13, 61,
-                34, 18, 46, 30, 33, 17, 45, 29,
-                10, 58, 06, 54, 09, 57, 05, 53,
-                42, 26, 38, 22, 41, 25, 37, 21
-            ];
+            let (size, data) = options.dither_matrix_kind.texture_data();
 
             let id = device.create_texture_ids(1, TextureTarget::Default)[0];
             device.init_texture(id,
-                                8,
-                                8,
+                                size,
+                                size,
                                 ImageFormat::A8,
                                 TextureFilter::Nearest,
                                 RenderTargetMode::None,
-                                Some(&dither_matrix));
+                                Some(data));
+            device.set_texture_label(id, "dither matrix");
 
             Some(id)
         } else {
@@ -1210,6 +2111,21 @@ impl Renderer {
         let blur_vao_id = device.create_vao_with_new_instances(&DESC_BLUR, mem::size_of::<BlurCommand>() as i32, prim_vao_id);
         let clip_vao_id = device.create_vao_with_new_instances(&DESC_CLIP, mem::size_of::<CacheClipInstance>() as i32, prim_vao_id);
 
+        device.set_vao_label(prim_vao_id, "prim instances");
+        device.set_vao_label(blur_vao_id, "blur");
+        device.set_vao_label(clip_vao_id, "clip");
+
+        let output_transform_program = try!{
+            device.create_program("output_transform",
+                                  "shared_other",
+                                  &DESC_OUTPUT_TRANSFORM)
+        };
+        let u_color_matrix = device.get_uniform_location(&output_transform_program,
+                                                         "uColorMatrix");
+        let u_gamma = device.get_uniform_location(&output_transform_program, "uGamma");
+        let output_transform_vao = device.create_vao(&DESC_OUTPUT_TRANSFORM, 32);
+        device.set_vao_label(output_transform_vao, "output transform");
+
         device.end_frame();
 
         let main_thread_dispatcher = Arc::new(Mutex::new(None));
@@ -1237,21 +2153,34 @@ impl Renderer {
             default_font_render_mode,
             debug: options.debug,
             cache_expiry_frames: options.cache_expiry_frames,
+            enable_depth_prepass: options.enable_depth_prepass,
+            blob_tile_size: options.blob_tile_size,
+            quality: options.quality,
         };
 
         let device_pixel_ratio = options.device_pixel_ratio;
         let debug_flags = options.debug_flags;
         let payload_tx_for_backend = payload_tx.clone();
         let recorder = options.recorder;
-        let worker_config = ThreadPoolConfig::new()
-            .thread_name(|idx|{ format!("WebRender:Worker#{}", idx) })
-            .start_handler(|idx| { register_thread_with_profiler(format!("WebRender:Worker#{}", idx)); });
+        let worker_settings = WorkerPoolSettings {
+            stack_size: options.worker_stack_size,
+            low_priority: options.low_priority_workers,
+        };
+        let worker_threads = options.worker_threads;
         let workers = options.workers.take().unwrap_or_else(||{
-            Arc::new(ThreadPool::new(worker_config).unwrap())
+            new_worker_pool(worker_threads, &worker_settings)
         });
-        let enable_render_on_scroll = options.enable_render_on_scroll;
+        let scheduling_policy = options.scheduling_policy;
+        let validate_display_lists = options.validate_display_lists;
 
         let blob_image_renderer = options.blob_image_renderer.take();
+
+        let (scene_builder, scene_tx, scene_rx) = SceneBuilder::new(config, api_tx.clone());
+        try!{ thread::Builder::new().name("SceneBuilder".to_string()).spawn(move || {
+            let mut scene_builder = scene_builder;
+            scene_builder.run();
+        })};
+
         try!{ thread::Builder::new().name("RenderBackend".to_string()).spawn(move || {
             let mut backend = RenderBackend::new(api_rx,
                                                  payload_rx,
@@ -1260,34 +2189,51 @@ impl Renderer {
                                                  device_pixel_ratio,
                                                  texture_cache,
                                                  workers,
+                                                 worker_settings,
                                                  backend_notifier,
                                                  context_handle,
                                                  config,
+                                                 scene_tx,
+                                                 scene_rx,
                                                  recorder,
                                                  backend_main_thread_dispatcher,
                                                  blob_image_renderer,
                                                  backend_vr_compositor,
-                                                 enable_render_on_scroll);
+                                                 scheduling_policy,
+                                                 validate_display_lists);
             backend.run(backend_profile_counters);
         })};
 
         let gpu_cache_texture = CacheTexture::new(&mut device);
 
-        let gpu_profile = GpuProfiler::new(device.rc_gl());
+        // Scissored clears are a loss on some tiled GPUs, where a full
+        // clear is nearly free. See the workarounds module.
+        let enable_clear_scissor = options.enable_clear_scissor &&
+                                   !device.get_capabilities().avoid_scissored_clears;
+
+        let mut gpu_profile = GpuProfiler::new(device.rc_gl());
+        // Auto-capture needs GPU timings even when the profiler HUD is off.
+        gpu_profile.set_enabled(debug_flags.contains(PROFILER_DBG) ||
+                                options.gpu_capture_threshold_ns.is_some());
 
         let renderer = Renderer {
             result_rx,
+            api_tx: api_tx.clone(),
             device,
             current_frame: None,
             pending_texture_updates: Vec::new(),
+            deferred_texture_updates: Vec::new(),
             pending_gpu_cache_updates: Vec::new(),
             pending_shader_updates: Vec::new(),
+            waiting_for_context_restore: false,
             cs_box_shadow,
             cs_text_run,
             cs_line,
             cs_blur,
             cs_clip_rectangle,
             cs_clip_border,
+            cs_blur_compute,
+            cs_clip_rectangle_compute,
             cs_clip_image,
             ps_rectangle,
             ps_rectangle_clip,
@@ -1311,36 +2257,78 @@ impl Renderer {
             debug: debug_renderer,
             debug_flags,
             enable_batcher: options.enable_batcher,
+            debug_batch_limit: 1,
+            debug_batch_cursor: 0,
+            debug_batch_total: 0,
+            prim_instance_arena: Vec::new(),
             backend_profile_counters: BackendProfileCounters::new(),
             profile_counters: RendererProfileCounters::new(),
-            profiler: Profiler::new(),
+            profiler: Profiler::new(options.profiler_frame_budget_ns),
             max_texture_size: max_texture_size,
             max_recorded_profiles: options.max_recorded_profiles,
             clear_framebuffer: options.clear_framebuffer,
             clear_color: options.clear_color,
-            enable_clear_scissor: options.enable_clear_scissor,
+            enable_clear_scissor,
+            color_target_format: options.render_target_format,
             last_time: 0,
+            frame_scheduler: FrameScheduler::new(),
+            frame_time_samples: None,
             color_render_targets: Vec::new(),
             alpha_render_targets: Vec::new(),
+            render_target_debug_info: Vec::new(),
+            render_target_debug_select: None,
             gpu_profile,
+            gpu_capture_threshold_ns: options.gpu_capture_threshold_ns,
+            renderdoc: RenderDoc::load(),
             prim_vao_id,
             blur_vao_id,
             clip_vao_id,
             gdt_index: 0,
             gpu_data_textures,
             pipeline_epoch_map: FastHashMap::default(),
+            stereo_params: None,
+            external_projection: None,
+            surface_orientation: options.surface_orientation,
+            output_transform_program,
+            output_transform_vao,
+            u_color_matrix,
+            u_gamma,
+            output_transform_texture_id: None,
+            render_to_texture: options.render_to_texture,
+            frame_output_texture_id: None,
+            frame_output_sync: None,
+            frame_capture: None,
+            acquired_webgl_textures: Vec::new(),
+            webgl_release_syncs: FastHashMap::default(),
+            presentation_feedback_handler: None,
+            pending_presentations: Vec::new(),
+            presented_epochs: FastHashMap::default(),
+            last_sampled_external_images: None,
+            painted_tag_hashes: FastHashMap::default(),
             main_thread_dispatcher,
             cache_texture_id_map: Vec::new(),
+            texture_cache_debug: None,
             dummy_cache_texture_id,
+            fallback_texture_id,
             dither_matrix_texture_id,
+            dither_matrix_kind: options.dither_matrix_kind,
             external_image_handler: None,
+            native_compositor_handler: None,
             external_images: FastHashMap::default(),
             vr_compositor_handler: vr_compositor,
             cpu_profiles: VecDeque::new(),
             gpu_profiles: VecDeque::new(),
+            target_profiles: VecDeque::new(),
+            last_pipeline_profiles: Vec::new(),
+            cpu_stage_budgets: options.cpu_stage_budgets,
+            cpu_budget_overruns: Vec::new(),
             gpu_cache_texture,
         };
 
+        if debug_flags.intersects(TEXTURE_CACHE_DBG | TEXTURE_CACHE_INSPECT_DBG) {
+            renderer.api_tx.send(ApiMsg::EnableTextureCacheDebug(true)).ok();
+        }
+
         let sender = RenderApiSender::new(api_tx, payload_tx);
         Ok((renderer, sender))
     }
@@ -1354,6 +2342,7 @@ impl Renderer {
             kind: GraphicsApi::OpenGL,
             version: self.device.gl().get_string(gl::VERSION),
             renderer: self.device.gl().get_string(gl::RENDERER),
+            gpu: self.device.gpu_info().clone(),
         }
     }
 
@@ -1365,6 +2354,16 @@ impl Renderer {
     ///
     /// The RenderNotifier will be called when processing e.g. of a (scrolling) frame is done,
     /// and therefore the screen should be updated.
+    /// Supply the timestamp of a display vblank, in nanoseconds on the same
+    /// clock as `time::precise_time_ns`. Once two timestamps have been
+    /// received, `render()` uses the derived refresh interval together with
+    /// measured frame costs to defer composites that would miss the next
+    /// vblank anyway. Embedders that don't drive this method get the old
+    /// draw-immediately behaviour.
+    pub fn notify_vsync(&mut self, timestamp_ns: u64) {
+        self.frame_scheduler.notify_vsync(timestamp_ns);
+    }
+
     pub fn set_render_notifier(&self, notifier: Box<RenderNotifier>) {
         let mut notifier_arc = self.notifier.lock().unwrap();
         *notifier_arc = Some(notifier);
@@ -1398,6 +2397,33 @@ impl Renderer {
         mem::replace(&mut self.pipeline_epoch_map, FastHashMap::default())
     }
 
+    /// Returns the external images that were sampled while compositing
+    /// the most recent frame, or `None` if no frame has been composited
+    /// since the last call. Media code can drive requestVideoFrameCallback
+    /// from this: a report that includes a video's `ExternalImageId` means
+    /// that video frame was composited, and a report without it means the
+    /// composite reused or dropped the frame.
+    pub fn flush_sampled_external_images(&mut self) -> Option<SampledExternalImages> {
+        self.last_sampled_external_images.take()
+    }
+
+    /// Tells the renderer that the GL swap for the last rendered frame has
+    /// completed. Every (pipeline, epoch) pair that reached the screen with
+    /// that swap is reported to the presentation feedback handler, stamped
+    /// with the current time.
+    pub fn notify_swap_complete(&mut self) {
+        if self.pending_presentations.is_empty() {
+            return;
+        }
+        let presentation_time_ns = precise_time_ns();
+        let presentations = mem::replace(&mut self.pending_presentations, Vec::new());
+        if let Some(ref mut handler) = self.presentation_feedback_handler {
+            for (pipeline_id, epoch, frame_id) in presentations {
+                handler.frame_presented(pipeline_id, epoch, frame_id, presentation_time_ns);
+            }
+        }
+    }
+
     /// Processes the result queue.
     ///
     /// Should be called before `render()`, as texture cache updates are done here.
@@ -1407,9 +2433,16 @@ impl Renderer {
         // Pull any pending results and return the most recent.
         while let Ok(msg) = self.result_rx.try_recv() {
             match msg {
-                ResultMsg::NewFrame(_document_id, mut frame, texture_update_list, profile_counters) => {
+                ResultMsg::ContextRestored => {
+                    self.waiting_for_context_restore = false;
+                }
+                // Anything still in flight from before a context loss refers
+                // to GPU resources that died with the old context; drop it.
+                _ if self.waiting_for_context_restore => {}
+                ResultMsg::NewFrame(_document_id, mut frame, texture_update_list, texture_cache_debug, profile_counters) => {
                     //TODO: associate `document_id` with target window
                     self.pending_texture_updates.push(texture_update_list);
+                    self.texture_cache_debug = texture_cache_debug;
                     if let Some(ref mut frame) = frame.frame {
                         // TODO(gw): This whole message / Frame / RendererFrame stuff
                         //           is really messy and needs to be refactored!!
@@ -1438,6 +2471,19 @@ impl Renderer {
                         self.current_frame = None;
                     }
                 }
+                ResultMsg::MemoryPressure(level) => {
+                    let mut reclaimed = self.trim_render_target_pool();
+                    if level >= MemoryPressureLevel::Severe {
+                        // Safe to drop because the backend restarted its
+                        // side of the gpu cache alongside this message:
+                        // every live block is re-uploaded with the next
+                        // frame, rebuilding the shadow copy from only
+                        // what is still in use.
+                        reclaimed += self.gpu_cache_texture.drop_shadow_copy();
+                    }
+                    debug!("memory pressure ({:?}): renderer reclaimed {} bytes",
+                           level, reclaimed);
+                }
                 ResultMsg::RefreshShader(path) => {
                     self.pending_shader_updates.push(path);
                 }
@@ -1456,12 +2502,28 @@ impl Renderer {
             SourceTexture::Invalid => TextureId::invalid(),
             SourceTexture::WebGL(id) => TextureId::new(id, TextureTarget::Default),
             SourceTexture::External(external_image) => {
-                *self.external_images
-                     .get(&(external_image.id, external_image.channel_index))
-                     .expect("BUG: External image should be resolved by now!")
+                match self.external_images.get(&(external_image.id, external_image.channel_index)) {
+                    Some(texture_id) => *texture_id,
+                    None => {
+                        report_renderer_error(&self.notifier,
+                                              RendererError::UnresolvedExternalImage(
+                                                  external_image.id,
+                                                  external_image.channel_index));
+                        self.fallback_texture_id
+                    }
+                }
             }
             SourceTexture::TextureCache(index) => {
-                self.cache_texture_id_map[index.0]
+                match self.cache_texture_id_map.get(index.0) {
+                    Some(texture_id) => *texture_id,
+                    None => {
+                        report_renderer_error(&self.notifier,
+                                              RendererError::UnresolvedTextureCacheId(
+                                                  index.0,
+                                                  self.cache_texture_id_map.len()));
+                        self.fallback_texture_id
+                    }
+                }
             }
         }
     }
@@ -1471,22 +2533,208 @@ impl Renderer {
         self.external_image_handler = Some(handler);
     }
 
+    /// Sets a sink receiving structured device events (shader compile
+    /// and link logs, resource problems), so the embedder can route
+    /// them to crash annotations or a console instead of stdout. See
+    /// `DeviceEventSink`.
+    pub fn set_device_event_sink(&mut self, sink: Box<DeviceEventSink>) {
+        self.device.set_event_sink(sink);
+    }
+
+    /// Set a handler that allocates the top level render target from the
+    /// OS compositor. See `NativeCompositorHandler`.
+    pub fn set_native_compositor_handler(&mut self, handler: Box<NativeCompositorHandler>) {
+        self.native_compositor_handler = Some(handler);
+    }
+
+    /// Returns the release fence inserted after the last composite that
+    /// sampled the given WebGL canvas texture, handing ownership of the
+    /// sync object to the caller. The producer waits on (and deletes) it
+    /// before rendering into the texture again.
+    pub fn take_webgl_release_sync(&mut self, texture_id: u32) -> Option<usize> {
+        self.webgl_release_syncs.remove(&texture_id)
+    }
+
+    /// Sets (or clears) the per-eye parameters for stereo presentation.
+    /// Takes effect with the next call to `render`.
+    pub fn set_stereo_params(&mut self, params: Option<StereoParams>) {
+        self.stereo_params = params;
+    }
+
+    /// Sets (or clears) a matrix post-multiplied onto the projection of
+    /// the framebuffer pass, so embedders can rotate or otherwise adjust
+    /// the presented output without an extra composite - e.g. a quarter
+    /// turn in clip space when an Android surface is rotated against the
+    /// display. It applies in display space: when a color transform
+    /// redirects the final pass to a texture, the matrix applies to the
+    /// composite of that texture, and it doesn't apply to stereo
+    /// presentation, where the VR compositor owns the display. Takes
+    /// effect with the next call to `render`; debug overlays are not
+    /// affected.
+    pub fn set_external_projection(&mut self, projection: Option<Transform3D<f32>>) {
+        self.external_projection = projection;
+    }
+
+    /// Sets the orientation of the surface relative to the content, e.g.
+    /// when an Android device rotates while its surface stays in the
+    /// display's native orientation. Takes effect with the next call to
+    /// `render`, which should be given the surface's (not the content's)
+    /// size. See `SurfaceOrientation`.
+    pub fn set_surface_orientation(&mut self, orientation: SurfaceOrientation) {
+        self.surface_orientation = orientation;
+    }
+
+    /// Set a callback for presentation feedback. The embedder must call
+    /// `notify_swap_complete` after each GL swap for the handler to be
+    /// invoked.
+    pub fn set_presentation_feedback_handler(&mut self, handler: Box<PresentationFeedbackHandler>) {
+        self.presentation_feedback_handler = Some(handler);
+    }
+
     /// Retrieve (and clear) the current list of recorded frame profiles.
-    pub fn get_frame_profiles(&mut self) -> (Vec<CpuProfile>, Vec<GpuProfile>) {
+    pub fn get_frame_profiles(&mut self)
+                              -> (Vec<CpuProfile>, Vec<GpuProfile>, Vec<FrameTargetProfile>) {
         let cpu_profiles = self.cpu_profiles.drain(..).collect();
         let gpu_profiles = self.gpu_profiles.drain(..).collect();
-        (cpu_profiles, gpu_profiles)
+        let target_profiles = self.target_profiles.drain(..).collect();
+        (cpu_profiles, gpu_profiles, target_profiles)
     }
 
-    /// Renders the current frame.
-    ///
-    /// A Frame is supplied by calling [`generate_frame()`][genframe].
-    /// [genframe]: ../../webrender_api/struct.DocumentApi.html#method.generate_frame
-    pub fn render(&mut self, framebuffer_size: DeviceUintSize) {
-        profile_scope!("render");
-
-        if let Some(mut frame) = self.current_frame.take() {
-            if let Some(ref mut frame) = frame.frame {
+    /// Retrieve the texture holding the last rendered frame, together
+    /// with a sync object that signals once the GPU has finished writing
+    /// it. Only available when `RendererOptions::render_to_texture` is
+    /// set and a frame has been rendered since the last call; each fence
+    /// is handed out once and the caller is responsible for deleting it
+    /// after waiting. The texture stays owned by WebRender and its
+    /// contents are valid until the next call to `render`.
+    pub fn get_frame_output(&mut self) -> Option<FrameOutput> {
+        let texture_id = match self.frame_output_texture_id {
+            Some(texture_id) => texture_id,
+            None => return None,
+        };
+        let size = self.device.get_texture_dimensions(texture_id);
+        self.frame_output_sync.take().map(|sync| {
+            FrameOutput {
+                texture_id: texture_id.name(),
+                size,
+                sync: sync as gl::GLsync,
+            }
+        })
+    }
+
+    /// Retrieve (and clear) the CPU stage budget overruns recorded since
+    /// the last call. Only populated when `cpu_stage_budgets` is set; a
+    /// test harness can fail the run on any non-empty result.
+    pub fn take_cpu_budget_overruns(&mut self) -> Vec<CpuBudgetOverrun> {
+        mem::replace(&mut self.cpu_budget_overruns, Vec::new())
+    }
+
+    /// Starts collecting the wall-clock time between successive rendered
+    /// frames, for benchmark runs; see `replay::BenchmarkRunner`. Any
+    /// samples from an earlier recording are discarded.
+    pub fn start_frame_time_recording(&mut self) {
+        self.frame_time_samples = Some(Vec::new());
+    }
+
+    /// Stops collecting frame times and returns the samples gathered
+    /// since `start_frame_time_recording`, in nanoseconds.
+    pub fn stop_frame_time_recording(&mut self) -> Vec<u64> {
+        self.frame_time_samples.take().unwrap_or_else(Vec::new)
+    }
+
+    /// Retrieve the per-pipeline statistics of the most recently drawn
+    /// frame, so compositor cost can be attributed to individual iframes
+    /// or pieces of browser UI. See `PipelineProfile`.
+    pub fn get_pipeline_profiles(&self) -> &[PipelineProfile] {
+        &self.last_pipeline_profiles
+    }
+
+    /// Retrieve a Graphviz DOT dump of the render task dependency graph of
+    /// the current frame, showing which passes, tasks, kinds and sizes make
+    /// up the frame. Only recorded when the frame builder is constructed
+    /// with `debug` enabled; returns None otherwise.
+    pub fn dump_render_task_graph(&self) -> Option<&str> {
+        self.current_frame
+            .as_ref()
+            .and_then(|renderer_frame| renderer_frame.frame.as_ref())
+            .and_then(|frame| frame.render_task_graph_dump.as_ref())
+            .map(|dump| dump.as_str())
+    }
+
+    /// Aggregates the recorded frame profiles into summary statistics over
+    /// at most `window` recent frames, without consuming the profiles.
+    /// Recording must be enabled by setting `max_recorded_profiles`; the
+    /// window is clamped to what has actually been recorded.
+    pub fn get_frame_stats(&self, window: usize) -> FrameStats {
+        let backend: Vec<u64> = self.cpu_profiles
+                                    .iter()
+                                    .rev()
+                                    .take(window)
+                                    .map(|profile| profile.backend_time_ns)
+                                    .collect();
+        let composite: Vec<u64> = self.cpu_profiles
+                                      .iter()
+                                      .rev()
+                                      .take(window)
+                                      .map(|profile| profile.composite_time_ns)
+                                      .collect();
+        let gpu: Vec<u64> = self.gpu_profiles
+                                .iter()
+                                .rev()
+                                .take(window)
+                                .map(|profile| profile.paint_time_ns)
+                                .collect();
+
+        FrameStats {
+            cpu_frames: backend.len(),
+            gpu_frames: gpu.len(),
+            backend_time: TimingStats::compute(backend),
+            composite_time: TimingStats::compute(composite),
+            gpu_time: TimingStats::compute(gpu),
+        }
+    }
+
+    /// Renders the current frame.
+    ///
+    /// A Frame is supplied by calling [`generate_frame()`][genframe].
+    /// [genframe]: ../../webrender_api/struct.DocumentApi.html#method.generate_frame
+    pub fn render(&mut self, framebuffer_size: DeviceUintSize) {
+        profile_scope!("render");
+
+        if framebuffer_size.width == 0 || framebuffer_size.height == 0 {
+            // The window is minimized or was resized to nothing. Drawing into
+            // a 0x0 framebuffer hits GL errors and driver asserts, so skip
+            // all of the draw calls, but keep servicing resource and GPU
+            // cache updates so that they don't pile up while the window is
+            // hidden. The pending frame stays around and is composited as
+            // soon as the window has a real size again.
+            if let Some(mut frame) = self.current_frame.take() {
+                if let Some(ref mut frame) = frame.frame {
+                    self.device.begin_frame(frame.device_pixel_ratio);
+                    self.update_texture_cache();
+                    self.update_gpu_cache(frame);
+                    self.device.end_frame();
+                }
+                self.current_frame = Some(frame);
+            }
+            return;
+        }
+
+        if self.current_frame.is_some() &&
+           !self.frame_scheduler.should_render(precise_time_ns()) {
+            // An average frame no longer fits before the upcoming vblank, so
+            // starting to draw now would only miss it. Keep the frame pending
+            // and tell the embedder, so that it calls render() again once the
+            // vblank has passed.
+            let mut notifier = self.notifier.lock().unwrap();
+            if let Some(ref mut notifier) = *notifier {
+                notifier.frame_deferred();
+            }
+            return;
+        }
+
+        if let Some(mut frame) = self.current_frame.take() {
+            if let Some(ref mut frame) = frame.frame {
                 let mut profile_timers = RendererProfileTimers::new();
 
                 {
@@ -1505,6 +2753,7 @@ impl Renderer {
                     }
                 }
 
+                let mut texture_update_ns = 0;
                 let cpu_frame_id = profile_timers.cpu_time.profile(|| {
                     let cpu_frame_id = {
                         let _gm = GpuMarker::new(self.device.rc_gl(), "begin frame");
@@ -1516,7 +2765,9 @@ impl Renderer {
                         self.device.set_blend(false);
                         //self.update_shaders();
 
+                        let texture_update_start = precise_time_ns();
                         self.update_texture_cache();
+                        texture_update_ns = precise_time_ns() - texture_update_start;
 
                         self.update_gpu_cache(frame);
 
@@ -1525,7 +2776,29 @@ impl Renderer {
                         frame_id
                     };
 
+                    // Read back the capture target filled by the previous
+                    // frame, now that the GPU has certainly finished with
+                    // it.
+                    self.read_back_capture_frame();
+
+                    // When a native compositor handler is installed, the
+                    // framebuffer pass renders into a surface it provides
+                    // instead of the window's framebuffer. It takes
+                    // precedence over `render_to_texture`, which redirects
+                    // everything that would hit the window - the final
+                    // pass, the output transform composite and the debug
+                    // overlays - into the frame output texture instead.
+                    if let Some(ref mut handler) = self.native_compositor_handler {
+                        let surface = handler.bind_surface(framebuffer_size);
+                        self.device.set_external_framebuffer(Some(surface.fbo_id));
+                    } else if self.render_to_texture {
+                        let texture_id = self.frame_output_target(&framebuffer_size);
+                        let fbo = self.device.texture_fbo_name(texture_id, 0);
+                        self.device.set_external_framebuffer(Some(fbo));
+                    }
+
                     self.draw_tile_frame(frame, &framebuffer_size);
+                    self.release_webgl_surfaces();
 
                     self.gpu_profile.end_frame();
                     cpu_frame_id
@@ -1534,6 +2807,27 @@ impl Renderer {
                 let current_time = precise_time_ns();
                 let ns = current_time - self.last_time;
                 self.profile_counters.frame_time.set(ns);
+                if let Some(ref mut samples) = self.frame_time_samples {
+                    samples.push(ns);
+                }
+                self.profile_counters.fbo_binds.set(self.device.fbo_bind_count());
+
+                let gpu_cost_ns = profile_timers.gpu_samples
+                                                .iter()
+                                                .map(|sample| sample.time_ns)
+                                                .sum();
+                self.frame_scheduler.record_frame_cost(profile_timers.cpu_time.get(),
+                                                       gpu_cost_ns);
+
+                // Auto-capture slow frames for driver bug reports. The
+                // samples just read are from an earlier frame, so the
+                // capture lands a few frames after the slow one, but it
+                // typically shows the same workload.
+                if let Some(threshold_ns) = self.gpu_capture_threshold_ns {
+                    if gpu_cost_ns > threshold_ns {
+                        self.trigger_frame_capture();
+                    }
+                }
 
                 if self.max_recorded_profiles > 0 {
                     while self.cpu_profiles.len() >= self.max_recorded_profiles {
@@ -1544,6 +2838,35 @@ impl Renderer {
                                                       profile_timers.cpu_time.get(),
                                                       self.profile_counters.draw_calls.get());
                     self.cpu_profiles.push_back(cpu_profile);
+
+                    while self.target_profiles.len() >= self.max_recorded_profiles {
+                        self.target_profiles.pop_front();
+                    }
+                    self.target_profiles.push_back(FrameTargetProfile::new(cpu_frame_id, frame));
+                }
+
+                if let Some(budgets) = self.cpu_stage_budgets {
+                    let stages = [
+                        ("backend", self.backend_profile_counters.total_time.get(), budgets.backend_ns),
+                        ("composite", profile_timers.cpu_time.get(), budgets.composite_ns),
+                        ("texture_update", texture_update_ns, budgets.texture_update_ns),
+                    ];
+                    for &(stage, actual_ns, budget_ns) in &stages {
+                        let budget_ns = match budget_ns {
+                            Some(budget_ns) => budget_ns,
+                            None => continue,
+                        };
+                        if actual_ns > budget_ns {
+                            error!("cpu budget overrun: frame={:?} stage={} actual_ns={} budget_ns={}",
+                                   cpu_frame_id, stage, actual_ns, budget_ns);
+                            self.cpu_budget_overruns.push(CpuBudgetOverrun {
+                                frame_id: cpu_frame_id,
+                                stage,
+                                actual_ns,
+                                budget_ns,
+                            });
+                        }
+                    }
                 }
 
                 if self.debug_flags.contains(PROFILER_DBG) {
@@ -1553,19 +2876,73 @@ impl Renderer {
                                                &self.profile_counters,
                                                &mut profile_timers,
                                                &mut self.debug);
+                } else if self.debug_flags.contains(COMPACT_PROFILER_DBG) {
+                    self.profiler.draw_compact_profile(&mut self.device,
+                                                       ns,
+                                                       &self.profile_counters,
+                                                       &mut self.debug);
                 }
 
                 self.profile_counters.reset();
                 self.profile_counters.frame_counter.inc();
 
+                if self.debug_flags.contains(BATCH_STEP_DBG) {
+                    self.debug_batch_total = self.debug_batch_cursor;
+                    let shown = cmp::min(self.debug_batch_limit,
+                                         self.debug_batch_total);
+                    let label = format!("batch {}/{}",
+                                        shown,
+                                        self.debug_batch_total);
+                    self.debug.add_text(16.0, 40.0, &label, debug_colors::WHITE.into());
+                }
+
                 let debug_size = DeviceUintSize::new(framebuffer_size.width as u32,
                                                      framebuffer_size.height as u32);
                 self.debug.render(&mut self.device, &debug_size);
+
+                // Blit the presented frame into a capture target when a
+                // frame sequence capture is active. This is GPU-side
+                // only; the readback happens when the next frame starts.
+                self.capture_presented_frame(&framebuffer_size);
+
+                // Fence the frame output texture, so the external
+                // compositor can make the GPU finish writing it before
+                // sampling.
+                if self.render_to_texture {
+                    let sync = self.device.gl().fence_sync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+                    if let Some(stale) = self.frame_output_sync.take() {
+                        // The embedder never collected the previous fence.
+                        self.device.gl().delete_sync(stale as gl::GLsync);
+                    }
+                    self.frame_output_sync = Some(sync as usize);
+                }
+
+                // Drop the framebuffer redirect before end_frame so the
+                // device restores the window's framebuffer binding.
+                self.device.set_external_framebuffer(None);
                 {
                     let _gm = GpuMarker::new(self.device.rc_gl(), "end frame");
                     self.device.end_frame();
                 }
+
+                // The frame, including any debug overlays, is complete in
+                // the native surface - hand it back to the OS compositor.
+                if let Some(ref mut handler) = self.native_compositor_handler {
+                    handler.present_surface();
+                }
+
                 self.last_time = current_time;
+
+                // Queue presentation feedback for every pipeline whose
+                // content reaches the screen for the first time with
+                // this frame's swap. Reported to the handler once the
+                // embedder confirms the swap via `notify_swap_complete`.
+                for (pipeline_id, epoch) in &self.pipeline_epoch_map {
+                    if self.presented_epochs.get(pipeline_id) != Some(epoch) {
+                        self.presented_epochs.insert(*pipeline_id, *epoch);
+                        self.pending_presentations.push((*pipeline_id, *epoch, cpu_frame_id));
+                    }
+                }
             }
 
             // Restore frame - avoid borrow checker!
@@ -1598,105 +2975,174 @@ impl Renderer {
     fn update_gpu_cache(&mut self, frame: &mut Frame) {
         let _gm = GpuMarker::new(self.device.rc_gl(), "gpu cache update");
         for update_list in self.pending_gpu_cache_updates.drain(..) {
-            self.gpu_cache_texture.update(&mut self.device, &update_list);
+            self.gpu_cache_texture.update(&mut self.device,
+                                          &update_list,
+                                          &mut self.profile_counters);
         }
         self.update_deferred_resolves(frame);
-        self.gpu_cache_texture.flush(&mut self.device);
+        self.gpu_cache_texture.flush(&mut self.device, &mut self.profile_counters);
     }
 
     fn update_texture_cache(&mut self) {
         let _gm = GpuMarker::new(self.device.rc_gl(), "texture cache update");
-        let mut pending_texture_updates = mem::replace(&mut self.pending_texture_updates, vec![]);
-        for update_list in pending_texture_updates.drain(..) {
-            for update in update_list.updates {
-                match update.op {
-                    TextureUpdateOp::Create { width, height, format, filter, mode, data } => {
-                        let CacheTextureId(cache_texture_index) = update.id;
-                        if self.cache_texture_id_map.len() == cache_texture_index {
-                            // Create a new native texture, as requested by the texture cache.
-                            let texture_id = self.device
-                                                 .create_texture_ids(1, TextureTarget::Default)[0];
-                            self.cache_texture_id_map.push(texture_id);
-                        }
-                        let texture_id = self.cache_texture_id_map[cache_texture_index];
-
-                        if let Some(image) = data {
-                            match image {
-                                ImageData::Raw(raw) => {
-                                    self.device.init_texture(texture_id,
-                                                             width,
-                                                             height,
-                                                             format,
-                                                             filter,
-                                                             mode,
-                                                             Some(raw.as_slice()));
-                                }
-                                ImageData::External(ext_image) => {
-                                    match ext_image.image_type {
-                                        ExternalImageType::ExternalBuffer => {
-                                            let handler = self.external_image_handler
-                                                              .as_mut()
-                                                              .expect("Found external image, but no handler set!");
+        let pending_texture_updates = mem::replace(&mut self.pending_texture_updates, vec![]);
+        // Uploads that spilled over from the previous frame go first:
+        // they belong to content the user is already being shown, while
+        // the new lists are for frames still in the pipeline. Beyond
+        // that, ops are never reordered: structural ops (Grow, Free)
+        // assume every earlier op on their texture has been applied.
+        let deferred = mem::replace(&mut self.deferred_texture_updates, Vec::new());
+
+        let mut budget = MAX_TEXTURE_UPLOAD_BYTES_PER_FRAME;
+        let mut exhausted = false;
+
+        let updates = deferred.into_iter().chain(
+            pending_texture_updates.into_iter()
+                                   .flat_map(|list| list.updates.into_iter()));
+        for update in updates {
+            let cost = texture_upload_cost(&update.op);
+
+            // Once one op spills over, everything behind it has to
+            // spill too, to keep the order. An upload that by itself
+            // exceeds the whole budget still goes through (on its own
+            // frame) rather than getting stuck forever.
+            if exhausted || (cost > budget && budget != MAX_TEXTURE_UPLOAD_BYTES_PER_FRAME) {
+                exhausted = true;
+                self.deferred_texture_updates.push(update);
+                continue;
+            }
+
+            budget = budget.saturating_sub(cost);
+            self.profile_counters.texture_upload_kb.add(cost / 1024);
+            self.apply_texture_update(update);
+        }
+
+        if !self.deferred_texture_updates.is_empty() {
+            self.profile_counters.deferred_uploads.add(self.deferred_texture_updates.len());
+            // Schedule another composite so that the spill-over drains
+            // even if no new frame arrives.
+            let mut notifier = self.notifier.lock().unwrap();
+            if let Some(ref mut notifier) = *notifier {
+                notifier.new_frame_ready();
+            }
+        }
+    }
+
+    fn apply_texture_update(&mut self, update: TextureUpdate) {
+        match update.op {
+            TextureUpdateOp::Create { width, height, format, filter, mode, data } => {
+                let CacheTextureId(cache_texture_index) = update.id;
+                if self.cache_texture_id_map.len() == cache_texture_index {
+                    // Create a new native texture, as requested by the texture cache.
+                    let texture_id = self.device
+                                         .create_texture_ids(1, TextureTarget::Default)[0];
+                    self.cache_texture_id_map.push(texture_id);
+                }
+                let texture_id = self.cache_texture_id_map[cache_texture_index];
 
+                if let Some(image) = data {
+                    match image {
+                        ImageData::Raw(raw) => {
+                            self.device.init_texture(texture_id,
+                                                     width,
+                                                     height,
+                                                     format,
+                                                     filter,
+                                                     mode,
+                                                     Some(raw.as_slice()));
+                        }
+                        ImageData::External(ext_image) => {
+                            match ext_image.image_type {
+                                ExternalImageType::ExternalBuffer => {
+                                    let device = &mut self.device;
+                                    match self.external_image_handler.as_mut() {
+                                        Some(handler) => {
                                             match handler.lock(ext_image.id, ext_image.channel_index).source {
                                                 ExternalImageSource::RawData(raw) => {
-                                                    self.device.init_texture(texture_id,
-                                                                             width,
-                                                                             height,
-                                                                             format,
-                                                                             filter,
-                                                                             mode,
-                                                                             Some(raw));
+                                                    device.init_texture(texture_id,
+                                                                        width,
+                                                                        height,
+                                                                        format,
+                                                                        filter,
+                                                                        mode,
+                                                                        Some(raw));
+                                                }
+                                                _ => {
+                                                    report_renderer_error(&self.notifier,
+                                                                          RendererError::InvalidExternalImageSource(
+                                                                              ext_image.id,
+                                                                              ext_image.channel_index));
+                                                    device.init_texture(texture_id,
+                                                                        width,
+                                                                        height,
+                                                                        format,
+                                                                        filter,
+                                                                        mode,
+                                                                        None);
                                                 }
-                                                _ => panic!("No external buffer found"),
                                             };
                                             handler.unlock(ext_image.id, ext_image.channel_index);
                                         }
-                                        ExternalImageType::Texture2DHandle |
-                                        ExternalImageType::TextureRectHandle |
-                                        ExternalImageType::TextureExternalHandle => {
-                                            panic!("External texture handle should not use TextureUpdateOp::Create.");
+                                        None => {
+                                            report_renderer_error(&self.notifier,
+                                                                  RendererError::MissingExternalImageHandler);
+                                            device.init_texture(texture_id,
+                                                                width,
+                                                                height,
+                                                                format,
+                                                                filter,
+                                                                mode,
+                                                                None);
                                         }
                                     }
                                 }
-                                _ => {
-                                    panic!("No suitable image buffer for TextureUpdateOp::Create.");
+                                ExternalImageType::Texture2DHandle |
+                                ExternalImageType::TextureRectHandle |
+                                ExternalImageType::TextureExternalHandle => {
+                                    panic!("External texture handle should not use TextureUpdateOp::Create.");
                                 }
                             }
-                        } else {
-                            self.device.init_texture(texture_id,
-                                                     width,
-                                                     height,
-                                                     format,
-                                                     filter,
-                                                     mode,
-                                                     None);
+                        }
+                        _ => {
+                            panic!("No suitable image buffer for TextureUpdateOp::Create.");
                         }
                     }
-                    TextureUpdateOp::Grow { width, height, format, filter, mode } => {
-                        let texture_id = self.cache_texture_id_map[update.id.0];
-                        self.device.resize_texture(texture_id,
-                                                   width,
-                                                   height,
-                                                   format,
-                                                   filter,
-                                                   mode);
-                    }
-                    TextureUpdateOp::Update { page_pos_x, page_pos_y, width, height, data, stride, offset } => {
-                        let texture_id = self.cache_texture_id_map[update.id.0];
-                        self.device.update_texture(texture_id,
-                                                   page_pos_x,
-                                                   page_pos_y,
-                                                   width, height, stride,
-                                                   &data[offset as usize..]);
-                    }
-                    TextureUpdateOp::UpdateForExternalBuffer { rect, id, channel_index, stride, offset } => {
-                        let handler = self.external_image_handler
-                                          .as_mut()
-                                          .expect("Found external image, but no handler set!");
-                        let device = &mut self.device;
-                        let cached_id = self.cache_texture_id_map[update.id.0];
+                } else {
+                    self.device.init_texture(texture_id,
+                                             width,
+                                             height,
+                                             format,
+                                             filter,
+                                             mode,
+                                             None);
+                }
+
+                self.device.set_texture_label(texture_id,
+                                              &format!("texture cache {}", cache_texture_index));
+            }
+            TextureUpdateOp::Grow { width, height, format, filter, mode } => {
+                let texture_id = self.cache_texture_id_map[update.id.0];
+                self.device.resize_texture(texture_id,
+                                           width,
+                                           height,
+                                           format,
+                                           filter,
+                                           mode);
+            }
+            TextureUpdateOp::Update { page_pos_x, page_pos_y, width, height, data, stride, offset } => {
+                let texture_id = self.cache_texture_id_map[update.id.0];
+                self.device.update_texture(texture_id,
+                                           page_pos_x,
+                                           page_pos_y,
+                                           width, height, stride,
+                                           &data[offset as usize..]);
+            }
+            TextureUpdateOp::UpdateForExternalBuffer { rect, id, channel_index, stride, offset } => {
+                let device = &mut self.device;
+                let cached_id = self.cache_texture_id_map[update.id.0];
 
+                match self.external_image_handler.as_mut() {
+                    Some(handler) => {
                         match handler.lock(id, channel_index).source {
                             ExternalImageSource::RawData(data) => {
                                 device.update_texture(cached_id,
@@ -1707,25 +3153,38 @@ impl Renderer {
                                                       stride,
                                                       &data[offset as usize..]);
                             }
-                            _ => panic!("No external buffer found"),
+                            _ => {
+                                report_renderer_error(&self.notifier,
+                                                      RendererError::InvalidExternalImageSource(id,
+                                                                                                channel_index));
+                            }
                         };
                         handler.unlock(id, channel_index);
                     }
-                    TextureUpdateOp::Free => {
-                        let texture_id = self.cache_texture_id_map[update.id.0];
-                        self.device.deinit_texture(texture_id);
+                    None => {
+                        // The cache entry keeps whatever was in it;
+                        // there is no source to update it from.
+                        report_renderer_error(&self.notifier,
+                                              RendererError::MissingExternalImageHandler);
                     }
                 }
             }
+            TextureUpdateOp::CopyRect { src_id, src_rect, dest_rect } => {
+                let src_texture_id = self.cache_texture_id_map[src_id.0];
+                let dest_texture_id = self.cache_texture_id_map[update.id.0];
+                self.device.copy_texture_rect(src_texture_id,
+                                              src_rect,
+                                              dest_texture_id,
+                                              dest_rect.origin);
+            }
+            TextureUpdateOp::Free => {
+                let texture_id = self.cache_texture_id_map[update.id.0];
+                self.device.deinit_texture(texture_id);
+            }
         }
     }
 
-    fn draw_instanced_batch<T>(&mut self,
-                               data: &[T],
-                               vao: VAOId,
-                               textures: &BatchTextures) {
-        self.device.bind_vao(vao);
-
+    fn bind_batch_textures(&mut self, textures: &BatchTextures) {
         for i in 0..textures.colors.len() {
             let texture_id = self.resolve_source_texture(&textures.colors[i]);
             self.device.bind_texture(TextureSampler::color(i), texture_id);
@@ -1735,9 +3194,43 @@ impl Renderer {
         if let Some(id) = self.dither_matrix_texture_id {
             self.device.bind_texture(TextureSampler::Dither, id);
         }
+    }
+
+    /// Whether instanced batching is in effect: the construction option
+    /// and the runtime debug flag both disable it.
+    fn batching_enabled(&self) -> bool {
+        self.enable_batcher && !self.debug_flags.contains(DISABLE_BATCHING_DBG)
+    }
+
+    /// While BATCH_STEP_DBG is set, returns whether the next batch in
+    /// frame submission order should be drawn, advancing the cursor.
+    fn should_draw_debug_batch(&mut self) -> bool {
+        if !self.debug_flags.contains(BATCH_STEP_DBG) {
+            return true;
+        }
+        self.debug_batch_cursor += 1;
+        self.debug_batch_cursor <= self.debug_batch_limit
+    }
 
-        if self.enable_batcher {
-            self.device.update_vao_instances(vao, data, VertexUsageHint::Stream);
+    fn draw_instanced_batch<T>(&mut self,
+                               data: &[T],
+                               vao: VAOId,
+                               textures: &BatchTextures,
+                               first_instance: Option<usize>) {
+        self.device.bind_vao(vao);
+        self.bind_batch_textures(textures);
+
+        if self.batching_enabled() {
+            match first_instance {
+                Some(first_instance) => {
+                    // The instances were uploaded as part of the target's
+                    // arena; just slice the draw out of it.
+                    self.device.set_vao_instance_offset(vao, first_instance);
+                }
+                None => {
+                    self.device.update_vao_instances(vao, data, VertexUsageHint::Stream);
+                }
+            }
             self.device.draw_indexed_triangles_instanced_u16(6, data.len() as i32);
             self.profile_counters.draw_calls.inc();
         } else {
@@ -1751,24 +3244,46 @@ impl Renderer {
         self.profile_counters.vertices.add(6 * data.len());
     }
 
-    fn submit_batch(&mut self,
-                    batch: &PrimitiveBatch,
-                    projection: &Transform3D<f32>,
-                    render_task_data: &[RenderTaskData],
-                    cache_texture: TextureId,
-                    render_target: Option<(TextureId, i32)>,
-                    target_dimensions: DeviceUintSize) {
+    // Draws several slices of the current target's instance arena with a
+    // single multi-draw indirect call. The base instance of each command
+    // selects the slice, so the instance attribute pointers must be at
+    // the front of the arena.
+    fn draw_multi_instanced_batch(&mut self,
+                                  draws: &[(usize, usize)],
+                                  vao: VAOId,
+                                  textures: &BatchTextures) {
+        self.device.bind_vao(vao);
+        self.bind_batch_textures(textures);
+
+        self.device.set_vao_instance_offset(vao, 0);
+        self.device.draw_indexed_triangles_instanced_multi_u16(6, draws);
+        self.profile_counters.draw_calls.inc();
+
+        let mut instance_count = 0;
+        for &(_, count) in draws {
+            instance_count += count;
+        }
+        self.profile_counters.vertices.add(6 * instance_count);
+    }
+
+    // Binds the shader a batch is drawn with, returning the GPU profile
+    // tag to mark its draws with.
+    fn bind_batch_shader(&mut self,
+                         batch: &PrimitiveBatch,
+                         projection: &Transform3D<f32>) -> GpuProfileTag {
         let transform_kind = batch.key.flags.transform_kind();
         let needs_clipping = batch.key.flags.needs_clipping();
         debug_assert!(!needs_clipping ||
                       match batch.key.blend_mode {
                           BlendMode::Alpha |
                           BlendMode::PremultipliedAlpha |
+                          BlendMode::Multiply |
+                          BlendMode::Screen |
                           BlendMode::Subpixel(..) => true,
                           BlendMode::None => false,
                       });
 
-        let marker = match batch.key.kind {
+        match batch.key.kind {
             AlphaBatchKind::Composite => {
                 self.ps_composite.bind(&mut self.device, projection);
                 GPU_TAG_PRIM_COMPOSITE
@@ -1804,6 +3319,8 @@ impl Renderer {
                     }
                     BlendMode::Alpha |
                     BlendMode::PremultipliedAlpha |
+                    BlendMode::Multiply |
+                    BlendMode::Screen |
                     BlendMode::None => {
                         self.ps_text_run.bind(&mut self.device, transform_kind, projection);
                     }
@@ -1855,7 +3372,18 @@ impl Renderer {
                 self.ps_cache_image.bind(&mut self.device, transform_kind, projection);
                 GPU_TAG_PRIM_CACHE_IMAGE
             }
-        };
+        }
+    }
+
+    fn submit_batch(&mut self,
+                    batch: &PrimitiveBatch,
+                    projection: &Transform3D<f32>,
+                    render_task_data: &[RenderTaskData],
+                    cache_texture: TextureId,
+                    render_target: Option<(TextureId, i32)>,
+                    target_dimensions: DeviceUintSize,
+                    first_instance: Option<usize>) {
+        let marker = self.bind_batch_shader(batch, projection);
 
         // Handle special case readback for composites.
         if batch.key.kind == AlphaBatchKind::Composite {
@@ -1920,13 +3448,31 @@ impl Renderer {
         let vao = self.prim_vao_id;
         self.draw_instanced_batch(&batch.instances,
                                   vao,
-                                  &batch.key.textures);
+                                  &batch.key.textures,
+                                  first_instance);
+    }
+
+    // Submits a run of batches that all share the GL state of `batch`
+    // with a single multi-draw indirect call, one (offset, count) arena
+    // slice per batch.
+    fn submit_multi_batch(&mut self,
+                          batch: &PrimitiveBatch,
+                          projection: &Transform3D<f32>,
+                          draws: &[(usize, usize)]) {
+        debug_assert!(batch.key.kind != AlphaBatchKind::Composite);
+
+        let marker = self.bind_batch_shader(batch, projection);
+
+        let _gm = self.gpu_profile.add_marker(marker);
+        let vao = self.prim_vao_id;
+        self.draw_multi_instanced_batch(draws, vao, &batch.key.textures);
     }
 
     fn draw_color_target(&mut self,
                          render_target: Option<(TextureId, i32)>,
                          target: &ColorRenderTarget,
                          target_size: DeviceUintSize,
+                         viewport: Option<DeviceUintRect>,
                          color_cache_texture: TextureId,
                          clear_color: Option<[f32; 4]>,
                          render_task_data: &[RenderTaskData],
@@ -1934,17 +3480,36 @@ impl Renderer {
         {
             let _gm = self.gpu_profile.add_marker(GPU_TAG_SETUP_TARGET);
             self.device.bind_draw_target(render_target, Some(target_size));
+            if let Some(viewport_rect) = viewport {
+                self.device.set_viewport_rect(viewport_rect);
+            }
             self.device.disable_depth();
             self.device.enable_depth_write();
             self.device.set_blend(false);
             self.device.set_blend_mode_alpha();
             match render_target {
-                Some(..) if self.enable_clear_scissor => {
+                // An eye viewport must not wipe out the other eye's half
+                // of the window, so restrict the clear to it.
+                None if viewport.is_some() => {
+                    self.device.clear_target_rect(clear_color,
+                                                  Some(1.0),
+                                                  viewport.unwrap().to_i32());
+                }
+                // The framebuffer pass allocates its root task at a fixed
+                // location, so its used rect stays empty when the pass is
+                // redirected to the output transform target; it takes the
+                // full clear below instead.
+                Some(..) if self.enable_clear_scissor && !target.used_rect().is_empty() => {
                     // TODO(gw): Applying a scissor rect and minimal clear here
                     // is a very large performance win on the Intel and nVidia
                     // GPUs that I have tested with. It's possible it may be a
                     // performance penalty on other GPU types - we should test this
                     // and consider different code paths.
+                    //
+                    // Nothing samples outside the used rect, so tiler GPUs
+                    // need not load last frame's contents before the
+                    // scissored clear.
+                    self.device.invalidate_target(true, true);
                     self.device.clear_target_rect(clear_color,
                                                   Some(1.0),
                                                   target.used_rect());
@@ -1957,6 +3522,45 @@ impl Renderer {
             self.device.disable_depth_write();
         }
 
+        // Gather the instances of every prim VAO draw on this target into
+        // one contiguous upload, in draw order. Each draw then sources a
+        // slice of the buffer via its recorded (offset, count), instead of
+        // re-uploading its instance vector individually.
+        let use_arena = self.batching_enabled();
+        // Multi-draw groups several batches into one call, which the
+        // batch stepping mode needs to attribute draws one by one.
+        let use_multi_draw = use_arena &&
+                             !self.debug_flags.contains(BATCH_STEP_DBG) &&
+                             self.device.get_capabilities().supports_multi_draw_indirect;
+        let mut arena_cursor = 0;
+
+        if use_arena {
+            self.prim_instance_arena.clear();
+            self.prim_instance_arena.extend_from_slice(&target.box_shadow_cache_prims);
+            self.prim_instance_arena.extend_from_slice(&target.text_run_cache_prims);
+            self.prim_instance_arena.extend_from_slice(&target.line_cache_prims);
+            for item in &target.alpha_batcher.batch_list.z_prepass_items {
+                self.prim_instance_arena.push(item.instance.clone());
+            }
+            for batch in target.alpha_batcher
+                               .batch_list
+                               .opaque_batches
+                               .iter()
+                               .rev() {
+                self.prim_instance_arena.extend_from_slice(&batch.instances);
+            }
+            for batch in &target.alpha_batcher.batch_list.alpha_batches {
+                self.prim_instance_arena.extend_from_slice(&batch.instances);
+            }
+
+            if !self.prim_instance_arena.is_empty() {
+                self.device.bind_vao(self.prim_vao_id);
+                self.device.update_vao_instances(self.prim_vao_id,
+                                                 &self.prim_instance_arena[..],
+                                                 VertexUsageHint::Stream);
+            }
+        }
+
         // Draw any blurs for this target.
         // Blurs are rendered as a standard 2-pass
         // separable implementation.
@@ -1965,21 +3569,59 @@ impl Renderer {
         //           blur radii with fixed weights.
         if !target.vertical_blurs.is_empty() || !target.horizontal_blurs.is_empty() {
             let _gm = self.gpu_profile.add_marker(GPU_TAG_BLUR);
-            let vao = self.blur_vao_id;
 
             self.device.set_blend(false);
-            self.cs_blur.bind(&mut self.device, projection);
 
-            if !target.vertical_blurs.is_empty() {
-                self.draw_instanced_batch(&target.vertical_blurs,
-                                          vao,
-                                          &BatchTextures::no_texture());
-            }
+            // Prefer the compute variant where available: one dispatch per
+            // blur task writes the target layer directly with image store,
+            // instead of rasterizing a quad per task over it. This can't
+            // apply to the main framebuffer, which isn't an image.
+            let use_compute = render_target.is_some() && self.cs_blur_compute.is_some();
+
+            if use_compute {
+                let (texture_id, layer_index) = render_target.unwrap();
+                let program = self.cs_blur_compute.as_ref().unwrap();
+                self.device.bind_compute_program(program);
+                self.device.bind_image_texture(0,
+                                               texture_id,
+                                               layer_index,
+                                               gl::WRITE_ONLY,
+                                               gl::RGBA8);
+
+                // The task rects of one target never overlap, so no
+                // barriers are needed between the dispatches.
+                for blur in target.vertical_blurs.iter().chain(target.horizontal_blurs.iter()) {
+                    let task = &render_task_data[blur.task_id as usize];
+                    self.device.set_compute_data(program, [blur.task_id,
+                                                           blur.src_task_id,
+                                                           blur.blur_direction,
+                                                           0]);
+                    self.device.dispatch_compute(compute_group_count(task.data[2] as u32),
+                                                 compute_group_count(task.data[3] as u32),
+                                                 1);
+                }
 
-            if !target.horizontal_blurs.is_empty() {
-                self.draw_instanced_batch(&target.horizontal_blurs,
-                                          vao,
-                                          &BatchTextures::no_texture());
+                // Later draws to this target and later passes sampling it
+                // must see the image writes.
+                self.device.compute_image_barrier();
+            } else {
+                let vao = self.blur_vao_id;
+
+                self.cs_blur.bind(&mut self.device, projection);
+
+                if !target.vertical_blurs.is_empty() {
+                    self.draw_instanced_batch(&target.vertical_blurs,
+                                              vao,
+                                              &BatchTextures::no_texture(),
+                                              None);
+                }
+
+                if !target.horizontal_blurs.is_empty() {
+                    self.draw_instanced_batch(&target.horizontal_blurs,
+                                              vao,
+                                              &BatchTextures::no_texture(),
+                                              None);
+                }
             }
         }
 
@@ -1989,9 +3631,13 @@ impl Renderer {
             let _gm = self.gpu_profile.add_marker(GPU_TAG_CACHE_BOX_SHADOW);
             let vao = self.prim_vao_id;
             self.cs_box_shadow.bind(&mut self.device, projection);
+            let first_instance = claim_arena_slice(use_arena,
+                                                   &mut arena_cursor,
+                                                   target.box_shadow_cache_prims.len());
             self.draw_instanced_batch(&target.box_shadow_cache_prims,
                                       vao,
-                                      &BatchTextures::no_texture());
+                                      &BatchTextures::no_texture(),
+                                      first_instance);
         }
 
         // Draw any textrun caches for this target. For now, this
@@ -2007,9 +3653,13 @@ impl Renderer {
             let _gm = self.gpu_profile.add_marker(GPU_TAG_CACHE_TEXT_RUN);
             let vao = self.prim_vao_id;
             self.cs_text_run.bind(&mut self.device, projection);
+            let first_instance = claim_arena_slice(use_arena,
+                                                   &mut arena_cursor,
+                                                   target.text_run_cache_prims.len());
             self.draw_instanced_batch(&target.text_run_cache_prims,
                                       vao,
-                                      &target.text_run_textures);
+                                      &target.text_run_textures,
+                                      first_instance);
         }
         if !target.line_cache_prims.is_empty() {
             // TODO(gw): Technically, we don't need blend for solid
@@ -2020,9 +3670,13 @@ impl Renderer {
             let _gm = self.gpu_profile.add_marker(GPU_TAG_CACHE_LINE);
             let vao = self.prim_vao_id;
             self.cs_line.bind(&mut self.device, projection);
+            let first_instance = claim_arena_slice(use_arena,
+                                                   &mut arena_cursor,
+                                                   target.line_cache_prims.len());
             self.draw_instanced_batch(&target.line_cache_prims,
                                       vao,
-                                      &BatchTextures::no_texture());
+                                      &BatchTextures::no_texture(),
+                                      first_instance);
         }
 
         if !target.alpha_batcher.is_empty() {
@@ -2035,23 +3689,103 @@ impl Renderer {
             self.device.enable_depth();
             self.device.enable_depth_write();
 
+            // Depth pre-pass: lay down depth for the large opaque
+            // regions of blended primitives before any batches draw, so
+            // the depth test can reject the alpha fragments hidden
+            // behind them. Color writes are off; only z is written.
+            let prepass_items = &target.alpha_batcher.batch_list.z_prepass_items;
+            if !prepass_items.is_empty() {
+                let _gm2 = GpuMarker::new(self.device.rc_gl(), "z prepass");
+                self.device.disable_color_write();
+                self.ps_rectangle.bind(&mut self.device,
+                                       TransformedRectKind::AxisAligned,
+                                       projection);
+                let vao = self.prim_vao_id;
+                for item in prepass_items {
+                    self.device.enable_scissor();
+                    self.device.set_scissor_rect(scissor_rect_for_target(&item.bounds,
+                                                                         render_target,
+                                                                         target_size,
+                                                                         self.surface_orientation));
+                    let first_instance = claim_arena_slice(use_arena,
+                                                           &mut arena_cursor,
+                                                           1);
+                    let data = [item.instance.clone()];
+                    self.draw_instanced_batch(&data,
+                                              vao,
+                                              &BatchTextures::no_texture(),
+                                              first_instance);
+                }
+                self.device.enable_color_write();
+            }
+
             // Draw opaque batches front-to-back for maximum
             // z-buffer efficiency!
-            for batch in target.alpha_batcher
-                               .batch_list
-                               .opaque_batches
-                               .iter()
-                               .rev() {
+            let opaque_batches = &target.alpha_batcher.batch_list.opaque_batches;
+            let mut multi_draws: Vec<(usize, usize)> = Vec::new();
+            let mut i = opaque_batches.len();
+            while i > 0 {
+                i -= 1;
+                let batch = &opaque_batches[i];
+                let mut scissor_bounds = batch.screen_bounds;
+                let first_instance = claim_arena_slice(use_arena,
+                                                       &mut arena_cursor,
+                                                       batch.instances.len());
+
+                // The arena slice was claimed either way, so skipped
+                // batches don't desync the draws that follow.
+                if !self.should_draw_debug_batch() {
+                    continue;
+                }
+
+                // The opaque batches were sorted by state when the batch
+                // list was finalized, so runs of batches that bind
+                // identical state are common here. Submit each run with a
+                // single multi-draw call.
+                if use_multi_draw {
+                    multi_draws.clear();
+                    multi_draws.push((first_instance.unwrap(), batch.instances.len()));
+                    while i > 0 && opaque_batches[i - 1].key.is_identical_to(&batch.key) {
+                        i -= 1;
+                        let next = &opaque_batches[i];
+                        let first_instance = claim_arena_slice(use_arena,
+                                                               &mut arena_cursor,
+                                                               next.instances.len());
+                        multi_draws.push((first_instance.unwrap(), next.instances.len()));
+                        scissor_bounds = scissor_bounds.union(&next.screen_bounds);
+                    }
+                    if multi_draws.len() > 1 {
+                        self.device.enable_scissor();
+                        self.device.set_scissor_rect(scissor_rect_for_target(&scissor_bounds,
+                                                                             render_target,
+                                                                             target_size,
+                                                                             self.surface_orientation));
+                        self.submit_multi_batch(batch, &projection, &multi_draws);
+                        continue;
+                    }
+                }
+
+                // Scissor the draw to the area the batch's items cover,
+                // so tiled GPUs can reject the rest of the target early.
+                self.device.enable_scissor();
+                self.device.set_scissor_rect(scissor_rect_for_target(&scissor_bounds,
+                                                                     render_target,
+                                                                     target_size,
+                                                                     self.surface_orientation));
                 self.submit_batch(batch,
                                   &projection,
                                   render_task_data,
                                   color_cache_texture,
                                   render_target,
-                                  target_size);
+                                  target_size,
+                                  first_instance);
             }
 
             self.device.disable_depth_write();
 
+            // Adjacent alpha batches with identical state were already
+            // merged when the batch list was finalized, so there are no
+            // runs left to group into a multi-draw here.
             for batch in &target.alpha_batcher.batch_list.alpha_batches {
                 if batch.key.blend_mode != prev_blend_mode {
                     match batch.key.blend_mode {
@@ -2066,31 +3800,68 @@ impl Renderer {
                             self.device.set_blend(true);
                             self.device.set_blend_mode_premultiplied_alpha();
                         }
+                        BlendMode::Multiply => {
+                            self.device.set_blend(true);
+                            self.device.set_blend_mode_multiply();
+                        }
+                        BlendMode::Screen => {
+                            self.device.set_blend(true);
+                            self.device.set_blend_mode_screen();
+                        }
                         BlendMode::Subpixel(color) => {
                             self.device.set_blend(true);
-                            self.device.set_blend_mode_subpixel(color);
+                            self.device.set_blend_mode_subpixel(color.into());
                         }
                     }
                     prev_blend_mode = batch.key.blend_mode;
                 }
 
+                // Composite batches read back from the framebuffer with a
+                // blit, which the scissor would clip in the wrong space;
+                // they run unscissored.
+                if batch.key.kind == AlphaBatchKind::Composite {
+                    self.device.disable_scissor();
+                } else {
+                    self.device.enable_scissor();
+                    self.device.set_scissor_rect(scissor_rect_for_target(&batch.screen_bounds,
+                                                                         render_target,
+                                                                         target_size,
+                                                                         self.surface_orientation));
+                }
+
+                let first_instance = claim_arena_slice(use_arena,
+                                                       &mut arena_cursor,
+                                                       batch.instances.len());
+                if !self.should_draw_debug_batch() {
+                    continue;
+                }
                 self.submit_batch(batch,
                                   &projection,
                                   render_task_data,
                                   color_cache_texture,
                                   render_target,
-                                  target_size);
+                                  target_size,
+                                  first_instance);
             }
 
+            self.device.disable_scissor();
             self.device.disable_depth();
             self.device.set_blend(false);
         }
+
+        // Once the target is drawn, its depth attachment is never sampled
+        // again, so tiler GPUs can drop it instead of storing it out to
+        // memory at the end of the pass.
+        if render_target.is_some() {
+            self.device.invalidate_target(false, true);
+        }
     }
 
     fn draw_alpha_target(&mut self,
                          render_target: (TextureId, i32),
                          target: &AlphaRenderTarget,
                          target_size: DeviceUintSize,
+                         render_task_data: &[RenderTaskData],
                          projection: &Transform3D<f32>) {
         {
             let _gm = self.gpu_profile.add_marker(GPU_TAG_SETUP_TARGET);
@@ -2103,6 +3874,9 @@ impl Renderer {
             // GPUs that I have tested with. It's possible it may be a
             // performance penalty on other GPU types - we should test this
             // and consider different code paths.
+            // Nothing samples outside the used rect, so tiler GPUs need
+            // not load last frame's contents before the scissored clear.
+            self.device.invalidate_target(true, false);
             let clear_color = [1.0, 1.0, 1.0, 0.0];
             self.device.clear_target_rect(Some(clear_color),
                                           None,
@@ -2123,7 +3897,8 @@ impl Renderer {
                 self.cs_clip_border.bind(&mut self.device, projection);
                 self.draw_instanced_batch(&target.clip_batcher.border_clears,
                                           vao,
-                                          &BatchTextures::no_texture());
+                                          &BatchTextures::no_texture(),
+                                          None);
             }
 
             // Draw any dots or dashes for border corners.
@@ -2138,7 +3913,8 @@ impl Renderer {
                 self.cs_clip_border.bind(&mut self.device, projection);
                 self.draw_instanced_batch(&target.clip_batcher.borders,
                                           vao,
-                                          &BatchTextures::no_texture());
+                                          &BatchTextures::no_texture(),
+                                          None);
             }
 
             // switch to multiplicative blending
@@ -2147,11 +3923,80 @@ impl Renderer {
 
             // draw rounded cornered rectangles
             if !target.clip_batcher.rectangles.is_empty() {
-                let _gm2 = GpuMarker::new(self.device.rc_gl(), "clip rectangles");
-                self.cs_clip_rectangle.bind(&mut self.device, projection);
-                self.draw_instanced_batch(&target.clip_batcher.rectangles,
-                                          vao,
-                                          &BatchTextures::no_texture());
+                // The compute variant covers rectangle instances only, and
+                // interleaving image stores with rasterized clip items
+                // would need a barrier around every batch, so it is only
+                // used when the rectangles have the mask to themselves.
+                // GLES image load/store has no r8 format, so the A8 mask
+                // can't be bound as an image there either.
+                let use_compute = self.cs_clip_rectangle_compute.is_some() &&
+                                  target.clip_batcher.border_clears.is_empty() &&
+                                  target.clip_batcher.borders.is_empty() &&
+                                  target.clip_batcher.images.is_empty() &&
+                                  self.device.gl().get_type() == gl::GlType::Gl &&
+                                  clip_task_runs_fit(&target.clip_batcher.rectangles);
+
+                if use_compute {
+                    let _gm2 = GpuMarker::new(self.device.rc_gl(), "clip rectangles [compute]");
+                    let (texture_id, layer_index) = render_target;
+                    let program = self.cs_clip_rectangle_compute.as_ref().unwrap();
+                    self.device.bind_compute_program(program);
+                    self.device.bind_image_texture(0,
+                                                   texture_id,
+                                                   layer_index,
+                                                   gl::WRITE_ONLY,
+                                                   gl::R8);
+
+                    // Order the image stores after the clear of the
+                    // used rect above.
+                    self.device.compute_image_barrier();
+
+                    // One dispatch per clip task evaluates all of that
+                    // task's instances, replacing the multiplicative
+                    // blend of the raster path with an in-register
+                    // multiply. Task rects never overlap, so no barriers
+                    // are needed between the dispatches.
+                    let rectangles = &target.clip_batcher.rectangles;
+                    let mut start = 0;
+                    while start < rectangles.len() {
+                        let task_id = rectangles[start].task_id;
+                        let mut end = start + 1;
+                        while end < rectangles.len() && rectangles[end].task_id == task_id {
+                            end += 1;
+                        }
+
+                        let mut instance_data = [0; MAX_COMPUTE_CLIP_INSTANCES * 4];
+                        for (i, instance) in rectangles[start .. end].iter().enumerate() {
+                            instance_data[i * 4 + 0] = instance.layer_index;
+                            instance_data[i * 4 + 1] = instance.address;
+                            instance_data[i * 4 + 2] = instance.segment;
+                        }
+
+                        // data0 of a clip task is (x0, y0, x1, y1).
+                        let task = &render_task_data[task_id as usize];
+                        let width = (task.data[2] - task.data[0]) as u32;
+                        let height = (task.data[3] - task.data[1]) as u32;
+
+                        self.device.set_compute_data(program, [task_id, 0, 0, (end - start) as i32]);
+                        self.device.set_compute_instances(program, &instance_data[0 .. (end - start) * 4]);
+                        self.device.dispatch_compute(compute_group_count(width),
+                                                     compute_group_count(height),
+                                                     1);
+
+                        start = end;
+                    }
+
+                    // The primitive shaders of later passes sample the
+                    // mask; flush the image writes.
+                    self.device.compute_image_barrier();
+                } else {
+                    let _gm2 = GpuMarker::new(self.device.rc_gl(), "clip rectangles");
+                    self.cs_clip_rectangle.bind(&mut self.device, projection);
+                    self.draw_instanced_batch(&target.clip_batcher.rectangles,
+                                              vao,
+                                              &BatchTextures::no_texture(),
+                                              None);
+                }
             }
             // draw image masks
             for (mask_texture_id, items) in target.clip_batcher.images.iter() {
@@ -2166,20 +4011,171 @@ impl Renderer {
                 self.cs_clip_image.bind(&mut self.device, projection);
                 self.draw_instanced_batch(items,
                                           vao,
-                                          &textures);
+                                          &textures,
+                                          None);
+            }
+        }
+    }
+
+    /// Returns the intermediate target the final pass is redirected to
+    /// while an output color transform is active, (re)allocating it to
+    /// match the framebuffer size.
+    fn output_transform_target(&mut self, framebuffer_size: &DeviceUintSize) -> TextureId {
+        let texture_id = match self.output_transform_texture_id {
+            Some(texture_id) => texture_id,
+            None => {
+                let texture_id = self.device.create_texture_ids(1, TextureTarget::Default)[0];
+                self.output_transform_texture_id = Some(texture_id);
+                texture_id
+            }
+        };
+
+        if self.device.get_texture_dimensions(texture_id) != *framebuffer_size {
+            self.device.init_texture(texture_id,
+                                     framebuffer_size.width,
+                                     framebuffer_size.height,
+                                     self.color_target_format,
+                                     TextureFilter::Linear,
+                                     RenderTargetMode::LayerRenderTarget(1),
+                                     None);
+            self.device.set_texture_label(texture_id, "output transform");
+        }
+
+        texture_id
+    }
+
+    /// Returns the texture frames are rendered into when
+    /// `render_to_texture` is set, (re)allocating it to match the
+    /// framebuffer size.
+    fn frame_output_target(&mut self, framebuffer_size: &DeviceUintSize) -> TextureId {
+        let texture_id = match self.frame_output_texture_id {
+            Some(texture_id) => texture_id,
+            None => {
+                let texture_id = self.device.create_texture_ids(1, TextureTarget::Default)[0];
+                self.frame_output_texture_id = Some(texture_id);
+                texture_id
+            }
+        };
+
+        if self.device.get_texture_dimensions(texture_id) != *framebuffer_size {
+            self.device.init_texture(texture_id,
+                                     framebuffer_size.width,
+                                     framebuffer_size.height,
+                                     self.color_target_format,
+                                     TextureFilter::Linear,
+                                     RenderTargetMode::LayerRenderTarget(1),
+                                     None);
+            self.device.set_texture_label(texture_id, "frame output");
+        }
+
+        texture_id
+    }
+
+    /// Composites the intermediate target holding the final pass to the
+    /// framebuffer through the frame's output color transform.
+    fn draw_output_transform(&mut self,
+                             transform: &OutputColorTransform,
+                             framebuffer_size: &DeviceUintSize) {
+        let _gm = GpuMarker::new(self.device.rc_gl(), "output transform");
+
+        let texture_id = self.output_transform_texture_id
+                             .expect("No output transform target?");
+
+        self.device.bind_draw_target(None, Some(*framebuffer_size));
+        self.device.disable_depth();
+        self.device.set_blend(false);
+
+        let content_size = self.surface_orientation.content_size(framebuffer_size);
+        let w = content_size.width as f32;
+        let h = content_size.height as f32;
+        let projection = Transform3D::ortho(0.0,
+                                            w,
+                                            h,
+                                            0.0,
+                                            ORTHO_NEAR_PLANE,
+                                            ORTHO_FAR_PLANE);
+        // The redirected pass rendered the content unrotated; any quarter
+        // turn applies here, where it reaches the surface. The rotated
+        // quad covers the surface exactly, since clip space is square.
+        let projection = match self.surface_orientation.matrix() {
+            Some(rotation) => projection.post_mul(&rotation),
+            None => projection,
+        };
+        // The redirected pass rendered without the external projection,
+        // so it applies here, where the output reaches display space.
+        let projection = match self.external_projection {
+            Some(ref external) => projection.post_mul(external),
+            None => projection,
+        };
+
+        // The redirected pass used the cache target projection, which
+        // stores rows in the order this framebuffer draw reads them, so
+        // the quad samples the target unflipped.
+        let vertices = [
+            OutputTransformVertex { x: 0.0, y: 0.0, u: 0.0, v: 0.0 },
+            OutputTransformVertex { x: w, y: 0.0, u: 1.0, v: 0.0 },
+            OutputTransformVertex { x: 0.0, y: h, u: 0.0, v: 1.0 },
+            OutputTransformVertex { x: w, y: h, u: 1.0, v: 1.0 },
+        ];
+        let indices: [u32; 6] = [0, 1, 2, 2, 1, 3];
+
+        self.device.bind_program(&self.output_transform_program);
+        self.device.set_uniforms(&self.output_transform_program, &projection);
+        self.device.set_uniform_mat3(self.u_color_matrix, &transform.matrix);
+        self.device.set_uniform_2f(self.u_gamma, transform.src_gamma, transform.dst_gamma);
+        self.device.bind_texture(TextureSampler::Color0, texture_id);
+        self.device.bind_vao(self.output_transform_vao);
+        self.device.update_vao_indices(self.output_transform_vao,
+                                       &indices,
+                                       VertexUsageHint::Dynamic);
+        self.device.update_vao_main_vertices(self.output_transform_vao,
+                                             &vertices,
+                                             VertexUsageHint::Dynamic);
+        self.device.draw_triangles_u32(0, indices.len() as i32);
+    }
+
+    /// Inserts a release fence for every WebGL canvas texture sampled by
+    /// the frame just drawn. The producer collects the fence with
+    /// `take_webgl_release_sync` and waits on it before rendering into
+    /// the texture again.
+    fn release_webgl_surfaces(&mut self) {
+        for texture_id in mem::replace(&mut self.acquired_webgl_textures, Vec::new()) {
+            let sync = self.device.gl().fence_sync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+            if let Some(stale) = self.webgl_release_syncs.insert(texture_id, sync as usize) {
+                // The producer never collected the previous fence; it
+                // clearly synchronizes by other means, so drop it.
+                self.device.gl().delete_sync(stale as gl::GLsync);
             }
         }
     }
 
     fn update_deferred_resolves(&mut self, frame: &mut Frame) {
+        // Acquire WebGL canvas surfaces: insert a server-side wait on
+        // each producer fence, so that no draw issued below samples a
+        // half-rendered canvas. Each fence is consumed exactly once.
+        for acquire in frame.webgl_acquires.drain(..) {
+            let sync = acquire.sync_object as gl::GLsync;
+            self.device.gl().wait_sync(sync, 0, gl::TIMEOUT_IGNORED);
+            self.device.gl().delete_sync(sync);
+            self.acquired_webgl_textures.push(acquire.texture_id);
+        }
+
         // The first thing we do is run through any pending deferred
         // resolves, and use a callback to get the UV rect for this
         // custom item. Then we patch the resource_rects structure
         // here before it's uploaded to the GPU.
         if !frame.deferred_resolves.is_empty() {
-            let handler = self.external_image_handler
-                              .as_mut()
-                              .expect("Found external image, but no handler set!");
+            let handler = match self.external_image_handler.as_mut() {
+                Some(handler) => handler,
+                None => {
+                    // Nothing can be resolved without a handler. Leave the
+                    // external images unresolved; resolve_source_texture
+                    // draws the fallback texture in their place.
+                    report_renderer_error(&self.notifier,
+                                          RendererError::MissingExternalImageHandler);
+                    return;
+                }
+            };
 
             for deferred_resolve in &frame.deferred_resolves {
                 GpuMarker::fire(self.device.gl(), "deferred resolve");
@@ -2199,7 +4195,16 @@ impl Renderer {
 
                 let texture_id = match image.source {
                     ExternalImageSource::NativeTexture(texture_id) => TextureId::new(texture_id, texture_target),
-                    _ => panic!("No native texture found."),
+                    _ => {
+                        // The image is locked either way, so record it with
+                        // the fallback texture: it gets drawn as debug pink
+                        // and unlocked at the end of the frame as usual.
+                        report_renderer_error(&self.notifier,
+                                              RendererError::InvalidExternalImageSource(
+                                                  ext_image.id,
+                                                  ext_image.channel_index));
+                        self.fallback_texture_id
+                    }
                 };
 
                 self.external_images.insert((ext_image.id, ext_image.channel_index), texture_id);
@@ -2217,55 +4222,116 @@ impl Renderer {
 
     fn unlock_external_images(&mut self) {
         if !self.external_images.is_empty() {
-            let handler = self.external_image_handler
-                              .as_mut()
-                              .expect("Found external image, but no handler set!");
-
-            for (ext_data, _) in self.external_images.drain() {
-                handler.unlock(ext_data.0, ext_data.1);
+            // Only a handler can have locked images, so if there is no
+            // handler there is nothing to unlock. The map can't be non-empty
+            // without one, but don't panic over it during drawing.
+            if let Some(ref mut handler) = self.external_image_handler {
+                for (ext_data, _) in self.external_images.drain() {
+                    handler.unlock(ext_data.0, ext_data.1);
+                }
+            } else {
+                self.external_images.clear();
             }
         }
     }
 
+    /// Releases the render target textures pooled between frames, in
+    /// response to memory pressure. The next frame that needs targets
+    /// creates fresh ones. Returns an estimate of the bytes released
+    /// (one layer per target).
+    fn trim_render_target_pool(&mut self) -> usize {
+        let mut bytes = 0;
+        for texture_id in self.color_render_targets.drain(..) {
+            let size = self.device.get_texture_dimensions(texture_id);
+            bytes += (size.width * size.height * 4) as usize;
+            self.device.deinit_texture(texture_id);
+        }
+        for texture_id in self.alpha_render_targets.drain(..) {
+            let size = self.device.get_texture_dimensions(texture_id);
+            bytes += (size.width * size.height) as usize;
+            self.device.deinit_texture(texture_id);
+        }
+        if let Some(texture_id) = self.output_transform_texture_id.take() {
+            let size = self.device.get_texture_dimensions(texture_id);
+            bytes += (size.width * size.height * 4) as usize;
+            self.device.deinit_texture(texture_id);
+        }
+        bytes
+    }
+
     fn start_frame(&mut self, frame: &mut Frame) {
         let _gm = self.gpu_profile.add_marker(GPU_TAG_SETUP_DATA);
 
-        // Assign render targets to the passes.
+        // Assign render targets to the passes. On devices that can't
+        // render to texture array layers each target gets its own 2D
+        // texture instead; see `Capabilities::avoid_texture_arrays`.
+        let use_2d_targets = self.device.get_capabilities().avoid_texture_arrays;
+
         for pass in &mut frame.passes {
             debug_assert!(pass.color_texture_id.is_none());
             debug_assert!(pass.alpha_texture_id.is_none());
+            debug_assert!(pass.color_texture_ids.is_empty());
+            debug_assert!(pass.alpha_texture_ids.is_empty());
 
             if pass.needs_render_target_kind(RenderTargetKind::Color) {
-                pass.color_texture_id = Some(self.color_render_targets
-                                                 .pop()
-                                                 .unwrap_or_else(|| {
-                                                     self.device
-                                                         .create_texture_ids(1, TextureTarget::Array)[0]
-                                                  }));
+                if use_2d_targets {
+                    for _ in 0..pass.required_target_count(RenderTargetKind::Color) {
+                        let texture_id = self.color_render_targets
+                                             .pop()
+                                             .unwrap_or_else(|| {
+                                                 self.device
+                                                     .create_texture_ids(1, TextureTarget::Default)[0]
+                                              });
+                        pass.color_texture_ids.push(texture_id);
+                    }
+                } else {
+                    pass.color_texture_id = Some(self.color_render_targets
+                                                     .pop()
+                                                     .unwrap_or_else(|| {
+                                                         self.device
+                                                             .create_texture_ids(1, TextureTarget::Array)[0]
+                                                      }));
+                }
             }
 
             if pass.needs_render_target_kind(RenderTargetKind::Alpha) {
-                pass.alpha_texture_id = Some(self.alpha_render_targets
-                                                 .pop()
-                                                 .unwrap_or_else(|| {
-                                                     self.device
-                                                         .create_texture_ids(1, TextureTarget::Array)[0]
-                                                  }));
+                if use_2d_targets {
+                    for _ in 0..pass.required_target_count(RenderTargetKind::Alpha) {
+                        let texture_id = self.alpha_render_targets
+                                             .pop()
+                                             .unwrap_or_else(|| {
+                                                 self.device
+                                                     .create_texture_ids(1, TextureTarget::Default)[0]
+                                              });
+                        pass.alpha_texture_ids.push(texture_id);
+                    }
+                } else {
+                    pass.alpha_texture_id = Some(self.alpha_render_targets
+                                                     .pop()
+                                                     .unwrap_or_else(|| {
+                                                         self.device
+                                                             .create_texture_ids(1, TextureTarget::Array)[0]
+                                                      }));
+                }
             }
         }
 
 
-        // Init textures and render targets to match this scene.
-        for pass in &frame.passes {
+        // Init textures and render targets to match this scene. The targets
+        // are relabelled each frame since they get reassigned to different
+        // passes as they cycle through the pool.
+        for (pass_index, pass) in frame.passes.iter().enumerate() {
             if let Some(texture_id) = pass.color_texture_id {
                 let target_count = pass.required_target_count(RenderTargetKind::Color);
                 self.device.init_texture(texture_id,
                                          frame.cache_size.width as u32,
                                          frame.cache_size.height as u32,
-                                         ImageFormat::BGRA8,
+                                         self.color_target_format,
                                          TextureFilter::Linear,
                                          RenderTargetMode::LayerRenderTarget(target_count as i32),
                                          None);
+                self.device.set_texture_label(texture_id,
+                                              &format!("color target (pass {})", pass_index));
             }
             if let Some(texture_id) = pass.alpha_texture_id {
                 let target_count = pass.required_target_count(RenderTargetKind::Alpha);
@@ -2276,8 +4342,36 @@ impl Renderer {
                                          TextureFilter::Nearest,
                                          RenderTargetMode::LayerRenderTarget(target_count as i32),
                                          None);
+                self.device.set_texture_label(texture_id,
+                                              &format!("alpha target (pass {})", pass_index));
             }
-        }
+            for (target_index, &texture_id) in pass.color_texture_ids.iter().enumerate() {
+                self.device.init_texture(texture_id,
+                                         frame.cache_size.width as u32,
+                                         frame.cache_size.height as u32,
+                                         self.color_target_format,
+                                         TextureFilter::Linear,
+                                         RenderTargetMode::LayerRenderTarget(1),
+                                         None);
+                self.device.set_texture_label(texture_id,
+                                              &format!("color target (pass {} target {})",
+                                                       pass_index,
+                                                       target_index));
+            }
+            for (target_index, &texture_id) in pass.alpha_texture_ids.iter().enumerate() {
+                self.device.init_texture(texture_id,
+                                         frame.cache_size.width as u32,
+                                         frame.cache_size.height as u32,
+                                         ImageFormat::A8,
+                                         TextureFilter::Nearest,
+                                         RenderTargetMode::LayerRenderTarget(1),
+                                         None);
+                self.device.set_texture_label(texture_id,
+                                              &format!("alpha target (pass {} target {})",
+                                                       pass_index,
+                                                       target_index));
+            }
+        }
 
         // TODO(gw): This is a hack / workaround for #728.
         // We should find a better way to implement these updates rather
@@ -2292,17 +4386,45 @@ impl Renderer {
                        framebuffer_size: &DeviceUintSize) {
         let _gm = GpuMarker::new(self.device.rc_gl(), "tile frame draw");
 
+        self.last_pipeline_profiles = frame.pipeline_profiles.clone();
+
+        // Drawing into an empty framebuffer is handled in render(), which
+        // doesn't get this far with a zero-sized window.
+        debug_assert!(framebuffer_size.width > 0 && framebuffer_size.height > 0);
+
+        // The size the content was laid out at; differs from the surface
+        // size by a width/height swap when a quarter turn orientation is
+        // set.
+        let content_size = self.surface_orientation.content_size(framebuffer_size);
+
         // Some tests use a restricted viewport smaller than the main screen size.
         // Ensure we clear the framebuffer in these tests.
         // TODO(gw): Find a better solution for this?
-        let needs_clear = frame.window_size.width < framebuffer_size.width ||
-                          frame.window_size.height < framebuffer_size.height;
+        let needs_clear = frame.window_size.width < content_size.width ||
+                          frame.window_size.height < content_size.height;
+
+        let stereo_params = self.stereo_params.clone();
+
+        // A color transform can't be folded into the final pass shaders,
+        // since blending happens in the rendered (not display) space.
+        // Instead the final pass is redirected to an intermediate target
+        // and composited to the framebuffer through the transform
+        // afterwards. Stereo presentation hands the output to a VR
+        // compositor that owns the display, so no transform applies there.
+        let output_transform = match stereo_params {
+            Some(..) => None,
+            None => frame.output_color_transform,
+        };
 
         self.device.disable_depth_write();
         self.device.disable_stencil();
         self.device.set_blend(false);
 
+        self.render_target_debug_info.clear();
+        self.debug_batch_cursor = 0;
+
         if frame.passes.is_empty() {
+            self.device.bind_draw_target(None, Some(*framebuffer_size));
             self.device.clear_target(Some(self.clear_color.to_array()), Some(1.0));
         } else {
             self.start_frame(frame);
@@ -2310,26 +4432,58 @@ impl Renderer {
             let mut src_color_id = self.dummy_cache_texture_id;
             let mut src_alpha_id = self.dummy_cache_texture_id;
 
-            for pass in &mut frame.passes {
+            for (pass_index, pass) in frame.passes.iter_mut().enumerate() {
+                let _gm = GpuMarker::new(self.device.rc_gl(),
+                                         &format!("pass {}", pass_index));
+
                 let size;
                 let clear_color;
                 let projection;
 
                 if pass.is_framebuffer {
-                    clear_color = if self.clear_framebuffer || needs_clear {
+                    // The intermediate output transform target holds stale
+                    // texels from earlier frames, so it always gets cleared.
+                    clear_color = if self.clear_framebuffer || needs_clear ||
+                                     output_transform.is_some() {
                         Some(frame.background_color.map_or(self.clear_color.to_array(), |color| {
                             color.to_array()
                         }))
                     } else {
                         None
                     };
-                    size = framebuffer_size;
-                    projection = Transform3D::ortho(0.0,
-                                                 size.width as f32,
-                                                 size.height as f32,
-                                                 0.0,
-                                                 ORTHO_NEAR_PLANE,
-                                                 ORTHO_FAR_PLANE)
+                    size = if output_transform.is_some() {
+                        // Redirected to a content sized texture; any
+                        // surface rotation applies when it is composited.
+                        &content_size
+                    } else {
+                        framebuffer_size
+                    };
+                    projection = if output_transform.is_some() {
+                        // Redirected to a texture: use the cache target
+                        // projection, so that the final composite samples
+                        // the target unflipped.
+                        Transform3D::ortho(0.0,
+                                        size.width as f32,
+                                        0.0,
+                                        size.height as f32,
+                                        ORTHO_NEAR_PLANE,
+                                        ORTHO_FAR_PLANE)
+                    } else {
+                        let window_projection = Transform3D::ortho(0.0,
+                                                                   content_size.width as f32,
+                                                                   content_size.height as f32,
+                                                                   0.0,
+                                                                   ORTHO_NEAR_PLANE,
+                                                                   ORTHO_FAR_PLANE);
+                        let window_projection = match self.surface_orientation.matrix() {
+                            Some(rotation) => window_projection.post_mul(&rotation),
+                            None => window_projection,
+                        };
+                        match self.external_projection {
+                            Some(ref external) => window_projection.post_mul(external),
+                            None => window_projection,
+                        }
+                    }
                 } else {
                     size = &frame.cache_size;
                     clear_color = Some([0.0, 0.0, 0.0, 0.0]);
@@ -2345,28 +4499,111 @@ impl Renderer {
                 self.device.bind_texture(TextureSampler::CacheRGBA8, src_color_id);
 
                 for (target_index, target) in pass.alpha_targets.targets.iter().enumerate() {
-                    self.draw_alpha_target((pass.alpha_texture_id.unwrap(), target_index as i32),
+                    let render_target = match pass.alpha_texture_id {
+                        Some(texture_id) => (texture_id, target_index as i32),
+                        None => (pass.alpha_texture_ids[target_index], 0),
+                    };
+                    self.draw_alpha_target(render_target,
                                            target,
                                            *size,
+                                           &frame.render_task_data,
                                            &projection);
                 }
 
                 for (target_index, target) in pass.color_targets.targets.iter().enumerate() {
                     let render_target = pass.color_texture_id.map(|texture_id| {
                         (texture_id, target_index as i32)
+                    }).or_else(|| {
+                        pass.color_texture_ids.get(target_index).map(|&texture_id| {
+                            (texture_id, 0)
+                        })
                     });
-                    self.draw_color_target(render_target,
-                                           target,
-                                           *size,
-                                           src_color_id,
-                                           clear_color,
-                                           &frame.render_task_data,
-                                           &projection);
-
+                    let render_target = match output_transform {
+                        Some(..) if pass.is_framebuffer => {
+                            Some((self.output_transform_target(&content_size), 0))
+                        }
+                        _ => render_target,
+                    };
+                    match stereo_params {
+                        Some(ref params) if pass.is_framebuffer => {
+                            // Draw both eye viewports of this frame in the
+                            // same pass: every offscreen pass above ran once
+                            // and its results are shared between the eyes.
+                            // With OVR_multiview2 the two draws could
+                            // collapse into one, but that needs multiview
+                            // aware shaders; see
+                            // `Capabilities::supports_multiview`.
+                            for eye in &[&params.left, &params.right] {
+                                self.draw_color_target(render_target,
+                                                       target,
+                                                       *size,
+                                                       Some(eye.viewport),
+                                                       src_color_id,
+                                                       clear_color,
+                                                       &frame.render_task_data,
+                                                       &eye.projection);
+                            }
+                        }
+                        _ => {
+                            self.draw_color_target(render_target,
+                                                   target,
+                                                   *size,
+                                                   None,
+                                                   src_color_id,
+                                                   clear_color,
+                                                   &frame.render_task_data,
+                                                   &projection);
+                        }
+                    }
                 }
 
-                src_color_id = pass.color_texture_id.unwrap_or(self.dummy_cache_texture_id);
-                src_alpha_id = pass.alpha_texture_id.unwrap_or(self.dummy_cache_texture_id);
+                // With the 2D target fallback only the first target of a
+                // pass can be sampled by the next one, since the cache
+                // samplers collapse to a single sampler2D. A pass only
+                // overflows its first target when a frame needs more cache
+                // space than one screen-sized target, which is rare enough
+                // to live with on the drivers that need the fallback.
+                src_color_id = pass.color_texture_id
+                                   .or(pass.color_texture_ids.first().cloned())
+                                   .unwrap_or(self.dummy_cache_texture_id);
+                src_alpha_id = pass.alpha_texture_id
+                                   .or(pass.alpha_texture_ids.first().cloned())
+                                   .unwrap_or(self.dummy_cache_texture_id);
+
+                // Record the target layers for the debug view before the
+                // textures go back to the pool.
+                if self.debug_flags.contains(RENDER_TARGET_DBG) {
+                    if pass.needs_render_target_kind(RenderTargetKind::Alpha) {
+                        for target_index in 0..pass.alpha_targets.targets.len() {
+                            let (texture_id, layer_index) = match pass.alpha_texture_id {
+                                Some(texture_id) => (texture_id, target_index as i32),
+                                None => (pass.alpha_texture_ids[target_index], 0),
+                            };
+                            self.render_target_debug_info.push(RenderTargetDebugItem {
+                                texture_id,
+                                layer_index,
+                                kind: RenderTargetKind::Alpha,
+                                pass_index,
+                                task_count: pass.alpha_targets.task_counts[target_index],
+                            });
+                        }
+                    }
+                    if pass.needs_render_target_kind(RenderTargetKind::Color) {
+                        for target_index in 0..pass.color_targets.targets.len() {
+                            let (texture_id, layer_index) = match pass.color_texture_id {
+                                Some(texture_id) => (texture_id, target_index as i32),
+                                None => (pass.color_texture_ids[target_index], 0),
+                            };
+                            self.render_target_debug_info.push(RenderTargetDebugItem {
+                                texture_id,
+                                layer_index,
+                                kind: RenderTargetKind::Color,
+                                pass_index,
+                                task_count: pass.color_targets.task_counts[target_index],
+                            });
+                        }
+                    }
+                }
 
                 // Return the texture IDs to the pool for next frame.
                 if let Some(texture_id) = pass.color_texture_id.take() {
@@ -2375,14 +4612,39 @@ impl Renderer {
                 if let Some(texture_id) = pass.alpha_texture_id.take() {
                     self.alpha_render_targets.push(texture_id);
                 }
+                self.color_render_targets.extend(pass.color_texture_ids.drain(..));
+                self.alpha_render_targets.extend(pass.alpha_texture_ids.drain(..));
             }
 
             self.color_render_targets.reverse();
             self.alpha_render_targets.reverse();
+
+            if let Some(ref transform) = output_transform {
+                self.draw_output_transform(transform, framebuffer_size);
+            }
+
             self.draw_render_target_debug(framebuffer_size);
             self.draw_texture_cache_debug(framebuffer_size);
+            self.draw_paint_flashing_debug(frame);
         }
 
+        // Record which external images this composite sampled, before the
+        // locks are dropped, so media code polling
+        // `flush_sampled_external_images` can tell which video frames
+        // reached the screen.
+        let mut image_ids: Vec<ExternalImageId> = self.external_images
+                                                      .keys()
+                                                      .map(|&(external_id, _)| external_id)
+                                                      .collect();
+        // Multi-channel images (e.g. YUV planes) are locked once per
+        // channel; report each image only once.
+        image_ids.sort_by_key(|image_id| image_id.0);
+        image_ids.dedup();
+        self.last_sampled_external_images = Some(SampledExternalImages {
+            image_ids,
+            composite_time_ns: precise_time_ns(),
+        });
+
         self.unlock_external_images();
     }
 
@@ -2395,7 +4657,253 @@ impl Renderer {
     }
 
     pub fn set_debug_flags(&mut self, flags: DebugFlags) {
+        // The backend only collects texture cache snapshots while the debug
+        // view needs them.
+        let cache_debug = flags.intersects(TEXTURE_CACHE_DBG | TEXTURE_CACHE_INSPECT_DBG);
+        if cache_debug != self.debug_flags.intersects(TEXTURE_CACHE_DBG | TEXTURE_CACHE_INSPECT_DBG) {
+            self.api_tx.send(ApiMsg::EnableTextureCacheDebug(cache_debug)).ok();
+            if !cache_debug {
+                self.texture_cache_debug = None;
+            }
+        }
+        if !flags.contains(RENDER_TARGET_DBG) {
+            self.render_target_debug_info.clear();
+            self.render_target_debug_select = None;
+        }
+        // Forget the recorded content hashes when paint flashing is turned
+        // off, so re-enabling it starts from a clean slate instead of
+        // flashing everything that changed in between.
+        if !flags.contains(PAINT_FLASHING_DBG) {
+            self.painted_tag_hashes.clear();
+        }
+        // Start the next stepping session from the first batch again.
+        if !flags.contains(BATCH_STEP_DBG) {
+            self.debug_batch_limit = 1;
+        }
         self.debug_flags = flags;
+        // Timer queries cost real GPU time on some drivers, so they are only
+        // issued while the profiler HUD is up (or auto-capture needs them).
+        self.gpu_profile.set_enabled(flags.contains(PROFILER_DBG) ||
+                                     self.gpu_capture_threshold_ns.is_some());
+    }
+
+    pub fn get_clear_color(&self) -> ColorF {
+        self.clear_color
+    }
+
+    /// Changes the color the framebuffer is cleared to. Takes effect on
+    /// the next frame.
+    pub fn set_clear_color(&mut self, color: ColorF) {
+        self.clear_color = color;
+    }
+
+    pub fn get_clear_framebuffer(&self) -> bool {
+        self.clear_framebuffer
+    }
+
+    pub fn set_clear_framebuffer(&mut self, clear: bool) {
+        self.clear_framebuffer = clear;
+    }
+
+    pub fn get_enable_clear_scissor(&self) -> bool {
+        self.enable_clear_scissor
+    }
+
+    /// Toggles scissored render target clears. Stays off on devices where
+    /// scissored clears miss the fast clear path, like the
+    /// construction-time option.
+    pub fn set_enable_clear_scissor(&mut self, enabled: bool) {
+        self.enable_clear_scissor = enabled &&
+            !self.device.get_capabilities().avoid_scissored_clears;
+    }
+
+    pub fn get_enable_batcher(&self) -> bool {
+        self.enable_batcher
+    }
+
+    /// Toggles instanced batching, e.g. to bisect a batching bug from a
+    /// debugger without restarting the compositor.
+    pub fn set_enable_batcher(&mut self, enabled: bool) {
+        self.enable_batcher = enabled;
+    }
+
+    /// Changes how many frames an unused resource stays in the texture
+    /// cache. Forwarded to the backend, so it applies from the next
+    /// generated frame onward.
+    pub fn set_cache_expiry_frames(&mut self, frames: u32) {
+        self.api_tx.send(ApiMsg::SetCacheExpiryFrames(frames)).ok();
+    }
+
+    /// Steps the render target debug view through its targets. Intended as
+    /// a key handling hook for embedders: each call advances from the
+    /// thumbnail overview to the first target shown full-screen, then
+    /// through the remaining targets in pass order, and finally back to the
+    /// overview. A no-op unless RENDER_TARGET_DBG is set.
+    pub fn cycle_debug_render_target(&mut self) {
+        if !self.debug_flags.contains(RENDER_TARGET_DBG) {
+            return;
+        }
+        self.render_target_debug_select = match self.render_target_debug_select {
+            None => Some(0),
+            Some(index) => {
+                if index + 1 < self.render_target_debug_info.len() {
+                    Some(index + 1)
+                } else {
+                    None
+                }
+            }
+        };
+    }
+
+    /// Steps the batch debug view one batch deeper into the frame.
+    /// Intended as a key handling hook for embedders: each call draws one
+    /// more batch, wrapping back to a single batch once the whole frame
+    /// is shown. A no-op unless BATCH_STEP_DBG is set.
+    pub fn step_debug_batch(&mut self) {
+        if !self.debug_flags.contains(BATCH_STEP_DBG) {
+            return;
+        }
+        if self.debug_batch_limit >= self.debug_batch_total {
+            self.debug_batch_limit = 1;
+        } else {
+            self.debug_batch_limit += 1;
+        }
+    }
+
+    /// Changes the frame budget the profiler overlay's frame bar graph
+    /// colors against, e.g. when the window moves to a display with a
+    /// different refresh rate.
+    pub fn set_profiler_frame_budget(&mut self, budget_ns: u64) {
+        self.profiler.set_frame_budget(budget_ns);
+    }
+
+    /// Requests that RenderDoc capture the next frame. A no-op unless the
+    /// `renderdoc` feature is enabled and RenderDoc is attached to the
+    /// process.
+    pub fn trigger_frame_capture(&mut self) {
+        if let Some(ref renderdoc) = self.renderdoc {
+            renderdoc.trigger_capture();
+        }
+    }
+
+    /// Starts capturing every presented frame to a y4m stream at `path`;
+    /// see the `frame_capture` module. Encoding runs on a background
+    /// thread and the readback is deferred by a frame, so the cost on the
+    /// render thread is a framebuffer blit and one pixel readback per
+    /// frame. `frame_rate` only sets the nominal rate written to the
+    /// stream header.
+    pub fn start_frame_sequence_capture(&mut self,
+                                        path: PathBuf,
+                                        frame_rate: u32) -> io::Result<()> {
+        // Restarting a capture finishes the previous stream first.
+        self.stop_frame_sequence_capture();
+        let encoder = try!(FrameSequenceEncoder::new(path, frame_rate));
+        self.frame_capture = Some(FrameCapture {
+            encoder,
+            targets: [TextureId::invalid(), TextureId::invalid()],
+            next_target: 0,
+            pending: None,
+        });
+        Ok(())
+    }
+
+    /// Stops the frame sequence capture, reading back the last pending
+    /// frame and finishing the stream. A no-op when no capture is active.
+    pub fn stop_frame_sequence_capture(&mut self) {
+        if self.frame_capture.is_none() {
+            return;
+        }
+
+        //Note: a fake frame, since the readback and texture deletion have
+        // to happen inside one.
+        self.device.begin_frame(1.0);
+        self.read_back_capture_frame();
+        if let Some(capture) = self.frame_capture.take() {
+            for &texture_id in &capture.targets {
+                if texture_id.is_valid() {
+                    self.device.deinit_texture(texture_id);
+                }
+            }
+            // Dropping the capture sends the stop message and joins the
+            // encoder thread, flushing the stream.
+        }
+        self.device.end_frame();
+    }
+
+    /// Reads back the capture target filled while rendering the previous
+    /// frame and hands the pixels to the encoder thread. The GPU finished
+    /// that frame long ago, so the readback doesn't stall the pipeline.
+    fn read_back_capture_frame(&mut self) {
+        let (texture_id, size) = match self.frame_capture {
+            Some(ref mut capture) => {
+                match capture.pending.take() {
+                    Some(pending) => pending,
+                    None => return,
+                }
+            }
+            None => return,
+        };
+
+        let mut data = vec![0u8; (size.width * size.height * 4) as usize];
+        self.device.bind_read_target(Some((texture_id, 0)));
+        self.device.gl().read_pixels_into_buffer(0,
+                                                 0,
+                                                 size.width as gl::GLsizei,
+                                                 size.height as gl::GLsizei,
+                                                 gl::RGBA,
+                                                 gl::UNSIGNED_BYTE,
+                                                 &mut data);
+
+        if let Some(ref capture) = self.frame_capture {
+            capture.encoder.encode_frame(CapturedFrame {
+                size,
+                data,
+            });
+        }
+    }
+
+    /// Blits the presented frame into the next capture target and marks
+    /// it pending, to be read back when the next frame starts.
+    fn capture_presented_frame(&mut self, framebuffer_size: &DeviceUintSize) {
+        let (texture_id, index) = match self.frame_capture {
+            Some(ref mut capture) => {
+                let index = capture.next_target;
+                capture.next_target = (index + 1) % capture.targets.len();
+                (capture.targets[index], index)
+            }
+            None => return,
+        };
+
+        let texture_id = if texture_id.is_valid() {
+            texture_id
+        } else {
+            self.device.create_texture_ids(1, TextureTarget::Default)[0]
+        };
+
+        if self.device.get_texture_dimensions(texture_id) != *framebuffer_size {
+            self.device.init_texture(texture_id,
+                                     framebuffer_size.width,
+                                     framebuffer_size.height,
+                                     ImageFormat::BGRA8,
+                                     TextureFilter::Linear,
+                                     RenderTargetMode::SimpleRenderTarget,
+                                     None);
+            self.device.set_texture_label(texture_id, "frame capture");
+        }
+
+        let blit_rect = rect(0,
+                             0,
+                             framebuffer_size.width as i32,
+                             framebuffer_size.height as i32);
+        self.device.bind_draw_target(Some((texture_id, 0)), Some(*framebuffer_size));
+        // The source is the presented framebuffer, so the source rect
+        // must be supplied explicitly.
+        self.device.blit_render_target(None, Some(blit_rect), blit_rect);
+
+        if let Some(ref mut capture) = self.frame_capture {
+            capture.targets[index] = texture_id;
+            capture.pending = Some((texture_id, *framebuffer_size));
+        }
     }
 
     pub fn save_cpu_profile(&self, filename: &str) {
@@ -2408,10 +4916,33 @@ impl Renderer {
             return;
         }
 
+        if self.render_target_debug_info.is_empty() {
+            return;
+        }
+
+        let fb_width = framebuffer_size.width as i32;
+        let fb_height = framebuffer_size.height as i32;
+
+        // When a target is selected, show it alone, scaled up to fill the
+        // framebuffer. The selection is clamped since the number of targets
+        // can change from frame to frame.
+        if let Some(selected) = self.render_target_debug_select {
+            let index = cmp::min(selected, self.render_target_debug_info.len() - 1);
+            let item = self.render_target_debug_info[index];
+            self.draw_render_target_debug_item(&item,
+                                               rect(0, 0, fb_width, fb_height),
+                                               framebuffer_size);
+            let label = format!("[{}/{}] {}",
+                                index + 1,
+                                self.render_target_debug_info.len(),
+                                Renderer::render_target_debug_label(&item));
+            self.debug.add_text(16.0, 24.0, &label, debug_colors::WHITE.into());
+            return;
+        }
+
         let mut spacing = 16;
         let mut size = 512;
-        let fb_width = framebuffer_size.width as i32;
-        let num_textures = self.color_render_targets.iter().chain(self.alpha_render_targets.iter()).count() as i32;
+        let num_textures = self.render_target_debug_info.len() as i32;
 
         if num_textures * (size + spacing) > fb_width {
             let factor = fb_width as f32 / (num_textures * (size + spacing)) as f32;
@@ -2419,22 +4950,64 @@ impl Renderer {
             spacing = (spacing as f32 * factor) as i32;
         }
 
-        for (i, texture_id) in self.color_render_targets.iter().chain(self.alpha_render_targets.iter()).enumerate() {
-            let layer_count = self.device.get_render_target_layer_count(*texture_id);
-            for layer_index in 0..layer_count {
-                let x = fb_width - (spacing + size) * (i as i32 + 1);
-                let y = spacing;
+        for i in 0..self.render_target_debug_info.len() {
+            let item = self.render_target_debug_info[i];
+            let x = fb_width - (spacing + size) * (i as i32 + 1);
+            let y = spacing;
+
+            self.draw_render_target_debug_item(&item,
+                                               rect(x, y, size, size),
+                                               framebuffer_size);
+
+            // The blit rect is in bottom-up framebuffer coordinates, while
+            // the debug text is drawn top-down.
+            let label = Renderer::render_target_debug_label(&item);
+            self.debug.add_text(x as f32 + 2.0,
+                                (fb_height - y - size) as f32 + 12.0,
+                                &label,
+                                debug_colors::WHITE.into());
+        }
+    }
 
-                let dest_rect = rect(x, y, size, size);
+    /// Draws one recorded render target layer into the given rect, which is
+    /// in bottom-up framebuffer coordinates like `blit_render_target`. Color
+    /// targets are blitted directly; alpha targets go through a false color
+    /// ramp so the R8 coverage values are visible.
+    fn draw_render_target_debug_item(&mut self,
+                                     item: &RenderTargetDebugItem,
+                                     dest_rect: DeviceIntRect,
+                                     framebuffer_size: &DeviceUintSize) {
+        match item.kind {
+            RenderTargetKind::Color => {
                 self.device.blit_render_target(
-                    Some((*texture_id, layer_index as i32)),
+                    Some((item.texture_id, item.layer_index)),
                     None,
                     dest_rect
                 );
             }
+            RenderTargetKind::Alpha => {
+                let fb_height = framebuffer_size.height as f32;
+                let target_rect = rect(dest_rect.origin.x as f32,
+                                       fb_height - (dest_rect.origin.y + dest_rect.size.height) as f32,
+                                       dest_rect.size.width as f32,
+                                       dest_rect.size.height as f32);
+                self.debug.draw_alpha_target_slice(&mut self.device,
+                                                   item.texture_id,
+                                                   item.layer_index,
+                                                   &target_rect,
+                                                   framebuffer_size);
+            }
         }
     }
 
+    fn render_target_debug_label(item: &RenderTargetDebugItem) -> String {
+        let kind = match item.kind {
+            RenderTargetKind::Color => "color",
+            RenderTargetKind::Alpha => "alpha",
+        };
+        format!("pass {} {} ({} tasks)", item.pass_index, kind, item.task_count)
+    }
+
     fn draw_texture_cache_debug(&mut self, framebuffer_size: &DeviceUintSize) {
         if !self.debug_flags.contains(TEXTURE_CACHE_DBG) {
             return;
@@ -2462,6 +5035,97 @@ impl Renderer {
 
             let dest_rect = rect(x, y, size, size);
             self.device.blit_render_target(Some((*texture_id, 0)), None, dest_rect);
+
+            // Overlay the allocation rects on the page we just blitted,
+            // color coded by format and dimmed with age, so the packing of
+            // the page and any stale entries are visible. The blit has a
+            // flipped y axis relative to the debug renderer, so texel rows
+            // are mapped from the bottom of the destination square.
+            if let Some(ref debug_info) = self.texture_cache_debug {
+                let texture_size = self.device.get_texture_dimensions(*texture_id);
+                let sx = size as f32 / texture_size.width as f32;
+                let sy = size as f32 / texture_size.height as f32;
+                let fb_height = framebuffer_size.height as f32;
+                let inspect = self.debug_flags.contains(TEXTURE_CACHE_INSPECT_DBG);
+
+                for alloc in &debug_info.allocations {
+                    if alloc.texture_id.0 != i {
+                        continue;
+                    }
+
+                    let x0 = x as f32 + alloc.rect.origin.x as f32 * sx;
+                    let x1 = x0 + alloc.rect.size.width as f32 * sx;
+                    let y1 = fb_height - y as f32 - alloc.rect.origin.y as f32 * sy;
+                    let y0 = y1 - alloc.rect.size.height as f32 * sy;
+
+                    let mut color = match alloc.format {
+                        ImageFormat::A8 => debug_colors::RED,
+                        ImageFormat::BGRA8 => debug_colors::GREEN,
+                        ImageFormat::RGB8 => debug_colors::BLUE,
+                        ImageFormat::RG8 => debug_colors::YELLOW,
+                        ImageFormat::Invalid |
+                        ImageFormat::RGBAF32 |
+                        ImageFormat::RGB10A2 |
+                        ImageFormat::RGBA16F => debug_colors::WHITE,
+                    };
+                    let age = debug_info.current_frame_id.0 - alloc.last_access.0;
+                    color.a = if age == 0 {
+                        0.4
+                    } else if age < 60 {
+                        0.25
+                    } else {
+                        0.1
+                    };
+                    self.debug.add_quad(x0, y0, x1, y1, color.into(), color.into());
+
+                    if inspect {
+                        let label = match alloc.owner {
+                            TextureCacheOwner::Image(key) => {
+                                format!("img {}:{}", (key.0).0, key.1)
+                            }
+                            TextureCacheOwner::Glyph(font_key, index) => {
+                                format!("g {}:{} #{}", (font_key.0).0, font_key.1, index)
+                            }
+                            TextureCacheOwner::Path(content_hash) => {
+                                format!("path {:08x}", content_hash as u32)
+                            }
+                        };
+                        self.debug.add_text(x0, y0, &label, debug_colors::WHITE.into());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Overlays a translucent flash on every tagged primitive whose content
+    /// changed since it was last drawn, which in practice means since the
+    /// last display list that touched it. Scrolling moves primitives on
+    /// screen without altering their content hash, so it does not flash.
+    fn draw_paint_flashing_debug(&mut self, frame: &Frame) {
+        if !self.debug_flags.contains(PAINT_FLASHING_DBG) {
+            return;
+        }
+
+        let mut color = debug_colors::MAGENTA;
+        color.a = 0.35;
+
+        for painted in &frame.painted_tags {
+            let changed = match self.painted_tag_hashes.insert(painted.tag,
+                                                               painted.content_hash) {
+                Some(previous_hash) => previous_hash != painted.content_hash,
+                None => true,
+            };
+            if !changed {
+                continue;
+            }
+
+            let rect = &painted.screen_rect;
+            self.debug.add_quad(rect.origin.x as f32,
+                                rect.origin.y as f32,
+                                (rect.origin.x + rect.size.width) as f32,
+                                (rect.origin.y + rect.size.height) as f32,
+                                color.into(),
+                                color.into());
         }
     }
 
@@ -2475,19 +5139,440 @@ impl Renderer {
                             rect: DeviceUintRect,
                             format: ReadPixelsFormat,
                             output: &mut [u8]) {
-        let (gl_format, gl_type, size) = match format {
+        assert_eq!(output.len(), (4 * rect.size.width * rect.size.height) as usize);
+        self.device.gl().flush();
+
+        let pixel_count = (rect.size.width * rect.size.height) as usize;
+
+        match self.color_target_format {
+            ImageFormat::RGBA16F => {
+                // A float framebuffer can't be read back as bytes; read
+                // floats and quantize on the CPU.
+                let mut data = vec![0.0f32; pixel_count * 4];
+                {
+                    let byte_len = data.len() * 4;
+                    let bytes: &mut [u8] = unsafe {
+                        slice::from_raw_parts_mut(data.as_mut_ptr() as *mut u8, byte_len)
+                    };
+                    self.device.gl().read_pixels_into_buffer(rect.origin.x as gl::GLint,
+                                                             rect.origin.y as gl::GLint,
+                                                             rect.size.width as gl::GLsizei,
+                                                             rect.size.height as gl::GLsizei,
+                                                             gl::RGBA,
+                                                             gl::FLOAT,
+                                                             bytes);
+                }
+                for (texel, out) in data.chunks(4).zip(output.chunks_mut(4)) {
+                    for i in 0..4 {
+                        out[i] = (texel[i].max(0.0).min(1.0) * 255.0 + 0.5) as u8;
+                    }
+                }
+            }
+            ImageFormat::RGB10A2 => {
+                let mut data = vec![0u32; pixel_count];
+                {
+                    let byte_len = data.len() * 4;
+                    let bytes: &mut [u8] = unsafe {
+                        slice::from_raw_parts_mut(data.as_mut_ptr() as *mut u8, byte_len)
+                    };
+                    self.device.gl().read_pixels_into_buffer(rect.origin.x as gl::GLint,
+                                                             rect.origin.y as gl::GLint,
+                                                             rect.size.width as gl::GLsizei,
+                                                             rect.size.height as gl::GLsizei,
+                                                             gl::RGBA,
+                                                             gl::UNSIGNED_INT_2_10_10_10_REV,
+                                                             bytes);
+                }
+                for (&texel, out) in data.iter().zip(output.chunks_mut(4)) {
+                    // Keep the top 8 of each 10 bit channel, and expand
+                    // the 2 bit alpha across the byte.
+                    out[0] = ((texel & 0x3ff) >> 2) as u8;
+                    out[1] = (((texel >> 10) & 0x3ff) >> 2) as u8;
+                    out[2] = (((texel >> 20) & 0x3ff) >> 2) as u8;
+                    out[3] = ((texel >> 30) * 85) as u8;
+                }
+            }
+            _ => {
+                let gl_format = match format {
+                    ReadPixelsFormat::Rgba8 => gl::RGBA,
+                    ReadPixelsFormat::Bgra8 => get_gl_format_bgra(self.device.gl()),
+                };
+                self.device.gl().read_pixels_into_buffer(rect.origin.x as gl::GLint,
+                                                         rect.origin.y as gl::GLint,
+                                                         rect.size.width as gl::GLsizei,
+                                                         rect.size.height as gl::GLsizei,
+                                                         gl_format,
+                                                         gl::UNSIGNED_BYTE,
+                                                         output);
+                return;
+            }
+        }
+
+        // The wide formats were read in RGBA channel order; swizzle for
+        // BGRA requests.
+        if format == ReadPixelsFormat::Bgra8 {
+            for texel in output.chunks_mut(4) {
+                texel.swap(0, 2);
+            }
+        }
+    }
+
+    /// Copies `rect` of the last presented frame into `output`, scaled on
+    /// the GPU to `size` pixels of the requested format, with rows in
+    /// top-to-bottom order. `rect` uses the same bottom-up framebuffer
+    /// coordinates as `read_pixels_into`.
+    ///
+    /// The downscale is a chain of half-size linear blits, so every source
+    /// pixel contributes to the result (a box filter) rather than the
+    /// sparse sampling a single large blit would do. This keeps thumbnail
+    /// generation off the main thread's readback path: only `size` pixels
+    /// ever cross back over the bus. Must be called between frames, after
+    /// `render`.
+    pub fn capture_screenshot(&mut self,
+                              rect: DeviceUintRect,
+                              size: DeviceUintSize,
+                              format: ReadPixelsFormat,
+                              output: &mut [u8]) {
+        let (gl_format, gl_type, bpp) = match format {
             ReadPixelsFormat::Rgba8 => (gl::RGBA, gl::UNSIGNED_BYTE, 4),
             ReadPixelsFormat::Bgra8 => (get_gl_format_bgra(self.device.gl()), gl::UNSIGNED_BYTE, 4),
         };
-        assert_eq!(output.len(), (size * rect.size.width * rect.size.height) as usize);
+        assert!(size.width > 0 && size.height > 0);
+        assert_eq!(output.len(), (bpp * size.width * size.height) as usize);
+
+        self.device.begin_frame(1.0);
+        let _gm = GpuMarker::new(self.device.rc_gl(), "screenshot");
+
+        let mut src_texture: Option<TextureId> = None;
+        let mut src_rect = DeviceIntRect::new(
+            DeviceIntPoint::new(rect.origin.x as i32, rect.origin.y as i32),
+            DeviceIntSize::new(rect.size.width as i32, rect.size.height as i32));
+        let mut src_size = rect.size;
+        let mut scratch_textures = Vec::new();
+
+        // Screenshots are rare enough that the scratch targets are simply
+        // allocated per call and freed below, rather than pooled. They
+        // stay BGRA8 whatever `color_target_format` is: the first blit
+        // converts a wide framebuffer on the GPU, which also covers the
+        // readback at the end.
+        loop {
+            let dest_size = if src_size.width / 2 <= size.width &&
+                               src_size.height / 2 <= size.height {
+                size
+            } else {
+                DeviceUintSize::new(cmp::max(size.width, src_size.width / 2),
+                                    cmp::max(size.height, src_size.height / 2))
+            };
+
+            let texture_id = self.device.create_texture_ids(1, TextureTarget::Default)[0];
+            self.device.init_texture(texture_id,
+                                     dest_size.width,
+                                     dest_size.height,
+                                     ImageFormat::BGRA8,
+                                     TextureFilter::Linear,
+                                     RenderTargetMode::SimpleRenderTarget,
+                                     None);
+            scratch_textures.push(texture_id);
+
+            let dest_rect = DeviceIntRect::new(
+                DeviceIntPoint::new(0, 0),
+                DeviceIntSize::new(dest_size.width as i32, dest_size.height as i32));
+            self.device.bind_draw_target(Some((texture_id, 0)), Some(dest_size));
+            self.device.blit_render_target(src_texture.map(|id| (id, 0)),
+                                           Some(src_rect),
+                                           dest_rect);
+
+            src_texture = Some(texture_id);
+            src_rect = dest_rect;
+            src_size = dest_size;
+
+            if dest_size == size {
+                break;
+            }
+        }
+
+        self.device.bind_read_target(src_texture.map(|id| (id, 0)));
         self.device.gl().flush();
-        self.device.gl().read_pixels_into_buffer(rect.origin.x as gl::GLint,
-                                                 rect.origin.y as gl::GLint,
-                                                 rect.size.width as gl::GLsizei,
-                                                 rect.size.height as gl::GLsizei,
+        self.device.gl().read_pixels_into_buffer(0,
+                                                 0,
+                                                 size.width as gl::GLsizei,
+                                                 size.height as gl::GLsizei,
                                                  gl_format,
                                                  gl_type,
                                                  output);
+
+        // GL hands rows back bottom-up; flip them into the top-down order
+        // image consumers expect. The buffer is only `size` pixels by now.
+        let row_len = (bpp * size.width) as usize;
+        let height = size.height as usize;
+        let mut row = vec![0u8; row_len];
+        for y in 0..height / 2 {
+            let top = y * row_len;
+            let bottom = (height - 1 - y) * row_len;
+            row.copy_from_slice(&output[top..top + row_len]);
+            for i in 0..row_len {
+                output[top + i] = output[bottom + i];
+            }
+            output[bottom..bottom + row_len].copy_from_slice(&row);
+        }
+
+        for texture_id in scratch_textures {
+            self.device.deinit_texture(texture_id);
+        }
+        self.device.end_frame();
+    }
+
+    /// Draws a calibration pattern at the given device pixel ratio,
+    /// reads it back, and checks that pixel snapping, text positioning
+    /// and width rounding behave consistently; see the `dpr_audit`
+    /// module. Returns the failing cases, empty when the ratio passes.
+    /// Intended for fractional ratios like 1.25, 1.5 and 1.75. Must be
+    /// called between frames, after `render`.
+    pub fn audit_device_pixel_ratio(&mut self, device_pixel_ratio: f32) -> Vec<DprAuditFailure> {
+        let dpr = device_pixel_ratio;
+        let size = DeviceUintSize::new(dpr_audit::TARGET_WIDTH,
+                                       dpr_audit::TARGET_HEIGHT);
+
+        self.device.begin_frame(dpr);
+        let _gm = GpuMarker::new(self.device.rc_gl(), "dpr audit");
+
+        let texture_id = self.device.create_texture_ids(1, TextureTarget::Default)[0];
+        self.device.init_texture(texture_id,
+                                 size.width,
+                                 size.height,
+                                 ImageFormat::BGRA8,
+                                 TextureFilter::Nearest,
+                                 RenderTargetMode::SimpleRenderTarget,
+                                 None);
+        self.device.set_texture_label(texture_id, "dpr audit");
+        self.device.bind_draw_target(Some((texture_id, 0)), Some(size));
+        self.device.clear_target(Some([0.0, 0.0, 0.0, 1.0]), None);
+
+        // The quads go in at raw, unsnapped device coordinates, the same
+        // way primitive edges reach the rasterizer; the analysis expects
+        // the snapping rules to land them on whole device pixels.
+        let white = debug_colors::WHITE.into();
+        for &x in &dpr_audit::bar_positions() {
+            self.debug.add_quad(x * dpr,
+                                dpr_audit::EDGE_BAND_TOP,
+                                (x + 1.0) * dpr,
+                                dpr_audit::EDGE_BAND_BOTTOM,
+                                white,
+                                white);
+            self.debug.add_quad(x * dpr,
+                                dpr_audit::WIDTH_BAND_TOP,
+                                (x + dpr_audit::WIDTH_BAR_LOGICAL) * dpr,
+                                dpr_audit::WIDTH_BAND_BOTTOM,
+                                white,
+                                white);
+        }
+        let text_x = dpr_audit::TEXT_LOGICAL_X * dpr;
+        self.debug.add_text(text_x,
+                            dpr_audit::TEXT_BASELINE,
+                            dpr_audit::TEXT_SAMPLE,
+                            white);
+        self.debug.add_text(text_x + dpr_audit::TEXT_COPY_OFFSET,
+                            dpr_audit::TEXT_BASELINE,
+                            dpr_audit::TEXT_SAMPLE,
+                            white);
+        self.debug.render(&mut self.device, &size);
+
+        let mut pixels = vec![0u8; (size.width * size.height * 4) as usize];
+        self.device.bind_read_target(Some((texture_id, 0)));
+        self.device.gl().read_pixels_into_buffer(0,
+                                                 0,
+                                                 size.width as gl::GLsizei,
+                                                 size.height as gl::GLsizei,
+                                                 gl::RGBA,
+                                                 gl::UNSIGNED_BYTE,
+                                                 &mut pixels);
+        self.device.deinit_texture(texture_id);
+        self.device.end_frame();
+
+        dpr_audit::analyze(&pixels, device_pixel_ratio)
+    }
+
+    /// Tell the renderer that the GL context was lost (for example via
+    /// EGL_CONTEXT_LOST on Android) and has been replaced by a fresh context
+    /// that shares no state with the old one. The replacement context must be
+    /// current when this is called.
+    ///
+    /// All device resources are recreated: shaders recompile lazily on first
+    /// use, the VAOs and cache textures are rebuilt here, and the render
+    /// backend is asked to flush its caches and publish new frames, which
+    /// re-uploads the texture cache contents to the new context.
+    pub fn notify_context_lost(&mut self) {
+        // Every GL name the device tracked died with the old context; forget
+        // them all rather than trying to delete them.
+        self.device.on_context_lost();
+
+        // Anything queued up for the GPU was aimed at resources that no
+        // longer exist. The backend re-sends everything in response to the
+        // NotifyContextLost message below.
+        self.current_frame = None;
+        self.pending_texture_updates.clear();
+        self.deferred_texture_updates.clear();
+        self.pending_gpu_cache_updates.clear();
+        // The drawn-but-unswapped frames died with the context, and content
+        // redrawn after the restore has to be reported again.
+        self.pending_presentations.clear();
+        self.presented_epochs.clear();
+        // Sync objects from the old context are dead; nothing to delete.
+        self.acquired_webgl_textures.clear();
+        self.webgl_release_syncs.clear();
+        self.cache_texture_id_map.clear();
+        self.texture_cache_debug = None;
+        self.color_render_targets.clear();
+        self.alpha_render_targets.clear();
+        self.render_target_debug_info.clear();
+
+        self.device.begin_frame(1.0);
+
+        // Shaders are compiled lazily, so dropping the stale programs is
+        // enough to get fresh ones compiled on first use.
+        self.cs_box_shadow.reset(&mut self.device);
+        self.cs_text_run.reset(&mut self.device);
+        self.cs_line.reset(&mut self.device);
+        self.cs_blur.reset(&mut self.device);
+        self.cs_clip_rectangle.reset(&mut self.device);
+        self.cs_clip_image.reset(&mut self.device);
+        self.cs_clip_border.reset(&mut self.device);
+
+        // The compute variants are compiled eagerly, so recompile them
+        // for the new context right away.
+        if let Some(mut program) = self.cs_blur_compute.take() {
+            self.device.delete_compute_program(&mut program);
+        }
+        if let Some(mut program) = self.cs_clip_rectangle_compute.take() {
+            self.device.delete_compute_program(&mut program);
+        }
+        if self.device.get_capabilities().supports_compute_shaders &&
+           !self.device.get_capabilities().avoid_texture_arrays {
+            self.cs_blur_compute = create_compute_shader("cs_blur.comp", &mut self.device);
+            self.cs_clip_rectangle_compute = create_compute_shader("cs_clip_rectangle.comp",
+                                                                   &mut self.device);
+        }
+        self.ps_rectangle.reset(&mut self.device);
+        self.ps_rectangle_clip.reset(&mut self.device);
+        self.ps_text_run.reset(&mut self.device);
+        self.ps_text_run_subpixel.reset(&mut self.device);
+        for shader in &mut self.ps_image {
+            if let &mut Some(ref mut shader) = shader {
+                shader.reset(&mut self.device);
+            }
+        }
+        for shader in &mut self.ps_yuv_image {
+            if let &mut Some(ref mut shader) = shader {
+                shader.reset(&mut self.device);
+            }
+        }
+        self.ps_border_corner.reset(&mut self.device);
+        self.ps_border_edge.reset(&mut self.device);
+        self.ps_gradient.reset(&mut self.device);
+        self.ps_angle_gradient.reset(&mut self.device);
+        self.ps_radial_gradient.reset(&mut self.device);
+        self.ps_box_shadow.reset(&mut self.device);
+        self.ps_cache_image.reset(&mut self.device);
+        self.ps_line.reset(&mut self.device);
+        self.ps_blend.reset(&mut self.device);
+        self.ps_hw_composite.reset(&mut self.device);
+        self.ps_split_composite.reset(&mut self.device);
+        self.ps_composite.reset(&mut self.device);
+
+        // Recreate the eagerly allocated device resources.
+        self.debug.deinit(&mut self.device);
+        self.debug = DebugRenderer::new(&mut self.device);
+
+        self.dummy_cache_texture_id = if self.device.get_capabilities().avoid_texture_arrays {
+            self.device.create_texture_ids(1, TextureTarget::Default)[0]
+        } else {
+            self.device.create_texture_ids(1, TextureTarget::Array)[0]
+        };
+        self.device.init_texture(self.dummy_cache_texture_id,
+                                 1,
+                                 1,
+                                 ImageFormat::BGRA8,
+                                 TextureFilter::Linear,
+                                 RenderTargetMode::LayerRenderTarget(1),
+                                 None);
+        self.device.set_texture_label(self.dummy_cache_texture_id, "dummy cache");
+
+        self.fallback_texture_id = self.device.create_texture_ids(1, TextureTarget::Default)[0];
+        self.device.init_texture(self.fallback_texture_id,
+                                 1,
+                                 1,
+                                 ImageFormat::BGRA8,
+                                 TextureFilter::Nearest,
+                                 RenderTargetMode::None,
+                                 Some(&[ 255, 0, 255, 255 ]));
+        self.device.set_texture_label(self.fallback_texture_id, "fallback");
+
+        if let Some(ref mut texture_id) = self.dither_matrix_texture_id {
+            let (size, data) = self.dither_matrix_kind.texture_data();
+
+            *texture_id = self.device.create_texture_ids(1, TextureTarget::Default)[0];
+            self.device.init_texture(*texture_id,
+                                     size,
+                                     size,
+                                     ImageFormat::A8,
+                                     TextureFilter::Nearest,
+                                     RenderTargetMode::None,
+                                     Some(data));
+            self.device.set_texture_label(*texture_id, "dither matrix");
+        }
+
+        self.gpu_cache_texture = CacheTexture::new(&mut self.device);
+
+        self.gpu_data_textures = [
+            GpuDataTextures::new(&mut self.device),
+            GpuDataTextures::new(&mut self.device),
+            GpuDataTextures::new(&mut self.device),
+            GpuDataTextures::new(&mut self.device),
+            GpuDataTextures::new(&mut self.device),
+        ];
+        self.gdt_index = 0;
+
+        let quad_indices: [u16; 6] = [ 0, 1, 2, 2, 1, 3 ];
+        let quad_vertices = [
+            PackedVertex {
+                pos: [0.0, 0.0],
+            },
+            PackedVertex {
+                pos: [1.0, 0.0],
+            },
+            PackedVertex {
+                pos: [0.0, 1.0],
+            },
+            PackedVertex {
+                pos: [1.0, 1.0],
+            },
+        ];
+
+        self.prim_vao_id = self.device.create_vao(&DESC_PRIM_INSTANCES, mem::size_of::<PrimitiveInstance>() as i32);
+        self.device.bind_vao(self.prim_vao_id);
+        self.device.update_vao_indices(self.prim_vao_id, &quad_indices, VertexUsageHint::Static);
+        self.device.update_vao_main_vertices(self.prim_vao_id, &quad_vertices, VertexUsageHint::Static);
+
+        self.blur_vao_id = self.device.create_vao_with_new_instances(&DESC_BLUR, mem::size_of::<BlurCommand>() as i32, self.prim_vao_id);
+        self.clip_vao_id = self.device.create_vao_with_new_instances(&DESC_CLIP, mem::size_of::<CacheClipInstance>() as i32, self.prim_vao_id);
+
+        self.device.set_vao_label(self.prim_vao_id, "prim instances");
+        self.device.set_vao_label(self.blur_vao_id, "blur");
+        self.device.set_vao_label(self.clip_vao_id, "clip");
+
+        self.gpu_profile = GpuProfiler::new(self.device.rc_gl());
+        self.gpu_profile.set_enabled(self.debug_flags.contains(PROFILER_DBG) ||
+                                     self.gpu_capture_threshold_ns.is_some());
+
+        self.device.end_frame();
+
+        // Ask the backend to flush its caches and rebuild each document, so
+        // that the frames and resource updates we receive from now on only
+        // reference resources uploaded to the new context. Until the backend
+        // confirms it has done so, incoming results were built for the old
+        // context and must be discarded.
+        self.waiting_for_context_restore = true;
+        self.api_tx.send(ApiMsg::NotifyContextLost).ok();
     }
 
     // De-initialize the Renderer safely, assuming the GL is still alive and active.
@@ -2495,6 +5580,21 @@ impl Renderer {
         //Note: this is a fake frame, only needed because texture deletion is require to happen inside a frame
         self.device.begin_frame(1.0);
         self.device.deinit_texture(self.dummy_cache_texture_id);
+        self.device.deinit_texture(self.fallback_texture_id);
+        if let Some(texture_id) = self.output_transform_texture_id.take() {
+            self.device.deinit_texture(texture_id);
+        }
+        if let Some(texture_id) = self.frame_output_texture_id.take() {
+            self.device.deinit_texture(texture_id);
+        }
+        if let Some(capture) = self.frame_capture.take() {
+            for &texture_id in &capture.targets {
+                if texture_id.is_valid() {
+                    self.device.deinit_texture(texture_id);
+                }
+            }
+        }
+        self.device.delete_program(&mut self.output_transform_program);
         self.debug.deinit(&mut self.device);
         self.cs_box_shadow.deinit(&mut self.device);
         self.cs_text_run.deinit(&mut self.device);
@@ -2503,6 +5603,12 @@ impl Renderer {
         self.cs_clip_rectangle.deinit(&mut self.device);
         self.cs_clip_image.deinit(&mut self.device);
         self.cs_clip_border.deinit(&mut self.device);
+        if let Some(mut program) = self.cs_blur_compute.take() {
+            self.device.delete_compute_program(&mut program);
+        }
+        if let Some(mut program) = self.cs_clip_rectangle_compute.take() {
+            self.device.delete_compute_program(&mut program);
+        }
         self.ps_rectangle.deinit(&mut self.device);
         self.ps_rectangle_clip.deinit(&mut self.device);
         self.ps_text_run.deinit(&mut self.device);
@@ -2571,11 +5677,194 @@ pub trait ExternalImageHandler {
     fn unlock(&mut self, key: ExternalImageId, channel_index: u8);
 }
 
+/// Describes a native compositor surface that a frame should be rendered
+/// into, wrapped in a GL framebuffer object by the embedder.
+pub struct NativeSurfaceInfo {
+    /// The FBO that renders into the surface. It must stay valid until
+    /// the matching `present_surface` call.
+    pub fbo_id: u32,
+}
+
+/// The interface an application implements to allocate the top level
+/// render target from the OS compositor (DirectComposition on Windows,
+/// CoreAnimation on macOS) instead of using the window's default
+/// framebuffer. While a handler is installed via
+/// `Renderer::set_native_compositor_handler`, every rendered frame is
+/// drawn into a surface the handler provides and committed through
+/// `present_surface`, letting the OS composite it directly - which
+/// enables partial presents and saves power compared to swapping a full
+/// window-sized framebuffer.
+pub trait NativeCompositorHandler {
+    /// Called once per rendered frame, before any drawing to the top
+    /// level target. The returned surface must be at least `window_size`
+    /// device pixels; the frame and any debug overlays are rendered
+    /// into it.
+    fn bind_surface(&mut self, window_size: DeviceUintSize) -> NativeSurfaceInfo;
+
+    /// Called when the frame has been fully rendered into the surface,
+    /// so the embedder can commit it to the OS compositor. The embedder
+    /// should not swap the window's framebuffer for frames presented
+    /// this way.
+    fn present_surface(&mut self);
+}
+
+/// Presentation parameters for one eye of a stereo frame.
+#[derive(Clone, Debug)]
+pub struct EyeParams {
+    /// The framebuffer rect this eye's view is drawn into.
+    pub viewport: DeviceUintRect,
+    /// The projection for this eye, replacing the default orthographic
+    /// window projection. WebVR runtimes supply an asymmetric frustum
+    /// per eye.
+    pub projection: Transform3D<f32>,
+}
+
+/// Per-eye parameters for stereo presentation, registered with
+/// `Renderer::set_stereo_params`. While set, the final framebuffer pass
+/// draws the left and right eye viewports of the same frame back to
+/// back; all offscreen passes run once and are shared between the eyes,
+/// so WebVR content no longer submits a full frame per eye.
+#[derive(Clone, Debug)]
+pub struct StereoParams {
+    pub left: EyeParams,
+    pub right: EyeParams,
+}
+
+/// The orientation of the window surface relative to the content, set
+/// with `RendererOptions::surface_orientation` or
+/// `Renderer::set_surface_orientation`. On Android the surface stays in
+/// the display's native orientation when the device rotates, and drawing
+/// pre-rotated into it keeps the system compositor from inserting an
+/// extra rotation pass. The value is the counter-clockwise rotation
+/// applied to the content as it is drawn into the surface.
+///
+/// While a quarter turn is set, the content is laid out at the surface
+/// size with width and height swapped (see `content_size`), and the
+/// framebuffer pass projection rotates it onto the surface. Readbacks
+/// operate in surface coordinates; `surface_rect` maps a content rect
+/// there.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SurfaceOrientation {
+    Rotate0,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+impl SurfaceOrientation {
+    /// True when the orientation swaps the surface's width and height
+    /// relative to the content.
+    pub fn is_quarter_turn(&self) -> bool {
+        match *self {
+            SurfaceOrientation::Rotate90 |
+            SurfaceOrientation::Rotate270 => true,
+            SurfaceOrientation::Rotate0 |
+            SurfaceOrientation::Rotate180 => false,
+        }
+    }
+
+    /// The size content is laid out at for a surface of `surface_size`:
+    /// the same size, with width and height swapped for quarter turns.
+    pub fn content_size(&self, surface_size: &DeviceUintSize) -> DeviceUintSize {
+        if self.is_quarter_turn() {
+            DeviceUintSize::new(surface_size.height, surface_size.width)
+        } else {
+            *surface_size
+        }
+    }
+
+    /// Maps a rect in content coordinates to surface coordinates, both
+    /// with their origin at the top left.
+    pub fn surface_rect(&self,
+                        rect: &DeviceIntRect,
+                        surface_size: &DeviceUintSize) -> DeviceIntRect {
+        let sw = surface_size.width as i32;
+        let sh = surface_size.height as i32;
+        let x = rect.origin.x;
+        let y = rect.origin.y;
+        let w = rect.size.width;
+        let h = rect.size.height;
+        match *self {
+            SurfaceOrientation::Rotate0 => *rect,
+            SurfaceOrientation::Rotate90 => {
+                DeviceIntRect::new(DeviceIntPoint::new(y, sh - (x + w)),
+                                   DeviceIntSize::new(h, w))
+            }
+            SurfaceOrientation::Rotate180 => {
+                DeviceIntRect::new(DeviceIntPoint::new(sw - (x + w), sh - (y + h)),
+                                   DeviceIntSize::new(w, h))
+            }
+            SurfaceOrientation::Rotate270 => {
+                DeviceIntRect::new(DeviceIntPoint::new(sw - (y + h), x),
+                                   DeviceIntSize::new(h, w))
+            }
+        }
+    }
+
+    /// The clip space rotation post-multiplied onto the framebuffer pass
+    /// projection. Built from exact constants, since every orientation
+    /// is axis aligned.
+    fn matrix(&self) -> Option<Transform3D<f32>> {
+        match *self {
+            SurfaceOrientation::Rotate0 => None,
+            SurfaceOrientation::Rotate90 => {
+                Some(Transform3D::row_major(0.0, 1.0, 0.0, 0.0,
+                                            -1.0, 0.0, 0.0, 0.0,
+                                            0.0, 0.0, 1.0, 0.0,
+                                            0.0, 0.0, 0.0, 1.0))
+            }
+            SurfaceOrientation::Rotate180 => {
+                Some(Transform3D::row_major(-1.0, 0.0, 0.0, 0.0,
+                                            0.0, -1.0, 0.0, 0.0,
+                                            0.0, 0.0, 1.0, 0.0,
+                                            0.0, 0.0, 0.0, 1.0))
+            }
+            SurfaceOrientation::Rotate270 => {
+                Some(Transform3D::row_major(0.0, -1.0, 0.0, 0.0,
+                                            1.0, 0.0, 0.0, 0.0,
+                                            0.0, 0.0, 1.0, 0.0,
+                                            0.0, 0.0, 0.0, 1.0))
+            }
+        }
+    }
+}
+
+/// The interface an application implements to receive presentation
+/// feedback. After the embedder performs the GL swap for a rendered frame
+/// and calls `Renderer::notify_swap_complete`, the handler is invoked once
+/// for every pipeline whose content reached the screen for the first time
+/// with that swap. This replaces polling `current_epoch` /
+/// `flush_rendered_epochs` for paint-time telemetry and reftest
+/// synchronization.
+pub trait PresentationFeedbackHandler {
+    fn frame_presented(&mut self,
+                       pipeline_id: PipelineId,
+                       epoch: Epoch,
+                       frame_id: FrameId,
+                       presentation_time_ns: u64);
+}
+
+/// Reports which external images (typically video frames) a composited
+/// frame actually sampled. Recorded per composite and handed to the
+/// embedder through `Renderer::flush_sampled_external_images`.
+#[derive(Clone, Debug)]
+pub struct SampledExternalImages {
+    /// The id of every external image locked and sampled while drawing
+    /// the frame. Multi-channel images appear once.
+    pub image_ids: Vec<ExternalImageId>,
+    /// When the frame's GL commands were submitted, on the same clock
+    /// as `time::precise_time_ns`.
+    pub composite_time_ns: u64,
+}
+
 pub struct RendererOptions {
     pub device_pixel_ratio: f32,
     pub resource_override_path: Option<PathBuf>,
     pub enable_aa: bool,
     pub enable_dithering: bool,
+    /// Which matrix the dithered gradient shaders sample. Only relevant
+    /// when `enable_dithering` is set.
+    pub dither_matrix_kind: DitherMatrixKind,
     pub max_recorded_profiles: usize,
     pub debug: bool,
     pub enable_scrollbars: bool,
@@ -2585,14 +5874,82 @@ pub struct RendererOptions {
     pub clear_framebuffer: bool,
     pub clear_color: ColorF,
     pub enable_clear_scissor: bool,
+    /// The format of the intermediate color render targets, and the
+    /// format readbacks assume the framebuffer to have. The default,
+    /// `BGRA8`, suits standard 8-bit swap chains. Embedders compositing
+    /// to a wide-gamut or HDR swap chain should pass `RGB10A2` or
+    /// `RGBA16F` so rendering isn't quantized through 8-bit
+    /// intermediates. Blending behaves the same in the wide formats
+    /// since fragment outputs stay within [0, 1], but note that
+    /// `RGB10A2` leaves only two bits of alpha in the cache targets
+    /// that later passes composite from; `RGBA16F` is the safer choice
+    /// for content with translucency.
+    pub render_target_format: ImageFormat,
     pub enable_batcher: bool,
+    /// When set, large opaque regions of blended images lay down a
+    /// depth-only pre-pass rect, so the depth test can reject alpha
+    /// fragments hidden behind them.
+    pub enable_depth_prepass: bool,
     pub max_texture_size: Option<u32>,
+    /// When set, blob images larger than one tile are rasterized in
+    /// tiles of this size, so that only the tiles near the viewport
+    /// have to be drawn. When `None`, blobs are only tiled if they
+    /// exceed the maximum texture size.
+    pub blob_tile_size: Option<TileSize>,
     pub cache_expiry_frames: u32,
     pub workers: Option<Arc<ThreadPool>>,
+    /// Number of threads in the internally built worker pool. `None` uses
+    /// one thread per logical CPU. Ignored when `workers` supplies a
+    /// pre-built pool.
+    pub worker_threads: Option<usize>,
+    /// Stack size of the internally built worker threads, in bytes.
+    /// `None` uses the platform default.
+    pub worker_stack_size: Option<usize>,
+    /// When set, the internally built worker threads run at a lower
+    /// scheduling priority, on a best-effort, per-platform basis.
+    pub low_priority_workers: bool,
     pub blob_image_renderer: Option<Box<BlobImageRenderer>>,
     pub recorder: Option<Box<ApiRecordingReceiver>>,
-    pub enable_render_on_scroll: bool,
+    /// Default scheduling policy for new documents, deciding whether
+    /// scroll and animation updates render a new frame or re-present the
+    /// last one. Documents can override this via
+    /// `RenderApi::set_scheduling_policy`.
+    pub scheduling_policy: SchedulingPolicy,
+    /// When set, the backend runs every incoming display list through a
+    /// validation pass before it enters the scene. Defects are reported
+    /// via `RenderNotifier::display_list_warning`, and lists that would
+    /// produce undefined rendering are dropped. Defaults to on in debug
+    /// builds only.
+    pub validate_display_lists: bool,
     pub debug_flags: DebugFlags,
+    /// When set, any frame whose GPU time exceeds this threshold triggers a
+    /// RenderDoc capture of the following frame. Has no effect unless the
+    /// `renderdoc` feature is enabled and RenderDoc is attached.
+    pub gpu_capture_threshold_ns: Option<u64>,
+    /// The frame time the profiler overlay's frame bar graph colors
+    /// against. Defaults to one 60Hz vsync; set to 8333333 when targeting
+    /// a 120Hz display.
+    pub profiler_frame_budget_ns: u64,
+    /// When set, per-stage CPU times are checked against these budgets
+    /// after every frame. Overruns are logged and can be retrieved via
+    /// `Renderer::take_cpu_budget_overruns`, so automated tests can fail
+    /// on performance regressions. See `CpuStageBudgets`.
+    pub cpu_stage_budgets: Option<CpuStageBudgets>,
+    /// The orientation of the surface relative to the content; see
+    /// `SurfaceOrientation`. Can be changed at runtime with
+    /// `Renderer::set_surface_orientation`.
+    pub surface_orientation: SurfaceOrientation,
+    /// When set, frames are rendered into an FBO-backed texture owned by
+    /// WebRender instead of the default framebuffer. After `render`, the
+    /// texture and a sync object are available from
+    /// `Renderer::get_frame_output`, so embedders that do their own final
+    /// composition (VR compositors, video capture) don't have to copy the
+    /// frame out of the default framebuffer.
+    pub render_to_texture: bool,
+    /// Baseline quality settings. Pressure signals reported through
+    /// `RenderApi::set_quality_signals` can downgrade quality below this
+    /// baseline at runtime, but never raise it above.
+    pub quality: QualitySettings,
 }
 
 impl Default for RendererOptions {
@@ -2602,6 +5959,7 @@ impl Default for RendererOptions {
             resource_override_path: None,
             enable_aa: true,
             enable_dithering: true,
+            dither_matrix_kind: DitherMatrixKind::Bayer,
             debug_flags: DebugFlags::empty(),
             max_recorded_profiles: 0,
             debug: false,
@@ -2612,13 +5970,376 @@ impl Default for RendererOptions {
             clear_framebuffer: true,
             clear_color: ColorF::new(1.0, 1.0, 1.0, 1.0),
             enable_clear_scissor: true,
+            render_target_format: ImageFormat::BGRA8,
             enable_batcher: true,
+            enable_depth_prepass: false,
             max_texture_size: None,
+            blob_tile_size: None,
             cache_expiry_frames: 600, // roughly, 10 seconds
             workers: None,
+            worker_threads: None,
+            worker_stack_size: None,
+            low_priority_workers: false,
             blob_image_renderer: None,
             recorder: None,
-            enable_render_on_scroll: true,
+            scheduling_policy: SchedulingPolicy::Synchronous,
+            validate_display_lists: cfg!(debug_assertions),
+            gpu_capture_threshold_ns: None,
+            profiler_frame_budget_ns: 1000000000 / 60,
+            cpu_stage_budgets: None,
+            quality: QualitySettings::full(),
+            surface_orientation: SurfaceOrientation::Rotate0,
+            render_to_texture: false,
+        }
+    }
+}
+
+/// A reason `RendererOptionsBuilder::build` rejected a configuration.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RendererOptionsError {
+    /// Subpixel text positioning builds on the antialiased glyph path,
+    /// so `enable_subpixel_aa` requires `enable_aa`.
+    SubpixelAaRequiresAa,
+    /// `worker_threads` must be at least one when given.
+    ZeroWorkerThreads,
+    /// Worker pool settings were given alongside a pre-built `workers`
+    /// pool, which ignores them.
+    WorkerSettingsIgnored,
+    /// The cache render targets are at least 1024x1024, so a smaller
+    /// `max_texture_size` can't hold a single render pass.
+    MaxTextureSizeTooSmall(u32),
+    /// A `cache_expiry_frames` of zero would expire every cached
+    /// resource at the end of the frame that uploaded it.
+    ZeroCacheExpiryFrames,
+    /// `blob_tile_size` must be at least one pixel when given.
+    ZeroBlobTileSize,
+    /// `render_target_format` must be a renderable color format: BGRA8,
+    /// RGB10A2 or RGBA16F.
+    UnsupportedRenderTargetFormat(ImageFormat),
+}
+
+/// Builds a validated `RendererOptions`.
+///
+/// The plain struct accepts interdependent fields in any combination;
+/// the builder checks them against each other in `build`, and the
+/// preset constructors give embedders a coherent starting point per
+/// platform instead of hand-picking every flag.
+pub struct RendererOptionsBuilder {
+    options: RendererOptions,
+}
+
+impl RendererOptionsBuilder {
+    pub fn new() -> RendererOptionsBuilder {
+        RendererOptionsBuilder {
+            options: RendererOptions::default(),
+        }
+    }
+
+    /// Defaults tuned for mobile GPUs: the depth pre-pass saves fill
+    /// rate on tiled renderers, the texture cache expires sooner to
+    /// keep memory down, and the worker pool yields to the UI thread.
+    pub fn mobile() -> RendererOptionsBuilder {
+        let mut builder = RendererOptionsBuilder::new();
+        builder.options.enable_depth_prepass = true;
+        builder.options.cache_expiry_frames = 300;
+        builder.options.low_priority_workers = true;
+        builder
+    }
+
+    /// Defaults tuned for desktop GPUs: subpixel text positioning is
+    /// the platform norm, and shader compilation is cheap enough to do
+    /// up front rather than on first use.
+    pub fn desktop() -> RendererOptionsBuilder {
+        let mut builder = RendererOptionsBuilder::new();
+        builder.options.enable_subpixel_aa = true;
+        builder.options.precache_shaders = true;
+        builder
+    }
+
+    /// Defaults tuned for reference and pixel tests: antialiasing and
+    /// dithering are off so output is bit-exact across GPUs, a single
+    /// worker keeps rasterization deterministic, and display list
+    /// validation is always on.
+    pub fn test() -> RendererOptionsBuilder {
+        let mut builder = RendererOptionsBuilder::new();
+        builder.options.enable_aa = false;
+        builder.options.enable_dithering = false;
+        builder.options.scheduling_policy = SchedulingPolicy::OnDemand;
+        builder.options.worker_threads = Some(1);
+        builder.options.validate_display_lists = true;
+        builder
+    }
+
+    pub fn device_pixel_ratio(mut self, ratio: f32) -> RendererOptionsBuilder {
+        self.options.device_pixel_ratio = ratio;
+        self
+    }
+
+    pub fn resource_override_path(mut self, path: Option<PathBuf>) -> RendererOptionsBuilder {
+        self.options.resource_override_path = path;
+        self
+    }
+
+    pub fn enable_aa(mut self, enable: bool) -> RendererOptionsBuilder {
+        self.options.enable_aa = enable;
+        self
+    }
+
+    pub fn enable_dithering(mut self, enable: bool) -> RendererOptionsBuilder {
+        self.options.enable_dithering = enable;
+        self
+    }
+
+    pub fn dither_matrix_kind(mut self, kind: DitherMatrixKind) -> RendererOptionsBuilder {
+        self.options.dither_matrix_kind = kind;
+        self
+    }
+
+    pub fn max_recorded_profiles(mut self, count: usize) -> RendererOptionsBuilder {
+        self.options.max_recorded_profiles = count;
+        self
+    }
+
+    pub fn debug(mut self, debug: bool) -> RendererOptionsBuilder {
+        self.options.debug = debug;
+        self
+    }
+
+    pub fn enable_scrollbars(mut self, enable: bool) -> RendererOptionsBuilder {
+        self.options.enable_scrollbars = enable;
+        self
+    }
+
+    pub fn precache_shaders(mut self, precache: bool) -> RendererOptionsBuilder {
+        self.options.precache_shaders = precache;
+        self
+    }
+
+    pub fn renderer_kind(mut self, kind: RendererKind) -> RendererOptionsBuilder {
+        self.options.renderer_kind = kind;
+        self
+    }
+
+    pub fn enable_subpixel_aa(mut self, enable: bool) -> RendererOptionsBuilder {
+        self.options.enable_subpixel_aa = enable;
+        self
+    }
+
+    pub fn clear_framebuffer(mut self, clear: bool) -> RendererOptionsBuilder {
+        self.options.clear_framebuffer = clear;
+        self
+    }
+
+    pub fn clear_color(mut self, color: ColorF) -> RendererOptionsBuilder {
+        self.options.clear_color = color;
+        self
+    }
+
+    pub fn enable_clear_scissor(mut self, enable: bool) -> RendererOptionsBuilder {
+        self.options.enable_clear_scissor = enable;
+        self
+    }
+
+    pub fn render_target_format(mut self, format: ImageFormat) -> RendererOptionsBuilder {
+        self.options.render_target_format = format;
+        self
+    }
+
+    pub fn enable_batcher(mut self, enable: bool) -> RendererOptionsBuilder {
+        self.options.enable_batcher = enable;
+        self
+    }
+
+    pub fn enable_depth_prepass(mut self, enable: bool) -> RendererOptionsBuilder {
+        self.options.enable_depth_prepass = enable;
+        self
+    }
+
+    pub fn max_texture_size(mut self, size: Option<u32>) -> RendererOptionsBuilder {
+        self.options.max_texture_size = size;
+        self
+    }
+
+    pub fn blob_tile_size(mut self, size: Option<TileSize>) -> RendererOptionsBuilder {
+        self.options.blob_tile_size = size;
+        self
+    }
+
+    pub fn cache_expiry_frames(mut self, frames: u32) -> RendererOptionsBuilder {
+        self.options.cache_expiry_frames = frames;
+        self
+    }
+
+    pub fn workers(mut self, workers: Option<Arc<ThreadPool>>) -> RendererOptionsBuilder {
+        self.options.workers = workers;
+        self
+    }
+
+    pub fn worker_threads(mut self, count: Option<usize>) -> RendererOptionsBuilder {
+        self.options.worker_threads = count;
+        self
+    }
+
+    pub fn worker_stack_size(mut self, size: Option<usize>) -> RendererOptionsBuilder {
+        self.options.worker_stack_size = size;
+        self
+    }
+
+    pub fn low_priority_workers(mut self, low_priority: bool) -> RendererOptionsBuilder {
+        self.options.low_priority_workers = low_priority;
+        self
+    }
+
+    pub fn blob_image_renderer(mut self, renderer: Option<Box<BlobImageRenderer>>) -> RendererOptionsBuilder {
+        self.options.blob_image_renderer = renderer;
+        self
+    }
+
+    pub fn recorder(mut self, recorder: Option<Box<ApiRecordingReceiver>>) -> RendererOptionsBuilder {
+        self.options.recorder = recorder;
+        self
+    }
+
+    pub fn scheduling_policy(mut self, policy: SchedulingPolicy) -> RendererOptionsBuilder {
+        self.options.scheduling_policy = policy;
+        self
+    }
+
+    pub fn validate_display_lists(mut self, validate: bool) -> RendererOptionsBuilder {
+        self.options.validate_display_lists = validate;
+        self
+    }
+
+    pub fn debug_flags(mut self, flags: DebugFlags) -> RendererOptionsBuilder {
+        self.options.debug_flags = flags;
+        self
+    }
+
+    pub fn gpu_capture_threshold_ns(mut self, threshold: Option<u64>) -> RendererOptionsBuilder {
+        self.options.gpu_capture_threshold_ns = threshold;
+        self
+    }
+
+    pub fn profiler_frame_budget_ns(mut self, budget: u64) -> RendererOptionsBuilder {
+        self.options.profiler_frame_budget_ns = budget;
+        self
+    }
+
+    pub fn cpu_stage_budgets(mut self, budgets: Option<CpuStageBudgets>) -> RendererOptionsBuilder {
+        self.options.cpu_stage_budgets = budgets;
+        self
+    }
+
+    pub fn quality(mut self, quality: QualitySettings) -> RendererOptionsBuilder {
+        self.options.quality = quality;
+        self
+    }
+
+    pub fn render_to_texture(mut self, enable: bool) -> RendererOptionsBuilder {
+        self.options.render_to_texture = enable;
+        self
+    }
+
+    pub fn surface_orientation(mut self, orientation: SurfaceOrientation) -> RendererOptionsBuilder {
+        self.options.surface_orientation = orientation;
+        self
+    }
+
+    /// Checks the interdependent fields against each other, returning
+    /// the options when coherent and every violated rule otherwise.
+    pub fn build(self) -> Result<RendererOptions, Vec<RendererOptionsError>> {
+        let mut errors = Vec::new();
+
+        if self.options.enable_subpixel_aa && !self.options.enable_aa {
+            errors.push(RendererOptionsError::SubpixelAaRequiresAa);
+        }
+        if self.options.worker_threads == Some(0) {
+            errors.push(RendererOptionsError::ZeroWorkerThreads);
+        }
+        if self.options.workers.is_some() &&
+           (self.options.worker_threads.is_some() ||
+            self.options.worker_stack_size.is_some() ||
+            self.options.low_priority_workers) {
+            errors.push(RendererOptionsError::WorkerSettingsIgnored);
+        }
+        if let Some(size) = self.options.max_texture_size {
+            if size < 1024 {
+                errors.push(RendererOptionsError::MaxTextureSizeTooSmall(size));
+            }
+        }
+        if self.options.cache_expiry_frames == 0 {
+            errors.push(RendererOptionsError::ZeroCacheExpiryFrames);
+        }
+        if self.options.blob_tile_size == Some(0) {
+            errors.push(RendererOptionsError::ZeroBlobTileSize);
         }
+        match self.options.render_target_format {
+            ImageFormat::BGRA8 |
+            ImageFormat::RGB10A2 |
+            ImageFormat::RGBA16F => {}
+            format => {
+                errors.push(RendererOptionsError::UnsupportedRenderTargetFormat(format));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(self.options)
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use euclid::Point2D;
+
+    #[test]
+    fn surface_orientation_content_size() {
+        let surface = DeviceUintSize::new(40, 60);
+        assert_eq!(SurfaceOrientation::Rotate0.content_size(&surface),
+                   DeviceUintSize::new(40, 60));
+        assert_eq!(SurfaceOrientation::Rotate90.content_size(&surface),
+                   DeviceUintSize::new(60, 40));
+        assert_eq!(SurfaceOrientation::Rotate180.content_size(&surface),
+                   DeviceUintSize::new(40, 60));
+        assert_eq!(SurfaceOrientation::Rotate270.content_size(&surface),
+                   DeviceUintSize::new(60, 40));
+    }
+
+    #[test]
+    fn surface_orientation_rects() {
+        // A rect near the top left of 40x60 content, mapped into the
+        // surface each orientation presents that content on.
+        let content = DeviceIntRect::new(DeviceIntPoint::new(5, 10),
+                                         DeviceIntSize::new(8, 4));
+        let portrait = DeviceUintSize::new(40, 60);
+        let landscape = DeviceUintSize::new(60, 40);
+        assert_eq!(SurfaceOrientation::Rotate0.surface_rect(&content, &portrait),
+                   content);
+        assert_eq!(SurfaceOrientation::Rotate90.surface_rect(&content, &landscape),
+                   DeviceIntRect::new(DeviceIntPoint::new(10, 27),
+                                      DeviceIntSize::new(4, 8)));
+        assert_eq!(SurfaceOrientation::Rotate180.surface_rect(&content, &portrait),
+                   DeviceIntRect::new(DeviceIntPoint::new(27, 46),
+                                      DeviceIntSize::new(8, 4)));
+        assert_eq!(SurfaceOrientation::Rotate270.surface_rect(&content, &landscape),
+                   DeviceIntRect::new(DeviceIntPoint::new(46, 5),
+                                      DeviceIntSize::new(4, 8)));
+    }
+
+    #[test]
+    fn surface_orientation_matrix() {
+        // A point on the middle of the content's right edge, in clip
+        // space, rotated counter-clockwise around the view axis.
+        let p = Point2D::new(1.0f32, 0.0);
+        assert!(SurfaceOrientation::Rotate0.matrix().is_none());
+        assert_eq!(SurfaceOrientation::Rotate90.matrix().unwrap().transform_point2d(&p),
+                   Point2D::new(0.0, 1.0));
+        assert_eq!(SurfaceOrientation::Rotate180.matrix().unwrap().transform_point2d(&p),
+                   Point2D::new(-1.0, 0.0));
+        assert_eq!(SurfaceOrientation::Rotate270.matrix().unwrap().transform_point2d(&p),
+                   Point2D::new(0.0, -1.0));
     }
 }
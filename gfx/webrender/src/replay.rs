@@ -0,0 +1,258 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Playback for `BinaryRecorder` streams.
+//!
+//! A recording captures the resource updates and document messages an
+//! application sent; this module reads such a stream back and re-submits
+//! the messages to a fresh backend through a `RenderApi`, either at the
+//! pacing they were recorded at or as fast as possible. Playback can loop
+//! for performance soak testing.
+//!
+//! Recordings deserialize with `bincode` against the current `ApiMsg`
+//! definition, so they can only be replayed by a build with the same
+//! message layout; the header's format discriminant check enforces that.
+
+use api::{ApiMsg, RenderApi};
+use bincode;
+use byteorder::{LittleEndian, ReadBytesExt};
+use record::{WEBRENDER_RECORDING_HEADER, WEBRENDER_RECORDING_HEADER_V1};
+use record::WEBRENDER_RECORDING_MSG_FORMAT;
+use std::cmp;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+use time::precise_time_ns;
+
+/// How playback should be paced.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplayTiming {
+    /// Submit each message at the offset it was recorded at. Falls back to
+    /// `AsFastAsPossible` for v1 recordings, which carry no timestamps.
+    Original,
+    /// Submit everything back to back, for throughput measurements.
+    AsFastAsPossible,
+}
+
+enum RecordKind {
+    Msg(Vec<u8>),
+    Payload(Vec<u8>),
+}
+
+struct Record {
+    /// Nanoseconds since the start of the recording; 0 for v1 recordings.
+    time_ns: u64,
+    kind: RecordKind,
+}
+
+pub struct BinaryPlayer {
+    records: Vec<Record>,
+    timed: bool,
+}
+
+impl BinaryPlayer {
+    /// Parses a recording from disk. Accepts both the current format and
+    /// the older untimed one.
+    pub fn open(path: &Path) -> io::Result<BinaryPlayer> {
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+        let mut reader = io::Cursor::new(data);
+
+        let header = reader.read_u64::<LittleEndian>()?;
+        let timed = if header == WEBRENDER_RECORDING_HEADER {
+            true
+        } else if header == WEBRENDER_RECORDING_HEADER_V1 {
+            false
+        } else {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      "not a webrender recording"));
+        };
+
+        // v1 recordings carried a build-specific type id here instead of
+        // the format discriminant; it can't be validated, so it's only
+        // read past. Such recordings fail message deserialization below
+        // if they were in fact made with a different `ApiMsg` layout.
+        let msg_format = reader.read_u64::<LittleEndian>()?;
+        if timed && msg_format != WEBRENDER_RECORDING_MSG_FORMAT {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      "recording uses a different message format"));
+        }
+
+        let mut records = Vec::new();
+        loop {
+            let time_ns = if timed {
+                match reader.read_u64::<LittleEndian>() {
+                    Ok(time_ns) => time_ns,
+                    Err(..) => break,
+                }
+            } else {
+                0
+            };
+
+            let len = match reader.read_u32::<LittleEndian>() {
+                Ok(len) => len,
+                Err(..) => break,
+            };
+
+            // A zero length marks a payload record; the actual data follows
+            // with its own length.
+            let (is_payload, len) = if len == 0 {
+                (true, reader.read_u32::<LittleEndian>()?)
+            } else {
+                (false, len)
+            };
+
+            let mut buf = vec![0; len as usize];
+            reader.read_exact(&mut buf)?;
+
+            records.push(Record {
+                time_ns,
+                kind: if is_payload {
+                    RecordKind::Payload(buf)
+                } else {
+                    RecordKind::Msg(buf)
+                },
+            });
+        }
+
+        Ok(BinaryPlayer {
+            records,
+            timed,
+        })
+    }
+
+    pub fn is_timed(&self) -> bool {
+        self.timed
+    }
+
+    /// Plays the recording once through the given API. The backend behind
+    /// it should be fresh: recorded messages carry the original document
+    /// ids and epochs.
+    pub fn play(&self, api: &RenderApi, timing: ReplayTiming) {
+        let start = precise_time_ns();
+
+        for record in &self.records {
+            if timing == ReplayTiming::Original && self.timed {
+                let target = start + record.time_ns;
+                let now = precise_time_ns();
+                if target > now {
+                    let delta = target - now;
+                    thread::sleep(Duration::new(delta / 1_000_000_000,
+                                                (delta % 1_000_000_000) as u32));
+                }
+            }
+
+            match record.kind {
+                RecordKind::Msg(ref buf) => {
+                    let msg: ApiMsg = bincode::deserialize(buf)
+                        .expect("Invalid message in recording");
+                    api.send_message(msg);
+                }
+                RecordKind::Payload(ref buf) => {
+                    api.send_payload(buf);
+                }
+            }
+        }
+    }
+
+    /// Plays the recording `iterations` times back to back, for soak
+    /// testing. The backend keeps its documents between iterations, so each
+    /// pass re-submits the same display lists and resources.
+    pub fn play_looped(&self, api: &RenderApi, timing: ReplayTiming, iterations: usize) {
+        for _ in 0 .. iterations {
+            self.play(api, timing);
+        }
+    }
+}
+
+/// Summary of a set of frame times, in nanoseconds. Percentiles are
+/// nearest-rank over the sorted samples.
+#[derive(Clone, Copy, Debug)]
+pub struct FrameTimeDistribution {
+    pub samples: usize,
+    pub min_ns: u64,
+    pub mean_ns: u64,
+    pub median_ns: u64,
+    pub p95_ns: u64,
+    pub p99_ns: u64,
+    pub max_ns: u64,
+}
+
+impl FrameTimeDistribution {
+    /// Summarizes a sample set, e.g. the result of
+    /// `Renderer::stop_frame_time_recording`. Returns `None` when there
+    /// are no samples.
+    pub fn from_samples(mut samples: Vec<u64>) -> Option<FrameTimeDistribution> {
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort();
+
+        let sum: u64 = samples.iter().sum();
+        Some(FrameTimeDistribution {
+            samples: samples.len(),
+            min_ns: samples[0],
+            mean_ns: sum / samples.len() as u64,
+            median_ns: percentile(&samples, 50),
+            p95_ns: percentile(&samples, 95),
+            p99_ns: percentile(&samples, 99),
+            max_ns: samples[samples.len() - 1],
+        })
+    }
+}
+
+fn percentile(sorted: &[u64], p: usize) -> u64 {
+    let rank = (sorted.len() * p + 99) / 100;
+    sorted[cmp::max(rank, 1) - 1]
+}
+
+/// Replays a recording as a benchmark, turning a captured user session -
+/// including its recorded scroll and animation input - into a frame time
+/// measurement.
+///
+/// The runner only drives the API side; the embedder keeps running its
+/// normal render loop, and collects the frame times on the render thread
+/// with `Renderer::start_frame_time_recording`. Phase boundaries are
+/// where message submission starts and ends, so a frame in flight when a
+/// phase ends can land in the next one; warmup and a reasonable
+/// iteration count make that noise insignificant.
+pub struct BenchmarkRunner {
+    player: BinaryPlayer,
+    /// Iterations played before measurement starts, so shader, glyph and
+    /// texture caches are warm. Defaults to 1.
+    pub warmup_iterations: usize,
+    /// Measured iterations. Defaults to 5.
+    pub iterations: usize,
+}
+
+impl BenchmarkRunner {
+    pub fn open(path: &Path) -> io::Result<BenchmarkRunner> {
+        Ok(BenchmarkRunner {
+            player: BinaryPlayer::open(path)?,
+            warmup_iterations: 1,
+            iterations: 5,
+        })
+    }
+
+    /// Plays the warmup iterations, calls `start_measuring` (which should
+    /// make the render thread call `Renderer::start_frame_time_recording`),
+    /// plays the measured iterations, then obtains the samples from
+    /// `finish` (`Renderer::stop_frame_time_recording`) and summarizes
+    /// them. Returns `None` when no frames were rendered.
+    pub fn run<S, F>(&self,
+                     api: &RenderApi,
+                     timing: ReplayTiming,
+                     start_measuring: S,
+                     finish: F) -> Option<FrameTimeDistribution>
+        where S: FnOnce(), F: FnOnce() -> Vec<u64>
+    {
+        self.player.play_looped(api, timing, self.warmup_iterations);
+        start_measuring();
+        self.player.play_looped(api, timing, self.iterations);
+        FrameTimeDistribution::from_samples(finish())
+    }
+}
@@ -4,30 +4,54 @@
 
 use device::TextureFilter;
 use frame::FrameId;
-use glyph_cache::GlyphCache;
+use glyph_cache::{CachedGlyphInfo, GlyphCache};
 use gpu_cache::{GpuCache, GpuCacheHandle};
-use internal_types::{FastHashMap, FastHashSet, SourceTexture, TextureUpdateList};
+use internal_types::{FastHashMap, FastHashSet, SourceTexture, TextureUpdateList, WebGLAcquire};
+use internal_types::{TextureCacheAllocInfo, TextureCacheDebugInfo, TextureCacheOwner};
 use profiler::{ResourceProfileCounters, TextureCacheProfileCounters};
 use std::cmp;
+use std::collections::hash_map;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::hash_map::Entry::{self, Occupied, Vacant};
 use std::fmt::Debug;
-use std::hash::Hash;
+use std::hash::{Hash, Hasher};
 use std::mem;
 use std::sync::Arc;
 use texture_cache::{TextureCache, TextureCacheItemId};
 use api::{BlobImageRenderer, BlobImageDescriptor, BlobImageError, BlobImageRequest};
 use api::{BlobImageResources, BlobImageData, ResourceUpdates, ResourceUpdate, AddFont};
 use api::{DevicePoint, DeviceIntSize, DeviceUintRect, DeviceUintSize};
-use api::{Epoch, FontInstanceKey, FontKey, FontTemplate};
-use api::{GlyphDimensions, GlyphKey, IdNamespace};
-use api::{ImageData, ImageDescriptor, ImageKey, ImageRendering};
+use api::{Epoch, FontInstanceKey, FontKey, FontTemplate, GlyphIndex, RawGlyph};
+use api::{GlyphDimensions, GlyphKey, IdNamespace, LayoutPoint};
+use api::{ImageData, ImageDescriptor, ImageFormat, ImageKey, ImageRendering};
 use api::{TileOffset, TileSize};
-use api::{ExternalImageData, ExternalImageType, WebGLContextId};
+use api::{ExternalImageData, ExternalImageType, WebGLContextId, WebGLSurfaceDescriptor};
 use rayon::ThreadPool;
 use glyph_rasterizer::{GlyphRasterizer, GlyphRequest};
+use path_rasterizer::{PathCache, PathKey, PathRasterizer, PathShape};
 
 const DEFAULT_TILE_SIZE: TileSize = 512;
 
+// Images whose longest side is at most this many pixels are always
+// uploaded at full size, regardless of the size they are displayed at.
+const IMAGE_DOWNSCALE_MIN_SIZE: u32 = 2048;
+
+// Headroom required over the display size before switching to a smaller
+// upload, so that zooming around a scale boundary doesn't thrash
+// re-uploads.
+const IMAGE_DOWNSCALE_HYSTERESIS: f32 = 1.25;
+
+// Maximum number of texture cache bytes relocated per idle frame while
+// compacting a fragmented page, to keep the copies from causing jank.
+const COMPACTION_BYTES_PER_FRAME: u32 = 512 * 1024;
+
+// How many frames a shared font outlives its last reference. Fonts
+// released during navigation are frequently re-added moments later by
+// the next page (back/forward), and briefly keeping the template and
+// rasterizer state alive lets the dedupe path revive them instead of
+// re-rasterizing and re-uploading the glyph atlases.
+const FONT_RETENTION_FRAMES: u32 = 10;
+
 // These coordinates are always in texels.
 // They are converted to normalized ST
 // values in the vertex shader. The reason
@@ -105,6 +129,9 @@ struct CachedImageInfo {
     texture_cache_id: TextureCacheItemId,
     epoch: Epoch,
     last_access: FrameId,
+    // The power-of-two factor the image was downsampled by before it
+    // was uploaded. 1 for a full-size upload.
+    upload_scale: u32,
 }
 
 pub struct ResourceClassCache<K,V> {
@@ -134,6 +161,10 @@ impl<K,V> ResourceClassCache<K,V> where K: Clone + Hash + Eq + Debug, V: Resourc
         self.resources.insert(key, value);
     }
 
+    pub fn iter(&self) -> hash_map::Iter<K,V> {
+        self.resources.iter()
+    }
+
     pub fn entry(&mut self, key: K, frame: FrameId) -> Entry<K,V> {
         let mut entry = self.resources.entry(key);
         match entry {
@@ -214,19 +245,49 @@ impl Into<BlobImageRequest> for ImageRequest {
 pub struct WebGLTexture {
     pub id: SourceTexture,
     pub size: DeviceIntSize,
+    /// True when the producing context renders with its origin at the
+    /// bottom left, so the surface is sampled flipped.
+    pub flip_y: bool,
+    /// The producer's pending GL fence sync handle, or 0. Taken (once)
+    /// by `take_webgl_acquires` for the renderer to wait on before the
+    /// frame samples the texture.
+    pub sync_object: usize,
 }
 
 struct Resources {
     font_templates: FastHashMap<FontKey, FontTemplate>,
     image_templates: ImageTemplates,
+
+    // Embedder-supplied glyph images, per font. Fonts with an entry
+    // here never touch the platform rasterizer; the glyph cache is
+    // (re)populated from these templates instead.
+    raw_glyphs: FastHashMap<FontKey, FastHashMap<GlyphIndex, RawGlyph>>,
+
+    // Content-sharing aliases: a key whose template is byte-identical to
+    // one added earlier (usually from another namespace) maps to that
+    // earlier, canonical key. All lookups go through the canonical key,
+    // so documents share rasterized glyphs and uploaded pixels.
+    font_aliases: FastHashMap<FontKey, FontKey>,
+    image_aliases: FastHashMap<ImageKey, ImageKey>,
+}
+
+impl Resources {
+    fn canonical_font_key(&self, key: FontKey) -> FontKey {
+        *self.font_aliases.get(&key).unwrap_or(&key)
+    }
+
+    fn canonical_image_key(&self, key: ImageKey) -> ImageKey {
+        *self.image_aliases.get(&key).unwrap_or(&key)
+    }
 }
 
 impl BlobImageResources for Resources {
     fn get_font_data(&self, key: FontKey) -> &FontTemplate {
-        self.font_templates.get(&key).unwrap()
+        self.font_templates.get(&self.canonical_font_key(key)).unwrap()
     }
     fn get_image(&self, key: ImageKey) -> Option<(&ImageData, &ImageDescriptor)> {
-        self.image_templates.get(key).map(|resource| { (&resource.data, &resource.descriptor) })
+        self.image_templates.get(self.canonical_image_key(key))
+            .map(|resource| { (&resource.data, &resource.descriptor) })
     }
 }
 
@@ -234,6 +295,10 @@ pub struct ResourceCache {
     cached_glyphs: GlyphCache,
     cached_images: ResourceClassCache<ImageRequest, CachedImageInfo>,
 
+    // Rasterized masks for filled path primitives, keyed on the path
+    // content and the device size it is displayed at.
+    cached_paths: PathCache,
+
     // TODO(pcwalton): Figure out the lifecycle of these.
     webgl_textures: FastHashMap<WebGLContextId, WebGLTexture>,
 
@@ -246,38 +311,94 @@ pub struct ResourceCache {
     // TODO(gw): We should expire (parts of) this cache semi-regularly!
     cached_glyph_dimensions: FastHashMap<GlyphRequest, Option<GlyphDimensions>>,
     glyph_rasterizer: GlyphRasterizer,
+    path_rasterizer: PathRasterizer,
+
+    // The thread pool that glyph rasterization runs on. Frame building
+    // borrows it to construct batch lists in parallel.
+    workers: Arc<ThreadPool>,
 
     // The set of images that aren't present or valid in the texture cache,
     // and need to be rasterized and/or uploaded this frame. This includes
     // both blobs and regular images.
     pending_image_requests: FastHashSet<ImageRequest>,
 
+    // Embedder-supplied glyphs requested this frame that aren't resident
+    // in the glyph cache yet. Uploaded from the raw glyph templates when
+    // the frame's resources are flushed.
+    pending_raw_glyphs: Vec<(FontInstanceKey, GlyphKey)>,
+
     blob_image_renderer: Option<Box<BlobImageRenderer>>,
 
     cache_expiry_frames: u32,
+
+    // When set, blob images larger than one tile are rasterized in
+    // tiles of this size, even if they would fit in a single texture.
+    blob_tile_size: Option<TileSize>,
+
+    // Blob requests that have been handed to the blob renderer but whose
+    // results haven't been uploaded to the texture cache yet, along with
+    // the frame the request was issued on. A request that misses its
+    // frame gets a placeholder and is picked up on a follow-up frame.
+    blob_requests_in_flight: FastHashMap<BlobImageRequest, FrameId>,
+
+    // The largest size each image was displayed at, and the frame it was
+    // last observed on. Drives the decision to downsample huge images
+    // before uploading them.
+    image_display_hints: FastHashMap<ImageKey, (DeviceUintSize, FrameId)>,
+
+    // Bookkeeping for the content-sharing aliases in `resources`:
+    // content hash -> canonical key, and canonical key -> (ref count,
+    // content hash). The count covers the canonical key itself plus
+    // each alias, so shared entries die with their last user.
+    font_dedupe: FastHashMap<u64, FontKey>,
+    font_refs: FastHashMap<FontKey, (u32, u64)>,
+    image_dedupe: FastHashMap<u64, ImageKey>,
+    image_refs: FastHashMap<ImageKey, (u32, u64)>,
+
+    // Canonical fonts whose reference count reached zero, and the frame
+    // that happened on. They keep their template, rasterizer state and
+    // dedupe entry for FONT_RETENTION_FRAMES frames, unless a matching
+    // add_font_template revives them first.
+    retired_fonts: Vec<(FontKey, FrameId)>,
 }
 
 impl ResourceCache {
     pub fn new(texture_cache: TextureCache,
                workers: Arc<ThreadPool>,
                blob_image_renderer: Option<Box<BlobImageRenderer>>,
-               cache_expiry_frames: u32) -> ResourceCache {
+               cache_expiry_frames: u32,
+               blob_tile_size: Option<TileSize>) -> ResourceCache {
         ResourceCache {
             cached_glyphs: GlyphCache::new(),
             cached_images: ResourceClassCache::new(),
+            cached_paths: ResourceClassCache::new(),
             webgl_textures: FastHashMap::default(),
             resources: Resources {
                 font_templates: FastHashMap::default(),
                 image_templates: ImageTemplates::new(),
+                raw_glyphs: FastHashMap::default(),
+                font_aliases: FastHashMap::default(),
+                image_aliases: FastHashMap::default(),
             },
             cached_glyph_dimensions: FastHashMap::default(),
             texture_cache,
             state: State::Idle,
             current_frame_id: FrameId(0),
             pending_image_requests: FastHashSet::default(),
-            glyph_rasterizer: GlyphRasterizer::new(workers),
+            pending_raw_glyphs: Vec::new(),
+            glyph_rasterizer: GlyphRasterizer::new(Arc::clone(&workers)),
+            path_rasterizer: PathRasterizer::new(Arc::clone(&workers)),
+            workers,
             blob_image_renderer,
             cache_expiry_frames,
+            blob_tile_size,
+            blob_requests_in_flight: FastHashMap::default(),
+            image_display_hints: FastHashMap::default(),
+            font_dedupe: FastHashMap::default(),
+            font_refs: FastHashMap::default(),
+            image_dedupe: FastHashMap::default(),
+            image_refs: FastHashMap::default(),
+            retired_fonts: Vec::new(),
         }
     }
 
@@ -285,6 +406,29 @@ impl ResourceCache {
         self.texture_cache.max_texture_size()
     }
 
+    pub fn workers(&self) -> &ThreadPool {
+        &self.workers
+    }
+
+    /// Replace the worker thread pool, e.g. after it has been resized at
+    /// runtime. The glyph rasterizer is rebuilt on top of the new pool,
+    /// since its per-worker font contexts are sized to the pool, and all
+    /// live font templates are re-registered with it.
+    pub fn set_workers(&mut self, workers: Arc<ThreadPool>) {
+        self.glyph_rasterizer = GlyphRasterizer::new(Arc::clone(&workers));
+        for (font_key, template) in &self.resources.font_templates {
+            self.glyph_rasterizer.add_font(*font_key, template.clone());
+        }
+        self.path_rasterizer = PathRasterizer::new(Arc::clone(&workers));
+        self.workers = workers;
+    }
+
+    /// Changes how many frames an unused resource stays cached before it
+    /// is expired. Takes effect at the end of the next frame.
+    pub fn set_cache_expiry_frames(&mut self, frames: u32) {
+        self.cache_expiry_frames = frames;
+    }
+
     fn should_tile(&self, descriptor: &ImageDescriptor, data: &ImageData) -> bool {
         let limit = self.max_texture_size();
         let size_check = descriptor.width > limit || descriptor.height > limit;
@@ -335,11 +479,44 @@ impl ResourceCache {
                 ResourceUpdate::DeleteFont(font) => {
                     self.delete_font_template(font);
                 }
+                ResourceUpdate::AddRawGlyphs(font, glyphs) => {
+                    let bytes: usize = glyphs.iter().map(|glyph| glyph.bytes.len()).sum();
+                    profile_counters.font_templates.inc(bytes);
+                    self.add_raw_glyphs(font, glyphs);
+                }
             }
         }
     }
 
     pub fn add_font_template(&mut self, font_key: FontKey, template: FontTemplate) {
+        // If an identical face was already added under another key (for
+        // example the same web font in two tabs), alias this key to it
+        // instead of storing a duplicate, so the documents share the
+        // rasterized glyphs.
+        if let Some(hash) = hash_font_template(&template) {
+            let canonical = self.font_dedupe.get(&hash).cloned();
+            if let Some(canonical) = canonical {
+                let matches = match self.resources.font_templates.get(&canonical) {
+                    Some(existing) => font_templates_match(existing, &template),
+                    None => false,
+                };
+                if matches {
+                    self.resources.font_aliases.insert(font_key, canonical);
+                    let refs = self.font_refs.get_mut(&canonical).unwrap();
+                    if refs.0 == 0 {
+                        // Revived within the retention window; cancel the
+                        // pending deletion.
+                        self.retired_fonts.retain(|&(retired, _)| retired != canonical);
+                    }
+                    refs.0 += 1;
+                    return;
+                }
+            } else {
+                self.font_dedupe.insert(hash, font_key);
+                self.font_refs.insert(font_key, (1, hash));
+            }
+        }
+
         // Push the new font to the font renderer, and also store
         // it locally for glyph metric requests.
         self.glyph_rasterizer.add_font(font_key, template.clone());
@@ -347,23 +524,152 @@ impl ResourceCache {
     }
 
     pub fn delete_font_template(&mut self, font_key: FontKey) {
+        // An alias never owned any rasterizer or template state; it just
+        // releases its reference on the shared entry.
+        if let Some(canonical) = self.resources.font_aliases.remove(&font_key) {
+            self.release_font(canonical);
+            return;
+        }
+        if self.font_refs.contains_key(&font_key) {
+            self.release_font(font_key);
+            return;
+        }
+        self.delete_font_resources(font_key);
+    }
+
+    fn release_font(&mut self, canonical: FontKey) {
+        let remaining = {
+            let refs = self.font_refs.get_mut(&canonical).unwrap();
+            if refs.0 == 0 {
+                // Already retired: a namespace clear can walk over a
+                // font whose last reference went away earlier.
+                return;
+            }
+            refs.0 -= 1;
+            refs.0
+        };
+        if remaining == 0 {
+            // Don't delete anything yet: the next page often re-adds the
+            // same face within a few frames, and the refs and dedupe
+            // entries kept here let add_font_template revive the font,
+            // glyph atlas included.
+            self.retired_fonts.push((canonical, self.current_frame_id));
+        }
+    }
+
+    // Deletes the retired fonts whose retention window has closed.
+    // Called once per frame from end_frame.
+    fn expire_retired_fonts(&mut self) {
+        if self.retired_fonts.is_empty() {
+            return;
+        }
+        let current_frame_id = self.current_frame_id;
+        let mut expired = Vec::new();
+        self.retired_fonts.retain(|&(key, retired_at)| {
+            if retired_at.0 + FONT_RETENTION_FRAMES <= current_frame_id.0 {
+                expired.push(key);
+                false
+            } else {
+                true
+            }
+        });
+        for key in expired {
+            self.delete_retired_font(key);
+        }
+    }
+
+    fn delete_retired_font(&mut self, key: FontKey) {
+        let (_, hash) = self.font_refs.remove(&key).unwrap();
+        if self.font_dedupe.get(&hash) == Some(&key) {
+            self.font_dedupe.remove(&hash);
+        }
+        self.delete_font_resources(key);
+    }
+
+    fn delete_font_resources(&mut self, font_key: FontKey) {
         self.glyph_rasterizer.delete_font(font_key);
         self.resources.font_templates.remove(&font_key);
+        self.resources.raw_glyphs.remove(&font_key);
         if let Some(ref mut r) = self.blob_image_renderer {
             r.delete_font(font_key);
         }
     }
 
+    pub fn add_raw_glyphs(&mut self, font_key: FontKey, glyphs: Vec<RawGlyph>) {
+        let font_key = self.resources.canonical_font_key(font_key);
+        let replaced = {
+            let entry = self.resources.raw_glyphs
+                            .entry(font_key)
+                            .or_insert_with(FastHashMap::default);
+            let mut replaced = false;
+            for glyph in glyphs {
+                debug_assert_eq!(glyph.bytes.len(),
+                                 (glyph.width * glyph.height * 4) as usize);
+                if entry.insert(glyph.index, glyph).is_some() {
+                    replaced = true;
+                }
+            }
+            replaced
+        };
+        if replaced {
+            // At least one index changed its image; drop the font's
+            // cached rasterizations so the next frame re-uploads from
+            // the new templates.
+            self.cached_glyphs.clear_fonts(&mut self.texture_cache,
+                                           |font| font.font_key == font_key);
+        }
+    }
+
+    // Blob images larger than one tile are tiled even when they would
+    // fit in a single texture: rasterizing per tile lets the worker
+    // pool draw the visible tiles in parallel instead of stalling on
+    // the whole image, and lets tiles far from the viewport expire
+    // from the texture cache.
+    fn blob_tiling(&self, descriptor: &ImageDescriptor, data: &ImageData) -> Option<TileSize> {
+        match self.blob_tile_size {
+            Some(tile_size) if data.is_blob() &&
+                               (descriptor.width > tile_size as u32 ||
+                                descriptor.height > tile_size as u32) => {
+                Some(tile_size)
+            }
+            _ => None,
+        }
+    }
+
     pub fn add_image_template(&mut self,
                               image_key: ImageKey,
                               descriptor: ImageDescriptor,
                               mut data: ImageData,
                               mut tiling: Option<TileSize>) {
+        // Share identical raw images across namespaces: an alias has no
+        // template of its own and all its lookups resolve to the
+        // canonical key, including its texture cache entries.
+        if let Some(hash) = hash_image_template(&descriptor, &data) {
+            let canonical = self.image_dedupe.get(&hash).cloned();
+            if let Some(canonical) = canonical {
+                let matches = match self.resources.image_templates.get(canonical) {
+                    Some(existing) => image_templates_match(existing, &descriptor, &data),
+                    None => false,
+                };
+                if matches {
+                    self.resources.image_aliases.insert(image_key, canonical);
+                    self.image_refs.get_mut(&canonical).unwrap().0 += 1;
+                    return;
+                }
+            } else {
+                self.image_dedupe.insert(hash, image_key);
+                self.image_refs.insert(image_key, (1, hash));
+            }
+        }
+
         if tiling.is_none() && self.should_tile(&descriptor, &data) {
             // We aren't going to be able to upload a texture this big, so tile it, even
             // if tiling was not requested.
             tiling = Some(DEFAULT_TILE_SIZE);
         }
+        if tiling.is_none() {
+            tiling = self.blob_tiling(&descriptor, &data);
+        }
 
         if let ImageData::Blob(ref mut blob) = data {
             self.blob_image_renderer.as_mut().unwrap().add(
@@ -389,6 +695,25 @@ impl ResourceCache {
                                  descriptor: ImageDescriptor,
                                  mut data: ImageData,
                                  dirty_rect: Option<DeviceUintRect>) {
+        // An update breaks content sharing: every other key keeps the
+        // pixels it had before the update.
+        if let Some(canonical) = self.resources.image_aliases.remove(&image_key) {
+            let copy = {
+                let template = self.resources.image_templates.get(canonical).unwrap();
+                ImageResource {
+                    descriptor: template.descriptor,
+                    data: template.data.clone(),
+                    epoch: Epoch(0),
+                    tiling: template.tiling,
+                    dirty_rect: None,
+                }
+            };
+            self.resources.image_templates.insert(image_key, copy);
+            self.release_image(canonical);
+        } else if self.image_refs.get(&image_key).map_or(false, |&(count, _)| count > 1) {
+            self.detach_image_aliases(image_key);
+        }
+
         let resource = if let Some(image) = self.resources.image_templates.get(image_key) {
 
             let next_epoch = Epoch(image.epoch.0 + 1);
@@ -397,6 +722,9 @@ impl ResourceCache {
             if tiling.is_none() && self.should_tile(&descriptor, &data) {
                 tiling = Some(DEFAULT_TILE_SIZE);
             }
+            if tiling.is_none() {
+                tiling = self.blob_tiling(&descriptor, &data);
+            }
 
             if let ImageData::Blob(ref mut blob) = data {
                 self.blob_image_renderer.as_mut().unwrap().update(
@@ -421,12 +749,93 @@ impl ResourceCache {
         };
 
         self.resources.image_templates.insert(image_key, resource);
+
+        // The pixels changed, so the content hash this key was shared
+        // under no longer describes it. Re-key it so that future adds
+        // of the new content can share it.
+        if let Some(&(count, old_hash)) = self.image_refs.get(&image_key) {
+            if self.image_dedupe.get(&old_hash) == Some(&image_key) {
+                self.image_dedupe.remove(&old_hash);
+            }
+            let new_hash = {
+                let template = self.resources.image_templates.get(image_key).unwrap();
+                hash_image_template(&template.descriptor, &template.data)
+            };
+            match new_hash {
+                Some(hash) if !self.image_dedupe.contains_key(&hash) => {
+                    self.image_dedupe.insert(hash, image_key);
+                    self.image_refs.insert(image_key, (count, hash));
+                }
+                _ => {
+                    // The aliases were detached above, so nothing relies
+                    // on this entry any more.
+                    self.image_refs.remove(&image_key);
+                }
+            }
+        }
+    }
+
+    // Gives each alias of a shared image its own copy of the current
+    // pixels, in preparation for the canonical key diverging from them.
+    fn detach_image_aliases(&mut self, canonical: ImageKey) {
+        let (descriptor, data, tiling) = {
+            let template = self.resources.image_templates.get(canonical).unwrap();
+            (template.descriptor, template.data.clone(), template.tiling)
+        };
+
+        let alias_keys: Vec<ImageKey> = self.resources.image_aliases
+            .iter()
+            .filter(|&(_, &target)| target == canonical)
+            .map(|(&key, _)| key)
+            .collect();
+        for key in alias_keys {
+            self.resources.image_aliases.remove(&key);
+            self.resources.image_templates.insert(key, ImageResource {
+                descriptor,
+                data: data.clone(),
+                epoch: Epoch(0),
+                tiling,
+                dirty_rect: None,
+            });
+        }
+
+        if let Some(refs) = self.image_refs.get_mut(&canonical) {
+            refs.0 = 1;
+        }
     }
 
     pub fn delete_image_template(&mut self, image_key: ImageKey) {
+        if let Some(canonical) = self.resources.image_aliases.remove(&image_key) {
+            self.release_image(canonical);
+            return;
+        }
+        if self.image_refs.contains_key(&image_key) {
+            self.release_image(image_key);
+            return;
+        }
+        self.delete_image_resources(image_key);
+    }
+
+    fn release_image(&mut self, canonical: ImageKey) {
+        let remaining = {
+            let refs = self.image_refs.get_mut(&canonical).unwrap();
+            refs.0 -= 1;
+            refs.0
+        };
+        if remaining == 0 {
+            let (_, hash) = self.image_refs.remove(&canonical).unwrap();
+            if self.image_dedupe.get(&hash) == Some(&canonical) {
+                self.image_dedupe.remove(&hash);
+            }
+            self.delete_image_resources(canonical);
+        }
+    }
+
+    fn delete_image_resources(&mut self, image_key: ImageKey) {
         let value = self.resources.image_templates.remove(image_key);
 
         self.cached_images.clear_keys(&mut self.texture_cache, |request| request.key == image_key);
+        self.image_display_hints.remove(&image_key);
 
         match value {
             Some(image) => {
@@ -444,6 +853,9 @@ impl ResourceCache {
         self.webgl_textures.insert(id, WebGLTexture {
             id: texture_id,
             size,
+            // GL contexts render with the origin at the bottom left.
+            flip_y: true,
+            sync_object: 0,
         });
     }
 
@@ -455,18 +867,69 @@ impl ResourceCache {
         webgl_texture.size = size;
     }
 
+    /// Registers or updates the composited surface of a WebGL canvas.
+    /// Called whenever the producer finishes a frame; a new sync object
+    /// replaces any previous one, which can only happen after the old
+    /// fence has been consumed or has become irrelevant.
+    pub fn update_webgl_surface(&mut self, id: WebGLContextId, descriptor: &WebGLSurfaceDescriptor) {
+        let webgl_texture = self.webgl_textures.get_mut(&id).unwrap();
+
+        webgl_texture.id = SourceTexture::WebGL(descriptor.texture_id);
+        webgl_texture.size = descriptor.size;
+        webgl_texture.flip_y = descriptor.flip_y;
+        webgl_texture.sync_object = descriptor.sync_object;
+    }
+
+    /// Returns the pending sync objects of all registered WebGL
+    /// surfaces, clearing them. Each fence must be waited on (and
+    /// deleted) exactly once, by the renderer, before the frame samples
+    /// the corresponding texture.
+    pub fn take_webgl_acquires(&mut self) -> Vec<WebGLAcquire> {
+        let mut acquires = Vec::new();
+        for texture in self.webgl_textures.values_mut() {
+            if texture.sync_object != 0 {
+                if let SourceTexture::WebGL(texture_id) = texture.id {
+                    acquires.push(WebGLAcquire {
+                        texture_id,
+                        sync_object: mem::replace(&mut texture.sync_object, 0),
+                    });
+                }
+            }
+        }
+        acquires
+    }
+
     pub fn request_image(&mut self,
                          key: ImageKey,
                          rendering: ImageRendering,
-                         tile: Option<TileOffset>) {
+                         tile: Option<TileOffset>,
+                         display_size: Option<DeviceUintSize>) {
 
         debug_assert_eq!(self.state, State::AddResources);
+        // Shared images resolve to their canonical key, so every alias
+        // of the same content hits the same texture cache entry.
+        let key = self.resources.canonical_image_key(key);
         let request = ImageRequest {
             key,
             rendering,
             tile,
         };
 
+        // Keep track of the largest size the image is displayed at this
+        // frame. It drives the upload scale below: a huge image that is
+        // only ever displayed small is downsampled before it is uploaded.
+        if let Some(size) = display_size {
+            let hint = self.image_display_hints
+                           .entry(key)
+                           .or_insert((size, self.current_frame_id));
+            if hint.1 < self.current_frame_id {
+                *hint = (size, self.current_frame_id);
+            } else {
+                hint.0.width = cmp::max(hint.0.width, size.width);
+                hint.0.height = cmp::max(hint.0.height, size.height);
+            }
+        }
+
         let template = self.resources.image_templates.get(key).unwrap();
 
         // Images that don't use the texture cache can early out.
@@ -474,13 +937,29 @@ impl ResourceCache {
             return;
         }
 
+        let current_scale = self.cached_images.resources.get(&request)
+                                .map_or(1, |info| info.upload_scale);
+        let upload_scale = match template.data {
+            ImageData::Raw(..) if request.tile.is_none() => {
+                let hint = self.image_display_hints.get(&key).map(|&(size, _)| size);
+                compute_upload_scale(&template.descriptor, hint, current_scale)
+            }
+            _ => 1,
+        };
+
         // If this image exists in the texture cache, *and* the epoch
         // in the cache matches that of the template, then it is
-        // valid to use as-is.
+        // valid to use as-is. A blob request that is still in flight
+        // doesn't count: what's in the cache is a placeholder that has
+        // to be replaced once rasterization finishes. Similarly, a
+        // change of upload scale means the cached copy is at the wrong
+        // resolution and has to be replaced.
         match self.cached_images.entry(request, self.current_frame_id) {
             Occupied(entry) => {
                 let cached_image = entry.get();
-                if cached_image.epoch == template.epoch {
+                if cached_image.epoch == template.epoch &&
+                   cached_image.upload_scale == upload_scale &&
+                   !self.blob_requests_in_flight.contains_key(&request.into()) {
                     return;
                 }
             }
@@ -490,8 +969,9 @@ impl ResourceCache {
         // We can start a worker thread rasterizing right now, if:
         //  - The image is a blob.
         //  - The blob hasn't already been requested this frame.
+        //  - The blob isn't still rasterizing for an earlier frame.
         if self.pending_image_requests.insert(request) {
-            if template.data.is_blob() {
+            if template.data.is_blob() && !self.blob_requests_in_flight.contains_key(&request.into()) {
                 if let Some(ref mut renderer) = self.blob_image_renderer {
                     let (offset, w, h) = match template.tiling {
                         Some(tile_size) => {
@@ -520,16 +1000,46 @@ impl ResourceCache {
                         },
                         template.dirty_rect,
                     );
+                    self.blob_requests_in_flight.insert(request.into(), self.current_frame_id);
                 }
             }
         }
     }
 
+    // Rewrites a font instance to use the canonical key of a shared
+    // font, so identical fonts added under different keys rasterize
+    // into (and hit) the same glyph cache entries.
+    fn canonical_font_instance(&self, font: &FontInstanceKey) -> FontInstanceKey {
+        let mut font = font.clone();
+        font.font_key = self.resources.canonical_font_key(font.font_key);
+        font
+    }
+
     pub fn request_glyphs(&mut self,
                           font: FontInstanceKey,
                           glyph_keys: &[GlyphKey]) {
         debug_assert_eq!(self.state, State::AddResources);
 
+        let font = self.canonical_font_instance(&font);
+
+        // Fonts with embedder-supplied glyph images never touch the
+        // platform rasterizer. Queue whichever requested glyphs aren't
+        // resident; they are uploaded from the stored templates when the
+        // frame's resources are flushed.
+        if self.resources.raw_glyphs.contains_key(&font.font_key) {
+            let glyph_key_cache =
+                self.cached_glyphs.get_glyph_key_cache_for_font_mut(font.clone());
+            for key in glyph_keys {
+                match glyph_key_cache.entry(key.clone(), self.current_frame_id) {
+                    Occupied(..) => {}
+                    Vacant(..) => {
+                        self.pending_raw_glyphs.push((font.clone(), key.clone()));
+                    }
+                }
+            }
+            return;
+        }
+
         self.glyph_rasterizer.request_glyphs(
             &mut self.cached_glyphs,
             self.current_frame_id,
@@ -538,8 +1048,124 @@ impl ResourceCache {
         );
     }
 
+    pub fn request_path(&mut self,
+                        shape: &Arc<PathShape>,
+                        size: DeviceUintSize,
+                        scale: f32) {
+        debug_assert_eq!(self.state, State::AddResources);
+
+        let key = PathKey {
+            content_hash: shape.content_hash,
+            size,
+        };
+        self.path_rasterizer.request_path(
+            &mut self.cached_paths,
+            self.current_frame_id,
+            Arc::clone(shape),
+            key,
+            scale,
+        );
+    }
+
+    /// Rasterizes and uploads the given glyphs now, outside any frame,
+    /// so that the first frame using them doesn't pay the cost. The
+    /// entries go through the regular glyph cache: anything already
+    /// cached is skipped, and pre-warmed glyphs that never get used
+    /// expire like any other idle entry.
+    pub fn pre_rasterize_glyphs(&mut self,
+                                font: FontInstanceKey,
+                                glyph_indices: &[u32],
+                                texture_cache_profile: &mut TextureCacheProfileCounters) {
+        debug_assert_eq!(self.state, State::Idle);
+
+        // The font may have been deleted while the request was in
+        // flight; there is nothing useful to warm up then.
+        let font = self.canonical_font_instance(&font);
+        if !self.resources.font_templates.contains_key(&font.font_key) {
+            return;
+        }
+
+        // Glyphs are keyed on their quantized subpixel offset; warming
+        // the zero offset covers alpha and mono text entirely, and one
+        // of the four variants of subpixel positioned text.
+        let glyph_keys: Vec<GlyphKey> = glyph_indices.iter()
+            .map(|&index| GlyphKey::new(index,
+                                        LayoutPoint::zero(),
+                                        font.render_mode,
+                                        font.subpx_dir))
+            .collect();
+
+        self.glyph_rasterizer.request_glyphs(
+            &mut self.cached_glyphs,
+            self.current_frame_id,
+            font,
+            &glyph_keys,
+        );
+        self.glyph_rasterizer.resolve_glyphs(
+            self.current_frame_id,
+            &mut self.cached_glyphs,
+            &mut self.texture_cache,
+            texture_cache_profile,
+        );
+    }
+
     pub fn pending_updates(&mut self) -> TextureUpdateList {
-        self.texture_cache.pending_updates()
+        // All the glyph and image requests raised during frame building
+        // end up in this one list; batch it up so the renderer uploads
+        // each texture page in as few passes as possible.
+        let mut updates = self.texture_cache.pending_updates();
+        updates.sort_and_merge();
+        updates
+    }
+
+    /// Collects a description of every live texture cache allocation along
+    /// with what owns it, for the renderer's texture cache debug overlay.
+    pub fn get_texture_cache_debug_info(&self) -> TextureCacheDebugInfo {
+        let mut allocations = Vec::new();
+
+        for (request, info) in self.cached_images.iter() {
+            let item = self.texture_cache.get(&info.texture_cache_id);
+            allocations.push(TextureCacheAllocInfo {
+                texture_id: item.texture_id,
+                rect: item.allocated_rect,
+                format: item.format,
+                last_access: info.last_access,
+                owner: TextureCacheOwner::Image(request.key),
+            });
+        }
+
+        for (font, glyph_key_cache) in &self.cached_glyphs.glyph_key_caches {
+            for (key, glyph) in glyph_key_cache.iter() {
+                let item = match glyph.texture_cache_id {
+                    Some(ref id) => self.texture_cache.get(id),
+                    // Whitespace glyphs have no cache allocation.
+                    None => continue,
+                };
+                allocations.push(TextureCacheAllocInfo {
+                    texture_id: item.texture_id,
+                    rect: item.allocated_rect,
+                    format: item.format,
+                    last_access: glyph.last_access,
+                    owner: TextureCacheOwner::Glyph(font.font_key, key.index),
+                });
+            }
+        }
+
+        for (key, info) in self.cached_paths.iter() {
+            let item = self.texture_cache.get(&info.texture_cache_id);
+            allocations.push(TextureCacheAllocInfo {
+                texture_id: item.texture_id,
+                rect: item.allocated_rect,
+                format: item.format,
+                last_access: info.last_access,
+                owner: TextureCacheOwner::Path(key.content_hash),
+            });
+        }
+
+        TextureCacheDebugInfo {
+            current_frame_id: self.current_frame_id,
+            allocations,
+        }
     }
 
     pub fn get_glyphs<F>(&self,
@@ -549,6 +1175,7 @@ impl ResourceCache {
         debug_assert_eq!(self.state, State::QueryResources);
         let mut texture_id = None;
 
+        let font = self.canonical_font_instance(&font);
         let glyph_key_cache = self.cached_glyphs.get_glyph_key_cache_for_font(&font);
 
         for (loop_index, key) in glyph_keys.iter().enumerate() {
@@ -570,7 +1197,8 @@ impl ResourceCache {
     pub fn get_glyph_dimensions(&mut self,
                                 font: &FontInstanceKey,
                                 key: &GlyphKey) -> Option<GlyphDimensions> {
-        let key = GlyphRequest::new(font, key);
+        let font = self.canonical_font_instance(font);
+        let key = GlyphRequest::new(&font, key);
 
         match self.cached_glyph_dimensions.entry(key.clone()) {
             Occupied(entry) => *entry.get(),
@@ -581,6 +1209,7 @@ impl ResourceCache {
     }
 
     pub fn get_glyph_index(&mut self, font_key: FontKey, ch: char) -> Option<u32> {
+        let font_key = self.resources.canonical_font_key(font_key);
         self.glyph_rasterizer.get_glyph_index(font_key, ch)
     }
 
@@ -591,7 +1220,7 @@ impl ResourceCache {
                             tile: Option<TileOffset>) -> CacheItem {
         debug_assert_eq!(self.state, State::QueryResources);
         let key = ImageRequest {
-            key: image_key,
+            key: self.resources.canonical_image_key(image_key),
             rendering: image_rendering,
             tile,
         };
@@ -603,7 +1232,25 @@ impl ResourceCache {
         }
     }
 
+    #[inline]
+    pub fn get_cached_path(&self,
+                           shape: &PathShape,
+                           size: DeviceUintSize) -> CacheItem {
+        debug_assert_eq!(self.state, State::QueryResources);
+        let key = PathKey {
+            content_hash: shape.content_hash,
+            size,
+        };
+        let path_info = self.cached_paths.get(&key, self.current_frame_id);
+        let item = self.texture_cache.get(&path_info.texture_cache_id);
+        CacheItem {
+            texture_id: SourceTexture::TextureCache(item.texture_id),
+            uv_rect_handle: item.uv_rect_handle,
+        }
+    }
+
     pub fn get_image_properties(&self, image_key: ImageKey) -> ImageProperties {
+        let image_key = self.resources.canonical_image_key(image_key);
         let image_template = &self.resources.image_templates.get(image_key).unwrap();
 
         let external_image = match image_template.data {
@@ -630,13 +1277,27 @@ impl ResourceCache {
     }
 
     pub fn get_tiled_image_map(&self) -> TiledImageMap {
-        self.resources.image_templates.images.iter().filter_map(|(&key, template)|
+        let mut map: TiledImageMap = self.resources.image_templates.images.iter().filter_map(|(&key, template)|
             template.tiling.map(|tile_size| (key, ImageTiling {
                 image_size: DeviceUintSize::new(template.descriptor.width,
                                                 template.descriptor.height),
                 tile_size,
             }))
-        ).collect()
+        ).collect();
+        // Display lists refer to aliases by their own key, so the frame
+        // builder needs an entry for each alias of a tiled image too.
+        for (&key, canonical) in &self.resources.image_aliases {
+            if let Some(template) = self.resources.image_templates.get(*canonical) {
+                if let Some(tile_size) = template.tiling {
+                    map.insert(key, ImageTiling {
+                        image_size: DeviceUintSize::new(template.descriptor.width,
+                                                        template.descriptor.height),
+                        tile_size,
+                    });
+                }
+            }
+        }
+        map
     }
 
     pub fn get_webgl_texture(&self, context_id: &WebGLContextId) -> &WebGLTexture {
@@ -661,6 +1322,8 @@ impl ResourceCache {
         debug_assert_eq!(self.state, State::AddResources);
         self.state = State::QueryResources;
 
+        self.upload_raw_glyphs(texture_cache_profile);
+
         self.glyph_rasterizer.resolve_glyphs(
             self.current_frame_id,
             &mut self.cached_glyphs,
@@ -668,9 +1331,40 @@ impl ResourceCache {
             texture_cache_profile,
         );
 
+        self.path_rasterizer.resolve_paths(
+            self.current_frame_id,
+            &mut self.cached_paths,
+            &mut self.texture_cache,
+            texture_cache_profile,
+        );
+
         // Apply any updates of new / updated images (incl. blobs) to the texture cache.
         self.update_texture_cache(texture_cache_profile);
 
+        // If nothing was uploaded this frame, spend a little of it
+        // compacting fragmented texture cache pages. This runs before the
+        // cache updates below so that relocated items that are in use get
+        // their new uv rects into the GPU cache this frame.
+        self.compact_texture_cache(texture_cache_profile);
+
+        // Any request still in flight from an earlier frame belongs to a
+        // tile that went out of view before rasterization finished. Pick
+        // up the results that are ready and throw them away, without
+        // blocking, so that these entries don't keep the follow-up frame
+        // loop alive.
+        let stale_requests: Vec<BlobImageRequest> = self.blob_requests_in_flight
+            .iter()
+            .filter(|&(_, &frame_id)| frame_id < self.current_frame_id)
+            .map(|(&request, _)| request)
+            .collect();
+        for request in stale_requests {
+            if let Some(ref mut renderer) = self.blob_image_renderer {
+                if renderer.try_resolve(request).is_some() {
+                    self.blob_requests_in_flight.remove(&request);
+                }
+            }
+        }
+
         // Expire any resources that haven't been used for `cache_expiry_frames`.
         let num_frames_back = self.cache_expiry_frames;
         let expiry_frame = FrameId(cmp::max(num_frames_back, self.current_frame_id.0) - num_frames_back);
@@ -682,6 +1376,60 @@ impl ResourceCache {
                                   gpu_cache,
                                   self.current_frame_id,
                                   expiry_frame);
+        self.cached_paths.update(&mut self.texture_cache,
+                                 gpu_cache,
+                                 self.current_frame_id,
+                                 expiry_frame);
+    }
+
+    // Populates the glyph cache entries queued by request_glyphs for
+    // fonts with embedder-supplied glyph images.
+    fn upload_raw_glyphs(&mut self,
+                         texture_cache_profile: &mut TextureCacheProfileCounters) {
+        let pending = mem::replace(&mut self.pending_raw_glyphs, Vec::new());
+        for (font, key) in pending {
+            let image_id = {
+                let glyph = self.resources.raw_glyphs
+                                .get(&font.font_key)
+                                .and_then(|glyphs| glyphs.get(&key.index));
+                match glyph {
+                    Some(glyph) if glyph.width > 0 && glyph.height > 0 => {
+                        Some(self.texture_cache.insert(
+                            ImageDescriptor {
+                                width: glyph.width,
+                                height: glyph.height,
+                                stride: None,
+                                format: ImageFormat::BGRA8,
+                                is_opaque: false,
+                                offset: 0,
+                            },
+                            TextureFilter::Linear,
+                            ImageData::Raw(Arc::clone(&glyph.bytes)),
+                            [glyph.left, glyph.top],
+                            texture_cache_profile,
+                        ))
+                    }
+                    // Indices the embedder never supplied render
+                    // nothing, like whitespace glyphs.
+                    _ => None,
+                }
+            };
+
+            let glyph_key_cache = self.cached_glyphs.get_glyph_key_cache_for_font_mut(font);
+            glyph_key_cache.insert(key, CachedGlyphInfo {
+                texture_cache_id: image_id,
+                last_access: self.current_frame_id,
+            });
+        }
+    }
+
+    /// Returns true if any blob image requested this frame missed its
+    /// rasterization and went out with a placeholder. The backend uses
+    /// this to schedule a follow-up frame that patches in the result.
+    pub fn has_missing_blob_images(&self) -> bool {
+        self.blob_requests_in_flight
+            .values()
+            .any(|&frame_id| frame_id == self.current_frame_id)
     }
 
     fn update_texture_cache(&mut self, texture_cache_profile: &mut TextureCacheProfileCounters) {
@@ -689,30 +1437,94 @@ impl ResourceCache {
             let image_template = self.resources.image_templates.get_mut(request.key).unwrap();
             debug_assert!(image_template.data.uses_texture_cache());
 
+            // Re-derive the upload scale from the hints gathered over the
+            // whole frame; request_image may have seen only some of them.
+            let current_scale = self.cached_images.resources.get(&request)
+                                    .map_or(1, |info| info.upload_scale);
+            let upload_scale = match image_template.data {
+                ImageData::Raw(..) if request.tile.is_none() => {
+                    let hint = self.image_display_hints
+                                   .get(&request.key)
+                                   .map(|&(size, _)| size);
+                    compute_upload_scale(&image_template.descriptor, hint, current_scale)
+                }
+                _ => 1,
+            };
+
+            let mut scaled_descriptor = None;
             let image_data = match image_template.data {
+                ImageData::Raw(ref bytes) if upload_scale > 1 => {
+                    // The image is displayed much smaller than it is, so
+                    // downsample it before uploading. The full-size pixels
+                    // stay in the template in case the image is displayed
+                    // larger again later.
+                    let (scaled_data, descriptor) =
+                        downscale_image(bytes, &image_template.descriptor, upload_scale);
+                    scaled_descriptor = Some(descriptor);
+                    ImageData::new(scaled_data)
+                }
                 ImageData::Raw(..) | ImageData::External(..) => {
                     // Safe to clone here since the Raw image data is an
                     // Arc, and the external image data is small.
                     image_template.data.clone()
                 }
                 ImageData::Blob(..) => {
+                    let blob_request: BlobImageRequest = request.into();
+                    let issued_frame = *self.blob_requests_in_flight
+                                            .get(&blob_request)
+                                            .expect("BUG: blob image that was never requested");
+
                     // Extract the rasterized image from the blob renderer.
-                    match self.blob_image_renderer.as_mut().unwrap().resolve(request.into()) {
-                        Ok(image) => ImageData::new(image.data),
+                    // A request issued this frame gets a chance to skip the
+                    // wait: when its result isn't in yet, a transparent
+                    // placeholder is uploaded instead and the backend
+                    // schedules a follow-up frame. A request that was
+                    // already in flight when this frame started has had a
+                    // frame's worth of time to finish, so block on it
+                    // rather than publishing another placeholder.
+                    let renderer = self.blob_image_renderer.as_mut().unwrap();
+                    let result = if issued_frame == self.current_frame_id {
+                        renderer.try_resolve(blob_request)
+                    } else {
+                        Some(renderer.resolve(blob_request))
+                    };
+
+                    match result {
+                        Some(Ok(image)) => {
+                            self.blob_requests_in_flight.remove(&blob_request);
+                            ImageData::new(image.data)
+                        }
+                        None => {
+                            // Still rasterizing on a worker. The request
+                            // stays in flight and the follow-up frame
+                            // replaces the placeholder with the result.
+                            let (w, h) = match request.tile {
+                                Some(tile) => {
+                                    compute_tile_size(&image_template.descriptor,
+                                                      image_template.tiling.unwrap(),
+                                                      tile)
+                                }
+                                None => {
+                                    (image_template.descriptor.width, image_template.descriptor.height)
+                                }
+                            };
+                            let bpp = image_template.descriptor.format.bytes_per_pixel().unwrap();
+                            ImageData::new(vec![0; (w * h * bpp) as usize])
+                        }
                         // TODO(nical): I think that we should handle these somewhat gracefully,
                         // at least in the out-of-memory scenario.
-                        Err(BlobImageError::Oom) => {
+                        Some(Err(BlobImageError::Oom)) => {
                             // This one should be recoverable-ish.
                             panic!("Failed to render a vector image (OOM)");
                         }
-                        Err(BlobImageError::InvalidKey) => {
+                        Some(Err(BlobImageError::InvalidKey)) => {
                             panic!("Invalid vector image key");
                         }
-                        Err(BlobImageError::InvalidData) => {
+                        Some(Err(BlobImageError::InvalidData)) => {
                             // TODO(nical): If we run into this we should kill the content process.
                             panic!("Invalid vector image data");
                         }
-                        Err(BlobImageError::Other(msg)) => {
+                        Some(Err(BlobImageError::Other(msg))) => {
                             panic!("Vector image error {}", msg);
                         }
                     }
@@ -753,26 +1565,42 @@ impl ResourceCache {
                     format: image_descriptor.format,
                     is_opaque: image_descriptor.is_opaque,
                 }
+            } else if let Some(descriptor) = scaled_descriptor {
+                descriptor
             } else {
                 image_template.descriptor.clone()
             };
 
+            // A dirty rect is expressed in full-size pixels, so it can't
+            // be applied to a downsampled upload.
+            let dirty_rect = if upload_scale > 1 {
+                None
+            } else {
+                image_template.dirty_rect
+            };
+
             match self.cached_images.entry(request, self.current_frame_id) {
                 Occupied(mut entry) => {
                     let entry = entry.get_mut();
 
                     // We should only get to this code path if the image
-                    // definitely needs to be updated.
-                    debug_assert!(entry.epoch != image_template.epoch);
+                    // definitely needs to be updated. A blob image is also
+                    // re-uploaded at the same epoch when it replaces the
+                    // placeholder of a request that missed its frame, and
+                    // so is an image whose upload scale changed.
+                    debug_assert!(entry.epoch != image_template.epoch ||
+                                  entry.upload_scale != upload_scale ||
+                                  image_template.data.is_blob());
                     self.texture_cache.update(&entry.texture_cache_id,
                                               descriptor,
                                               filter,
                                               image_data,
-                                              image_template.dirty_rect);
+                                              dirty_rect);
 
                     // Update the cached epoch
                     debug_assert_eq!(self.current_frame_id, entry.last_access);
                     entry.epoch = image_template.epoch;
+                    entry.upload_scale = upload_scale;
                     image_template.dirty_rect = None;
                 }
                 Vacant(entry) => {
@@ -786,18 +1614,77 @@ impl ResourceCache {
                         texture_cache_id: image_id,
                         epoch: image_template.epoch,
                         last_access: self.current_frame_id,
+                        upload_scale,
                     });
                 }
             };
         }
     }
 
+    // Moves items off a fragmented texture cache page, a budget's worth
+    // per idle frame, until the page is empty and its texture can be
+    // released. Long sessions fragment pages to the point where large
+    // allocations fail and fall back to standalone textures; this wins
+    // the space back without a visible pause.
+    fn compact_texture_cache(&mut self, texture_cache_profile: &mut TextureCacheProfileCounters) {
+        // Only work on frames that didn't upload anything themselves.
+        if self.texture_cache.has_pending_updates() {
+            return;
+        }
+
+        let page = match self.texture_cache.start_compaction() {
+            Some(page) => page,
+            None => return,
+        };
+
+        let mut budget = COMPACTION_BYTES_PER_FRAME;
+
+        for info in self.cached_images.resources.values() {
+            if budget == 0 {
+                break;
+            }
+            if self.texture_cache.get(&info.texture_cache_id).texture_id == page {
+                let bytes = self.texture_cache.relocate_item(&info.texture_cache_id,
+                                                             texture_cache_profile);
+                budget = budget.saturating_sub(bytes);
+            }
+        }
+
+        'glyphs: for glyph_key_cache in self.cached_glyphs.glyph_key_caches.values() {
+            for (_, info) in glyph_key_cache.iter() {
+                if budget == 0 {
+                    break 'glyphs;
+                }
+                if let Some(ref id) = info.texture_cache_id {
+                    if self.texture_cache.get(id).texture_id == page {
+                        let bytes = self.texture_cache.relocate_item(id, texture_cache_profile);
+                        budget = budget.saturating_sub(bytes);
+                    }
+                }
+            }
+        }
+
+        for info in self.cached_paths.resources.values() {
+            if budget == 0 {
+                break;
+            }
+            if self.texture_cache.get(&info.texture_cache_id).texture_id == page {
+                let bytes = self.texture_cache.relocate_item(&info.texture_cache_id,
+                                                             texture_cache_profile);
+                budget = budget.saturating_sub(bytes);
+            }
+        }
+
+        self.texture_cache.end_compaction_if_empty();
+    }
+
     pub fn end_frame(&mut self) {
         debug_assert_eq!(self.state, State::QueryResources);
         self.state = State::Idle;
+        self.expire_retired_fonts();
     }
 
-    pub fn on_memory_pressure(&mut self) {
+    pub fn on_memory_pressure(&mut self) -> usize {
         // This is drastic. It will basically flush everything out of the cache,
         // and the next frame will have to rebuild all of its resources.
         // We may want to look into something less extreme, but on the other hand this
@@ -806,18 +1693,132 @@ impl ResourceCache {
         // The advantage of clearing the cache completely is that it gets rid of any
         // remaining fragmentation that could have persisted if we kept around the most
         // recently used resources.
+        let bytes = self.cached_texture_bytes();
         self.cached_images.clear(&mut self.texture_cache);
         self.cached_glyphs.clear(&mut self.texture_cache);
+        self.cached_paths.clear(&mut self.texture_cache);
+
+        // Retired fonts are only kept as a navigation optimization;
+        // under pressure, delete them right away.
+        let retired: Vec<_> = self.retired_fonts.drain(..).collect();
+        for (key, _) in retired {
+            self.delete_retired_font(key);
+        }
+
+        bytes
+    }
+
+    /// The texture bytes covered by every cached image and glyph, for
+    /// reporting what a full flush reclaims.
+    fn cached_texture_bytes(&self) -> usize {
+        let mut bytes = 0;
+        for info in self.cached_images.resources.values() {
+            bytes += self.texture_cache.item_bytes(&info.texture_cache_id);
+        }
+        for glyph_key_cache in self.cached_glyphs.glyph_key_caches.values() {
+            for (_, info) in glyph_key_cache.iter() {
+                if let Some(ref id) = info.texture_cache_id {
+                    bytes += self.texture_cache.item_bytes(id);
+                }
+            }
+        }
+        for info in self.cached_paths.resources.values() {
+            bytes += self.texture_cache.item_bytes(&info.texture_cache_id);
+        }
+        bytes
+    }
+
+    /// Evicts cache entries that were not referenced by the most recent
+    /// frame, returning the texture bytes they covered. This is the
+    /// moderate response to memory pressure: entries the last frame used
+    /// stay put, so nothing has to be rebuilt right away.
+    pub fn evict_idle_resources(&mut self) -> usize {
+        let mut bytes = 0;
+        let frame_id = self.current_frame_id;
+
+        let idle_images: Vec<_> = self.cached_images.resources.iter()
+            .filter(|&(_, info)| info.last_access < frame_id)
+            .map(|(&request, _)| request)
+            .collect();
+        for request in idle_images {
+            let info = self.cached_images.resources.remove(&request).unwrap();
+            bytes += self.texture_cache.item_bytes(&info.texture_cache_id);
+            info.free(&mut self.texture_cache);
+        }
+
+        for glyph_key_cache in self.cached_glyphs.glyph_key_caches.values_mut() {
+            let idle_glyphs: Vec<_> = glyph_key_cache.resources.iter()
+                .filter(|&(_, info)| info.last_access < frame_id)
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in idle_glyphs {
+                let info = glyph_key_cache.resources.remove(&key).unwrap();
+                if let Some(ref id) = info.texture_cache_id {
+                    bytes += self.texture_cache.item_bytes(id);
+                }
+                info.free(&mut self.texture_cache);
+            }
+        }
+
+        let idle_paths: Vec<_> = self.cached_paths.resources.iter()
+            .filter(|&(_, info)| info.last_access < frame_id)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in idle_paths {
+            let info = self.cached_paths.resources.remove(&key).unwrap();
+            bytes += self.texture_cache.item_bytes(&info.texture_cache_id);
+            info.free(&mut self.texture_cache);
+        }
+
+        bytes
+    }
+
+    pub fn on_context_lost(&mut self) {
+        // Everything in the texture cache lives in textures that died with the
+        // GL context. Flush the cached images and glyphs so that the next
+        // frame re-requests them from the retained templates, and restart the
+        // texture cache itself so that new cache textures get allocated and
+        // the pending uploads aimed at the dead textures are dropped.
+        self.cached_images.clear(&mut self.texture_cache);
+        self.cached_glyphs.clear(&mut self.texture_cache);
+        self.cached_paths.clear(&mut self.texture_cache);
+        self.texture_cache.clear();
     }
 
     pub fn clear_namespace(&mut self, namespace: IdNamespace) {
+        // Aliases in this namespace just drop their reference on the
+        // shared template, which may live in another namespace.
+        let image_aliases: Vec<_> = self.resources.image_aliases.iter()
+                                                                .filter(|&(key, _)| key.0 == namespace)
+                                                                .map(|(&key, &canonical)| (key, canonical))
+                                                                .collect();
+        for (key, canonical) in image_aliases {
+            self.resources.image_aliases.remove(&key);
+            self.release_image(canonical);
+        }
+
+        let font_aliases: Vec<_> = self.resources.font_aliases.iter()
+                                                              .filter(|&(key, _)| key.0 == namespace)
+                                                              .map(|(&key, &canonical)| (key, canonical))
+                                                              .collect();
+        for (key, canonical) in font_aliases {
+            self.resources.font_aliases.remove(&key);
+            self.release_font(canonical);
+        }
+
         //TODO: use `retain` when we are on Rust-1.18
         let image_keys: Vec<_> = self.resources.image_templates.images.keys()
                                                                       .filter(|&key| key.0 == namespace)
                                                                       .cloned()
                                                                       .collect();
         for key in &image_keys {
-            self.resources.image_templates.images.remove(key);
+            if self.image_refs.contains_key(key) {
+                // A canonical key with aliases elsewhere; the template
+                // (and its cache entries) outlive this namespace.
+                self.release_image(*key);
+            } else {
+                self.resources.image_templates.images.remove(key);
+            }
         }
 
         let font_keys: Vec<_> = self.resources.font_templates.keys()
@@ -825,11 +1826,37 @@ impl ResourceCache {
                                                              .cloned()
                                                              .collect();
         for key in &font_keys {
-            self.resources.font_templates.remove(key);
+            if self.font_refs.contains_key(key) {
+                self.release_font(*key);
+            } else {
+                self.resources.font_templates.remove(key);
+            }
+        }
+
+        // Raw glyph sets for fonts that had no template of their own go
+        // away with their namespace. Refcounted fonts keep theirs; the
+        // release above retires them and expiry cleans up.
+        let raw_glyph_fonts: Vec<_> = self.resources.raw_glyphs.keys()
+                                                               .filter(|&key| key.0 == namespace)
+                                                               .cloned()
+                                                               .collect();
+        for key in raw_glyph_fonts {
+            if !self.font_refs.contains_key(&key) {
+                self.resources.raw_glyphs.remove(&key);
+            }
         }
 
-        self.cached_images.clear_keys(&mut self.texture_cache, |request| request.key.0 == namespace);
-        self.cached_glyphs.clear_fonts(&mut self.texture_cache, |font| font.font_key.0 == namespace);
+        // Canonical keys that survived the releases above keep their
+        // cache entries: aliases in other namespaces still resolve to
+        // them.
+        let retained_images = &self.image_refs;
+        let retained_fonts = &self.font_refs;
+        self.cached_images.clear_keys(&mut self.texture_cache, |request| {
+            request.key.0 == namespace && !retained_images.contains_key(&request.key)
+        });
+        self.cached_glyphs.clear_fonts(&mut self.texture_cache, |font| {
+            font.font_key.0 == namespace && !retained_fonts.contains_key(&font.font_key)
+        });
     }
 }
 
@@ -884,3 +1911,354 @@ pub fn compute_tile_size(descriptor: &ImageDescriptor,
 
     (actual_width, actual_height)
 }
+
+/// Picks the power-of-two factor to downsample an image by before
+/// uploading it, so that a huge image that is only displayed small
+/// doesn't occupy a full-size allocation in the texture cache.
+///
+/// `current_scale` is the factor the image is uploaded at right now;
+/// switching to a smaller upload requires some headroom over the display
+/// size so that zoom changes around a scale boundary don't thrash
+/// re-uploads.
+fn compute_upload_scale(descriptor: &ImageDescriptor,
+                        display_size: Option<DeviceUintSize>,
+                        current_scale: u32) -> u32 {
+    let display_size = match display_size {
+        Some(size) => size,
+        None => return 1,
+    };
+
+    if cmp::max(descriptor.width, descriptor.height) <= IMAGE_DOWNSCALE_MIN_SIZE {
+        return 1;
+    }
+
+    // Only 8-bit formats can be downsampled on the CPU here.
+    match descriptor.format {
+        ImageFormat::A8 |
+        ImageFormat::RG8 |
+        ImageFormat::RGB8 |
+        ImageFormat::BGRA8 => {}
+        _ => return 1,
+    }
+
+    let display_width = cmp::max(display_size.width, 1);
+    let display_height = cmp::max(display_size.height, 1);
+
+    // The largest power of two that keeps the upload at least as large
+    // as the area it is displayed in.
+    let mut scale = 1;
+    while descriptor.width / (scale * 2) >= display_width &&
+          descriptor.height / (scale * 2) >= display_height {
+        scale *= 2;
+    }
+
+    if scale > current_scale {
+        let headroom_width = (display_width as f32 * IMAGE_DOWNSCALE_HYSTERESIS) as u32;
+        let headroom_height = (display_height as f32 * IMAGE_DOWNSCALE_HYSTERESIS) as u32;
+        if descriptor.width / scale < headroom_width ||
+           descriptor.height / scale < headroom_height {
+            return current_scale;
+        }
+    }
+
+    scale
+}
+
+/// Box-filters `scale`x`scale` blocks of an image into a smaller copy
+/// for uploading. `scale` must evenly address the image through the
+/// descriptor's stride; trailing rows and columns that don't fill a
+/// whole block are dropped.
+fn downscale_image(bytes: &[u8],
+                   descriptor: &ImageDescriptor,
+                   scale: u32) -> (Vec<u8>, ImageDescriptor) {
+    let bpp = descriptor.format.bytes_per_pixel().unwrap() as usize;
+    let stride = descriptor.compute_stride() as usize;
+    let offset = descriptor.offset as usize;
+
+    let out_width = descriptor.width / scale;
+    let out_height = descriptor.height / scale;
+    let mut output = Vec::with_capacity((out_width * out_height) as usize * bpp);
+
+    for out_y in 0..out_height {
+        for out_x in 0..out_width {
+            for channel in 0..bpp {
+                let mut sum = 0u32;
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let x = (out_x * scale + dx) as usize;
+                        let y = (out_y * scale + dy) as usize;
+                        sum += bytes[offset + y * stride + x * bpp + channel] as u32;
+                    }
+                }
+                output.push((sum / (scale * scale)) as u8);
+            }
+        }
+    }
+
+    let scaled_descriptor = ImageDescriptor {
+        width: out_width,
+        height: out_height,
+        stride: None,
+        offset: 0,
+        format: descriptor.format,
+        is_opaque: descriptor.is_opaque,
+    };
+
+    (output, scaled_descriptor)
+}
+
+/// Content hash of a font template, for sharing identical fonts added
+/// under different keys. Returns None for fonts that can't be shared.
+fn hash_font_template(template: &FontTemplate) -> Option<u64> {
+    match *template {
+        FontTemplate::Raw(ref bytes, index) => {
+            let mut hasher = DefaultHasher::new();
+            bytes.hash(&mut hasher);
+            index.hash(&mut hasher);
+            Some(hasher.finish())
+        }
+        // Native handles reference platform fonts, not content.
+        FontTemplate::Native(..) => None,
+    }
+}
+
+// Verifies that two font templates that hashed equally really are the
+// same font, so that a hash collision can't alias unrelated fonts.
+fn font_templates_match(existing: &FontTemplate, template: &FontTemplate) -> bool {
+    match (existing, template) {
+        (&FontTemplate::Raw(ref existing_bytes, existing_index),
+         &FontTemplate::Raw(ref bytes, index)) => {
+            existing_index == index &&
+                (Arc::ptr_eq(existing_bytes, bytes) || **existing_bytes == **bytes)
+        }
+        _ => false,
+    }
+}
+
+/// Content hash of an image template. Only raw images can be shared:
+/// blob and external images are backed by state held outside the
+/// resource cache.
+fn hash_image_template(descriptor: &ImageDescriptor,
+                       data: &ImageData) -> Option<u64> {
+    match *data {
+        ImageData::Raw(ref bytes) => {
+            let mut hasher = DefaultHasher::new();
+            descriptor.width.hash(&mut hasher);
+            descriptor.height.hash(&mut hasher);
+            descriptor.stride.hash(&mut hasher);
+            descriptor.offset.hash(&mut hasher);
+            descriptor.is_opaque.hash(&mut hasher);
+            (descriptor.format as u32).hash(&mut hasher);
+            bytes.hash(&mut hasher);
+            Some(hasher.finish())
+        }
+        ImageData::Blob(..) | ImageData::External(..) => None,
+    }
+}
+
+// As with fonts, a hash match is confirmed with a real comparison
+// before two image keys are aliased.
+fn image_templates_match(existing: &ImageResource,
+                         descriptor: &ImageDescriptor,
+                         data: &ImageData) -> bool {
+    match (&existing.data, data) {
+        (&ImageData::Raw(ref existing_bytes), &ImageData::Raw(ref bytes)) => {
+            existing.descriptor == *descriptor &&
+                (Arc::ptr_eq(existing_bytes, bytes) || **existing_bytes == **bytes)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rayon::Configuration;
+
+    fn test_cache() -> ResourceCache {
+        let workers = Arc::new(ThreadPool::new(Configuration::new()).unwrap());
+        ResourceCache::new(TextureCache::new(4096), workers, None, 1, None)
+    }
+
+    fn raw_image(byte: u8) -> (ImageDescriptor, ImageData) {
+        (ImageDescriptor::new(2, 2, ImageFormat::BGRA8, true),
+         ImageData::new(vec![byte; 2 * 2 * 4]))
+    }
+
+    fn image_bytes(cache: &ResourceCache, key: ImageKey) -> u8 {
+        match cache.resources.image_templates.get(key).unwrap().data {
+            ImageData::Raw(ref bytes) => bytes[0],
+            _ => panic!("expected raw image data"),
+        }
+    }
+
+    #[test]
+    fn image_alias_update_detaches_the_alias() {
+        let mut cache = test_cache();
+        let canonical = ImageKey::new(IdNamespace(1), 0);
+        let alias = ImageKey::new(IdNamespace(2), 0);
+
+        let (descriptor, data) = raw_image(10);
+        cache.add_image_template(canonical, descriptor, data, None);
+        let (descriptor, data) = raw_image(10);
+        cache.add_image_template(alias, descriptor, data, None);
+
+        assert_eq!(cache.resources.image_aliases.get(&alias), Some(&canonical));
+        assert_eq!(cache.image_refs.get(&canonical).unwrap().0, 2);
+        assert!(cache.resources.image_templates.get(alias).is_none());
+
+        // Updating the alias must give it its own template and leave the
+        // canonical key's pixels alone.
+        let (descriptor, data) = raw_image(20);
+        cache.update_image_template(alias, descriptor, data, None);
+
+        assert!(cache.resources.image_aliases.is_empty());
+        assert_eq!(cache.image_refs.get(&canonical).unwrap().0, 1);
+        assert_eq!(image_bytes(&cache, alias), 20);
+        assert_eq!(image_bytes(&cache, canonical), 10);
+    }
+
+    #[test]
+    fn image_canonical_update_detaches_its_aliases() {
+        let mut cache = test_cache();
+        let canonical = ImageKey::new(IdNamespace(1), 0);
+        let alias = ImageKey::new(IdNamespace(2), 0);
+
+        let (descriptor, data) = raw_image(10);
+        cache.add_image_template(canonical, descriptor, data, None);
+        let (descriptor, data) = raw_image(10);
+        cache.add_image_template(alias, descriptor, data, None);
+
+        // Updating the canonical key must hand the alias a copy of the
+        // pixels it was sharing before the update.
+        let (descriptor, data) = raw_image(20);
+        cache.update_image_template(canonical, descriptor, data, None);
+
+        assert!(cache.resources.image_aliases.is_empty());
+        assert_eq!(image_bytes(&cache, alias), 10);
+        assert_eq!(image_bytes(&cache, canonical), 20);
+
+        // The canonical key is re-keyed under its new content hash, so a
+        // later add of the new content shares it again.
+        let third = ImageKey::new(IdNamespace(3), 0);
+        let (descriptor, data) = raw_image(20);
+        cache.add_image_template(third, descriptor, data, None);
+        assert_eq!(cache.resources.image_aliases.get(&third), Some(&canonical));
+        assert_eq!(cache.image_refs.get(&canonical).unwrap().0, 2);
+    }
+
+    #[test]
+    fn image_delete_order_does_not_matter() {
+        // Canonical deleted first: the template has to survive until the
+        // alias releases the last reference.
+        let mut cache = test_cache();
+        let canonical = ImageKey::new(IdNamespace(1), 0);
+        let alias = ImageKey::new(IdNamespace(2), 0);
+
+        let (descriptor, data) = raw_image(10);
+        cache.add_image_template(canonical, descriptor, data, None);
+        let (descriptor, data) = raw_image(10);
+        cache.add_image_template(alias, descriptor, data, None);
+
+        cache.delete_image_template(canonical);
+        assert!(cache.resources.image_templates.get(canonical).is_some());
+        assert_eq!(cache.image_refs.get(&canonical).unwrap().0, 1);
+
+        cache.delete_image_template(alias);
+        assert!(cache.resources.image_templates.images.is_empty());
+        assert!(cache.resources.image_aliases.is_empty());
+        assert!(cache.image_refs.is_empty());
+        assert!(cache.image_dedupe.is_empty());
+
+        // Alias deleted first: same outcome.
+        let mut cache = test_cache();
+        let (descriptor, data) = raw_image(10);
+        cache.add_image_template(canonical, descriptor, data, None);
+        let (descriptor, data) = raw_image(10);
+        cache.add_image_template(alias, descriptor, data, None);
+
+        cache.delete_image_template(alias);
+        assert!(cache.resources.image_templates.get(canonical).is_some());
+
+        cache.delete_image_template(canonical);
+        assert!(cache.resources.image_templates.images.is_empty());
+        assert!(cache.image_refs.is_empty());
+        assert!(cache.image_dedupe.is_empty());
+    }
+
+    #[test]
+    fn clear_namespace_with_cross_namespace_aliases() {
+        // Clearing the alias's namespace only drops a reference.
+        let mut cache = test_cache();
+        let canonical = ImageKey::new(IdNamespace(1), 0);
+        let alias = ImageKey::new(IdNamespace(2), 0);
+
+        let (descriptor, data) = raw_image(10);
+        cache.add_image_template(canonical, descriptor, data, None);
+        let (descriptor, data) = raw_image(10);
+        cache.add_image_template(alias, descriptor, data, None);
+
+        cache.clear_namespace(IdNamespace(2));
+        assert!(cache.resources.image_aliases.is_empty());
+        assert!(cache.resources.image_templates.get(canonical).is_some());
+        assert_eq!(cache.image_refs.get(&canonical).unwrap().0, 1);
+
+        cache.clear_namespace(IdNamespace(1));
+        assert!(cache.resources.image_templates.images.is_empty());
+        assert!(cache.image_refs.is_empty());
+
+        // Clearing the canonical key's namespace first: the shared
+        // template outlives its namespace while the alias still needs it.
+        let mut cache = test_cache();
+        let (descriptor, data) = raw_image(10);
+        cache.add_image_template(canonical, descriptor, data, None);
+        let (descriptor, data) = raw_image(10);
+        cache.add_image_template(alias, descriptor, data, None);
+
+        cache.clear_namespace(IdNamespace(1));
+        assert_eq!(cache.resources.canonical_image_key(alias), canonical);
+        assert!(cache.resources.image_templates.get(canonical).is_some());
+        assert_eq!(cache.image_refs.get(&canonical).unwrap().0, 1);
+
+        cache.clear_namespace(IdNamespace(2));
+        assert!(cache.resources.image_templates.images.is_empty());
+        assert!(cache.image_refs.is_empty());
+        assert!(cache.image_dedupe.is_empty());
+    }
+
+    #[test]
+    fn retired_font_is_revived_by_a_matching_add() {
+        // The bytes don't need to parse as a font for the dedupe
+        // bookkeeping; the rasterizer just warns and moves on.
+        let bytes = vec![7; 16];
+        let mut cache = test_cache();
+        let canonical = FontKey::new(IdNamespace(1), 0);
+        let alias = FontKey::new(IdNamespace(2), 0);
+
+        cache.add_font_template(canonical, FontTemplate::Raw(Arc::new(bytes.clone()), 0));
+        cache.add_font_template(alias, FontTemplate::Raw(Arc::new(bytes.clone()), 0));
+        assert_eq!(cache.resources.font_aliases.get(&alias), Some(&canonical));
+        assert_eq!(cache.font_refs.get(&canonical).unwrap().0, 2);
+
+        // Dropping the last reference retires the font instead of
+        // deleting it.
+        cache.delete_font_template(alias);
+        cache.delete_font_template(canonical);
+        assert_eq!(cache.retired_fonts.len(), 1);
+        assert!(cache.resources.font_templates.get(&canonical).is_some());
+
+        // Re-adding the same face within the retention window revives it.
+        let revived = FontKey::new(IdNamespace(3), 0);
+        cache.add_font_template(revived, FontTemplate::Raw(Arc::new(bytes), 0));
+        assert!(cache.retired_fonts.is_empty());
+        assert_eq!(cache.resources.font_aliases.get(&revived), Some(&canonical));
+        assert_eq!(cache.font_refs.get(&canonical).unwrap().0, 1);
+
+        // Under memory pressure a retired font is deleted right away.
+        cache.delete_font_template(revived);
+        cache.on_memory_pressure();
+        assert!(cache.resources.font_templates.is_empty());
+        assert!(cache.font_refs.is_empty());
+        assert!(cache.font_dedupe.is_empty());
+    }
+}
@@ -9,6 +9,7 @@ use internal_types::FastHashMap;
 /// Stores a map of the animated property bindings for the current display list. These
 /// can be used to animate the transform and/or opacity of a display list without
 /// re-submitting the display list itself.
+#[derive(Clone)]
 pub struct SceneProperties {
     transform_properties: FastHashMap<PropertyBindingId, LayoutTransform>,
     float_properties: FastHashMap<PropertyBindingId, f32>,
@@ -77,7 +78,7 @@ impl SceneProperties {
 }
 
 /// A representation of the layout within the display port for a given document or iframe.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ScenePipeline {
     pub pipeline_id: PipelineId,
     pub epoch: Epoch,
@@ -87,6 +88,7 @@ pub struct ScenePipeline {
 }
 
 /// A complete representation of the layout bundling visible pipelines together.
+#[derive(Clone)]
 pub struct Scene {
     pub root_pipeline_id: Option<PipelineId>,
     pub pipeline_map: FastHashMap<PipelineId, ScenePipeline>,
@@ -0,0 +1,106 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Scene building on a dedicated thread.
+//!
+//! Flattening a large display list into a frame builder can take several
+//! milliseconds, and doing it on the render backend thread blocks scroll and
+//! animation messages for that long. Instead, the backend snapshots everything
+//! a build needs and sends it here. Once the scene has been built the result
+//! is handed back and swapped into the document, while the old scene keeps
+//! producing frames in the meantime.
+
+use api::{ApiMsg, DeviceUintRect, DeviceUintSize, DocumentId};
+use api::channel::MsgSender;
+use frame::Frame;
+use frame_builder::FrameBuilderConfig;
+use gpu_cache::GpuCacheHandle;
+use internal_types::{FastHashMap, QualitySettings};
+use resource_cache::TiledImageMap;
+use scene::Scene;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use thread_profiler::register_thread_with_profiler;
+
+/// A snapshot of everything needed to build a scene, sent by the render
+/// backend to the scene builder thread.
+pub struct SceneRequest {
+    pub document_id: DocumentId,
+    pub scene: Scene,
+    pub tiled_image_map: TiledImageMap,
+    pub window_size: DeviceUintSize,
+    pub inner_rect: DeviceUintRect,
+    pub accumulated_scale_factor: f32,
+    /// The quality settings in effect when the build was requested, which
+    /// may have been downgraded below the configured baseline by pressure
+    /// signals since this thread's config copy was made.
+    pub quality: QualitySettings,
+    /// GPU cache locations harvested from the frame being replaced, so that
+    /// primitives that are unchanged in the new scene keep their blocks.
+    pub retained_gpu_locations: FastHashMap<u64, GpuCacheHandle>,
+}
+
+/// A built scene, sent back to the render backend to be swapped into the
+/// document in place of the one it was built from.
+pub struct BuiltScene {
+    pub document_id: DocumentId,
+    pub scene: Scene,
+    pub frame: Frame,
+}
+
+pub struct SceneBuilder {
+    rx: Receiver<SceneRequest>,
+    tx: Sender<BuiltScene>,
+    api_tx: MsgSender<ApiMsg>,
+    config: FrameBuilderConfig,
+}
+
+impl SceneBuilder {
+    pub fn new(config: FrameBuilderConfig,
+               api_tx: MsgSender<ApiMsg>)
+               -> (SceneBuilder, Sender<SceneRequest>, Receiver<BuiltScene>) {
+        let (in_tx, in_rx) = channel();
+        let (out_tx, out_rx) = channel();
+        let builder = SceneBuilder {
+            rx: in_rx,
+            tx: out_tx,
+            api_tx,
+            config,
+        };
+        (builder, in_tx, out_rx)
+    }
+
+    pub fn run(&mut self) {
+        register_thread_with_profiler("SceneBuilder".to_string());
+
+        while let Ok(request) = self.rx.recv() {
+            profile_scope!("build_scene");
+            self.build_scene(request);
+        }
+    }
+
+    fn build_scene(&mut self, request: SceneRequest) {
+        let mut config = self.config;
+        config.quality = request.quality;
+        let mut frame = Frame::new(config);
+        frame.seed_gpu_locations(request.retained_gpu_locations);
+        frame.create(&request.scene,
+                     request.tiled_image_map,
+                     request.window_size,
+                     request.inner_rect,
+                     request.accumulated_scale_factor);
+
+        if self.tx.send(BuiltScene {
+            document_id: request.document_id,
+            scene: request.scene,
+            frame,
+        }).is_err() {
+            // The render backend is gone, so this thread is shutting down.
+            return;
+        }
+
+        // The backend is probably blocked waiting for a message, so make sure
+        // it wakes up and notices the built scene.
+        self.api_tx.send(ApiMsg::WakeUp).ok();
+    }
+}
@@ -121,6 +121,13 @@ impl TexturePage {
         self.find_index_of_best_rect(requested_dimensions).is_some()
     }
 
+    /// The number of unallocated texels on this page.
+    fn free_area(&self) -> u32 {
+        let mut rects = Vec::new();
+        self.free_list.copy_to_vec(&mut rects);
+        rects.iter().map(|rect| rect.size.width * rect.size.height).sum()
+    }
+
     pub fn allocate(&mut self,
                     requested_dimensions: &DeviceUintSize) -> Option<DeviceUintPoint> {
         if requested_dimensions.width == 0 || requested_dimensions.height == 0 {
@@ -506,6 +513,18 @@ impl TextureCacheArena {
         }
         None
     }
+
+    fn remove_page(&mut self, id: CacheTextureId) {
+        for page_list in &mut [&mut self.pages_a8,
+                               &mut self.pages_rgb8,
+                               &mut self.pages_rgba8,
+                               &mut self.pages_rg8] {
+            if let Some(index) = page_list.iter().position(|page| page.texture_id == id) {
+                page_list.swap_remove(index);
+                return;
+            }
+        }
+    }
 }
 
 pub struct CacheTextureIdList {
@@ -545,6 +564,11 @@ pub struct TextureCache {
     arena: TextureCacheArena,
     pending_updates: TextureUpdateList,
     max_texture_size: u32,
+
+    // The page currently being emptied by compaction, if any. New
+    // allocations skip this page so that it eventually drains and its
+    // texture can be released.
+    compacting_page: Option<CacheTextureId>,
 }
 
 #[derive(PartialEq, Eq, Debug)]
@@ -573,6 +597,7 @@ impl TextureCache {
             pending_updates: TextureUpdateList::new(),
             arena: TextureCacheArena::new(),
             max_texture_size,
+            compacting_page: None,
         }
     }
 
@@ -584,6 +609,19 @@ impl TextureCache {
         mem::replace(&mut self.pending_updates, TextureUpdateList::new())
     }
 
+    pub fn has_pending_updates(&self) -> bool {
+        !self.pending_updates.updates.is_empty()
+    }
+
+    /// Throw away every cached item along with any pending updates. Used when
+    /// the GL context was lost: the textures the items lived in no longer
+    /// exist, so the cache texture ids restart from zero and the renderer must
+    /// forget its id mappings in step with this.
+    pub fn clear(&mut self) {
+        let max_texture_size = self.max_texture_size;
+        *self = TextureCache::new(max_texture_size);
+    }
+
     pub fn allocate(
         &mut self,
         requested_width: u32,
@@ -667,7 +705,12 @@ impl TextureCache {
             ImageFormat::BGRA8 => (&mut self.arena.pages_rgba8, &mut profile.pages_rgba8),
             ImageFormat::RGB8 => (&mut self.arena.pages_rgb8, &mut profile.pages_rgb8),
             ImageFormat::RG8 => (&mut self.arena.pages_rg8, &mut profile.pages_rg8),
-            ImageFormat::Invalid | ImageFormat::RGBAF32 => unreachable!(),
+            // The wide formats are only used for render targets, never
+            // for cached image data.
+            ImageFormat::Invalid |
+            ImageFormat::RGBAF32 |
+            ImageFormat::RGB10A2 |
+            ImageFormat::RGBA16F => unreachable!(),
         };
 
 
@@ -685,6 +728,11 @@ impl TextureCache {
 
         let mut page_id = None; //using ID here to please the borrow checker
         for (i, page) in page_list.iter_mut().enumerate() {
+            // Don't put anything new on a page that compaction is
+            // trying to empty.
+            if Some(page.texture_id) == self.compacting_page {
+                continue;
+            }
             if page.can_allocate(&requested_size) {
                 page_id = Some(i);
                 break;
@@ -1012,6 +1060,128 @@ impl TextureCache {
         self.free_item_rect(item);
     }
 
+    /// The number of texture bytes an item's allocation covers, for
+    /// reporting how much an eviction reclaims.
+    pub fn item_bytes(&self, id: &TextureCacheItemId) -> usize {
+        let item = self.items.get(id);
+        (item.allocated_rect.size.width *
+         item.allocated_rect.size.height *
+         item.format.bytes_per_pixel().unwrap_or(0)) as usize
+    }
+
+    /// Starts compacting a fragmented page, or reports the page already
+    /// being compacted. Picks the page with the fewest live allocations
+    /// among formats that have more than one page, on the grounds that
+    /// it is the cheapest page to empty.
+    pub fn start_compaction(&mut self) -> Option<CacheTextureId> {
+        if self.compacting_page.is_some() {
+            return self.compacting_page;
+        }
+
+        let mut best: Option<(u32, CacheTextureId)> = None;
+        for page_list in &[&self.arena.pages_a8,
+                           &self.arena.pages_rgb8,
+                           &self.arena.pages_rgba8,
+                           &self.arena.pages_rg8] {
+            // A format with a single page can't benefit: the items would
+            // simply move to a fresh page of the same format.
+            if page_list.len() < 2 {
+                continue;
+            }
+
+            let free_areas: Vec<u32> = page_list.iter()
+                                                .map(|page| page.free_area())
+                                                .collect();
+            let total_free: u32 = free_areas.iter().sum();
+
+            for (i, page) in page_list.iter().enumerate() {
+                // The items on the page must fit into the free space of
+                // the format's other pages, or compaction would just
+                // shuffle them onto a fresh page forever.
+                let page_area = page.texture_size.width * page.texture_size.height;
+                let allocated_area = page_area - free_areas[i];
+                if allocated_area > total_free - free_areas[i] {
+                    continue;
+                }
+
+                let better = match best {
+                    Some((allocations, _)) => page.allocations < allocations,
+                    None => true,
+                };
+                if better {
+                    best = Some((page.allocations, page.texture_id));
+                }
+            }
+        }
+
+        self.compacting_page = best.map(|(_, texture_id)| texture_id);
+        self.compacting_page
+    }
+
+    /// Moves an item off the page being compacted into another page of
+    /// the same format, copying the pixels on the GPU. Returns the number
+    /// of bytes copied, for budgeting.
+    pub fn relocate_item(&mut self,
+                         id: &TextureCacheItemId,
+                         profile: &mut TextureCacheProfileCounters) -> u32 {
+        let old_item = self.items.get(id).clone();
+
+        let result = self.allocate_impl(old_item.allocated_rect.size.width,
+                                        old_item.allocated_rect.size.height,
+                                        old_item.format,
+                                        TextureFilter::Linear,
+                                        old_item.user_data,
+                                        profile,
+                                        Some(id));
+        debug_assert_eq!(result.kind, AllocationKind::TexturePage);
+
+        self.pending_updates.push(TextureUpdate {
+            id: result.item.texture_id,
+            op: TextureUpdateOp::CopyRect {
+                src_id: old_item.texture_id,
+                src_rect: old_item.allocated_rect,
+                dest_rect: result.item.allocated_rect,
+            },
+        });
+
+        // The uv rect the item advertises in the GPU cache is stale now.
+        // Starting from a fresh handle makes the next use of the item
+        // request a new block holding the relocated rect.
+        self.items.get_mut(id).uv_rect_handle = GpuCacheHandle::new();
+
+        let bytes = old_item.allocated_rect.size.width *
+                    old_item.allocated_rect.size.height *
+                    old_item.format.bytes_per_pixel().unwrap_or(0);
+        self.free_item_rect(old_item);
+        bytes
+    }
+
+    /// Releases the compacted page's texture once everything has been
+    /// moved off it. Returns true when the page is gone.
+    pub fn end_compaction_if_empty(&mut self) -> bool {
+        let texture_id = match self.compacting_page {
+            Some(texture_id) => texture_id,
+            None => return true,
+        };
+
+        let drained = match self.arena.texture_page_for_id(texture_id) {
+            Some(page) => page.allocations == 0,
+            None => false,
+        };
+        if !drained {
+            return false;
+        }
+
+        self.arena.remove_page(texture_id);
+        self.pending_updates.push(TextureUpdate {
+            id: texture_id,
+            op: TextureUpdateOp::Free,
+        });
+        self.cache_id_list.free(texture_id);
+        self.compacting_page = None;
+        true
+    }
+
     fn free_item_rect(&mut self, item: TextureCacheItem) {
         match self.arena.texture_page_for_id(item.texture_id) {
             Some(texture_page) => texture_page.free(&item.allocated_rect),
@@ -5,31 +5,43 @@
 use border::{BorderCornerInstance, BorderCornerSide};
 use device::TextureId;
 use gpu_cache::{GpuCache, GpuCacheHandle, GpuCacheUpdateList};
-use internal_types::BatchTextures;
-use internal_types::{FastHashMap, SourceTexture};
+use internal_types::{BatchTextures, HardwareCompositeOp};
+use internal_types::{CacheTextureId, FastHashMap, SourceTexture, WebGLAcquire};
 use mask_cache::MaskCacheInfo;
-use prim_store::{CLIP_DATA_GPU_BLOCKS, DeferredResolve, ImagePrimitiveKind, PrimitiveCacheKey};
-use prim_store::{PrimitiveIndex, PrimitiveKind, PrimitiveMetadata, PrimitiveStore};
-use profiler::FrameProfileCounters;
+use prim_store::{CLIP_DATA_GPU_BLOCKS, DeferredResolve, ImagePrimitiveKind, PaintedTag};
+use prim_store::{PrimitiveCacheKey, PrimitiveIndex, PrimitiveKind, PrimitiveMetadata, PrimitiveStore};
+use profiler::{FrameProfileCounters, PipelineProfile};
+use rayon::prelude::*;
 use render_task::{AlphaRenderItem, MaskGeometryKind, MaskSegment, RenderTask, RenderTaskData};
 use render_task::{RenderTaskId, RenderTaskIndex, RenderTaskKey, RenderTaskKind};
 use render_task::RenderTaskLocation;
 use renderer::BlendMode;
 use renderer::ImageBufferKind;
 use resource_cache::ResourceCache;
-use std::{f32, i32, mem, usize};
+use std::{cmp, f32, i32, mem, usize};
 use texture_allocator::GuillotineAllocator;
 use util::{TransformedRect, TransformedRectKind};
 use api::{BuiltDisplayList, ClipAndScrollInfo, ClipId, ColorF, DeviceIntPoint, ImageKey};
 use api::{DeviceIntRect, DeviceIntSize, DeviceUintPoint, DeviceUintSize, FontInstanceKey};
-use api::{ExternalImageType, FilterOp, FontRenderMode, ImageRendering, LayerRect};
-use api::{LayerToWorldTransform, MixBlendMode, PipelineId, PropertyBinding, TransformStyle};
+use api::{ExternalImageId, ExternalImageType, FilterOp, FontRenderMode, ImageRendering, LayerRect};
+use api::{LayerToWorldTransform, MixBlendMode, OutputColorTransform, PipelineId, PixelSnapping};
+use api::{PropertyBinding, TransformStyle};
 use api::{TileOffset, WorldToLayerTransform, YuvColorSpace, YuvFormat, LayerVector2D};
 
 // Special sentinel value recognized by the shader. It is considered to be
 // a dummy task that doesn't mask out anything.
 const OPAQUE_TASK_INDEX: RenderTaskIndex = RenderTaskIndex(i32::MAX as usize);
 
+// The smallest opaque region worth a depth pre-pass rect, per side in
+// device pixels. Below this the depth writes cost more than the alpha
+// fragments they could reject.
+const MIN_Z_PREPASS_SIZE: i32 = 128;
+
+// How many render items each parallel batch building job covers.
+// Batches never merge across a job boundary, so smaller slices mean
+// more batches to draw, while larger slices mean less parallelism.
+const ITEMS_PER_BATCH_JOB: usize = 64;
+
 
 pub type DisplayListMap = FastHashMap<PipelineId, BuiltDisplayList>;
 
@@ -45,7 +57,14 @@ impl AlphaBatchHelpers for PrimitiveStore {
             PrimitiveKind::TextRun => {
                 let text_run_cpu = &self.cpu_text_runs[metadata.cpu_prim_index.0];
                 match text_run_cpu.normal_render_mode {
-                    FontRenderMode::Subpixel => BlendMode::Subpixel(text_run_cpu.color),
+                    // Subpixel text blends against a constant color, so
+                    // the color has to be batch state. Quantizing it to
+                    // the 8 bits the display resolves keeps runs with
+                    // equivalent colors in one batch.
+                    FontRenderMode::Subpixel => BlendMode::Subpixel(text_run_cpu.color.into()),
+                    // The alpha path reads the run color per instance
+                    // from the gpu cache (vColor), so differently
+                    // colored runs batch together here.
                     FontRenderMode::Alpha | FontRenderMode::Mono => BlendMode::Alpha,
                 }
             }
@@ -171,6 +190,11 @@ struct AlphaBatchTask {
 pub struct BatchList {
     pub alpha_batches: Vec<PrimitiveBatch>,
     pub opaque_batches: Vec<PrimitiveBatch>,
+    /// Depth-only rects laid down before the batches are drawn, so the
+    /// depth test can reject alpha fragments hidden behind the opaque
+    /// parts of blended primitives. Only filled when the depth pre-pass
+    /// is enabled.
+    pub z_prepass_items: Vec<ZPrepassItem>,
 }
 
 impl BatchList {
@@ -178,9 +202,20 @@ impl BatchList {
         BatchList {
             alpha_batches: Vec::new(),
             opaque_batches: Vec::new(),
+            z_prepass_items: Vec::new(),
         }
     }
 
+    /// Appends the batches of a list that was built from items that
+    /// come after every item in this one. Batches are never merged
+    /// across the boundary; finalize() recovers adjacent batches that
+    /// ended up with identical keys.
+    fn append(&mut self, other: BatchList) {
+        self.alpha_batches.extend(other.alpha_batches);
+        self.opaque_batches.extend(other.opaque_batches);
+        self.z_prepass_items.extend(other.z_prepass_items);
+    }
+
     fn with_suitable_batch<F>(&mut self,
                               key: &AlphaBatchKey,
                               item_bounding_rect: &DeviceIntRect,
@@ -196,7 +231,11 @@ impl BatchList {
             BlendMode::None => {
                 (&mut self.opaque_batches, false)
             }
-            BlendMode::Alpha | BlendMode::PremultipliedAlpha | BlendMode::Subpixel(..) => {
+            BlendMode::Alpha |
+            BlendMode::PremultipliedAlpha |
+            BlendMode::Multiply |
+            BlendMode::Screen |
+            BlendMode::Subpixel(..) => {
                 (&mut self.alpha_batches, true)
             }
         };
@@ -236,11 +275,21 @@ impl BatchList {
 
         let batch = &mut batches[selected_batch_index.unwrap()];
         batch.item_rects.push(*item_bounding_rect);
+        batch.screen_bounds = batch.screen_bounds.union(item_bounding_rect);
 
         batch
     }
 
     fn finalize(&mut self) {
+        // The opaque batches are drawn with the depth test enabled, so
+        // the z-buffer guards their ordering and they can be submitted
+        // in any order. Sort them so that batches binding the same
+        // program and textures end up adjacent; the sort is stable,
+        // which keeps the front-to-back order within each group.
+        self.opaque_batches.sort_by(|a, b| {
+            batch_state_sort_key(&a.key).cmp(&batch_state_sort_key(&b.key))
+        });
+
         // Reverse the instance arrays in the opaque batches
         // to get maximum z-buffer efficiency by drawing
         // front-to-back.
@@ -250,6 +299,30 @@ impl BatchList {
         for batch in &mut self.opaque_batches {
             batch.instances.reverse();
         }
+
+        // The alpha batches must keep their back-to-front order, but
+        // adjacent batches that ended up with identical keys can be
+        // drawn as one. These occur because get_suitable_batch only
+        // looks back a few batches before starting a new one.
+        let alpha_batches = mem::replace(&mut self.alpha_batches, Vec::new());
+        for batch in alpha_batches {
+            // Composites stay in their own batch, as in
+            // get_suitable_batch: the result of one can affect the
+            // input of the next.
+            let merge = batch.key.kind != AlphaBatchKind::Composite &&
+                        match self.alpha_batches.last() {
+                            Some(last) => last.key.is_identical_to(&batch.key),
+                            None => false,
+                        };
+            if merge {
+                let last = self.alpha_batches.last_mut().unwrap();
+                last.screen_bounds = last.screen_bounds.union(&batch.screen_bounds);
+                last.instances.extend(batch.instances);
+                last.item_rects.extend(batch.item_rects);
+            } else {
+                self.alpha_batches.push(batch);
+            }
+        }
     }
 }
 
@@ -259,15 +332,129 @@ pub struct AlphaBatcher {
     tasks: Vec<AlphaBatchTask>,
 }
 
+/// Texture bindings and gpu cache addresses for a render item that can
+/// only be gathered with mutable access to the gpu cache: external
+/// images allocate deferred per-frame blocks, WebGL contexts push
+/// their UV rect, and glyph lookups walk the glyph cache. They are
+/// resolved sequentially before batch construction, so that building
+/// the batch lists needs only shared data and can run on the worker
+/// threads.
+enum ResolvedTextures {
+    None,
+    Color(SourceTexture, i32),
+    Yuv(BatchTextures, [i32; 3]),
+    TextRun(SourceTexture, Vec<(i32, i32)>),
+}
+
 impl AlphaRenderItem {
+    fn resolve_textures(&self,
+                        ctx: &RenderTargetContext,
+                        gpu_cache: &mut GpuCache,
+                        deferred_resolves: &mut Vec<DeferredResolve>)
+                        -> ResolvedTextures {
+        let prim_index = match *self {
+            AlphaRenderItem::Primitive(_, prim_index, _) => prim_index,
+            _ => return ResolvedTextures::None,
+        };
+        let prim_metadata = ctx.prim_store.get_metadata(prim_index);
+
+        match prim_metadata.prim_kind {
+            PrimitiveKind::Image => {
+                let image_cpu = &ctx.prim_store.cpu_images[prim_metadata.cpu_prim_index.0];
+
+                let (color_texture_id, uv_address) = match image_cpu.kind {
+                    ImagePrimitiveKind::Image(image_key, image_rendering, tile_offset, _) => {
+                        resolve_image(image_key,
+                                      image_rendering,
+                                      tile_offset,
+                                      ctx.resource_cache,
+                                      gpu_cache,
+                                      deferred_resolves)
+                    }
+                    ImagePrimitiveKind::WebGL(context_id) => {
+                        let webgl_texture = ctx.resource_cache.get_webgl_texture(&context_id);
+                        // GL contexts render with the origin at the bottom
+                        // left, so the surface is usually sampled flipped.
+                        let uv_rect = if webgl_texture.flip_y {
+                            [ 0.0,
+                              webgl_texture.size.height as f32,
+                              webgl_texture.size.width as f32,
+                              0.0]
+                        } else {
+                            [ 0.0,
+                              0.0,
+                              webgl_texture.size.width as f32,
+                              webgl_texture.size.height as f32]
+                        };
+                        let cache_handle = gpu_cache.push_per_frame_blocks(&[uv_rect.into()]);
+                        (webgl_texture.id, cache_handle)
+                    }
+                    ImagePrimitiveKind::Path(ref shape, cache_size) => {
+                        let cache_item = ctx.resource_cache.get_cached_path(shape, cache_size);
+                        (cache_item.texture_id, cache_item.uv_rect_handle)
+                    }
+                };
+
+                ResolvedTextures::Color(color_texture_id, uv_address.as_int(gpu_cache))
+            }
+            PrimitiveKind::YuvImage => {
+                let mut textures = BatchTextures::no_texture();
+                let mut uv_rect_addresses = [0; 3];
+                let image_yuv_cpu = &ctx.prim_store.cpu_yuv_images[prim_metadata.cpu_prim_index.0];
+
+                //yuv channel
+                let channel_count = image_yuv_cpu.format.get_plane_num();
+                debug_assert!(channel_count <= 3);
+                for channel in 0..channel_count {
+                    let image_key = image_yuv_cpu.yuv_key[channel];
+
+                    let (texture, address) = resolve_image(image_key,
+                                                           image_yuv_cpu.image_rendering,
+                                                           None,
+                                                           ctx.resource_cache,
+                                                           gpu_cache,
+                                                           deferred_resolves);
+                    textures.colors[channel] = texture;
+                    uv_rect_addresses[channel] = address.as_int(gpu_cache);
+                }
+
+                ResolvedTextures::Yuv(textures, uv_rect_addresses)
+            }
+            PrimitiveKind::TextRun => {
+                let text_cpu = &ctx.prim_store.cpu_text_runs[prim_metadata.cpu_prim_index.0];
+                let font_size_dp = text_cpu.logical_font_size.scale_by(ctx.device_pixel_ratio);
+
+                // TODO(gw): avoid / recycle this allocation in the future.
+                let mut glyphs = Vec::new();
+
+                let font = FontInstanceKey::new(text_cpu.font_key,
+                                                font_size_dp,
+                                                text_cpu.color,
+                                                text_cpu.normal_render_mode,
+                                                text_cpu.glyph_options,
+                                                text_cpu.subpx_dir);
+
+                let texture_id = ctx.resource_cache.get_glyphs(font,
+                                                               &text_cpu.glyph_keys,
+                                                               |index, handle| {
+                    let uv_address = handle.as_int(gpu_cache);
+                    glyphs.push((index as i32, uv_address));
+                });
+
+                ResolvedTextures::TextRun(texture_id, glyphs)
+            }
+            _ => ResolvedTextures::None,
+        }
+    }
+
     fn add_to_batch(&self,
                     batch_list: &mut BatchList,
-                    ctx: &RenderTargetContext,
-                    gpu_cache: &mut GpuCache,
+                    ctx: &BatchContext,
+                    gpu_cache: &GpuCache,
+                    resolved_textures: &ResolvedTextures,
                     render_tasks: &RenderTaskCollection,
                     child_pass_index: RenderPassIndex,
-                    task_index: RenderTaskIndex,
-                    deferred_resolves: &mut Vec<DeferredResolve>) {
+                    task_index: RenderTaskIndex) {
         match *self {
             AlphaRenderItem::Blend(stacking_context_index, src_id, filter, z) => {
                 let stacking_context = &ctx.stacking_context_store[stacking_context_index.0];
@@ -312,10 +499,14 @@ impl AlphaRenderItem {
                                              BatchTextures::no_texture());
                 let batch = batch_list.get_suitable_batch(&key, &stacking_context.screen_bounds);
 
+                let opacity = match composite_op {
+                    HardwareCompositeOp::PremultipliedAlphaWithOpacity(opacity) => opacity as i32,
+                    _ => 65535,
+                };
                 let instance = CompositePrimitiveInstance::new(task_index,
                                                                src_task_index,
                                                                RenderTaskIndex(0),
-                                                               0,
+                                                               opacity,
                                                                0,
                                                                z);
 
@@ -354,6 +545,13 @@ impl AlphaRenderItem {
                     },
                     None => (TransformedRectKind::AxisAligned, PackedLayerIndex(0)),
                 };
+                // Primitives that must not snap take the complex transform
+                // path, which interpolates at subpixel precision and is
+                // valid for any transform.
+                let transform_kind = match prim_metadata.pixel_snapping {
+                    PixelSnapping::Never => TransformedRectKind::Complex,
+                    PixelSnapping::Auto | PixelSnapping::Always => transform_kind,
+                };
                 let needs_clipping = prim_metadata.needs_clipping();
                 let mut flags = AlphaBatchKeyFlags::empty();
                 if needs_clipping {
@@ -429,26 +627,35 @@ impl AlphaRenderItem {
                         batch.add_instance(base_instance.build(0, 0, 0));
                     }
                     PrimitiveKind::Image => {
-                        let image_cpu = &ctx.prim_store.cpu_images[prim_metadata.cpu_prim_index.0];
-
-                        let (color_texture_id, uv_address) = match image_cpu.kind {
-                            ImagePrimitiveKind::Image(image_key, image_rendering, tile_offset, _) => {
-                                resolve_image(image_key,
-                                              image_rendering,
-                                              tile_offset,
-                                              ctx.resource_cache,
-                                              gpu_cache,
-                                              deferred_resolves)
+                        // An opaque image that is only blended because of
+                        // its clip mask is still fully opaque inside the
+                        // mask's inner rect. When that region is large,
+                        // record a depth-only pre-pass rect there so the
+                        // depth test can reject alpha fragments hidden
+                        // behind it.
+                        if ctx.enable_depth_prepass &&
+                           prim_metadata.opacity.is_opaque &&
+                           transform_kind == TransformedRectKind::AxisAligned {
+                            let inner_rect = prim_metadata.clip_task
+                                                          .as_ref()
+                                                          .and_then(|task| task.inner_clip_rect())
+                                                          .and_then(|rect| rect.intersection(item_bounding_rect));
+                            if let Some(opaque_rect) = inner_rect {
+                                if opaque_rect.size.width >= MIN_Z_PREPASS_SIZE &&
+                                   opaque_rect.size.height >= MIN_Z_PREPASS_SIZE {
+                                    batch_list.z_prepass_items.push(ZPrepassItem {
+                                        instance: base_instance.build(0, 0, 0),
+                                        bounds: opaque_rect,
+                                    });
+                                }
                             }
-                            ImagePrimitiveKind::WebGL(context_id) => {
-                                let webgl_texture = ctx.resource_cache.get_webgl_texture(&context_id);
-                                let uv_rect = [ 0.0,
-                                                webgl_texture.size.height as f32,
-                                                webgl_texture.size.width as f32,
-                                                0.0];
-                                let cache_handle = gpu_cache.push_per_frame_blocks(&[uv_rect.into()]);
-                                (webgl_texture.id, cache_handle)
+                        }
+
+                        let (color_texture_id, uv_address) = match *resolved_textures {
+                            ResolvedTextures::Color(texture_id, uv_address) => {
+                                (texture_id, uv_address)
                             }
+                            _ => panic!("BUG: image primitive without resolved textures"),
                         };
 
                         let batch_kind = match color_texture_id {
@@ -475,28 +682,15 @@ impl AlphaRenderItem {
 
                         let key = AlphaBatchKey::new(batch_kind, flags, blend_mode, textures);
                         let batch = batch_list.get_suitable_batch(&key, item_bounding_rect);
-                        batch.add_instance(base_instance.build(uv_address.as_int(gpu_cache), 0, 0));
+                        batch.add_instance(base_instance.build(uv_address, 0, 0));
                     }
                     PrimitiveKind::TextRun => {
-                        let text_cpu = &ctx.prim_store.cpu_text_runs[prim_metadata.cpu_prim_index.0];
-                        let font_size_dp = text_cpu.logical_font_size.scale_by(ctx.device_pixel_ratio);
-
-                        // TODO(gw): avoid / recycle this allocation in the future.
-                        let mut instances = Vec::new();
-
-                        let font = FontInstanceKey::new(text_cpu.font_key,
-                                                        font_size_dp,
-                                                        text_cpu.color,
-                                                        text_cpu.normal_render_mode,
-                                                        text_cpu.glyph_options,
-                                                        text_cpu.subpx_dir);
-
-                        let texture_id = ctx.resource_cache.get_glyphs(font,
-                                                                       &text_cpu.glyph_keys,
-                                                                       |index, handle| {
-                            let uv_address = handle.as_int(gpu_cache);
-                            instances.push(base_instance.build(index as i32, uv_address, 0));
-                        });
+                        let (texture_id, glyphs) = match *resolved_textures {
+                            ResolvedTextures::TextRun(texture_id, ref glyphs) => {
+                                (texture_id, glyphs)
+                            }
+                            _ => panic!("BUG: text run without resolved textures"),
+                        };
 
                         if texture_id != SourceTexture::Invalid {
                             let textures = BatchTextures {
@@ -506,7 +700,9 @@ impl AlphaRenderItem {
                             let key = AlphaBatchKey::new(AlphaBatchKind::TextRun, flags, blend_mode, textures);
                             let batch = batch_list.get_suitable_batch(&key, item_bounding_rect);
 
-                            batch.add_instances(&instances);
+                            for &(glyph_index, uv_address) in glyphs {
+                                batch.add_instance(base_instance.build(glyph_index, uv_address, 0));
+                            }
                         }
                     }
                     PrimitiveKind::TextShadow => {
@@ -526,35 +722,32 @@ impl AlphaRenderItem {
                         }
                     }
                     PrimitiveKind::AngleGradient => {
+                        let gradient_cpu = &ctx.prim_store.cpu_gradients[prim_metadata.cpu_prim_index.0];
+                        let gradient_address = ctx.prim_store
+                                                  .gradient_data_address(gradient_cpu.gradient_data_key,
+                                                                         gpu_cache);
                         let key = AlphaBatchKey::new(AlphaBatchKind::AngleGradient, flags, blend_mode, no_textures);
                         let batch = batch_list.get_suitable_batch(&key, item_bounding_rect);
-                        batch.add_instance(base_instance.build(0, 0, 0));
+                        batch.add_instance(base_instance.build(gradient_address, 0, 0));
                     }
                     PrimitiveKind::RadialGradient => {
+                        let gradient_cpu = &ctx.prim_store.cpu_radial_gradients[prim_metadata.cpu_prim_index.0];
+                        let gradient_address = ctx.prim_store
+                                                  .gradient_data_address(gradient_cpu.gradient_data_key,
+                                                                         gpu_cache);
                         let key = AlphaBatchKey::new(AlphaBatchKind::RadialGradient, flags, blend_mode, no_textures);
                         let batch = batch_list.get_suitable_batch(&key, item_bounding_rect);
-                        batch.add_instance(base_instance.build(0, 0, 0));
+                        batch.add_instance(base_instance.build(gradient_address, 0, 0));
                     }
                     PrimitiveKind::YuvImage => {
-                        let mut textures = BatchTextures::no_texture();
-                        let mut uv_rect_addresses = [0; 3];
                         let image_yuv_cpu = &ctx.prim_store.cpu_yuv_images[prim_metadata.cpu_prim_index.0];
 
-                        //yuv channel
-                        let channel_count = image_yuv_cpu.format.get_plane_num();
-                        debug_assert!(channel_count <= 3);
-                        for channel in 0..channel_count {
-                            let image_key = image_yuv_cpu.yuv_key[channel];
-
-                            let (texture, address) = resolve_image(image_key,
-                                                                   image_yuv_cpu.image_rendering,
-                                                                   None,
-                                                                   ctx.resource_cache,
-                                                                   gpu_cache,
-                                                                   deferred_resolves);
-                            textures.colors[channel] = texture;
-                            uv_rect_addresses[channel] = address.as_int(gpu_cache);
-                        }
+                        let (textures, uv_rect_addresses) = match *resolved_textures {
+                            ResolvedTextures::Yuv(textures, uv_rect_addresses) => {
+                                (textures, uv_rect_addresses)
+                            }
+                            _ => panic!("BUG: yuv image without resolved textures"),
+                        };
 
                         let get_buffer_kind = |texture: SourceTexture| {
                             match texture {
@@ -631,6 +824,29 @@ impl AlphaRenderItem {
     }
 }
 
+fn build_batch_list(items: &[AlphaRenderItem],
+                    resolved_textures: &[ResolvedTextures],
+                    ctx: &BatchContext,
+                    gpu_cache: &GpuCache,
+                    render_tasks: &RenderTaskCollection,
+                    child_pass_index: RenderPassIndex,
+                    task_index: RenderTaskIndex)
+                    -> BatchList {
+    let mut batch_list = BatchList::new();
+
+    for (item, resolved) in items.iter().zip(resolved_textures) {
+        item.add_to_batch(&mut batch_list,
+                          ctx,
+                          gpu_cache,
+                          resolved,
+                          render_tasks,
+                          child_pass_index,
+                          task_index);
+    }
+
+    batch_list
+}
+
 impl AlphaBatcher {
     fn new() -> AlphaBatcher {
         AlphaBatcher {
@@ -643,26 +859,107 @@ impl AlphaBatcher {
         self.tasks.push(task);
     }
 
+    /// Tallies the batch instances each pipeline's primitives contributed
+    /// to this batcher, for the per-pipeline frame profiles.
+    pub fn accumulate_pipeline_profiles(&self,
+                                        clip_scroll_group_store: &[ClipScrollGroup],
+                                        pipeline_profiles: &mut FastHashMap<PipelineId, PipelineProfile>) {
+        for task in &self.tasks {
+            for item in &task.items {
+                let group_index = match *item {
+                    AlphaRenderItem::Primitive(Some(group_index), ..) => group_index,
+                    _ => continue,
+                };
+                let pipeline_id = clip_scroll_group_store[group_index.0]
+                                      .scroll_node_id
+                                      .pipeline_id();
+                pipeline_profiles.entry(pipeline_id)
+                                 .or_insert_with(|| PipelineProfile::new(pipeline_id))
+                                 .batch_instances += 1;
+            }
+        }
+    }
+
     fn build(&mut self,
              ctx: &RenderTargetContext,
              gpu_cache: &mut GpuCache,
              render_tasks: &RenderTaskCollection,
              child_pass_index: RenderPassIndex,
              deferred_resolves: &mut Vec<DeferredResolve>) {
+        // Resolve everything that needs mutable access to the gpu
+        // cache or the glyph cache up front: image UV addresses,
+        // deferred blocks for external images and rasterized glyphs.
+        // One entry per item, in submission order.
+        let mut resolved_textures = Vec::with_capacity(self.tasks.len());
         for task in &self.tasks {
+            let mut task_textures = Vec::with_capacity(task.items.len());
+            for item in &task.items {
+                task_textures.push(item.resolve_textures(ctx, gpu_cache, deferred_resolves));
+            }
+            resolved_textures.push(task_textures);
+        }
+
+        // From here on the gpu cache is only read.
+        let gpu_cache = &*gpu_cache;
+
+        let batch_ctx = BatchContext {
+            stacking_context_store: ctx.stacking_context_store,
+            clip_scroll_group_store: ctx.clip_scroll_group_store,
+            prim_store: ctx.prim_store,
+            enable_depth_prepass: ctx.enable_depth_prepass,
+        };
+
+        // Split the item list of each task into contiguous slices and
+        // build a batch list per slice. The slices are independent of
+        // each other, so they can be handed to the worker pool, and
+        // appending the per-slice lists in submission order keeps the
+        // output deterministic.
+        let mut jobs = Vec::new();
+        for (task, task_textures) in self.tasks.iter().zip(&resolved_textures) {
             let task_index = render_tasks.get_static_task_index(&task.task_id);
 
-            for item in &task.items {
-                item.add_to_batch(&mut self.batch_list,
-                                  ctx,
-                                  gpu_cache,
-                                  render_tasks,
-                                  child_pass_index,
-                                  task_index,
-                                  deferred_resolves);
+            let mut start = 0;
+            while start < task.items.len() {
+                let end = cmp::min(start + ITEMS_PER_BATCH_JOB, task.items.len());
+                jobs.push((task_index,
+                           &task.items[start .. end],
+                           &task_textures[start .. end]));
+                start = end;
             }
         }
 
+        let batch_lists: Vec<BatchList> = if jobs.len() > 1 {
+            ctx.resource_cache.workers().install(|| {
+                jobs.par_iter()
+                    .map(|&(task_index, items, resolved)| {
+                        build_batch_list(items,
+                                         resolved,
+                                         &batch_ctx,
+                                         gpu_cache,
+                                         render_tasks,
+                                         child_pass_index,
+                                         task_index)
+                    })
+                    .collect()
+            })
+        } else {
+            jobs.iter()
+                .map(|&(task_index, items, resolved)| {
+                    build_batch_list(items,
+                                     resolved,
+                                     &batch_ctx,
+                                     gpu_cache,
+                                     render_tasks,
+                                     child_pass_index,
+                                     task_index)
+                })
+                .collect()
+        };
+
+        for batch_list in batch_lists {
+            self.batch_list.append(batch_list);
+        }
+
         self.batch_list.finalize();
     }
 
@@ -793,6 +1090,18 @@ pub struct RenderTargetContext<'a> {
     pub clip_scroll_group_store: &'a [ClipScrollGroup],
     pub prim_store: &'a PrimitiveStore,
     pub resource_cache: &'a ResourceCache,
+    pub enable_depth_prepass: bool,
+}
+
+/// The subset of `RenderTargetContext` that batch construction needs
+/// once the textures have been resolved. Everything here is plain
+/// shared data, so the batch building jobs can run on the worker
+/// threads.
+struct BatchContext<'a> {
+    stacking_context_store: &'a [StackingContext],
+    clip_scroll_group_store: &'a [ClipScrollGroup],
+    prim_store: &'a PrimitiveStore,
+    enable_depth_prepass: bool,
 }
 
 struct TextureAllocator {
@@ -862,6 +1171,10 @@ pub enum RenderTargetKind {
 pub struct RenderTargetList<T> {
     target_size: DeviceUintSize,
     pub targets: Vec<T>,
+    /// Number of tasks assigned to each target. The tasks themselves are
+    /// consumed when the pass is built, so the counts are recorded here
+    /// for the render target debug view.
+    pub task_counts: Vec<usize>,
 }
 
 impl<T: RenderTarget> RenderTargetList<T> {
@@ -871,9 +1184,11 @@ impl<T: RenderTarget> RenderTargetList<T> {
             targets.push(T::new(target_size));
         }
 
+        let task_counts = vec![0; targets.len()];
         RenderTargetList {
             targets,
             target_size,
+            task_counts,
         }
     }
 
@@ -899,6 +1214,7 @@ impl<T: RenderTarget> RenderTargetList<T> {
                 gpu_cache: &GpuCache,
                 render_tasks: &mut RenderTaskCollection,
                 pass_index: RenderPassIndex) {
+        *self.task_counts.last_mut().unwrap() += 1;
         self.targets.last_mut().unwrap().add_task(task, ctx, gpu_cache, render_tasks, pass_index);
     }
 
@@ -914,6 +1230,7 @@ impl<T: RenderTarget> RenderTargetList<T> {
                 let origin = new_target.allocate(alloc_size)
                                        .expect(&format!("Each render task must allocate <= size of one target! ({:?})", alloc_size));
                 self.targets.push(new_target);
+                self.task_counts.push(0);
                 origin
             }
         };
@@ -1173,6 +1490,11 @@ pub struct RenderPass {
     pub alpha_targets: RenderTargetList<AlphaRenderTarget>,
     pub color_texture_id: Option<TextureId>,
     pub alpha_texture_id: Option<TextureId>,
+    /// One 2D texture per target, used instead of the array textures
+    /// above on devices that can't render to texture array layers. See
+    /// `Capabilities::avoid_texture_arrays`.
+    pub color_texture_ids: Vec<TextureId>,
+    pub alpha_texture_ids: Vec<TextureId>,
 }
 
 impl RenderPass {
@@ -1185,6 +1507,8 @@ impl RenderPass {
             tasks: vec![],
             color_texture_id: None,
             alpha_texture_id: None,
+            color_texture_ids: vec![],
+            alpha_texture_ids: vec![],
         }
     }
 
@@ -1228,6 +1552,35 @@ impl RenderPass {
         }
     }
 
+    /// Total pixels of intermediate target memory this pass allocates.
+    /// The framebuffer pass draws to the window, so only its alpha
+    /// targets count.
+    pub fn intermediate_target_pixels(&self) -> usize {
+        let color_size = self.color_targets.target_size;
+        let alpha_size = self.alpha_targets.target_size;
+        let color_count = if self.is_framebuffer {
+            0
+        } else {
+            self.color_targets.target_count()
+        };
+        color_count * (color_size.width * color_size.height) as usize +
+            self.alpha_targets.target_count() * (alpha_size.width * alpha_size.height) as usize
+    }
+
+    /// Like `intermediate_target_pixels`, but in bytes: color targets are
+    /// BGRA8 (4 bytes per pixel) and alpha targets A8 (1 byte per pixel).
+    pub fn intermediate_target_bytes(&self) -> usize {
+        let color_size = self.color_targets.target_size;
+        let alpha_size = self.alpha_targets.target_size;
+        let color_count = if self.is_framebuffer {
+            0
+        } else {
+            self.color_targets.target_count()
+        };
+        color_count * (color_size.width * color_size.height) as usize * 4 +
+            self.alpha_targets.target_count() * (alpha_size.width * alpha_size.height) as usize
+    }
+
     pub fn build(&mut self,
                  ctx: &RenderTargetContext,
                  gpu_cache: &mut GpuCache,
@@ -1349,6 +1702,16 @@ impl AlphaBatchKey {
             textures_compatible(self.textures.colors[1], other.textures.colors[1]) &&
             textures_compatible(self.textures.colors[2], other.textures.colors[2])
     }
+
+    /// True if the two keys bind exactly the same GL state. Unlike
+    /// `is_compatible_with` this doesn't treat invalid textures as
+    /// wildcards, so batches with identical keys can be drawn as one.
+    pub fn is_identical_to(&self, other: &AlphaBatchKey) -> bool {
+        self.kind == other.kind &&
+            self.flags == other.flags &&
+            self.blend_mode == other.blend_mode &&
+            self.textures == other.textures
+    }
 }
 
 #[repr(C)]
@@ -1363,12 +1726,76 @@ fn textures_compatible(t1: SourceTexture, t2: SourceTexture) -> bool {
     t1 == SourceTexture::Invalid || t2 == SourceTexture::Invalid || t1 == t2
 }
 
+fn texture_sort_key(texture: &SourceTexture) -> u64 {
+    match *texture {
+        SourceTexture::Invalid => 0,
+        SourceTexture::TextureCache(CacheTextureId(index)) => {
+            (1 << 32) | index as u64
+        }
+        SourceTexture::External(ref data) => {
+            let ExternalImageId(id) = data.id;
+            (2 << 32) | id
+        }
+        SourceTexture::WebGL(id) => {
+            (3 << 32) | id as u64
+        }
+    }
+}
+
+/// Orders batch keys by the GL state they will bind, so that batches
+/// with equal state sort next to each other. This is purely a grouping
+/// heuristic: the renderer still binds state per batch, so keys that
+/// happen to collide merely end up adjacent.
+fn batch_state_sort_key(key: &AlphaBatchKey) -> (u32, u8, u8, [u64; 3]) {
+    let kind = match key.kind {
+        AlphaBatchKind::Composite => 0 << 24,
+        AlphaBatchKind::HardwareComposite => 1 << 24,
+        AlphaBatchKind::SplitComposite => 2 << 24,
+        AlphaBatchKind::Blend => 3 << 24,
+        AlphaBatchKind::Rectangle => 4 << 24,
+        AlphaBatchKind::TextRun => 5 << 24,
+        AlphaBatchKind::Image(buffer_kind) => {
+            (6 << 24) | (buffer_kind as u32)
+        }
+        AlphaBatchKind::YuvImage(buffer_kind, format, color_space) => {
+            (7 << 24) |
+                ((buffer_kind as u32) << 16) |
+                ((format as u32) << 8) |
+                (color_space as u32)
+        }
+        AlphaBatchKind::AlignedGradient => 8 << 24,
+        AlphaBatchKind::AngleGradient => 9 << 24,
+        AlphaBatchKind::RadialGradient => 10 << 24,
+        AlphaBatchKind::BoxShadow => 11 << 24,
+        AlphaBatchKind::CacheImage => 12 << 24,
+        AlphaBatchKind::BorderCorner => 13 << 24,
+        AlphaBatchKind::BorderEdge => 14 << 24,
+        AlphaBatchKind::Line => 15 << 24,
+    };
+
+    let blend_mode = match key.blend_mode {
+        BlendMode::None => 0,
+        BlendMode::Alpha => 1,
+        BlendMode::PremultipliedAlpha => 2,
+        BlendMode::Subpixel(..) => 3,
+        BlendMode::Multiply => 4,
+        BlendMode::Screen => 5,
+    };
+
+    (kind,
+     key.flags.bits(),
+     blend_mode,
+     [texture_sort_key(&key.textures.colors[0]),
+      texture_sort_key(&key.textures.colors[1]),
+      texture_sort_key(&key.textures.colors[2])])
+}
+
 // All Packed Primitives below must be 16 byte aligned.
 #[derive(Debug)]
 pub struct BlurCommand {
-    task_id: i32,
-    src_task_id: i32,
-    blur_direction: i32,
+    pub task_id: i32,
+    pub src_task_id: i32,
+    pub blur_direction: i32,
 }
 
 /// A clipping primitive drawn into the clipping mask.
@@ -1376,11 +1803,11 @@ pub struct BlurCommand {
 /// way `address` is treated.
 #[derive(Clone, Copy, Debug)]
 pub struct CacheClipInstance {
-    task_id: i32,
-    layer_index: i32,
-    address: i32,
-    segment: i32,
-    resource_address: i32,
+    pub task_id: i32,
+    pub layer_index: i32,
+    pub address: i32,
+    pub segment: i32,
+    pub resource_address: i32,
 }
 
 // 32 bytes per instance should be enough for anyone!
@@ -1491,11 +1918,23 @@ impl<'a> From<&'a PrimitiveInstance> for CompositePrimitiveInstance {
     }
 }
 
+/// A single depth-only pre-pass rect: the instance is drawn with
+/// ps_rectangle with color writes disabled, scissored to `bounds`.
+#[derive(Debug)]
+pub struct ZPrepassItem {
+    pub instance: PrimitiveInstance,
+    /// The screen rect known to be fully opaque.
+    pub bounds: DeviceIntRect,
+}
+
 #[derive(Debug)]
 pub struct PrimitiveBatch {
     pub key: AlphaBatchKey,
     pub instances: Vec<PrimitiveInstance>,
     pub item_rects: Vec<DeviceIntRect>,
+    /// The union of the item rects: the only screen area the batch's
+    /// draw can touch. The renderer scissors each batch to this.
+    pub screen_bounds: DeviceIntRect,
 }
 
 impl PrimitiveBatch {
@@ -1504,6 +1943,7 @@ impl PrimitiveBatch {
             key,
             instances: Vec::new(),
             item_rects: Vec::new(),
+            screen_bounds: DeviceIntRect::zero(),
         }
     }
 
@@ -1573,9 +2013,14 @@ impl StackingContext {
                is_page_root: bool,
                reference_frame_id: ClipId,
                transform_style: TransformStyle,
-               composite_ops: CompositeOps)
+               composite_ops: CompositeOps,
+               will_change: bool)
                -> StackingContext {
         let isolation = match transform_style {
+            // A context that is likely to animate is isolated into its own
+            // render target, so that animating it only recomposites the
+            // cached content instead of redrawing it.
+            TransformStyle::Flat if will_change => ContextIsolation::Full,
             TransformStyle::Flat => ContextIsolation::None,
             TransformStyle::Preserve3D => ContextIsolation::Items,
         };
@@ -1701,6 +2146,11 @@ pub struct Frame {
     pub passes: Vec<RenderPass>,
     pub profile_counters: FrameProfileCounters,
 
+    // When set, the renderer draws the final pass to an intermediate
+    // target and composites it to the framebuffer through this
+    // transform, so the output matches the display's color profile.
+    pub output_color_transform: Option<OutputColorTransform>,
+
     pub layer_texture_data: Vec<PackedLayer>,
     pub render_task_data: Vec<RenderTaskData>,
 
@@ -1713,6 +2163,23 @@ pub struct Frame {
     // will use a callback to resolve these and
     // patch the data structures.
     pub deferred_resolves: Vec<DeferredResolve>,
+
+    // Producer fences of WebGL canvas surfaces. The render thread waits
+    // on these before issuing any draw that samples the corresponding
+    // texture, so compositing never observes a half-rendered canvas.
+    pub webgl_acquires: Vec<WebGLAcquire>,
+
+    // The screen footprint of every visible tagged primitive, consumed
+    // by the renderer's paint flashing debug view.
+    pub painted_tags: Vec<PaintedTag>,
+
+    // Frame building statistics broken down by pipeline. See
+    // `PipelineProfile`.
+    pub pipeline_profiles: Vec<PipelineProfile>,
+
+    // A Graphviz DOT dump of the render task dependency graph, recorded
+    // when frame builder debugging is enabled.
+    pub render_task_graph_dump: Option<String>,
 }
 
 fn resolve_image(image_key: ImageKey,
@@ -0,0 +1,158 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A small database of GPU driver workarounds.
+//!
+//! The GL strings are the only portable way to identify the driver, so
+//! this module parses GL_RENDERER and GL_VERSION into a structured
+//! `GpuInfo` and derives one boolean per known driver bug from it. The
+//! device stores the booleans in its `Capabilities`, where the renderer
+//! picks them up to select safe code paths; embedders get the parsed
+//! info back through `Renderer::get_graphics_api_info`.
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum GpuVendor {
+    Adreno,
+    Amd,
+    Intel,
+    Mali,
+    Nvidia,
+    PowerVr,
+    Unknown,
+}
+
+#[derive(Clone, Debug)]
+pub struct GpuInfo {
+    pub vendor: GpuVendor,
+    /// The model number parsed from the renderer string, e.g. 330 for
+    /// "Adreno (TM) 330" or 760 for "Mali-T760". Zero when the renderer
+    /// string contains no number.
+    pub model: u32,
+    /// True for OpenGL ES contexts.
+    pub is_gles: bool,
+    pub version_major: u32,
+    pub version_minor: u32,
+    /// The unparsed GL strings, for logging and about:support.
+    pub renderer: String,
+    pub version: String,
+}
+
+impl GpuInfo {
+    pub fn new(renderer: &str, version: &str) -> GpuInfo {
+        let vendor = if renderer.contains("Adreno") {
+            GpuVendor::Adreno
+        } else if renderer.contains("Mali") {
+            GpuVendor::Mali
+        } else if renderer.contains("PowerVR") {
+            GpuVendor::PowerVr
+        } else if renderer.contains("NVIDIA") ||
+                  renderer.contains("GeForce") ||
+                  renderer.contains("Tegra") {
+            GpuVendor::Nvidia
+        } else if renderer.contains("Intel") {
+            GpuVendor::Intel
+        } else if renderer.contains("AMD") || renderer.contains("Radeon") {
+            GpuVendor::Amd
+        } else {
+            GpuVendor::Unknown
+        };
+
+        // Version strings look like "4.5.0 NVIDIA 381.22" on desktop and
+        // "OpenGL ES 3.1 V@145.0" on mobile.
+        let is_gles = version.starts_with("OpenGL ES");
+        let digits = version.trim_left_matches(|c: char| !c.is_digit(10));
+        let mut parts = digits.split(|c: char| !c.is_digit(10));
+        let version_major = parts.next().and_then(|s| s.parse().ok()).unwrap_or(0);
+        let version_minor = parts.next().and_then(|s| s.parse().ok()).unwrap_or(0);
+
+        GpuInfo {
+            vendor,
+            model: first_number(renderer),
+            is_gles,
+            version_major,
+            version_minor,
+            renderer: renderer.to_owned(),
+            version: version.to_owned(),
+        }
+    }
+
+    /// Compute shaders arrived in GL 4.3 and GLES 3.1.
+    pub fn supports_compute_shaders(&self) -> bool {
+        if self.is_gles {
+            self.version_major > 3 ||
+                (self.version_major == 3 && self.version_minor >= 1)
+        } else {
+            self.version_major > 4 ||
+                (self.version_major == 4 && self.version_minor >= 3)
+        }
+    }
+
+    /// Multi-draw indirect arrived in GL 4.3. The GLES extension for it
+    /// is not in our bindings, so GLES always takes the fallback.
+    pub fn supports_multi_draw_indirect(&self) -> bool {
+        !self.is_gles &&
+            (self.version_major > 4 ||
+                (self.version_major == 4 && self.version_minor >= 3))
+    }
+
+    /// Framebuffer invalidation arrived in GL 4.3 and GLES 3.0. Older
+    /// contexts may still expose it through an extension, which the
+    /// device checks for separately.
+    pub fn supports_framebuffer_invalidation(&self) -> bool {
+        if self.is_gles {
+            self.version_major >= 3
+        } else {
+            self.version_major > 4 ||
+                (self.version_major == 4 && self.version_minor >= 3)
+        }
+    }
+
+    /// PBO texture uploads stall, or land as corrupt texels, on Adreno
+    /// 3xx/4xx drivers. Upload from client memory there instead.
+    pub fn avoid_pbo_uploads(&self) -> bool {
+        self.vendor == GpuVendor::Adreno && self.model < 500
+    }
+
+    /// glTexSubImage2D from client memory makes the driver copy the
+    /// texels synchronously, which shows up as long stalls on some
+    /// tiled GPUs. Staging the upload through an orphaned PBO lets the
+    /// copy happen asynchronously there. Mutually exclusive with
+    /// `avoid_pbo_uploads` by construction.
+    pub fn prefer_pbo_texture_uploads(&self) -> bool {
+        match self.vendor {
+            GpuVendor::Mali | GpuVendor::PowerVr => true,
+            GpuVendor::Adreno => !self.avoid_pbo_uploads(),
+            _ => false,
+        }
+    }
+
+    /// Rendering to texture array layers produces garbage on Mali-T6xx
+    /// and T7xx drivers. The renderer falls back to plain 2D textures
+    /// for its pass targets there.
+    pub fn avoid_texture_arrays(&self) -> bool {
+        self.vendor == GpuVendor::Mali && self.model < 800
+    }
+
+    /// Scissored clears fall off the fast clear path on Adreno, where a
+    /// full-target clear is nearly free.
+    pub fn avoid_scissored_clears(&self) -> bool {
+        self.vendor == GpuVendor::Adreno
+    }
+}
+
+/// Returns the first run of digits in the string as a number, e.g. 540
+/// for "Adreno (TM) 540".
+fn first_number(s: &str) -> u32 {
+    let mut value = 0;
+    let mut found = false;
+    for c in s.chars() {
+        if let Some(digit) = c.to_digit(10) {
+            value = value * 10 + digit;
+            found = true;
+        } else if found {
+            break;
+        }
+    }
+    value
+}
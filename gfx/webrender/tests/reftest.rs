@@ -0,0 +1,510 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A pixel-diff based reftest harness.
+//!
+//! `ReftestHarness` renders display lists headlessly and compares the
+//! results pixel by pixel, either against another display list or against a
+//! reference PNG. The comparison produces a structured `ReftestDiff` (max
+//! channel delta, differing pixel count and a per-channel diff image) so
+//! tests can assert exact equality or allow small antialiasing fuzz.
+//!
+//! The image diffing itself is pure and tested below without a GPU. Tests
+//! that actually render are marked `#[ignore]` since they need a working GL
+//! driver; run them with `cargo test -- --ignored` on a machine with one.
+
+extern crate gleam;
+extern crate glutin;
+extern crate png;
+extern crate webrender;
+
+use gleam::gl;
+use std::fs::File;
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use webrender::api::*;
+
+/// An RGBA8 image, stored row-major with the top row first (PNG order).
+pub struct ReftestImage {
+    pub size: DeviceUintSize,
+    pub pixels: Vec<u8>,
+}
+
+/// The result of comparing two images of the same size.
+pub struct ReftestDiff {
+    /// The largest absolute difference seen on any channel of any pixel.
+    pub max_difference: u8,
+    /// The number of pixels that differ on at least one channel.
+    pub num_differences: usize,
+    /// Per-channel absolute differences, as an image. Useful to dump when
+    /// investigating a failure: differing areas light up against black.
+    pub diff_image: ReftestImage,
+}
+
+impl ReftestDiff {
+    pub fn is_identical(&self) -> bool {
+        self.num_differences == 0
+    }
+}
+
+/// Compares two images of the same size, producing a structured diff.
+pub fn pixel_diff(test: &ReftestImage, reference: &ReftestImage) -> ReftestDiff {
+    assert_eq!(test.size, reference.size);
+    assert_eq!(test.pixels.len(), reference.pixels.len());
+
+    let mut max_difference = 0;
+    let mut num_differences = 0;
+    let mut diff_pixels = Vec::with_capacity(test.pixels.len());
+
+    for (test_pixel, ref_pixel) in test.pixels.chunks(4).zip(reference.pixels.chunks(4)) {
+        let mut pixel_differs = false;
+
+        for (&t, &r) in test_pixel.iter().zip(ref_pixel.iter()) {
+            let delta = if t > r { t - r } else { r - t };
+            if delta > 0 {
+                pixel_differs = true;
+            }
+            if delta > max_difference {
+                max_difference = delta;
+            }
+            diff_pixels.push(delta);
+        }
+
+        if pixel_differs {
+            num_differences += 1;
+        }
+    }
+
+    ReftestDiff {
+        max_difference,
+        num_differences,
+        diff_image: ReftestImage {
+            size: test.size,
+            pixels: diff_pixels,
+        },
+    }
+}
+
+/// Loads a reference PNG as RGBA8. RGB images get an opaque alpha channel
+/// added; other color types aren't supported.
+pub fn load_png(path: &Path) -> ReftestImage {
+    let decoder = png::Decoder::new(File::open(path).unwrap());
+    let (info, mut reader) = decoder.read_info().unwrap();
+    let mut data = vec![0; info.buffer_size()];
+    reader.next_frame(&mut data).unwrap();
+
+    assert_eq!(info.bit_depth, png::BitDepth::Eight);
+    let pixels = match info.color_type {
+        png::ColorType::RGBA => data,
+        png::ColorType::RGB => {
+            let mut rgba = Vec::with_capacity(data.len() / 3 * 4);
+            for rgb in data.chunks(3) {
+                rgba.extend_from_slice(rgb);
+                rgba.push(0xff);
+            }
+            rgba
+        }
+        color_type => panic!("Unsupported reference PNG color type {:?}", color_type),
+    };
+
+    ReftestImage {
+        size: DeviceUintSize::new(info.width, info.height),
+        pixels,
+    }
+}
+
+struct Notifier {
+    tx: Sender<()>,
+}
+
+impl RenderNotifier for Notifier {
+    fn new_frame_ready(&mut self) {
+        self.tx.send(()).unwrap();
+    }
+
+    fn new_scroll_frame_ready(&mut self, _composite_needed: bool) {
+    }
+}
+
+/// Owns a headless GL context and a renderer, and knows how to turn display
+/// lists into `ReftestImage`s.
+pub struct ReftestHarness {
+    window: glutin::HeadlessContext,
+    renderer: webrender::Renderer,
+    api: RenderApi,
+    document_id: DocumentId,
+    rx: Receiver<()>,
+    size: DeviceUintSize,
+    layout_size: LayoutSize,
+    epoch: Epoch,
+}
+
+impl ReftestHarness {
+    pub fn new(size: DeviceUintSize) -> ReftestHarness {
+        ReftestHarness::new_at_ratio(LayoutSize::new(size.width as f32, size.height as f32),
+                                     1.0)
+    }
+
+    /// Like `new`, but renders the given layout size at a device pixel
+    /// ratio, so a test can run across a matrix of fractional ratios.
+    pub fn new_at_ratio(layout_size: LayoutSize, device_pixel_ratio: f32) -> ReftestHarness {
+        let size = DeviceUintSize::new((layout_size.width * device_pixel_ratio).ceil() as u32,
+                                       (layout_size.height * device_pixel_ratio).ceil() as u32);
+        let window = glutin::HeadlessRendererBuilder::new(size.width, size.height)
+                     .with_gl(glutin::GlRequest::GlThenGles {
+                         opengl_version: (3, 2),
+                         opengles_version: (3, 0)
+                     })
+                     .build()
+                     .unwrap();
+
+        unsafe {
+            window.make_current().ok();
+        }
+
+        let gl = match gl::GlType::default() {
+            gl::GlType::Gl => unsafe { gl::GlFns::load_with(|symbol| window.get_proc_address(symbol) as *const _) },
+            gl::GlType::Gles => unsafe { gl::GlesFns::load_with(|symbol| window.get_proc_address(symbol) as *const _) },
+        };
+
+        let opts = webrender::RendererOptions {
+            // Reftests need every run to produce the same pixels.
+            enable_aa: false,
+            enable_dithering: false,
+            enable_subpixel_aa: false,
+            device_pixel_ratio,
+            .. webrender::RendererOptions::default()
+        };
+
+        let (mut renderer, sender) = webrender::renderer::Renderer::new(gl, opts).unwrap();
+        let api = sender.create_api();
+        let document_id = api.add_document(size);
+
+        let (tx, rx) = channel();
+        renderer.set_render_notifier(Box::new(Notifier { tx }));
+
+        ReftestHarness {
+            window,
+            renderer,
+            api,
+            document_id,
+            rx,
+            size,
+            layout_size,
+            epoch: Epoch(0),
+        }
+    }
+
+    /// Renders a display list and reads back the framebuffer.
+    pub fn render_display_list(&mut self,
+                               builder: DisplayListBuilder,
+                               resources: ResourceUpdates) -> ReftestImage {
+        self.epoch.0 += 1;
+
+        let display_list = builder.finalize();
+        self.api.set_root_pipeline(self.document_id, display_list.0);
+        self.api.set_display_list(
+            self.document_id,
+            self.epoch,
+            Some(ColorF::new(1.0, 1.0, 1.0, 1.0)),
+            self.layout_size,
+            display_list,
+            false,
+            resources
+        );
+        self.api.generate_frame(self.document_id, None);
+
+        // Wait for the backend to build the frame, then draw it.
+        self.rx.recv().unwrap();
+        self.renderer.update();
+        self.renderer.render(self.size);
+
+        let rect = DeviceUintRect::new(DeviceUintPoint::zero(), self.size);
+        let pixels = self.renderer.read_pixels_rgba8(rect);
+
+        // GL reads the framebuffer bottom row first; flip to PNG order.
+        let stride = self.size.width as usize * 4;
+        let mut flipped = Vec::with_capacity(pixels.len());
+        for row in pixels.chunks(stride).rev() {
+            flipped.extend_from_slice(row);
+        }
+
+        ReftestImage {
+            size: self.size,
+            pixels: flipped,
+        }
+    }
+
+    /// Renders both display lists and diffs the results.
+    pub fn compare_display_lists(&mut self,
+                                 test: (DisplayListBuilder, ResourceUpdates),
+                                 reference: (DisplayListBuilder, ResourceUpdates)) -> ReftestDiff {
+        let test_image = self.render_display_list(test.0, test.1);
+        let ref_image = self.render_display_list(reference.0, reference.1);
+        pixel_diff(&test_image, &ref_image)
+    }
+
+    /// Renders a display list and diffs it against a reference PNG.
+    pub fn compare_to_png(&mut self,
+                          test: (DisplayListBuilder, ResourceUpdates),
+                          reference: &Path) -> ReftestDiff {
+        let test_image = self.render_display_list(test.0, test.1);
+        let ref_image = load_png(reference);
+        assert_eq!(test_image.size, ref_image.size,
+                   "Reference PNG {:?} doesn't match the harness window size", reference);
+        pixel_diff(&test_image, &ref_image)
+    }
+
+    pub fn deinit(self) {
+        self.renderer.deinit();
+    }
+}
+
+fn solid_image(size: DeviceUintSize, color: [u8; 4]) -> ReftestImage {
+    let mut pixels = Vec::with_capacity((size.width * size.height * 4) as usize);
+    for _ in 0 .. size.width * size.height {
+        pixels.extend_from_slice(&color);
+    }
+    ReftestImage {
+        size,
+        pixels,
+    }
+}
+
+#[test]
+fn diff_identical_images() {
+    let size = DeviceUintSize::new(4, 4);
+    let a = solid_image(size, [10, 20, 30, 255]);
+    let b = solid_image(size, [10, 20, 30, 255]);
+
+    let diff = pixel_diff(&a, &b);
+    assert!(diff.is_identical());
+    assert_eq!(diff.max_difference, 0);
+    assert!(diff.diff_image.pixels.iter().all(|&delta| delta == 0));
+}
+
+#[test]
+fn diff_reports_channel_deltas() {
+    let size = DeviceUintSize::new(4, 4);
+    let a = solid_image(size, [10, 20, 30, 255]);
+    let mut b = solid_image(size, [10, 20, 30, 255]);
+
+    // Perturb the green channel of the last pixel.
+    let index = b.pixels.len() - 3;
+    b.pixels[index] = 25;
+
+    let diff = pixel_diff(&a, &b);
+    assert!(!diff.is_identical());
+    assert_eq!(diff.max_difference, 5);
+    assert_eq!(diff.num_differences, 1);
+    assert_eq!(diff.diff_image.pixels[index], 5);
+}
+
+// Renders the same rectangle as one primitive and as two abutting halves;
+// the outputs must be identical. Needs a working GL driver, hence ignored
+// by default.
+#[test]
+#[ignore]
+fn reftest_abutting_rects() {
+    let size = DeviceUintSize::new(64, 64);
+    let layout_size = LayoutSize::new(64.0, 64.0);
+    let pipeline_id = PipelineId(0, 0);
+    let bounds = LayoutRect::new(LayoutPoint::zero(), layout_size);
+    let color = ColorF::new(0.0, 1.0, 0.0, 1.0);
+
+    let mut test = DisplayListBuilder::new(pipeline_id, layout_size);
+    test.push_stacking_context(ScrollPolicy::Scrollable,
+                               bounds,
+                               None,
+                               TransformStyle::Flat,
+                               None,
+                               MixBlendMode::Normal,
+                               PixelSnapping::Auto,
+                               false,
+                               Vec::new());
+    test.push_rect(LayoutRect::new(LayoutPoint::new(8.0, 8.0), LayoutSize::new(24.0, 48.0)),
+                   None,
+                   color);
+    test.push_rect(LayoutRect::new(LayoutPoint::new(32.0, 8.0), LayoutSize::new(24.0, 48.0)),
+                   None,
+                   color);
+    test.pop_stacking_context();
+
+    let mut reference = DisplayListBuilder::new(pipeline_id, layout_size);
+    reference.push_stacking_context(ScrollPolicy::Scrollable,
+                                    bounds,
+                                    None,
+                                    TransformStyle::Flat,
+                                    None,
+                                    MixBlendMode::Normal,
+                                    PixelSnapping::Auto,
+                                    false,
+                                    Vec::new());
+    reference.push_rect(LayoutRect::new(LayoutPoint::new(8.0, 8.0), LayoutSize::new(48.0, 48.0)),
+                        None,
+                        color);
+    reference.pop_stacking_context();
+
+    let mut harness = ReftestHarness::new(size);
+    let diff = harness.compare_display_lists((test, ResourceUpdates::new()),
+                                             (reference, ResourceUpdates::new()));
+    assert!(diff.is_identical(),
+            "{} pixels differ (max channel delta {})",
+            diff.num_differences,
+            diff.max_difference);
+    harness.deinit();
+}
+
+/// The device pixel ratios the matrix tests below run at: integer ratios
+/// as a baseline, plus the fractional ones common on Android densities
+/// and desktop display scaling settings.
+const COMMON_RATIOS: &'static [f32] = &[1.0, 1.25, 1.5, 1.75, 2.0, 2.25];
+
+/// Builds a display list that fills one stacking context with solid rects.
+fn rects_display_list(layout_size: LayoutSize,
+                      rects: &[LayoutRect],
+                      color: ColorF) -> (DisplayListBuilder, ResourceUpdates) {
+    let pipeline_id = PipelineId(0, 0);
+    let bounds = LayoutRect::new(LayoutPoint::zero(), layout_size);
+
+    let mut builder = DisplayListBuilder::new(pipeline_id, layout_size);
+    builder.push_stacking_context(ScrollPolicy::Scrollable,
+                                  bounds,
+                                  None,
+                                  TransformStyle::Flat,
+                                  None,
+                                  MixBlendMode::Normal,
+                                  PixelSnapping::Auto,
+                                  false,
+                                  Vec::new());
+    for rect in rects {
+        builder.push_rect(*rect, None, color);
+    }
+    builder.pop_stacking_context();
+
+    (builder, ResourceUpdates::new())
+}
+
+/// Renders a test and a reference display list at every ratio in
+/// `COMMON_RATIOS` and asserts that each pair matches exactly.
+fn run_dpr_matrix<F>(layout_size: LayoutSize, name: &str, build: F)
+    where F: Fn(f32) -> ((DisplayListBuilder, ResourceUpdates),
+                         (DisplayListBuilder, ResourceUpdates))
+{
+    for &ratio in COMMON_RATIOS {
+        let mut harness = ReftestHarness::new_at_ratio(layout_size, ratio);
+        let (test, reference) = build(ratio);
+        let diff = harness.compare_display_lists(test, reference);
+        assert!(diff.is_identical(),
+                "{}: {} pixels differ at ratio {} (max channel delta {})",
+                name,
+                diff.num_differences,
+                ratio,
+                diff.max_difference);
+        harness.deinit();
+    }
+}
+
+// Two abutting rects with a fractional shared edge must render exactly
+// like the single rect covering both: snapping has to place the shared
+// edge on the same device pixel for both rects at every ratio, or a seam
+// or overlap appears. Needs a working GL driver, hence ignored by default.
+#[test]
+#[ignore]
+fn reftest_dpr_abutting_rects_matrix() {
+    let layout_size = LayoutSize::new(64.0, 64.0);
+    let color = ColorF::new(0.0, 1.0, 0.0, 1.0);
+    let split = 23.3;
+
+    run_dpr_matrix(layout_size, "abutting rects", |_ratio| {
+        let test = rects_display_list(
+            layout_size,
+            &[LayoutRect::new(LayoutPoint::new(8.3, 8.0),
+                              LayoutSize::new(split - 8.3, 48.0)),
+              LayoutRect::new(LayoutPoint::new(split, 8.0),
+                              LayoutSize::new(55.7 - split, 48.0))],
+            color);
+        let reference = rects_display_list(
+            layout_size,
+            &[LayoutRect::new(LayoutPoint::new(8.3, 8.0),
+                              LayoutSize::new(55.7 - 8.3, 48.0))],
+            color);
+        (test, reference)
+    });
+}
+
+// A rect with fractional logical edges must rasterize exactly like the
+// same rect with its edges pre-snapped through `snap_to_device_pixel`:
+// the renderer's snapping has to agree with the helper at every ratio.
+#[test]
+#[ignore]
+fn reftest_dpr_presnapped_edges_matrix() {
+    let layout_size = LayoutSize::new(64.0, 64.0);
+    let color = ColorF::new(0.0, 1.0, 0.0, 1.0);
+    let (x0, y0, x1, y1) = (8.3, 8.7, 41.9, 47.1);
+
+    run_dpr_matrix(layout_size, "presnapped edges", |ratio| {
+        let test = rects_display_list(
+            layout_size,
+            &[LayoutRect::new(LayoutPoint::new(x0, y0),
+                              LayoutSize::new(x1 - x0, y1 - y0))],
+            color);
+        let (sx0, sy0) = (snap_to_device_pixel(x0, ratio),
+                          snap_to_device_pixel(y0, ratio));
+        let (sx1, sy1) = (snap_to_device_pixel(x1, ratio),
+                          snap_to_device_pixel(y1, ratio));
+        let reference = rects_display_list(
+            layout_size,
+            &[LayoutRect::new(LayoutPoint::new(sx0, sy0),
+                              LayoutSize::new(sx1 - sx0, sy1 - sy0))],
+            color);
+        (test, reference)
+    });
+}
+
+// The same rect must hit the same device pixels whether its fractional
+// position is its own origin or comes from a containing stacking
+// context, so snapping can't depend on how an offset is composed.
+#[test]
+#[ignore]
+fn reftest_dpr_nested_context_offset_matrix() {
+    let layout_size = LayoutSize::new(64.0, 64.0);
+    let pipeline_id = PipelineId(0, 0);
+    let bounds = LayoutRect::new(LayoutPoint::zero(), layout_size);
+    let color = ColorF::new(0.0, 1.0, 0.0, 1.0);
+    let offset = LayoutPoint::new(12.3, 12.7);
+    let rect_size = LayoutSize::new(24.0, 24.0);
+
+    run_dpr_matrix(layout_size, "nested context offset", |_ratio| {
+        let mut test = DisplayListBuilder::new(pipeline_id, layout_size);
+        test.push_stacking_context(ScrollPolicy::Scrollable,
+                                   bounds,
+                                   None,
+                                   TransformStyle::Flat,
+                                   None,
+                                   MixBlendMode::Normal,
+                                   PixelSnapping::Auto,
+                                   false,
+                                   Vec::new());
+        test.push_stacking_context(ScrollPolicy::Scrollable,
+                                   LayoutRect::new(offset, rect_size),
+                                   None,
+                                   TransformStyle::Flat,
+                                   None,
+                                   MixBlendMode::Normal,
+                                   PixelSnapping::Auto,
+                                   false,
+                                   Vec::new());
+        test.push_rect(LayoutRect::new(LayoutPoint::zero(), rect_size),
+                       None,
+                       color);
+        test.pop_stacking_context();
+        test.pop_stacking_context();
+
+        let reference = rects_display_list(layout_size,
+                                           &[LayoutRect::new(offset, rect_size)],
+                                           color);
+        ((test, ResourceUpdates::new()), reference)
+    });
+}
@@ -9,9 +9,9 @@ use std::cell::Cell;
 use std::fmt;
 use std::marker::PhantomData;
 use {BuiltDisplayList, BuiltDisplayListDescriptor, ClipId, ColorF, DeviceIntPoint, DeviceIntSize};
-use {DeviceUintRect, DeviceUintSize, FontKey, GlyphDimensions, GlyphKey};
+use {DeviceUintRect, DeviceUintSize, ExternalImageId, FontKey, GlyphDimensions, GlyphKey};
 use {ImageData, ImageDescriptor, ImageKey, LayoutPoint, LayoutVector2D, LayoutSize, LayoutTransform};
-use {FontInstanceKey, NativeFontHandle, WorldPoint};
+use {FontInstanceKey, ItemTag, NativeFontHandle, RawGlyph, WorldPoint};
 #[cfg(feature = "webgl")]
 use {WebGLCommand, WebGLContextId};
 
@@ -30,6 +30,7 @@ pub enum ResourceUpdate {
     DeleteImage(ImageKey),
     AddFont(AddFont),
     DeleteFont(FontKey),
+    AddRawGlyphs(FontKey, Vec<RawGlyph>),
 }
 
 impl ResourceUpdates {
@@ -74,6 +75,18 @@ impl ResourceUpdates {
     pub fn delete_font(&mut self, key: FontKey) {
         self.updates.push(ResourceUpdate::DeleteFont(key));
     }
+
+    /// Supplies pre-rasterized images for glyph indices of a font, so
+    /// embedders with their own glyph atlases (e.g. UI toolkits) can
+    /// render text without platform rasterization. The glyphs flow
+    /// through the regular text shaders and glyph cache. The font key
+    /// needs no template: a purely bitmap font is just a key with raw
+    /// glyphs, and `delete_font` releases them. Re-adding an index
+    /// replaces its image. Indices the embedder never supplies render
+    /// nothing, like whitespace glyphs.
+    pub fn add_raw_glyphs(&mut self, key: FontKey, glyphs: Vec<RawGlyph>) {
+        self.updates.push(ResourceUpdate::AddRawGlyphs(key, glyphs));
+    }
 }
 
 #[derive(Clone, Deserialize, Serialize)]
@@ -112,37 +125,73 @@ pub enum DocumentMsg {
     },
     SetPageZoom(ZoomFactor),
     SetPinchZoom(ZoomFactor),
+    SetAsyncZoom(ZoomFactor),
     SetPan(DeviceIntPoint),
+    SetOutputColorTransform(Option<OutputColorTransform>),
     SetRootPipeline(PipelineId),
     SetWindowParameters {
         window_size: DeviceUintSize,
         inner_rect: DeviceUintRect,
     },
+    SetSchedulingPolicy(SchedulingPolicy),
     Scroll(ScrollLocation, WorldPoint, ScrollEventPhase),
     ScrollNodeWithId(LayoutPoint, ClipId, ScrollClamping),
     TickScrollingBounce,
     GetScrollNodeState(MsgSender<Vec<ScrollLayerState>>),
+    HitTest(Option<PipelineId>, WorldPoint, MsgSender<HitTestResult>),
     GenerateFrame(Option<DynamicProperties>),
+    UpdateDynamicProperties(DynamicProperties),
 }
 
-impl fmt::Debug for DocumentMsg {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str(match *self {
+impl DocumentMsg {
+    /// The name of the message kind, e.g. for tracing output.
+    pub fn name(&self) -> &'static str {
+        match *self {
             DocumentMsg::SetDisplayList{..} => "DocumentMsg::SetDisplayList",
             DocumentMsg::SetPageZoom(..) => "DocumentMsg::SetPageZoom",
             DocumentMsg::SetPinchZoom(..) => "DocumentMsg::SetPinchZoom",
+            DocumentMsg::SetAsyncZoom(..) => "DocumentMsg::SetAsyncZoom",
             DocumentMsg::SetPan(..) => "DocumentMsg::SetPan",
+            DocumentMsg::SetOutputColorTransform(..) => "DocumentMsg::SetOutputColorTransform",
             DocumentMsg::SetRootPipeline(..) => "DocumentMsg::SetRootPipeline",
             DocumentMsg::SetWindowParameters{..} => "DocumentMsg::SetWindowParameters",
+            DocumentMsg::SetSchedulingPolicy(..) => "DocumentMsg::SetSchedulingPolicy",
             DocumentMsg::Scroll(..) => "DocumentMsg::Scroll",
             DocumentMsg::ScrollNodeWithId(..) => "DocumentMsg::ScrollNodeWithId",
             DocumentMsg::TickScrollingBounce => "DocumentMsg::TickScrollingBounce",
             DocumentMsg::GetScrollNodeState(..) => "DocumentMsg::GetScrollNodeState",
+            DocumentMsg::HitTest(..) => "DocumentMsg::HitTest",
             DocumentMsg::GenerateFrame(..) => "DocumentMsg::GenerateFrame",
-        })
+            DocumentMsg::UpdateDynamicProperties(..) => "DocumentMsg::UpdateDynamicProperties",
+        }
+    }
+}
+
+impl fmt::Debug for DocumentMsg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
     }
 }
 
+/// Describes the surface a WebGL canvas is composited from. The canvas
+/// context and the compositor share the texture directly, so the canvas
+/// contents reach the screen without a readback.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct WebGLSurfaceDescriptor {
+    /// The raw GL texture the canvas context renders into.
+    pub texture_id: u32,
+    pub size: DeviceIntSize,
+    /// True when the canvas renders with its origin at the bottom left
+    /// (the GL default), in which case the compositor samples the
+    /// surface flipped.
+    pub flip_y: bool,
+    /// The raw GL fence sync handle the canvas signalled after its last
+    /// draw into the texture, or 0 when the producer synchronizes by
+    /// other means. The renderer issues a server-side wait on it (and
+    /// deletes it) before the first draw that samples the texture.
+    pub sync_object: usize,
+}
+
 #[derive(Clone, Deserialize, Serialize)]
 pub enum ApiMsg {
     /// Add/remove/update images and fonts.
@@ -151,17 +200,26 @@ pub enum ApiMsg {
     GetGlyphDimensions(FontInstanceKey, Vec<GlyphKey>, MsgSender<Vec<Option<GlyphDimensions>>>),
     /// Gets the glyph indices from a string
     GetGlyphIndices(FontKey, String, MsgSender<Vec<Option<u32>>>),
+    /// Rasterizes and uploads glyphs ahead of first use. See
+    /// `RenderApi::request_glyphs`.
+    RequestGlyphs(FontInstanceKey, Vec<u32>),
     /// Adds a new document namespace.
     CloneApi(MsgSender<IdNamespace>),
+    /// Allocates a fresh id namespace without creating a new api. See
+    /// `RenderApi::generate_id_namespace`.
+    GenerateIdNamespace(MsgSender<IdNamespace>),
     /// Adds a new document with given initial size.
     AddDocument(DocumentId, DeviceUintSize),
+    /// Changes the scheduling priority of an existing document.
+    SetDocumentPriority(DocumentId, DocumentPriority),
     /// A message targeted at a particular document.
-    UpdateDocument(DocumentId, DocumentMsg),
+    UpdateDocument(DocumentId, DocumentMsg, TransactionId),
     /// Deletes an existing document.
     DeleteDocument(DocumentId),
     RequestWebGLContext(DeviceIntSize, GLContextAttributes, MsgSender<Result<(WebGLContextId, GLLimits), String>>),
     ResizeWebGLContext(WebGLContextId, DeviceIntSize),
     WebGLCommand(WebGLContextId, WebGLCommand),
+    UpdateWebGLSurface(WebGLContextId, WebGLSurfaceDescriptor),
     // WebVR commands that must be called in the WebGL render thread.
     VRCompositorCommand(WebGLContextId, VRCompositorCommand),
     /// An opaque handle that must be passed to the render notifier. It is used by Gecko
@@ -171,7 +229,27 @@ pub enum ApiMsg {
     /// Removes all resources associated with a namespace.
     ClearNamespace(IdNamespace),
     /// Flush from the caches anything that isn't necessary, to free some memory.
-    MemoryPressure,
+    MemoryPressure(MemoryPressureLevel),
+    /// Updates the embedder pressure signals that drive the adaptive
+    /// quality settings. See `QualitySignals`.
+    SetQualitySignals(QualitySignals),
+    /// Resizes the worker thread pool to the given number of threads.
+    SetWorkerThreads(usize),
+    /// Wake the render backend up so that it notices work delivered on side
+    /// channels, such as scenes built on the scene builder thread.
+    WakeUp,
+    /// Changes how many frames an unused resource stays in the texture
+    /// cache before it is expired. See
+    /// `RendererOptions::cache_expiry_frames`.
+    SetCacheExpiryFrames(u32),
+    /// Toggles collection of texture cache allocation info, which the
+    /// backend publishes along with each frame for the renderer's texture
+    /// cache debug overlay.
+    EnableTextureCacheDebug(bool),
+    /// The GL context was lost and replaced: every texture the backend has
+    /// uploaded is gone, and new frames must be produced with fresh GPU
+    /// resources.
+    NotifyContextLost,
     ShutDown,
 }
 
@@ -181,17 +259,27 @@ impl fmt::Debug for ApiMsg {
             ApiMsg::UpdateResources(..) => "ApiMsg::UpdateResources",
             ApiMsg::GetGlyphDimensions(..) => "ApiMsg::GetGlyphDimensions",
             ApiMsg::GetGlyphIndices(..) => "ApiMsg::GetGlyphIndices",
+            ApiMsg::RequestGlyphs(..) => "ApiMsg::RequestGlyphs",
             ApiMsg::CloneApi(..) => "ApiMsg::CloneApi",
+            ApiMsg::GenerateIdNamespace(..) => "ApiMsg::GenerateIdNamespace",
             ApiMsg::AddDocument(..) => "ApiMsg::AddDocument",
+            ApiMsg::SetDocumentPriority(..) => "ApiMsg::SetDocumentPriority",
             ApiMsg::UpdateDocument(..) => "ApiMsg::UpdateDocument",
             ApiMsg::DeleteDocument(..) => "ApiMsg::DeleteDocument",
             ApiMsg::RequestWebGLContext(..) => "ApiMsg::RequestWebGLContext",
             ApiMsg::ResizeWebGLContext(..) => "ApiMsg::ResizeWebGLContext",
             ApiMsg::WebGLCommand(..) => "ApiMsg::WebGLCommand",
+            ApiMsg::UpdateWebGLSurface(..) => "ApiMsg::UpdateWebGLSurface",
             ApiMsg::VRCompositorCommand(..) => "ApiMsg::VRCompositorCommand",
             ApiMsg::ExternalEvent(..) => "ApiMsg::ExternalEvent",
             ApiMsg::ClearNamespace(..) => "ApiMsg::ClearNamespace",
-            ApiMsg::MemoryPressure => "ApiMsg::MemoryPressure",
+            ApiMsg::MemoryPressure(..) => "ApiMsg::MemoryPressure",
+            ApiMsg::SetQualitySignals(..) => "ApiMsg::SetQualitySignals",
+            ApiMsg::SetWorkerThreads(..) => "ApiMsg::SetWorkerThreads",
+            ApiMsg::WakeUp => "ApiMsg::WakeUp",
+            ApiMsg::SetCacheExpiryFrames(..) => "ApiMsg::SetCacheExpiryFrames",
+            ApiMsg::EnableTextureCacheDebug(..) => "ApiMsg::EnableTextureCacheDebug",
+            ApiMsg::NotifyContextLost => "ApiMsg::NotifyContextLost",
             ApiMsg::ShutDown => "ApiMsg::ShutDown",
         })
     }
@@ -227,6 +315,73 @@ pub struct IdNamespace(pub u32);
 #[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub struct DocumentId(pub IdNamespace, pub u32);
 
+/// How eagerly the render backend services a document's messages relative to
+/// other documents. Documents start out high-priority.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum DocumentPriority {
+    /// Serviced as soon as its messages arrive.
+    High,
+    /// Serviced with a time budget, after any pending high-priority work.
+    /// Suitable for hidden or offscreen documents, so that e.g. a background
+    /// tab's display list can't delay a foreground tab's scroll frame.
+    Low,
+}
+
+/// How aggressively `RenderApi::notify_memory_pressure` frees memory.
+/// Each level also applies every measure of the levels below it, and
+/// the amount of memory reclaimed is reported per thread in the debug
+/// log.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum MemoryPressureLevel {
+    /// Release the render target textures pooled between frames; they
+    /// are re-created on demand.
+    Low,
+    /// Also evict texture cache entries that were not referenced by the
+    /// most recent frame.
+    Moderate,
+    /// Also drop the CPU-side shadow copies of the GPU cache, rebuilding
+    /// them from only the data that is still in use.
+    Severe,
+    /// Flush every cache completely; the next frame rebuilds everything
+    /// it needs from the retained resource templates.
+    Critical,
+}
+
+/// Embedder-reported pressure signals, set via
+/// `RenderApi::set_quality_signals`. While a signal is raised, rendering
+/// quality is downgraded to reduce GPU load or memory use; clearing the
+/// signal restores full quality.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct QualitySignals {
+    /// The device is thermally throttled, so GPU work should be reduced.
+    pub thermal_throttle: bool,
+    /// The system is low on memory, so intermediate surfaces should
+    /// shrink.
+    pub low_memory: bool,
+}
+
+/// Decides whether scroll and animation updates render a new frame or
+/// only update state, leaving the last rendered frame on screen until
+/// the embedder asks for a new one with `RenderApi::generate_frame`.
+///
+/// The default for all documents is set via
+/// `RendererOptions::scheduling_policy`; individual documents can
+/// override it with `RenderApi::set_scheduling_policy`.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum SchedulingPolicy {
+    /// Every scroll or animation update renders a new frame immediately.
+    Synchronous,
+    /// Scroll and animation updates only move internal state; the last
+    /// rendered frame is re-presented until `generate_frame` is called.
+    /// Suits embedders that drive rendering from their own vsync loop.
+    OnDemand,
+    /// Like `Synchronous`, but scroll-driven frames are rendered at most
+    /// once per interval; updates that arrive in between re-present the
+    /// last frame. The interval is in nanoseconds, so 33333333 caps
+    /// scroll-driven rendering at roughly 30 frames per second.
+    Throttled { min_frame_interval_ns: u64 },
+}
+
 /// This type carries no valuable semantics for WR. However, it reflects the fact that
 /// clients (Servo) may generate pipelines by different semi-independent sources.
 /// These pipelines still belong to the same `IdNamespace` and the same `DocumentId`.
@@ -250,6 +405,15 @@ impl PipelineId {
 #[derive(Clone, Copy, Debug, Deserialize, Serialize)]
 pub struct ResourceId(pub u32);
 
+/// An id the issuing `RenderApi` assigns to every document message it
+/// sends, unique and increasing within that api instance. The backend's
+/// message tracing output (see the `message-tracing` feature) includes
+/// it, so compositor work can be correlated with the transaction that
+/// caused it. Id 0 is reserved for messages the backend generates
+/// internally.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct TransactionId(pub u64);
+
 /// An opaque pointer-sized value.
 #[repr(C)]
 #[derive(Clone, Deserialize, Serialize)]
@@ -297,6 +461,7 @@ impl RenderApiSender {
             payload_sender: self.payload_sender.clone(),
             namespace_id: sync_rx.recv().unwrap(),
             next_id: Cell::new(ResourceId(0)),
+            next_transaction_id: Cell::new(1),
         }
     }
 }
@@ -306,6 +471,9 @@ pub struct RenderApi {
     payload_sender: PayloadSender,
     namespace_id: IdNamespace,
     next_id: Cell<ResourceId>,
+    // Transaction id 0 is reserved for backend-internal messages, so the
+    // counter starts at 1.
+    next_transaction_id: Cell<u64>,
 }
 
 impl RenderApi {
@@ -332,11 +500,49 @@ impl RenderApi {
         self.api_sender.send(msg).unwrap();
     }
 
+    /// Change the scheduling priority of a document. Lowering the priority of
+    /// an offscreen document stops its updates from delaying frames of
+    /// high-priority documents; raising it back processes any of its work
+    /// that was deferred in the meantime.
+    pub fn set_document_priority(&self, document_id: DocumentId, priority: DocumentPriority) {
+        let msg = ApiMsg::SetDocumentPriority(document_id, priority);
+        self.api_sender.send(msg).unwrap();
+    }
+
+    /// Allocates a fresh id namespace, distinct from this api's own and
+    /// from every other namespace the backend has handed out. Generating
+    /// the resources of a short-lived subtree (e.g. the images and fonts
+    /// of an iframe) into a dedicated namespace makes tearing the subtree
+    /// down a single `clear_namespace` call instead of a delete per key.
+    pub fn generate_id_namespace(&self) -> IdNamespace {
+        let (tx, rx) = channel::msg_channel().unwrap();
+        self.api_sender.send(ApiMsg::GenerateIdNamespace(tx)).unwrap();
+        rx.recv().unwrap()
+    }
+
+    /// Releases every image, font and glyph cache entry whose key lives
+    /// in the given namespace, in one pass through the resource caches.
+    /// Templates that content-sharing deduplication aliased into another
+    /// namespace survive until their last reference goes away. Dropping a
+    /// `RenderApi` does this implicitly for its own namespace.
+    pub fn clear_namespace(&self, namespace: IdNamespace) {
+        self.api_sender.send(ApiMsg::ClearNamespace(namespace)).unwrap();
+    }
+
     pub fn generate_font_key(&self) -> FontKey {
         let new_id = self.next_unique_id();
         FontKey::new(self.namespace_id, new_id)
     }
 
+    /// Creates a `FontKey` scoped to the given namespace rather than this
+    /// api's own. The id counter is shared with the other `generate_*`
+    /// methods, so keys stay unique as long as each namespace is fed from
+    /// a single `RenderApi`.
+    pub fn generate_font_key_in(&self, namespace: IdNamespace) -> FontKey {
+        let new_id = self.next_unique_id();
+        FontKey::new(namespace, new_id)
+    }
+
     /// Gets the dimensions for the supplied glyph keys
     ///
     /// Note: Internally, the internal texture cache doesn't store
@@ -363,12 +569,32 @@ impl RenderApi {
         rx.recv().unwrap()
     }
 
+    /// Rasterizes the given glyphs and uploads them to the glyph cache
+    /// before any display list uses them. Calling this during page-load
+    /// idle time moves the rasterization cost of a text-heavy page off
+    /// its first frame. Pre-warmed glyphs behave like any other cache
+    /// entry: ones that never get used expire after the regular cache
+    /// expiry period, so over-requesting costs memory only briefly. For
+    /// subpixel positioned text, only the zero subpixel offset variant
+    /// is warmed.
+    pub fn request_glyphs(&self, font: FontInstanceKey, glyph_indices: Vec<u32>) {
+        let msg = ApiMsg::RequestGlyphs(font, glyph_indices);
+        self.api_sender.send(msg).unwrap();
+    }
+
     /// Creates an `ImageKey`.
     pub fn generate_image_key(&self) -> ImageKey {
         let new_id = self.next_unique_id();
         ImageKey::new(self.namespace_id, new_id)
     }
 
+    /// Creates an `ImageKey` scoped to the given namespace rather than
+    /// this api's own. See `generate_font_key_in`.
+    pub fn generate_image_key_in(&self, namespace: IdNamespace) -> ImageKey {
+        let new_id = self.next_unique_id();
+        ImageKey::new(namespace, new_id)
+    }
+
     /// Adds an image identified by the `ImageKey`.
     pub fn update_resources(&self, resources: ResourceUpdates) {
         self.api_sender.send(ApiMsg::UpdateResources(resources)).unwrap();
@@ -392,6 +618,17 @@ impl RenderApi {
         self.api_sender.send(msg).unwrap();
     }
 
+    /// Registers or updates the surface a WebGL canvas is composited
+    /// from. Call it again whenever the canvas finishes a frame, with a
+    /// fresh sync object, so that the compositor only ever samples a
+    /// completed rendering.
+    pub fn update_webgl_surface(&self,
+                                context_id: WebGLContextId,
+                                descriptor: WebGLSurfaceDescriptor) {
+        let msg = ApiMsg::UpdateWebGLSurface(context_id, descriptor);
+        self.api_sender.send(msg).unwrap();
+    }
+
     pub fn send_vr_compositor_command(&self, context_id: WebGLContextId, command: VRCompositorCommand) {
         let msg = ApiMsg::VRCompositorCommand(context_id, command);
         self.api_sender.send(msg).unwrap();
@@ -402,8 +639,22 @@ impl RenderApi {
         self.api_sender.send(msg).unwrap();
     }
 
-    pub fn notify_memory_pressure(&self) {
-        self.api_sender.send(ApiMsg::MemoryPressure).unwrap();
+    pub fn notify_memory_pressure(&self, level: MemoryPressureLevel) {
+        self.api_sender.send(ApiMsg::MemoryPressure(level)).unwrap();
+    }
+
+    /// Updates the embedder pressure signals. Rendering quality is
+    /// downgraded while signals are raised and restored once the embedder
+    /// reports them cleared, so this should be called on every change.
+    pub fn set_quality_signals(&self, signals: QualitySignals) {
+        self.api_sender.send(ApiMsg::SetQualitySignals(signals)).unwrap();
+    }
+
+    /// Resizes the worker thread pool to the given number of threads. The
+    /// pool is rebuilt with the settings it was originally created with;
+    /// a pre-built pool supplied by the embedder is replaced entirely.
+    pub fn set_worker_threads(&self, count: usize) {
+        self.api_sender.send(ApiMsg::SetWorkerThreads(count)).unwrap();
     }
 
     pub fn shut_down(&self) {
@@ -447,7 +698,9 @@ impl RenderApi {
         // This assertion fails on Servo use-cases, because it creates different
         // `RenderApi` instances for layout and compositor.
         //assert_eq!(document_id.0, self.namespace_id);
-        self.api_sender.send(ApiMsg::UpdateDocument(document_id, msg)).unwrap()
+        let transaction_id = TransactionId(self.next_transaction_id.get());
+        self.next_transaction_id.set(transaction_id.0 + 1);
+        self.api_sender.send(ApiMsg::UpdateDocument(document_id, msg, transaction_id)).unwrap()
     }
 
         /// Sets the root pipeline.
@@ -542,10 +795,39 @@ impl RenderApi {
         self.send(document_id, DocumentMsg::SetPinchZoom(pinch_zoom));
     }
 
+    /// Sets a zoom factor that is applied as a pre-transform on the
+    /// document's root layer at frame build time, without rebuilding the
+    /// scene or re-rasterizing any content. Combined with `set_pan`, this
+    /// lets a pinch zoom gesture run entirely on the compositor; at the
+    /// end of the gesture the content is typically re-laid-out via
+    /// `set_pinch_zoom` and the async zoom reset to 1.0.
+    pub fn set_async_zoom(&self, document_id: DocumentId, zoom: ZoomFactor) {
+        self.send(document_id, DocumentMsg::SetAsyncZoom(zoom));
+    }
+
     pub fn set_pan(&self, document_id: DocumentId, pan: DeviceIntPoint) {
         self.send(document_id, DocumentMsg::SetPan(pan));
     }
 
+    /// Overrides the scheduling policy for this document. Other documents
+    /// keep the policy from `RendererOptions::scheduling_policy`, so e.g.
+    /// a background document can be switched to `OnDemand` while the
+    /// focused one keeps rendering scrolls synchronously.
+    pub fn set_scheduling_policy(&self, document_id: DocumentId, policy: SchedulingPolicy) {
+        self.send(document_id, DocumentMsg::SetSchedulingPolicy(policy));
+    }
+
+    /// Sets the color transform that is applied when this document is
+    /// composited to the framebuffer, so that rendering can target the
+    /// output display's color profile. Pass `None` to composite the
+    /// rendered values unmodified. Takes effect on the next generated
+    /// frame without rebuilding the scene.
+    pub fn set_output_color_transform(&self,
+                                      document_id: DocumentId,
+                                      transform: Option<OutputColorTransform>) {
+        self.send(document_id, DocumentMsg::SetOutputColorTransform(transform));
+    }
+
     pub fn set_window_parameters(&self,
                                  document_id: DocumentId,
                                  window_size: DeviceUintSize,
@@ -566,6 +848,19 @@ impl RenderApi {
         rx.recv().unwrap()
     }
 
+    /// Returns the tagged display items under `point` in the last built
+    /// scene, front to back, hit tested against the clip-scroll tree's
+    /// current scroll offsets and transforms. Pass a pipeline id to
+    /// restrict the results to items from that pipeline.
+    pub fn hit_test(&self,
+                    document_id: DocumentId,
+                    pipeline_id: Option<PipelineId>,
+                    point: WorldPoint) -> HitTestResult {
+        let (tx, rx) = channel::msg_channel().unwrap();
+        self.send(document_id, DocumentMsg::HitTest(pipeline_id, point, tx));
+        rx.recv().unwrap()
+    }
+
     /// Generate a new frame. Optionally, supply a list of animated
     /// property bindings that should be used to resolve bindings
     /// in the current display list.
@@ -573,6 +868,19 @@ impl RenderApi {
                           property_bindings: Option<DynamicProperties>) {
         self.send(document_id, DocumentMsg::GenerateFrame(property_bindings));
     }
+
+    /// Supply a new set of values for the animated properties bound in the
+    /// current display list, and generate a new frame with them applied.
+    ///
+    /// Unlike `generate_frame()`, this never rebuilds the scene: bound
+    /// transforms are patched directly into the clip-scroll tree, and bound
+    /// opacities are resolved while the new frame is built. That makes it
+    /// cheap enough to call once per composited frame for compositor driven
+    /// animations of transforms and opacity.
+    pub fn update_dynamic_properties(&self, document_id: DocumentId,
+                                     properties: DynamicProperties) {
+        self.send(document_id, DocumentMsg::UpdateDynamicProperties(properties));
+    }
 }
 
 impl Drop for RenderApi {
@@ -599,6 +907,30 @@ pub struct ScrollLayerState {
     pub scroll_offset: LayoutVector2D,
 }
 
+/// One tagged display item under a hit test point. The transforms and
+/// scroll offsets reflect the compositor's current clip-scroll tree, so
+/// the results account for any async scrolling and zooming that layout
+/// has not heard about yet.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct HitTestItem {
+    pub pipeline: PipelineId,
+    /// The clip-scroll node the item belongs to.
+    pub clip_id: ClipId,
+    /// The current scroll offset of that node, if it is a scroll frame.
+    pub scroll_offset: LayoutVector2D,
+    /// The tag the embedder attached to the item when building the
+    /// display list.
+    pub tag: ItemTag,
+    /// The hit test point, relative to the item's origin.
+    pub point_in_item: LayoutPoint,
+}
+
+/// All the tagged display items under a hit test point, front to back.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct HitTestResult {
+    pub items: Vec<HitTestItem>,
+}
+
 #[derive(Clone, Copy, Debug, Deserialize, Serialize)]
 pub enum ScrollLocation {
     /// Scroll by a certain amount.
@@ -609,6 +941,42 @@ pub enum ScrollLocation {
     End
 }
 
+/// A color transform applied to a document's rendered output when it is
+/// composited to the framebuffer, typically derived from the output
+/// display's ICC profile. The composited color is decoded to linear
+/// light with `src_gamma`, multiplied by `matrix`, and re-encoded with
+/// `dst_gamma`:
+///
+/// ```text
+/// output = pow(matrix * pow(color, src_gamma), 1 / dst_gamma)
+/// ```
+///
+/// Alpha is passed through unmodified.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct OutputColorTransform {
+    /// Row-major 3x3 matrix mapping linear source RGB to linear display
+    /// RGB.
+    pub matrix: [f32; 9],
+    /// Exponent that decodes rendered values to linear light.
+    pub src_gamma: f32,
+    /// Gamma of the output display; its reciprocal re-encodes the
+    /// transformed values.
+    pub dst_gamma: f32,
+}
+
+impl OutputColorTransform {
+    /// A transform that leaves the rendered values unchanged.
+    pub fn identity() -> OutputColorTransform {
+        OutputColorTransform {
+            matrix: [1.0, 0.0, 0.0,
+                     0.0, 1.0, 0.0,
+                     0.0, 0.0, 1.0],
+            src_gamma: 2.2,
+            dst_gamma: 2.2,
+        }
+    }
+}
+
 /// Represents a zoom factor.
 #[derive(Clone, Copy, Serialize, Deserialize, Debug)]
 pub struct ZoomFactor(f32);
@@ -722,9 +1090,64 @@ pub trait VRCompositorHandler: Send {
     fn handle(&mut self, command: VRCompositorCommand, texture: Option<(u32, DeviceIntSize)>);
 }
 
+/// A non-fatal error hit by the renderer while drawing a frame. The renderer
+/// draws debug-pink fallback content in place of whatever failed to resolve
+/// and carries on; the error is reported to the embedder through
+/// `RenderNotifier::renderer_error`.
+#[derive(Clone, Debug)]
+pub enum RendererError {
+    /// The display list references an external image, but no external image
+    /// handler has been set on the renderer.
+    MissingExternalImageHandler,
+    /// The external image handler returned a source that does not match the
+    /// image's type (for example raw data where a native texture is needed).
+    InvalidExternalImageSource(ExternalImageId, u8),
+    /// An external image was not resolved by the time it was needed for
+    /// drawing.
+    UnresolvedExternalImage(ExternalImageId, u8),
+    /// A texture cache id was not known to the renderer. The second field is
+    /// the number of cache textures the renderer knows about.
+    UnresolvedTextureCacheId(usize, usize),
+}
+
+/// A defect found in a display list by the render backend's validation
+/// pass. The `usize` is the index of the offending item in its display
+/// list. See `RendererOptions::validate_display_lists`; warnings are
+/// reported to the embedder through
+/// `RenderNotifier::display_list_warning`.
+#[derive(Clone, Copy, Debug)]
+pub enum DisplayListWarning {
+    /// An item rect or clip rect has a NaN or infinite coordinate. The
+    /// display list is rejected, since such rects poison every bounds
+    /// computation they touch.
+    NonFiniteRect(usize),
+    /// An item rect or clip rect has a negative width or height. The
+    /// item draws nothing, so the list is kept.
+    NegativeRectSize(usize),
+    /// A stacking context pop without a matching push, or pushes left
+    /// open at the end of the list. The display list is rejected.
+    UnbalancedStackingContexts(usize),
+    /// A box shadow, text shadow or blur filter radius beyond what the
+    /// blur passes handle sensibly. Rejected only when the radius is
+    /// not finite.
+    ExcessiveBlurRadius(usize, f32),
+}
+
 pub trait RenderNotifier: Send {
     fn new_frame_ready(&mut self);
     fn new_scroll_frame_ready(&mut self, composite_needed: bool);
+    /// The renderer deferred drawing the pending frame until after the next
+    /// vblank, because it estimated that a composite started now would miss
+    /// it. The embedder should call `Renderer::render` again once the vblank
+    /// has passed.
+    fn frame_deferred(&mut self) {}
+    /// The renderer hit a non-fatal error while drawing, rendered fallback
+    /// content in place of the affected primitives, and carried on.
+    fn renderer_error(&mut self, _error: RendererError) {}
+    /// The backend's validation pass found a defect in a display list
+    /// for the given pipeline. See
+    /// `RendererOptions::validate_display_lists`.
+    fn display_list_warning(&mut self, _pipeline_id: PipelineId, _warning: DisplayListWarning) {}
     fn external_event(&mut self, _evt: ExternalEvent) { unimplemented!() }
     fn shut_down(&mut self) {}
 }
@@ -38,6 +38,12 @@ impl<T> MsgReceiver<T> {
         use std::error::Error;
         self.rx.recv().map_err(|e| io::Error::new(ErrorKind::Other, e.description()))
     }
+
+    pub fn try_recv(&self) -> Result<T, Error> {
+        use std::io;
+        use std::error::Error;
+        self.rx.try_recv().map_err(|e| io::Error::new(ErrorKind::Other, e.description()))
+    }
 }
 
 #[derive(Clone)]
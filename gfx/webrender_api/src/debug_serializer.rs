@@ -0,0 +1,62 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A human-editable text form for display lists and their resources.
+//!
+//! The binary display list format is compact but opaque; this module
+//! serializes a built display list (plus the resource updates it depends
+//! on) to RON, and loads such a file back into a replayable display list.
+//! That lets a rendering bug be reduced to a small text file, edited by
+//! hand, and replayed with a standalone tool.
+
+use display_list::{BuiltDisplayList, DebugDisplayItem};
+use ron;
+use std::io::{Read, Write};
+use std::io;
+use {LayoutSize, PipelineId, ResourceUpdates};
+
+/// Everything needed to replay one frame of one pipeline: the resources
+/// (images, fonts) and the display list in its semantic, editable form.
+#[derive(Deserialize, Serialize)]
+pub struct DebugScene {
+    pub pipeline_id: PipelineId,
+    pub content_size: LayoutSize,
+    pub resources: ResourceUpdates,
+    pub items: Vec<DebugDisplayItem>,
+}
+
+impl DebugScene {
+    pub fn new(pipeline_id: PipelineId,
+               content_size: LayoutSize,
+               resources: ResourceUpdates,
+               display_list: &BuiltDisplayList) -> DebugScene {
+        DebugScene {
+            pipeline_id,
+            content_size,
+            resources,
+            items: display_list.to_debug_items(),
+        }
+    }
+
+    /// Rebuilds the scene in the form `RenderApi::set_display_list` wants.
+    pub fn into_display_list(self) -> (PipelineId, LayoutSize, ResourceUpdates, BuiltDisplayList) {
+        let display_list = BuiltDisplayList::from_debug_items(self.items);
+        (self.pipeline_id, self.content_size, self.resources, display_list)
+    }
+}
+
+/// Writes a scene in pretty-printed RON.
+pub fn write_scene<W: Write>(scene: &DebugScene, writer: &mut W) -> io::Result<()> {
+    let text = ron::ser::pretty::to_string(scene)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))?;
+    writer.write_all(text.as_bytes())
+}
+
+/// Reads a scene written by `write_scene` (or edited by hand).
+pub fn read_scene<R: Read>(reader: &mut R) -> io::Result<DebugScene> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+    ron::de::from_str(&text)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))
+}
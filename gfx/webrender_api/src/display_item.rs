@@ -4,6 +4,7 @@
 
 use app_units::Au;
 use euclid::SideOffsets2D;
+use std::ops::Not;
 use {ColorF, FontKey, ImageKey, LayoutPoint, LayoutRect, LayoutSize, LayoutTransform};
 use {GlyphOptions, LayoutVector2D, PipelineId, PropertyBinding, WebGLContextId};
 
@@ -38,12 +39,18 @@ impl ClipAndScrollInfo {
     }
 }
 
+/// An opaque identifier attached to a display item by the embedder, used
+/// to report hits back from compositor-side hit testing. WebRender never
+/// interprets the values.
+pub type ItemTag = (u64, u16);
+
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 pub struct DisplayItem {
     pub item: SpecificDisplayItem,
     pub rect: LayoutRect,
     pub local_clip: LocalClip,
     pub clip_and_scroll: ClipAndScrollInfo,
+    pub tag: Option<ItemTag>,
 }
 
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
@@ -60,6 +67,7 @@ pub enum SpecificDisplayItem {
     BoxShadow(BoxShadowDisplayItem),
     Gradient(GradientDisplayItem),
     RadialGradient(RadialGradientDisplayItem),
+    FilledPath(FilledPathDisplayItem),
     Iframe(IframeDisplayItem),
     PushStackingContext(PushStackingContextDisplayItem),
     PopStackingContext,
@@ -121,6 +129,7 @@ pub enum LineStyle {
     Dotted,
     Dashed,
     Wavy,
+    Double,
 }
 
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
@@ -131,6 +140,38 @@ pub struct TextDisplayItem {
     pub glyph_options: Option<GlyphOptions>,
 } // IMPLICIT: glyphs: Vec<GlyphInstance>
 
+/// A path filled with a solid color, for SVG shapes and CSS shapes that
+/// would otherwise each need a blob image. The path is rasterized into a
+/// cached mask at the size the item is displayed at.
+///
+/// Path coordinates are relative to the item rect origin; anything the
+/// path covers outside the rect is clipped away.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct FilledPathDisplayItem {
+    pub color: ColorF,
+    pub fill_rule: FillRule,
+} // IMPLICIT: commands: Vec<PathCommand>
+
+/// How self-intersecting regions of a filled path are resolved; these
+/// match the SVG fill-rule values.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum FillRule {
+    Nonzero,
+    EvenOdd,
+}
+
+/// One segment of a filled path. The first command of a path is expected
+/// to be a `MoveTo`; every subpath is implicitly closed.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum PathCommand {
+    MoveTo(LayoutPoint),
+    LineTo(LayoutPoint),
+    /// A quadratic bezier: control point, then end point.
+    QuadraticTo(LayoutPoint, LayoutPoint),
+    Close,
+}
+
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 pub struct WebGLDisplayItem {
     pub context_id: WebGLContextId,
@@ -272,6 +313,20 @@ pub struct TextShadow {
 pub enum ExtendMode {
     Clamp,
     Repeat,
+    Reflect,
+}
+
+/// The color space gradient stops are interpolated in.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum ColorInterpolation {
+    /// Interpolate directly on the gamma-encoded sRGB values, matching
+    /// what CSS specifies and what most other engines do.
+    Srgb,
+    /// Convert the stops to linear RGB before interpolating. This avoids
+    /// the dark, desaturated bands that sRGB interpolation produces
+    /// between saturated colors.
+    LinearRgb,
 }
 
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
@@ -279,6 +334,7 @@ pub struct Gradient {
     pub start_point: LayoutPoint,
     pub end_point: LayoutPoint,
     pub extend_mode: ExtendMode,
+    pub interpolation: ColorInterpolation,
 } // IMPLICIT: stops: Vec<GradientStop>
 
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
@@ -304,6 +360,7 @@ pub struct RadialGradient {
     pub end_radius: f32,
     pub ratio_xy: f32,
     pub extend_mode: ExtendMode,
+    pub interpolation: ColorInterpolation,
 } // IMPLICIT stops: Vec<GradientStop>
 
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
@@ -325,6 +382,8 @@ pub struct StackingContext {
     pub transform_style: TransformStyle,
     pub perspective: Option<LayoutTransform>,
     pub mix_blend_mode: MixBlendMode,
+    pub pixel_snapping: PixelSnapping,
+    pub will_change: bool,
 } // IMPLICIT: filters: Vec<FilterOp>
 
 #[repr(u32)]
@@ -336,6 +395,20 @@ pub enum ScrollPolicy {
 
 known_heap_size!(0, ScrollPolicy);
 
+/// A hint controlling pixel snapping for the items of a stacking context.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum PixelSnapping {
+    /// Snap while the accumulated transform is axis-aligned and not animated.
+    Auto        = 0,
+    /// Snap whenever the transform is axis-aligned, even while it animates.
+    Always      = 1,
+    /// Never snap; content is rendered with subpixel precision.
+    Never       = 2,
+}
+
+known_heap_size!(0, PixelSnapping);
+
 #[repr(u32)]
 #[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub enum TransformStyle {
@@ -518,18 +591,44 @@ impl LocalClip {
                                        ComplexClipRegion {
                                             rect: complex.rect.translate(offset),
                                             radii: complex.radii,
+                                            mode: complex.mode,
                                         })
             }
         }
     }
 }
 
+/// Whether a clip region keeps the pixels inside or outside of its shape.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ClipMode {
+    /// Pixels inside the region are visible.
+    Clip,
+    /// Pixels outside the region are visible. Used to clip out a rounded
+    /// rect, e.g. for overflow with an ancestor border-radius, without
+    /// falling back to an image mask.
+    ClipOut,
+}
+
+impl Not for ClipMode {
+    type Output = ClipMode;
+
+    fn not(self) -> ClipMode {
+        match self {
+            ClipMode::Clip => ClipMode::ClipOut,
+            ClipMode::ClipOut => ClipMode::Clip,
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 pub struct ComplexClipRegion {
     /// The boundaries of the rectangle.
     pub rect: LayoutRect,
     /// Border radii of this rectangle.
     pub radii: BorderRadius,
+    /// Whether pixels inside or outside of the rounded rect are kept.
+    pub mode: ClipMode,
 }
 
 impl BorderRadius {
@@ -589,10 +688,11 @@ impl BorderRadius {
 
 impl ComplexClipRegion {
     /// Create a new complex clip region.
-    pub fn new(rect: LayoutRect, radii: BorderRadius) -> ComplexClipRegion {
+    pub fn new(rect: LayoutRect, radii: BorderRadius, mode: ClipMode) -> ComplexClipRegion {
         ComplexClipRegion {
             rect,
             radii,
+            mode,
         }
     }
 }
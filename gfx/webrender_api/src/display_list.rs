@@ -8,11 +8,12 @@ use serde::{Deserialize, Serialize, Serializer};
 use serde::ser::{SerializeSeq, SerializeMap};
 use time::precise_time_ns;
 use {BorderDetails, BorderDisplayItem, BorderWidths, BoxShadowClipMode, BoxShadowDisplayItem};
-use {ClipAndScrollInfo, ClipDisplayItem, ClipId, ColorF, ComplexClipRegion, DisplayItem};
-use {ExtendMode, FilterOp, FontKey, GlyphIndex, GlyphInstance, GlyphOptions, Gradient};
+use {ClipAndScrollInfo, ClipDisplayItem, ClipId, ColorF, ColorInterpolation, ComplexClipRegion, DisplayItem};
+use {ExtendMode, FillRule, FilledPathDisplayItem, FilterOp, FontKey, GlyphIndex, GlyphInstance};
+use {GlyphOptions, Gradient, PathCommand};
 use {GradientDisplayItem, GradientStop, IframeDisplayItem, ImageDisplayItem, ImageKey, ImageMask};
-use {ImageRendering, LayoutPoint, LayoutRect, LayoutSize, LayoutTransform, LayoutVector2D};
-use {LineDisplayItem, LineOrientation, LineStyle, LocalClip, MixBlendMode, PipelineId};
+use {ImageRendering, ItemTag, LayoutPoint, LayoutRect, LayoutSize, LayoutTransform, LayoutVector2D};
+use {LineDisplayItem, LineOrientation, LineStyle, LocalClip, MixBlendMode, PipelineId, PixelSnapping};
 use {PropertyBinding, PushStackingContextDisplayItem, RadialGradient, RadialGradientDisplayItem};
 use {RectangleDisplayItem, ScrollFrameDisplayItem, ScrollPolicy, ScrollSensitivity};
 use {SpecificDisplayItem, StackingContext, TextDisplayItem, TextShadow, TransformStyle};
@@ -73,6 +74,7 @@ pub struct BuiltDisplayListIter<'a> {
     cur_stops: ItemRange<GradientStop>,
     cur_glyphs: ItemRange<GlyphInstance>,
     cur_filters: ItemRange<FilterOp>,
+    cur_path_commands: ItemRange<PathCommand>,
     cur_complex_clip: (ItemRange<ComplexClipRegion>, usize),
     peeking: Peek,
 }
@@ -188,10 +190,12 @@ impl<'a> BuiltDisplayListIter<'a> {
                 rect: LayoutRect::zero(),
                 local_clip: LocalClip::from(LayoutRect::zero()),
                 clip_and_scroll: ClipAndScrollInfo::simple(ClipId::new(0, PipelineId::dummy())),
+                tag: None,
             },
             cur_stops: ItemRange::default(),
             cur_glyphs: ItemRange::default(),
             cur_filters: ItemRange::default(),
+            cur_path_commands: ItemRange::default(),
             cur_complex_clip: (ItemRange::default(), 0),
             peeking: Peek::NotPeeking,
         }
@@ -237,6 +241,7 @@ impl<'a> BuiltDisplayListIter<'a> {
                 Clip(_) | ScrollFrame(_) =>
                     self.cur_complex_clip = self.skip_slice::<ComplexClipRegion>(),
                 Text(_) => self.cur_glyphs = self.skip_slice::<GlyphInstance>().0,
+                FilledPath(_) => self.cur_path_commands = self.skip_slice::<PathCommand>().0,
                 PushStackingContext(_) => self.cur_filters = self.skip_slice::<FilterOp>().0,
                 _ => { /* do nothing */ }
             }
@@ -335,6 +340,10 @@ impl<'a, 'b> DisplayItemRef<'a, 'b> {
         &self.iter.cur_item.item
     }
 
+    pub fn tag(&self) -> Option<ItemTag> {
+        self.iter.cur_item.tag
+    }
+
     pub fn complex_clip(&self) -> &(ItemRange<ComplexClipRegion>, usize) {
         &self.iter.cur_complex_clip
     }
@@ -351,6 +360,10 @@ impl<'a, 'b> DisplayItemRef<'a, 'b> {
         self.iter.cur_filters
     }
 
+    pub fn path_commands(&self) -> ItemRange<PathCommand> {
+        self.iter.cur_path_commands
+    }
+
     pub fn display_list(&self) -> &BuiltDisplayList {
         self.iter.display_list()
     }
@@ -423,6 +436,10 @@ impl<'a, 'b> Serialize for DisplayItemRef<'a, 'b> {
                 map.serialize_entry("glyphs",
                     &self.iter.list.get(self.glyphs()).collect::<Vec<_>>())?;
             }
+            SpecificDisplayItem::FilledPath(_) => {
+                map.serialize_entry("path_commands",
+                    &self.iter.list.get(self.path_commands()).collect::<Vec<_>>())?;
+            }
             SpecificDisplayItem::PushStackingContext(_) => {
                 map.serialize_entry("filters",
                     &self.iter.list.get(self.filters()).collect::<Vec<_>>())?;
@@ -457,6 +474,9 @@ pub struct DisplayListBuilder {
     next_clip_id: u64,
     builder_start_time: u64,
 
+    /// The hit-testing tag attached to subsequently pushed items, if any.
+    current_tag: Option<ItemTag>,
+
     /// The size of the content of this display list. This is used to allow scrolling
     /// outside the bounds of the display list items themselves.
     content_size: LayoutSize,
@@ -482,6 +502,7 @@ impl DisplayListBuilder {
             glyphs: FastHashMap::default(),
             next_clip_id: FIRST_CLIP_ID,
             builder_start_time: start_time,
+            current_tag: None,
             content_size,
         }
     }
@@ -510,6 +531,7 @@ impl DisplayListBuilder {
             rect,
             local_clip: local_clip,
             clip_and_scroll: *self.clip_stack.last().unwrap(),
+            tag: self.current_tag,
         }, bincode::Infinite).unwrap();
     }
 
@@ -519,6 +541,7 @@ impl DisplayListBuilder {
             rect: LayoutRect::zero(),
             local_clip: LocalClip::from(LayoutRect::zero()),
             clip_and_scroll: *self.clip_stack.last().unwrap(),
+            tag: None,
         }, bincode::Infinite).unwrap();
     }
 
@@ -607,6 +630,21 @@ impl DisplayListBuilder {
         self.push_item(item, rect, local_clip);
     }
 
+    pub fn push_filled_path(&mut self,
+                            rect: LayoutRect,
+                            local_clip: Option<LocalClip>,
+                            color: ColorF,
+                            fill_rule: FillRule,
+                            commands: &[PathCommand]) {
+        let item = SpecificDisplayItem::FilledPath(FilledPathDisplayItem {
+            color,
+            fill_rule,
+        });
+
+        self.push_item(item, rect, local_clip);
+        self.push_iter(commands);
+    }
+
     pub fn push_text(&mut self,
                      rect: LayoutRect,
                      local_clip: Option<LocalClip>,
@@ -703,11 +741,13 @@ impl DisplayListBuilder {
 
                     (offset - 0.5, offset + 0.5)
                 }
-                ExtendMode::Repeat => {
+                ExtendMode::Repeat | ExtendMode::Reflect => {
                     // A repeating gradient with stops that are all in the same
                     // position should just display the last color. I believe the
                     // spec says that it should be the average color of the gradient,
-                    // but this matches what Gecko and Blink does
+                    // but this matches what Gecko and Blink does. A degenerate
+                    // reflected gradient collapses to a solid color the same way,
+                    // since reflecting a zero-length segment changes nothing.
                     stops.push(GradientStop {
                         color: last.color,
                         offset: 0.0,
@@ -729,7 +769,8 @@ impl DisplayListBuilder {
                            start_point: LayoutPoint,
                            end_point: LayoutPoint,
                            mut stops: Vec<GradientStop>,
-                           extend_mode: ExtendMode) -> Gradient {
+                           extend_mode: ExtendMode,
+                           interpolation: ColorInterpolation) -> Gradient {
         let (start_offset,
              end_offset) = DisplayListBuilder::normalize_stops(&mut stops, extend_mode);
 
@@ -741,6 +782,7 @@ impl DisplayListBuilder {
             start_point: start_point + start_to_end * start_offset,
             end_point: start_point + start_to_end * end_offset,
             extend_mode,
+            interpolation,
         }
     }
 
@@ -750,7 +792,8 @@ impl DisplayListBuilder {
                                   center: LayoutPoint,
                                   radius: LayoutSize,
                                   mut stops: Vec<GradientStop>,
-                                  extend_mode: ExtendMode) -> RadialGradient {
+                                  extend_mode: ExtendMode,
+                                  interpolation: ColorInterpolation) -> RadialGradient {
         if radius.width <= 0.0 || radius.height <= 0.0 {
             // The shader cannot handle a non positive radius. So
             // reuse the stops vector and construct an equivalent
@@ -777,6 +820,7 @@ impl DisplayListBuilder {
                 end_radius: 1.0,
                 ratio_xy: 1.0,
                 extend_mode,
+                interpolation,
             };
         }
 
@@ -792,6 +836,7 @@ impl DisplayListBuilder {
             end_radius: radius.width * end_offset,
             ratio_xy: radius.width / radius.height,
             extend_mode,
+            interpolation,
         }
     }
 
@@ -804,7 +849,8 @@ impl DisplayListBuilder {
                                           end_radius: f32,
                                           ratio_xy: f32,
                                           stops: Vec<GradientStop>,
-                                          extend_mode: ExtendMode) -> RadialGradient {
+                                          extend_mode: ExtendMode,
+                                          interpolation: ColorInterpolation) -> RadialGradient {
 
         self.push_stops(&stops);
 
@@ -815,6 +861,7 @@ impl DisplayListBuilder {
             end_radius,
             ratio_xy,
             extend_mode,
+            interpolation,
         }
     }
 
@@ -891,6 +938,8 @@ impl DisplayListBuilder {
                                  transform_style: TransformStyle,
                                  perspective: Option<LayoutTransform>,
                                  mix_blend_mode: MixBlendMode,
+                                 pixel_snapping: PixelSnapping,
+                                 will_change: bool,
                                  filters: Vec<FilterOp>) {
         let item = SpecificDisplayItem::PushStackingContext(PushStackingContextDisplayItem {
             stacking_context: StackingContext {
@@ -899,6 +948,8 @@ impl DisplayListBuilder {
                 transform_style,
                 perspective,
                 mix_blend_mode,
+                pixel_snapping,
+                will_change,
             }
         });
 
@@ -981,6 +1032,12 @@ impl DisplayListBuilder {
         assert!(self.clip_stack.len() > 0);
     }
 
+    /// Attaches a hit-testing tag to all items pushed until the next call.
+    /// Passing `None` makes subsequent items invisible to hit testing.
+    pub fn set_item_tag(&mut self, tag: Option<ItemTag>) {
+        self.current_tag = tag;
+    }
+
     pub fn push_iframe(&mut self,
                        rect: LayoutRect,
                        local_clip: Option<LocalClip>,
@@ -1051,3 +1108,159 @@ impl DisplayListBuilder {
          })
     }
 }
+
+/// A self-contained, semantic form of one display item, used by the debug
+/// text serializer. The aux arrays that the binary format stores out of
+/// line (glyphs, filters, complex clips, gradient stops) are inlined so a
+/// serialized item can be edited in isolation.
+#[cfg(feature = "debug_serializer")]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DebugDisplayItem {
+    pub item: SpecificDisplayItem,
+    pub rect: LayoutRect,
+    pub local_clip: LocalClip,
+    pub clip_and_scroll: ClipAndScrollInfo,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<ItemTag>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub glyphs: Vec<GlyphInstance>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub filters: Vec<FilterOp>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub path_commands: Vec<PathCommand>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub complex_clips: Vec<ComplexClipRegion>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub gradient_stops: Vec<GradientStop>,
+}
+
+#[cfg(feature = "debug_serializer")]
+impl BuiltDisplayList {
+    /// Converts the list into semantic items for the debug text serializer.
+    /// `SetGradientStops` dummy items are re-synthesized in front of the
+    /// gradients their stops belong to, since the iterator skips them.
+    pub fn to_debug_items(&self) -> Vec<DebugDisplayItem> {
+        let mut items = Vec::new();
+        let mut iter = self.iter();
+
+        while let Some(item) = iter.next() {
+            let gradient_stops = item.gradient_stops();
+            if !gradient_stops.is_empty() {
+                items.push(DebugDisplayItem {
+                    item: SpecificDisplayItem::SetGradientStops,
+                    rect: LayoutRect::zero(),
+                    local_clip: LocalClip::from(LayoutRect::zero()),
+                    clip_and_scroll: item.clip_and_scroll(),
+                    tag: None,
+                    glyphs: Vec::new(),
+                    filters: Vec::new(),
+                    path_commands: Vec::new(),
+                    complex_clips: Vec::new(),
+                    gradient_stops: self.get(gradient_stops).collect(),
+                });
+            }
+
+            let glyphs = match *item.item() {
+                SpecificDisplayItem::Text(_) => self.get(item.glyphs()).collect(),
+                _ => Vec::new(),
+            };
+            let filters = match *item.item() {
+                SpecificDisplayItem::PushStackingContext(_) => self.get(item.filters()).collect(),
+                _ => Vec::new(),
+            };
+            let path_commands = match *item.item() {
+                SpecificDisplayItem::FilledPath(_) => self.get(item.path_commands()).collect(),
+                _ => Vec::new(),
+            };
+            let &(complex_clips, complex_clip_count) = item.complex_clip();
+            let complex_clips = if complex_clip_count > 0 {
+                self.get(complex_clips).collect()
+            } else {
+                Vec::new()
+            };
+
+            items.push(DebugDisplayItem {
+                item: *item.item(),
+                rect: item.rect(),
+                local_clip: *item.local_clip(),
+                clip_and_scroll: item.clip_and_scroll(),
+                tag: item.tag(),
+                glyphs,
+                filters,
+                path_commands,
+                complex_clips,
+                gradient_stops: Vec::new(),
+            });
+        }
+
+        items
+    }
+
+    /// Rebuilds a binary display list from semantic items, the inverse of
+    /// `to_debug_items`. The trailing glyph cache segment is regenerated
+    /// from the text items, like `DisplayListBuilder::finalize` does.
+    pub fn from_debug_items(items: Vec<DebugDisplayItem>) -> BuiltDisplayList {
+        fn push_aux<T: Serialize>(data: &mut Vec<u8>, aux: &[T]) {
+            bincode::serialize_into(data, &aux.len(), bincode::Infinite).unwrap();
+            for elem in aux {
+                bincode::serialize_into(data, elem, bincode::Infinite).unwrap();
+            }
+        }
+
+        let start_time = precise_time_ns();
+        let mut data = Vec::new();
+        let mut glyphs: FastHashMap<(FontKey, ColorF), FastHashSet<GlyphIndex>> =
+            FastHashMap::default();
+
+        for debug_item in items {
+            bincode::serialize_into(&mut data, &DisplayItem {
+                item: debug_item.item,
+                rect: debug_item.rect,
+                local_clip: debug_item.local_clip,
+                clip_and_scroll: debug_item.clip_and_scroll,
+                tag: debug_item.tag,
+            }, bincode::Infinite).unwrap();
+
+            match debug_item.item {
+                SpecificDisplayItem::SetGradientStops => {
+                    push_aux(&mut data, &debug_item.gradient_stops);
+                }
+                SpecificDisplayItem::Clip(_) |
+                SpecificDisplayItem::ScrollFrame(_) => {
+                    push_aux(&mut data, &debug_item.complex_clips);
+                }
+                SpecificDisplayItem::Text(ref text) => {
+                    push_aux(&mut data, &debug_item.glyphs);
+                    glyphs.entry((text.font_key, text.color))
+                          .or_insert_with(FastHashSet::default)
+                          .extend(debug_item.glyphs.iter().map(|glyph| glyph.index));
+                }
+                SpecificDisplayItem::FilledPath(_) => {
+                    push_aux(&mut data, &debug_item.path_commands);
+                }
+                SpecificDisplayItem::PushStackingContext(_) => {
+                    push_aux(&mut data, &debug_item.filters);
+                }
+                _ => {}
+            }
+        }
+
+        let glyph_offset = data.len();
+        for ((font_key, color), sub_glyphs) in glyphs {
+            bincode::serialize_into(&mut data, &font_key, bincode::Infinite).unwrap();
+            bincode::serialize_into(&mut data, &color, bincode::Infinite).unwrap();
+            let sub_glyphs: Vec<GlyphIndex> = sub_glyphs.into_iter().collect();
+            push_aux(&mut data, &sub_glyphs);
+        }
+
+        BuiltDisplayList {
+            descriptor: BuiltDisplayListDescriptor {
+                builder_start_time: start_time,
+                builder_finish_time: precise_time_ns(),
+                send_start_time: 0,
+                glyph_offset,
+            },
+            data,
+        }
+    }
+}
@@ -224,3 +224,38 @@ pub struct GlyphInstance {
     pub index: GlyphIndex,
     pub point: LayoutPoint,
 }
+
+/// A pre-rasterized glyph image supplied by the embedder, bypassing
+/// platform font rasterization. See `ResourceUpdates::add_raw_glyphs`.
+///
+/// The pixel data is tightly packed premultiplied BGRA8, the format the
+/// glyph atlas stores, so `bytes` is `width * height * 4` long. `left`
+/// and `top` position the image relative to the glyph origin, in whole
+/// device pixels, with `top` measured upwards.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct RawGlyph {
+    pub index: GlyphIndex,
+    pub width: u32,
+    pub height: u32,
+    pub left: f32,
+    pub top: f32,
+    pub bytes: Arc<Vec<u8>>,
+}
+
+impl RawGlyph {
+    pub fn new(index: GlyphIndex,
+               width: u32,
+               height: u32,
+               left: f32,
+               top: f32,
+               bytes: Vec<u8>) -> RawGlyph {
+        RawGlyph {
+            index,
+            width,
+            height,
+            left,
+            top,
+            bytes: Arc::new(bytes),
+        }
+    }
+}
@@ -55,6 +55,11 @@ pub enum ImageFormat {
     BGRA8    = 3,
     RGBAF32  = 4,
     RG8      = 5,
+    /// 10 bits per color channel with 2 bits of alpha, for wide-gamut
+    /// output surfaces.
+    RGB10A2  = 6,
+    /// Half-float RGBA, for HDR output surfaces.
+    RGBA16F  = 7,
 }
 
 impl ImageFormat {
@@ -65,6 +70,8 @@ impl ImageFormat {
             ImageFormat::BGRA8 => Some(4),
             ImageFormat::RGBAF32 => Some(16),
             ImageFormat::RG8 => Some(2),
+            ImageFormat::RGB10A2 => Some(4),
+            ImageFormat::RGBA16F => Some(8),
             ImageFormat::Invalid => None,
         }
     }
@@ -162,6 +169,16 @@ pub trait BlobImageRenderer: Send {
 
     fn resolve(&mut self, key: BlobImageRequest) -> BlobImageResult;
 
+    /// Non-blocking counterpart of resolve. Returns None when the result
+    /// is not ready yet, in which case the request stays pending and the
+    /// result has to be picked up by a later resolve or try_resolve call.
+    ///
+    /// The default implementation simply blocks, so renderers that
+    /// rasterize asynchronously should override it.
+    fn try_resolve(&mut self, key: BlobImageRequest) -> Option<BlobImageResult> {
+        Some(self.resolve(key))
+    }
+
     fn delete_font(&mut self, key: FontKey);
 }
 
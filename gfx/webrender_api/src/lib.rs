@@ -19,6 +19,8 @@ extern crate heapsize;
 extern crate ipc_channel;
 #[cfg(feature = "webgl")]
 extern crate offscreen_gl_context;
+#[cfg(feature = "debug_serializer")]
+extern crate ron;
 #[macro_use]
 extern crate serde;
 extern crate time;
@@ -36,6 +38,8 @@ mod units;
 mod api;
 mod color;
 pub mod channel;
+#[cfg(feature = "debug_serializer")]
+pub mod debug_serializer;
 mod display_item;
 mod display_list;
 mod font;
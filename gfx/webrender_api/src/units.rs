@@ -100,6 +100,16 @@ pub fn device_length(value: f32, device_pixel_ratio: f32) -> DeviceIntLength {
     DeviceIntLength::new((value * device_pixel_ratio).round() as i32)
 }
 
+/// Snap a layout-space value to the nearest device pixel boundary, returning
+/// the snapped value in layout space. The rounding is done in device space so
+/// that the result is a stable function of the device pixel ratio. This
+/// matters for fractional ratios such as 1.5 or 2.25, where rounding in
+/// layout space can move a hairline to a different device pixel depending on
+/// accumulated floating point error.
+pub fn snap_to_device_pixel(value: f32, device_pixel_ratio: f32) -> f32 {
+    (value * device_pixel_ratio).round() / device_pixel_ratio
+}
+
 pub fn as_scroll_parent_rect(rect: &LayerRect) -> ScrollLayerRect {
     ScrollLayerRect::from_untyped(&rect.to_untyped())
 }
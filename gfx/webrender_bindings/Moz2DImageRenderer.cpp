@@ -27,6 +27,8 @@ static MOZ_THREAD_LOCAL(FT_Library) sFTLibrary;
 static bool Moz2DRenderCallback(const Range<const uint8_t> aBlob,
                                 gfx::IntSize aSize,
                                 gfx::SurfaceFormat aFormat,
+                                const uint16_t* aTileSize,
+                                const wr::TileOffset* aTileOffset,
                                 Range<uint8_t> aOutput)
 {
   MOZ_ASSERT(aSize.width > 0 && aSize.height > 0);
@@ -71,6 +73,23 @@ static bool Moz2DRenderCallback(const Range<const uint8_t> aBlob,
     return false;
   }
 
+  if (aTileOffset) {
+    // It's overkill to use a TiledDrawTarget for a single tile, but
+    // it's the easiest way to get the DrawTarget to handle the tile's
+    // offset into the recording for us.
+    gfx::Tile tile;
+    tile.mDrawTarget = dt;
+    tile.mTileOrigin = gfx::IntPoint(aTileOffset->x * *aTileSize,
+                                     aTileOffset->y * *aTileSize);
+    gfx::TileSet tileSet;
+    tileSet.mTiles = &tile;
+    tileSet.mTileCount = 1;
+    dt = gfx::Factory::CreateTiledDrawTarget(tileSet);
+    if (!dt) {
+      return false;
+    }
+  }
+
   gfx::InlineTranslator translator(dt, fontContext);
 
   auto ret = translator.TranslateRecording((char*)aBlob.begin().get(), aBlob.length());
@@ -93,11 +112,15 @@ extern "C" {
 bool wr_moz2d_render_cb(const mozilla::wr::ByteSlice blob,
                         uint32_t width, uint32_t height,
                         mozilla::wr::ImageFormat aFormat,
+                        const uint16_t* aTileSize,
+                        const mozilla::wr::TileOffset* aTileOffset,
                         mozilla::wr::MutByteSlice output)
 {
   return mozilla::wr::Moz2DRenderCallback(mozilla::wr::ByteSliceToRange(blob),
                                           mozilla::gfx::IntSize(width, height),
                                           mozilla::wr::ImageFormatToSurfaceFormat(aFormat),
+                                          aTileSize,
+                                          aTileOffset,
                                           mozilla::wr::MutByteSliceToRange(output));
 }
 
@@ -618,6 +618,13 @@ struct BuiltDisplayList {
   wr::BuiltDisplayListDescriptor dl_desc;
 };
 
+// Tile coordinate of a tiled blob image. This mirrors webrender's
+// TileOffset (a pair of u16s).
+struct TileOffset {
+  uint16_t x;
+  uint16_t y;
+};
+
 static inline wr::WrFilterOpType ToWrFilterOpType(const layers::CSSFilterType type) {
   switch (type) {
     case layers::CSSFilterType::BLUR:
@@ -299,6 +299,8 @@ impl<'a> Into<ComplexClipRegion> for &'a WrComplexClipRegion {
         ComplexClipRegion {
             rect: self.rect.into(),
             radii: self.radii.into(),
+            // The FFI doesn't expose clip-out regions yet.
+            mode: ClipMode::Clip,
         }
     }
 }
@@ -588,9 +590,13 @@ pub extern "C" fn wr_window_new(window_id: WrWindowId,
         debug_flags: debug_flags,
         recorder: recorder,
         blob_image_renderer: Some(Box::new(Moz2dImageRenderer::new(workers.clone()))),
+        // Large blobs are rasterized in tiles on the worker pool, and only
+        // the tiles near the viewport are kept in the texture cache.
+        blob_tile_size: Some(512),
         workers: Some(workers.clone()),
         cache_expiry_frames: 60, // see https://github.com/servo/webrender/pull/1294#issuecomment-304318800
-        enable_render_on_scroll: false,
+        // Gecko drives rendering from its own refresh driver.
+        scheduling_policy: SchedulingPolicy::OnDemand,
         ..Default::default()
     };
 
@@ -982,6 +988,8 @@ pub extern "C" fn wr_dp_begin(state: &mut WrState,
                                 TransformStyle::Flat,
                                 None,
                                 MixBlendMode::Normal,
+                                PixelSnapping::Auto,
+                                false,
                                 Vec::new());
 }
 
@@ -1046,6 +1054,8 @@ pub extern "C" fn wr_dp_push_stacking_context(state: &mut WrState,
                                 transform_style,
                                 None,
                                 mix_blend_mode,
+                                PixelSnapping::Auto,
+                                false,
                                 filters);
 }
 
@@ -1358,7 +1368,8 @@ pub extern "C" fn wr_dp_push_border_gradient(state: &mut WrState,
                                                               .create_gradient(start_point.into(),
                                                                                end_point.into(),
                                                                                stops_vector,
-                                                                               extend_mode.into()),
+                                                                               extend_mode.into(),
+                                                                               ColorInterpolation::Srgb),
                                                      outset: outset.into(),
                                                  });
     state.frame_builder
@@ -1393,7 +1404,8 @@ pub extern "C" fn wr_dp_push_border_radial_gradient(state: &mut WrState,
                                                    .create_radial_gradient(center.into(),
                                                                            radius.into(),
                                                                            stops_vector,
-                                                                           extend_mode.into()),
+                                                                           extend_mode.into(),
+                                                                           ColorInterpolation::Srgb),
                                           outset: outset.into(),
                                       });
     state.frame_builder
@@ -1425,7 +1437,8 @@ pub extern "C" fn wr_dp_push_linear_gradient(state: &mut WrState,
                         .create_gradient(start_point.into(),
                                          end_point.into(),
                                          stops_vector,
-                                         extend_mode.into());
+                                         extend_mode.into(),
+                                         ColorInterpolation::Srgb);
     state.frame_builder
          .dl_builder
          .push_gradient(rect.into(),
@@ -1456,7 +1469,8 @@ pub extern "C" fn wr_dp_push_radial_gradient(state: &mut WrState,
                         .create_radial_gradient(center.into(),
                                                 radius.into(),
                                                 stops_vector,
-                                                extend_mode.into());
+                                                extend_mode.into(),
+                                                ColorInterpolation::Srgb);
     state.frame_builder
          .dl_builder
          .push_radial_gradient(rect.into(),
@@ -1537,11 +1551,14 @@ pub unsafe extern "C" fn wr_dec_ref_arc(arc: *const VecU8) {
 // Update for the new blob image interface changes.
 //
 extern "C" {
-     // TODO: figure out the API for tiled blob images.
+     // `tile_size` and `tile_offset` are null for non-tiled blobs; for
+     // tiled blobs they select which tile of the recording to draw.
      pub fn wr_moz2d_render_cb(blob: ByteSlice,
                                width: u32,
                                height: u32,
                                format: ImageFormat,
+                               tile_size: *const TileSize,
+                               tile_offset: *const TileOffset,
                                output: MutByteSlice)
                                -> bool;
 }
@@ -3,11 +3,12 @@ use bindings::{ByteSlice, MutByteSlice, wr_moz2d_render_cb};
 use rayon::ThreadPool;
 
 use std::collections::hash_map::{HashMap, Entry};
+use std::ptr;
 use std::sync::mpsc::{channel, Sender, Receiver};
 use std::sync::Arc;
 
 pub struct Moz2dImageRenderer {
-    blob_commands: HashMap<ImageKey, Arc<BlobImageData>>,
+    blob_commands: HashMap<ImageKey, (Arc<BlobImageData>, Option<TileSize>)>,
 
     // The images rendered in the current frame (not kept here between frames)
     rendered_images: HashMap<BlobImageRequest, Option<BlobImageResult>>,
@@ -19,12 +20,14 @@ pub struct Moz2dImageRenderer {
 }
 
 impl BlobImageRenderer for Moz2dImageRenderer {
-    fn add(&mut self, key: ImageKey, data: BlobImageData, _tiling: Option<TileSize>) {
-        self.blob_commands.insert(key, Arc::new(data));
+    fn add(&mut self, key: ImageKey, data: BlobImageData, tiling: Option<TileSize>) {
+        self.blob_commands.insert(key, (Arc::new(data), tiling));
     }
 
     fn update(&mut self, key: ImageKey, data: BlobImageData) {
-        self.blob_commands.insert(key, Arc::new(data));
+        // The tile size of an image doesn't change after it is added.
+        let tiling = self.blob_commands.get(&key).and_then(|&(_, tiling)| tiling);
+        self.blob_commands.insert(key, (Arc::new(data), tiling));
     }
 
     fn delete(&mut self, key: ImageKey) {
@@ -37,8 +40,6 @@ impl BlobImageRenderer for Moz2dImageRenderer {
                descriptor: &BlobImageDescriptor,
                _dirty_rect: Option<DeviceUintRect>) {
         debug_assert!(!self.rendered_images.contains_key(&request));
-        // TODO: implement tiling.
-        assert!(request.tile.is_none());
 
         // Add None in the map of rendered images. This makes it possible to differentiate
         // between commands that aren't finished yet (entry in the map is equal to None) and
@@ -48,8 +49,15 @@ impl BlobImageRenderer for Moz2dImageRenderer {
 
         let tx = self.tx.clone();
         let descriptor = descriptor.clone();
-        let commands = Arc::clone(self.blob_commands.get(&request.key).unwrap());
-
+        let (ref commands, tiling) = *self.blob_commands.get(&request.key).unwrap();
+        let commands = Arc::clone(commands);
+        let tile = match (request.tile, tiling) {
+            (Some(tile_offset), Some(tile_size)) => Some((tile_size, tile_offset)),
+            _ => None,
+        };
+
+        // Rasterization happens on the worker pool, so requesting all
+        // the visible tiles of an image up front draws them in parallel.
         self.workers.spawn(move || {
             let buf_size = (descriptor.width
                 * descriptor.height
@@ -57,11 +65,20 @@ impl BlobImageRenderer for Moz2dImageRenderer {
             let mut output = vec![255u8; buf_size];
 
             let result = unsafe {
+                let (tile_size_ptr, tile_offset_ptr) = match tile {
+                    Some((ref tile_size, ref tile_offset)) => {
+                        (tile_size as *const TileSize, tile_offset as *const TileOffset)
+                    }
+                    None => (ptr::null(), ptr::null()),
+                };
+
                 if wr_moz2d_render_cb(
                     ByteSlice::new(&commands[..]),
                     descriptor.width,
                     descriptor.height,
                     descriptor.format,
+                    tile_size_ptr,
+                    tile_offset_ptr,
                     MutByteSlice::new(output.as_mut_slice())
                 ) {
                     Ok(RasterizedBlobImage {
@@ -106,6 +123,25 @@ impl BlobImageRenderer for Moz2dImageRenderer {
         // If we break out of the loop above it means the channel closed unexpectedly.
         Err(BlobImageError::Other("Channel closed".into()))
     }
+
+    fn try_resolve(&mut self, request: BlobImageRequest) -> Option<BlobImageResult> {
+        // Pick up everything the workers have delivered so far.
+        while let Ok((req, result)) = self.rx.try_recv() {
+            self.rendered_images.insert(req, Some(result));
+        }
+
+        match self.rendered_images.entry(request) {
+            Entry::Vacant(_) => Some(Err(BlobImageError::InvalidKey)),
+            Entry::Occupied(entry) => {
+                if entry.get().is_some() {
+                    entry.remove()
+                } else {
+                    // Still rasterizing on a worker.
+                    None
+                }
+            }
+        }
+    }
     fn delete_font(&mut self, _font: FontKey) {
     }
 }